@@ -0,0 +1,179 @@
+//! Times the path from sources to an accepted upload, in process: packing
+//! a project into an archive, and uploading it (streamed while it is
+//! packed) or a single `.tex` file to a local server that reads the whole
+//! request and answers with a task id, so no network is involved.
+//! `cargo bench`.
+
+use bytes::Bytes;
+use chem_tex_summury_creator::accounts;
+use chem_tex_summury_creator::api::{self, CompileOptions};
+use chem_tex_summury_creator::pack::{self, PackOptions, Project};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+const CHAPTERS: usize = 40;
+const FIGURES: usize = 4;
+const FIGURE_BYTES: usize = 2 << 20;
+
+fn pack_and_upload(c: &mut Criterion) {
+    let root = std::env::temp_dir().join(format!("chemtex-bench-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let project = root.join("project");
+    write_project(&project);
+    let main = project.join("main.tex");
+    let single = long_document(CHAPTERS * 10);
+    let archives = root.join("archives");
+    fs::create_dir_all(&archives).unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let server = runtime.block_on(serve());
+    // Uploads go to the local server through an account pointing at it.
+    let home = root.join("home");
+    fs::create_dir_all(home.join("data")).unwrap();
+    fs::write(
+        home.join("data/accounts.toml"),
+        format!("[accounts.bench]\nserver = \"http://{}\"\n", server),
+    )
+    .unwrap();
+    std::env::set_var("CHEMTEX_HOME", &home);
+    let account = accounts::select(Some("bench")).unwrap();
+    let client = api::build_client().unwrap();
+    let options = CompileOptions::default();
+
+    let project_files = pack::project_files(&main, &PackOptions::default()).unwrap();
+    let project_bytes: u64 = project_files
+        .iter()
+        .map(|file| fs::metadata(file).unwrap().len())
+        .sum();
+    let mut group = c.benchmark_group("project");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(project_bytes));
+    group.bench_function("pack", |b| {
+        b.iter(|| pack::pack_project(&main, &archives).unwrap())
+    });
+    let packed = Project {
+        main: main.clone(),
+        options: PackOptions::default(),
+    };
+    group.bench_function("pack + streamed upload", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(api::upload_project(
+                    &client,
+                    &account,
+                    &packed,
+                    &project_files,
+                    "main.zip",
+                    &options,
+                ))
+                .unwrap()
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("single .tex");
+    group.throughput(Throughput::Bytes(single.len() as u64));
+    let contents = Bytes::from(single);
+    group.bench_function("upload", |b| {
+        b.iter(|| {
+            runtime
+                .block_on(api::upload_file(
+                    &client,
+                    &account,
+                    contents.clone(),
+                    "long.tex",
+                    &options,
+                ))
+                .unwrap()
+        })
+    });
+    group.finish();
+    let _ = fs::remove_dir_all(&root);
+}
+
+/// A server that takes every upload: it reads the request to the end, as
+/// the real one does, and hands out a task id.
+async fn serve() -> SocketAddr {
+    let make = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+            let _ = hyper::body::to_bytes(request.into_body()).await;
+            Ok::<_, Infallible>(Response::new(Body::from(
+                r#"{"success":true,"data":{"taskId":"bench"}}"#,
+            )))
+        }))
+    });
+    let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make);
+    let address = server.local_addr();
+    tokio::spawn(server);
+    address
+}
+
+fn write_project(dir: &Path) {
+    fs::create_dir_all(dir.join("chapters")).unwrap();
+    let mut main =
+        String::from("\\documentclass{article}\n\\usepackage{graphicx}\n\\begin{document}\n");
+    for i in 0..CHAPTERS {
+        main.push_str(&format!("\\input{{chapters/ch{}}}\n", i));
+        fs::write(dir.join(format!("chapters/ch{}.tex", i)), chapter(i)).unwrap();
+    }
+    for i in 0..FIGURES {
+        main.push_str(&format!(
+            "\\begin{{figure}}\\includegraphics{{fig{}.png}}\\caption{{Figure {}}}\\end{{figure}}\n",
+            i, i
+        ));
+        fs::write(
+            dir.join(format!("fig{}.png", i)),
+            noise(FIGURE_BYTES, i as u64),
+        )
+        .unwrap();
+    }
+    main.push_str("\\end{document}\n");
+    fs::write(dir.join("main.tex"), main).unwrap();
+}
+
+fn chapter(i: usize) -> String {
+    let mut text = format!("\\section{{Chapter {}}}\n", i);
+    for line in 0..200 {
+        text.push_str(&format!(
+            "Reaction {} of chapter {}: $\\Delta H = {}$ kJ/mol, the yield was {}\\%.\n",
+            line,
+            i,
+            line * 3 + i,
+            (line * 7 + i) % 100
+        ));
+    }
+    text
+}
+
+fn long_document(chapters: usize) -> String {
+    let mut text = String::from("\\documentclass{article}\n\\begin{document}\n");
+    for i in 0..chapters {
+        text.push_str(&chapter(i));
+    }
+    text.push_str("\\end{document}\n");
+    text
+}
+
+/// Incompressible bytes, like the images of a real project.
+fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+criterion_group!(benches, pack_and_upload);
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+use crate::deps;
+use anyhow::Result;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Float environments whose content needs a text alternative.
+const FIGURES: &[&str] = &["figure", "figure*", "wrapfigure", "SCfigure"];
+
+/// A figure that a screen reader cannot describe: it has neither a
+/// `\caption` nor alt text on its images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: figure has no \\caption and no alt text (\\includegraphics[alt={{...}}])",
+            self.file.display(),
+            self.line
+        )
+    }
+}
+
+/// Figures of `main` and the files it includes that have no `\caption` and
+/// an image without `alt=` (graphicx's text alternative), or no image at all
+/// (TikZ, chemfig).
+pub fn check_figures(main: &Path) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    // The open figure's first line, whether it has a caption, how many
+    // images it has and whether one of them lacks alt text.
+    let mut open: Option<(usize, bool, usize, bool)> = None;
+    let lines = deps::read_lines(main)?;
+    for (index, line) in lines.iter().enumerate() {
+        let code = deps::strip_comment(&line.text);
+        if open.is_none()
+            && deps::command_arguments(code, "\\begin")
+                .iter()
+                .any(|name| FIGURES.contains(name))
+        {
+            open = Some((index, false, 0, false));
+        }
+        let Some((start, captioned, images, undescribed)) = open.as_mut() else {
+            continue;
+        };
+        *captioned |= code.contains("\\caption");
+        for alt in image_alt(code) {
+            *images += 1;
+            *undescribed |= !alt;
+        }
+        let closed = deps::command_arguments(code, "\\end")
+            .iter()
+            .any(|name| FIGURES.contains(name));
+        if closed {
+            if !*captioned && (*images == 0 || *undescribed) {
+                let start = &lines[*start];
+                findings.push(Finding {
+                    file: start.file.clone(),
+                    line: start.number,
+                });
+            }
+            open = None;
+        }
+    }
+    Ok(findings)
+}
+
+/// For every `\includegraphics` on `code`, whether it has an `alt` key.
+fn image_alt(code: &str) -> Vec<bool> {
+    let mut alts = Vec::new();
+    let mut rest = code;
+    while let Some(pos) = rest.find("\\includegraphics") {
+        rest = &rest[pos + "\\includegraphics".len()..];
+        rest = rest.strip_prefix('*').unwrap_or(rest);
+        let options = rest
+            .trim_start()
+            .strip_prefix('[')
+            .and_then(|options| options.split_once(']'))
+            .map(|(options, _)| options)
+            .unwrap_or("");
+        alts.push(
+            options
+                .split(',')
+                .any(|option| option.split('=').next().map(str::trim) == Some("alt")),
+        );
+    }
+    alts
+}
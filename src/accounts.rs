@@ -0,0 +1,421 @@
+//! Named accounts for working against more than one compile service, kept
+//! in `<data dir>/accounts.toml`:
+//!
+//! ```toml
+//! default = "university"
+//!
+//! [accounts.university]
+//! server = "https://tex.chem.example.edu"
+//! token = "..."
+//! engine = "lualatex"
+//!
+//! [accounts.personal]
+//! token = "..."
+//! ```
+//!
+//! Each account has its own server (the public one when left out), an API
+//! token sent as `Authorization: Bearer` to that server only, and the engine
+//! and profile its jobs default to. The account is picked by `--account
+//! NAME` on any command, then `CHEMTEX_ACCOUNT`, then `account` in the
+//! project's `.chemtex.toml`, then `default`; with none of these, or no
+//! file, the public server is used without a token.
+
+use crate::api::{self, CompileOptions};
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::job::Job;
+use crate::redact;
+use crate::storage;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use url::Url;
+
+const ACCOUNTS_FILE: &str = "accounts.toml";
+const TOKEN_ENV: &str = "CHEMTEX_TOKEN";
+
+const USAGE: &str = "\
+Usage: chemtex accounts [list]
+       chemtex accounts add NAME [--server URL] [--token-stdin] [--engine E] [--profile P]
+       chemtex accounts remove NAME
+       chemtex accounts default NAME
+
+Accounts are kept in <data dir>/accounts.toml, readable by you only. Pick
+one with `chemtex --account NAME ...`, CHEMTEX_ACCOUNT=NAME or `account =
+\"NAME\"` in a project's .chemtex.toml; otherwise the default account is
+used. `add` replaces an account of the same name; its API token is read from
+CHEMTEX_TOKEN, or from stdin with --token-stdin:
+  chemtex accounts add university --server URL --token-stdin < token.txt";
+
+/// The account chosen for this invocation by [`choose`], before any
+/// command runs; jobs start from it (see [`for_input`]).
+static CHOSEN: OnceLock<Selected> = OnceLock::new();
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Accounts {
+    default: Option<String>,
+    #[serde(default)]
+    accounts: BTreeMap<String, Account>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    pub server: Option<String>,
+    pub token: Option<String>,
+    pub engine: Option<String>,
+    pub profile: Option<String>,
+}
+
+/// An account picked to compile with: every request to the server takes
+/// it, so jobs of different projects each use their own.
+#[derive(Debug, Clone)]
+pub struct Selected {
+    name: Option<String>,
+    account: Account,
+    server: Url,
+    /// Named on the command line or in the environment, which a project's
+    /// `account` does not override.
+    explicit: bool,
+}
+
+impl Default for Selected {
+    /// The public server, without a token.
+    fn default() -> Self {
+        Self {
+            name: None,
+            account: Account::default(),
+            server: Url::parse(api::BASE_URL).expect("BASE_URL is a valid URL"),
+            explicit: false,
+        }
+    }
+}
+
+/// A server to send requests to: an http(s) URL that paths can be added to.
+fn parse_server(server: &str) -> Result<Url> {
+    let url = Url::parse(server).with_context(|| format!("Not a URL: {}", server))?;
+    if !matches!(url.scheme(), "http" | "https") || url.cannot_be_a_base() {
+        bail!("Not an http or https URL: {}", server);
+    }
+    Ok(url)
+}
+
+fn accounts_path() -> Result<PathBuf> {
+    Ok(storage::data_dir()?.join(ACCOUNTS_FILE))
+}
+
+impl Accounts {
+    fn load() -> Result<Self> {
+        let path = accounts_path()?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let accounts: Self =
+            toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        for (name, account) in &accounts.accounts {
+            if let Some(server) = &account.server {
+                parse_server(server).with_context(|| {
+                    format!("Invalid server of account {} in {}", name, path.display())
+                })?;
+            }
+        }
+        Ok(accounts)
+    }
+
+    /// Written with owner-only permissions, as it holds tokens.
+    fn save(&self) -> Result<()> {
+        let path = accounts_path()?;
+        storage::ensure_dir(path.parent().unwrap_or(&path))?;
+        let text = toml::to_string(self).context("Failed to serialize accounts")?;
+        storage::write_private(&path, text.as_bytes())
+    }
+
+    fn get(&self, name: &str) -> Result<&Account> {
+        self.accounts.get(name).with_context(|| {
+            let known: Vec<&str> = self.accounts.keys().map(String::as_str).collect();
+            if known.is_empty() {
+                format!("Unknown account: {} (see `chemtex accounts add`)", name)
+            } else {
+                format!("Unknown account: {} (known: {})", name, known.join(", "))
+            }
+        })
+    }
+}
+
+/// Removes `--account NAME` (or `--account=NAME`) from `args`, which every
+/// command accepts, and returns the name.
+pub fn take_arg(args: &mut Vec<String>) -> Result<Option<String>> {
+    let mut name = None;
+    let mut i = 0;
+    while i < args.len() {
+        if let Some(value) = args[i].strip_prefix("--account=") {
+            name = Some(value.to_string());
+            args.remove(i);
+        } else if args[i] == "--account" {
+            anyhow::ensure!(i + 1 < args.len(), "Option --account requires a value");
+            name = Some(args.remove(i + 1));
+            args.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    Ok(name)
+}
+
+/// The account `name` from the command line names, else
+/// `CHEMTEX_ACCOUNT`, else the default account; the public server when
+/// there is none.
+pub fn select(name: Option<&str>) -> Result<Selected> {
+    let from_env = std::env::var("CHEMTEX_ACCOUNT")
+        .ok()
+        .filter(|name| !name.is_empty());
+    let name = name.map(str::to_string).or(from_env);
+    let explicit = name.is_some();
+    let accounts = Accounts::load()?;
+    let Some(name) = name.or_else(|| accounts.default.clone()) else {
+        return Ok(Selected::default());
+    };
+    let account = accounts.get(&name)?.clone();
+    Selected::new(name, account, explicit)
+}
+
+/// Selects the account of this invocation, as [`select`] does; called once,
+/// with the `--account` [`take_arg`] found.
+pub fn choose(name: Option<&str>) -> Result<()> {
+    let selected = select(name)?;
+    CHOSEN
+        .set(selected)
+        .map_err(|_| anyhow::anyhow!("The account is already chosen"))
+}
+
+/// The account of this invocation (see [`choose`]).
+pub fn chosen() -> Selected {
+    CHOSEN.get().cloned().unwrap_or_default()
+}
+
+/// The account to compile `input` with: the `account` of the
+/// `.chemtex.toml` next to it, unless the invocation named one.
+pub fn for_input(input: &Path) -> Result<Selected> {
+    let dir = input.parent().unwrap_or(Path::new(""));
+    let project = ProjectConfig::find(dir)?.and_then(|config| config.account);
+    chosen().for_project(project.as_deref())
+}
+
+impl Selected {
+    fn new(name: String, account: Account, explicit: bool) -> Result<Self> {
+        if let Some(token) = &account.token {
+            redact::secret(token);
+        }
+        let server = match &account.server {
+            Some(server) => parse_server(server)
+                .with_context(|| format!("Invalid server of account {}", name))?,
+            None => Url::parse(api::BASE_URL).expect("BASE_URL is a valid URL"),
+        };
+        Ok(Self {
+            name: Some(name),
+            account,
+            server,
+            explicit,
+        })
+    }
+
+    /// A project's `account` in place of this one, unless this one was
+    /// named on the command line or in the environment.
+    pub fn for_project(&self, name: Option<&str>) -> Result<Self> {
+        match name {
+            Some(name) if !self.explicit => {
+                let account = Accounts::load()?.get(name)?.clone();
+                Self::new(name.to_string(), account, false)
+            }
+            _ => Ok(self.clone()),
+        }
+    }
+
+    /// The account's server, or the public one.
+    pub fn server(&self) -> &Url {
+        &self.server
+    }
+
+    /// The `Authorization` header for a request to `url`: the account's
+    /// token, for its own server only, never a CDN or another host.
+    pub fn authorization(&self, url: &Url) -> Option<String> {
+        let token = self.account.token.as_deref()?;
+        (url.origin() == self.server.origin()).then(|| format!("Bearer {}", token))
+    }
+
+    /// `job` with the engine and profile of the account where its own
+    /// options leave them out.
+    pub fn with_defaults(&self, job: &Job) -> Job {
+        let mut job = job.clone();
+        let CompileOptions {
+            engine, profile, ..
+        } = &mut job.options;
+        if engine.is_none() {
+            engine.clone_from(&self.account.engine);
+        }
+        if profile.is_none() {
+            profile.clone_from(&self.account.profile);
+        }
+        job
+    }
+}
+
+/// A token for `accounts add`: `CHEMTEX_TOKEN` when set, else a line of
+/// stdin with `--token-stdin`. Never an argument, which other users can
+/// read in the process list and which lands in shell history.
+fn read_token(args: &Args) -> Result<Option<String>> {
+    if let Some(token) = std::env::var(TOKEN_ENV)
+        .ok()
+        .filter(|t| !t.trim().is_empty())
+    {
+        return Ok(Some(token.trim().to_string()));
+    }
+    if !args.flag("token-stdin") {
+        return Ok(None);
+    }
+    let mut token = String::new();
+    std::io::stdin()
+        .read_line(&mut token)
+        .context("Failed to read the token")?;
+    let token = token.trim();
+    anyhow::ensure!(!token.is_empty(), "No token on stdin");
+    Ok(Some(token.to_string()))
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["token-stdin"], &["server", "engine", "profile"])?;
+    let mut accounts = Accounts::load()?;
+    match (args.positional(0), args.positional(1)) {
+        (None | Some("list"), None) => {
+            if accounts.accounts.is_empty() {
+                println!("No accounts; the public server {} is used", api::BASE_URL);
+            }
+            let active = chosen().name;
+            for (name, account) in &accounts.accounts {
+                let marker = if active.as_deref() == Some(name) {
+                    "*"
+                } else {
+                    " "
+                };
+                let mut details = vec![account
+                    .server
+                    .clone()
+                    .unwrap_or_else(|| api::BASE_URL.to_string())];
+                if account.token.is_some() {
+                    details.push("token".to_string());
+                }
+                if let Some(engine) = &account.engine {
+                    details.push(format!("engine {}", engine));
+                }
+                if let Some(profile) = &account.profile {
+                    details.push(format!("profile {}", profile));
+                }
+                let default = if accounts.default.as_deref() == Some(name) {
+                    " (default)"
+                } else {
+                    ""
+                };
+                println!("{} {}{}: {}", marker, name, default, details.join(", "));
+            }
+            Ok(())
+        }
+        (Some("add"), Some(name)) => {
+            let account = Account {
+                server: args.value("server").map(str::to_string),
+                token: read_token(&args)?,
+                engine: args.value("engine").map(str::to_string),
+                profile: args.value("profile").map(str::to_string),
+            };
+            if let Some(server) = &account.server {
+                parse_server(server).context("Invalid --server")?;
+            }
+            accounts.accounts.insert(name.to_string(), account);
+            accounts.default.get_or_insert_with(|| name.to_string());
+            accounts.save()?;
+            println!("Saved account {}", name);
+            Ok(())
+        }
+        (Some("remove"), Some(name)) => {
+            accounts.get(name)?;
+            accounts.accounts.remove(name);
+            if accounts.default.as_deref() == Some(name) {
+                accounts.default = None;
+            }
+            accounts.save()?;
+            println!("Removed account {}", name);
+            Ok(())
+        }
+        (Some("default"), Some(name)) => {
+            accounts.get(name)?;
+            accounts.default = Some(name.to_string());
+            accounts.save()?;
+            println!("Default account: {}", name);
+            Ok(())
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn account_option_is_taken_from_any_command() {
+        let mut raw = args(&["batch", "--account", "uni", "docs", "--jobs", "2"]);
+        assert_eq!(take_arg(&mut raw).unwrap().as_deref(), Some("uni"));
+        assert_eq!(raw, args(&["batch", "docs", "--jobs", "2"]));
+
+        let mut raw = args(&["--account=home", "a.tex"]);
+        assert_eq!(take_arg(&mut raw).unwrap().as_deref(), Some("home"));
+        assert_eq!(raw, args(&["a.tex"]));
+
+        assert!(take_arg(&mut args(&["a.tex", "--account"])).is_err());
+    }
+
+    #[test]
+    fn tokens_go_to_the_account_server_only() {
+        let account = Account {
+            server: Some("https://latex.example.edu/api".to_string()),
+            token: Some("s3cret".to_string()),
+            ..Account::default()
+        };
+        let selected = Selected::new("uni".to_string(), account, true).unwrap();
+        let own = Url::parse("https://latex.example.edu/api/tasks/1").unwrap();
+        let cdn = Url::parse("https://cdn.example.net/result.pdf").unwrap();
+        assert_eq!(
+            selected.authorization(&own).as_deref(),
+            Some("Bearer s3cret")
+        );
+        assert_eq!(selected.authorization(&cdn), None);
+        assert_eq!(Selected::default().authorization(&own), None);
+
+        // An account named for the invocation wins over the project's.
+        let kept = selected.for_project(Some("home")).unwrap();
+        assert_eq!(kept.name.as_deref(), Some("uni"));
+    }
+
+    #[test]
+    fn servers_are_http_urls() {
+        assert!(parse_server("http://localhost:8080").is_ok());
+        assert!(parse_server("https://latex.example.edu/api").is_ok());
+        for server in [
+            "mailto:x",
+            "data:,x",
+            "ftp://example.edu",
+            "latex.example.edu",
+        ] {
+            assert!(parse_server(server).is_err(), "{}", server);
+        }
+    }
+}
@@ -0,0 +1,1444 @@
+use crate::accounts::Selected;
+use crate::callbacks;
+use crate::cli::Args;
+use crate::cookies;
+use crate::eta::{self, QueueEta};
+use crate::fixtures::{self, Interaction, Upload};
+use crate::har;
+use crate::pack::{self, Project};
+use crate::paths;
+use crate::poller::{AdaptiveInterval, StatusPoller};
+use crate::progress::Progress;
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::Bytes;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_RANGE, COOKIE, IF_RANGE, RANGE,
+    SET_COOKIE,
+};
+use reqwest::multipart;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Instant};
+use url::Url;
+
+/// The public server, used unless an account names another (see
+/// [`Selected::server`]).
+pub const BASE_URL: &str = "https://texcompile.ru";
+const POLL_TIMEOUT_SECS: u64 = 600;
+const REQUEST_TIMOUT_SECS: u64 = 600;
+/// Compressed entries of a streamed upload waiting for the connection.
+const STREAM_CHUNKS: usize = 4;
+/// Parallel ranges of one download, however many `CHEMTEX_DOWNLOAD_SEGMENTS`
+/// asks for.
+const MAX_SEGMENTS: usize = 16;
+/// Per-job settings sent alongside the uploaded file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompileOptions {
+    pub engine: Option<String>,
+    pub profile: Option<String>,
+    /// Queue priority for servers that honour one. It does not change the
+    /// PDF, so it is left out of the build cache key, as are the tags.
+    pub priority: Option<String>,
+    /// `--tag key=value` labels sent as the task's metadata, for telling CI
+    /// builds from interactive ones in server logs and local stats.
+    pub tags: BTreeMap<String, String>,
+    /// `--draft` or `--final`, sent for servers that build the two
+    /// differently. Unlike the priority, it is part of the cache key.
+    pub mode: Option<BuildMode>,
+}
+
+/// What a build is for: a quick look at the document while it is being
+/// written, or the copy to hand out, built with every pass and the
+/// bibliography. Drafts of some profiles are made locally (see
+/// [`crate::manifest::Profile`]); final builds always go to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildMode {
+    Draft,
+    Final,
+}
+
+impl BuildMode {
+    /// `--draft` or `--final`, if either was given.
+    pub fn from_args(args: &Args) -> Result<Option<Self>> {
+        match (args.flag("draft"), args.flag("final")) {
+            (true, true) => anyhow::bail!("--draft and --final cannot be combined"),
+            (_, true) if args.flag("local") => {
+                anyhow::bail!("--final builds go through the server; drop --local")
+            }
+            (true, false) => Ok(Some(Self::Draft)),
+            (false, true) => Ok(Some(Self::Final)),
+            (false, false) => Ok(None),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Draft => "draft",
+            Self::Final => "final",
+        }
+    }
+}
+
+impl CompileOptions {
+    /// Takes `--priority` and every `--tag key=value` of the command line.
+    pub fn set_labels(&mut self, args: &Args) -> Result<()> {
+        if let Some(priority) = args.value("priority") {
+            self.priority = Some(priority.to_string());
+        }
+        for spec in args.values("tag") {
+            let (key, value) = parse_tag(spec)?;
+            self.tags.insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn apply(&self, mut form: multipart::Form) -> multipart::Form {
+        if let Some(engine) = &self.engine {
+            form = form.text("engine", engine.clone());
+        }
+        if let Some(profile) = &self.profile {
+            form = form.text("profile", profile.clone());
+        }
+        if let Some(priority) = &self.priority {
+            form = form.text("priority", priority.clone());
+        }
+        if let Some(mode) = self.mode {
+            form = form.text("mode", mode.as_str());
+        }
+        if !self.tags.is_empty() {
+            let metadata = serde_json::to_string(&self.tags).unwrap_or_default();
+            form = form.text("metadata", metadata);
+        }
+        form
+    }
+}
+
+/// Splits `key=value`; the key may not be empty.
+pub fn parse_tag(spec: &str) -> Result<(String, String)> {
+    match spec.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => anyhow::bail!("Invalid value for --tag: {} (expected key=value)", spec),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CompilationStatus {
+    Queued,
+    Processing,
+    Completed,
+    Failed,
+    Unknown(String),
+}
+
+impl CompilationStatus {
+    /// Tolerates casing and a few synonyms (`QUEUED`, `in_progress`, `done`).
+    fn from_str(s: &str) -> Self {
+        let key: String = s
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect();
+        match key.as_str() {
+            "queued" | "pending" | "waiting" => Self::Queued,
+            "processing" | "inprogress" | "running" | "compiling" => Self::Processing,
+            "completed" | "complete" | "done" | "success" | "succeeded" => Self::Completed,
+            "failed" | "failure" | "error" => Self::Failed,
+            _ => Self::Unknown(s.to_string()),
+        }
+    }
+
+    /// The status as the server documents it.
+    fn as_str(&self) -> &str {
+        match self {
+            Self::Queued => "Queued",
+            Self::Processing => "Processing",
+            Self::Completed => "Completed",
+            Self::Failed => "Failed",
+            Self::Unknown(status) => status,
+        }
+    }
+}
+
+/// What polling does with statuses this version does not know, from
+/// `[status]` in `.chemtex.toml`:
+///
+/// ```toml
+/// [status]
+/// terminal = ["Expired", "Cancelled"]
+/// max_unknown = 5
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusPolicy {
+    /// Statuses that end the task (compared ignoring case): completed when
+    /// the server gave a download URL, failed otherwise.
+    pub terminal: Vec<String>,
+    /// Gives up after this many unknown statuses in a row.
+    pub max_unknown: u32,
+}
+
+impl Default for StatusPolicy {
+    fn default() -> Self {
+        Self {
+            terminal: Vec::new(),
+            max_unknown: 10,
+        }
+    }
+}
+
+impl StatusPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn is_terminal(&self, status: &str) -> bool {
+        self.terminal
+            .iter()
+            .any(|terminal| terminal.eq_ignore_ascii_case(status.trim()))
+    }
+}
+
+/// Fields of a response this version does not know, kept so schema changes
+/// on the server are noticed (see [`report_unknown_fields`]).
+type UnknownFields = BTreeMap<String, serde_json::Value>;
+
+#[derive(Debug, Deserialize)]
+struct UploadResponse {
+    /// Missing means "whether there is data".
+    success: Option<bool>,
+    data: Option<UploadData>,
+    error: Option<String>,
+    message: Option<String>,
+    #[serde(flatten)]
+    unknown: UnknownFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadData {
+    #[serde(rename = "taskId", alias = "task_id", alias = "taskID", alias = "id")]
+    task_id: String,
+    #[serde(flatten)]
+    unknown: UnknownFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusResponse {
+    success: Option<bool>,
+    data: Option<StatusData>,
+    error: Option<String>,
+    #[serde(flatten)]
+    unknown: UnknownFields,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusData {
+    status: String,
+    #[serde(rename = "downloadUrl", alias = "download_url", alias = "pdfUrl")]
+    download_url: Option<String>,
+    #[serde(rename = "errorMessage", alias = "error_message", alias = "error")]
+    error_message: Option<String>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    duration: Option<u64>,
+    #[serde(
+        rename = "queuePosition",
+        alias = "queue_position",
+        default,
+        deserialize_with = "lenient_number"
+    )]
+    queue_position: Option<u32>,
+    #[serde(rename = "logUrl", alias = "log_url")]
+    log_url: Option<String>,
+    #[serde(default, deserialize_with = "lenient_number")]
+    warnings: Option<u32>,
+    #[serde(flatten)]
+    unknown: UnknownFields,
+}
+
+/// A count that may arrive as `12`, `12.0` or `"12"`; anything else is
+/// treated as missing rather than failing the whole response.
+fn lenient_number<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: TryFrom<u64>,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    let number = match value {
+        Some(serde_json::Value::Number(n)) => n
+            .as_u64()
+            .or_else(|| n.as_f64().filter(|f| *f >= 0.0).map(|f| f.round() as u64)),
+        Some(serde_json::Value::String(s)) => s.trim().parse::<f64>().ok().map(|f| f as u64),
+        _ => None,
+    };
+    Ok(number.and_then(|n| T::try_from(n).ok()))
+}
+
+/// Parses a response body, showing the body itself when it does not parse:
+/// an HTML error page or a changed schema says more than serde's position.
+fn parse_body<T: serde::de::DeserializeOwned>(body: &str, what: &str) -> Result<T> {
+    serde_json::from_str(body).map_err(|e| {
+        const SHOWN: usize = 500;
+        let shown: String = body.chars().take(SHOWN).collect();
+        let ellipsis = if body.chars().count() > SHOWN {
+            "..."
+        } else {
+            ""
+        };
+        anyhow::anyhow!(
+            "Failed to parse {} ({}); the server sent:\n{}{}",
+            what,
+            e,
+            shown.trim(),
+            ellipsis
+        )
+    })
+}
+
+/// Logs, once per process and field, fields the server added that this
+/// version does not read, so schema drift shows up before it breaks things.
+fn report_unknown_fields(what: &str, unknown: &UnknownFields) {
+    static REPORTED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+    let mut reported = REPORTED.lock().unwrap_or_else(|e| e.into_inner());
+    let new: Vec<&str> = unknown
+        .keys()
+        .filter(|key| reported.insert(format!("{}.{}", what, key)))
+        .map(String::as_str)
+        .collect();
+    if !new.is_empty() {
+        eprintln!(
+            "Note: the server sent field(s) this version does not know in {}: {}",
+            what,
+            new.join(", ")
+        );
+    }
+}
+
+/// A task on the server, with the account it was submitted with, which
+/// every later request about it goes through.
+#[derive(Debug, Clone)]
+pub struct RemoteTask {
+    pub id: String,
+    pub account: Selected,
+}
+
+/// Result of a task that finished compiling on the server.
+#[derive(Debug, Clone)]
+pub struct CompletedTask {
+    pub download_url: String,
+    pub log_url: Option<String>,
+    pub warnings: Option<u32>,
+    pub duration_ms: Option<u64>,
+    /// When the task left the queue, as far as polling could tell.
+    pub processing_started: Option<std::time::Instant>,
+    /// The first queue position reported for the task.
+    pub queue_position: Option<u32>,
+}
+
+impl StatusData {
+    fn compilation_status(&self) -> CompilationStatus {
+        CompilationStatus::from_str(&self.status)
+    }
+
+    fn format_duration(&self) -> String {
+        self.duration
+            .map(format_milliseconds)
+            .unwrap_or_else(|| "неизвестно".to_string())
+    }
+}
+
+/// The server reported that the document did not compile. Travels inside
+/// `anyhow::Error`; downcast to get at the log.
+#[derive(Debug, Clone)]
+pub struct CompilationFailed {
+    pub message: String,
+    pub duration_ms: Option<u64>,
+    pub log_url: Option<String>,
+    pub processing_started: Option<std::time::Instant>,
+    pub queue_position: Option<u32>,
+}
+
+impl fmt::Display for CompilationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let duration = self
+            .duration_ms
+            .map(format_milliseconds)
+            .unwrap_or_else(|| "неизвестно".to_string());
+        write!(f, "Compilation failed after {}: {}", duration, self.message)
+    }
+}
+
+impl std::error::Error for CompilationFailed {}
+
+/// The server refused a request, or answered it with an error instead of
+/// what was asked for. Travels inside `anyhow::Error`, like
+/// [`CompilationFailed`].
+#[derive(Debug, Clone)]
+pub struct ServerError {
+    /// `Upload`, `Status check`, ...
+    pub request: &'static str,
+    /// The HTTP status, when it was not a success.
+    pub status: Option<reqwest::StatusCode>,
+    pub message: String,
+}
+
+impl ServerError {
+    fn status(request: &'static str, reply: &Reply) -> Self {
+        Self {
+            request,
+            status: Some(reply.status),
+            message: String::new(),
+        }
+    }
+
+    /// With the body of the reply, which says why.
+    fn reply(request: &'static str, reply: &Reply) -> Self {
+        Self {
+            message: reply.text().trim().to_string(),
+            ..Self::status(request, reply)
+        }
+    }
+
+    fn message(request: &'static str, message: String) -> Self {
+        Self {
+            request,
+            status: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.status, self.message.is_empty()) {
+            (Some(status), true) => write!(f, "{} failed with status {}", self.request, status),
+            (Some(status), false) => write!(
+                f,
+                "{} failed with status {}: {}",
+                self.request, status, self.message
+            ),
+            (None, _) => write!(f, "{} failed: {}", self.request, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// The task did not finish within the time polling waits for it.
+#[derive(Debug, Clone)]
+pub struct PollTimeout {
+    pub after: Duration,
+}
+
+impl fmt::Display for PollTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = u64::try_from(self.after.as_millis()).unwrap_or(u64::MAX);
+        write!(f, "Compilation timeout after {}", format_milliseconds(ms))
+    }
+}
+
+impl std::error::Error for PollTimeout {}
+
+pub fn format_milliseconds(ms: u64) -> String {
+    let seconds = ms / 1000;
+    if seconds < 60 {
+        format!("{} сек.", seconds)
+    } else {
+        let minutes = seconds / 60;
+        let remaining_seconds = seconds % 60;
+        if minutes < 60 {
+            format! {"{} мин. {} сек.", minutes, remaining_seconds}
+        } else {
+            let hours = minutes / 60;
+            let remaining_minutes = minutes % 60;
+            format!("{} ч. {} м.", hours, remaining_minutes)
+        }
+    }
+}
+
+pub fn build_client() -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(REQUEST_TIMOUT_SECS))
+        .build()
+        .context("Failed to create http client")
+}
+
+/// Uploads `file_contents`, which the request body shares rather than
+/// copies (a mapped file stays mapped).
+pub async fn upload_file(
+    client: &reqwest::Client,
+    account: &Selected,
+    file_contents: Bytes,
+    file_name: &str,
+    options: &CompileOptions,
+) -> Result<String> {
+    // Hashing the upload is only worth it when something records it.
+    let upload =
+        (fixtures::active() || har::capturing()).then(|| Upload::new(file_name, &file_contents));
+    let length = file_contents.len() as u64;
+    let part = multipart::Part::stream_with_length(reqwest::Body::from(file_contents), length)
+        .file_name(paths::upload_name(file_name))
+        .mime_str(mime_type_from_filename(file_name)?)
+        .context("Failed to set MIME type")?;
+    submit(client, account, part, upload, options).await
+}
+
+/// Uploads `files` of a project while [`pack::stream_files`] packs them, so the first
+/// files are on their way before the last are compressed, and returns the
+/// task id with the size of the archive. The upload has no length up front
+/// and is sent in chunks; it is recorded and captured without its hash.
+pub async fn upload_project(
+    client: &reqwest::Client,
+    account: &Selected,
+    project: &Project,
+    files: &[PathBuf],
+    file_name: &str,
+    options: &CompileOptions,
+) -> Result<(String, u64)> {
+    // `None` aborts the body, so the server does not take a cut-off archive
+    // for a complete one.
+    let (chunks, mut received) = mpsc::channel::<Option<Vec<u8>>>(STREAM_CHUNKS);
+    let project = project.clone();
+    let files = files.to_vec();
+    let packing = tokio::task::spawn_blocking(move || {
+        let packed = pack::stream_files(&project, &files, |chunk| {
+            chunks
+                .blocking_send(Some(chunk))
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        });
+        // When the upload stopped reading first, its error says why.
+        let upload_stopped = chunks.is_closed();
+        if packed.is_err() {
+            let _ = chunks.blocking_send(None);
+        }
+        (packed, upload_stopped)
+    });
+    let (mut sender, body) = hyper::Body::channel();
+    // A replayed upload is never sent, but the project is packed all the same.
+    let replaying = fixtures::replaying();
+    tokio::spawn(async move {
+        while let Some(chunk) = received.recv().await {
+            let Some(chunk) = chunk else {
+                sender.abort();
+                return;
+            };
+            if sender.send_data(chunk.into()).await.is_err() && !replaying {
+                return;
+            }
+        }
+    });
+
+    let part = multipart::Part::stream(reqwest::Body::from(body))
+        .file_name(paths::upload_name(file_name))
+        .mime_str(mime_type_from_filename(file_name)?)
+        .context("Failed to set MIME type")?;
+    let submitted = submit(client, account, part, None, options).await;
+    let (packed, upload_stopped) = packing.await.context("Packing the project failed")?;
+    let packed = match (packed, upload_stopped) {
+        (Err(e), false) => return Err(e.context("Failed to pack the project")),
+        (packed, _) => packed,
+    };
+    let task_id = submitted?;
+    Ok((task_id, packed?))
+}
+
+/// Sends the multipart form of an upload and returns the new task's id.
+async fn submit(
+    client: &reqwest::Client,
+    account: &Selected,
+    part: multipart::Part,
+    upload: Option<Upload>,
+    options: &CompileOptions,
+) -> Result<String> {
+    let mut form = options.apply(multipart::Form::new().part("texFile", part));
+    if let Some(url) = callbacks::url() {
+        form = form.text("callback_url", url);
+    }
+
+    let reply = send(
+        client,
+        account,
+        client.post(endpoint(account, &["upload"])?).multipart(form),
+        upload,
+        Limit::Response,
+    )
+    .await
+    .context("Failed to submit form")?;
+
+    if !reply.status.is_success() {
+        return Err(ServerError::reply("Upload", &reply).into());
+    }
+
+    let body = reply.text();
+    let upload_response: UploadResponse = parse_body(&body, "upload response")?;
+    report_unknown_fields("the upload response", &upload_response.unknown);
+    if let Some(data) = &upload_response.data {
+        report_unknown_fields("the upload data", &data.unknown);
+    }
+
+    let success = upload_response
+        .success
+        .unwrap_or(upload_response.data.is_some());
+    if !success {
+        let error_msg = upload_response
+            .error
+            .or(upload_response.message)
+            .unwrap_or_else(|| "Unknown error".to_string());
+        return Err(ServerError::message("Upload", error_msg).into());
+    }
+
+    let task_id = upload_response
+        .data
+        .map(|d| d.task_id)
+        .context("No task ID in response")?;
+
+    Ok(task_id)
+}
+
+pub async fn poll_status(
+    client: &reqwest::Client,
+    task: &RemoteTask,
+    poller: &StatusPoller,
+    policy: &StatusPolicy,
+    progress: &Progress,
+    eta: &QueueEta,
+    label: &str,
+) -> Result<CompletedTask> {
+    let (account, task_id) = (&task.account, task.id.as_str());
+    let deadline = Instant::now() + Duration::from_secs(POLL_TIMEOUT_SECS);
+    let mut interval = AdaptiveInterval::new();
+    let mut processing_started = None;
+    let mut first_position = None;
+    let mut unknown_in_a_row = 0;
+    let url = endpoint(account, &["status", task_id])?;
+    let callback = callbacks::register(task_id);
+    // Waited for once, before falling back to polling.
+    let mut callback_grace = callbacks::grace();
+
+    loop {
+        poller.acquire().await;
+        let reply = send(
+            client,
+            account,
+            client.get(url.clone()),
+            None,
+            Limit::Response,
+        )
+        .await
+        .context("Failed to check status")?;
+
+        if !reply.status.is_success() {
+            return Err(ServerError::reply("Status check", &reply).into());
+        }
+
+        let body = reply.text();
+        let status_response: StatusResponse = parse_body(&body, "status response")?;
+        report_unknown_fields("the status response", &status_response.unknown);
+
+        let success = status_response
+            .success
+            .unwrap_or(status_response.data.is_some());
+        if !success {
+            let error_msg = status_response
+                .error
+                .unwrap_or_else(|| "Unknown error".to_string());
+            return Err(ServerError::message("Status check", error_msg).into());
+        }
+
+        let mut status_data = status_response.data.context("No status data in response")?;
+        report_unknown_fields("the status data", &status_data.unknown);
+
+        let compilation_status = match status_data.compilation_status() {
+            CompilationStatus::Unknown(status) if policy.is_terminal(&status) => {
+                if status_data.download_url.is_some() {
+                    CompilationStatus::Completed
+                } else {
+                    status_data
+                        .error_message
+                        .get_or_insert_with(|| format!("Task ended with status {}", status));
+                    CompilationStatus::Failed
+                }
+            }
+            other => other,
+        };
+        unknown_in_a_row = match compilation_status {
+            CompilationStatus::Unknown(_) => unknown_in_a_row + 1,
+            _ => 0,
+        };
+        let wait = interval.next(compilation_status.as_str(), status_data.queue_position);
+        progress.status(
+            label,
+            compilation_status.as_str(),
+            status_data.queue_position,
+            status_data.duration,
+        );
+        match compilation_status {
+            CompilationStatus::Queued => {
+                let position = status_data.queue_position.filter(|&pos| pos > 0);
+                if let Some(position) = position {
+                    first_position.get_or_insert(position);
+                }
+                let queue_info = position
+                    .map(|pos| format!(" (position: {})", pos))
+                    .unwrap_or_default();
+                let eta_info = position
+                    .and_then(|pos| eta.remaining(pos))
+                    .map(|remaining| format!("{}, ", eta::format(remaining)))
+                    .unwrap_or_default();
+                let duration_info = status_data.format_duration();
+                // The estimate goes with the detail, so that plain progress
+                // is not repeated whenever it changes.
+                progress.status_line(
+                    label,
+                    &format!("Status: Queued{}", queue_info),
+                    &format!("{}Time in queue: {}", eta_info, duration_info),
+                );
+            }
+            CompilationStatus::Processing => {
+                processing_started.get_or_insert_with(std::time::Instant::now);
+                let duration_info = status_data.format_duration();
+                progress.status_line(
+                    label,
+                    "Status: Processing...",
+                    &format!("Time: {}", duration_info),
+                );
+            }
+            CompilationStatus::Completed => {
+                progress.status_line(
+                    label,
+                    "Status: Completed!",
+                    &format!("Compilation time: {}", status_data.format_duration()),
+                );
+                let download_url = status_data
+                    .download_url
+                    .context("No download URL in completed status")?;
+                return Ok(CompletedTask {
+                    download_url,
+                    log_url: status_data
+                        .log_url
+                        .and_then(|url| normalize_url(account, &url).ok())
+                        .map(String::from),
+                    warnings: status_data.warnings,
+                    duration_ms: status_data.duration,
+                    processing_started: processing_started
+                        .or_else(|| estimate_processing_start(status_data.duration)),
+                    queue_position: first_position,
+                });
+            }
+            CompilationStatus::Failed => {
+                return Err(CompilationFailed {
+                    message: status_data
+                        .error_message
+                        .unwrap_or_else(|| "Unknown error".to_string()),
+                    duration_ms: status_data.duration,
+                    log_url: status_data
+                        .log_url
+                        .and_then(|url| normalize_url(account, &url).ok())
+                        .map(String::from),
+                    processing_started: processing_started
+                        .or_else(|| estimate_processing_start(status_data.duration)),
+                    queue_position: first_position,
+                }
+                .into());
+            }
+            CompilationStatus::Unknown(status) => {
+                if unknown_in_a_row >= policy.max_unknown {
+                    anyhow::bail!(
+                        "Gave up after {} unknown statuses in a row (add \"{}\" to `terminal` \
+                         under [status] in .chemtex.toml if it ends the task); the last \
+                         response was:\n{}",
+                        unknown_in_a_row,
+                        status,
+                        body.trim()
+                    );
+                }
+                progress.log(label, format!("Status: {} (unknown)", status));
+            }
+        }
+
+        if Instant::now() + wait > deadline {
+            return Err(PollTimeout {
+                after: Duration::from_secs(POLL_TIMEOUT_SECS),
+            }
+            .into());
+        }
+        if fixtures::replaying() {
+            continue;
+        }
+        match callback_grace.take() {
+            Some(grace) => {
+                if !callback.wait(grace.max(wait)).await {
+                    progress.log(
+                        label,
+                        format!(
+                            "No completion callback within {}, polling",
+                            format_milliseconds(grace.as_millis() as u64)
+                        ),
+                    );
+                }
+            }
+            // A late callback still cuts the wait short.
+            None if callbacks::url().is_some() => {
+                callback.wait(wait).await;
+            }
+            None => sleep(wait).await,
+        }
+    }
+}
+
+/// A task that finished between two polls was never seen processing; back
+/// out the start from the compile time the server reported.
+fn estimate_processing_start(duration_ms: Option<u64>) -> Option<std::time::Instant> {
+    std::time::Instant::now().checked_sub(Duration::from_millis(duration_ms?))
+}
+
+/// Fetches the TeX log of a finished task.
+pub async fn fetch_log(client: &reqwest::Client, account: &Selected, url: &str) -> Result<String> {
+    let reply = send(
+        client,
+        account,
+        client.get(normalize_url(account, url)?),
+        None,
+        Limit::Download,
+    )
+    .await
+    .context("Failed to download compile log")?;
+
+    if !reply.status.is_success() {
+        return Err(ServerError::status("Compile log download", &reply).into());
+    }
+    Ok(reply.text())
+}
+
+/// Asks the server to drop a queued or running task.
+pub async fn cancel_task(client: &reqwest::Client, task: &RemoteTask) -> Result<()> {
+    let reply = send(
+        client,
+        &task.account,
+        client.post(endpoint(&task.account, &["cancel", &task.id])?),
+        None,
+        Limit::Response,
+    )
+    .await
+    .context("Failed to send cancel request")?;
+
+    if !reply.status.is_success() {
+        return Err(ServerError::status("Cancel", &reply).into());
+    }
+    Ok(())
+}
+
+pub async fn download_pdf(
+    client: &reqwest::Client,
+    account: &Selected,
+    url: &str,
+) -> Result<Vec<u8>> {
+    let full_url = normalize_url(account, url)?;
+
+    match download_segmented(client, account, &full_url).await {
+        Ok(Some(pdf)) => return Ok(pdf),
+        Ok(None) => {}
+        Err(e) => eprintln!(
+            "Note: segmented download failed ({:#}), downloading in one piece",
+            e
+        ),
+    }
+
+    let reply = send(client, account, client.get(full_url), None, Limit::Download)
+        .await
+        .context("Failed to download PDF")?;
+
+    if !reply.status.is_success() {
+        return Err(ServerError::status("PDF download", &reply).into());
+    }
+
+    Ok(reply.body)
+}
+
+/// Fetches a large PDF in parallel ranges when the server supports them,
+/// which makes image-heavy documents much faster to get over high-latency
+/// links. `None` when the file is small, or the server does not accept
+/// ranges or give the length.
+///
+/// Every segment must come back as the requested range of the same version
+/// of the file (`If-Range` with its validator, `Content-Range` checked), and
+/// the reassembled file must have the announced length and, when the server
+/// sends one, the SHA-256 `Digest`.
+async fn download_segmented(
+    client: &reqwest::Client,
+    account: &Selected,
+    url: &Url,
+) -> Result<Option<Vec<u8>>> {
+    let segments = segment_count()?;
+    if segments < 2 {
+        return Ok(None);
+    }
+    // A server (or recording) without HEAD just gets the plain download.
+    let probe = send(
+        client,
+        account,
+        client.head(url.clone()),
+        None,
+        Limit::Response,
+    );
+    let Ok(probe) = probe.await else {
+        return Ok(None);
+    };
+    let header = |name: &str| {
+        probe
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    let accepts_ranges = header("accept-ranges").is_some_and(|value| value == "bytes");
+    let Some(length) = header("content-length").and_then(|value| value.parse::<u64>().ok()) else {
+        return Ok(None);
+    };
+    // An empty file has no ranges to split into.
+    if !probe.status.is_success()
+        || !accepts_ranges
+        || length == 0
+        || length < segmented_min_bytes()?
+    {
+        return Ok(None);
+    }
+    let max = Limit::Download.max_bytes()?;
+    if length > max {
+        return Err(too_large(max, Limit::Download));
+    }
+    // A weak ETag does not promise identical bytes, so it cannot tie the
+    // segments to one version of the file.
+    let validator = header("etag")
+        .filter(|etag| !etag.starts_with("W/"))
+        .or(header("last-modified"))
+        .map(String::from);
+    let digest = header("digest")
+        .or(header("repr-digest"))
+        .and_then(sha256_digest);
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for (start, end) in segment_ranges(length, segments) {
+        let mut request = client
+            .get(url.clone())
+            .header(RANGE, format!("bytes={}-{}", start, end));
+        if let Some(validator) = &validator {
+            request = request.header(IF_RANGE, validator);
+        }
+        let client = client.clone();
+        let account = account.clone();
+        tasks.spawn(async move {
+            let reply = send(&client, &account, request, None, Limit::Download).await?;
+            check_segment(&reply, start, end, length)?;
+            Ok::<_, anyhow::Error>((start, reply.body))
+        });
+    }
+    let mut pdf = vec![0; length as usize];
+    while let Some(segment) = tasks.join_next().await {
+        let (start, body) = segment.context("Segment download panicked")??;
+        let start = start as usize;
+        pdf[start..start + body.len()].copy_from_slice(&body);
+    }
+    if let Some(expected) = digest {
+        let actual = STANDARD.encode(Sha256::digest(&pdf));
+        if actual != expected {
+            anyhow::bail!("the reassembled PDF does not match the server's SHA-256 digest");
+        }
+    }
+    Ok(Some(pdf))
+}
+
+/// A segment must be exactly the `start..=end` range of a `total`-byte file.
+fn check_segment(reply: &Reply, start: u64, end: u64, total: u64) -> Result<()> {
+    if reply.status != reqwest::StatusCode::PARTIAL_CONTENT {
+        anyhow::bail!(
+            "bytes {}-{} came back with status {} instead of a range",
+            start,
+            end,
+            reply.status
+        );
+    }
+    let expected = format!("bytes {}-{}/{}", start, end, total);
+    let content_range = reply
+        .headers
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok());
+    if content_range != Some(expected.as_str()) {
+        anyhow::bail!(
+            "asked for {}, got Content-Range {}",
+            expected,
+            content_range.unwrap_or("(none)")
+        );
+    }
+    if reply.body.len() as u64 != end - start + 1 {
+        anyhow::bail!(
+            "bytes {}-{} came back as {} bytes",
+            start,
+            end,
+            reply.body.len()
+        );
+    }
+    Ok(())
+}
+
+/// The base64 SHA-256 of a `Digest: sha-256=...` or
+/// `Repr-Digest: sha-256=:...:` header.
+fn sha256_digest(header: &str) -> Option<String> {
+    header.split(',').find_map(|entry| {
+        let (algorithm, value) = entry.trim().split_once('=')?;
+        algorithm
+            .eq_ignore_ascii_case("sha-256")
+            .then(|| value.trim_matches(':').to_string())
+    })
+}
+
+/// Parallel segments per large download, from `CHEMTEX_DOWNLOAD_SEGMENTS`
+/// (default 4; 1 downloads in one piece), at most [`MAX_SEGMENTS`].
+fn segment_count() -> Result<usize> {
+    let count: usize = match std::env::var("CHEMTEX_DOWNLOAD_SEGMENTS") {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid CHEMTEX_DOWNLOAD_SEGMENTS: {}", value))?,
+        Err(_) => 4,
+    };
+    Ok(count.min(MAX_SEGMENTS))
+}
+
+/// The inclusive byte ranges of `length` bytes in `segments` parts of equal
+/// size (the last may be shorter), never more parts than bytes.
+fn segment_ranges(length: u64, segments: usize) -> Vec<(u64, u64)> {
+    if length == 0 || segments == 0 {
+        return Vec::new();
+    }
+    let size = length.div_ceil(segments as u64);
+    (0..length)
+        .step_by(size as usize)
+        .map(|start| (start, (start + size).min(length) - 1))
+        .collect()
+}
+
+/// Smaller downloads are not worth splitting, from
+/// `CHEMTEX_SEGMENTED_MIN_BYTES` (default 16M).
+fn segmented_min_bytes() -> Result<u64> {
+    match std::env::var("CHEMTEX_SEGMENTED_MIN_BYTES") {
+        Ok(value) => parse_size(&value)
+            .with_context(|| format!("Invalid CHEMTEX_SEGMENTED_MIN_BYTES: {}", value)),
+        Err(_) => Ok(16 << 20),
+    }
+}
+
+/// A response from the server, or from the fixtures being replayed.
+struct Reply {
+    status: reqwest::StatusCode,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Every request to the server goes through here, so `--record`,
+/// `--replay` (see [`fixtures`]), `--capture-http` (see [`har`]) and the
+/// stored [`cookies`] see all of them. The body is read within `limit`; for
+/// error statuses a body that cannot be read is replaced by the reason,
+/// since the status says more.
+async fn send(
+    client: &reqwest::Client,
+    account: &Selected,
+    request: reqwest::RequestBuilder,
+    upload: Option<Upload>,
+    limit: Limit,
+) -> Result<Reply> {
+    let mut request = request.build().context("Invalid request")?;
+    if !fixtures::replaying() {
+        if let Some(cookie) = cookies::header(request.url())? {
+            let value = HeaderValue::from_str(&cookie).context("Invalid stored cookie")?;
+            request.headers_mut().insert(COOKIE, value);
+        }
+        if let Some(token) = account.authorization(request.url()) {
+            let value = HeaderValue::from_str(&token).context("Invalid account token")?;
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+    }
+    let exchange = har::begin(&request, upload.as_ref());
+    let range = request
+        .headers()
+        .get(RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let replayed = fixtures::replayed(
+        request.method().as_str(),
+        request.url().as_str(),
+        range.as_deref(),
+        upload.as_ref(),
+    )?;
+    if let Some(recorded) = replayed {
+        let max = limit.max_bytes()?;
+        if recorded.body.len() as u64 > max {
+            return Err(too_large(max, limit));
+        }
+        let status = reqwest::StatusCode::from_u16(recorded.status)
+            .with_context(|| format!("Invalid recorded status {}", recorded.status))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &recorded.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid recorded header {}", name))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid recorded value of {}", name))?;
+            headers.insert(name, value);
+        }
+        if let Some(exchange) = exchange {
+            exchange.response(status, &headers, &recorded.body)?;
+        }
+        return Ok(Reply {
+            status,
+            headers,
+            body: recorded.body,
+        });
+    }
+
+    let recording =
+        fixtures::recording().then(|| (request.method().clone(), request.url().clone()));
+    let mut response = match client.execute(request).await {
+        Ok(response) => response,
+        Err(e) => {
+            if let Some(exchange) = exchange {
+                exchange.failed(&e)?;
+            }
+            return Err(e.into());
+        }
+    };
+    let status = response.status();
+    cookies::store(response.url(), response.headers())?;
+    // The body's length comes from the body itself, not these headers.
+    let headers = std::mem::take(response.headers_mut());
+    let body = match read_limited(response, limit).await {
+        Ok(body) => body,
+        Err(e) if !status.is_success() => format!("({:#})", e).into_bytes(),
+        Err(e) => {
+            if let Some(exchange) = exchange {
+                exchange.failed(&format!("{:#}", e))?;
+            }
+            return Err(e);
+        }
+    };
+    if let Some(exchange) = exchange {
+        exchange.response(status, &headers, &body)?;
+    }
+    if let Some((method, url)) = recording {
+        let interaction = Interaction {
+            method: method.to_string(),
+            url: url.to_string(),
+            range,
+            upload,
+            status: status.as_u16(),
+            headers: headers
+                .iter()
+                .filter(|(name, _)| *name != SET_COOKIE)
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            body: None,
+            body_file: None,
+        };
+        fixtures::recorded(interaction, &body)?;
+    }
+    Ok(Reply {
+        status,
+        headers,
+        body,
+    })
+}
+
+/// Caps on what the server can make the client hold in memory (and write to
+/// disk), overridable with environment variables (`CHEMTEX_MAX_DOWNLOAD_BYTES=1G`).
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    /// JSON and error bodies.
+    Response,
+    /// PDFs and compile logs.
+    Download,
+}
+
+impl Limit {
+    fn variable(self) -> &'static str {
+        match self {
+            Self::Response => "CHEMTEX_MAX_RESPONSE_BYTES",
+            Self::Download => "CHEMTEX_MAX_DOWNLOAD_BYTES",
+        }
+    }
+
+    fn max_bytes(self) -> Result<u64> {
+        let default = match self {
+            Self::Response => 1 << 20,
+            Self::Download => 256 << 20,
+        };
+        match std::env::var(self.variable()) {
+            Ok(value) => parse_size(&value)
+                .with_context(|| format!("Invalid {}: {}", self.variable(), value)),
+            Err(_) => Ok(default),
+        }
+    }
+}
+
+/// `1048576`, `512K`, `100M` or `2G` (powers of 1024).
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (digits, shift) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 30),
+        _ => (value, 0),
+    };
+    let number: u64 = digits.trim().parse().context("Expected a size like 100M")?;
+    number
+        .checked_mul(1 << shift)
+        .context("Size does not fit in 64 bits")
+}
+
+/// Reads a body of at most the `limit`, chunk by chunk, giving up as soon as
+/// the declared length or the bytes received exceed it.
+async fn read_limited(mut response: reqwest::Response, limit: Limit) -> Result<Vec<u8>> {
+    let max = limit.max_bytes()?;
+    if response.content_length().is_some_and(|length| length > max) {
+        return Err(too_large(max, limit));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > max {
+            return Err(too_large(max, limit));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+fn too_large(max: u64, limit: Limit) -> anyhow::Error {
+    anyhow::anyhow!(
+        "The server sent more than {} bytes; set {} to allow more",
+        max,
+        limit.variable()
+    )
+}
+
+/// Resolves a URL the server returned against the server: absolute URLs
+/// (a CDN host, another port) are kept, `//host/...` takes the base's scheme,
+/// and paths are joined onto the base, keeping query strings. Spaces and
+/// non-ASCII characters are percent-encoded; existing escapes are kept.
+pub fn normalize_url(account: &Selected, url: &str) -> Result<Url> {
+    let url = url.trim();
+    account
+        .server()
+        .join(url)
+        .with_context(|| format!("Invalid URL from the server: {}", url))
+}
+
+/// `<server>/api/<segments>`, each segment percent-encoded.
+fn endpoint(account: &Selected, segments: &[&str]) -> Result<Url> {
+    let mut url = account.server().clone();
+    url.path_segments_mut()
+        .map_err(|()| anyhow!("Not a server URL: {}", account.server()))?
+        .pop_if_empty()
+        .push("api")
+        .extend(segments);
+    Ok(url)
+}
+
+pub fn mime_type_from_filename(filename: &str) -> Result<&'static str> {
+    if filename.ends_with(".tex") {
+        Ok("text/x-tex")
+    } else if filename.ends_with(".zip") {
+        Ok("application/zip")
+    } else {
+        anyhow::bail!("Unsupported file type. Expected .tex or .zip");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downloads_split_into_contiguous_ranges() {
+        assert_eq!(segment_ranges(10, 4), [(0, 2), (3, 5), (6, 8), (9, 9)]);
+        assert_eq!(segment_ranges(3, MAX_SEGMENTS), [(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(segment_ranges(5, 1), [(0, 4)]);
+        assert!(segment_ranges(0, 4).is_empty());
+    }
+
+    #[test]
+    fn normalize_url_resolves_server_urls() {
+        let cases = [
+            // Relative paths, with and without a leading slash.
+            ("/files/a.pdf", "https://texcompile.ru/files/a.pdf"),
+            ("files/a.pdf", "https://texcompile.ru/files/a.pdf"),
+            ("  /files/a.pdf\n", "https://texcompile.ru/files/a.pdf"),
+            // Query strings and fragments survive.
+            (
+                "/download/a.pdf?token=abc&expires=1700000000",
+                "https://texcompile.ru/download/a.pdf?token=abc&expires=1700000000",
+            ),
+            (
+                "https://cdn.example.com/a.pdf#page=2",
+                "https://cdn.example.com/a.pdf#page=2",
+            ),
+            // Absolute URLs on other hosts, schemes and ports.
+            (
+                "https://cdn.example.com/files/a.pdf",
+                "https://cdn.example.com/files/a.pdf",
+            ),
+            (
+                "http://texcompile.ru:8080/files/a.pdf",
+                "http://texcompile.ru:8080/files/a.pdf",
+            ),
+            (
+                "https://texcompile.ru:443/files/a.pdf",
+                "https://texcompile.ru/files/a.pdf",
+            ),
+            (
+                "//cdn.example.com/files/a.pdf",
+                "https://cdn.example.com/files/a.pdf",
+            ),
+            // Percent-encoding: added where needed, kept where present.
+            (
+                "/files/my report.pdf",
+                "https://texcompile.ru/files/my%20report.pdf",
+            ),
+            (
+                "/files/my%20report.pdf",
+                "https://texcompile.ru/files/my%20report.pdf",
+            ),
+            (
+                "/files/отчёт.pdf",
+                "https://texcompile.ru/files/%D0%BE%D1%82%D1%87%D1%91%D1%82.pdf",
+            ),
+            (
+                "/logs/a.log?name=my%20log",
+                "https://texcompile.ru/logs/a.log?name=my%20log",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                normalize_url(&Selected::default(), input).unwrap().as_str(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_url_rejects_malformed_urls() {
+        for input in ["http://[::1/a.pdf", "https://exa mple.com/a.pdf"] {
+            assert!(
+                normalize_url(&Selected::default(), input).is_err(),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn endpoint_encodes_segments() {
+        let cases: [(&[&str], &str); 3] = [
+            (&["upload"], "https://texcompile.ru/api/upload"),
+            (
+                &["status", "abc123"],
+                "https://texcompile.ru/api/status/abc123",
+            ),
+            (
+                &["cancel", "a/b c"],
+                "https://texcompile.ru/api/cancel/a%2Fb%20c",
+            ),
+        ];
+        for (segments, expected) in cases {
+            assert_eq!(
+                endpoint(&Selected::default(), segments).unwrap().as_str(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn tags_are_key_value_pairs() {
+        assert_eq!(
+            parse_tag("source = ci").unwrap(),
+            ("source".to_string(), "ci".to_string())
+        );
+        assert_eq!(
+            parse_tag("note=a=b").unwrap(),
+            ("note".to_string(), "a=b".to_string())
+        );
+        for spec in ["ci", "=ci", " =ci"] {
+            assert!(parse_tag(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn status_strings_tolerate_casing_and_synonyms() {
+        let cases = [
+            ("Queued", CompilationStatus::Queued),
+            ("QUEUED", CompilationStatus::Queued),
+            ("in_progress", CompilationStatus::Processing),
+            ("Processing", CompilationStatus::Processing),
+            ("done", CompilationStatus::Completed),
+            ("Failed", CompilationStatus::Failed),
+            ("paused", CompilationStatus::Unknown("paused".to_string())),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(CompilationStatus::from_str(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn status_response_tolerates_drift() {
+        let body = r#"{"data": {"status": "completed", "download_url": "/a.pdf",
+            "duration": 1234.4, "queuePosition": "3", "region": "eu"}, "traceId": "x"}"#;
+        let response: StatusResponse = parse_body(body, "status response").unwrap();
+        assert_eq!(response.success, None);
+        assert_eq!(response.unknown.keys().collect::<Vec<_>>(), ["traceId"]);
+        let data = response.data.unwrap();
+        assert_eq!(data.compilation_status(), CompilationStatus::Completed);
+        assert_eq!(data.download_url.as_deref(), Some("/a.pdf"));
+        assert_eq!(data.duration, Some(1234));
+        assert_eq!(data.queue_position, Some(3));
+        assert_eq!(data.unknown.keys().collect::<Vec<_>>(), ["region"]);
+    }
+
+    #[test]
+    fn unparsable_body_is_shown() {
+        let error = parse_body::<StatusResponse>("<html>502 Bad Gateway</html>", "status response")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("<html>502 Bad Gateway</html>"), "{}", error);
+    }
+
+    #[test]
+    fn sizes_parse_with_binary_suffixes() {
+        let cases = [
+            ("1048576", Some(1 << 20)),
+            ("512K", Some(512 << 10)),
+            (" 100m ", Some(100 << 20)),
+            ("2G", Some(2 << 30)),
+            ("M", None),
+            ("1.5G", None),
+            ("99999999999G", None),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_size(input).ok(), expected, "{:?}", input);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::accounts;
+use crate::api::{self, RemoteTask, StatusPolicy};
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::eta::QueueEta;
+use crate::fixtures;
+use crate::history;
+use crate::job;
+use crate::paths;
+use crate::poller::StatusPoller;
+use crate::progress::Progress;
+use crate::report::DocumentStatus;
+use crate::session::{self, Session};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: chemtex attach [TASK_ID] [--out FILE] [--record DIR | --replay DIR]
+       chemtex attach --last [--out FILE] [--record DIR | --replay DIR]
+
+Waits for a task that an interrupted compile left running on the server and
+downloads its PDF to the document's output path (or FILE). Without a task
+id, the most recently interrupted job is picked from the history.
+
+--last resumes the latest run that was cut short by a crash or power loss,
+from its session journal: it downloads the PDF if the run had got that far,
+or else waits for its task, without uploading the document again. A run
+that stopped before its upload finished has nothing to resume and must be
+compiled again.";
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["help", "last"], &["out", "record", "replay"])?;
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    fixtures::configure(&args)?;
+    if args.flag("last") {
+        return resume_last(args.value("out")).await;
+    }
+    let entries = history::load()?;
+    let entry = match args.positional(0) {
+        Some(task_id) => entries
+            .iter()
+            .rev()
+            .find(|entry| entry.document.task_id.as_deref() == Some(task_id)),
+        None => entries.iter().rev().find(|entry| {
+            entry.document.status == DocumentStatus::Cancelled && entry.document.task_id.is_some()
+        }),
+    };
+    let task_id = match (args.positional(0), entry) {
+        (Some(task_id), _) => task_id.to_string(),
+        (None, Some(entry)) => entry.document.task_id.clone().unwrap_or_default(),
+        (None, None) => anyhow::bail!("No interrupted job with a task id in the history"),
+    };
+    let output = match (args.value("out"), entry) {
+        (Some(out), _) => PathBuf::from(out),
+        (None, Some(entry)) => PathBuf::from(&entry.document.output),
+        (None, None) => PathBuf::from(paths::portable_name(&format!("{}.pdf", task_id))),
+    };
+
+    let (status_policy, account) = match entry {
+        Some(entry) => {
+            let input = Path::new(&entry.document.input);
+            (
+                ProjectConfig::status_for(input)?,
+                accounts::for_input(input)?,
+            )
+        }
+        None => (StatusPolicy::default(), accounts::chosen()),
+    };
+
+    let client = api::build_client()?;
+    println!("Attaching to task {}...", task_id);
+    let task = RemoteTask {
+        id: task_id,
+        account,
+    };
+    let completed = api::poll_status(
+        &client,
+        &task,
+        &StatusPoller::default(),
+        &status_policy,
+        &Progress::default(),
+        &QueueEta::from_history(),
+        "",
+    )
+    .await?;
+    let pdf = api::download_pdf(&client, &task.account, &completed.download_url).await?;
+    job::write_output(&output, &pdf)?;
+    println!("PDF saved to: {}", output.display());
+    Ok(())
+}
+
+/// Finishes the newest run left in the session journal.
+async fn resume_last(out: Option<&str>) -> Result<()> {
+    let mut session = None;
+    for path in session::abandoned()? {
+        let Some(found) = Session::resume(&path)? else {
+            continue;
+        };
+        if found.record().task_id.is_some() {
+            session = Some(found);
+            break;
+        }
+        // Dropping it clears the journal: there is no task to attach to.
+        println!(
+            "{} was interrupted before its upload finished; compile it again",
+            found.record().input.display()
+        );
+    }
+    let Some(session) = session else {
+        anyhow::bail!("No interrupted run with a task in the session journal");
+    };
+    let record = session.record();
+    let task_id = record.task_id.clone().unwrap_or_default();
+    let output = out
+        .map(PathBuf::from)
+        .unwrap_or_else(|| record.output.clone());
+
+    let task = RemoteTask {
+        id: task_id,
+        account: accounts::for_input(&record.input)?,
+    };
+    let client = api::build_client()?;
+    let mut pdf = None;
+    if let Some(url) = &record.download_url {
+        println!("Resuming the download of {}...", record.input.display());
+        match api::download_pdf(&client, &task.account, url).await {
+            Ok(bytes) => pdf = Some(bytes),
+            Err(e) => println!("Download failed ({:#}); asking for the task again", e),
+        }
+    }
+    let pdf = match pdf {
+        Some(pdf) => pdf,
+        None => {
+            println!(
+                "Attaching to task {} of {}...",
+                task.id,
+                record.input.display()
+            );
+            let completed = api::poll_status(
+                &client,
+                &task,
+                &StatusPoller::default(),
+                &ProjectConfig::status_for(&record.input)?,
+                &Progress::default(),
+                &QueueEta::from_history(),
+                "",
+            )
+            .await?;
+            api::download_pdf(&client, &task.account, &completed.download_url).await?
+        }
+    };
+    job::write_output(&output, &pdf)?;
+    println!("PDF saved to: {}", output.display());
+    Ok(())
+}
@@ -0,0 +1,365 @@
+//! Signed build attestations: `--sign` signs the provenance record of each
+//! PDF (see [`crate::provenance`]) with a local Ed25519 key, and `chemtex
+//! verify report.pdf` checks the signature, the PDF against the hash it
+//! records and, with `--sources DIR`, the sources too.
+//!
+//! Signatures and public keys are in minisign's formats, with its legacy
+//! algorithm (Ed25519 over the whole file): `report.provenance.json.minisig`
+//! carries the PDF's hash in its trusted comment. The secret key is kept
+//! unencrypted in `<data dir>/signing.key`, readable by its owner only.
+
+use crate::cache;
+use crate::cli::Args;
+use crate::provenance::{self, FileHash, Provenance};
+use crate::storage;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, Signer as _, SigningKey, Verifier as _, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECRET_KEY_FILE: &str = "signing.key";
+const PUBLIC_KEY_FILE: &str = "signing.pub";
+/// minisign's name for Ed25519 over the whole file.
+const ALGORITHM: &[u8; 2] = b"Ed";
+
+const KEYS_USAGE: &str = "\
+Usage: chemtex keys generate [--force]
+       chemtex keys public
+
+`generate` creates the Ed25519 key `--sign` signs provenance records with,
+in <data dir>/signing.key, and its public key in signing.pub; hand the
+public key to whoever checks your PDFs with `chemtex verify`. `public`
+prints it.";
+
+const VERIFY_USAGE: &str = "\
+Usage: chemtex verify <report.pdf> [--key chemtex.pub] [--sources DIR]
+
+Checks report.provenance.json.minisig against the public key (your own
+without --key), then the PDF against the hash its provenance records.
+--sources DIR also checks every source file it lists, relative to DIR (the
+directory of the main file).";
+
+/// A secret key loaded for `--sign`.
+#[derive(Debug, Clone)]
+pub struct Signer {
+    key_id: [u8; 8],
+    key: SigningKey,
+}
+
+#[derive(Debug)]
+struct PublicKey {
+    key_id: [u8; 8],
+    key: VerifyingKey,
+}
+
+impl Signer {
+    /// The key in the data directory, which `chemtex keys generate` creates.
+    pub fn load_default() -> Result<Self> {
+        let path = storage::data_dir()?.join(SECRET_KEY_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                anyhow::bail!("No signing key; create one with `chemtex keys generate`")
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let (key_id, seed) =
+            parse_key(&text).with_context(|| format!("Invalid signing key: {}", path.display()))?;
+        Ok(Self {
+            key_id,
+            key: SigningKey::from_bytes(&seed.try_into().expect("length checked")),
+        })
+    }
+
+    fn public(&self) -> PublicKey {
+        PublicKey {
+            key_id: self.key_id,
+            key: self.key.verifying_key(),
+        }
+    }
+
+    /// Signs the provenance record at `path`, writing `<path>.minisig`.
+    pub fn sign(&self, path: &Path, provenance: &Provenance) -> Result<PathBuf> {
+        let contents =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let signature = self.key.sign(&contents).to_bytes();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let trusted = format!(
+            "timestamp:{}\tfile:{}\tpdf_sha256:{}",
+            timestamp,
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            provenance.output.sha256
+        );
+        let global = self
+            .key
+            .sign(&[&signature[..], trusted.as_bytes()].concat())
+            .to_bytes();
+        let text = format!(
+            "untrusted comment: chemtex signature from key {}\n{}\ntrusted comment: {}\n{}\n",
+            key_id_hex(&self.key_id),
+            STANDARD.encode([&ALGORITHM[..], &self.key_id, &signature].concat()),
+            trusted,
+            STANDARD.encode(global)
+        );
+        let sig_path = signature_path(path);
+        fs::write(&sig_path, text)
+            .with_context(|| format!("Failed to write {}", sig_path.display()))?;
+        Ok(sig_path)
+    }
+}
+
+impl PublicKey {
+    fn parse(text: &str) -> Result<Self> {
+        let (key_id, bytes) = parse_key(text)?;
+        let key = VerifyingKey::from_bytes(&bytes.try_into().expect("length checked"))
+            .context("Not an Ed25519 public key")?;
+        Ok(Self { key_id, key })
+    }
+
+    fn to_minisign(&self) -> String {
+        format!(
+            "untrusted comment: minisign public key {}\n{}\n",
+            key_id_hex(&self.key_id),
+            STANDARD.encode([&ALGORITHM[..], &self.key_id, self.key.as_bytes()].concat())
+        )
+    }
+
+    /// Checks `signature` (the text of a `.minisig` file) over `contents`
+    /// and returns its trusted comment.
+    fn verify(&self, contents: &[u8], signature: &str) -> Result<String> {
+        let mut lines = signature.lines().filter(|line| !line.trim().is_empty());
+        let (Some(_untrusted), Some(sig), Some(trusted), Some(global)) =
+            (lines.next(), lines.next(), lines.next(), lines.next())
+        else {
+            anyhow::bail!("Truncated signature file");
+        };
+        let sig = STANDARD.decode(sig.trim())?;
+        anyhow::ensure!(sig.len() == 74, "Invalid signature");
+        anyhow::ensure!(
+            &sig[..2] == ALGORITHM,
+            "Unsupported signature algorithm (only Ed25519 over the whole file)"
+        );
+        anyhow::ensure!(
+            sig[2..10] == self.key_id,
+            "Signed by key {}, not {}",
+            key_id_hex(sig[2..10].try_into().expect("8 bytes")),
+            key_id_hex(&self.key_id)
+        );
+        let signature = Signature::from_slice(&sig[10..]).context("Invalid signature")?;
+        self.key
+            .verify(contents, &signature)
+            .context("The signature does not match the provenance record")?;
+
+        let trusted = trusted
+            .strip_prefix("trusted comment: ")
+            .context("Missing trusted comment")?;
+        let global = Signature::from_slice(&STANDARD.decode(global.trim())?)
+            .context("Invalid signature of the trusted comment")?;
+        self.key
+            .verify(&[&sig[10..], trusted.as_bytes()].concat(), &global)
+            .context("The trusted comment was altered")?;
+        Ok(trusted.to_string())
+    }
+}
+
+pub fn signature_path(provenance: &Path) -> PathBuf {
+    let mut name = provenance.as_os_str().to_os_string();
+    name.push(".minisig");
+    PathBuf::from(name)
+}
+
+/// The key id and key bytes of a minisign-style key file: a comment line,
+/// then base64 of `Ed`, the 8-byte key id and the 32-byte key.
+fn parse_key(text: &str) -> Result<([u8; 8], Vec<u8>)> {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("untrusted comment:"))
+        .context("Empty key file")?;
+    let bytes = STANDARD.decode(line)?;
+    anyhow::ensure!(
+        bytes.len() == 2 + 8 + 32 && &bytes[..2] == ALGORITHM,
+        "Not an Ed25519 key"
+    );
+    Ok((
+        bytes[2..10].try_into().expect("8 bytes"),
+        bytes[10..].to_vec(),
+    ))
+}
+
+/// minisign shows key ids as the hex of a little-endian number.
+fn key_id_hex(key_id: &[u8; 8]) -> String {
+    format!("{:016X}", u64::from_le_bytes(*key_id))
+}
+
+pub fn run_keys(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["force"], &[])?;
+    let dir = storage::data_dir()?;
+    let secret = dir.join(SECRET_KEY_FILE);
+    let public = dir.join(PUBLIC_KEY_FILE);
+    match args.positional(0) {
+        Some("generate") => {
+            if secret.exists() && !args.flag("force") {
+                anyhow::bail!(
+                    "{} exists; --force replaces it, and PDFs signed with it can no longer \
+                     be checked against the new key",
+                    secret.display()
+                );
+            }
+            let mut seed = [0u8; 32];
+            let mut key_id = [0u8; 8];
+            getrandom::getrandom(&mut seed)
+                .and_then(|()| getrandom::getrandom(&mut key_id))
+                .map_err(|e| anyhow::anyhow!("No randomness for the key: {}", e))?;
+            let signer = Signer {
+                key_id,
+                key: SigningKey::from_bytes(&seed),
+            };
+            storage::ensure_dir(&dir)?;
+            storage::write_private(
+                &secret,
+                format!(
+                    "untrusted comment: chemtex secret key {}\n{}\n",
+                    key_id_hex(&key_id),
+                    STANDARD.encode([&ALGORITHM[..], &key_id, &seed].concat())
+                )
+                .as_bytes(),
+            )?;
+            let public_key = signer.public().to_minisign();
+            fs::write(&public, &public_key)
+                .with_context(|| format!("Failed to write {}", public.display()))?;
+            println!("Secret key: {}", secret.display());
+            println!("Public key: {}\n", public.display());
+            print!("{}", public_key);
+            Ok(())
+        }
+        Some("public") => {
+            print!("{}", Signer::load_default()?.public().to_minisign());
+            Ok(())
+        }
+        _ => anyhow::bail!(KEYS_USAGE),
+    }
+}
+
+pub fn run_verify(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &["key", "sources"])?;
+    let pdf = PathBuf::from(args.positional(0).context(VERIFY_USAGE)?);
+    let public = match args.value("key") {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read public key: {}", path))?;
+            PublicKey::parse(&text).with_context(|| format!("Invalid public key: {}", path))?
+        }
+        None => Signer::load_default()?.public(),
+    };
+
+    let record_path = provenance::path_for(&pdf);
+    let record = fs::read(&record_path)
+        .with_context(|| format!("No provenance record: {}", record_path.display()))?;
+    let sig_path = signature_path(&record_path);
+    let signature = fs::read_to_string(&sig_path)
+        .with_context(|| format!("No signature: {}", sig_path.display()))?;
+    let trusted = public
+        .verify(&record, &signature)
+        .with_context(|| format!("Bad signature: {}", sig_path.display()))?;
+    println!(
+        "Good signature by key {} ({})",
+        key_id_hex(&public.key_id),
+        trusted
+    );
+
+    let provenance: Provenance = serde_json::from_slice(&record)
+        .with_context(|| format!("Invalid provenance record: {}", record_path.display()))?;
+    let actual = FileHash::of(&pdf, provenance.output.path.clone())?;
+    anyhow::ensure!(
+        actual == provenance.output,
+        "{} is not the PDF the provenance describes (sha256 {}, expected {})",
+        pdf.display(),
+        actual.sha256,
+        provenance.output.sha256
+    );
+    println!(
+        "{} matches its provenance: task {}, {} source file(s), built {}",
+        pdf.display(),
+        provenance.task_id.as_deref().unwrap_or("(cached)"),
+        provenance.sources.len(),
+        chrono::DateTime::from_timestamp(provenance.finished_at as i64, 0)
+            .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_default()
+    );
+
+    if let Some(dir) = args.value("sources") {
+        let mismatched: Vec<&str> = provenance
+            .sources
+            .iter()
+            .filter(|source| {
+                let path = Path::new(dir).join(&source.path);
+                fs::read(path).map_or(true, |contents| {
+                    cache::to_hex(&Sha256::digest(&contents)) != source.sha256
+                })
+            })
+            .map(|source| source.path.as_str())
+            .collect();
+        anyhow::ensure!(
+            mismatched.is_empty(),
+            "Sources in {} differ from the ones the PDF was built from: {}",
+            dir,
+            mismatched.join(", ")
+        );
+        println!(
+            "All {} source file(s) in {} match",
+            provenance.sources.len(),
+            dir
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_verify_only_for_the_signed_contents() {
+        let signer = Signer {
+            key_id: *b"chemtex!",
+            key: SigningKey::from_bytes(&[7; 32]),
+        };
+        let dir = std::env::temp_dir().join(format!("chemtex-attest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("doc.provenance.json");
+        fs::write(&path, "{}").unwrap();
+        let provenance = Provenance {
+            output: FileHash {
+                path: "doc.pdf".to_string(),
+                sha256: "ab".repeat(32),
+                bytes: 1,
+            },
+            ..serde_json::from_str(
+                r#"{"format_version": 1, "tool": "chemtex", "tool_version": "0",
+                "document": "doc", "output": {"path": "", "sha256": "", "bytes": 0},
+                "sources": [], "engine": null, "profile": null, "server": "",
+                "tex": null, "task_id": null, "cached": false, "build_key": null,
+                "started_at": 0, "finished_at": 0}"#,
+            )
+            .unwrap()
+        };
+        let sig_path = signer.sign(&path, &provenance).unwrap();
+        let signature = fs::read_to_string(&sig_path).unwrap();
+        let public = PublicKey::parse(&signer.public().to_minisign()).unwrap();
+
+        let trusted = public.verify(b"{}", &signature).unwrap();
+        assert!(trusted.ends_with(&format!("pdf_sha256:{}", "ab".repeat(32))));
+        assert!(public.verify(b"{ }", &signature).is_err());
+        let forged = signature.replace("pdf_sha256:ab", "pdf_sha256:cd");
+        assert!(public.verify(b"{}", &forged).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
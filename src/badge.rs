@@ -0,0 +1,206 @@
+//! Build status badges for READMEs: after a compile (or batch) with
+//! `--badge build.svg`, or a `[badge]` section in `.chemtex.toml`, an SVG in
+//! the usual flat style says whether the document built, how many pages it
+//! has and when it was built. `--badge-url` (or `url`) also PUTs the badge
+//! there, for a bucket or web server the README links to.
+//!
+//! ```toml
+//! [badge]
+//! path = "docs/build.svg"
+//! url = "https://files.example.org/badges/titration.svg"
+//! ```
+
+use crate::cli::Args;
+use crate::count;
+use crate::job::JobReport;
+use crate::redact;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const PASSING_COLOR: &str = "#4c1";
+const FAILING_COLOR: &str = "#e05d44";
+const LABEL_COLOR: &str = "#555";
+/// Average advance of Verdana 11px, which the badge is set in.
+const CHAR_WIDTH: f64 = 6.5;
+const PADDING: f64 = 10.0;
+
+/// Where the badge goes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BadgeConfig {
+    /// Relative to the project directory in `.chemtex.toml`.
+    pub path: Option<PathBuf>,
+    pub url: Option<String>,
+}
+
+impl BadgeConfig {
+    /// `--badge` and `--badge-url`, over `config` from `.chemtex.toml`;
+    /// none when neither says where to put it.
+    pub fn from_args(args: &Args, config: Option<BadgeConfig>) -> Option<Self> {
+        let mut badge = config.unwrap_or_default();
+        if let Some(path) = args.value("badge") {
+            badge.path = Some(PathBuf::from(path));
+        }
+        if let Some(url) = args.value("badge-url") {
+            badge.url = Some(url.to_string());
+        }
+        (badge.path.is_some() || badge.url.is_some()).then_some(badge)
+    }
+
+    /// The config with its path taken relative to `dir`.
+    pub fn relative_to(mut self, dir: &Path) -> Self {
+        self.path = self.path.map(|path| dir.join(path));
+        self
+    }
+}
+
+/// What a badge says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Badge {
+    pub label: String,
+    pub passing: bool,
+    pub pages: Option<u32>,
+    pub built: DateTime<Local>,
+}
+
+impl Badge {
+    /// The state of `reports`: passing when all of them compiled, with the
+    /// pages of every PDF.
+    pub fn from_reports(label: &str, reports: &[JobReport]) -> Self {
+        let pages = reports
+            .iter()
+            .filter_map(|report| report.result.as_ref().ok())
+            .map(|output| {
+                fs::read(output)
+                    .ok()
+                    .and_then(|pdf| count::page_count(&pdf))
+            })
+            .sum::<Option<u32>>();
+        Self {
+            label: label.to_string(),
+            passing: reports.iter().all(JobReport::is_success),
+            pages: pages.filter(|_| !reports.is_empty()),
+            built: Local::now(),
+        }
+    }
+
+    /// `passing | 12 pages | 2026-10-16`.
+    pub fn message(&self) -> String {
+        let mut parts = vec![match self.passing {
+            true => "passing".to_string(),
+            false => "failing".to_string(),
+        }];
+        if let (true, Some(pages)) = (self.passing, self.pages) {
+            parts.push(format!(
+                "{} page{}",
+                pages,
+                if pages == 1 { "" } else { "s" }
+            ));
+        }
+        parts.push(self.built.format("%Y-%m-%d").to_string());
+        parts.join(" | ")
+    }
+
+    pub fn svg(&self) -> String {
+        let message = self.message();
+        let width = |text: &str| (text.chars().count() as f64 * CHAR_WIDTH + PADDING).round();
+        let (label_width, message_width) = (width(&self.label), width(&message));
+        let total = label_width + message_width;
+        let color = if self.passing {
+            PASSING_COLOR
+        } else {
+            FAILING_COLOR
+        };
+        let (label, message) = (escape(&self.label), escape(&message));
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{total}" height="20" role="img" aria-label="{label}: {message}">
+<title>{label}: {message}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{total}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="{LABEL_COLOR}"/><rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/><rect width="{total}" height="20" fill="url(#s)"/></g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14">{label}</text>
+<text x="{message_x}" y="15" fill="#010101" fill-opacity=".3">{message}</text><text x="{message_x}" y="14">{message}</text>
+</g>
+</svg>
+"##,
+            label_x = label_width / 2.0,
+            message_x = label_width + message_width / 2.0,
+        )
+    }
+}
+
+/// Writes the badge to the configured path and uploads it to the URL. A
+/// badge that cannot be placed does not fail the build; it is reported.
+pub async fn place(client: &reqwest::Client, config: &BadgeConfig, badge: &Badge) {
+    let svg = badge.svg();
+    if let Some(path) = &config.path {
+        match write(path, &svg) {
+            Ok(()) => eprintln!("Badge written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write badge: {:#}", e),
+        }
+    }
+    if let Some(url) = &config.url {
+        match upload(client, url, svg).await {
+            Ok(()) => eprintln!("Badge uploaded to {}", redact::text(url)),
+            Err(e) => eprintln!("Failed to upload badge: {:#}", e),
+        }
+    }
+}
+
+fn write(path: &Path, svg: &str) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    fs::write(path, svg).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+async fn upload(client: &reqwest::Client, url: &str, svg: String) -> Result<()> {
+    let response = client
+        .put(url)
+        .header(reqwest::header::CONTENT_TYPE, "image/svg+xml")
+        // Served fresh by caches that honour it, as READMEs are.
+        .header(reqwest::header::CACHE_CONTROL, "no-cache")
+        .body(svg)
+        .send()
+        .await
+        .context("Failed to send the badge")?;
+    let status = response.status();
+    anyhow::ensure!(status.is_success(), "The server answered {}", status);
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn badges_say_whether_the_document_built() {
+        let mut badge = Badge {
+            label: "titration".to_string(),
+            passing: true,
+            pages: Some(12),
+            built: Local.with_ymd_and_hms(2026, 10, 16, 9, 0, 0).unwrap(),
+        };
+        assert_eq!(badge.message(), "passing | 12 pages | 2026-10-16");
+        let svg = badge.svg();
+        assert!(svg.contains(r#"aria-label="titration: passing | 12 pages | 2026-10-16""#));
+        assert!(svg.contains(PASSING_COLOR));
+
+        badge.passing = false;
+        assert_eq!(badge.message(), "failing | 2026-10-16");
+        assert!(badge.svg().contains(FAILING_COLOR));
+    }
+}
@@ -0,0 +1,481 @@
+use crate::cli::Args;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+
+const USAGE: &str = "\
+Usage: chemtex balance \"<reaction>\" [--over TEXT] [--under TEXT]
+
+Balances the reaction and prints it as an mhchem line, e.g.
+  chemtex balance \"Fe(s) + O2(g) -> Fe2O3(s)\"
+  \\ce{4Fe(s) + 3O2(g) -> 2Fe2O3(s)}
+
+Species are separated by \" + \"; states such as (s), (aq) and conditions
+written mhchem-style (->[\\Delta]) are kept. Charges use ^: Fe^3+, SO4^{2-}, e-.
+In sources, a line %%chemtex:balance(<reaction>) is replaced when packing.
+
+Options:
+  --over TEXT   Condition written above the arrow
+  --under TEXT  Condition written below the arrow";
+
+const ARROWS: &[&str] = &["<=>>", "<<=>", "<=>", "<->", "->", "<-", "="];
+const STATES: &[&str] = &["(s)", "(l)", "(g)", "(aq)"];
+/// Pseudo-element balancing the charge.
+pub const CHARGE: &str = "+-";
+const TOO_LARGE: &str = "The coefficients are too large to compute";
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &["over", "under"])?;
+    let reaction = args.positional(0).context(USAGE)?;
+    let mut reaction = Reaction::parse(reaction)?;
+    if let Some(over) = args.value("over") {
+        reaction.conditions = format!("[{}]", over);
+    }
+    if let Some(under) = args.value("under") {
+        if reaction.conditions.is_empty() {
+            reaction.conditions = "[]".to_string();
+        }
+        reaction.conditions.push_str(&format!("[{}]", under));
+    }
+    println!("{}", reaction.balanced()?);
+    Ok(())
+}
+
+/// Balances `reaction` into a `\ce{...}` line.
+pub fn balance(reaction: &str) -> Result<String> {
+    Reaction::parse(reaction)?.balanced()
+}
+
+pub struct Reaction {
+    pub reactants: Vec<Species>,
+    pub products: Vec<Species>,
+    arrow: String,
+    /// mhchem arrow arguments as written: `[above][below]`.
+    conditions: String,
+}
+
+pub struct Species {
+    /// Formula as written, without coefficient or state.
+    pub formula: String,
+    state: String,
+    pub elements: BTreeMap<String, i64>,
+}
+
+impl Reaction {
+    pub fn parse(text: &str) -> Result<Self> {
+        let (left, arrow, right) = split_arrow(text)
+            .with_context(|| format!("No reaction arrow (->, <=>, =) in {:?}", text))?;
+        let (conditions, right) = split_conditions(right);
+        let side = |part: &str| -> Result<Vec<Species>> {
+            part.split(" + ")
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(Species::parse)
+                .collect()
+        };
+        let reaction = Self {
+            reactants: side(left)?,
+            products: side(right)?,
+            // A bare `=` is an equals sign to mhchem.
+            arrow: if arrow == "=" { "->" } else { arrow }.to_string(),
+            conditions: conditions.to_string(),
+        };
+        if reaction.reactants.is_empty() || reaction.products.is_empty() {
+            anyhow::bail!("Both sides of {:?} need at least one species", text);
+        }
+        Ok(reaction)
+    }
+
+    /// The balanced `\ce{...}` line.
+    pub fn balanced(&self) -> Result<String> {
+        let coefficients = self.coefficients()?;
+        let (left, right) = coefficients.split_at(self.reactants.len());
+        let side = |species: &[Species], coefficients: &[i64]| {
+            species
+                .iter()
+                .zip(coefficients)
+                .map(|(s, &c)| match c {
+                    1 => format!("{}{}", s.formula, s.state),
+                    c => format!("{}{}{}", c, s.formula, s.state),
+                })
+                .collect::<Vec<_>>()
+                .join(" + ")
+        };
+        Ok(format!(
+            "\\ce{{{} {}{} {}}}",
+            side(&self.reactants, left),
+            self.arrow,
+            self.conditions,
+            side(&self.products, right)
+        ))
+    }
+
+    /// Smallest positive integer coefficients: the one-dimensional null space
+    /// of the element-by-species matrix (products counted negative), reactants
+    /// first.
+    pub fn coefficients(&self) -> Result<Vec<i64>> {
+        let species: Vec<(&Species, i64)> = self
+            .reactants
+            .iter()
+            .map(|s| (s, 1))
+            .chain(self.products.iter().map(|s| (s, -1)))
+            .collect();
+        let mut elements: Vec<&String> = species
+            .iter()
+            .flat_map(|(s, _)| s.elements.keys())
+            .collect();
+        elements.sort();
+        elements.dedup();
+
+        let mut matrix: Vec<Vec<Fraction>> = elements
+            .iter()
+            .map(|element| {
+                species
+                    .iter()
+                    .map(|(s, sign)| {
+                        Fraction::from(sign * s.elements.get(*element).copied().unwrap_or(0))
+                    })
+                    .collect()
+            })
+            .collect();
+        let columns = species.len();
+        let pivots = reduce(&mut matrix, columns)?;
+        let free: Vec<usize> = (0..columns).filter(|c| !pivots.contains(c)).collect();
+        let [free] = free.as_slice() else {
+            if free.is_empty() {
+                anyhow::bail!("The reaction cannot be balanced; check the formulas");
+            }
+            anyhow::bail!(
+                "The reaction does not balance uniquely; it combines independent reactions"
+            );
+        };
+
+        let mut solution = vec![Fraction::from(0); columns];
+        solution[*free] = Fraction::from(1);
+        for (row, &pivot) in pivots.iter().enumerate() {
+            solution[pivot] = matrix[row][*free].neg()?;
+        }
+        let denominators = solution
+            .iter()
+            .try_fold(1, |acc, f| lcm(acc, f.denominator))
+            .context(TOO_LARGE)?;
+        let mut integers: Vec<i128> = solution
+            .iter()
+            .map(|f| f.numerator.checked_mul(denominators / f.denominator))
+            .collect::<Option<_>>()
+            .context(TOO_LARGE)?;
+        let divisor = integers.iter().fold(0, |acc, &n| gcd(acc, n.abs()));
+        if integers.iter().any(|&n| n < 0) {
+            integers.iter_mut().for_each(|n| *n = -*n);
+        }
+        if integers.iter().any(|&n| n <= 0) {
+            anyhow::bail!("The reaction cannot be balanced with positive coefficients");
+        }
+        integers
+            .into_iter()
+            .map(|n| i64::try_from(n / divisor).context("Coefficient too large"))
+            .collect()
+    }
+}
+
+/// Splits at the earliest arrow that follows a space.
+pub fn split_arrow(text: &str) -> Option<(&str, &str, &str)> {
+    let mut best: Option<(usize, &str)> = None;
+    for arrow in ARROWS {
+        let pattern = format!(" {}", arrow);
+        if let Some(index) = text.find(&pattern) {
+            if best.is_none_or(|(i, _)| index < i) {
+                best = Some((index, arrow));
+            }
+        }
+    }
+    let (index, arrow) = best?;
+    let after = index + 1 + arrow.len();
+    Some((&text[..index], arrow, &text[after..]))
+}
+
+/// `[above][below] rest` → (`[above][below]`, `rest`).
+pub fn split_conditions(text: &str) -> (&str, &str) {
+    let mut end = 0;
+    while text[end..].starts_with('[') {
+        let mut depth = 0;
+        let Some(close) = text[end..].char_indices().find_map(|(i, c)| {
+            match c {
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(i)
+        }) else {
+            break;
+        };
+        end += close + 1;
+    }
+    (&text[..end], &text[end..])
+}
+
+impl Species {
+    pub fn parse(text: &str) -> Result<Self> {
+        // A coefficient already written is recomputed.
+        let text = text.trim_start_matches(|c: char| c.is_ascii_digit() || c.is_whitespace());
+        let (formula, state) = STATES
+            .iter()
+            .find_map(|state| text.strip_suffix(state).map(|f| (f, *state)))
+            .unwrap_or((text, ""));
+        let elements = parse_formula(formula)
+            .with_context(|| format!("Cannot read the formula {:?}", formula))?;
+        Ok(Self {
+            formula: formula.to_string(),
+            state: state.to_string(),
+            elements,
+        })
+    }
+}
+
+/// Atom counts (and charge, as [`CHARGE`]) of a formula such as `Ca(OH)2`,
+/// `[Fe(CN)6]^{3-}`, `CuSO4*5H2O` or `e-`.
+fn parse_formula(formula: &str) -> Result<BTreeMap<String, i64>> {
+    let (body, charge) = split_charge(formula)?;
+    let mut counts = BTreeMap::new();
+    if body != "e" {
+        // Hydrate dots: `CuSO4*5H2O`, `CuSO4.5H2O`.
+        for part in body.split(['*', '.', '·']) {
+            let digits = part.chars().take_while(char::is_ascii_digit).count();
+            let multiplier = count(&part[..digits])?;
+            let chars: Vec<char> = part[digits..].chars().collect();
+            let mut index = 0;
+            let group = parse_group(&chars, &mut index)?;
+            if index != chars.len() {
+                anyhow::bail!("Unexpected {:?}", chars[index]);
+            }
+            add(&mut counts, group, multiplier)?;
+        }
+    }
+    if charge != 0 {
+        counts.insert(CHARGE.to_string(), charge);
+    }
+    Ok(counts)
+}
+
+/// `SO4^{2-}` → (`SO4`, -2); `Na+` → (`Na`, 1); `e-` → (`e`, -1).
+fn split_charge(formula: &str) -> Result<(&str, i64)> {
+    let (body, charge) = match formula.split_once('^') {
+        Some((body, charge)) => (body, charge.trim_start_matches('{').trim_end_matches('}')),
+        None => match formula.char_indices().last() {
+            Some((i, '+' | '-')) => (&formula[..i], &formula[i..]),
+            _ => return Ok((formula, 0)),
+        },
+    };
+    let sign = match charge.chars().last() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => anyhow::bail!("Charge {:?} needs a sign", charge),
+    };
+    let magnitude = match &charge[..charge.len() - 1] {
+        "" => 1,
+        digits => digits
+            .parse::<i64>()
+            .with_context(|| format!("Bad charge {:?}", charge))?,
+    };
+    Ok((body, sign * magnitude))
+}
+
+fn parse_group(chars: &[char], index: &mut usize) -> Result<BTreeMap<String, i64>> {
+    let mut counts = BTreeMap::new();
+    while let Some(&c) = chars.get(*index) {
+        let group = match c {
+            '(' | '[' => {
+                *index += 1;
+                let inner = parse_group(chars, index)?;
+                match chars.get(*index) {
+                    Some(')' | ']') => *index += 1,
+                    _ => anyhow::bail!("Unclosed {:?}", c),
+                }
+                inner
+            }
+            ')' | ']' => break,
+            c if c.is_ascii_uppercase() => {
+                let mut element = c.to_string();
+                *index += 1;
+                while let Some(&lower) = chars.get(*index).filter(|c| c.is_ascii_lowercase()) {
+                    element.push(lower);
+                    *index += 1;
+                }
+                BTreeMap::from([(element, 1)])
+            }
+            other => anyhow::bail!("Unexpected {:?}", other),
+        };
+        let start = *index;
+        while chars.get(*index).is_some_and(char::is_ascii_digit) {
+            *index += 1;
+        }
+        let multiplier = count(&chars[start..*index].iter().collect::<String>())?;
+        add(&mut counts, group, multiplier)?;
+    }
+    Ok(counts)
+}
+
+/// A subscript or hydrate count; none written is 1.
+fn count(digits: &str) -> Result<i64> {
+    match digits {
+        "" => Ok(1),
+        digits => digits
+            .parse()
+            .with_context(|| format!("The count {} is too large", digits)),
+    }
+}
+
+/// Adds `multiplier` times the atoms of `group` to `counts`.
+fn add(
+    counts: &mut BTreeMap<String, i64>,
+    group: BTreeMap<String, i64>,
+    multiplier: i64,
+) -> Result<()> {
+    for (element, count) in group {
+        let total = counts.entry(element).or_insert(0);
+        *total = count
+            .checked_mul(multiplier)
+            .and_then(|atoms| total.checked_add(atoms))
+            .context("Too many atoms to count")?;
+    }
+    Ok(())
+}
+
+/// Brings `matrix` to reduced row echelon form, dropping all-zero rows;
+/// returns the pivot column of each remaining row.
+fn reduce(matrix: &mut Vec<Vec<Fraction>>, columns: usize) -> Result<Vec<usize>> {
+    let mut pivots = Vec::new();
+    let mut row = 0;
+    for column in 0..columns {
+        let Some(found) = (row..matrix.len()).find(|&r| !matrix[r][column].is_zero()) else {
+            continue;
+        };
+        matrix.swap(row, found);
+        let pivot = matrix[row][column];
+        for value in matrix[row].iter_mut() {
+            *value = value.div(pivot)?;
+        }
+        for other in 0..matrix.len() {
+            let factor = matrix[other][column];
+            if other == row || factor.is_zero() {
+                continue;
+            }
+            let source = matrix[row].clone();
+            for (value, from) in matrix[other].iter_mut().zip(source) {
+                *value = value.sub(factor.mul(from)?)?;
+            }
+        }
+        pivots.push(column);
+        row += 1;
+    }
+    matrix.truncate(row);
+    Ok(pivots)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fraction {
+    numerator: i128,
+    denominator: i128,
+}
+
+impl From<i64> for Fraction {
+    fn from(n: i64) -> Self {
+        Self {
+            numerator: n.into(),
+            denominator: 1,
+        }
+    }
+}
+
+/// Arithmetic fails with [`TOO_LARGE`] rather than overflowing.
+impl Fraction {
+    fn new(numerator: Option<i128>, denominator: Option<i128>) -> Result<Self> {
+        let (numerator, denominator) = numerator.zip(denominator).context(TOO_LARGE)?;
+        let divisor = gcd(numerator.abs(), denominator.abs()).max(1);
+        let sign = denominator.signum();
+        Ok(Self {
+            numerator: sign * numerator / divisor,
+            denominator: sign * denominator / divisor,
+        })
+    }
+
+    fn is_zero(self) -> bool {
+        self.numerator == 0
+    }
+
+    fn neg(self) -> Result<Self> {
+        Self::new(self.numerator.checked_neg(), Some(self.denominator))
+    }
+
+    fn mul(self, other: Self) -> Result<Self> {
+        Self::new(
+            self.numerator.checked_mul(other.numerator),
+            self.denominator.checked_mul(other.denominator),
+        )
+    }
+
+    fn div(self, other: Self) -> Result<Self> {
+        Self::new(
+            self.numerator.checked_mul(other.denominator),
+            self.denominator.checked_mul(other.numerator),
+        )
+    }
+
+    fn sub(self, other: Self) -> Result<Self> {
+        let left = self.numerator.checked_mul(other.denominator);
+        let right = other.numerator.checked_mul(self.denominator);
+        Self::new(
+            left.zip(right).and_then(|(l, r)| l.checked_sub(r)),
+            self.denominator.checked_mul(other.denominator),
+        )
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn lcm(a: i128, b: i128) -> Option<i128> {
+    (a / gcd(a, b).max(1)).checked_mul(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reactions_balance_with_the_smallest_coefficients() {
+        assert_eq!(
+            balance("Fe(s) + O2(g) -> Fe2O3(s)").unwrap(),
+            "\\ce{4Fe(s) + 3O2(g) -> 2Fe2O3(s)}"
+        );
+        assert_eq!(
+            balance("CuSO4*5H2O -> CuSO4 + H2O").unwrap(),
+            "\\ce{CuSO4*5H2O -> CuSO4 + 5H2O}"
+        );
+    }
+
+    #[test]
+    fn counts_too_large_are_errors_not_wrong_equations() {
+        // Overflows i64: read as 1 it would "balance" as H2 -> 2H.
+        let error = balance("H99999999999999999999 -> H").unwrap_err();
+        assert!(format!("{:#}", error).contains("too large"), "{:#}", error);
+
+        let error = balance("(H4611686018427387904)2 -> H").unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("Too many atoms"),
+            "{:#}",
+            error
+        );
+
+        // Coefficients beyond i128 while solving.
+        let error = balance(
+            "Xe9000000000000000001 + Kr9000000000000000007 + Ar9000000000000000011 -> XeKrAr",
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), TOO_LARGE);
+    }
+}
@@ -0,0 +1,765 @@
+use crate::api::{self, BuildMode};
+use crate::attest::Signer;
+use crate::badge::{self, Badge, BadgeConfig};
+use crate::ci::{self, OutputFormat};
+use crate::cli::Args;
+use crate::fixtures;
+use crate::har;
+use crate::job::{self, Job, JobReport, Phase, Runner};
+use crate::local::{self, LocalMode};
+use crate::manifest::Manifest;
+use crate::paths;
+use crate::poller::StatusPoller;
+use crate::progress::Progress;
+use crate::publish;
+use crate::report::{self, BatchReport};
+use crate::shutdown;
+use crate::state::{self, BuildState};
+use crate::window::{self, SubmitWindow};
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+const DEFAULT_JOBS: usize = 4;
+
+const USAGE: &str = "\
+Usage: chemtex batch <dir> [options]
+       chemtex batch --manifest jobs.yaml [options]
+       chemtex batch --retry-failed [options]
+
+Options:
+  --jobs N              Compile up to N documents concurrently (default 4)
+  --out-dir DIR         Write PDFs to DIR
+  --max-rps R           Cap status checks at R requests per second (default 2)
+  --fail-fast           Abort remaining jobs after the first failure
+  --no-cache            Always submit, ignoring the build cache
+  --changed             Only compile documents whose sources changed
+  --retry-failed        Resubmit the failed documents of the last batch
+  --report FILE         Write a JSON report
+  --html-report FILE    Write a self-contained HTML report
+  --junit FILE          Write a JUnit XML report for CI test views
+  --format FORMAT       text (default) or github for Actions annotations
+  --plain               Print a status only when it changes, for screen
+                        readers and logs
+  --cancel-on-interrupt On Ctrl-C, cancel the uploaded tasks instead of
+                        leaving them for `chemtex attach`
+  --no-validate         Upload .tex files without \\documentclass too
+  --provenance          Write a provenance JSON next to every PDF
+  --sign                Also sign it (see `chemtex keys`)
+  --local               Compile with Tectonic on this machine (the
+                        `tectonic` program must be installed)
+  --draft               Build drafts: locally for the manifest's profiles
+                        with `draft: local`, on the server otherwise
+  --final               Build final copies on the server, with every pass
+                        and the bibliography, never locally
+  --priority P          Ask the server to queue the documents at priority P
+  --submit-window HH:MM-HH:MM
+                        Wait for off-peak hours before submitting, starting
+                        at the window's quietest hour by the history, unless
+                        --priority is urgent or high
+  --tag KEY=VALUE       Label the documents in the server's metadata and the
+                        history (repeatable)
+  --publish github:OWNER/REPO@TAG
+                        Upload every PDF to that GitHub release (see
+                        `chemtex publish`)
+  --badge FILE          Write a status badge for a README: passing or
+                        failing, total pages and the build date
+  --badge-url URL       PUT the badge to URL
+  --record DIR          Save the server's responses in DIR
+  --replay DIR          Answer requests from a recording in DIR, offline
+  --capture-http FILE   Write the HTTP session as a HAR file
+  --capture-bodies MODE full, truncated (default) or none: how much of the
+                        response bodies goes into the HAR file";
+
+/// Flags accepted by every command that compiles a set of documents through
+/// [`compile`].
+pub const COMPILE_FLAGS: &[&str] = &[
+    "fail-fast",
+    "no-cache",
+    "changed",
+    "plain",
+    "cancel-on-interrupt",
+    "no-validate",
+    "provenance",
+    "sign",
+    "local",
+    "draft",
+    "final",
+];
+/// Options accepted by every command that compiles through [`compile`].
+pub const COMPILE_OPTIONS: &[&str] = &[
+    "jobs",
+    "out-dir",
+    "report",
+    "html-report",
+    "junit",
+    "max-rps",
+    "format",
+    "record",
+    "replay",
+    "capture-http",
+    "capture-bodies",
+    "priority",
+    "tag",
+    "submit-window",
+    "publish",
+    "badge",
+    "badge-url",
+];
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &[COMPILE_FLAGS, &["retry-failed"]].concat(),
+        &[COMPILE_OPTIONS, &["manifest"]].concat(),
+    )?;
+    let out_dir = args.value("out-dir").map(PathBuf::from);
+
+    let (jobs, source) = match (args.value("manifest"), args.positional(0)) {
+        (None, None) if args.flag("retry-failed") => {
+            let path = report::last_report_path()?;
+            let jobs = BatchReport::load(&path)?.failed_jobs()?;
+            if jobs.is_empty() {
+                println!("No failed documents in the last batch report");
+                return Ok(());
+            }
+            (jobs, format!("failed jobs of {}", path.display()))
+        }
+        _ if args.flag("retry-failed") => anyhow::bail!(USAGE),
+        (Some(manifest_path), None) => {
+            let manifest_path = Path::new(manifest_path);
+            let jobs = Manifest::load(manifest_path)?.into_jobs(
+                manifest_path,
+                out_dir.as_deref(),
+                BuildMode::from_args(&args)?,
+            )?;
+            (jobs, manifest_path.display().to_string())
+        }
+        (None, Some(dir)) => {
+            let dir = PathBuf::from(dir);
+            let jobs = jobs_from_dir(&dir, &out_dir.unwrap_or_default())?;
+            (jobs, dir.display().to_string())
+        }
+        _ => anyhow::bail!(USAGE),
+    };
+
+    compile(&args, jobs, &source).await
+}
+
+/// Compiles `jobs` according to the shared batch options: skips up-to-date
+/// documents with `--changed`, prints the summary, writes the requested
+/// reports and fails if any document did not compile.
+pub async fn compile(args: &Args, mut jobs: Vec<Job>, source: &str) -> Result<()> {
+    let max_jobs = args.parsed::<usize>("jobs")?.unwrap_or(DEFAULT_JOBS).max(1);
+    let format = OutputFormat::parse(args.value("format"))?;
+    if format == OutputFormat::Json {
+        anyhow::bail!("--format json is for single compiles; use --report FILE for a batch");
+    }
+    let publish_target = publish::target(args)?;
+    let submit_window = args
+        .value("submit-window")
+        .map(SubmitWindow::parse)
+        .transpose()?;
+
+    let mode = BuildMode::from_args(args)?;
+    for job in &mut jobs {
+        job.options.set_labels(args)?;
+        job.options.mode = mode;
+    }
+    if jobs.iter().any(|job| job.local) && !local::available() {
+        println!("Tectonic is not available, so drafts are built on the server");
+        for job in &mut jobs {
+            job.local = false;
+        }
+    }
+    let renamed = disambiguate_outputs(&mut jobs);
+    if !renamed.is_empty() {
+        println!(
+            "{} document(s) would write the same PDF as another; writing instead:",
+            renamed.len()
+        );
+        for (name, output) in &renamed {
+            println!("  {} -> {}", name, output.display());
+        }
+    }
+
+    // Read only to skip documents; saved after every batch all the same.
+    let state = match args.flag("changed") {
+        true => Some(BuildState::load_default()?),
+        false => None,
+    };
+    let mut fingerprints: Vec<(Job, Option<String>)> = jobs
+        .into_iter()
+        .map(|job| {
+            let fingerprint = state::fingerprint(&job).ok();
+            (job, fingerprint)
+        })
+        .collect();
+
+    let found = fingerprints.len();
+    if let Some(state) = &state {
+        let mut stale: Vec<bool> = fingerprints
+            .iter()
+            .map(|(job, fingerprint)| {
+                !fingerprint
+                    .as_deref()
+                    .is_some_and(|f| state.is_up_to_date(job, f))
+            })
+            .collect();
+        // A job is rebuilt when a job it depends on is.
+        loop {
+            let rebuilt: HashSet<&str> = fingerprints
+                .iter()
+                .zip(&stale)
+                .filter(|(_, stale)| **stale)
+                .map(|((job, _), _)| job.name.as_str())
+                .collect();
+            let newly: Vec<usize> = (0..fingerprints.len())
+                .filter(|&i| {
+                    !stale[i]
+                        && fingerprints[i]
+                            .0
+                            .depends_on
+                            .iter()
+                            .any(|d| rebuilt.contains(d.as_str()))
+                })
+                .collect();
+            if newly.is_empty() {
+                break;
+            }
+            for i in newly {
+                stale[i] = true;
+            }
+        }
+        let mut stale = stale.into_iter();
+        fingerprints.retain(|(job, _)| {
+            let up_to_date = !stale.next().unwrap_or(true);
+            if up_to_date {
+                println!("[{}] Up to date, skipping", job.name);
+            }
+            !up_to_date
+        });
+    }
+    if fingerprints.is_empty() {
+        println!("All {} document(s) are up to date", found);
+        return Ok(());
+    }
+    let (jobs, fingerprints): (Vec<Job>, Vec<Option<String>>) = fingerprints.into_iter().unzip();
+
+    println!(
+        "Found {} document(s) in {}, compiling {} with up to {} concurrent job(s)",
+        found,
+        source,
+        jobs.len(),
+        max_jobs
+    );
+    if let Some(submit_window) = submit_window {
+        if window::is_urgent(args.value("priority")) {
+            println!(
+                "Submitting now: --priority {} is not deferred",
+                args.value("priority").unwrap_or_default()
+            );
+        } else {
+            let plan = submit_window.plan(Local::now());
+            if !plan.delay().is_zero() {
+                println!(
+                    "Waiting for the submit window {}: submitting {}",
+                    submit_window,
+                    window::describe(&plan)
+                );
+                window::wait(&plan).await?;
+            }
+        }
+    }
+
+    fixtures::configure(args)?;
+    har::configure(args)?;
+    let use_cache = !args.flag("no-cache") && !fixtures::active();
+    let mut runner = Runner::new(api::build_client()?, use_cache)?;
+    if args.flag("plain") {
+        runner.progress = Progress::plain();
+    }
+    runner.cancel_on_interrupt = args.flag("cancel-on-interrupt");
+    runner.skip_validation = args.flag("no-validate");
+    runner.provenance = args.flag("provenance");
+    if args.flag("sign") {
+        runner.signer = Some(Signer::load_default()?);
+    }
+    if args.flag("local") {
+        local::check_supported()?;
+        runner.local = LocalMode::Always;
+    }
+    if let Some(max_rps) = args.parsed::<f64>("max-rps")? {
+        runner.poller = StatusPoller::new(max_rps).context("Invalid --max-rps")?;
+    }
+    runner.collect_diagnostics = format == OutputFormat::Github || args.value("junit").is_some();
+    let reports = run_jobs(&runner, jobs, max_jobs, args.flag("fail-fast")).await;
+
+    print_table(&reports);
+    print_phase_totals(&reports);
+    print_failures(&reports);
+    if format == OutputFormat::Github {
+        report_to_github(&reports)?;
+    }
+
+    let saved = state
+        .map_or_else(BuildState::load_default, Ok)
+        .and_then(|mut state| {
+            for (report, fingerprint) in reports.iter().zip(fingerprints) {
+                if let (true, Some(fingerprint)) = (report.is_success(), fingerprint) {
+                    state.record(&report.job.input, fingerprint);
+                }
+            }
+            state.save()
+        });
+    if let Err(e) = saved {
+        eprintln!("Failed to save build state: {:#}", e);
+    }
+
+    let report = BatchReport::from_reports(&reports);
+    if let Err(e) = report.save_as_last() {
+        eprintln!("Failed to save batch report: {:#}", e);
+    }
+    if let Some(path) = args.value("report") {
+        report.write_json(Path::new(path))?;
+        println!("JSON report written to {}", path);
+    }
+    if let Some(path) = args.value("html-report") {
+        report.write_html(Path::new(path))?;
+        println!("HTML report written to {}", path);
+    }
+    if let Some(path) = args.value("junit") {
+        report.write_junit(Path::new(path))?;
+        println!("JUnit report written to {}", path);
+    }
+
+    if let Some(config) = BadgeConfig::from_args(args, None) {
+        let badge = Badge::from_reports("documents", &reports);
+        badge::place(&runner.client, &config, &badge).await;
+    }
+
+    let mut unpublished = 0;
+    if let Some(target) = &publish_target {
+        for output in reports.iter().filter_map(|r| r.result.as_ref().ok()) {
+            if let Err(e) = publish::publish(&runner.client, target, output).await {
+                eprintln!("Failed to publish {}: {:#}", output.display(), e);
+                unpublished += 1;
+            }
+        }
+    }
+
+    let failed = reports.iter().filter(|r| !r.is_success()).count();
+    if unpublished > 0 && failed == 0 {
+        anyhow::bail!("{} PDF(s) could not be published", unpublished);
+    }
+    if failed > 0 {
+        anyhow::bail!(
+            "{} of {} document(s) did not compile",
+            failed,
+            reports.len()
+        );
+    }
+    Ok(())
+}
+
+/// Runs `jobs` with at most `max_jobs` in flight, returning reports in input order.
+///
+/// A job starts once the jobs it depends on (by name, among `jobs`) have
+/// compiled; when one fails, the jobs depending on it are reported blocked
+/// without being started. Dependencies on jobs not in `jobs` are taken as
+/// met.
+///
+/// With `fail_fast`, the first failure aborts every remaining job and asks the
+/// server to cancel tasks that were already uploaded. SIGINT or SIGTERM
+/// aborts them too, leaving their tasks to `chemtex attach` (see
+/// [`Runner::interrupted`]).
+pub async fn run_jobs(
+    runner: &Runner,
+    jobs: Vec<Job>,
+    max_jobs: usize,
+    fail_fast: bool,
+) -> Vec<JobReport> {
+    let started = Instant::now();
+    let semaphore = Arc::new(Semaphore::new(max_jobs));
+    let pending: Vec<Job> = jobs.clone();
+    let mut set = JoinSet::new();
+    let spawn = |set: &mut JoinSet<(usize, JobReport)>, index: usize| {
+        let runner = runner.clone();
+        let semaphore = Arc::clone(&semaphore);
+        let job = pending[index].clone();
+        set.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let label = format!("[{}] ", job.name);
+            (index, runner.run(&job, &label).await)
+        });
+    };
+
+    let by_name: HashMap<&str, usize> = pending
+        .iter()
+        .enumerate()
+        .map(|(i, job)| (job.name.as_str(), i))
+        .collect();
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); pending.len()];
+    let mut unmet: Vec<usize> = vec![0; pending.len()];
+    for (i, job) in pending.iter().enumerate() {
+        for dependency in &job.depends_on {
+            if let Some(&d) = by_name.get(dependency.as_str()).filter(|&&d| d != i) {
+                dependents[d].push(i);
+                unmet[i] += 1;
+            }
+        }
+    }
+    for index in (0..pending.len()).filter(|&i| unmet[i] == 0) {
+        spawn(&mut set, index);
+    }
+
+    let mut reports: Vec<Option<JobReport>> = pending.iter().map(|_| None).collect();
+    let mut interrupted = false;
+    let mut aborted = false;
+    loop {
+        let joined = tokio::select! {
+            joined = set.join_next() => match joined {
+                Some(joined) => joined,
+                None => break,
+            },
+            () = shutdown::requested(), if !interrupted => {
+                println!("Interrupted, stopping all jobs");
+                set.abort_all();
+                interrupted = true;
+                continue;
+            }
+        };
+        match joined {
+            Ok((index, report)) => {
+                let failed = !report.is_success();
+                reports[index] = Some(report);
+                if failed && fail_fast && !interrupted && !aborted {
+                    println!("Job failed, aborting remaining jobs (--fail-fast)");
+                    set.abort_all();
+                    aborted = true;
+                }
+                if interrupted || aborted {
+                    continue;
+                }
+                if failed {
+                    block_dependents(index, &pending, &dependents, &mut reports);
+                } else {
+                    for &d in &dependents[index] {
+                        unmet[d] -= 1;
+                        if unmet[d] == 0 && reports[d].is_none() {
+                            spawn(&mut set, d);
+                        }
+                    }
+                }
+            }
+            Err(e) if e.is_cancelled() => {}
+            Err(e) => eprintln!("Batch worker panicked: {}", e),
+        }
+    }
+
+    if interrupted {
+        for (report, job) in reports.iter_mut().zip(&pending) {
+            if report.is_none() {
+                let label = format!("[{}] ", job.name);
+                *report = Some(
+                    runner
+                        .interrupted(job.clone(), &label, started.elapsed())
+                        .await,
+                );
+            }
+        }
+    }
+
+    let mut cancelled_tasks = Vec::with_capacity(pending.len());
+    for job in &pending {
+        let task = runner.in_flight.take(job.id);
+        if let Some(task) = &task {
+            match api::cancel_task(&runner.client, task).await {
+                Ok(()) => println!("[{}] Cancelled remote task {}", job.name, task.id),
+                Err(e) => eprintln!("[{}] Failed to cancel task {}: {:#}", job.name, task.id, e),
+            }
+        }
+        cancelled_tasks.push(task.map(|task| task.id));
+    }
+
+    reports
+        .into_iter()
+        .zip(pending)
+        .zip(cancelled_tasks)
+        .map(|((report, job), task_id)| {
+            report.unwrap_or_else(|| JobReport::cancelled(job, task_id))
+        })
+        .collect()
+}
+
+/// Reports every job depending on the failed job `index`, directly or not,
+/// as blocked.
+fn block_dependents(
+    index: usize,
+    jobs: &[Job],
+    dependents: &[Vec<usize>],
+    reports: &mut [Option<JobReport>],
+) {
+    for &d in &dependents[index] {
+        if reports[d].is_none() {
+            println!(
+                "[{}] Not compiled: {} failed",
+                jobs[d].name, jobs[index].name
+            );
+            reports[d] = Some(JobReport::blocked(jobs[d].clone(), &jobs[index].name));
+            block_dependents(d, jobs, dependents, reports);
+        }
+    }
+}
+
+pub fn jobs_from_dir(dir: &Path, out_dir: &Path) -> Result<Vec<Job>> {
+    let inputs = discover(dir)?;
+    if inputs.is_empty() {
+        anyhow::bail!("No documents found in {}", dir.display());
+    }
+
+    inputs
+        .iter()
+        .map(|input| {
+            let mut job = Job::new(input, out_dir)?;
+            job.name = display_name(dir, input);
+            Ok(job)
+        })
+        .collect()
+}
+
+/// Finds every standalone document under `dir`: `.zip` archives and `.tex`
+/// files that declare their own `\documentclass` (chapters pulled in via
+/// `\input` are skipped).
+fn discover(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(current) = pending.pop() {
+        let entries = fs::read_dir(&current)
+            .with_context(|| format!("Failed to read directory: {}", current.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with('.'));
+            if hidden {
+                continue;
+            }
+            if path.is_dir() {
+                pending.push(path);
+            } else if is_standalone_document(&path) {
+                found.push(path);
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// The main file of a single-document project: the only standalone document
+/// under `dir`, or `main.tex` at its top level when there are several.
+pub fn find_main_document(dir: &Path) -> Result<PathBuf> {
+    let documents = discover(dir)?;
+    match documents.as_slice() {
+        [] => anyhow::bail!("No documents found in {}", dir.display()),
+        [only] => Ok(only.clone()),
+        _ => documents
+            .iter()
+            .find(|d| d.strip_prefix(dir).ok() == Some(Path::new("main.tex")))
+            .cloned()
+            .with_context(|| {
+                let names: Vec<String> = documents.iter().map(|d| display_name(dir, d)).collect();
+                format!(
+                    "Several documents in {}: {}",
+                    dir.display(),
+                    names.join(", ")
+                )
+            }),
+    }
+}
+
+fn is_standalone_document(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("zip") => true,
+        Some("tex") => fs::read_to_string(path)
+            .map(|text| text.contains("\\documentclass"))
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn display_name(root: &Path, input: &Path) -> String {
+    input
+        .strip_prefix(root)
+        .unwrap_or(input)
+        .display()
+        .to_string()
+}
+
+/// Gives every job its own output file. Jobs whose outputs clash (chapters
+/// with the same name in different folders; compared ignoring case, as
+/// Windows and macOS do) are named after their relative path instead,
+/// `part1/kinetics.tex` becoming `part1-kinetics.pdf`, and any clash left
+/// gets a numbered suffix, in job order. Returns the renamed jobs' names and
+/// new outputs.
+pub fn disambiguate_outputs(jobs: &mut [Job]) -> Vec<(String, PathBuf)> {
+    fn key(path: &Path) -> String {
+        std::path::absolute(path)
+            .unwrap_or_else(|_| path.to_path_buf())
+            .to_string_lossy()
+            .to_lowercase()
+    }
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for job in jobs.iter() {
+        *counts.entry(key(&job.output)).or_default() += 1;
+    }
+    let clashing: Vec<bool> = jobs
+        .iter()
+        .map(|job| counts[&key(&job.output)] > 1)
+        .collect();
+    let mut taken: HashSet<String> = jobs
+        .iter()
+        .zip(&clashing)
+        .filter(|(_, clashing)| !**clashing)
+        .map(|(job, _)| key(&job.output))
+        .collect();
+
+    let mut renamed = Vec::new();
+    for (job, clashing) in jobs.iter_mut().zip(clashing) {
+        if !clashing {
+            continue;
+        }
+        let dir = job.output.parent().unwrap_or(Path::new("")).to_path_buf();
+        let derived = Path::new(&job.name).with_extension("");
+        let stem = paths::portable_name(
+            &derived
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy())
+                .filter(|part| part != "." && part != "..")
+                .collect::<Vec<_>>()
+                .join("-"),
+        );
+        let mut output = dir.join(format!("{}.pdf", stem));
+        let mut suffix = 2;
+        while !taken.insert(key(&output)) {
+            output = dir.join(format!("{}-{}.pdf", stem, suffix));
+            suffix += 1;
+        }
+        if output != job.output {
+            job.output = output.clone();
+            renamed.push((job.name.clone(), output));
+        }
+    }
+    renamed
+}
+
+fn print_table(reports: &[JobReport]) {
+    let rows: Vec<[String; 5]> = reports
+        .iter()
+        .map(|r| {
+            let (status, detail) = match &r.result {
+                Ok(output) if r.details.cached => {
+                    ("CACHED".to_string(), output.display().to_string())
+                }
+                Ok(output) => ("OK".to_string(), output.display().to_string()),
+                Err(e) if r.cancelled => ("CANCELLED".to_string(), format!("{:#}", e)),
+                Err(e) => ("FAILED".to_string(), format!("{:#}", e)),
+            };
+            [
+                r.job.name.clone(),
+                status,
+                r.details.task_id.clone().unwrap_or_else(|| "-".to_string()),
+                api::format_milliseconds(r.elapsed.as_millis() as u64),
+                detail,
+            ]
+        })
+        .collect();
+
+    let headers = ["Document", "Status", "Task ID", "Time", "Result"];
+    let mut widths = headers.map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()).take(4) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: [&str; 5]| {
+        let mut line = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            if i < 4 {
+                line.push_str(&format!("{:<width$}  ", cell, width = widths[i]));
+            } else {
+                line.push_str(cell);
+            }
+        }
+        line
+    };
+
+    println!();
+    println!("{}", format_row(headers));
+    for row in &rows {
+        println!("{}", format_row(row.each_ref().map(String::as_str)));
+    }
+}
+
+/// Where the batch spent its time, summed over documents, so a slow network
+/// stands out from a busy server queue.
+fn print_phase_totals(reports: &[JobReport]) {
+    let totals: Vec<(Phase, Duration)> = Phase::ALL
+        .iter()
+        .filter_map(|&phase| {
+            let durations: Vec<Duration> = reports
+                .iter()
+                .filter_map(|r| r.details.phase(phase))
+                .collect();
+            (!durations.is_empty()).then(|| (phase, durations.into_iter().sum()))
+        })
+        .collect();
+    if !totals.is_empty() {
+        println!();
+        println!("Time spent: {}", job::format_phases(&totals));
+    }
+}
+
+fn report_to_github(reports: &[JobReport]) -> Result<()> {
+    for report in reports {
+        ci::annotate(report);
+    }
+    let artifacts: Vec<String> = reports
+        .iter()
+        .filter_map(|r| r.result.as_ref().ok())
+        .map(|output| output.display().to_string())
+        .collect();
+    let failed = reports.iter().filter(|r| !r.is_success()).count();
+    ci::set_output("succeeded", &artifacts.len().to_string())?;
+    ci::set_output("failed", &failed.to_string())?;
+    ci::set_output("artifacts", &artifacts.join("\n"))
+}
+
+fn print_failures(reports: &[JobReport]) {
+    let failures: Vec<&JobReport> = reports
+        .iter()
+        .filter(|r| !r.is_success() && !r.cancelled)
+        .collect();
+    if failures.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("Failed documents:");
+    for report in failures {
+        if let Err(e) = &report.result {
+            println!("  {}: {:#}", report.job.name, e);
+        }
+    }
+}
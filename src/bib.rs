@@ -0,0 +1,746 @@
+use crate::api;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::deps::DependencyGraph;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const CROSSREF_URL: &str = "https://api.crossref.org/works/";
+
+const USAGE: &str = "\
+Usage: chemtex bib add <DOI>... [--file FILE] [--acs]
+
+Looks DOIs up on Crossref and appends BibTeX entries for them to the
+project's bibliography, e.g.
+  chemtex bib add 10.1021/ja01577a030 https://doi.org/10.1002/anie.201915678
+
+DOIs already in the file are skipped. The file is the .bib the main file
+of the project in the current directory reads, or the only .bib there
+(references.bib when there is none).
+
+Options:
+  --file FILE   Bibliography to append to
+  --acs         Abbreviate journal names in ACS (CASSI) style";
+
+/// Full titles of common chemistry journals, normalised as by
+/// [`normalize_journal`], and their ACS abbreviations.
+const JOURNALS: &[(&str, &str)] = &[
+    ("accounts of chemical research", "Acc. Chem. Res."),
+    (
+        "acs applied materials and interfaces",
+        "ACS Appl. Mater. Interfaces",
+    ),
+    ("acs catalysis", "ACS Catal."),
+    ("acs central science", "ACS Cent. Sci."),
+    ("acs nano", "ACS Nano"),
+    ("acs omega", "ACS Omega"),
+    ("advanced energy materials", "Adv. Energy Mater."),
+    ("advanced functional materials", "Adv. Funct. Mater."),
+    ("advanced materials", "Adv. Mater."),
+    ("analytica chimica acta", "Anal. Chim. Acta"),
+    ("analytical chemistry", "Anal. Chem."),
+    ("angewandte chemie", "Angew. Chem."),
+    (
+        "angewandte chemie international edition",
+        "Angew. Chem., Int. Ed.",
+    ),
+    ("biochemistry", "Biochemistry"),
+    (
+        "bioorganic and medicinal chemistry letters",
+        "Bioorg. Med. Chem. Lett.",
+    ),
+    ("chemical communications", "Chem. Commun."),
+    ("chemical physics letters", "Chem. Phys. Lett."),
+    ("chemical reviews", "Chem. Rev."),
+    ("chemical science", "Chem. Sci."),
+    ("chemical society reviews", "Chem. Soc. Rev."),
+    ("chemistry a european journal", "Chem. - Eur. J."),
+    ("chemistry of materials", "Chem. Mater."),
+    ("coordination chemistry reviews", "Coord. Chem. Rev."),
+    ("crystal growth and design", "Cryst. Growth Des."),
+    ("dalton transactions", "Dalton Trans."),
+    ("electrochimica acta", "Electrochim. Acta"),
+    ("energy and environmental science", "Energy Environ. Sci."),
+    (
+        "environmental science and technology",
+        "Environ. Sci. Technol.",
+    ),
+    (
+        "european journal of inorganic chemistry",
+        "Eur. J. Inorg. Chem.",
+    ),
+    (
+        "european journal of organic chemistry",
+        "Eur. J. Org. Chem.",
+    ),
+    ("green chemistry", "Green Chem."),
+    (
+        "industrial and engineering chemistry research",
+        "Ind. Eng. Chem. Res.",
+    ),
+    ("inorganic chemistry", "Inorg. Chem."),
+    ("journal of biological chemistry", "J. Biol. Chem."),
+    ("journal of catalysis", "J. Catal."),
+    (
+        "journal of chemical and engineering data",
+        "J. Chem. Eng. Data",
+    ),
+    ("journal of chemical education", "J. Chem. Educ."),
+    (
+        "journal of chemical information and modeling",
+        "J. Chem. Inf. Model.",
+    ),
+    ("journal of chemical physics", "J. Chem. Phys."),
+    (
+        "journal of chemical theory and computation",
+        "J. Chem. Theory Comput.",
+    ),
+    ("journal of chromatography a", "J. Chromatogr. A"),
+    ("journal of computational chemistry", "J. Comput. Chem."),
+    ("journal of materials chemistry a", "J. Mater. Chem. A"),
+    ("journal of medicinal chemistry", "J. Med. Chem."),
+    ("journal of molecular structure", "J. Mol. Struct."),
+    ("journal of natural products", "J. Nat. Prod."),
+    ("journal of organic chemistry", "J. Org. Chem."),
+    ("journal of physical chemistry a", "J. Phys. Chem. A"),
+    ("journal of physical chemistry b", "J. Phys. Chem. B"),
+    ("journal of physical chemistry c", "J. Phys. Chem. C"),
+    (
+        "journal of physical chemistry letters",
+        "J. Phys. Chem. Lett.",
+    ),
+    (
+        "journal of the american chemical society",
+        "J. Am. Chem. Soc.",
+    ),
+    (
+        "journal of the electrochemical society",
+        "J. Electrochem. Soc.",
+    ),
+    ("langmuir", "Langmuir"),
+    ("macromolecules", "Macromolecules"),
+    ("nature", "Nature"),
+    ("nature chemistry", "Nat. Chem."),
+    ("nature communications", "Nat. Commun."),
+    ("organic and biomolecular chemistry", "Org. Biomol. Chem."),
+    ("organic letters", "Org. Lett."),
+    ("organometallics", "Organometallics"),
+    (
+        "physical chemistry chemical physics",
+        "Phys. Chem. Chem. Phys.",
+    ),
+    ("physical review letters", "Phys. Rev. Lett."),
+    (
+        "proceedings of the national academy of sciences",
+        "Proc. Natl. Acad. Sci. U.S.A.",
+    ),
+    (
+        "proceedings of the national academy of sciences of the united states of america",
+        "Proc. Natl. Acad. Sci. U.S.A.",
+    ),
+    ("rsc advances", "RSC Adv."),
+    ("science", "Science"),
+    ("tetrahedron", "Tetrahedron"),
+    ("tetrahedron letters", "Tetrahedron Lett."),
+];
+
+/// CASSI abbreviations of title words, for journals missing from
+/// [`JOURNALS`]; words not listed are kept whole.
+const WORDS: &[(&str, &str)] = &[
+    ("academy", "Acad."),
+    ("advanced", "Adv."),
+    ("agricultural", "Agric."),
+    ("american", "Am."),
+    ("analytical", "Anal."),
+    ("annual", "Annu."),
+    ("applied", "Appl."),
+    ("biochemistry", "Biochem."),
+    ("biological", "Biol."),
+    ("bulletin", "Bull."),
+    ("catalysis", "Catal."),
+    ("chemical", "Chem."),
+    ("chemistry", "Chem."),
+    ("communications", "Commun."),
+    ("computational", "Comput."),
+    ("crystallography", "Crystallogr."),
+    ("education", "Educ."),
+    ("edition", "Ed."),
+    ("electrochemical", "Electrochem."),
+    ("engineering", "Eng."),
+    ("environmental", "Environ."),
+    ("european", "Eur."),
+    ("industrial", "Ind."),
+    ("inorganic", "Inorg."),
+    ("international", "Int."),
+    ("journal", "J."),
+    ("letters", "Lett."),
+    ("materials", "Mater."),
+    ("medicinal", "Med."),
+    ("molecular", "Mol."),
+    ("national", "Natl."),
+    ("organic", "Org."),
+    ("pharmaceutical", "Pharm."),
+    ("physical", "Phys."),
+    ("physics", "Phys."),
+    ("polymer", "Polym."),
+    ("proceedings", "Proc."),
+    ("research", "Res."),
+    ("review", "Rev."),
+    ("reviews", "Rev."),
+    ("science", "Sci."),
+    ("sciences", "Sci."),
+    ("society", "Soc."),
+    ("spectroscopy", "Spectrosc."),
+    ("structure", "Struct."),
+    ("surface", "Surf."),
+    ("technology", "Technol."),
+    ("theoretical", "Theor."),
+    ("transactions", "Trans."),
+];
+
+/// Words left out of abbreviated journal titles and citation keys.
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "at", "for", "from", "in", "of", "on", "the", "to", "with",
+];
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["acs"], &["file"])?;
+    match args.positional(0) {
+        Some("add") => add(&args).await,
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+async fn add(args: &Args) -> Result<()> {
+    let dois: Vec<String> = (1..)
+        .map_while(|i| args.positional(i))
+        .map(normalize_doi)
+        .collect::<Result<_>>()?;
+    if dois.is_empty() {
+        anyhow::bail!(USAGE);
+    }
+    let path = match args.value("file") {
+        Some(file) => PathBuf::from(file),
+        None => project_bibliography(Path::new("."))?,
+    };
+    let mut text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read file: {}", path.display()))
+        }
+    };
+    let mut existing = Existing::scan(&text);
+
+    let client = api::build_client()?;
+    let mut added = 0;
+    let mut failed = 0;
+    for doi in &dois {
+        if let Some(key) = existing.dois.get(&doi.to_lowercase()) {
+            println!("{}: already in {} as {}", doi, path.display(), key);
+            continue;
+        }
+        let work = match fetch(&client, doi).await {
+            Ok(work) => work,
+            Err(e) => {
+                eprintln!("{}: {:#}", doi, e);
+                failed += 1;
+                continue;
+            }
+        };
+        let key = existing.unique_key(&work.key());
+        if !text.is_empty() && !text.ends_with("\n\n") {
+            text.push_str(if text.ends_with('\n') { "\n" } else { "\n\n" });
+        }
+        text.push_str(&work.entry(&key, doi, args.flag("acs")));
+        println!("{}: added as {}", doi, key);
+        existing.keys.insert(key.clone());
+        existing.dois.insert(doi.to_lowercase(), key);
+        added += 1;
+    }
+    if added > 0 {
+        fs::write(&path, &text)
+            .with_context(|| format!("Failed to write file: {}", path.display()))?;
+    }
+    if failed > 0 {
+        anyhow::bail!("{} DOI(s) could not be resolved", failed);
+    }
+    Ok(())
+}
+
+/// `https://doi.org/10.1021/ja01577a030`, `doi:10.1021/...` → `10.1021/...`.
+pub fn normalize_doi(raw: &str) -> Result<String> {
+    let mut doi = raw.trim();
+    for prefix in [
+        "https://doi.org/",
+        "http://doi.org/",
+        "https://dx.doi.org/",
+        "http://dx.doi.org/",
+        "doi.org/",
+        "doi:",
+    ] {
+        if doi.len() >= prefix.len() && doi[..prefix.len()].eq_ignore_ascii_case(prefix) {
+            doi = doi[prefix.len()..].trim();
+        }
+    }
+    if !doi.starts_with("10.") || !doi.contains('/') {
+        anyhow::bail!("{:?} is not a DOI (expected 10.xxxx/...)", raw);
+    }
+    Ok(doi.to_string())
+}
+
+/// The `.bib` the project's main file reads, else the only one in `dir`,
+/// else `dir/references.bib`.
+fn project_bibliography(dir: &Path) -> Result<PathBuf> {
+    let main = ProjectConfig::find(dir)?
+        .and_then(|config| config.main)
+        .map(|main| dir.join(main))
+        .unwrap_or_else(|| dir.join("main.tex"));
+    if main.is_file() {
+        let graph = DependencyGraph::scan(&main)?;
+        if let Some(bib) = graph.files.iter().find(|f| is_bib(f)) {
+            return Ok(bib.clone());
+        }
+    }
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).context("Failed to read the current directory")? {
+        let path = entry?.path();
+        if is_bib(&path) {
+            found.push(path);
+        }
+    }
+    match found.len() {
+        0 => Ok(dir.join("references.bib")),
+        1 => Ok(found.remove(0)),
+        _ => anyhow::bail!("Several .bib files here; choose one with --file"),
+    }
+}
+
+fn is_bib(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("bib")
+}
+
+/// Keys and DOIs of the entries already in a `.bib` file.
+#[derive(Debug, Default)]
+struct Existing {
+    keys: HashSet<String>,
+    /// Lowercased DOI → key.
+    dois: HashMap<String, String>,
+}
+
+impl Existing {
+    fn scan(text: &str) -> Self {
+        let mut existing = Self::default();
+        let mut key = String::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(rest) = line.strip_prefix('@') {
+                if let Some((_, rest)) = rest.split_once(['{', '(']) {
+                    key = rest.split(',').next().unwrap_or("").trim().to_string();
+                    existing.keys.insert(key.clone());
+                }
+                continue;
+            }
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("doi") {
+                let value = value
+                    .trim()
+                    .trim_end_matches(',')
+                    .trim_matches(|c| c == '{' || c == '}' || c == '"');
+                if let Ok(doi) = normalize_doi(value) {
+                    existing.dois.insert(doi.to_lowercase(), key.clone());
+                }
+            }
+        }
+        existing
+    }
+
+    /// `base`, or `base` with the first free suffix `a`, `b`, ...
+    fn unique_key(&self, base: &str) -> String {
+        if !self.keys.contains(base) {
+            return base.to_string();
+        }
+        ('a'..='z')
+            .map(|suffix| format!("{}{}", base, suffix))
+            .find(|key| !self.keys.contains(key))
+            .unwrap_or_else(|| format!("{}{}", base, self.keys.len()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    message: Work,
+}
+
+/// The parts of a Crossref work record that go into an entry.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct Work {
+    #[serde(rename = "type")]
+    kind: String,
+    title: Vec<String>,
+    author: Vec<Person>,
+    editor: Vec<Person>,
+    container_title: Vec<String>,
+    short_container_title: Vec<String>,
+    volume: Option<String>,
+    issue: Option<String>,
+    page: Option<String>,
+    publisher: Option<String>,
+    issued: Option<Issued>,
+    #[serde(rename = "ISBN")]
+    isbn: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Person {
+    given: Option<String>,
+    family: Option<String>,
+    /// Organisations have a name instead.
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Issued {
+    #[serde(rename = "date-parts")]
+    date_parts: Vec<Vec<Option<i64>>>,
+}
+
+async fn fetch(client: &reqwest::Client, doi: &str) -> Result<Work> {
+    let mut url = Url::parse(CROSSREF_URL).expect("valid Crossref URL");
+    url.path_segments_mut()
+        .expect("Crossref URL has a path")
+        .pop_if_empty()
+        .push(doi);
+    let response = client
+        .get(url)
+        .header("User-Agent", concat!("chemtex/", env!("CARGO_PKG_VERSION")))
+        .send()
+        .await
+        .context("Failed to reach Crossref")?;
+    match response.status() {
+        reqwest::StatusCode::NOT_FOUND => anyhow::bail!("Not found on Crossref"),
+        status if !status.is_success() => anyhow::bail!("Crossref returned status {}", status),
+        _ => {}
+    }
+    let response: Response = response
+        .json()
+        .await
+        .context("Failed to parse the Crossref record")?;
+    Ok(response.message)
+}
+
+impl Work {
+    fn year(&self) -> Option<i64> {
+        self.issued
+            .as_ref()?
+            .date_parts
+            .first()?
+            .first()
+            .copied()
+            .flatten()
+    }
+
+    /// `Lipkowitz1958Structure`: first author, year and first significant
+    /// title word, in ASCII.
+    fn key(&self) -> String {
+        let mut key = String::new();
+        if let Some(person) = self.author.first().or(self.editor.first()) {
+            // "van der Waals" → VanderWaals; organisations by their first word.
+            let name = match (&person.family, &person.name) {
+                (Some(family), _) => ascii_fold(family),
+                (None, Some(name)) => ascii_fold(name.split_whitespace().next().unwrap_or("")),
+                (None, None) => String::new(),
+            };
+            key.push_str(&capitalize(&name));
+        }
+        if let Some(year) = self.year() {
+            key.push_str(&year.to_string());
+        }
+        let title = strip_tags(self.title.first().map(String::as_str).unwrap_or(""));
+        let word = title
+            .split(|c: char| !c.is_alphanumeric())
+            .map(ascii_fold)
+            .find(|w| w.len() > 1 && !STOP_WORDS.contains(&w.to_lowercase().as_str()));
+        if let Some(word) = word {
+            key.push_str(&capitalize(&word));
+        }
+        if key.is_empty() {
+            key.push_str("entry");
+        }
+        key
+    }
+
+    fn entry(&self, key: &str, doi: &str, acs: bool) -> String {
+        let (kind, container) = match self.kind.as_str() {
+            "journal-article" => ("article", "journal"),
+            "book" | "monograph" | "edited-book" | "reference-book" => ("book", ""),
+            "book-chapter" | "book-section" | "book-part" => ("incollection", "booktitle"),
+            "proceedings-article" => ("inproceedings", "booktitle"),
+            "dissertation" => ("phdthesis", ""),
+            _ => ("misc", ""),
+        };
+        let mut fields: Vec<(&str, String)> = Vec::new();
+        if !self.author.is_empty() {
+            fields.push(("author", people(&self.author)));
+        }
+        if !self.editor.is_empty() && kind != "article" {
+            fields.push(("editor", people(&self.editor)));
+        }
+        if let Some(title) = self.title.first() {
+            fields.push(("title", latex_title(title)));
+        }
+        if !container.is_empty() {
+            if let Some(full) = self.container_title.first() {
+                let name = match acs && kind == "article" {
+                    true => acs_abbreviation(full, self.short_container_title.first()),
+                    false => full.clone(),
+                };
+                fields.push((container, escape(&decode_entities(&name))));
+            }
+        }
+        if let Some(year) = self.year() {
+            fields.push(("year", year.to_string()));
+        }
+        if let Some(volume) = &self.volume {
+            fields.push(("volume", escape(volume)));
+        }
+        if let Some(issue) = &self.issue {
+            fields.push(("number", escape(issue)));
+        }
+        if let Some(page) = &self.page {
+            fields.push(("pages", page.replace("--", "-").replace('-', "--")));
+        }
+        if matches!(kind, "book" | "incollection" | "inproceedings") {
+            if let Some(publisher) = &self.publisher {
+                fields.push(("publisher", escape(publisher)));
+            }
+            if let Some(isbn) = self.isbn.first() {
+                fields.push(("isbn", isbn.clone()));
+            }
+        }
+        fields.push(("doi", doi.to_string()));
+
+        let mut out = format!("@{}{{{},\n", kind, key);
+        for (name, value) in fields {
+            out.push_str(&format!("  {} = {{{}}},\n", name, value));
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn people(people: &[Person]) -> String {
+    people
+        .iter()
+        .map(
+            |person| match (&person.family, &person.given, &person.name) {
+                (Some(family), Some(given), _) => format!("{}, {}", escape(family), escape(given)),
+                (Some(family), None, _) => escape(family),
+                // Braced so BibTeX does not split an organisation into names.
+                (None, _, Some(name)) => format!("{{{}}}", escape(name)),
+                _ => "others".to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join(" and ")
+}
+
+/// The ACS abbreviation of a journal: from [`JOURNALS`], else Crossref's
+/// short title, else abbreviated word by word.
+pub fn acs_abbreviation(title: &str, short: Option<&String>) -> String {
+    let normalized = normalize_journal(title);
+    if let Some((_, abbreviation)) = JOURNALS.iter().find(|(name, _)| *name == normalized) {
+        return abbreviation.to_string();
+    }
+    if let Some(short) = short.filter(|s| s.contains('.')) {
+        return short.clone();
+    }
+    let words: Vec<&str> = normalized
+        .split(' ')
+        .filter(|w| !STOP_WORDS.contains(w))
+        .collect();
+    if words.len() <= 1 {
+        return title.to_string();
+    }
+    let original: HashMap<String, &str> = title
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|w| (w.to_lowercase(), w))
+        .collect();
+    words
+        .iter()
+        .map(|word| match WORDS.iter().find(|(full, _)| full == word) {
+            Some((_, abbreviation)) => abbreviation.to_string(),
+            None => original.get(*word).copied().unwrap_or(word).to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lowercase words without punctuation, `&` read as `and` and a leading
+/// "the" dropped.
+fn normalize_journal(title: &str) -> String {
+    let title = decode_entities(title).replace('&', " and ").to_lowercase();
+    let words: Vec<&str> = title
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let words = match words.first() {
+        Some(&"the") => &words[1..],
+        _ => &words[..],
+    };
+    words.join(" ")
+}
+
+/// Crossref titles carry HTML markup (`CO<sub>2</sub>`, `<i>in situ</i>`);
+/// it becomes LaTeX, and words with capitals past their first letter are
+/// braced so bibliography styles keep their case.
+fn latex_title(title: &str) -> String {
+    let mut out = String::new();
+    let mut open: Vec<bool> = Vec::new();
+    let mut rest = title;
+    while let Some(start) = rest.find('<') {
+        out.push_str(&protect_words(&escape(&decode_entities(&rest[..start]))));
+        let Some(end) = rest[start..].find('>') else {
+            rest = &rest[start..];
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_lowercase();
+        rest = &rest[start + end + 1..];
+        if tag.starts_with('/') {
+            if open.pop() == Some(true) {
+                out.push('}');
+            }
+            continue;
+        }
+        let name = tag.split_whitespace().next().unwrap_or("");
+        let command = match name {
+            "sub" => Some("\\textsubscript{"),
+            "sup" => Some("\\textsuperscript{"),
+            "i" | "em" => Some("\\textit{"),
+            "b" | "strong" => Some("\\textbf{"),
+            "scp" => Some("\\textsc{"),
+            _ => None,
+        };
+        if tag.ends_with('/') {
+            continue;
+        }
+        if let Some(command) = command {
+            out.push_str(command);
+        }
+        open.push(command.is_some());
+    }
+    out.push_str(&protect_words(&escape(&decode_entities(rest))));
+    for opened in open {
+        if opened {
+            out.push('}');
+        }
+    }
+    collapse_spaces(&out)
+}
+
+fn protect_words(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let needs = word.chars().skip(1).any(char::is_uppercase)
+                || (word.chars().any(char::is_uppercase)
+                    && word.chars().any(|c| c.is_ascii_digit()));
+            match needs && !word.contains('\\') {
+                true => format!("{{{}}}", word),
+                false => word.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn strip_tags(text: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    decode_entities(&out)
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Escapes the characters BibTeX passes on to LaTeX as specials.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' | '%' | '#' | '_' | '$' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '{' | '}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn collapse_spaces(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Drops accents from Latin letters and everything else outside ASCII
+/// letters and digits, for citation keys.
+fn ascii_fold(word: &str) -> String {
+    word.chars()
+        .filter_map(|c| {
+            let folded = match c {
+                'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ą' => 'a',
+                'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' => 'A',
+                'ç' | 'ć' | 'č' => 'c',
+                'Ç' | 'Ć' | 'Č' => 'C',
+                'è' | 'é' | 'ê' | 'ë' | 'ę' | 'ě' => 'e',
+                'É' | 'È' => 'E',
+                'ì' | 'í' | 'î' | 'ï' => 'i',
+                'ł' => 'l',
+                'Ł' => 'L',
+                'ñ' | 'ń' | 'ň' => 'n',
+                'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => 'o',
+                'Ö' | 'Ø' => 'O',
+                'ř' => 'r',
+                'ś' | 'š' => 's',
+                'Š' | 'Ś' => 'S',
+                'ù' | 'ú' | 'û' | 'ü' | 'ů' => 'u',
+                'Ü' => 'U',
+                'ý' | 'ÿ' => 'y',
+                'ž' | 'ź' | 'ż' => 'z',
+                'Ž' => 'Z',
+                'ß' => 's',
+                c => c,
+            };
+            folded.is_ascii_alphanumeric().then_some(folded)
+        })
+        .collect()
+}
@@ -0,0 +1,424 @@
+use crate::api::{self, CompileOptions};
+use crate::cli::Args;
+use crate::storage;
+use crate::vault;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ENTRY_FILE: &str = "entry.json";
+/// The PDF of an entry written before artifacts were shared.
+const PDF_FILE: &str = "output.pdf";
+const DEFAULT_MAX_BYTES: u64 = 1 << 30;
+
+const USAGE: &str = "\
+Usage: chemtex cache stats
+       chemtex cache gc [--max-size 500M]
+       chemtex cache clear
+
+Builds are cached under <cache dir>/builds, their PDFs and compile logs
+under <cache dir>/artifacts by content hash, so identical PDFs are stored
+once. After every build the least recently used builds are evicted until
+the artifacts fit in CHEMTEX_CACHE_MAX_BYTES (default 1G); `gc` does the
+same now, to --max-size if given. `clear` removes the cache and the
+project file index.";
+
+/// Local cache of successful builds, keyed by the uploaded bytes plus the
+/// options they were compiled with.
+#[derive(Debug, Clone)]
+pub struct BuildCache {
+    root: PathBuf,
+    artifacts: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub task_id: String,
+    pub download_url: String,
+    pub created_at: u64,
+    /// When the build was last reused, for eviction; 0 until then.
+    #[serde(default)]
+    pub used_at: u64,
+    /// SHA-256 of the PDF in the artifacts directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pdf: Option<String>,
+    /// Likewise for the compile log, once it was fetched.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log: Option<String>,
+}
+
+impl CacheEntry {
+    fn last_used(&self) -> u64 {
+        self.used_at.max(self.created_at)
+    }
+}
+
+/// A cache hit: the stored metadata and the PDF, if it is still on disk.
+#[derive(Debug)]
+pub struct CachedBuild {
+    pub entry: CacheEntry,
+    pub pdf: Option<Vec<u8>>,
+}
+
+/// What [`BuildCache::gc`] removed.
+#[derive(Debug, Default)]
+pub struct Collected {
+    pub builds: usize,
+    pub artifacts: usize,
+    pub bytes: u64,
+}
+
+impl BuildCache {
+    pub fn open_default() -> Result<Self> {
+        Ok(Self::at(&storage::cache_dir()?))
+    }
+
+    fn at(dir: &Path) -> Self {
+        Self {
+            root: dir.join("builds"),
+            artifacts: dir.join("artifacts"),
+        }
+    }
+
+    /// Deterministic key over everything that influences the compiled PDF.
+    pub fn key(contents: &[u8], file_name: &str, options: &CompileOptions) -> String {
+        let mut hasher = Sha256::new();
+        for field in [
+            file_name,
+            options.engine.as_deref().unwrap_or(""),
+            options.profile.as_deref().unwrap_or(""),
+        ] {
+            hasher.update((field.len() as u64).to_le_bytes());
+            hasher.update(field.as_bytes());
+        }
+        // Left out when unset, so builds cached before modes keep their key.
+        if let Some(mode) = options.mode {
+            hasher.update(mode.as_str().as_bytes());
+        }
+        hasher.update(contents);
+        to_hex(&hasher.finalize())
+    }
+
+    /// The build stored under `key`, marked as used.
+    pub fn lookup(&self, key: &str) -> Option<CachedBuild> {
+        let dir = self.root.join(key);
+        let mut entry = self.entry(key)?;
+        let pdf = match &entry.pdf {
+            Some(hash) => self.artifact(hash),
+            None => fs::read(dir.join(PDF_FILE)).ok(),
+        };
+        entry.used_at = now();
+        let _ = self.write_entry(&entry);
+        Some(CachedBuild { entry, pdf })
+    }
+
+    pub fn store(&self, key: &str, task_id: &str, download_url: &str, pdf: &[u8]) -> Result<()> {
+        let dir = self.root.join(key);
+        storage::ensure_dir(&dir)?;
+        let entry = CacheEntry {
+            key: key.to_string(),
+            task_id: task_id.to_string(),
+            download_url: download_url.to_string(),
+            created_at: now(),
+            used_at: 0,
+            pdf: Some(
+                self.put_artifact(pdf)
+                    .context("Failed to write cached PDF")?,
+            ),
+            log: None,
+        };
+        // A PDF from before artifacts were shared.
+        let _ = fs::remove_file(dir.join(PDF_FILE));
+        self.write_entry(&entry)?;
+        self.gc(max_bytes()?)?;
+        Ok(())
+    }
+
+    /// Keeps the compile log of the build under `key`.
+    pub fn store_log(&self, key: &str, log: &str) -> Result<()> {
+        let Some(mut entry) = self.entry(key) else {
+            return Ok(());
+        };
+        entry.log = Some(
+            self.put_artifact(log.as_bytes())
+                .context("Failed to write cached log")?,
+        );
+        self.write_entry(&entry)
+    }
+
+    /// The compile log of the build under `key`, if it was kept.
+    pub fn log(&self, key: &str) -> Option<String> {
+        let hash = self.entry(key)?.log?;
+        String::from_utf8(self.artifact(&hash)?).ok()
+    }
+
+    /// Evicts the least recently used builds until the artifacts take at
+    /// most `max_bytes`, then removes artifacts no build refers to.
+    pub fn gc(&self, max_bytes: u64) -> Result<Collected> {
+        let mut collected = Collected::default();
+        let mut entries = Vec::new();
+        for (key, dir) in list(&self.root)? {
+            match self.entry(&key) {
+                Some(entry) => entries.push(entry),
+                // Half-written or from an incompatible version.
+                None => {
+                    fs::remove_dir_all(&dir).ok();
+                    collected.builds += 1;
+                }
+            }
+        }
+        let mut sizes: HashMap<String, u64> = HashMap::new();
+        for (hash, path) in list(&self.artifacts)? {
+            sizes.insert(hash, fs::metadata(&path).map(|m| m.len()).unwrap_or(0));
+        }
+        entries.sort_by_key(CacheEntry::last_used);
+        let mut references: HashMap<&str, usize> = HashMap::new();
+        for hash in entries.iter().flat_map(entry_artifacts) {
+            *references.entry(hash).or_default() += 1;
+        }
+        let mut total: u64 = sizes.values().sum();
+
+        for entry in &entries {
+            if total <= max_bytes {
+                break;
+            }
+            fs::remove_dir_all(self.root.join(&entry.key))
+                .with_context(|| format!("Failed to evict cached build {}", entry.key))?;
+            collected.builds += 1;
+            for hash in entry_artifacts(entry) {
+                let count = references.get_mut(hash).expect("counted above");
+                *count -= 1;
+                if *count == 0 {
+                    total = total.saturating_sub(sizes.get(hash).copied().unwrap_or(0));
+                }
+            }
+        }
+
+        for (hash, size) in &sizes {
+            let path = self.artifacts.join(hash);
+            let unused = match references.get(hash.as_str()) {
+                Some(0) => true,
+                Some(_) => false,
+                // Kept while fresh: another run may be about to refer to it.
+                None => stale(&path),
+            };
+            if unused {
+                fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove cached artifact {}", hash))?;
+                collected.artifacts += 1;
+                collected.bytes += size;
+            }
+        }
+        Ok(collected)
+    }
+
+    fn entry(&self, key: &str) -> Option<CacheEntry> {
+        let text = fs::read_to_string(self.root.join(key).join(ENTRY_FILE)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn write_entry(&self, entry: &CacheEntry) -> Result<()> {
+        let json = serde_json::to_string_pretty(entry)?;
+        let path = self.root.join(&entry.key).join(ENTRY_FILE);
+        write_atomically(&path, json.as_bytes()).context("Failed to write cache entry")
+    }
+
+    /// Stores `contents` by hash, once, encrypted when that is on (see
+    /// [`vault`]). Hashes are of the plain contents.
+    fn put_artifact(&self, contents: &[u8]) -> Result<String> {
+        let hash = to_hex(&Sha256::digest(contents));
+        let path = self.artifacts.join(&hash);
+        if !path.is_file() {
+            storage::ensure_dir(&self.artifacts)?;
+            write_atomically(&path, &vault::seal(contents)?)?;
+        }
+        Ok(hash)
+    }
+
+    /// The artifact with this hash, if it is intact (and opens, when it
+    /// was encrypted).
+    fn artifact(&self, hash: &str) -> Option<Vec<u8>> {
+        let contents = vault::open(&fs::read(self.artifacts.join(hash)).ok()?).ok()?;
+        (to_hex(&Sha256::digest(&contents)) == hash).then_some(contents)
+    }
+}
+
+fn entry_artifacts(entry: &CacheEntry) -> impl Iterator<Item = &str> {
+    entry.pdf.iter().chain(&entry.log).map(String::as_str)
+}
+
+/// The names and paths of the entries of `dir`, none when it is missing.
+fn list(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read directory: {}", dir.display()))
+        }
+    };
+    let mut listed = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Leftovers of an interrupted `write_atomically`, or a write in
+        // progress.
+        if name.ends_with(".tmp") {
+            if stale(&entry.path()) {
+                fs::remove_file(entry.path()).ok();
+            }
+            continue;
+        }
+        listed.push((name, entry.path()));
+    }
+    Ok(listed)
+}
+
+/// Whether `path` was last written ten minutes ago or earlier.
+fn stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age.as_secs() >= 600)
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn max_bytes() -> Result<u64> {
+    match std::env::var("CHEMTEX_CACHE_MAX_BYTES") {
+        Ok(value) => api::parse_size(&value)
+            .with_context(|| format!("Invalid CHEMTEX_CACHE_MAX_BYTES: {}", value)),
+        Err(_) => Ok(DEFAULT_MAX_BYTES),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &["max-size"])?;
+    let dir = storage::cache_dir()?;
+    let cache = BuildCache::at(&dir);
+    match args.positional(0) {
+        Some("stats") => stats(&cache, &dir),
+        Some("gc") => {
+            let max = match args.value("max-size") {
+                Some(value) => api::parse_size(value)
+                    .with_context(|| format!("Invalid --max-size: {}", value))?,
+                None => max_bytes()?,
+            };
+            let collected = cache.gc(max)?;
+            println!(
+                "Removed {} builds and {} artifacts ({})",
+                collected.builds,
+                collected.artifacts,
+                format_bytes(collected.bytes)
+            );
+            Ok(())
+        }
+        Some("clear") => {
+            for name in ["builds", "artifacts", "index"] {
+                let path = dir.join(name);
+                match fs::remove_dir_all(&path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(e)
+                            .with_context(|| format!("Failed to remove {}", path.display()))
+                    }
+                }
+            }
+            println!("Cleared {}", dir.display());
+            Ok(())
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+fn stats(cache: &BuildCache, dir: &Path) -> Result<()> {
+    let entries: Vec<CacheEntry> = list(&cache.root)?
+        .into_iter()
+        .filter_map(|(key, _)| cache.entry(&key))
+        .collect();
+    let artifacts = list(&cache.artifacts)?;
+    let bytes: u64 = artifacts
+        .iter()
+        .map(|(_, path)| fs::metadata(path).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    let logs = entries.iter().filter(|entry| entry.log.is_some()).count();
+    println!("Cache:     {}", dir.display());
+    println!("Builds:    {} ({} with their log)", entries.len(), logs);
+    println!(
+        "Artifacts: {} ({} of {})",
+        artifacts.len(),
+        format_bytes(bytes),
+        format_bytes(max_bytes()?)
+    );
+    if let Some(oldest) = entries.iter().map(CacheEntry::last_used).min() {
+        let days = now().saturating_sub(oldest) / 86_400;
+        println!("Least recently used: {} days ago", days);
+    }
+    Ok(())
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 30 => format!("{:.1} GiB", b as f64 / (1u64 << 30) as f64),
+        b if b >= 1 << 20 => format!("{:.1} MiB", b as f64 / (1u64 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.1} KiB", b as f64 / 1024.0),
+        b => format!("{} B", b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used_builds_and_shares_artifacts() {
+        let dir = std::env::temp_dir().join(format!("chemtex-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = BuildCache::at(&dir);
+        let big = vec![b'a'; 600];
+        cache.store("old", "t1", "/files/1.pdf", &big).unwrap();
+        cache.store("same", "t2", "/files/2.pdf", &big).unwrap();
+        cache
+            .store("new", "t3", "/files/3.pdf", &[b'b'; 600])
+            .unwrap();
+        assert_eq!(list(&cache.artifacts).unwrap().len(), 2);
+
+        let mut old = cache.entry("old").unwrap();
+        old.created_at = 1;
+        cache.write_entry(&old).unwrap();
+        let mut same = cache.entry("same").unwrap();
+        same.created_at = 2;
+        cache.write_entry(&same).unwrap();
+        // Evicting "old" frees nothing while "same" shares its PDF.
+        let collected = cache.gc(1000).unwrap();
+        assert_eq!(collected.builds, 2);
+        assert_eq!((collected.artifacts, collected.bytes), (1, 600));
+        assert!(cache.lookup("old").is_none());
+        assert_eq!(cache.lookup("new").unwrap().pdf.unwrap(), vec![b'b'; 600]);
+
+        cache.store_log("new", "LaTeX Warning").unwrap();
+        assert_eq!(cache.log("new").as_deref(), Some("LaTeX Warning"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
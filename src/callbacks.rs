@@ -0,0 +1,115 @@
+//! Completion webhooks, for servers that call back when a task finishes.
+//! `chemtex daemon --callback-url URL` sends URL with every upload and
+//! takes the calls on `POST /callbacks`; a task whose callback arrives is
+//! checked at once instead of at its next poll. `--callback-listen ADDR`
+//! serves that endpoint alone on a second address, the one to expose, so
+//! the rest of the daemon's API stays private.
+//!
+//! A callback only wakes the status check, whose answer is what counts, so
+//! a forged or repeated one costs a request and nothing else. When none
+//! arrives within the grace period the task is polled as usual.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long a task waits for its callback before polling, by default.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(30);
+
+struct Receiver {
+    url: String,
+    grace: Duration,
+}
+
+static RECEIVER: OnceLock<Receiver> = OnceLock::new();
+
+/// Tasks being polled, by id.
+static WAITING: Mutex<Option<HashMap<String, Arc<Notify>>>> = Mutex::new(None);
+
+/// Takes callbacks at `url` from now on.
+pub fn enable(url: &str, grace: Duration) {
+    let _ = RECEIVER.set(Receiver {
+        url: url.to_string(),
+        grace,
+    });
+}
+
+/// The URL sent with uploads, when callbacks are taken.
+pub fn url() -> Option<&'static str> {
+    RECEIVER.get().map(|receiver| receiver.url.as_str())
+}
+
+/// How long to wait for a callback before polling, when callbacks are
+/// taken.
+pub fn grace() -> Option<Duration> {
+    RECEIVER.get().map(|receiver| receiver.grace)
+}
+
+/// Registers `task_id` for callbacks until the returned guard is dropped.
+pub fn register(task_id: &str) -> Registration {
+    let notify = Arc::clone(
+        WAITING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert_with(HashMap::new)
+            .entry(task_id.to_string())
+            .or_default(),
+    );
+    Registration {
+        task_id: task_id.to_string(),
+        notify,
+    }
+}
+
+/// Wakes the task a callback is about; false when no such task is waiting.
+pub fn notify(task_id: &str) -> bool {
+    let waiting = WAITING.lock().unwrap_or_else(|e| e.into_inner());
+    match waiting.as_ref().and_then(|tasks| tasks.get(task_id)) {
+        Some(notify) => {
+            // Kept as a permit if the task is between two waits.
+            notify.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+pub struct Registration {
+    task_id: String,
+    notify: Arc<Notify>,
+}
+
+impl Registration {
+    /// Sleeps for `wait`, or less if the callback comes first; true if it
+    /// did.
+    pub async fn wait(&self, wait: Duration) -> bool {
+        tokio::time::timeout(wait, self.notify.notified())
+            .await
+            .is_ok()
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(tasks) = WAITING.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            tasks.remove(&self.task_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_callback_ends_the_wait_of_its_task_only() {
+        let registration = register("task-callback");
+        assert!(!notify("task-other"));
+        assert!(notify("task-callback"));
+        assert!(registration.wait(Duration::from_secs(60)).await);
+        assert!(!registration.wait(Duration::from_millis(10)).await);
+        drop(registration);
+        assert!(!notify("task-callback"));
+    }
+}
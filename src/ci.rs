@@ -0,0 +1,170 @@
+use crate::api::CompilationFailed;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::job::JobReport;
+use crate::metrics;
+use crate::redact;
+use crate::report::DocumentReport;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+/// How results are reported on the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    /// GitHub Actions workflow commands (`::error file=...::...`).
+    Github,
+    /// One JSON object on stdout (see [`json_result`]), progress on stderr.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(value: Option<&str>) -> Result<Self> {
+        match value {
+            None | Some("text") => Ok(Self::Text),
+            Some("github") => Ok(Self::Github),
+            Some("json") => Ok(Self::Json),
+            Some(other) => anyhow::bail!(
+                "Unknown --format: {} (expected text, github or json)",
+                other
+            ),
+        }
+    }
+}
+
+/// Prints one annotation per diagnostic of `report`, falling back to a
+/// single error on the input file when a failed job has no diagnostics.
+pub fn annotate(report: &JobReport) {
+    let input = &report.job.input;
+    for diagnostic in &report.details.diagnostics {
+        let command = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let file = workspace_path(&diagnostic.source_path(input));
+        let mut properties = format!("file={}", escape_property(&file));
+        if let Some(line) = diagnostic.line {
+            properties.push_str(&format!(",line={}", line));
+        }
+        println!(
+            "::{} {}::{}",
+            command,
+            properties,
+            escape_data(&diagnostic.message)
+        );
+    }
+
+    if let Err(e) = &report.result {
+        let has_error = report
+            .details
+            .diagnostics
+            .iter()
+            .any(|d| d.severity == Severity::Error);
+        if !has_error && !report.cancelled {
+            println!(
+                "::error file={}::{}",
+                escape_property(&workspace_path(input)),
+                escape_data(&format!("{:#}", e))
+            );
+        }
+    }
+}
+
+/// Compile log lines quoted in a JSON error, at most.
+const EXCERPT_LINES: usize = 20;
+
+/// The outcome of `report` for `--format json`: `{"document": ...}` (see
+/// [`DocumentReport`]) when it succeeded, or else `{"error": {"kind": ...}}`
+/// with the task, the log and the errors parsed from it (`diagnostics`), so
+/// wrappers can branch on the kind instead of matching messages.
+pub fn json_result(report: &JobReport, diagnostics: &[Diagnostic]) -> serde_json::Value {
+    let Err(e) = &report.result else {
+        return redact::json(json!({ "document": DocumentReport::from(report) }));
+    };
+    let errors: Vec<&Diagnostic> = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .collect();
+    let excerpt: Vec<String> = errors
+        .iter()
+        .take(EXCERPT_LINES)
+        .map(|d| match (&d.file, d.line) {
+            (Some(file), Some(line)) => format!("{}:{}: {}", file, line, d.message),
+            (None, Some(line)) => format!("l.{}: {}", line, d.message),
+            _ => d.message.clone(),
+        })
+        .collect();
+    redact::json(json!({
+        "error": {
+            "kind": error_kind(report, e),
+            "message": format!("{:#}", e),
+            "task_id": report.details.task_id,
+            "log_url": report.details.log_url,
+            "log_excerpt": (!excerpt.is_empty()).then(|| excerpt.join("\n")),
+            "diagnostics": errors,
+        }
+    }))
+}
+
+/// `compilation_failed`, `interrupted`, `timeout`, `network_error`,
+/// `server_error` or `local_error` (bad input, files that cannot be read).
+fn error_kind(report: &JobReport, error: &anyhow::Error) -> &'static str {
+    if report.cancelled {
+        return "interrupted";
+    }
+    if error.downcast_ref::<CompilationFailed>().is_some() {
+        return "compilation_failed";
+    }
+    match metrics::failure_class(error) {
+        "timeout" => "timeout",
+        "network" => "network_error",
+        "server" => "server_error",
+        _ => "local_error",
+    }
+}
+
+/// Sets a step output through `$GITHUB_OUTPUT`; does nothing outside Actions.
+pub fn set_output(name: &str, value: &str) -> Result<()> {
+    let Some(path) = std::env::var_os("GITHUB_OUTPUT") else {
+        return Ok(());
+    };
+    let line = if value.contains('\n') {
+        let delimiter = format!("chemtex_{}_eof", name);
+        format!("{}<<{}\n{}\n{}\n", name, delimiter, value, delimiter)
+    } else {
+        format!("{}={}\n", name, value)
+    };
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to write step output {}", name))
+}
+
+/// Annotations must name files relative to the checkout, which is also the
+/// working directory of a step.
+fn workspace_path(path: &Path) -> String {
+    let root = std::env::var_os("GITHUB_WORKSPACE")
+        .map(Into::into)
+        .or_else(|| std::env::current_dir().ok());
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let relative = root
+        .as_deref()
+        .and_then(|root| absolute.strip_prefix(root).ok())
+        .unwrap_or(path);
+    relative.display().to_string().replace('\\', "/")
+}
+
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
@@ -0,0 +1,214 @@
+use crate::deps::strip_comment;
+use anyhow::Result;
+
+/// A bibliography style set at pack time with `--citation-style` or
+/// `citation_style` in `.chemtex.toml`, so one source can go to a journal
+/// and to a Russian university without edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CitationStyle {
+    /// American Chemical Society: numbered, superscript citations.
+    Acs,
+    /// GOST R 7.0.5-2008, numbered in order of citation.
+    Gost,
+    /// APA, author–year.
+    Apa,
+}
+
+const BEGIN_DOCUMENT: &str = "\\begin{document}";
+const MARKER: &str = "% chemtex --citation-style";
+
+impl CitationStyle {
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name.to_lowercase().as_str() {
+            "acs" => Self::Acs,
+            "gost" => Self::Gost,
+            "apa" => Self::Apa,
+            other => anyhow::bail!("Unknown citation style {:?} (acs, gost, apa)", other),
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Acs => "acs",
+            Self::Gost => "gost",
+            Self::Apa => "apa",
+        }
+    }
+
+    /// biblatex `style=` value (biblatex-chem, biblatex-gost, biblatex-apa).
+    fn biblatex_style(self) -> &'static str {
+        match self {
+            Self::Acs => "chem-acs",
+            Self::Gost => "gost-numeric",
+            Self::Apa => "apa",
+        }
+    }
+
+    /// natbib options and BibTeX style (achemso, gost, plain natbib).
+    fn natbib(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Acs => ("super,sort&compress,comma", "achemso"),
+            Self::Gost => ("numbers,sort&compress", "ugost2008"),
+            Self::Apa => ("authoryear,round", "apalike"),
+        }
+    }
+
+    /// Rewrites the preamble of a main file for this style. A document on
+    /// biblatex keeps it with the style swapped; one on BibTeX gets natbib
+    /// and the matching `.bst`, replacing natbib, cite or a `.bst` it chose
+    /// itself. Files without a bibliography or a `\begin{document}`, and
+    /// ones whose biblatex is loaded elsewhere, come back unchanged, with a
+    /// warning that the style was not set.
+    pub fn inject(self, text: &str) -> String {
+        let skip = |reason: &str| {
+            eprintln!(
+                "Warning: --citation-style {} not applied: {}",
+                self.name(),
+                reason
+            );
+            text.to_string()
+        };
+        let Some(begin) = text.find(BEGIN_DOCUMENT) else {
+            return skip("the main file has no \\begin{document}");
+        };
+        let uses_biblatex = text.contains("\\addbibresource") || text.contains("{biblatex}");
+        if !uses_biblatex && !text.contains("\\bibliography{") {
+            return skip("the main file has no \\bibliography or \\addbibresource");
+        }
+        let (preamble, body) = text.split_at(begin);
+        let commands = commands(preamble);
+        let loads_biblatex = commands
+            .iter()
+            .any(|(_, command)| is_package(command, "biblatex"));
+        if uses_biblatex && !loads_biblatex {
+            // Loaded by the class or an input file; loading it again here
+            // would clash.
+            return skip("biblatex is not loaded in the main file's preamble");
+        }
+
+        let mut out = String::with_capacity(text.len() + 200);
+        for (lines, command) in &commands {
+            if uses_biblatex && is_package(command, "biblatex") {
+                out.push_str(&self.biblatex_line(command));
+                out.push('\n');
+            } else if !uses_biblatex
+                && (is_package(command, "natbib")
+                    || is_package(command, "cite")
+                    || command.starts_with("\\bibliographystyle"))
+            {
+                for line in lines.split_inclusive('\n') {
+                    out.push_str(&format!("% {}", line));
+                }
+            } else {
+                out.push_str(lines);
+            }
+        }
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(&format!("{} {}\n", MARKER, self.name()));
+        if !uses_biblatex {
+            let (options, style) = self.natbib();
+            out.push_str(&format!("\\usepackage[{}]{{natbib}}\n", options));
+            out.push_str(&format!("\\bibliographystyle{{{}}}\n", style));
+        }
+        if !uses_biblatex {
+            // A \bibliographystyle left in the body would override ours.
+            for line in body.split_inclusive('\n') {
+                match strip_comment(line)
+                    .trim()
+                    .starts_with("\\bibliographystyle")
+                {
+                    true => out.push_str(&format!("% {}", line)),
+                    false => out.push_str(line),
+                }
+            }
+        } else {
+            out.push_str(body);
+        }
+        out
+    }
+
+    /// `\usepackage[backend=biber,style=authoryear]{biblatex}` with the
+    /// style options replaced and the others kept.
+    fn biblatex_line(self, command: &str) -> String {
+        let mut options: Vec<String> = command
+            .split_once('[')
+            .and_then(|(_, rest)| rest.split_once(']'))
+            .map(|(options, _)| {
+                options
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|o| !o.is_empty())
+                    .filter(|o| {
+                        let name = o.split('=').next().unwrap_or("").trim();
+                        !matches!(name, "style" | "citestyle" | "bibstyle")
+                    })
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if !options.iter().any(|o| o.starts_with("backend")) {
+            options.insert(0, "backend=biber".to_string());
+        }
+        options.push(format!("style={}", self.biblatex_style()));
+        if self == Self::Gost {
+            // Russian and English entries each typeset in their own language.
+            options.push("autolang=other".to_string());
+        }
+        format!("\\usepackage[{}]{{biblatex}}", options.join(","))
+    }
+}
+
+/// The preamble as commands, each with the lines it spans, comments
+/// dropped from the command: an option list may run over several lines,
+/// `\usepackage[\n  style=apa,\n]{biblatex}`.
+fn commands(preamble: &str) -> Vec<(String, String)> {
+    let mut commands = Vec::new();
+    let (mut lines, mut command) = (String::new(), String::new());
+    let mut depth = 0;
+    for line in preamble.split_inclusive('\n') {
+        let code = strip_comment(line).trim();
+        lines.push_str(line);
+        command.push_str(code);
+        depth += code.matches(['[', '{']).count() as isize;
+        depth -= code.matches([']', '}']).count() as isize;
+        if depth <= 0 {
+            commands.push((std::mem::take(&mut lines), std::mem::take(&mut command)));
+            depth = 0;
+        }
+    }
+    if !lines.is_empty() {
+        commands.push((lines, command));
+    }
+    commands
+}
+
+fn is_package(command: &str, package: &str) -> bool {
+    command.starts_with("\\usepackage") && command.ends_with(&format!("{{{}}}", package))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn biblatex_options_over_several_lines_get_the_style() {
+        let text = "\\documentclass{article}\n\\usepackage[\n  backend=biber, % fast\n  style=apa,\n  sorting=nyt\n]{biblatex}\n\\addbibresource{refs.bib}\n\\begin{document}\n\\printbibliography\n\\end{document}\n";
+        assert_eq!(
+            CitationStyle::Gost.inject(text),
+            "\\documentclass{article}\n\\usepackage[backend=biber,sorting=nyt,style=gost-numeric,autolang=other]{biblatex}\n\\addbibresource{refs.bib}\n% chemtex --citation-style gost\n\\begin{document}\n\\printbibliography\n\\end{document}\n"
+        );
+    }
+
+    #[test]
+    fn bibtex_documents_get_natbib_in_place_of_their_own() {
+        let text = "\\documentclass{article}\n\\usepackage[numbers,\n  sort]{natbib}\n\\begin{document}\n\\bibliographystyle{plain}\n\\bibliography{refs}\n\\end{document}\n";
+        assert_eq!(
+            CitationStyle::Acs.inject(text),
+            "\\documentclass{article}\n% \\usepackage[numbers,\n%   sort]{natbib}\n% chemtex --citation-style acs\n\\usepackage[super,sort&compress,comma]{natbib}\n\\bibliographystyle{achemso}\n\\begin{document}\n% \\bibliographystyle{plain}\n\\bibliography{refs}\n\\end{document}\n"
+        );
+        let elsewhere = "\\documentclass{thesis}\n\\addbibresource{refs.bib}\n\\begin{document}\n\\end{document}\n";
+        assert_eq!(CitationStyle::Apa.inject(elsewhere), elsewhere);
+    }
+}
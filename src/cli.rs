@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use std::str::FromStr;
+
+/// Minimal command-line parser shared by the subcommands.
+///
+/// Every subcommand declares which `--flags` it accepts and which `--options`
+/// take a value; anything else starting with `--` is rejected.
+#[derive(Debug, Default)]
+pub struct Args {
+    positionals: Vec<String>,
+    flags: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    pub fn parse(raw: &[String], flags: &[&str], options: &[&str]) -> Result<Self> {
+        let mut args = Self::default();
+        let mut iter = raw.iter();
+
+        while let Some(arg) = iter.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                args.positionals.push(arg.clone());
+                continue;
+            };
+
+            if let Some((name, value)) = name.split_once('=') {
+                if !options.contains(&name) {
+                    anyhow::bail!("Unknown option: --{}", name);
+                }
+                args.options.push((name.to_string(), value.to_string()));
+            } else if options.contains(&name) {
+                let value = iter
+                    .next()
+                    .with_context(|| format!("Option --{} requires a value", name))?;
+                args.options.push((name.to_string(), value.clone()));
+            } else if flags.contains(&name) {
+                args.flags.push(name.to_string());
+            } else {
+                anyhow::bail!("Unknown option: --{}", name);
+            }
+        }
+
+        Ok(args)
+    }
+
+    pub fn positional(&self, index: usize) -> Option<&str> {
+        self.positionals.get(index).map(String::as_str)
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    /// Returns the last value given for `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value given for a repeatable option, in order.
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn parsed<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.value(name)
+            .map(|v| {
+                v.parse::<T>()
+                    .map_err(|e| anyhow::anyhow!("Invalid value for --{}: {} ({})", name, v, e))
+            })
+            .transpose()
+    }
+}
@@ -0,0 +1,374 @@
+//! `chemtex compare --engines pdflatex,lualatex main.tex`: compiles the same
+//! document with each engine in parallel and reports how the builds differ
+//! (whether they compiled, their page counts and warnings, and which pages
+//! look different), to help pick the engine chemistry packages work best
+//! with.
+//!
+//! Pages are compared as rendered by pdftoppm when it is installed, each
+//! engine against the first one that compiled.
+
+use crate::api::{self, CompileOptions};
+use crate::batch;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::count;
+use crate::diagnostics::Severity;
+use crate::fixtures;
+use crate::har;
+use crate::job::{Job, JobReport, Runner};
+use crate::pack::{self, TempDir};
+use crate::paths;
+use crate::progress::Progress;
+use crate::rasterize::{self, GrayPage};
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Resolution pages are compared at; enough to see a reflowed line.
+const VISUAL_DPI: u32 = 50;
+/// A pixel counts as changed when its grey level moves by more than this,
+/// so antialiasing differences do not.
+const PIXEL_TOLERANCE: u8 = 32;
+/// Pages with fewer changed pixels look the same.
+const SAME_PAGE_FRACTION: f64 = 0.001;
+/// Warnings listed per engine.
+const LISTED_WARNINGS: usize = 5;
+
+const USAGE: &str = "\
+Usage: chemtex compare --engines ENGINE,ENGINE[,...] <file.tex|file.zip|project_dir>
+                       [--profile NAME] [--out-dir DIR] [--no-visual] [--no-cache] [--plain]
+                       [--record DIR | --replay DIR] [--capture-http FILE]
+
+Compiles the document once per engine, in parallel, writing <name>-<engine>.pdf
+to --out-dir (the current directory by default), and compares the builds:
+whether each compiled, page counts, warnings only some engines give, and with
+pdftoppm installed, which pages look different from the first engine's.";
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["no-visual", "no-cache", "plain"],
+        &[
+            "engines",
+            "profile",
+            "out-dir",
+            "record",
+            "replay",
+            "capture-http",
+            "capture-bodies",
+        ],
+    )?;
+    let (Some(input), Some(engines)) = (args.positional(0), args.value("engines")) else {
+        anyhow::bail!(USAGE);
+    };
+    let mut engines: Vec<&str> = engines
+        .split(',')
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .collect();
+    let mut seen = BTreeSet::new();
+    engines.retain(|engine| seen.insert(*engine));
+    anyhow::ensure!(
+        engines.len() >= 2,
+        "--engines needs at least two engines to compare, like pdflatex,lualatex"
+    );
+
+    let scratch = TempDir::new("compare")?;
+    let input = Path::new(input);
+    let (input, name) = if input.is_dir() {
+        let config = ProjectConfig::find(input)?.unwrap_or_default();
+        let main = match &config.main {
+            Some(main) => input.join(main),
+            None => batch::find_main_document(input)?,
+        };
+        let archive = pack::pack_project(&main, scratch.path())?;
+        (archive, stem(&main)?)
+    } else {
+        (input.to_path_buf(), stem(input)?)
+    };
+    let out_dir = PathBuf::from(args.value("out-dir").unwrap_or(""));
+
+    let jobs = engines
+        .iter()
+        .map(|engine| {
+            let mut job = Job::new(&input, &out_dir)?;
+            // Named apart for the progress labels and the history.
+            job.name = format!("{}-{}", name, engine);
+            job.output = out_dir.join(format!("{}.pdf", job.name));
+            job.options = CompileOptions {
+                engine: Some(engine.to_string()),
+                profile: args.value("profile").map(str::to_string),
+                ..CompileOptions::default()
+            };
+            Ok(job)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    fixtures::configure(&args)?;
+    har::configure(&args)?;
+    let use_cache = !args.flag("no-cache") && !fixtures::active();
+    let mut runner = Runner::new(api::build_client()?, use_cache)?;
+    if args.flag("plain") {
+        runner.progress = Progress::plain();
+    }
+    runner.collect_diagnostics = true;
+    println!(
+        "Compiling {} with {}",
+        paths::file_name(&input)?,
+        engines.join(", ")
+    );
+    let reports = batch::run_jobs(&runner, jobs, engines.len(), false).await;
+
+    print_table(&reports);
+    print_warnings(&reports);
+    if !args.flag("no-visual") {
+        print_visual(&reports)?;
+    }
+    if reports.iter().all(|report| !report.is_success()) {
+        anyhow::bail!("No engine compiled {}", name);
+    }
+    Ok(())
+}
+
+fn stem(path: &Path) -> Result<String> {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .map(str::to_string)
+        .with_context(|| format!("Invalid file name: {}", path.display()))
+}
+
+fn engine(report: &JobReport) -> &str {
+    report.job.options.engine.as_deref().unwrap_or_default()
+}
+
+fn pages(report: &JobReport) -> Option<u32> {
+    let output = report.result.as_ref().ok()?;
+    count::page_count(&fs::read(output).ok()?)
+}
+
+fn warnings(report: &JobReport) -> BTreeSet<String> {
+    report
+        .details
+        .diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Warning)
+        .map(|d| d.message.clone())
+        .collect()
+}
+
+fn print_table(reports: &[JobReport]) {
+    let rows: Vec<[String; 6]> = reports
+        .iter()
+        .map(|r| {
+            let (status, detail) = match &r.result {
+                Ok(output) => ("OK".to_string(), output.display().to_string()),
+                Err(e) => ("FAILED".to_string(), format!("{:#}", e)),
+            };
+            let warnings = match (&r.result, r.details.warnings) {
+                (Ok(_), Some(count)) => count.to_string(),
+                (Ok(_), None) => warnings(r).len().to_string(),
+                (Err(_), _) => "-".to_string(),
+            };
+            [
+                engine(r).to_string(),
+                status,
+                pages(r).map_or_else(|| "-".to_string(), |pages| pages.to_string()),
+                warnings,
+                api::format_milliseconds(r.elapsed.as_millis() as u64),
+                detail,
+            ]
+        })
+        .collect();
+
+    let headers = ["Engine", "Status", "Pages", "Warnings", "Time", "Result"];
+    let mut widths = headers.map(|h| h.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()).take(5) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: [&str; 6]| {
+        let mut line = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            if i < 5 {
+                line.push_str(&format!("{:<width$}  ", cell, width = widths[i]));
+            } else {
+                line.push_str(cell);
+            }
+        }
+        line
+    };
+
+    println!();
+    println!("{}", format_row(headers));
+    for row in &rows {
+        println!("{}", format_row(row.each_ref().map(String::as_str)));
+    }
+}
+
+/// The warnings of each engine that compiled which not every other one
+/// gave.
+fn print_warnings(reports: &[JobReport]) {
+    let compiled: Vec<(&str, BTreeSet<String>)> = reports
+        .iter()
+        .filter(|r| r.is_success())
+        .map(|r| (engine(r), warnings(r)))
+        .collect();
+    for (engine, own) in &compiled {
+        let only: Vec<&String> = own
+            .iter()
+            .filter(|warning| {
+                compiled
+                    .iter()
+                    .any(|(other, theirs)| other != engine && !theirs.contains(*warning))
+            })
+            .collect();
+        if only.is_empty() {
+            continue;
+        }
+        println!();
+        println!("Warnings not every engine gave, with {}:", engine);
+        for warning in only.iter().take(LISTED_WARNINGS) {
+            println!("  {}", warning);
+        }
+        if only.len() > LISTED_WARNINGS {
+            println!("  ... and {} more", only.len() - LISTED_WARNINGS);
+        }
+    }
+}
+
+fn print_visual(reports: &[JobReport]) -> Result<()> {
+    let compiled: Vec<(&str, &Path)> = reports
+        .iter()
+        .filter_map(|r| Some((engine(r), r.result.as_ref().ok()?.as_path())))
+        .collect();
+    let [(baseline, baseline_pdf), others @ ..] = compiled.as_slice() else {
+        return Ok(());
+    };
+    if others.is_empty() {
+        return Ok(());
+    }
+    println!();
+    if !rasterize::available() {
+        println!("Pages not compared: install pdftoppm (poppler) to compare them");
+        return Ok(());
+    }
+    let baseline_pages = rasterize::gray_pages(baseline_pdf, VISUAL_DPI)?;
+    for (engine, pdf) in others {
+        let pages = rasterize::gray_pages(pdf, VISUAL_DPI)?;
+        println!(
+            "{} against {}: {}",
+            engine,
+            baseline,
+            describe(&page_differences(&baseline_pages, &pages))
+        );
+    }
+    Ok(())
+}
+
+/// How a page of one build compares to the same page of another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PageDifference {
+    Same,
+    /// The fraction of pixels that changed.
+    Changed(f64),
+    /// The page is in one build only.
+    Missing,
+}
+
+fn page_differences(baseline: &[GrayPage], other: &[GrayPage]) -> Vec<PageDifference> {
+    (0..baseline.len().max(other.len()))
+        .map(|i| match (baseline.get(i), other.get(i)) {
+            (Some(a), Some(b)) => match changed_fraction(a, b) {
+                fraction if fraction < SAME_PAGE_FRACTION => PageDifference::Same,
+                fraction => PageDifference::Changed(fraction),
+            },
+            _ => PageDifference::Missing,
+        })
+        .collect()
+}
+
+/// The fraction of pixels whose grey level changed; pages of different
+/// sizes are entirely different.
+fn changed_fraction(a: &GrayPage, b: &GrayPage) -> f64 {
+    if (a.width, a.height) != (b.width, b.height) || a.pixels.is_empty() {
+        return 1.0;
+    }
+    let changed = a
+        .pixels
+        .iter()
+        .zip(&b.pixels)
+        .filter(|(x, y)| x.abs_diff(**y) > PIXEL_TOLERANCE)
+        .count();
+    changed as f64 / a.pixels.len() as f64
+}
+
+fn describe(differences: &[PageDifference]) -> String {
+    let same = differences
+        .iter()
+        .filter(|d| **d == PageDifference::Same)
+        .count();
+    if same == differences.len() {
+        return format!("all {} page(s) look the same", same);
+    }
+    let mut details = Vec::new();
+    for (i, difference) in differences.iter().enumerate() {
+        match difference {
+            PageDifference::Same => {}
+            PageDifference::Changed(fraction) => {
+                details.push(format!("page {} ({:.1}% changed)", i + 1, fraction * 100.0))
+            }
+            PageDifference::Missing => details.push(format!("page {} (in one only)", i + 1)),
+        }
+    }
+    format!(
+        "{} of {} page(s) look the same; different: {}",
+        same,
+        differences.len(),
+        details.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(pixels: &[u8]) -> GrayPage {
+        GrayPage {
+            width: pixels.len(),
+            height: 1,
+            pixels: pixels.to_vec(),
+        }
+    }
+
+    #[test]
+    fn pages_are_compared_pixel_by_pixel() {
+        let white = page(&[255; 4000]);
+        let mut antialiased = white.clone();
+        antialiased.pixels[0] = 240;
+        let mut reflowed = white.clone();
+        reflowed.pixels[..400].fill(0);
+
+        let differences = page_differences(
+            &[white.clone(), white.clone()],
+            &[antialiased, reflowed, white],
+        );
+        assert_eq!(
+            differences,
+            [
+                PageDifference::Same,
+                PageDifference::Changed(0.1),
+                PageDifference::Missing
+            ]
+        );
+        assert_eq!(
+            describe(&differences),
+            "1 of 3 page(s) look the same; different: page 2 (10.0% changed), page 3 (in one only)"
+        );
+        assert_eq!(
+            describe(&[PageDifference::Same]),
+            "all 1 page(s) look the same"
+        );
+    }
+}
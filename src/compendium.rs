@@ -0,0 +1,570 @@
+use crate::api;
+use crate::cli::Args;
+use crate::deps::strip_comment;
+use crate::job::{Job, Runner};
+use crate::pack::{self, PackOptions, TempDir};
+use crate::preprocess;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: chemtex compendium <summary.tex>... --out FILE [options]
+
+Merges standalone summaries into one document and compiles it, e.g.
+  chemtex compendium lectures/*.tex --out semester.tex
+
+Each summary becomes a chapter (a part, if it has chapters itself) titled
+after its \\title. Packages are loaded once with their options combined,
+labels used by several summaries are renamed, and figures and inputs are
+re-pointed from the summary's directory, which must lie under the output's.
+Section headings go into a shared index next to the table of contents.
+
+Options:
+  --out FILE       The master document to write (required)
+  --title TEXT     Its title (default: the output's file name)
+  --class NAME     Document class (default: report)
+  --formula-index  Also index every \\ce{...} compound when compiling
+  --no-compile     Only write the master document
+  --no-cache       Always submit, ignoring the build cache";
+
+/// Commands whose argument is a (comma-separated) path relative to the
+/// document.
+const PATH_COMMANDS: &[&str] = &[
+    "includegraphics",
+    "input",
+    "include",
+    "bibliography",
+    "addbibresource",
+];
+
+/// Commands taking (comma-separated) labels.
+const LABEL_COMMANDS: &[&str] = &[
+    "label", "ref", "eqref", "pageref", "autoref", "nameref", "vref", "cref", "Cref",
+];
+
+/// Front and back matter the master document has once, dropped from the
+/// summaries' bodies.
+const MATTER: &[&str] = &[
+    "\\maketitle",
+    "\\tableofcontents",
+    "\\listoffigures",
+    "\\listoftables",
+    "\\printindex",
+    "\\printbibliography",
+];
+
+/// Indexed headings.
+const HEADINGS: &[&str] = &["section", "subsection"];
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["no-compile", "no-cache", "formula-index"],
+        &["out", "title", "class"],
+    )?;
+    let inputs: Vec<PathBuf> = (0..)
+        .map_while(|i| args.positional(i))
+        .map(PathBuf::from)
+        .collect();
+    let output = PathBuf::from(args.value("out").context(USAGE)?);
+    if inputs.is_empty() {
+        anyhow::bail!(USAGE);
+    }
+    let title = match args.value("title") {
+        Some(title) => title.to_string(),
+        None => output
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("Compendium")
+            .to_string(),
+    };
+    let out_dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut summaries = Vec::new();
+    for input in &inputs {
+        summaries.push(Summary::read(input, &out_dir)?);
+    }
+    let master = merge(
+        &mut summaries,
+        &title,
+        args.value("class").unwrap_or("report"),
+    );
+    fs::write(&output, master)
+        .with_context(|| format!("Failed to write file: {}", output.display()))?;
+    println!(
+        "Merged {} summaries into {}",
+        summaries.len(),
+        output.display()
+    );
+
+    if args.flag("no-compile") {
+        return Ok(());
+    }
+    let scratch = TempDir::new("compendium")?;
+    let options = PackOptions {
+        formula_index: args.flag("formula-index"),
+        ..PackOptions::default()
+    };
+    let archive = pack::pack_project_with(&output, scratch.path(), &options)?;
+    let runner = Runner::new(api::build_client()?, !args.flag("no-cache"))?;
+    let mut job = Job::new(&archive, Path::new(""))?;
+    job.output = output.with_extension("pdf");
+    runner.run(&job, "").await.result?;
+    Ok(())
+}
+
+/// One standalone document, split up for merging.
+struct Summary {
+    name: String,
+    title: String,
+    class_options: Vec<String>,
+    /// Logical preamble lines (commands spanning lines joined), without
+    /// comments.
+    preamble: Vec<String>,
+    body: String,
+}
+
+impl Summary {
+    fn read(path: &Path, out_dir: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        let (preamble, body) = text
+            .split_once("\\begin{document}")
+            .with_context(|| format!("{} has no \\begin{{document}}", path.display()))?;
+        let body = body.split("\\end{document}").next().unwrap_or(body);
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .context("Invalid file name")?
+            .to_string();
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        // Directives read their data relative to the summary, so they are
+        // expanded before the text moves.
+        let body = preprocess::expand(body, dir)
+            .with_context(|| format!("Failed to expand {}", path.display()))?;
+        let prefix = relative_dir(dir, out_dir).with_context(|| {
+            format!(
+                "{} is outside the output directory {}",
+                path.display(),
+                out_dir.display()
+            )
+        })?;
+
+        let mut summary = Self {
+            title: name.clone(),
+            name,
+            class_options: Vec::new(),
+            preamble: Vec::new(),
+            body: String::new(),
+        };
+        for line in logical_lines(preamble) {
+            if let Some(rest) = line.strip_prefix("\\documentclass") {
+                summary.class_options = optional_argument(rest)
+                    .map(|options| split_list(&options))
+                    .unwrap_or_default();
+            } else if let Some(title) = line.strip_prefix("\\title") {
+                if let Some(title) = braced(title) {
+                    summary.title = title;
+                }
+            } else if !["\\author", "\\date", "\\makeindex"]
+                .iter()
+                .any(|c| command_is(&line, c))
+            {
+                summary.preamble.push(rewrite_paths(&line, &prefix));
+            }
+        }
+        let body: Vec<&str> = body
+            .lines()
+            .filter(|line| {
+                let command = strip_comment(line).trim();
+                !MATTER.iter().any(|m| command_is(command, m))
+            })
+            .collect();
+        summary.body = index_headings(&rewrite_paths(&body.join("\n"), &prefix));
+        Ok(summary)
+    }
+
+    fn labels(&self) -> HashSet<String> {
+        let mut labels = HashSet::new();
+        rewrite_arguments(&self.body, "label", |label| {
+            labels.insert(label.to_string());
+            label.to_string()
+        });
+        labels
+    }
+}
+
+/// The master document: the first summary's class options on `class`,
+/// harmonised packages, the other preamble lines once each, then a chapter
+/// per summary.
+fn merge(summaries: &mut [Summary], title: &str, class: &str) -> String {
+    // Labels defined by more than one summary are prefixed with the
+    // summary's name everywhere but in the first that defines them.
+    let mut owners: HashMap<String, usize> = HashMap::new();
+    for (i, summary) in summaries.iter_mut().enumerate() {
+        let clashing: HashSet<String> = summary
+            .labels()
+            .into_iter()
+            .filter(|label| *owners.entry(label.clone()).or_insert(i) != i)
+            .collect();
+        if clashing.is_empty() {
+            continue;
+        }
+        eprintln!(
+            "{}: {} label(s) also used by an earlier summary, prefixed with {}:",
+            summary.name,
+            clashing.len(),
+            summary.name
+        );
+        let name = summary.name.clone();
+        let mut body = summary.body.clone();
+        for command in LABEL_COMMANDS {
+            body = rewrite_arguments(&body, command, |labels| {
+                split_list(labels)
+                    .into_iter()
+                    .map(|label| match clashing.contains(&label) {
+                        true => format!("{}:{}", name, label),
+                        false => label,
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            });
+        }
+        summary.body = body;
+    }
+
+    let mut packages: Vec<(String, Vec<String>)> = Vec::new();
+    let mut others: Vec<String> = Vec::new();
+    let mut defined: HashMap<String, String> = HashMap::new();
+    let mut bibliographies: Vec<String> = Vec::new();
+    for summary in summaries.iter() {
+        for line in &summary.preamble {
+            if let Some(rest) = line.strip_prefix("\\usepackage") {
+                let options = optional_argument(rest)
+                    .map(|o| split_list(&o))
+                    .unwrap_or_default();
+                let names = braced(rest).map(|n| split_list(&n)).unwrap_or_default();
+                // imakeidx, loaded for the shared index, replaces makeidx.
+                for name in names.into_iter().filter(|n| n != "makeidx") {
+                    match packages.iter_mut().find(|(p, _)| *p == name) {
+                        Some((_, existing)) => {
+                            for option in &options {
+                                if !existing.contains(option) {
+                                    existing.push(option.clone());
+                                }
+                            }
+                        }
+                        None => packages.push((name, options.clone())),
+                    }
+                }
+            } else if let Some(rest) = line.strip_prefix("\\addbibresource") {
+                if let Some(file) = braced(rest).filter(|f| !bibliographies.contains(f)) {
+                    bibliographies.push(file);
+                }
+            } else if let Some(name) = defined_command(line) {
+                match defined.get(&name) {
+                    Some(first) if first != line => eprintln!(
+                        "{} defines {} differently; keeping the first definition",
+                        summary.name, name
+                    ),
+                    Some(_) => {}
+                    None => {
+                        defined.insert(name, line.clone());
+                        others.push(line.clone());
+                    }
+                }
+            } else if !others.contains(line) {
+                others.push(line.clone());
+            }
+        }
+    }
+    if !packages.iter().any(|(name, _)| name == "imakeidx") {
+        packages.push(("imakeidx".to_string(), Vec::new()));
+    }
+    // hyperref and what builds on it go last.
+    let late = ["hyperref", "cleveref"];
+    packages.sort_by_key(|(name, _)| late.iter().position(|l| l == name));
+    let bibtex: Vec<String> = summaries
+        .iter()
+        .flat_map(|s| {
+            let mut files = Vec::new();
+            rewrite_arguments(&s.body, "bibliography", |file| {
+                files.extend(split_list(file));
+                String::new()
+            });
+            files
+        })
+        .fold(Vec::new(), |mut all, file| {
+            if !all.contains(&file) {
+                all.push(file);
+            }
+            all
+        });
+
+    let mut out = String::new();
+    let class_options = summaries
+        .first()
+        .map(|s| s.class_options.join(","))
+        .unwrap_or_default();
+    match class_options.is_empty() {
+        true => out.push_str(&format!("\\documentclass{{{}}}\n", class)),
+        false => out.push_str(&format!(
+            "\\documentclass[{}]{{{}}}\n",
+            class_options, class
+        )),
+    }
+    for (name, options) in &packages {
+        match options.is_empty() {
+            true => out.push_str(&format!("\\usepackage{{{}}}\n", name)),
+            false => out.push_str(&format!(
+                "\\usepackage[{}]{{{}}}\n",
+                options.join(","),
+                name
+            )),
+        }
+    }
+    out.push_str("\\makeindex[intoc]\n");
+    for line in &others {
+        out.push_str(line);
+        out.push('\n');
+    }
+    for file in &bibliographies {
+        out.push_str(&format!("\\addbibresource{{{}}}\n", file));
+    }
+    out.push_str(&format!("\\title{{{}}}\n\\date{{}}\n", title));
+    out.push_str("\n\\begin{document}\n\\maketitle\n\\tableofcontents\n");
+
+    let has_chapters = summaries.iter().any(|s| s.body.contains("\\chapter"));
+    let unit = if has_chapters { "part" } else { "chapter" };
+    for summary in summaries.iter() {
+        let mut body = summary.body.clone();
+        for command in ["bibliography", "bibliographystyle"] {
+            body = remove_command(&body, command);
+        }
+        out.push_str(&format!(
+            "\n% {}\n\\{}{{{}}}\n",
+            summary.name, unit, summary.title
+        ));
+        out.push_str(body.trim());
+        out.push('\n');
+    }
+
+    out.push('\n');
+    if !bibtex.is_empty() {
+        out.push_str(&format!("\\bibliography{{{}}}\n", bibtex.join(",")));
+    }
+    if !bibliographies.is_empty() {
+        out.push_str("\\printbibliography\n");
+    }
+    out.push_str("\\printindex\n\\end{document}\n");
+    out
+}
+
+/// Preamble lines without comments, a command whose braces span several
+/// lines joined into one.
+fn logical_lines(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0i32;
+    for line in text.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() && depth == 0 {
+            continue;
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(line);
+        depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        if depth <= 0 {
+            lines.push(std::mem::take(&mut current));
+            depth = 0;
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+/// `line` is `command` itself, not a longer command sharing its prefix.
+fn command_is(line: &str, command: &str) -> bool {
+    line.strip_prefix(command)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+/// The name a `\newcommand{\x}`, `\renewcommand\x`, `\DeclareMathOperator`
+/// or `\def\x` line defines.
+fn defined_command(line: &str) -> Option<String> {
+    let rest = [
+        "\\newcommand",
+        "\\renewcommand",
+        "\\providecommand",
+        "\\DeclareMathOperator",
+        "\\newenvironment",
+        "\\def",
+    ]
+    .iter()
+    .find_map(|command| {
+        line.strip_prefix(command)
+            .filter(|rest| !rest.starts_with(|c: char| c.is_ascii_alphabetic()))
+    })?;
+    let rest = rest.trim_start_matches('*').trim_start();
+    let name: String = match rest.strip_prefix('{') {
+        Some(inner) => inner.split('}').next()?.trim().to_string(),
+        None => {
+            let tail = rest.strip_prefix('\\')?;
+            let name: String = tail
+                .chars()
+                .take_while(|c| c.is_ascii_alphabetic())
+                .collect();
+            format!("\\{}", name)
+        }
+    };
+    Some(name)
+}
+
+/// The `[...]` argument at the start of `text`.
+fn optional_argument(text: &str) -> Option<String> {
+    let rest = text.trim_start().strip_prefix('[')?;
+    Some(rest.split_once(']')?.0.to_string())
+}
+
+/// The first `{...}` argument in `text`, nested braces included.
+fn braced(text: &str) -> Option<String> {
+    let start = text.find('{')?;
+    let mut depth = 0;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(text[start + 1..start + i].to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// `dir` relative to `base` as a `/`-separated prefix (empty when they are
+/// the same); `None` when `dir` is not under `base`.
+fn relative_dir(dir: &Path, base: &Path) -> Option<String> {
+    let canonical = |p: &Path| match p.as_os_str().is_empty() {
+        true => Path::new(".").canonicalize(),
+        false => p.canonicalize(),
+    };
+    let dir = canonical(dir).ok()?;
+    let base = canonical(base).ok()?;
+    let relative = dir.strip_prefix(&base).ok()?;
+    let parts: Vec<String> = relative
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    Some(parts.iter().map(|p| format!("{}/", p)).collect())
+}
+
+fn rewrite_paths(text: &str, prefix: &str) -> String {
+    if prefix.is_empty() {
+        return text.to_string();
+    }
+    let mut text = text.to_string();
+    for command in PATH_COMMANDS {
+        text = rewrite_arguments(&text, command, |paths| {
+            split_list(paths)
+                .into_iter()
+                .map(|path| match path.starts_with('/') || path.contains(':') {
+                    true => path,
+                    false => format!("{}{}", prefix, path),
+                })
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+    }
+    text
+}
+
+/// Adds an index entry after every indexed heading.
+fn index_headings(text: &str) -> String {
+    let mut text = text.to_string();
+    for heading in HEADINGS {
+        text = rewrite_arguments(&text, heading, |title| {
+            let entry: String = title
+                .chars()
+                .filter(|c| !matches!(c, '!' | '@' | '|'))
+                .collect();
+            // The closing brace of the heading comes from the rewrite, so
+            // this reads `title}\index{entry` to give `\section{title}\index{entry}`.
+            format!("{}}}\\index{{{}", title, entry)
+        });
+    }
+    text
+}
+
+fn remove_command(text: &str, command: &str) -> String {
+    text.lines()
+        .filter(|line| !command_is(strip_comment(line).trim(), &format!("\\{}", command)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Replaces the mandatory argument of every `\command` (starred or with an
+/// optional `[...]` argument first) by `f` of it; longer commands sharing
+/// the prefix are left alone.
+fn rewrite_arguments(text: &str, command: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let needle = format!("\\{}", command);
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(&needle) {
+        let after = &rest[pos + needle.len()..];
+        if after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            out.push_str(&rest[..pos + needle.len()]);
+            rest = after;
+            continue;
+        }
+        let mut skip = after.len() - after.trim_start_matches('*').len();
+        let tail = &after[skip..];
+        if tail.trim_start().starts_with('[') {
+            let options = tail.trim_start();
+            if let Some(end) = options.find(']') {
+                skip += tail.len() - options.len() + end + 1;
+            }
+        }
+        let tail = &after[skip..];
+        let Some(open) = tail.find('{').filter(|&i| tail[..i].trim().is_empty()) else {
+            out.push_str(&rest[..pos + needle.len()]);
+            rest = after;
+            continue;
+        };
+        let Some(argument) = braced(&tail[open..]) else {
+            out.push_str(&rest[..pos + needle.len()]);
+            rest = after;
+            continue;
+        };
+        out.push_str(&rest[..pos + needle.len() + skip + open + 1]);
+        out.push_str(&f(&argument));
+        out.push('}');
+        rest = &tail[open + argument.len() + 2..];
+    }
+    out.push_str(rest);
+    out
+}
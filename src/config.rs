@@ -0,0 +1,116 @@
+use crate::api::StatusPolicy;
+use crate::badge::BadgeConfig;
+use crate::hooks::Hooks;
+use crate::titlepage::TitlePage;
+use crate::variables::Variables;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = ".chemtex.toml";
+
+/// Project settings in `.chemtex.toml` at the root of a document project:
+///
+/// ```toml
+/// main = "main.tex"
+/// engine = "pdflatex"
+/// account = "university"
+/// output = "titration.pdf"
+/// citation_style = "gost"
+/// formula_index = true
+/// lang = "ru,en"
+/// qr = "commit"
+///
+/// [variables]
+/// author = "Jane Doe"
+/// course = "CHEM 201"
+///
+/// [titlepage]
+/// layout = "russian"
+/// university = "Lomonosov Moscow State University"
+///
+/// [badge]
+/// path = "docs/build.svg"
+///
+/// [hooks]
+/// pre_compile = "python plots/render.py"
+/// ```
+///
+/// Paths are relative to the project directory. `variables` fill `{{name}}`
+/// placeholders left in the sources when the project is packed; `titlepage`
+/// generates the title page (see [`crate::titlepage`]), `badge` a build
+/// status badge (see [`crate::badge`]), and `hooks` run around every
+/// compile of the project (see [`Hooks`]).
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub main: Option<PathBuf>,
+    pub engine: Option<String>,
+    pub profile: Option<String>,
+    /// The account to compile with unless `--account` or `CHEMTEX_ACCOUNT`
+    /// names another; see [`crate::accounts`].
+    pub account: Option<String>,
+    pub output: Option<PathBuf>,
+    /// `acs`, `gost` or `apa`; see [`crate::citations`].
+    pub citation_style: Option<String>,
+    /// Adds an index of every `\ce{...}` compound; see [`crate::formulas`].
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub formula_index: bool,
+    /// Languages for babel or polyglossia, main first: `ru,en`; see
+    /// [`crate::languages`].
+    pub lang: Option<String>,
+    #[serde(default, skip_serializing_if = "Variables::is_empty")]
+    pub variables: Variables,
+    pub titlepage: Option<TitlePage>,
+    /// `commit` or a URL to stamp as a QR code; see [`crate::qr`].
+    pub qr: Option<String>,
+    /// Handling of task statuses the client does not know; see
+    /// [`StatusPolicy`].
+    #[serde(default, skip_serializing_if = "StatusPolicy::is_default")]
+    pub status: StatusPolicy,
+    pub badge: Option<BadgeConfig>,
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
+}
+
+impl ProjectConfig {
+    /// Loads `dir/.chemtex.toml`, if there is one.
+    pub fn find(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(CONFIG_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read config: {}", path.display()))
+            }
+        };
+        toml::from_str(&text)
+            .map(Some)
+            .with_context(|| format!("Failed to parse config: {}", path.display()))
+    }
+
+    /// The `[status]` policy for compiling `input`: that of the
+    /// `.chemtex.toml` next to it, or the default.
+    pub fn status_for(input: &Path) -> Result<StatusPolicy> {
+        let dir = input.parent().unwrap_or(Path::new(""));
+        Ok(Self::find(dir)?
+            .map(|config| config.status)
+            .unwrap_or_default())
+    }
+
+    /// The `[hooks]` for compiling `input`: those of the `.chemtex.toml`
+    /// next to it, run from its directory.
+    pub fn hooks_for(input: &Path) -> Result<Hooks> {
+        let dir = input.parent().unwrap_or(Path::new(""));
+        Ok(Self::find(dir)?
+            .map(|config| config.hooks.in_dir(dir))
+            .unwrap_or_default())
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(CONFIG_FILE);
+        let text = toml::to_string(self).context("Failed to serialize config")?;
+        fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
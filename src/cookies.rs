@@ -0,0 +1,337 @@
+//! Cookies the compile server sets (backend affinity, CSRF tokens), kept in
+//! `<data dir>/cookies.json` and sent back on later requests, also by later
+//! invocations: some deployments route a task's status checks to the backend
+//! that took the upload only when the affinity cookie comes along.
+//!
+//! Set `CHEMTEX_NO_COOKIES=1` to send and keep none.
+
+use crate::redact;
+use crate::storage;
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use reqwest::header::{HeaderMap, SET_COOKIE};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const COOKIES_FILE: &str = "cookies.json";
+
+/// Loaded on the first request.
+static JAR: Mutex<Option<Jar>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Cookie {
+    name: String,
+    value: String,
+    /// Lowercase, without a leading dot.
+    domain: String,
+    /// Sent to `domain` only, not its subdomains (no `Domain` attribute).
+    host_only: bool,
+    path: String,
+    secure: bool,
+    /// Unix time; `None` for a session cookie, which is kept until the
+    /// server replaces it since every chemtex run is a short session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires: Option<i64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Jar {
+    cookies: Vec<Cookie>,
+}
+
+fn enabled() -> bool {
+    std::env::var_os("CHEMTEX_NO_COOKIES").is_none()
+}
+
+fn jar_path() -> Result<PathBuf> {
+    Ok(storage::data_dir()?.join(COOKIES_FILE))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Runs `f` on the jar, loading it first if needed. A jar that does not
+/// parse is started afresh rather than failing the request.
+fn with_jar<T>(f: impl FnOnce(&mut Jar) -> T) -> Result<T> {
+    let mut jar = JAR.lock().unwrap_or_else(|e| e.into_inner());
+    if jar.is_none() {
+        let path = jar_path()?;
+        let loaded = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_default(),
+            Err(_) => Jar::default(),
+        };
+        *jar = Some(loaded);
+    }
+    let jar = jar.as_mut().expect("loaded above");
+    let result = f(jar);
+    for cookie in &jar.cookies {
+        redact::secret(&cookie.value);
+    }
+    Ok(result)
+}
+
+/// The `Cookie` header for a request to `url`, if any stored cookie applies.
+pub fn header(url: &Url) -> Result<Option<String>> {
+    if !enabled() {
+        return Ok(None);
+    }
+    with_jar(|jar| jar.header(url, now()))
+}
+
+/// Keeps the cookies of a response to `url`.
+pub fn store(url: &Url, headers: &HeaderMap) -> Result<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    let set_cookies: Vec<String> = headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(String::from)
+        .collect();
+    if set_cookies.is_empty() {
+        return Ok(());
+    }
+    let changed = with_jar(|jar| {
+        let now = now();
+        let mut changed = false;
+        for set_cookie in &set_cookies {
+            if let Some(cookie) = parse(set_cookie, url, now) {
+                changed |= jar.insert(cookie, now);
+            }
+        }
+        changed.then(|| serde_json::to_string_pretty(jar))
+    })?;
+    let Some(json) = changed.transpose()? else {
+        return Ok(());
+    };
+    let path = jar_path()?;
+    if let Some(dir) = path.parent() {
+        storage::ensure_dir(dir)?;
+    }
+    fs::write(&path, json).with_context(|| format!("Failed to write cookies: {}", path.display()))
+}
+
+impl Jar {
+    fn header(&self, url: &Url, now: i64) -> Option<String> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let pairs: Vec<String> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.expires.is_none_or(|expires| expires > now))
+            .filter(|cookie| cookie.matches_domain(&host))
+            .filter(|cookie| path_matches(url.path(), &cookie.path))
+            .filter(|cookie| !cookie.secure || url.scheme() == "https")
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        (!pairs.is_empty()).then(|| pairs.join("; "))
+    }
+
+    /// Replaces the cookie with the same name, domain and path; an expired
+    /// one deletes it. Returns whether the jar changed.
+    fn insert(&mut self, cookie: Cookie, now: i64) -> bool {
+        let before = self.cookies.clone();
+        let live = cookie.expires.is_none_or(|expires| expires > now);
+        let existing = self.cookies.iter().position(|stored| {
+            stored.name == cookie.name
+                && stored.domain == cookie.domain
+                && stored.path == cookie.path
+        });
+        match existing {
+            Some(i) if live => self.cookies[i] = cookie,
+            Some(i) => {
+                self.cookies.remove(i);
+            }
+            None if live => self.cookies.push(cookie),
+            None => {}
+        }
+        self.cookies
+            .retain(|stored| stored.expires.is_none_or(|expires| expires > now));
+        self.cookies != before
+    }
+}
+
+impl Cookie {
+    fn matches_domain(&self, host: &str) -> bool {
+        host == self.domain
+            || (!self.host_only
+                && host
+                    .strip_suffix(&self.domain)
+                    .is_some_and(|prefix| prefix.ends_with('.')))
+    }
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || request_path
+            .strip_prefix(cookie_path)
+            .is_some_and(|rest| cookie_path.ends_with('/') || rest.starts_with('/'))
+}
+
+/// Parses a `Set-Cookie` header received from `url` (RFC 6265, without
+/// the public suffix list: a `Domain` must be the host or a parent of it).
+fn parse(set_cookie: &str, url: &Url, now: i64) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut parts = set_cookie.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.trim().trim_matches('"').to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url.path()),
+        secure: false,
+        expires: None,
+    };
+    let mut max_age = None;
+    for attribute in parts {
+        let (key, value) = match attribute.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => (attribute.trim(), ""),
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                let parent = host
+                    .strip_suffix(&domain)
+                    .is_some_and(|prefix| prefix.ends_with('.'));
+                if domain != host && !parent {
+                    return None;
+                }
+                cookie.domain = domain;
+                cookie.host_only = false;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "max-age" => max_age = value.parse::<i64>().ok(),
+            "expires" => {
+                if let Ok(date) = DateTime::parse_from_rfc2822(&value.replace('-', " ")) {
+                    cookie.expires = Some(date.timestamp());
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(max_age) = max_age {
+        cookie.expires = Some(now.saturating_add(max_age));
+    }
+    Some(cookie)
+}
+
+/// The directory of the request path, the default scope of a cookie.
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn jar(set_cookies: &[(&str, &str)]) -> Jar {
+        let mut jar = Jar::default();
+        for (from, set_cookie) in set_cookies {
+            if let Some(cookie) = parse(set_cookie, &url(from), NOW) {
+                jar.insert(cookie, NOW);
+            }
+        }
+        jar
+    }
+
+    #[test]
+    fn parses_attributes() {
+        let cookie = parse(
+            "backend=b2; Path=/api; Domain=.TexCompile.ru; Secure; HttpOnly; Max-Age=60",
+            &url("https://texcompile.ru/api/upload"),
+            NOW,
+        )
+        .unwrap();
+        assert_eq!(cookie.name, "backend");
+        assert_eq!(cookie.value, "b2");
+        assert_eq!(cookie.domain, "texcompile.ru");
+        assert!(!cookie.host_only);
+        assert_eq!(cookie.path, "/api");
+        assert!(cookie.secure);
+        assert_eq!(cookie.expires, Some(NOW + 60));
+
+        let cookie = parse(
+            "csrf=\"x y\"; Expires=Wed, 21-Oct-2065 07:28:00 GMT",
+            &url("https://texcompile.ru/api/status/t1"),
+            NOW,
+        )
+        .unwrap();
+        assert_eq!(cookie.value, "x y");
+        assert_eq!(cookie.path, "/api/status");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.expires, Some(3_023_335_680));
+    }
+
+    #[test]
+    fn rejects_foreign_domains() {
+        let from = url("https://texcompile.ru/api/upload");
+        assert!(parse("a=1; Domain=example.com", &from, NOW).is_none());
+        assert!(parse("a=1; Domain=evil-texcompile.ru", &from, NOW).is_none());
+        assert!(parse("=1", &from, NOW).is_none());
+    }
+
+    #[test]
+    fn sends_matching_cookies() {
+        let jar = jar(&[
+            ("https://texcompile.ru/api/upload", "backend=b2; Path=/"),
+            ("https://texcompile.ru/api/upload", "api=1; Path=/api"),
+            ("https://texcompile.ru/", "wide=1; Domain=texcompile.ru"),
+            ("https://texcompile.ru/", "tls=1; Secure"),
+        ]);
+        assert_eq!(
+            jar.header(&url("https://texcompile.ru/api/status/t1"), NOW)
+                .as_deref(),
+            Some("backend=b2; api=1; wide=1; tls=1")
+        );
+        assert_eq!(
+            jar.header(&url("http://texcompile.ru/apiary"), NOW)
+                .as_deref(),
+            Some("backend=b2; wide=1")
+        );
+        assert_eq!(
+            jar.header(&url("https://cdn.texcompile.ru/f.pdf"), NOW)
+                .as_deref(),
+            Some("wide=1")
+        );
+        assert_eq!(jar.header(&url("https://example.com/"), NOW), None);
+    }
+
+    #[test]
+    fn replaces_and_expires_cookies() {
+        let from = "https://texcompile.ru/api/upload";
+        let jar = jar(&[
+            (from, "backend=b1; Path=/"),
+            (from, "backend=b2; Path=/"),
+            (from, "short=1; Path=/; Max-Age=10"),
+            (from, "gone=1; Path=/"),
+            (from, "gone=; Path=/; Max-Age=0"),
+        ]);
+        let to = url("https://texcompile.ru/api/status/t1");
+        assert_eq!(jar.header(&to, NOW).as_deref(), Some("backend=b2; short=1"));
+        assert_eq!(jar.header(&to, NOW + 10).as_deref(), Some("backend=b2"));
+    }
+}
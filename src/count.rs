@@ -0,0 +1,812 @@
+use crate::batch;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::deps::{self, DependencyGraph};
+use crate::history;
+use crate::report::DocumentStatus;
+use crate::stats::print_table;
+use crate::storage;
+use anyhow::{Context, Result};
+use chrono::{Local, TimeZone};
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+Usage: chemtex count <main.tex|project_dir> [options]
+
+Counts words, equations and floats per section over the include graph, the
+way texcount does, and estimates the page count of the PDF, e.g.
+  chemtex count thesis.tex --max-pages 80
+
+Text in headings and captions is counted apart from the body; math, \\ce
+formulas and quantities count as formulas, not words. The estimate is
+calibrated on the last build whose PDF is newer than every source (once
+seen, a document keeps its calibration), and is a rough guess before that.
+
+Options:
+  --max-words N   Fail if the body has more than N words
+  --max-pages N   Fail if the estimate is above N pages";
+
+const DENSITY_FILE: &str = "page-density.json";
+
+/// Words on a page of an 11pt article, for documents never built.
+const DEFAULT_WORDS_PER_PAGE: f64 = 450.0;
+/// Space a displayed equation and a float take up, in words.
+const EQUATION_WORDS: u64 = 40;
+const FLOAT_WORDS: u64 = 250;
+
+const SECTIONS: &[&str] = &[
+    "part",
+    "chapter",
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+];
+
+/// Environments counted as one displayed equation each; their contents are
+/// not words.
+const DISPLAY_MATH: &[&str] = &[
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "alignat",
+    "alignat*",
+    "gather",
+    "gather*",
+    "multline",
+    "multline*",
+    "flalign",
+    "flalign*",
+    "eqnarray",
+    "eqnarray*",
+    "displaymath",
+    "math",
+    "reaction",
+    "reaction*",
+    "reactions",
+    "reactions*",
+];
+
+const FLOATS: &[&str] = &[
+    "figure",
+    "figure*",
+    "table",
+    "table*",
+    "scheme",
+    "wrapfigure",
+    "wraptable",
+];
+
+/// Environments whose contents are skipped entirely.
+const SKIPPED: &[&str] = &[
+    "verbatim",
+    "verbatim*",
+    "Verbatim",
+    "lstlisting",
+    "minted",
+    "comment",
+    "tikzpicture",
+    "thebibliography",
+];
+
+/// Commands whose first N arguments are not text.
+const IGNORED_ARGUMENTS: &[(&str, usize)] = &[
+    ("addbibresource", 1),
+    ("autocite", 1),
+    ("autoref", 1),
+    ("bibliography", 1),
+    ("bibliographystyle", 1),
+    ("chemfig", 1),
+    ("cite", 1),
+    ("citep", 1),
+    ("citet", 1),
+    ("cref", 1),
+    ("Cref", 1),
+    ("documentclass", 1),
+    ("end", 1),
+    ("eqref", 1),
+    ("href", 1),
+    ("hspace", 1),
+    ("include", 1),
+    ("includegraphics", 1),
+    ("input", 1),
+    ("label", 1),
+    ("newcommand", 2),
+    ("pageref", 1),
+    ("parencite", 1),
+    ("ref", 1),
+    ("renewcommand", 2),
+    ("setcounter", 2),
+    ("setlength", 2),
+    ("textcite", 1),
+    ("url", 1),
+    ("usepackage", 1),
+    ("vspace", 1),
+];
+
+/// Commands typeset as one formula: their arguments are not words.
+const FORMULAS: &[(&str, usize)] = &[
+    ("ce", 1),
+    ("ch", 1),
+    ("num", 1),
+    ("SI", 2),
+    ("si", 1),
+    ("qty", 2),
+    ("unit", 1),
+];
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &["max-words", "max-pages"])?;
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+    let (main, output) = match input.is_dir() {
+        true => {
+            let config = ProjectConfig::find(&input)?.unwrap_or_default();
+            let main = match &config.main {
+                Some(main) => input.join(main),
+                None => batch::find_main_document(&input)?,
+            };
+            (main, config.output.map(|output| input.join(output)))
+        }
+        false => (input, None),
+    };
+
+    let lines = deps::read_lines(&main)?;
+    let text: String = lines
+        .iter()
+        .map(|line| format!("{}\n", deps::strip_comment(&line.text)))
+        .collect();
+    let body = match text.find("\\begin{document}") {
+        Some(start) => &text[start + "\\begin{document}".len()..],
+        None => text.as_str(),
+    };
+    let sections = count(body);
+    let mut total = Counts::default();
+    for section in &sections {
+        total.add(&section.counts);
+    }
+
+    let rows: Vec<[String; 6]> = sections
+        .iter()
+        .map(|section| {
+            let title = match &section.number {
+                Some(number) => format!("{} {}", number, section.title),
+                None => section.title.clone(),
+            };
+            section
+                .counts
+                .row(format!("{}{}", "  ".repeat(section.depth), title))
+        })
+        .chain(std::iter::once(total.row("Total".to_string())))
+        .collect();
+    print_table(
+        &[
+            "Section",
+            "Words",
+            "Headings",
+            "Captions",
+            "Equations",
+            "Floats",
+        ],
+        &rows,
+    );
+    println!(
+        "{} inline formula(s); {} file(s) counted",
+        total.inline,
+        lines
+            .iter()
+            .map(|line| &line.file)
+            .collect::<HashSet<_>>()
+            .len()
+    );
+
+    let estimate = estimate(&main, output.as_deref(), &total)?;
+    println!("{}", estimate.describe());
+
+    let mut exceeded = Vec::new();
+    if let Some(limit) = args.parsed::<u64>("max-words")? {
+        if total.words > limit {
+            exceeded.push(format!("{} words, the limit is {}", total.words, limit));
+        }
+    }
+    if let Some(limit) = args.parsed::<f64>("max-pages")? {
+        if estimate.pages > limit {
+            exceeded.push(format!(
+                "about {:.0} pages, the limit is {}",
+                estimate.pages, limit
+            ));
+        }
+    }
+    if !exceeded.is_empty() {
+        anyhow::bail!("{} is too long: {}", main.display(), exceeded.join("; "));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Counts {
+    /// Words of running text.
+    pub words: u64,
+    pub headings: u64,
+    pub captions: u64,
+    /// Inline math, `\ce` formulas and quantities.
+    pub inline: u64,
+    /// Displayed equations and reactions.
+    pub equations: u64,
+    pub floats: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.words += other.words;
+        self.headings += other.headings;
+        self.captions += other.captions;
+        self.inline += other.inline;
+        self.equations += other.equations;
+        self.floats += other.floats;
+    }
+
+    /// The space the counted material takes up, in words.
+    fn weight(&self) -> u64 {
+        self.words
+            + self.headings
+            + self.captions
+            + self.inline
+            + self.equations * EQUATION_WORDS
+            + self.floats * FLOAT_WORDS
+    }
+
+    fn row(&self, label: String) -> [String; 6] {
+        [
+            label,
+            self.words.to_string(),
+            self.headings.to_string(),
+            self.captions.to_string(),
+            self.equations.to_string(),
+            self.floats.to_string(),
+        ]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub title: String,
+    pub depth: usize,
+    /// `1.2`, or `None` for starred headings and text before the first one.
+    pub number: Option<String>,
+    pub counts: Counts,
+}
+
+/// Counts the body of a document section by section.
+pub fn count(body: &str) -> Vec<Section> {
+    let mut counter = Counter::new(body);
+    counter.sections.push(Section {
+        title: "(before the first heading)".to_string(),
+        depth: 0,
+        number: None,
+        counts: Counts::default(),
+    });
+    counter.run();
+    let mut sections = counter.sections;
+    if sections.len() > 1 && sections[0].counts.weight() == 0 {
+        sections.remove(0);
+    }
+    // Indent relative to the outermost heading used.
+    let top = sections.iter().skip(1).map(|s| s.depth).min().unwrap_or(0);
+    for section in sections.iter_mut() {
+        section.depth = section.depth.saturating_sub(top);
+    }
+    sections
+}
+
+/// A word of `text`, at a character offset into it.
+#[derive(Debug, Clone)]
+pub struct Word {
+    pub offset: usize,
+    pub text: String,
+}
+
+/// The words of running text, headings and captions in `body`, in order,
+/// skipping math, formulas, commands and arguments that are not text.
+pub fn words(body: &str) -> Vec<Word> {
+    let mut counter = Counter::new(body);
+    counter.sections.push(Section {
+        title: String::new(),
+        depth: 0,
+        number: None,
+        counts: Counts::default(),
+    });
+    counter.run();
+    counter.words
+}
+
+/// What running text is counted as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Text,
+    Heading,
+    Caption,
+}
+
+struct Counter {
+    chars: Vec<char>,
+    pos: usize,
+    mode: Mode,
+    sections: Vec<Section>,
+    numbers: Vec<usize>,
+    /// Offset of `chars` in the text counting started on.
+    base: usize,
+    words: Vec<Word>,
+}
+
+impl Counter {
+    fn new(text: &str) -> Self {
+        Self {
+            chars: text.chars().collect(),
+            pos: 0,
+            mode: Mode::Text,
+            sections: Vec::new(),
+            numbers: vec![0; SECTIONS.len()],
+            base: 0,
+            words: Vec::new(),
+        }
+    }
+
+    fn counts(&mut self) -> &mut Counts {
+        &mut self
+            .sections
+            .last_mut()
+            .expect("counting starts with a section")
+            .counts
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, pattern: &str) -> bool {
+        pattern
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn run(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.command();
+            } else if self.starts_with("$$") {
+                self.pos += 2;
+                self.skip_past("$$");
+                self.counts().equations += 1;
+            } else if c == '$' {
+                self.pos += 1;
+                self.skip_past("$");
+                self.counts().inline += 1;
+            } else if c.is_alphanumeric() {
+                self.word();
+            } else {
+                self.pos += 1;
+            }
+        }
+    }
+
+    fn word(&mut self) {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            let joins = matches!(c, '-' | '\'' | '’')
+                && self
+                    .chars
+                    .get(self.pos + 1)
+                    .is_some_and(|next| next.is_alphanumeric());
+            if !c.is_alphanumeric() && !joins {
+                break;
+            }
+            self.pos += 1;
+        }
+        self.words.push(Word {
+            offset: self.base + start,
+            text: self.chars[start..self.pos].iter().collect(),
+        });
+        let mode = self.mode;
+        let counts = self.counts();
+        match mode {
+            Mode::Text => counts.words += 1,
+            Mode::Heading => counts.headings += 1,
+            Mode::Caption => counts.captions += 1,
+        }
+    }
+
+    fn command(&mut self) {
+        self.pos += 1;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            // \( \) \[ \] or an escaped character.
+            match self.peek() {
+                Some('(') => {
+                    self.skip_past("\\)");
+                    self.counts().inline += 1;
+                }
+                Some('[') => {
+                    self.skip_past("\\]");
+                    self.counts().equations += 1;
+                }
+                Some(_) => self.pos += 1,
+                None => {}
+            }
+            return;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        let starred = self.peek() == Some('*');
+        if starred {
+            self.pos += 1;
+        }
+
+        if let Some(depth) = SECTIONS.iter().position(|s| *s == name) {
+            self.optional();
+            let title = self.group().unwrap_or(self.pos..self.pos);
+            self.heading(depth, title, starred);
+        } else if name == "begin" {
+            let environment = self.group().map(|range| self.text(range));
+            self.environment(&environment.unwrap_or_default());
+        } else if name == "caption" {
+            self.optional();
+            if let Some(caption) = self.group() {
+                self.nested(caption, Mode::Caption);
+            }
+        } else if let Some((_, skipped)) = IGNORED_ARGUMENTS.iter().find(|(c, _)| *c == name) {
+            self.optional();
+            for _ in 0..*skipped {
+                self.group();
+            }
+        } else if let Some((_, skipped)) = FORMULAS.iter().find(|(c, _)| *c == name) {
+            self.optional();
+            for _ in 0..*skipped {
+                self.group();
+            }
+            self.counts().inline += 1;
+        }
+        // Other commands: the name is not a word, their arguments are text.
+    }
+
+    fn heading(&mut self, depth: usize, title: Range<usize>, starred: bool) {
+        let number = match (starred, SECTIONS[depth]) {
+            (true, _) | (false, "paragraph") => None,
+            (false, "part") => {
+                self.numbers[0] += 1;
+                Some(self.numbers[0].to_string())
+            }
+            (false, _) => {
+                self.numbers[depth] += 1;
+                for deeper in &mut self.numbers[depth + 1..] {
+                    *deeper = 0;
+                }
+                // Numbers start at the outermost level in use; parts are
+                // numbered apart.
+                let first = self.numbers[1..=depth]
+                    .iter()
+                    .position(|&n| n > 0)
+                    .map_or(depth, |i| i + 1);
+                let parts: Vec<String> = self.numbers[first..=depth]
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect();
+                Some(parts.join("."))
+            }
+        };
+        self.sections.push(Section {
+            title: plain(&self.text(title.clone())),
+            depth,
+            number,
+            counts: Counts::default(),
+        });
+        self.nested(title, Mode::Heading);
+    }
+
+    fn environment(&mut self, environment: &str) {
+        let end = format!("\\end{{{}}}", environment);
+        if DISPLAY_MATH.contains(&environment) {
+            self.skip_past(&end);
+            self.counts().equations += 1;
+        } else if SKIPPED.contains(&environment) {
+            self.skip_past(&end);
+        } else if FLOATS.contains(&environment) {
+            self.counts().floats += 1;
+        }
+    }
+
+    /// Counts the characters in `range` in `mode` into the current section.
+    fn nested(&mut self, range: Range<usize>, mode: Mode) {
+        let mut nested = Counter::new(&self.text(range.clone()));
+        nested.mode = mode;
+        nested.base = self.base + range.start;
+        nested.sections.push(Section {
+            title: String::new(),
+            depth: 0,
+            number: None,
+            counts: Counts::default(),
+        });
+        nested.run();
+        for section in &nested.sections {
+            let counts = section.counts.clone();
+            self.counts().add(&counts);
+        }
+        self.words.extend(nested.words);
+    }
+
+    fn text(&self, range: Range<usize>) -> String {
+        self.chars[range].iter().collect()
+    }
+
+    fn skip_past(&mut self, pattern: &str) {
+        while self.pos < self.chars.len() {
+            if self.starts_with(pattern) {
+                self.pos += pattern.chars().count();
+                return;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn optional(&mut self) {
+        self.skip_whitespace();
+        if self.peek() == Some('[') {
+            self.balanced('[', ']');
+        }
+    }
+
+    /// Where the contents of a `{...}` group are, if one follows.
+    fn group(&mut self) -> Option<Range<usize>> {
+        self.skip_whitespace();
+        if self.peek() != Some('{') {
+            return None;
+        }
+        let start = self.pos + 1;
+        self.balanced('{', '}');
+        let end = self.pos.saturating_sub(1).max(start);
+        Some(start..end)
+    }
+
+    /// Skips a group from `open` to its matching `close`.
+    fn balanced(&mut self, open: char, close: char) {
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '\\' {
+                self.pos += 1;
+            } else if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// A heading's title without commands and braces.
+fn plain(title: &str) -> String {
+    let mut result = String::new();
+    let mut chars = title.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                while chars.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+                    chars.next();
+                }
+            }
+            '{' | '}' | '$' => {}
+            '~' => result.push(' '),
+            c => result.push(c),
+        }
+    }
+    result.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Words of weight per page of a document, measured on a build of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Calibration {
+    weight: u64,
+    pages: u32,
+    built_at: u64,
+}
+
+struct Estimate {
+    pages: f64,
+    calibration: Option<Calibration>,
+    /// The calibrating build is of the current sources.
+    current: bool,
+}
+
+impl Estimate {
+    fn describe(&self) -> String {
+        match (&self.calibration, self.current) {
+            (Some(calibration), true) => format!(
+                "Pages: {} (the last build, {}, is up to date)",
+                calibration.pages,
+                format_time(calibration.built_at)
+            ),
+            (Some(calibration), false) => format!(
+                "Estimated pages: about {:.0} (calibrated on the build of {}: {} pages)",
+                self.pages,
+                format_time(calibration.built_at),
+                calibration.pages
+            ),
+            (None, _) => format!(
+                "Estimated pages: about {:.0} (no build to calibrate on, assuming {} words a page)",
+                self.pages, DEFAULT_WORDS_PER_PAGE
+            ),
+        }
+    }
+}
+
+/// Estimates the pages of `main`'s PDF from the calibration of its last
+/// up-to-date build, recording a new one when its PDF is newer than the
+/// sources.
+fn estimate(main: &Path, output: Option<&Path>, counts: &Counts) -> Result<Estimate> {
+    let path = storage::data_dir()?.join(DENSITY_FILE);
+    let mut calibrations: BTreeMap<String, Calibration> = match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse {}", path.display()))?,
+        Err(_) => BTreeMap::new(),
+    };
+    let key = fs::canonicalize(main)
+        .unwrap_or_else(|_| main.to_path_buf())
+        .to_string_lossy()
+        .into_owned();
+    let weight = counts.weight();
+
+    let sources_changed = DependencyGraph::scan(main)?
+        .files
+        .iter()
+        .filter_map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .max();
+    let current = last_build(main, output)?.and_then(|(pdf, built)| {
+        if sources_changed.is_some_and(|changed| changed > built) {
+            return None;
+        }
+        let pages = page_count(&fs::read(pdf).ok()?)?;
+        Some(Calibration {
+            weight,
+            pages,
+            built_at: built
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        })
+    });
+    if let Some(calibration) = current {
+        calibrations.insert(key, calibration.clone());
+        storage::ensure_dir(path.parent().unwrap_or(Path::new("")))?;
+        fs::write(&path, serde_json::to_string_pretty(&calibrations)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        return Ok(Estimate {
+            pages: calibration.pages as f64,
+            calibration: Some(calibration),
+            current: true,
+        });
+    }
+    Ok(match calibrations.remove(&key) {
+        Some(calibration) if calibration.weight > 0 => Estimate {
+            pages: calibration.pages as f64 * weight as f64 / calibration.weight as f64,
+            calibration: Some(calibration),
+            current: false,
+        },
+        _ => Estimate {
+            pages: (weight as f64 / DEFAULT_WORDS_PER_PAGE).max(1.0),
+            calibration: None,
+            current: false,
+        },
+    })
+}
+
+/// The newest PDF built from `main`: the configured output, the PDF next to
+/// it, or one recorded in the job history under the same name.
+fn last_build(main: &Path, output: Option<&Path>) -> Result<Option<(PathBuf, SystemTime)>> {
+    let pdf = main.with_extension("pdf");
+    let mut candidates: Vec<PathBuf> = output.into_iter().map(Path::to_path_buf).collect();
+    candidates.push(pdf.clone());
+    for entry in history::load()? {
+        let recorded = PathBuf::from(&entry.document.output);
+        if entry.document.status == DocumentStatus::Ok && recorded.file_name() == pdf.file_name() {
+            candidates.push(recorded);
+        }
+    }
+    Ok(candidates
+        .into_iter()
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .max_by_key(|(_, modified)| *modified))
+}
+
+/// The `/Count` of a PDF's page tree, looking into compressed object
+/// streams when the page tree is not in plain sight.
+pub fn page_count(pdf: &[u8]) -> Option<u32> {
+    if let Some(count) = page_tree_count(pdf) {
+        return Some(count);
+    }
+    let mut count = None;
+    let mut rest = pdf;
+    while let Some(start) = find(rest, b"stream") {
+        let after = &rest[start + b"stream".len()..];
+        let data = after
+            .strip_prefix(b"\r\n")
+            .or_else(|| after.strip_prefix(b"\n"))
+            .unwrap_or(after);
+        let Some(end) = find(data, b"endstream") else {
+            break;
+        };
+        let mut inflated = Vec::new();
+        if ZlibDecoder::new(&data[..end])
+            .read_to_end(&mut inflated)
+            .is_ok()
+        {
+            count = count.max(page_tree_count(&inflated));
+        }
+        rest = &data[end + b"endstream".len()..];
+    }
+    count
+}
+
+/// The largest `/Count` of a `/Type /Pages` dictionary in `data`: the root
+/// of the page tree.
+fn page_tree_count(data: &[u8]) -> Option<u32> {
+    let mut count = None;
+    let mut offset = 0;
+    while let Some(pos) = find(&data[offset..], b"/Count") {
+        let at = offset + pos;
+        offset = at + b"/Count".len();
+        let open = data[..at].windows(2).rposition(|w| w == b"<<").unwrap_or(0);
+        let close = find(&data[at..], b">>").map_or(data.len(), |end| at + end);
+        let dictionary: Vec<u8> = data[open..close]
+            .iter()
+            .copied()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        if find(&dictionary, b"/Type/Pages").is_none() {
+            continue;
+        }
+        let digits: String = data[offset..]
+            .iter()
+            .skip_while(|b| b.is_ascii_whitespace())
+            .take_while(|b| b.is_ascii_digit())
+            .map(|&b| b as char)
+            .collect();
+        if let Ok(n) = digits.parse::<u32>() {
+            count = count.max(Some(n));
+        }
+    }
+    count
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn format_time(secs: u64) -> String {
+    Local
+        .timestamp_opt(secs as i64, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Duration, Local, Timelike};
+
+/// How far ahead [`CronSchedule::next_after`] searches before giving up on
+/// expressions that never match (`0 0 31 2 *`).
+const SEARCH_LIMIT_MINUTES: i64 = 366 * 24 * 60 * 4;
+
+/// A classic five-field cron expression (`minute hour day-of-month month
+/// day-of-week`) with `*`, lists, ranges, `/step` and `MON`/`JAN` names,
+/// evaluated in local time.
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>,
+    months: Vec<bool>,
+    days_of_week: Vec<bool>,
+    /// Cron matches either day field when both are restricted.
+    day_of_month_restricted: bool,
+    day_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields.as_slice() else {
+            anyhow::bail!(
+                "Cron expression needs 5 fields (minute hour day month weekday): {}",
+                expression
+            );
+        };
+        let parse = |field: &str, name: &str, min, max, names: &[&str]| {
+            parse_field(field, min, max, names)
+                .with_context(|| format!("Invalid {} field in {:?}", name, expression))
+        };
+
+        let mut days_of_week = parse(day_of_week, "weekday", 0, 7, &WEEKDAYS)?;
+        // Both 0 and 7 mean Sunday.
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: parse(minute, "minute", 0, 59, &[])?,
+            hours: parse(hour, "hour", 0, 23, &[])?,
+            days_of_month: parse(day_of_month, "day", 1, 31, &[])?,
+            months: parse(month, "month", 1, 12, &MONTHS)?,
+            days_of_week,
+            day_of_month_restricted: *day_of_month != "*",
+            day_of_week_restricted: *day_of_week != "*",
+        })
+    }
+
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+
+    /// The first matching minute strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut candidate = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        for _ in 0..SEARCH_LIMIT_MINUTES {
+            if self.matches(&candidate) {
+                return Some(candidate);
+            }
+            candidate += Duration::minutes(1);
+        }
+        None
+    }
+
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        let day_of_month = self.days_of_month[time.day() as usize];
+        let day_of_week = self.days_of_week[time.weekday().num_days_from_sunday() as usize];
+        let day = match (self.day_of_month_restricted, self.day_of_week_restricted) {
+            (true, true) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.months[time.month() as usize]
+            && day
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+/// Returns a table indexed by value (`0..=max`) of the values the field
+/// selects.
+fn parse_field(field: &str, min: usize, max: usize, names: &[&str]) -> Result<Vec<bool>> {
+    let mut selected = vec![false; max + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().context("Invalid step")?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            anyhow::bail!("Step must be positive");
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start, min, names)?, value(end, min, names)?)
+        } else {
+            let start = value(range, min, names)?;
+            // `5/15` means every 15 starting at 5.
+            (start, if part.contains('/') { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            anyhow::bail!("{} is outside {}-{}", range, min, max);
+        }
+        for v in (start..=end).step_by(step) {
+            selected[v] = true;
+        }
+    }
+    Ok(selected)
+}
+
+/// A number or a name; names are numbered from `min` (`JAN` = 1, `SUN` = 0).
+fn value(text: &str, min: usize, names: &[&str]) -> Result<usize> {
+    if let Ok(number) = text.parse() {
+        return Ok(number);
+    }
+    names
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(text))
+        .map(|index| index + min)
+        .with_context(|| format!("Unknown value {:?}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn selected(field: &str, min: usize, max: usize, names: &[&str]) -> Vec<usize> {
+        let table = parse_field(field, min, max, names).unwrap();
+        (0..=max).filter(|&v| table[v]).collect()
+    }
+
+    #[test]
+    fn fields_take_ranges_lists_and_steps() {
+        assert_eq!(selected("*/15", 0, 59, &[]), [0, 15, 30, 45]);
+        assert_eq!(selected("5/20", 0, 59, &[]), [5, 25, 45]);
+        assert_eq!(selected("10-20/5", 0, 59, &[]), [10, 15, 20]);
+        assert_eq!(selected("1,3,9-11", 0, 23, &[]), [1, 3, 9, 10, 11]);
+        assert_eq!(selected("MON-FRI", 0, 7, &WEEKDAYS), [1, 2, 3, 4, 5]);
+        assert_eq!(selected("jan,Mar", 1, 12, &MONTHS), [1, 3]);
+        for invalid in ["60", "5-1", "*/0", "1-", "MON"] {
+            assert!(parse_field(invalid, 0, 59, &[]).is_err(), "{}", invalid);
+        }
+        assert!(CronSchedule::parse("0 0 * *").is_err());
+        assert!(CronSchedule::parse("0 0 0 * *").is_err());
+    }
+
+    #[test]
+    fn next_runs_follow_the_fields() {
+        // 2026-06-13 is a Saturday.
+        let weekdays = CronSchedule::parse("30 9 * * MON-FRI").unwrap();
+        assert_eq!(
+            weekdays.next_after(at(2026, 6, 13, 10, 0)),
+            Some(at(2026, 6, 15, 9, 30))
+        );
+        let quarters = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            quarters.next_after(at(2026, 6, 13, 10, 15)),
+            Some(at(2026, 6, 13, 10, 30))
+        );
+        // 7 is Sunday as well as 0.
+        let sundays = CronSchedule::parse("0 8 * * 7").unwrap();
+        assert_eq!(
+            sundays.next_after(at(2026, 6, 13, 10, 0)),
+            Some(at(2026, 6, 14, 8, 0))
+        );
+        assert_eq!(
+            CronSchedule::parse("0 0 31 2 *")
+                .unwrap()
+                .next_after(at(2026, 6, 13, 10, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either_day() {
+        // Both restricted: the 13th, or any Friday.
+        let either = CronSchedule::parse("0 12 13 * FRI").unwrap();
+        assert_eq!(
+            either.next_after(at(2026, 6, 13, 13, 0)),
+            Some(at(2026, 6, 19, 12, 0))
+        );
+        assert_eq!(
+            either.next_after(at(2026, 7, 10, 13, 0)),
+            Some(at(2026, 7, 13, 12, 0))
+        );
+        // Only the day of the month restricted: the 13th alone.
+        let thirteenth = CronSchedule::parse("0 12 13 * *").unwrap();
+        assert_eq!(
+            thirteenth.next_after(at(2026, 6, 13, 13, 0)),
+            Some(at(2026, 7, 13, 12, 0))
+        );
+        // Only the day of the week restricted: Fridays alone.
+        let fridays = CronSchedule::parse("0 12 * * FRI").unwrap();
+        assert_eq!(
+            fridays.next_after(at(2026, 7, 10, 13, 0)),
+            Some(at(2026, 7, 17, 12, 0))
+        );
+    }
+}
@@ -0,0 +1,668 @@
+use crate::api::{self, CompileOptions};
+use crate::batch;
+use crate::callbacks;
+use crate::cli::Args;
+use crate::cron::CronSchedule;
+use crate::diagnostics::Diagnostic;
+use crate::hooks::Hooks;
+use crate::job::{Job, JobReport, Runner};
+use crate::lsp;
+use crate::metrics::Metrics;
+use crate::redact;
+use crate::window::{self, SubmitWindow};
+use anyhow::{Context, Result};
+use chrono::Local;
+use hyper::body::HttpBody;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:7878";
+const DEFAULT_JOBS: usize = 2;
+/// Largest request body accepted, in bytes.
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+/// The bearer token clients must send, required to listen beyond loopback.
+const TOKEN_ENV: &str = "CHEMTEX_DAEMON_TOKEN";
+
+const USAGE: &str = "\
+Usage: chemtex daemon [options]
+
+Options:
+  --listen ADDR    Address to serve the local API on (default 127.0.0.1:7878)
+  --jobs N         Compile up to N documents concurrently (default 2)
+  --root DIR       Directory /compile requests may read and write in
+                   (default: the working directory)
+  --config FILE    Daemon configuration (YAML), e.g. compile schedules
+  --no-cache       Always submit, ignoring the build cache
+  --submit-window HH:MM-HH:MM
+                   Hold jobs that are not urgent, and scheduled ones, until
+                   off-peak hours (the window's quietest by the history)
+  --callback-url URL
+                   Ask the server to call URL, this daemon's /callbacks as
+                   the server reaches it, when a task finishes, and finish
+                   the job at once instead of at its next status check
+  --callback-listen ADDR
+                   Also take callbacks, and nothing else, on ADDR: the one
+                   to expose to the server, keeping the API private
+  --callback-grace SECONDS
+                   Poll tasks whose callback has not come after this long
+                   (default 30)
+
+Endpoints:
+  POST /compile        {\"input\": \"main.tex\", \"output\": \"main.pdf\", \"engine\": ..., \"profile\": ...,
+                        \"urgent\": true to skip the submit window}
+  GET  /jobs           List all jobs
+  GET  /jobs/:id       Show one job
+  GET  /jobs/:id/pdf   Download the compiled PDF
+  GET  /jobs/:id/diagnostics
+                       Diagnostics as textDocument/publishDiagnostics params
+  GET  /metrics        Prometheus metrics
+  POST /callbacks      {\"task_id\": ...}, from the server (see --callback-url)
+
+Paths in /compile requests are relative to --root and cannot leave it. When
+CHEMTEX_DAEMON_TOKEN is set, every request needs the header
+`Authorization: Bearer <token>`; listening on an address other than
+loopback needs it set.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum JobState {
+    /// Held until the submit window.
+    Deferred,
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DaemonJob {
+    id: u64,
+    /// The [`Job::id`] its remote task goes by.
+    #[serde(skip)]
+    job_id: u64,
+    input: PathBuf,
+    output: PathBuf,
+    options: JobOptions,
+    state: JobState,
+    task_id: Option<String>,
+    submitted_at: u64,
+    finished_at: Option<u64>,
+    elapsed_ms: Option<u64>,
+    error: Option<String>,
+    diagnostics: Vec<Diagnostic>,
+    /// Cron expression of the schedule that started this job.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<String>,
+    /// Unix time a deferred job is submitted at.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deferred_until: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct JobOptions {
+    engine: Option<String>,
+    profile: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompileRequest {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    #[serde(flatten)]
+    options: JobOptions,
+    /// Submitted at once, even outside the submit window.
+    #[serde(default)]
+    urgent: bool,
+}
+
+/// `--config` file of the daemon.
+///
+/// ```yaml
+/// schedules:
+///   - cron: "0 7 * * MON"
+///     compile: weekly_summary/
+///     hooks:
+///       post_compile: ./publish.sh "$CHEMTEX_OUTPUT"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DaemonConfig {
+    #[serde(default)]
+    schedules: Vec<ScheduleEntry>,
+}
+
+/// Recompiles a document, or every document in a directory, on a cron
+/// schedule. Relative paths are resolved against the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleEntry {
+    cron: String,
+    compile: PathBuf,
+    #[serde(flatten)]
+    options: JobOptions,
+    #[serde(default)]
+    hooks: Hooks,
+}
+
+impl DaemonConfig {
+    fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config: {}", path.display()))?;
+        let mut config: Self = serde_yaml::from_str(&text)
+            .with_context(|| format!("Failed to parse config: {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        for entry in &mut config.schedules {
+            entry.compile = base.join(&entry.compile);
+            entry.hooks.dir = base.to_path_buf();
+        }
+        Ok(config)
+    }
+}
+
+/// State shared by all connections: the warm client, the job table and the
+/// concurrency limit.
+struct Daemon {
+    runner: Runner,
+    jobs: Mutex<Vec<DaemonJob>>,
+    slots: Semaphore,
+    metrics: Metrics,
+    submit_window: Option<SubmitWindow>,
+    /// Canonical; requests cannot reach outside it.
+    root: PathBuf,
+    token: Option<String>,
+}
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["no-cache", "help"],
+        &[
+            "listen",
+            "jobs",
+            "root",
+            "config",
+            "submit-window",
+            "callback-url",
+            "callback-listen",
+            "callback-grace",
+        ],
+    )?;
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let addr: SocketAddr = args
+        .value("listen")
+        .unwrap_or(DEFAULT_LISTEN)
+        .parse()
+        .context("Invalid --listen address")?;
+    let max_jobs = args.parsed::<usize>("jobs")?.unwrap_or(DEFAULT_JOBS).max(1);
+    let root = args.value("root").unwrap_or(".");
+    let root = fs::canonicalize(root).with_context(|| format!("Invalid --root: {}", root))?;
+    let token = std::env::var(TOKEN_ENV)
+        .ok()
+        .map(|token| token.trim().to_string())
+        .filter(|token| !token.is_empty());
+    match &token {
+        Some(token) => redact::secret(token),
+        None if !addr.ip().is_loopback() => anyhow::bail!(
+            "Listening on {} lets other machines compile and read files: set {} to the \
+             token clients must send",
+            addr,
+            TOKEN_ENV
+        ),
+        None => {}
+    }
+    let config = match args.value("config") {
+        Some(path) => DaemonConfig::load(Path::new(path))?,
+        None => DaemonConfig::default(),
+    };
+    let submit_window = args
+        .value("submit-window")
+        .map(SubmitWindow::parse)
+        .transpose()?;
+    if let Some(url) = args.value("callback-url") {
+        url::Url::parse(url).with_context(|| format!("Invalid --callback-url: {}", url))?;
+        let grace = args
+            .parsed::<u64>("callback-grace")?
+            .map_or(callbacks::DEFAULT_GRACE, Duration::from_secs);
+        callbacks::enable(url, grace);
+    }
+
+    let mut runner = Runner::new(api::build_client()?, !args.flag("no-cache"))?;
+    runner.collect_diagnostics = true;
+    let daemon = Arc::new(Daemon {
+        runner,
+        jobs: Mutex::new(Vec::new()),
+        slots: Semaphore::new(max_jobs),
+        metrics: Metrics::default(),
+        submit_window,
+        root,
+        token,
+    });
+
+    for entry in config.schedules {
+        let schedule = CronSchedule::parse(&entry.cron)?;
+        println!(
+            "Scheduled {} at \"{}\"",
+            entry.compile.display(),
+            schedule.expression()
+        );
+        tokio::spawn(Arc::clone(&daemon).run_schedule(schedule, entry));
+    }
+
+    let make_service = make_service_fn(move |_| {
+        let daemon = Arc::clone(&daemon);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let daemon = Arc::clone(&daemon);
+                async move { Ok::<_, Infallible>(daemon.handle(request).await) }
+            }))
+        }
+    });
+
+    if let Some(listen) = args.value("callback-listen") {
+        anyhow::ensure!(
+            callbacks::url().is_some(),
+            "--callback-listen needs --callback-url"
+        );
+        let callback_addr: SocketAddr = listen
+            .parse()
+            .context("Invalid --callback-listen address")?;
+        let callback_server = Server::try_bind(&callback_addr)
+            .with_context(|| format!("Failed to listen on {}", callback_addr))?
+            .serve(make_service_fn(|_| async {
+                Ok::<_, Infallible>(service_fn(|request| async {
+                    Ok::<_, Infallible>(callbacks_only(request).await)
+                }))
+            }));
+        println!("Taking callbacks on http://{}/callbacks", callback_addr);
+        tokio::spawn(async move {
+            if let Err(e) = callback_server.await {
+                eprintln!("Callback server failed: {}", e);
+            }
+        });
+    }
+
+    let server = Server::try_bind(&addr)
+        .with_context(|| format!("Failed to listen on {}", addr))?
+        .serve(make_service);
+    println!("Daemon listening on http://{}", addr);
+    server.await.context("Daemon server failed")
+}
+
+impl Daemon {
+    async fn handle(self: Arc<Self>, request: Request<Body>) -> Response<Body> {
+        if !self.authorized(&request) {
+            return json_response(
+                StatusCode::UNAUTHORIZED,
+                &serde_json::json!({ "error": "Missing or wrong Authorization header" }),
+            );
+        }
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        let result = match (&method, segments.as_slice()) {
+            (&Method::POST, ["compile"]) => self.submit(request).await,
+            (&Method::POST, ["callbacks"]) => callback(request).await,
+            (&Method::GET, ["metrics"]) => Ok(Response::builder()
+                .header("Content-Type", "text/plain; version=0.0.4")
+                .body(Body::from(self.metrics.render()))
+                .unwrap_or_default()),
+            (&Method::GET, ["jobs"]) => Ok(json_response(StatusCode::OK, &self.snapshot())),
+            (&Method::GET, ["jobs", id]) => {
+                self.job(id).map(|job| json_response(StatusCode::OK, &job))
+            }
+            (&Method::GET, ["jobs", id, "pdf"]) => self.pdf(id),
+            (&Method::GET, ["jobs", id, "diagnostics"]) => self.job(id).map(|job| {
+                let payloads =
+                    lsp::publish_diagnostics(&job.input, &job.diagnostics, &HashSet::new());
+                json_response(StatusCode::OK, &payloads)
+            }),
+            _ => Err(ApiError::new(StatusCode::NOT_FOUND, "Not found")),
+        };
+
+        result
+            .unwrap_or_else(|e| json_response(e.status, &serde_json::json!({ "error": e.message })))
+    }
+
+    /// Whether `request` carries the token, when one is required.
+    fn authorized(&self, request: &Request<Body>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let sent = request
+            .headers()
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or("");
+        // Compared in full, so the time taken does not tell how much matched.
+        sent.len() == token.len()
+            && sent
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+
+    /// `path` of a request, against the root; `..`, absolute paths elsewhere
+    /// and symbolic links out of the root are refused.
+    fn confine(&self, path: &Path) -> Result<PathBuf, ApiError> {
+        let outside = || {
+            ApiError::new(
+                StatusCode::FORBIDDEN,
+                format!("{} is outside {}", path.display(), self.root.display()),
+            )
+        };
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(outside());
+        }
+        let resolved = self.root.join(path);
+        let mut existing = resolved.as_path();
+        while !existing.exists() {
+            existing = existing.parent().ok_or_else(outside)?;
+        }
+        match fs::canonicalize(existing) {
+            Ok(real) if real.starts_with(&self.root) => Ok(resolved),
+            _ => Err(outside()),
+        }
+    }
+
+    async fn submit(self: Arc<Self>, request: Request<Body>) -> Result<Response<Body>, ApiError> {
+        let body = read_body(request).await?;
+        let compile: CompileRequest = serde_json::from_slice(&body).map_err(|e| {
+            ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e))
+        })?;
+
+        let input = self.confine(&compile.input)?;
+        let mut job = Job::new(&input, Path::new(""))
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("{:#}", e)))?;
+        job.output = match compile.output {
+            Some(output) => self.confine(&output)?,
+            None => input.parent().unwrap_or(&self.root).join(&job.output),
+        };
+        let entry = self.enqueue(job, compile.options, None, compile.urgent);
+        Ok(json_response(StatusCode::ACCEPTED, &entry))
+    }
+
+    /// Adds a job to the table and starts it once a slot is free.
+    fn enqueue(
+        self: &Arc<Self>,
+        mut job: Job,
+        options: JobOptions,
+        schedule: Option<String>,
+        urgent: bool,
+    ) -> DaemonJob {
+        job.options = CompileOptions {
+            engine: options.engine.clone(),
+            profile: options.profile.clone(),
+            ..CompileOptions::default()
+        };
+
+        let entry = {
+            let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+            let entry = DaemonJob {
+                id: jobs.len() as u64 + 1,
+                job_id: job.id,
+                input: job.input.clone(),
+                output: job.output.clone(),
+                options,
+                state: JobState::Queued,
+                task_id: None,
+                submitted_at: unix_now(),
+                finished_at: None,
+                elapsed_ms: None,
+                error: None,
+                diagnostics: Vec::new(),
+                schedule,
+                deferred_until: None,
+            };
+            jobs.push(entry.clone());
+            entry
+        };
+        job.name = format!("job-{}", entry.id);
+
+        self.metrics.record_submission();
+        let daemon = Arc::clone(self);
+        let id = entry.id;
+        tokio::spawn(async move { daemon.execute(id, job, urgent).await });
+        entry
+    }
+
+    /// Enqueues the entry's documents every time its schedule fires.
+    async fn run_schedule(self: Arc<Self>, schedule: CronSchedule, entry: ScheduleEntry) {
+        loop {
+            let now = Local::now();
+            let Some(next) = schedule.next_after(now) else {
+                eprintln!("Schedule \"{}\" never fires", schedule.expression());
+                return;
+            };
+            let wait = (next - now).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+
+            let jobs = if entry.compile.is_dir() {
+                batch::jobs_from_dir(&entry.compile, &entry.compile)
+            } else {
+                let dir = entry.compile.parent().unwrap_or(Path::new(""));
+                Job::new(&entry.compile, dir).map(|job| vec![job])
+            };
+            match jobs {
+                Ok(jobs) => {
+                    for mut job in jobs {
+                        if !entry.hooks.is_empty() {
+                            job.hooks = entry.hooks.clone();
+                        }
+                        let queued = self.enqueue(
+                            job,
+                            entry.options.clone(),
+                            Some(schedule.expression().to_string()),
+                            false,
+                        );
+                        println!(
+                            "Schedule \"{}\" queued job {} for {}",
+                            schedule.expression(),
+                            queued.id,
+                            queued.input.display()
+                        );
+                    }
+                }
+                Err(e) => eprintln!(
+                    "Schedule \"{}\" could not start: {:#}",
+                    schedule.expression(),
+                    e
+                ),
+            }
+        }
+    }
+
+    async fn execute(&self, id: u64, job: Job, urgent: bool) {
+        if let (Some(submit_window), false) = (self.submit_window, urgent) {
+            let plan = submit_window.plan(Local::now());
+            if !plan.delay().is_zero() {
+                self.update(id, |entry| {
+                    entry.state = JobState::Deferred;
+                    entry.deferred_until = Some(plan.at.timestamp() as u64);
+                });
+                println!(
+                    "Job {} deferred to the submit window {}: submitting {}",
+                    id,
+                    submit_window,
+                    window::describe(&plan)
+                );
+                tokio::time::sleep(plan.delay()).await;
+                self.update(id, |entry| entry.state = JobState::Queued);
+            }
+        }
+        let queued = Instant::now();
+        let Ok(_permit) = self.slots.acquire().await else {
+            return;
+        };
+        self.metrics.record_slot_wait(queued.elapsed());
+        self.update(id, |entry| entry.state = JobState::Running);
+
+        let label = format!("[{}] ", job.name);
+        let report = self.runner.run(&job, &label).await;
+        self.metrics.record_report(&report);
+        self.update(id, |entry| finish(entry, &report));
+    }
+
+    fn update(&self, id: u64, change: impl FnOnce(&mut DaemonJob)) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = jobs.iter_mut().find(|entry| entry.id == id) {
+            change(entry);
+        }
+    }
+
+    fn snapshot(&self) -> Vec<DaemonJob> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.iter()
+            .map(|entry| self.with_live_task_id(entry))
+            .collect()
+    }
+
+    fn job(&self, id: &str) -> Result<DaemonJob, ApiError> {
+        let id: u64 = id
+            .parse()
+            .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "Invalid job id"))?;
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| self.with_live_task_id(entry))
+            .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "No such job"))
+    }
+
+    /// Fills in the remote task id of a running job from the runner.
+    fn with_live_task_id(&self, entry: &DaemonJob) -> DaemonJob {
+        let mut entry = entry.clone();
+        if entry.task_id.is_none() {
+            entry.task_id = self.runner.in_flight.get(entry.job_id);
+        }
+        entry
+    }
+
+    fn pdf(&self, id: &str) -> Result<Response<Body>, ApiError> {
+        let job = self.job(id)?;
+        if job.state != JobState::Succeeded {
+            return Err(ApiError::new(StatusCode::CONFLICT, "Job has not succeeded"));
+        }
+        let bytes = std::fs::read(&job.output)
+            .map_err(|e| ApiError::new(StatusCode::GONE, format!("PDF unavailable: {}", e)))?;
+        Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/pdf")
+            .body(Body::from(bytes))
+            .unwrap_or_default())
+    }
+}
+
+/// The `--callback-listen` listener: `POST /callbacks` and nothing else,
+/// so exposing it to the server does not expose the API. A forged callback
+/// only brings a status check forward (see [`callbacks`]).
+async fn callbacks_only(request: Request<Body>) -> Response<Body> {
+    let result = match (request.method(), request.uri().path().trim_matches('/')) {
+        (&Method::POST, "callbacks") => callback(request).await,
+        _ => Err(ApiError::new(StatusCode::NOT_FOUND, "Not found")),
+    };
+    result.unwrap_or_else(|e| json_response(e.status, &serde_json::json!({ "error": e.message })))
+}
+
+/// A completion callback: `{"task_id": ...}`, or the id under `data` as
+/// in the server's other responses.
+async fn callback(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let body = read_body(request).await?;
+    let value: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid callback: {}", e)))?;
+    let task_id = value
+        .get("task_id")
+        .or_else(|| value.pointer("/data/task_id"))
+        .and_then(|id| id.as_str())
+        .ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "No task_id in callback"))?;
+    if !callbacks::notify(task_id) {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("No job is waiting for task {}", task_id),
+        ));
+    }
+    Ok(json_response(
+        StatusCode::ACCEPTED,
+        &serde_json::json!({ "task_id": task_id }),
+    ))
+}
+
+/// The body of `request`, refused past [`MAX_REQUEST_BYTES`].
+async fn read_body(request: Request<Body>) -> Result<Vec<u8>, ApiError> {
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+        if bytes.len() + chunk.len() > MAX_REQUEST_BYTES {
+            return Err(ApiError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Request bodies are limited to {} bytes", MAX_REQUEST_BYTES),
+            ));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn finish(entry: &mut DaemonJob, report: &JobReport) {
+    entry.task_id = report.details.task_id.clone();
+    entry.finished_at = Some(unix_now());
+    entry.elapsed_ms = Some(report.elapsed.as_millis() as u64);
+    entry.diagnostics = report.details.diagnostics.clone();
+    match &report.result {
+        Ok(output) => {
+            entry.state = JobState::Succeeded;
+            entry.output = output.clone();
+        }
+        Err(e) => {
+            entry.state = JobState::Failed;
+            entry.error = Some(format!("{:#}", e));
+        }
+    }
+}
+
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let value = serde_json::to_value(value)
+        .map(redact::json)
+        .unwrap_or_default();
+    let body = serde_json::to_vec_pretty(&value).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .unwrap_or_default()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,255 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const GRAPHICS_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "eps", "svg"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyKind {
+    Input,
+    Graphics,
+    Bibliography,
+    Package,
+    Class,
+}
+
+impl DependencyKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Input => "input",
+            Self::Graphics => "graphics",
+            Self::Bibliography => "bibliography",
+            Self::Package => "package",
+            Self::Class => "class",
+        }
+    }
+}
+
+/// One reference of the graph, in the file it appears in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub from: PathBuf,
+    pub kind: DependencyKind,
+    /// As written, e.g. `chapters/intro`.
+    pub target: String,
+    /// The file it resolved to, if any.
+    pub path: Option<PathBuf>,
+}
+
+/// The files a document is built from, resolved by scanning `\input`,
+/// `\include`, `\includegraphics`, bibliography commands and local
+/// packages/classes, starting from the main file.
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    pub files: Vec<PathBuf>,
+    /// References that could not be resolved to a file on disk. Packages and
+    /// classes are only recorded when a local copy exists, so they never
+    /// show up here.
+    pub missing: Vec<PathBuf>,
+    /// Every reference found, resolved or not, in scanning order.
+    pub dependencies: Vec<Dependency>,
+}
+
+impl DependencyGraph {
+    pub fn scan(main: &Path) -> Result<Self> {
+        let mut graph = Self {
+            files: vec![main.to_path_buf()],
+            ..Self::default()
+        };
+        if main.extension().and_then(|e| e.to_str()) != Some("tex") {
+            return Ok(graph);
+        }
+
+        let base = main.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut seen: HashSet<PathBuf> = HashSet::from([main.to_path_buf()]);
+        let mut pending = vec![main.to_path_buf()];
+
+        while let Some(current) = pending.pop() {
+            let text = fs::read_to_string(&current)
+                .with_context(|| format!("Failed to read file: {}", current.display()))?;
+
+            for reference in references(&text) {
+                let resolved = resolve(&base, &reference);
+                graph.dependencies.push(Dependency {
+                    from: current.clone(),
+                    kind: reference.kind,
+                    target: reference.target.clone(),
+                    path: resolved.clone(),
+                });
+                match resolved {
+                    Some(path) => {
+                        if seen.insert(path.clone()) {
+                            graph.files.push(path.clone());
+                            if path.extension().and_then(|e| e.to_str()) == Some("tex") {
+                                pending.push(path);
+                            }
+                        }
+                    }
+                    None if matches!(
+                        reference.kind,
+                        DependencyKind::Package | DependencyKind::Class
+                    ) => {}
+                    None => graph.missing.push(PathBuf::from(&reference.target)),
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reference {
+    kind: DependencyKind,
+    target: String,
+}
+
+fn references(text: &str) -> Vec<Reference> {
+    let mut found = Vec::new();
+    for line in text.lines() {
+        let line = strip_comment(line);
+        for (command, kind) in [
+            ("\\input", DependencyKind::Input),
+            ("\\include", DependencyKind::Input),
+            ("\\subfile", DependencyKind::Input),
+            ("\\includegraphics", DependencyKind::Graphics),
+            ("\\bibliography", DependencyKind::Bibliography),
+            ("\\addbibresource", DependencyKind::Bibliography),
+            ("\\usepackage", DependencyKind::Package),
+            ("\\documentclass", DependencyKind::Class),
+        ] {
+            for argument in command_arguments(line, command) {
+                for target in argument.split(',') {
+                    let target = target.trim();
+                    if !target.is_empty() {
+                        found.push(Reference {
+                            kind,
+                            target: target.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+/// One line of a document in reading order.
+pub struct SourceLine {
+    pub file: PathBuf,
+    pub number: usize,
+    pub text: String,
+}
+
+/// The lines of `main` with the files it `\input`s or `\include`s inlined
+/// after the line that pulls them in, each file once.
+pub fn read_lines(main: &Path) -> Result<Vec<SourceLine>> {
+    let mut lines = Vec::new();
+    let root = main.parent().unwrap_or(Path::new(""));
+    read_lines_into(main, root, &mut HashSet::new(), &mut lines)?;
+    Ok(lines)
+}
+
+/// TeX resolves inputs against the directory it runs in, `root`.
+fn read_lines_into(
+    file: &Path,
+    root: &Path,
+    seen: &mut HashSet<PathBuf>,
+    lines: &mut Vec<SourceLine>,
+) -> Result<()> {
+    if !seen.insert(file.to_path_buf()) {
+        return Ok(());
+    }
+    let text = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read file: {}", file.display()))?;
+    for (index, line) in text.lines().enumerate() {
+        let code = strip_comment(line);
+        let inputs: Vec<&str> = ["\\input", "\\include"]
+            .iter()
+            .flat_map(|command| command_arguments(code, command))
+            .collect();
+        lines.push(SourceLine {
+            file: file.to_path_buf(),
+            number: index + 1,
+            text: line.to_string(),
+        });
+        for input in inputs {
+            let mut path = root.join(input.trim());
+            if path.extension().is_none() {
+                path.set_extension("tex");
+            }
+            if path.is_file() {
+                read_lines_into(&path, root, seen, lines)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drops everything after an unescaped `%`.
+pub fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'%' && (i == 0 || bytes[i - 1] != b'\\') {
+            return &line[..i];
+        }
+    }
+    line
+}
+
+/// Returns the mandatory `{...}` argument of every occurrence of `command`
+/// in `line`, skipping an optional `[...]` argument. Longer commands sharing
+/// the prefix (`\includegraphics` vs `\include`) are not matched.
+pub fn command_arguments<'a>(line: &'a str, command: &str) -> Vec<&'a str> {
+    let mut arguments = Vec::new();
+    let mut rest = line;
+
+    while let Some(pos) = rest.find(command) {
+        let after = &rest[pos + command.len()..];
+        rest = after;
+        if after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            continue;
+        }
+
+        let mut tail = after.trim_start();
+        if tail.starts_with('[') {
+            match tail.find(']') {
+                Some(end) => tail = tail[end + 1..].trim_start(),
+                None => continue,
+            }
+        }
+        if let Some(body) = tail.strip_prefix('{') {
+            if let Some(end) = body.find('}') {
+                arguments.push(&body[..end]);
+                rest = &body[end + 1..];
+            }
+        }
+    }
+
+    arguments
+}
+
+fn resolve(base: &Path, reference: &Reference) -> Option<PathBuf> {
+    let target = Path::new(&reference.target);
+    let candidates: Vec<PathBuf> = match reference.kind {
+        DependencyKind::Input => vec![target.with_extension("tex"), target.to_path_buf()],
+        DependencyKind::Bibliography => vec![target.with_extension("bib"), target.to_path_buf()],
+        DependencyKind::Package => vec![target.with_extension("sty")],
+        DependencyKind::Class => vec![target.with_extension("cls")],
+        DependencyKind::Graphics => {
+            let mut candidates = vec![target.to_path_buf()];
+            candidates.extend(
+                GRAPHICS_EXTENSIONS
+                    .iter()
+                    .map(|ext| target.with_extension(ext)),
+            );
+            candidates
+        }
+    };
+
+    candidates
+        .into_iter()
+        .map(|candidate| base.join(candidate))
+        .find(|path| path.is_file())
+}
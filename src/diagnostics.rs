@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How many lines after a `! ...` error to search for its `l.<n>` marker.
+const ERROR_CONTEXT_LINES: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One problem found in a TeX log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Source file this diagnostic points at. Log paths (`./chapters/kinetics.tex`)
+    /// are relative to the directory of the main file; diagnostics without a
+    /// file belong to the main file.
+    pub fn source_path(&self, main: &Path) -> PathBuf {
+        let Some(file) = self.file.as_deref() else {
+            return main.to_path_buf();
+        };
+        let path = Path::new(file);
+        if path.is_absolute() {
+            return path.to_path_buf();
+        }
+        let root = main.parent().unwrap_or(Path::new(""));
+        root.join(path.strip_prefix("./").unwrap_or(path))
+    }
+}
+
+/// Extracts errors and warnings from a TeX log.
+///
+/// Understands classic `! Message` / `l.41 ...` errors, `file:line: message`
+/// errors (`-file-line-error`), LaTeX and package warnings with `on input
+/// line N`, and over/underfull box warnings. The file of each entry is taken
+/// from TeX's `(./file.tex ... )` nesting.
+pub fn parse_log(log: &str) -> Vec<Diagnostic> {
+    let lines: Vec<&str> = log.lines().collect();
+    let mut files = FileStack::default();
+    let mut diagnostics = Vec::new();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+
+        if let Some(message) = line.strip_prefix("! ") {
+            let line_number = lines
+                .iter()
+                .skip(index + 1)
+                .take(ERROR_CONTEXT_LINES)
+                .find_map(|l| error_line_marker(l));
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                file: files.current(),
+                line: line_number,
+                message: name_undefined(message.trim(), &lines[index + 1..]),
+            });
+        } else if let Some(mut diagnostic) = file_line_error(line) {
+            diagnostic.message = name_undefined(&diagnostic.message, &lines[index + 1..]);
+            diagnostics.push(diagnostic);
+        } else if is_warning_start(line) {
+            let (message, consumed) = join_continuation(&lines[index..]);
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                file: files.current(),
+                line: input_line(&message),
+                message,
+            });
+            for continuation in &lines[index..index + consumed] {
+                files.scan(continuation);
+            }
+            index += consumed;
+            continue;
+        } else if line.starts_with("Overfull \\") || line.starts_with("Underfull \\") {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                file: files.current(),
+                line: box_line(line),
+                message: line.trim().to_string(),
+            });
+        }
+
+        files.scan(line);
+        index += 1;
+    }
+
+    diagnostics
+}
+
+/// Builds a single diagnostic from a server error message when no log is
+/// available, picking up a `file:line:` prefix if there is one.
+pub fn from_message(message: &str) -> Diagnostic {
+    let first_line = message.lines().next().unwrap_or(message);
+    file_line_error(first_line).unwrap_or_else(|| Diagnostic {
+        severity: Severity::Error,
+        file: None,
+        line: None,
+        message: message.trim().to_string(),
+    })
+}
+
+/// Tracks which file TeX is reading from the parentheses it writes around
+/// every opened file.
+#[derive(Debug, Default)]
+struct FileStack {
+    stack: Vec<Option<String>>,
+}
+
+impl FileStack {
+    fn current(&self) -> Option<String> {
+        self.stack.iter().rev().find_map(|entry| entry.clone())
+    }
+
+    fn scan(&mut self, line: &str) {
+        let mut chars = line.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '(' => {
+                    let rest = &line[i + 1..];
+                    let end = rest
+                        .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                        .unwrap_or(rest.len());
+                    let token = &rest[..end];
+                    if looks_like_file(token) {
+                        self.stack.push(Some(token.to_string()));
+                        for _ in 0..token.chars().count() {
+                            chars.next();
+                        }
+                    } else {
+                        self.stack.push(None);
+                    }
+                }
+                ')' => {
+                    self.stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn looks_like_file(token: &str) -> bool {
+    let name = token.rsplit('/').next().unwrap_or(token);
+    ((token.starts_with("./") || token.starts_with('/') || token.contains('/'))
+        && name.contains('.'))
+        || [
+            ".tex", ".sty", ".cls", ".aux", ".bbl", ".cfg", ".def", ".fd", ".toc",
+        ]
+        .iter()
+        .any(|ext| token.ends_with(ext))
+}
+
+fn error_line_marker(line: &str) -> Option<u32> {
+    let rest = line.strip_prefix("l.")?;
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+/// Names the command in an "Undefined control sequence." error: TeX breaks
+/// the `l.<n>` context line right after it.
+fn name_undefined(message: &str, following: &[&str]) -> String {
+    if message != "Undefined control sequence." {
+        return message.to_string();
+    }
+    let command = following
+        .iter()
+        .take(ERROR_CONTEXT_LINES)
+        .find(|l| error_line_marker(l).is_some())
+        .and_then(|context| {
+            let start = context.rfind('\\')?;
+            let name: String = context[start + 1..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphabetic() || *c == '@')
+                .collect();
+            (!name.is_empty()).then_some(name)
+        });
+    match command {
+        Some(name) => format!("Undefined control sequence \\{}.", name),
+        None => message.to_string(),
+    }
+}
+
+/// `./chapter2.tex:41: Undefined control sequence.`
+fn file_line_error(line: &str) -> Option<Diagnostic> {
+    let mut parts = line.splitn(3, ':');
+    let file = parts.next()?;
+    let number = parts.next()?;
+    let message = parts.next()?;
+    if !file.ends_with(".tex") || number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(Diagnostic {
+        severity: Severity::Error,
+        file: Some(file.to_string()),
+        line: number.parse().ok(),
+        message: message.trim().to_string(),
+    })
+}
+
+fn is_warning_start(line: &str) -> bool {
+    line.starts_with("LaTeX Warning:")
+        || line.starts_with("LaTeX Font Warning:")
+        || (line.starts_with("Package ") || line.starts_with("Class "))
+            && line.contains(" Warning:")
+}
+
+/// Joins a multi-line warning (continuation lines are indented or prefixed
+/// with `(package)`) and returns it with the number of lines used.
+fn join_continuation(lines: &[&str]) -> (String, usize) {
+    let mut message = lines[0].trim().to_string();
+    let mut consumed = 1;
+    for line in &lines[1..] {
+        let trimmed = line.trim_start();
+        let is_continuation = !line.is_empty()
+            && (line.starts_with(' ') || trimmed.starts_with('(') && trimmed.contains(')'))
+            && !trimmed.starts_with("(./");
+        if !is_continuation {
+            break;
+        }
+        let text = trimmed
+            .strip_prefix('(')
+            .and_then(|t| t.split_once(')'))
+            .map(|(_, rest)| rest.trim())
+            .unwrap_or(trimmed);
+        message.push(' ');
+        message.push_str(text);
+        consumed += 1;
+    }
+    (message, consumed)
+}
+
+fn input_line(message: &str) -> Option<u32> {
+    let rest = &message[message.find("input line ")? + "input line ".len()..];
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+/// `Overfull \hbox (12.3pt too wide) in paragraph at lines 41--42`
+fn box_line(line: &str) -> Option<u32> {
+    let rest = &line[line.find(" at line")? + " at line".len()..];
+    let rest = rest.trim_start_matches('s').trim_start();
+    let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
@@ -0,0 +1,397 @@
+use crate::cli::Args;
+use anyhow::{Context, Result};
+
+const USAGE: &str = "\
+Usage: chemtex elements <symbol>... [--props LIST] [--as text|table] [--caption TEXT]
+
+Looks up element data, e.g.
+  chemtex elements Fe Cu Zn --props mass,en --as table
+
+Properties: number, name, mass, en (Pauling electronegativity), config
+(electron configuration), period, group. In sources, \\elem{Fe}{mass} is
+replaced by the value when packing.
+
+Options:
+  --props LIST    Comma-separated properties (default: number,name,mass,en)
+  --as KIND       text (default) or table (booktabs/siunitx)
+  --caption TEXT  Caption of the table";
+
+const DEFAULT_PROPS: &str = "number,name,mass,en";
+
+/// Symbol, name, standard atomic weight (IUPAC, abridged; elements without
+/// stable isotopes carry the mass number of their longest-lived one),
+/// Pauling electronegativity and ground-state configuration.
+const ELEMENTS: &[(&str, &str, f64, Option<f64>, &str)] = &[
+    ("H", "Hydrogen", 1.008, Some(2.2), "1s1"),
+    ("He", "Helium", 4.0026, None, "1s2"),
+    ("Li", "Lithium", 6.94, Some(0.98), "[He] 2s1"),
+    ("Be", "Beryllium", 9.0122, Some(1.57), "[He] 2s2"),
+    ("B", "Boron", 10.81, Some(2.04), "[He] 2s2 2p1"),
+    ("C", "Carbon", 12.011, Some(2.55), "[He] 2s2 2p2"),
+    ("N", "Nitrogen", 14.007, Some(3.04), "[He] 2s2 2p3"),
+    ("O", "Oxygen", 15.999, Some(3.44), "[He] 2s2 2p4"),
+    ("F", "Fluorine", 18.998, Some(3.98), "[He] 2s2 2p5"),
+    ("Ne", "Neon", 20.18, None, "[He] 2s2 2p6"),
+    ("Na", "Sodium", 22.99, Some(0.93), "[Ne] 3s1"),
+    ("Mg", "Magnesium", 24.305, Some(1.31), "[Ne] 3s2"),
+    ("Al", "Aluminium", 26.982, Some(1.61), "[Ne] 3s2 3p1"),
+    ("Si", "Silicon", 28.085, Some(1.9), "[Ne] 3s2 3p2"),
+    ("P", "Phosphorus", 30.974, Some(2.19), "[Ne] 3s2 3p3"),
+    ("S", "Sulfur", 32.06, Some(2.58), "[Ne] 3s2 3p4"),
+    ("Cl", "Chlorine", 35.45, Some(3.16), "[Ne] 3s2 3p5"),
+    ("Ar", "Argon", 39.948, None, "[Ne] 3s2 3p6"),
+    ("K", "Potassium", 39.098, Some(0.82), "[Ar] 4s1"),
+    ("Ca", "Calcium", 40.078, Some(1.0), "[Ar] 4s2"),
+    ("Sc", "Scandium", 44.956, Some(1.36), "[Ar] 3d1 4s2"),
+    ("Ti", "Titanium", 47.867, Some(1.54), "[Ar] 3d2 4s2"),
+    ("V", "Vanadium", 50.942, Some(1.63), "[Ar] 3d3 4s2"),
+    ("Cr", "Chromium", 51.996, Some(1.66), "[Ar] 3d5 4s1"),
+    ("Mn", "Manganese", 54.938, Some(1.55), "[Ar] 3d5 4s2"),
+    ("Fe", "Iron", 55.845, Some(1.83), "[Ar] 3d6 4s2"),
+    ("Co", "Cobalt", 58.933, Some(1.88), "[Ar] 3d7 4s2"),
+    ("Ni", "Nickel", 58.693, Some(1.91), "[Ar] 3d8 4s2"),
+    ("Cu", "Copper", 63.546, Some(1.9), "[Ar] 3d10 4s1"),
+    ("Zn", "Zinc", 65.38, Some(1.65), "[Ar] 3d10 4s2"),
+    ("Ga", "Gallium", 69.723, Some(1.81), "[Ar] 3d10 4s2 4p1"),
+    ("Ge", "Germanium", 72.63, Some(2.01), "[Ar] 3d10 4s2 4p2"),
+    ("As", "Arsenic", 74.922, Some(2.18), "[Ar] 3d10 4s2 4p3"),
+    ("Se", "Selenium", 78.971, Some(2.55), "[Ar] 3d10 4s2 4p4"),
+    ("Br", "Bromine", 79.904, Some(2.96), "[Ar] 3d10 4s2 4p5"),
+    ("Kr", "Krypton", 83.798, Some(3.0), "[Ar] 3d10 4s2 4p6"),
+    ("Rb", "Rubidium", 85.468, Some(0.82), "[Kr] 5s1"),
+    ("Sr", "Strontium", 87.62, Some(0.95), "[Kr] 5s2"),
+    ("Y", "Yttrium", 88.906, Some(1.22), "[Kr] 4d1 5s2"),
+    ("Zr", "Zirconium", 91.224, Some(1.33), "[Kr] 4d2 5s2"),
+    ("Nb", "Niobium", 92.906, Some(1.6), "[Kr] 4d4 5s1"),
+    ("Mo", "Molybdenum", 95.95, Some(2.16), "[Kr] 4d5 5s1"),
+    ("Tc", "Technetium", 98.0, Some(1.9), "[Kr] 4d5 5s2"),
+    ("Ru", "Ruthenium", 101.07, Some(2.2), "[Kr] 4d7 5s1"),
+    ("Rh", "Rhodium", 102.91, Some(2.28), "[Kr] 4d8 5s1"),
+    ("Pd", "Palladium", 106.42, Some(2.2), "[Kr] 4d10"),
+    ("Ag", "Silver", 107.87, Some(1.93), "[Kr] 4d10 5s1"),
+    ("Cd", "Cadmium", 112.41, Some(1.69), "[Kr] 4d10 5s2"),
+    ("In", "Indium", 114.82, Some(1.78), "[Kr] 4d10 5s2 5p1"),
+    ("Sn", "Tin", 118.71, Some(1.96), "[Kr] 4d10 5s2 5p2"),
+    ("Sb", "Antimony", 121.76, Some(2.05), "[Kr] 4d10 5s2 5p3"),
+    ("Te", "Tellurium", 127.6, Some(2.1), "[Kr] 4d10 5s2 5p4"),
+    ("I", "Iodine", 126.9, Some(2.66), "[Kr] 4d10 5s2 5p5"),
+    ("Xe", "Xenon", 131.29, Some(2.6), "[Kr] 4d10 5s2 5p6"),
+    ("Cs", "Caesium", 132.91, Some(0.79), "[Xe] 6s1"),
+    ("Ba", "Barium", 137.33, Some(0.89), "[Xe] 6s2"),
+    ("La", "Lanthanum", 138.91, Some(1.1), "[Xe] 5d1 6s2"),
+    ("Ce", "Cerium", 140.12, Some(1.12), "[Xe] 4f1 5d1 6s2"),
+    ("Pr", "Praseodymium", 140.91, Some(1.13), "[Xe] 4f3 6s2"),
+    ("Nd", "Neodymium", 144.24, Some(1.14), "[Xe] 4f4 6s2"),
+    ("Pm", "Promethium", 145.0, Some(1.13), "[Xe] 4f5 6s2"),
+    ("Sm", "Samarium", 150.36, Some(1.17), "[Xe] 4f6 6s2"),
+    ("Eu", "Europium", 151.96, Some(1.2), "[Xe] 4f7 6s2"),
+    ("Gd", "Gadolinium", 157.25, Some(1.2), "[Xe] 4f7 5d1 6s2"),
+    ("Tb", "Terbium", 158.93, Some(1.1), "[Xe] 4f9 6s2"),
+    ("Dy", "Dysprosium", 162.5, Some(1.22), "[Xe] 4f10 6s2"),
+    ("Ho", "Holmium", 164.93, Some(1.23), "[Xe] 4f11 6s2"),
+    ("Er", "Erbium", 167.26, Some(1.24), "[Xe] 4f12 6s2"),
+    ("Tm", "Thulium", 168.93, Some(1.25), "[Xe] 4f13 6s2"),
+    ("Yb", "Ytterbium", 173.05, Some(1.1), "[Xe] 4f14 6s2"),
+    ("Lu", "Lutetium", 174.97, Some(1.27), "[Xe] 4f14 5d1 6s2"),
+    ("Hf", "Hafnium", 178.49, Some(1.3), "[Xe] 4f14 5d2 6s2"),
+    ("Ta", "Tantalum", 180.95, Some(1.5), "[Xe] 4f14 5d3 6s2"),
+    ("W", "Tungsten", 183.84, Some(2.36), "[Xe] 4f14 5d4 6s2"),
+    ("Re", "Rhenium", 186.21, Some(1.9), "[Xe] 4f14 5d5 6s2"),
+    ("Os", "Osmium", 190.23, Some(2.2), "[Xe] 4f14 5d6 6s2"),
+    ("Ir", "Iridium", 192.22, Some(2.2), "[Xe] 4f14 5d7 6s2"),
+    ("Pt", "Platinum", 195.08, Some(2.28), "[Xe] 4f14 5d9 6s1"),
+    ("Au", "Gold", 196.97, Some(2.54), "[Xe] 4f14 5d10 6s1"),
+    ("Hg", "Mercury", 200.59, Some(2.0), "[Xe] 4f14 5d10 6s2"),
+    (
+        "Tl",
+        "Thallium",
+        204.38,
+        Some(1.62),
+        "[Xe] 4f14 5d10 6s2 6p1",
+    ),
+    ("Pb", "Lead", 207.2, Some(2.33), "[Xe] 4f14 5d10 6s2 6p2"),
+    (
+        "Bi",
+        "Bismuth",
+        208.98,
+        Some(2.02),
+        "[Xe] 4f14 5d10 6s2 6p3",
+    ),
+    ("Po", "Polonium", 209.0, Some(2.0), "[Xe] 4f14 5d10 6s2 6p4"),
+    ("At", "Astatine", 210.0, Some(2.2), "[Xe] 4f14 5d10 6s2 6p5"),
+    ("Rn", "Radon", 222.0, Some(2.2), "[Xe] 4f14 5d10 6s2 6p6"),
+    ("Fr", "Francium", 223.0, Some(0.7), "[Rn] 7s1"),
+    ("Ra", "Radium", 226.0, Some(0.9), "[Rn] 7s2"),
+    ("Ac", "Actinium", 227.0, Some(1.1), "[Rn] 6d1 7s2"),
+    ("Th", "Thorium", 232.04, Some(1.3), "[Rn] 6d2 7s2"),
+    ("Pa", "Protactinium", 231.04, Some(1.5), "[Rn] 5f2 6d1 7s2"),
+    ("U", "Uranium", 238.03, Some(1.38), "[Rn] 5f3 6d1 7s2"),
+    ("Np", "Neptunium", 237.0, Some(1.36), "[Rn] 5f4 6d1 7s2"),
+    ("Pu", "Plutonium", 244.0, Some(1.28), "[Rn] 5f6 7s2"),
+    ("Am", "Americium", 243.0, Some(1.13), "[Rn] 5f7 7s2"),
+    ("Cm", "Curium", 247.0, Some(1.28), "[Rn] 5f7 6d1 7s2"),
+    ("Bk", "Berkelium", 247.0, Some(1.3), "[Rn] 5f9 7s2"),
+    ("Cf", "Californium", 251.0, Some(1.3), "[Rn] 5f10 7s2"),
+    ("Es", "Einsteinium", 252.0, Some(1.3), "[Rn] 5f11 7s2"),
+    ("Fm", "Fermium", 257.0, Some(1.3), "[Rn] 5f12 7s2"),
+    ("Md", "Mendelevium", 258.0, Some(1.3), "[Rn] 5f13 7s2"),
+    ("No", "Nobelium", 259.0, Some(1.3), "[Rn] 5f14 7s2"),
+    ("Lr", "Lawrencium", 266.0, Some(1.3), "[Rn] 5f14 7s2 7p1"),
+    ("Rf", "Rutherfordium", 267.0, None, "[Rn] 5f14 6d2 7s2"),
+    ("Db", "Dubnium", 268.0, None, "[Rn] 5f14 6d3 7s2"),
+    ("Sg", "Seaborgium", 269.0, None, "[Rn] 5f14 6d4 7s2"),
+    ("Bh", "Bohrium", 270.0, None, "[Rn] 5f14 6d5 7s2"),
+    ("Hs", "Hassium", 277.0, None, "[Rn] 5f14 6d6 7s2"),
+    ("Mt", "Meitnerium", 278.0, None, "[Rn] 5f14 6d7 7s2"),
+    ("Ds", "Darmstadtium", 281.0, None, "[Rn] 5f14 6d8 7s2"),
+    ("Rg", "Roentgenium", 282.0, None, "[Rn] 5f14 6d9 7s2"),
+    ("Cn", "Copernicium", 285.0, None, "[Rn] 5f14 6d10 7s2"),
+    ("Nh", "Nihonium", 286.0, None, "[Rn] 5f14 6d10 7s2 7p1"),
+    ("Fl", "Flerovium", 289.0, None, "[Rn] 5f14 6d10 7s2 7p2"),
+    ("Mc", "Moscovium", 290.0, None, "[Rn] 5f14 6d10 7s2 7p3"),
+    ("Lv", "Livermorium", 293.0, None, "[Rn] 5f14 6d10 7s2 7p4"),
+    ("Ts", "Tennessine", 294.0, None, "[Rn] 5f14 6d10 7s2 7p5"),
+    ("Og", "Oganesson", 294.0, None, "[Rn] 5f14 6d10 7s2 7p6"),
+];
+
+/// Last atomic number of each period.
+const PERIOD_ENDS: [u32; 7] = [2, 10, 18, 36, 54, 86, 118];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Element {
+    pub number: u32,
+    pub symbol: &'static str,
+    pub name: &'static str,
+    /// Standard atomic weight, g/mol.
+    pub mass: f64,
+    pub electronegativity: Option<f64>,
+    /// Such as `[Ar] 3d6 4s2`.
+    pub configuration: &'static str,
+}
+
+impl Element {
+    pub fn period(&self) -> u32 {
+        PERIOD_ENDS
+            .iter()
+            .position(|&end| self.number <= end)
+            .unwrap_or(6) as u32
+            + 1
+    }
+
+    /// IUPAC group 1–18; `None` for the lanthanides and actinides after
+    /// La and Ac.
+    pub fn group(&self) -> Option<u32> {
+        let period = self.period();
+        let start = match period {
+            1 => 0,
+            p => PERIOD_ENDS[p as usize - 2],
+        };
+        let position = self.number - start;
+        match (period, position) {
+            (1, 1) => Some(1),
+            (1, _) => Some(18),
+            (2 | 3, p) if p > 2 => Some(p + 10),
+            (6 | 7, 4..=17) => None,
+            (6 | 7, p) if p > 17 => Some(p - 14),
+            (_, p) => Some(p),
+        }
+    }
+
+    /// `prop` as plain text, or `None` when the element has no such value.
+    pub fn property(&self, prop: &str) -> Result<Option<String>> {
+        Ok(match prop {
+            "number" => Some(self.number.to_string()),
+            "symbol" => Some(self.symbol.to_string()),
+            "name" => Some(self.name.to_string()),
+            "mass" => Some(self.mass.to_string()),
+            "en" => self.electronegativity.map(|en| en.to_string()),
+            "config" => Some(self.configuration.to_string()),
+            "period" => Some(self.period().to_string()),
+            "group" => self.group().map(|g| g.to_string()),
+            other => anyhow::bail!(
+                "Unknown property {:?} (number, symbol, name, mass, en, config, period, group)",
+                other
+            ),
+        })
+    }
+
+    /// `prop` for use in LaTeX text: configurations get superscripts.
+    pub fn property_tex(&self, prop: &str) -> Result<Option<String>> {
+        let value = self.property(prop)?;
+        if prop != "config" {
+            return Ok(value);
+        }
+        Ok(value.map(|config| {
+            config
+                .split(' ')
+                .map(|part| match part.find(|c: char| c.is_ascii_alphabetic()) {
+                    Some(i) if !part.starts_with('[') => {
+                        let (shell, electrons) = part.split_at(i + 1);
+                        format!("{}\\textsuperscript{{{}}}", shell, electrons)
+                    }
+                    _ => part.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("~")
+        }))
+    }
+}
+
+/// The element with `symbol` (case-sensitive, as in formulas).
+pub fn lookup(symbol: &str) -> Option<Element> {
+    let index = ELEMENTS.iter().position(|e| e.0 == symbol)?;
+    let (symbol, name, mass, electronegativity, configuration) = ELEMENTS[index];
+    Some(Element {
+        number: index as u32 + 1,
+        symbol,
+        name,
+        mass,
+        electronegativity,
+        configuration,
+    })
+}
+
+/// The English names of the elements, in order.
+pub fn names() -> impl Iterator<Item = &'static str> {
+    ELEMENTS.iter().map(|e| e.1)
+}
+
+/// Standard atomic weight of the element `symbol`, g/mol.
+pub fn atomic_mass(symbol: &str) -> Option<f64> {
+    lookup(symbol).map(|e| e.mass)
+}
+
+/// Expands `\elem{Fe}{mass}` to the value, for the pack-time preprocessor.
+pub fn macro_value(symbol: &str, prop: &str) -> Result<String> {
+    let element = lookup(symbol).with_context(|| format!("Unknown element {:?}", symbol))?;
+    element
+        .property_tex(prop)?
+        .with_context(|| format!("{} has no value for {:?}", element.name, prop))
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &["props", "as", "caption"])?;
+    let mut elements = Vec::new();
+    for symbol in (0..).map_while(|i| args.positional(i)) {
+        // `Fe,Cu` as well as `Fe Cu`.
+        for symbol in symbol.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            elements.push(lookup(symbol).with_context(|| format!("Unknown element {:?}", symbol))?);
+        }
+    }
+    if elements.is_empty() {
+        anyhow::bail!("{}", USAGE);
+    }
+    let props: Vec<&str> = args
+        .value("props")
+        .unwrap_or(DEFAULT_PROPS)
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .collect();
+    // Rejects unknown properties before printing anything.
+    for prop in &props {
+        elements[0].property(prop)?;
+    }
+    match args.value("as").unwrap_or("text") {
+        "text" => print!("{}", text_table(&elements, &props)?),
+        "table" => print!("{}", latex_table(&elements, &props, args.value("caption"))?),
+        other => anyhow::bail!("Unknown output kind {:?}, expected text or table", other),
+    }
+    Ok(())
+}
+
+fn text_table(elements: &[Element], props: &[&str]) -> Result<String> {
+    let mut rows = vec![std::iter::once("symbol")
+        .chain(props.iter().copied())
+        .map(str::to_string)
+        .collect::<Vec<_>>()];
+    for element in elements {
+        let mut row = vec![element.symbol.to_string()];
+        for prop in props {
+            row.push(element.property(prop)?.unwrap_or_else(|| "-".to_string()));
+        }
+        rows.push(row);
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    let mut out = String::new();
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Numeric properties go in siunitx `S` columns sized to their values.
+fn latex_table(elements: &[Element], props: &[&str], caption: Option<&str>) -> Result<String> {
+    let mut columns = vec!["l".to_string()];
+    let mut headers = vec!["Element".to_string()];
+    let mut cells: Vec<Vec<String>> = elements
+        .iter()
+        .map(|e| vec![format!("\\ce{{{}}}", e.symbol)])
+        .collect();
+    for prop in props {
+        let values = elements
+            .iter()
+            .map(|e| e.property_tex(prop))
+            .collect::<Result<Vec<_>>>()?;
+        let numeric = !matches!(*prop, "name" | "symbol" | "config");
+        if numeric {
+            let present: Vec<&str> = values.iter().flatten().map(String::as_str).collect();
+            columns.push(format!("S[table-format={}]", table_format(&present)));
+            headers.push(format!("{{{}}}", header(prop)));
+        } else {
+            columns.push("l".to_string());
+            headers.push(header(prop).to_string());
+        }
+        for (row, value) in cells.iter_mut().zip(values) {
+            row.push(match (value, numeric) {
+                (Some(value), _) => value,
+                (None, true) => "{--}".to_string(),
+                (None, false) => "--".to_string(),
+            });
+        }
+    }
+
+    let mut out = String::from("\\begin{table}[h]\n\\centering\n");
+    if let Some(caption) = caption {
+        out.push_str(&format!("\\caption{{{}}}\n", caption));
+    }
+    out.push_str(&format!("\\begin{{tabular}}{{{}}}\n", columns.join(" ")));
+    out.push_str("\\toprule\n");
+    out.push_str(&format!("{} \\\\\n", headers.join(" & ")));
+    out.push_str("\\midrule\n");
+    for row in cells {
+        out.push_str(&format!("{} \\\\\n", row.join(" & ")));
+    }
+    out.push_str("\\bottomrule\n\\end{tabular}\n\\end{table}\n");
+    Ok(out)
+}
+
+fn header(prop: &str) -> &'static str {
+    match prop {
+        "number" => "$Z$",
+        "symbol" => "Symbol",
+        "name" => "Name",
+        "mass" => "$A_\\mathrm{r}$",
+        "en" => "$\\chi_\\mathrm{P}$",
+        "config" => "Configuration",
+        "period" => "Period",
+        _ => "Group",
+    }
+}
+
+/// `table-format` wide enough for every value: `55.845`, `4.0026` → `2.4`.
+fn table_format(values: &[&str]) -> String {
+    let (integer, fraction) = values.iter().fold((1, 0), |(i, f), value| {
+        let (int, frac) = value.split_once('.').unwrap_or((value, ""));
+        (i.max(int.len()), f.max(frac.len()))
+    });
+    format!("{}.{}", integer, fraction)
+}
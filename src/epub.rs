@@ -0,0 +1,319 @@
+use crate::batch;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::html::{self, Document, Heading, Sources, Target};
+use crate::pack::{self, PackOptions, TempDir};
+use crate::rasterize;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const USAGE: &str = "\
+Usage: chemtex epub <main.tex|project_dir> [options]
+
+Converts a summary into a reflowable EPUB for e-readers, for the subset of
+LaTeX the templates use. Math and \\ce formulas become MathML; structures
+written by `chemtex smiles` are drawn as images. Equations MathML cannot
+show, other chemfig structures, TikZ pictures and PDF figures are compiled
+by the remote compiler and rasterized with pdftoppm (poppler); without it,
+or with --no-render, they are left as TeX or a note.
+
+Options:
+  --out FILE    The book to write (default: the main file's name, .epub)
+  --no-render   Do not render fallback images
+  --no-cache    Always submit, ignoring the build cache";
+
+const CONTAINER: &str = "\
+<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">
+  <rootfiles>
+    <rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>
+  </rootfiles>
+</container>
+";
+
+const STYLE: &str = "\
+body { font-family: serif; line-height: 1.45; }
+header { text-align: center; margin-bottom: 2em; }
+figure, .center { text-align: center; margin: 1em 0; }
+figure img, img.display { display: block; max-width: 100%; margin: 0.5em auto; }
+img.inline { vertical-align: middle; max-height: 1.6em; }
+figcaption, .caption { font-size: 0.9em; }
+table { border-collapse: collapse; margin: 1em auto; }
+th, td { padding: 0.2em 0.6em; }
+thead th { border-top: 2px solid; border-bottom: 1px solid; }
+tbody tr:last-child td { border-bottom: 2px solid; }
+.equation { display: flex; align-items: center; justify-content: center; margin: 0.8em 0; }
+.equation .number { margin-left: auto; padding-left: 1em; }
+.math { margin: 0.8em 0; overflow-x: auto; }
+.unsupported { border: 1px dashed #999; padding: 0.4em; font-size: 0.9em; }
+.footnotes { font-size: 0.9em; border-top: 1px solid #999; margin-top: 2em; }
+";
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["no-render", "no-cache"], &["out"])?;
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+
+    // Projects are packed, as for compiling, so variables, citation styles
+    // and generated tables are what the PDF has.
+    let scratch = TempDir::new("epub")?;
+    let (main, sources) = match input.is_dir() {
+        true => {
+            let config = ProjectConfig::find(&input)?.unwrap_or_default();
+            let main = match &config.main {
+                Some(main) => input.join(main),
+                None => batch::find_main_document(&input)?,
+            };
+            // An e-book has no pages to index or a title page to set.
+            let options = PackOptions {
+                formula_index: false,
+                titlepage: None,
+                ..PackOptions::from_config(&config)?
+            };
+            let archive = pack::pack_project_with(&main, scratch.path(), &options)?;
+            (main, Sources::read(&archive)?)
+        }
+        false => (input.clone(), Sources::read(&input)?),
+    };
+    let output = match args.value("out") {
+        Some(out) => PathBuf::from(out),
+        None => main.with_extension("epub"),
+    };
+
+    let mut document = html::convert(&sources, Target::Epub)
+        .with_context(|| format!("Failed to convert {}", main.display()))?;
+    let fallbacks: Vec<String> = document
+        .fallbacks
+        .iter()
+        .map(|f| f.source.clone())
+        .collect();
+    let mut images = None;
+    if !fallbacks.is_empty() {
+        if args.flag("no-render") {
+            eprintln!("{} fallback(s) left as TeX or a note", fallbacks.len());
+        } else if !rasterize::available() {
+            eprintln!(
+                "pdftoppm (poppler) not found; {} fallback(s) left as TeX or a note",
+                fallbacks.len()
+            );
+        } else {
+            println!("Rendering {} fallback image(s)...", fallbacks.len());
+            images = Some(
+                rasterize::snippets(
+                    &main,
+                    &document.preamble,
+                    &fallbacks,
+                    !args.flag("no-cache"),
+                )
+                .await?,
+            );
+        }
+    }
+    document.fill_fallbacks(images);
+
+    write_book(&output, &document, &main)?;
+    println!("EPUB saved to: {}", output.display());
+    if !document.unsupported.is_empty() {
+        let left_out: Vec<String> = document
+            .unsupported
+            .iter()
+            .map(|(what, count)| format!("{} {}", count, what))
+            .collect();
+        eprintln!("Not in the EPUB: {}", left_out.join(", "));
+    }
+    Ok(())
+}
+
+/// Writes an EPUB 3 book with a single chapter.
+fn write_book(output: &Path, document: &Document, main: &Path) -> Result<()> {
+    let title = match &document.title {
+        Some(title) => html_text(title),
+        None => main
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "Summary".to_string()),
+    };
+    let author = document.author.as_deref().map(html_text);
+    let identifier = identifier(&title, author.as_deref().unwrap_or_default());
+
+    let file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let mut zip = ZipWriter::new(file);
+    // The mimetype comes first and uncompressed, so it can be sniffed.
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file("mimetype", stored)?;
+    zip.write_all(b"application/epub+zip")?;
+    zip.start_file("META-INF/container.xml", deflated)?;
+    zip.write_all(CONTAINER.as_bytes())?;
+    zip.start_file("OEBPS/content.opf", deflated)?;
+    zip.write_all(package(document, &title, author.as_deref(), &identifier).as_bytes())?;
+    zip.start_file("OEBPS/nav.xhtml", deflated)?;
+    zip.write_all(navigation(document, &title).as_bytes())?;
+    zip.start_file("OEBPS/text.xhtml", deflated)?;
+    zip.write_all(chapter(document, &title).as_bytes())?;
+    zip.start_file("OEBPS/style.css", deflated)?;
+    zip.write_all(STYLE.as_bytes())?;
+    for image in &document.images {
+        zip.start_file(format!("OEBPS/{}", image.name), stored)?;
+        zip.write_all(&image.bytes)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+fn package(document: &Document, title: &str, author: Option<&str>, identifier: &str) -> String {
+    let mut metadata = format!(
+        "    <dc:identifier id=\"book-id\">{}</dc:identifier>\n    <dc:title>{}</dc:title>\n    <dc:language>{}</dc:language>\n",
+        identifier,
+        escape(title),
+        document.language
+    );
+    if let Some(author) = author {
+        metadata.push_str(&format!(
+            "    <dc:creator>{}</dc:creator>\n",
+            escape(author)
+        ));
+    }
+    metadata.push_str(&format!(
+        "    <meta property=\"dcterms:modified\">{}</meta>\n",
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    ));
+    let mut manifest = String::from(
+        "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n    <item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>\n",
+    );
+    let properties = match document.body.contains("<math") {
+        true => " properties=\"mathml\"",
+        false => "",
+    };
+    manifest.push_str(&format!(
+        "    <item id=\"text\" href=\"text.xhtml\" media-type=\"application/xhtml+xml\"{}/>\n",
+        properties
+    ));
+    for (index, image) in document.images.iter().enumerate() {
+        manifest.push_str(&format!(
+            "    <item id=\"image-{}\" href=\"{}\" media-type=\"{}\"/>\n",
+            index + 1,
+            escape(&image.name),
+            image.media_type
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{}\">\n\
+         \x20 <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}  </metadata>\n\
+         \x20 <manifest>\n{}  </manifest>\n\
+         \x20 <spine>\n    <itemref idref=\"text\"/>\n  </spine>\n</package>\n",
+        document.language, metadata, manifest
+    )
+}
+
+/// The table of contents, nested by heading depth.
+fn navigation(document: &Document, title: &str) -> String {
+    let mut list = String::from("<ol>\n");
+    let mut open: Vec<usize> = Vec::new();
+    let headings: Vec<&Heading> = document.toc.iter().collect();
+    for (index, heading) in headings.iter().enumerate() {
+        list.push_str(&format!(
+            "<li><a href=\"text.xhtml#{}\">{} {}</a>",
+            heading.id,
+            heading.number,
+            escape(&html_text(&heading.title))
+        ));
+        match headings.get(index + 1) {
+            Some(next) if next.depth > heading.depth => {
+                list.push_str("\n<ol>\n");
+                open.push(heading.depth);
+            }
+            next => {
+                list.push_str("</li>\n");
+                let depth = next.map_or(0, |next| next.depth);
+                while open.last().is_some_and(|&d| d >= depth) {
+                    open.pop();
+                    list.push_str("</ol>\n</li>\n");
+                }
+            }
+        }
+    }
+    list.push_str("</ol>\n");
+    if document.toc.is_empty() {
+        list = "<ol>\n<li><a href=\"text.xhtml\">Text</a></li>\n</ol>\n".to_string();
+    }
+    format!(
+        "{}<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n{}</nav>\n</body>\n</html>\n",
+        head(document, title),
+        list
+    )
+}
+
+fn chapter(document: &Document, title: &str) -> String {
+    format!(
+        "{}{}</body>\n</html>\n",
+        head(document, title),
+        document.body
+    )
+}
+
+fn head(document: &Document, title: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"{0}\" xml:lang=\"{0}\">\n\
+         <head>\n<meta charset=\"utf-8\"/>\n<title>{1}</title>\n\
+         <link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>\n</head>\n<body>\n",
+        document.language,
+        escape(title)
+    )
+}
+
+/// A stable identifier, so a rebuilt book replaces the old one.
+fn identifier(title: &str, author: &str) -> String {
+    let digest = Sha256::digest(format!("{}\n{}", title, author).as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "urn:uuid:{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Text of converted HTML, for metadata.
+fn html_text(html: &str) -> String {
+    // MathML keeps its TeX in an annotation.
+    let mut html = html.to_string();
+    while let Some(start) = html.find("<annotation") {
+        let end = html[start..]
+            .find("</annotation>")
+            .map_or(html.len(), |i| start + i + "</annotation>".len());
+        html.replace_range(start..end, "");
+    }
+    let mut out = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.replace("&#160;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
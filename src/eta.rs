@@ -0,0 +1,100 @@
+//! Queue wait estimates: how long the jobs in the history waited per place
+//! in the queue they were first given, so a queued task's position can be
+//! read as the time it has left ("position 7, ~4 min remaining").
+
+use crate::history::{self, HistoryEntry};
+use std::time::Duration;
+
+/// Recent jobs the estimate is taken from, at most.
+const SAMPLES: usize = 50;
+/// Fewer jobs than this say too little to go by.
+const MIN_SAMPLES: usize = 3;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueEta {
+    /// Median wait per queue position.
+    per_position: Option<Duration>,
+}
+
+impl QueueEta {
+    /// The estimate from the history; none when it cannot be read.
+    pub fn from_history() -> Self {
+        history::load()
+            .map(|entries| Self::from_entries(&entries))
+            .unwrap_or_default()
+    }
+
+    pub fn from_entries(entries: &[HistoryEntry]) -> Self {
+        let mut rates: Vec<u64> = entries
+            .iter()
+            .rev()
+            .filter_map(|entry| {
+                let position = entry.document.queue_position.filter(|&p| p > 0)?;
+                Some(entry.document.phases.queue_ms? / u64::from(position))
+            })
+            .take(SAMPLES)
+            .collect();
+        if rates.len() < MIN_SAMPLES {
+            return Self::default();
+        }
+        rates.sort_unstable();
+        Self {
+            per_position: Some(Duration::from_millis(rates[rates.len() / 2])),
+        }
+    }
+
+    /// The expected wait at `position`, if the history allows a guess.
+    pub fn remaining(&self, position: u32) -> Option<Duration> {
+        self.per_position.map(|rate| rate * position)
+    }
+}
+
+/// `~4 min remaining`, `~40 s remaining` or `<10 s remaining`.
+pub fn format(remaining: Duration) -> String {
+    let seconds = remaining.as_secs();
+    if seconds < 10 {
+        "<10 s remaining".to_string()
+    } else if seconds < 90 {
+        format!("~{} s remaining", (seconds + 5) / 10 * 10)
+    } else {
+        format!("~{} min remaining", (seconds + 30) / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(queue_position: u32, queue_ms: u64) -> HistoryEntry {
+        serde_json::from_value(serde_json::json!({
+            "finished_at": 0,
+            "name": "doc",
+            "input": "doc.tex",
+            "output": "doc.pdf",
+            "engine": null,
+            "profile": null,
+            "status": "ok",
+            "task_id": null,
+            "elapsed_ms": 0,
+            "compile_ms": null,
+            "warnings": null,
+            "log_url": null,
+            "error": null,
+            "queue_position": queue_position,
+            "phases": { "queue_ms": queue_ms },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn estimates_by_the_median_wait_per_position() {
+        let entries = [entry(5, 10_000), entry(10, 30_000), entry(1, 4_000)];
+        let eta = QueueEta::from_entries(&entries);
+        assert_eq!(eta.remaining(7), Some(Duration::from_secs(21)));
+        assert_eq!(format(Duration::from_secs(21)), "~20 s remaining");
+        assert_eq!(format(Duration::from_secs(230)), "~4 min remaining");
+
+        let eta = QueueEta::from_entries(&entries[..2]);
+        assert_eq!(eta.remaining(7), None);
+    }
+}
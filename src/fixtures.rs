@@ -0,0 +1,247 @@
+//! Record-and-replay of server interactions. `--record DIR` saves every
+//! request chemtex sends to the compile server with the response it got;
+//! `--replay DIR` answers the same requests from those files without the
+//! network. Replays make the integration tests in `tests/` possible and let
+//! users attach a reproducible bug report: uploads are recorded by name,
+//! size and hash only, never their contents, so the TeX sources stay
+//! private (the PDF and log the server sent back are included).
+//!
+//! A fixture directory holds `interactions.jsonl`, one [`Interaction`] per
+//! line in the order they happened, and a `NNN.bin` file for each response
+//! body that is not UTF-8 (PDFs).
+
+use crate::cache;
+use crate::cli::Args;
+use crate::redact;
+use crate::storage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+const INTERACTIONS_FILE: &str = "interactions.jsonl";
+
+static MODE: OnceLock<Mode> = OnceLock::new();
+
+enum Mode {
+    Record(Mutex<Recorder>),
+    Replay(Mutex<Replayer>),
+}
+
+/// One request and the response to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    /// The `Range` header of a segment download.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<Upload>,
+    pub status: u16,
+    /// Response headers, without cookies.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// A UTF-8 body.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Any other body, in this file of the fixture directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_file: Option<String>,
+}
+
+/// What identifies an uploaded file without revealing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Upload {
+    pub file_name: String,
+    pub bytes: usize,
+    pub sha256: String,
+}
+
+impl Upload {
+    pub fn new(file_name: &str, contents: &[u8]) -> Self {
+        Self {
+            file_name: file_name.to_string(),
+            bytes: contents.len(),
+            sha256: cache::to_hex(&Sha256::digest(contents)),
+        }
+    }
+}
+
+struct Recorder {
+    dir: PathBuf,
+    file: File,
+    count: usize,
+}
+
+struct Replayer {
+    dir: PathBuf,
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+/// Applies `--record DIR` or `--replay DIR` of a compiling command.
+pub fn configure(args: &Args) -> Result<()> {
+    match (args.value("record"), args.value("replay")) {
+        (Some(_), Some(_)) => anyhow::bail!("--record and --replay cannot be combined"),
+        (Some(dir), None) => record(Path::new(dir)),
+        (None, Some(dir)) => replay(Path::new(dir)),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Starts recording into `dir`, replacing an earlier recording there.
+pub fn record(dir: &Path) -> Result<()> {
+    storage::ensure_dir(dir)?;
+    let path = dir.join(INTERACTIONS_FILE);
+    let file =
+        File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let recorder = Recorder {
+        dir: dir.to_path_buf(),
+        file,
+        count: 0,
+    };
+    set(Mode::Record(Mutex::new(recorder)))
+}
+
+/// Answers server requests from the recording in `dir` from now on.
+pub fn replay(dir: &Path) -> Result<()> {
+    let path = dir.join(INTERACTIONS_FILE);
+    let text = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read fixtures: {}", path.display()))?;
+    let interactions = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("Invalid interaction at {}:{}", path.display(), i + 1))
+        })
+        .collect::<Result<Vec<Interaction>>>()?;
+    let replayer = Replayer {
+        dir: dir.to_path_buf(),
+        used: vec![false; interactions.len()],
+        interactions,
+    };
+    set(Mode::Replay(Mutex::new(replayer)))
+}
+
+fn set(mode: Mode) -> Result<()> {
+    MODE.set(mode)
+        .map_err(|_| anyhow::anyhow!("Fixtures are already being recorded or replayed"))
+}
+
+/// Whether responses come from a fixture directory rather than the server,
+/// so there is no point in waiting between status checks.
+pub fn replaying() -> bool {
+    matches!(MODE.get(), Some(Mode::Replay(_)))
+}
+
+/// Whether requests are being recorded.
+pub fn recording() -> bool {
+    matches!(MODE.get(), Some(Mode::Record(_)))
+}
+
+/// Whether requests are recorded or replayed; the build cache is bypassed
+/// then, since a cache hit would skip the server altogether.
+pub fn active() -> bool {
+    MODE.get().is_some()
+}
+
+/// A recorded response.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// The recorded response to a request when replaying, `None` otherwise.
+/// Requests are matched by method, URL and range, taking the earliest
+/// unused interaction, so repeated status checks see the statuses
+/// in the order they were recorded; uploads prefer an interaction for the
+/// same file name, so concurrent batch uploads get their own task ids.
+pub fn replayed(
+    method: &str,
+    url: &str,
+    range: Option<&str>,
+    upload: Option<&Upload>,
+) -> Result<Option<Response>> {
+    let Some(Mode::Replay(replayer)) = MODE.get() else {
+        return Ok(None);
+    };
+    let mut replayer = replayer.lock().unwrap_or_else(|e| e.into_inner());
+    let candidates: Vec<usize> = (0..replayer.interactions.len())
+        .filter(|&i| !replayer.used[i])
+        .filter(|&i| {
+            let interaction = &replayer.interactions[i];
+            interaction.method == method
+                && interaction.url == url
+                && interaction.range.as_deref() == range
+        })
+        .collect();
+    let same_file = |&i: &usize| {
+        let recorded = replayer.interactions[i].upload.as_ref();
+        recorded.map(|u| &u.file_name) == upload.map(|u| &u.file_name)
+    };
+    let index = candidates
+        .iter()
+        .copied()
+        .find(same_file)
+        .or(candidates.first().copied())
+        .with_context(|| {
+            format!(
+                "No recorded response left for {} {} in {}",
+                method,
+                url,
+                replayer.dir.display()
+            )
+        })?;
+    replayer.used[index] = true;
+    let interaction = &replayer.interactions[index];
+    let body = match (&interaction.body, &interaction.body_file) {
+        (_, Some(file)) => {
+            let path = replayer.dir.join(file);
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?
+        }
+        (Some(body), None) => body.clone().into_bytes(),
+        (None, None) => Vec::new(),
+    };
+    Ok(Some(Response {
+        status: interaction.status,
+        headers: interaction.headers.clone(),
+        body,
+    }))
+}
+
+/// Appends an interaction, with `body` as its response body, to the
+/// recording, if one is running. Credential headers are masked; the URL is
+/// kept as it was, since replays match on it.
+pub fn recorded(mut interaction: Interaction, body: &[u8]) -> Result<()> {
+    let Some(Mode::Record(recorder)) = MODE.get() else {
+        return Ok(());
+    };
+    for (name, value) in interaction.headers.iter_mut() {
+        *value = redact::header(name, value);
+    }
+    let mut recorder = recorder.lock().unwrap_or_else(|e| e.into_inner());
+    recorder.count += 1;
+    match std::str::from_utf8(body) {
+        Ok(text) => interaction.body = Some(text.to_string()),
+        Err(_) => {
+            let name = format!("{:03}.bin", recorder.count);
+            let path = recorder.dir.join(&name);
+            fs::write(&path, body)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            interaction.body_file = Some(name);
+        }
+    }
+    let line = serde_json::to_string(&interaction)?;
+    writeln!(recorder.file, "{}", line)
+        .and_then(|()| recorder.file.flush())
+        .context("Failed to record the interaction")
+}
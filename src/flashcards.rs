@@ -0,0 +1,660 @@
+use crate::balance;
+use crate::cli::Args;
+use crate::deps::{self, strip_comment};
+use crate::formulas;
+use crate::rasterize;
+use crate::sqlite::{Database, Value};
+use anyhow::{Context, Result};
+use serde_json::json;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+const USAGE: &str = "\
+Usage: chemtex flashcards <summary.tex> --out deck.apkg [options]
+
+Turns a summary into an Anki deck with a card for every definition
+environment, every reaction (\\ce{...} with an arrow; the question shows the
+reactants) and every labelled equation. Answers are compiled by the remote
+compiler and rasterized with pdftoppm (poppler); without it, or with
+--no-render, Anki typesets them itself with MathJax.
+
+Options:
+  --out FILE    The deck to write (required)
+  --deck NAME   Deck name (default: the summary's \\title, else its file name)
+  --no-render   Leave answers as LaTeX for Anki's MathJax
+  --no-cache    Always submit, ignoring the build cache";
+
+/// Environments turned into definition cards; `[Term]` is the question.
+const DEFINITIONS: &[&str] = &["definition", "defn", "dfn", "term"];
+/// Display environments turned into cards when they carry a `\label`.
+const EQUATIONS: &[&str] = &["equation", "align", "gather", "multline"];
+const SECTIONS: &[&str] = &["\\chapter", "\\section", "\\subsection"];
+/// Note type shared by every deck, so re-imports update the same notes.
+const MODEL_ID: i64 = 1_700_000_040_001;
+const CSS: &str = ".card { font-family: serif; font-size: 22px; text-align: center; }\n\
+                   .card img { max-width: 100%; }\n\
+                   .section { font-size: 14px; color: #888; }";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Definition,
+    Reaction,
+    Equation,
+}
+
+impl Kind {
+    fn tag(self) -> &'static str {
+        match self {
+            Self::Definition => "definition",
+            Self::Reaction => "reaction",
+            Self::Equation => "equation",
+        }
+    }
+}
+
+struct Card {
+    kind: Kind,
+    /// Question, as HTML.
+    front: String,
+    /// Answer as LaTeX, compiled into an image or shown through MathJax.
+    answer: String,
+    section: Option<String>,
+}
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["no-render", "no-cache"], &["out", "deck"])?;
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+    let output = PathBuf::from(args.value("out").context(USAGE)?);
+
+    let lines = deps::read_lines(&input)?;
+    let code: Vec<&str> = lines.iter().map(|l| strip_comment(&l.text)).collect();
+    let text = code.join("\n");
+    let (preamble, body) = match text.find("\\begin{document}") {
+        Some(begin) => text.split_at(begin),
+        None => ("", text.as_str()),
+    };
+    let deck = match args.value("deck") {
+        Some(deck) => deck.to_string(),
+        None => command_argument(preamble, "\\title")
+            .map(|title| unescape(&strip_html(&latex_to_html(&title))))
+            .unwrap_or_else(|| file_stem(&input)),
+    };
+    let cards = extract(body);
+    if cards.is_empty() {
+        anyhow::bail!(
+            "No definitions, reactions or labelled equations in {}",
+            input.display()
+        );
+    }
+
+    let mut images = Vec::new();
+    if !args.flag("no-render") {
+        if rasterize::available() {
+            let answers: Vec<String> = cards.iter().map(|card| card.answer.clone()).collect();
+            println!("Rendering {} answer(s)...", cards.len());
+            images =
+                rasterize::snippets(&input, preamble, &answers, !args.flag("no-cache")).await?;
+        } else {
+            eprintln!("pdftoppm (poppler) not found; answers are left to Anki's MathJax");
+        }
+    }
+
+    write_package(&output, &deck, &cards, &images)?;
+    println!(
+        "Wrote {} card(s) to {} (deck \"{}\")",
+        cards.len(),
+        output.display(),
+        deck
+    );
+    Ok(())
+}
+
+/// Cards in the order their sources appear in `body`.
+fn extract(body: &str) -> Vec<Card> {
+    let mut found: Vec<(usize, Card)> = Vec::new();
+    let section_at = |position: usize| {
+        SECTIONS
+            .iter()
+            .filter_map(|command| {
+                let start = body[..position].rfind(&format!("{}{{", command))?;
+                Some((start, command_argument(&body[start..], command)?))
+            })
+            .max_by_key(|&(start, _)| start)
+            .map(|(_, title)| latex_to_html(&title))
+    };
+
+    for name in DEFINITIONS {
+        for (position, options, content) in environments(body, name) {
+            let term = options.or_else(|| command_argument(content, "\\emph"));
+            let front = match (term, section_at(position)) {
+                (Some(term), _) => format!("<b>{}</b>", latex_to_html(&term)),
+                (None, Some(section)) => format!("Definition: {}", section),
+                (None, None) => continue,
+            };
+            found.push((
+                position,
+                Card {
+                    kind: Kind::Definition,
+                    front,
+                    answer: content.trim().to_string(),
+                    section: section_at(position),
+                },
+            ));
+        }
+    }
+
+    for name in EQUATIONS {
+        for (position, _, content) in environments(body, name) {
+            let Some(label) = command_argument(content, "\\label") else {
+                continue;
+            };
+            let mut equation = content.replace("\\nonumber", "");
+            while let Some(start) = equation.find("\\label{") {
+                let end = equation[start..]
+                    .find('}')
+                    .map_or(equation.len(), |i| start + i + 1);
+                equation.replace_range(start..end, "");
+            }
+            let answer = match *name {
+                "equation" => format!("\\[{}\\]", equation.trim()),
+                other => format!(
+                    "\\begin{{{}*}}{}\\end{{{}*}}",
+                    other,
+                    equation.trim(),
+                    other
+                ),
+            };
+            found.push((
+                position,
+                Card {
+                    kind: Kind::Equation,
+                    front: latex_to_html(&label_title(&label)),
+                    answer,
+                    section: section_at(position),
+                },
+            ));
+        }
+    }
+
+    let mut rest = body;
+    let mut seen = Vec::new();
+    while let Some((before, argument, after)) = formulas::next_formula(rest) {
+        let position = body.len() - rest.len() + before.len();
+        rest = after;
+        let Some((left, arrow, right)) = balance::split_arrow(argument) else {
+            continue;
+        };
+        if seen.contains(&argument) {
+            continue;
+        }
+        seen.push(argument);
+        let (conditions, _) = balance::split_conditions(right);
+        let question = format!("\\ce{{{} {}{} ?}}", left.trim(), arrow, conditions);
+        found.push((
+            position,
+            Card {
+                kind: Kind::Reaction,
+                front: format!("\\({}\\)", escape(&question)),
+                answer: format!("\\ce{{{}}}", argument.trim()),
+                section: section_at(position),
+            },
+        ));
+    }
+
+    found.sort_by_key(|&(position, _)| position);
+    found.into_iter().map(|(_, card)| card).collect()
+}
+
+/// `(position, [options], content)` of every `\begin{name}...\end{name}`.
+fn environments<'a>(body: &'a str, name: &str) -> Vec<(usize, Option<String>, &'a str)> {
+    let begin = format!("\\begin{{{}}}", name);
+    let end = format!("\\end{{{}}}", name);
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(start) = body[from..].find(&begin).map(|i| from + i) {
+        let inner = start + begin.len();
+        let Some(stop) = body[inner..].find(&end).map(|i| inner + i) else {
+            break;
+        };
+        let mut content = &body[inner..stop];
+        let mut options = None;
+        if let Some(rest) = content.trim_start().strip_prefix('[') {
+            if let Some((term, tail)) = rest.split_once(']') {
+                options = Some(term.trim().to_string());
+                content = tail;
+            }
+        }
+        found.push((start, options, content));
+        from = stop + end.len();
+    }
+    found
+}
+
+/// The braced argument after the first `command` in `text`.
+fn command_argument(text: &str, command: &str) -> Option<String> {
+    let start = text.find(&format!("{}{{", command))? + command.len();
+    let mut depth = 0;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(text[start + 1..start + i].to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `eq:arrhenius-equation` → `Arrhenius equation`.
+fn label_title(label: &str) -> String {
+    let name = label.rsplit(':').next().unwrap_or(label);
+    let words = name.replace(['-', '_'], " ");
+    let mut chars = words.trim().chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => label.to_string(),
+    }
+}
+
+/// Compiles every answer on a page of its own and rasterizes the pages.
+/// An `.apkg`: a zip with the collection database, the media files named
+/// by number and a `media` map from numbers to file names.
+fn write_package(output: &Path, deck: &str, cards: &[Card], images: &[Vec<u8>]) -> Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let (seconds, millis) = (now.as_secs() as i64, now.as_millis() as i64);
+    let deck_id = 1 + (hash(deck) % (1 << 40)) as i64;
+
+    let mut media = serde_json::Map::new();
+    let mut files = Vec::new();
+    let mut notes = Vec::new();
+    let mut rows = Vec::new();
+    for (i, card) in cards.iter().enumerate() {
+        let back = match images.get(i) {
+            Some(image) => {
+                let name = format!("chemtex-{:016x}.png", hash(&card.answer));
+                media.insert(files.len().to_string(), json!(name));
+                files.push(image);
+                format!("<img src=\"{}\">", name)
+            }
+            None if card.kind == Kind::Definition => latex_to_html(&card.answer),
+            None if card.kind == Kind::Reaction => format!("\\({}\\)", escape(&card.answer)),
+            None => escape(&card.answer),
+        };
+        let front = match &card.section {
+            Some(section) => format!("{}<div class=\"section\">{}</div>", card.front, section),
+            None => card.front.clone(),
+        };
+        let sort_field = unescape(&strip_html(&card.front));
+        let id = millis + i as i64;
+        notes.push(vec![
+            Value::Integer(id),
+            Value::Text(format!(
+                "{:x}",
+                hash(&format!("{}\x1f{}", deck, card.answer))
+            )),
+            Value::Integer(MODEL_ID),
+            Value::Integer(seconds),
+            Value::Integer(-1),
+            Value::Text(format!(" chemtex {} ", card.kind.tag())),
+            Value::Text(format!("{}\x1f{}", front, back)),
+            Value::Text(sort_field.clone()),
+            Value::Integer(checksum(&sort_field)),
+            Value::Integer(0),
+            Value::Text(String::new()),
+        ]);
+        let mut card_row = vec![
+            Value::Integer(id),
+            Value::Integer(id),
+            Value::Integer(deck_id),
+        ];
+        // ord, mod, usn, type, queue, due, then zeroes up to data.
+        card_row.extend([0, seconds, -1, 0, 0, i as i64 + 1].map(Value::Integer));
+        card_row.extend([0; 8].map(Value::Integer));
+        card_row.push(Value::Text(String::new()));
+        rows.push(card_row);
+    }
+
+    let mut database = Database::default();
+    database.table(
+        "col",
+        COL_SQL,
+        true,
+        vec![collection(deck, deck_id, seconds, millis)],
+    );
+    database.table("notes", NOTES_SQL, true, notes);
+    database.table("cards", CARDS_SQL, true, rows);
+    database.table("revlog", REVLOG_SQL, true, Vec::new());
+    database.table("graves", GRAVES_SQL, false, Vec::new());
+    for (name, table, columns) in INDEXES {
+        let sql = format!(
+            "CREATE INDEX {} on {} ({})",
+            name,
+            table,
+            columns
+                .iter()
+                .map(|(column, _)| *column)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let positions: Vec<usize> = columns.iter().map(|&(_, position)| position).collect();
+        database.index(name, table, &sql, &positions);
+    }
+
+    let file = File::create(output)
+        .with_context(|| format!("Failed to create file: {}", output.display()))?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+    zip.start_file("collection.anki2", options)?;
+    zip.write_all(&database.to_bytes()?)?;
+    for (number, image) in files.iter().enumerate() {
+        zip.start_file(number.to_string(), options)?;
+        zip.write_all(image)?;
+    }
+    zip.start_file("media", options)?;
+    zip.write_all(serde_json::Value::Object(media).to_string().as_bytes())?;
+    zip.finish().context("Failed to write the deck")?;
+    Ok(())
+}
+
+/// The single row of `col`: settings, the note type and the decks as JSON.
+fn collection(deck: &str, deck_id: i64, seconds: i64, millis: i64) -> Vec<Value> {
+    let conf = json!({
+        "activeDecks": [1], "curDeck": 1, "newSpread": 0, "collapseTime": 1200,
+        "timeLim": 0, "estTimes": true, "dueCounts": true, "curModel": null,
+        "nextPos": 1, "sortType": "noteFld", "sortBackwards": false, "addToCur": true,
+    });
+    let field = |name: &str, ord: u32| {
+        json!({"name": name, "ord": ord, "font": "Arial", "size": 20, "media": [],
+               "rtl": false, "sticky": false})
+    };
+    let model = json!({
+        "id": MODEL_ID, "name": "ChemTeX", "type": 0, "mod": seconds, "usn": -1,
+        "sortf": 0, "did": deck_id, "tags": [], "vers": [], "css": CSS,
+        "flds": [field("Front", 0), field("Back", 1)],
+        "tmpls": [{
+            "name": "Card 1", "ord": 0, "did": null, "bqfmt": "", "bafmt": "",
+            "qfmt": "{{Front}}", "afmt": "{{FrontSide}}<hr id=answer>{{Back}}",
+        }],
+        "req": [[0, "any", [0]]],
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\
+                     \\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\
+                     \\usepackage[version=4]{mhchem}\n\\pagestyle{empty}\n\
+                     \\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}",
+    });
+    let deck_json = |id: i64, name: &str| {
+        json!({"id": id, "name": name, "desc": "", "mod": seconds, "usn": -1, "conf": 1,
+               "dyn": 0, "collapsed": false, "extendNew": 0, "extendRev": 50,
+               "newToday": [0, 0], "revToday": [0, 0], "lrnToday": [0, 0],
+               "timeToday": [0, 0]})
+    };
+    let dconf = json!({"1": {
+        "id": 1, "name": "Default", "mod": 0, "usn": 0, "dyn": false, "maxTaken": 60,
+        "timer": 0, "autoplay": true, "replayq": true,
+        "new": {"delays": [1, 10], "ints": [1, 4, 7], "initialFactor": 2500,
+                "order": 1, "perDay": 20, "bury": true, "separate": true},
+        "rev": {"perDay": 100, "ease4": 1.3, "fuzz": 0.05, "ivlFct": 1,
+                "maxIvl": 36500, "minSpace": 1, "bury": true},
+        "lapse": {"delays": [10], "mult": 0, "minInt": 1, "leechFails": 8,
+                  "leechAction": 0},
+    }});
+    let mut decks = serde_json::Map::new();
+    decks.insert("1".to_string(), deck_json(1, "Default"));
+    decks.insert(deck_id.to_string(), deck_json(deck_id, deck));
+    let mut models = serde_json::Map::new();
+    models.insert(MODEL_ID.to_string(), model);
+
+    vec![
+        Value::Integer(1),
+        Value::Integer(seconds - seconds % 86_400),
+        Value::Integer(millis),
+        Value::Integer(millis),
+        Value::Integer(11),
+        Value::Integer(0),
+        Value::Integer(0),
+        Value::Integer(0),
+        Value::Text(conf.to_string()),
+        Value::Text(serde_json::Value::Object(models).to_string()),
+        Value::Text(serde_json::Value::Object(decks).to_string()),
+        Value::Text(dconf.to_string()),
+        Value::Text("{}".to_string()),
+    ]
+}
+
+/// Running text of a definition as HTML; math stays LaTeX, in the `\(...\)`
+/// and `\[...\]` delimiters Anki's MathJax reads.
+fn latex_to_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut in_math = false;
+    while let Some(c) = rest.chars().next() {
+        let advance = |n: usize| &rest[n..];
+        if let Some(tail) = rest.strip_prefix("$$") {
+            out.push_str(if in_math { "\\]" } else { "\\[" });
+            in_math = !in_math;
+            rest = tail;
+        } else if c == '$' {
+            out.push_str(if in_math { "\\)" } else { "\\(" });
+            in_math = !in_math;
+            rest = advance(1);
+        } else if in_math {
+            out.push_str(&escape(&c.to_string()));
+            rest = advance(c.len_utf8());
+        } else if let Some(tail) = rest.strip_prefix("\\ce{") {
+            let argument = command_argument(rest, "\\ce").unwrap_or_default();
+            out.push_str(&format!("\\(\\ce{{{}}}\\)", escape(&argument)));
+            rest = tail.get(argument.len() + 1..).unwrap_or("");
+        } else if let Some((tag, command)) = [("b", "\\textbf"), ("i", "\\emph"), ("i", "\\textit")]
+            .into_iter()
+            .find(|(_, command)| rest.starts_with(&format!("{}{{", command)))
+        {
+            let argument = command_argument(rest, command).unwrap_or_default();
+            out.push_str(&format!("<{}>{}</{}>", tag, latex_to_html(&argument), tag));
+            rest = rest.get(command.len() + argument.len() + 2..).unwrap_or("");
+        } else if let Some(tail) = rest.strip_prefix("\\\\") {
+            out.push_str("<br>");
+            rest = tail;
+        } else if let Some((replacement, length)) = [
+            ("—", "---"),
+            ("–", "--"),
+            ("“", "``"),
+            ("”", "''"),
+            ("%", "\\%"),
+            ("&amp;", "\\&"),
+            ("&nbsp;", "~"),
+        ]
+        .into_iter()
+        .find(|(_, source)| rest.starts_with(source))
+        .map(|(replacement, source)| (replacement, source.len()))
+        {
+            out.push_str(replacement);
+            rest = advance(length);
+        } else if c.is_whitespace() {
+            if !out.ends_with(' ') {
+                out.push(' ');
+            }
+            rest = advance(c.len_utf8());
+        } else {
+            out.push_str(&escape(&c.to_string()));
+            rest = advance(c.len_utf8());
+        }
+    }
+    out.trim().to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The reverse of [`escape`], for text that leaves HTML.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn strip_html(html: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("flashcards")
+        .to_string()
+}
+
+fn hash(text: &str) -> u64 {
+    let digest = Sha256::digest(text.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Anki's duplicate check: the first 32 bits of the sort field's SHA-1.
+fn checksum(text: &str) -> i64 {
+    let digest = Sha1::digest(text.as_bytes());
+    u32::from_be_bytes(digest[..4].try_into().unwrap()).into()
+}
+
+/// Anki's schema 11, as its own `.apkg` exports have it.
+const COL_SQL: &str = "CREATE TABLE col (id integer primary key, crt integer not null, \
+    mod integer not null, scm integer not null, ver integer not null, dty integer not null, \
+    usn integer not null, ls integer not null, conf text not null, models text not null, \
+    decks text not null, dconf text not null, tags text not null)";
+const NOTES_SQL: &str = "CREATE TABLE notes (id integer primary key, guid text not null, \
+    mid integer not null, mod integer not null, usn integer not null, tags text not null, \
+    flds text not null, sfld integer not null, csum integer not null, flags integer not null, \
+    data text not null)";
+const CARDS_SQL: &str = "CREATE TABLE cards (id integer primary key, nid integer not null, \
+    did integer not null, ord integer not null, mod integer not null, usn integer not null, \
+    type integer not null, queue integer not null, due integer not null, ivl integer not null, \
+    factor integer not null, reps integer not null, lapses integer not null, \
+    left integer not null, odue integer not null, odid integer not null, \
+    flags integer not null, data text not null)";
+const REVLOG_SQL: &str = "CREATE TABLE revlog (id integer primary key, cid integer not null, \
+    usn integer not null, ease integer not null, ivl integer not null, \
+    lastIvl integer not null, factor integer not null, time integer not null, \
+    type integer not null)";
+const GRAVES_SQL: &str = "CREATE TABLE graves (usn integer not null, oid integer not null, \
+    type integer not null)";
+/// Name, table and (column, position) pairs.
+type IndexSpec = (&'static str, &'static str, &'static [(&'static str, usize)]);
+const INDEXES: &[IndexSpec] = &[
+    ("ix_notes_usn", "notes", &[("usn", 4)]),
+    ("ix_cards_usn", "cards", &[("usn", 5)]),
+    ("ix_revlog_usn", "revlog", &[("usn", 2)]),
+    ("ix_cards_nid", "cards", &[("nid", 1)]),
+    (
+        "ix_cards_sched",
+        "cards",
+        &[("did", 2), ("queue", 7), ("due", 8)],
+    ),
+    ("ix_revlog_cid", "revlog", &[("cid", 1)]),
+    ("ix_notes_csum", "notes", &[("csum", 8)]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    #[test]
+    fn checksums_are_the_first_word_of_the_sha1() {
+        // SHA-1("abc") = a9993e36 4706816a ...
+        assert_eq!(checksum("abc"), 0xa9993e36);
+        assert_eq!(checksum(""), 0xda39a3ee);
+    }
+
+    #[test]
+    fn deck_names_are_plain_text() {
+        assert_eq!(
+            unescape(&strip_html(&latex_to_html(
+                "Acids \\& \\textbf{Bases} <pH>"
+            ))),
+            "Acids & Bases <pH>"
+        );
+    }
+
+    #[test]
+    fn decks_open_in_sqlite_with_anki_checksums() {
+        let dir = std::env::temp_dir().join(format!("chemtex-flashcards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Enough notes for the tables and indexes to span several pages.
+        let cards: Vec<Card> = (0..400)
+            .map(|i| Card {
+                kind: Kind::Definition,
+                front: format!("<b>Term {} &amp; more</b>", i),
+                answer: format!("Definition {} {}", i, "x".repeat(i % 50)),
+                section: Some("Acids".to_string()),
+            })
+            .collect();
+        let output = dir.join("deck.apkg");
+        write_package(&output, "Acids & Bases", &cards, &[]).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let mut bytes = Vec::new();
+        archive
+            .by_name("collection.anki2")
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let database = dir.join("collection.anki2");
+        std::fs::write(&database, bytes).unwrap();
+        let connection = rusqlite::Connection::open(&database).unwrap();
+        let check: String = connection
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .unwrap();
+        let decks: String = connection
+            .query_row("SELECT decks FROM col", [], |row| row.get(0))
+            .unwrap();
+        let mut statement = connection
+            .prepare("SELECT sfld, csum FROM notes ORDER BY id")
+            .unwrap();
+        let notes: Vec<(String, i64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let cards: i64 = connection
+            .query_row("SELECT count(*) FROM cards", [], |row| row.get(0))
+            .unwrap();
+        drop(statement);
+        drop(connection);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(check, "ok");
+        assert!(decks.contains("\"Acids & Bases\""));
+        assert_eq!(notes.len(), 400);
+        assert_eq!(cards, 400);
+        assert_eq!(notes[7].0, "Term 7 & more");
+        for (sort_field, csum) in &notes {
+            let digest = Sha1::digest(sort_field.as_bytes());
+            assert_eq!(
+                *csum,
+                i64::from(u32::from_be_bytes(digest[..4].try_into().unwrap()))
+            );
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use crate::balance::{Reaction, Species, CHARGE};
+use crate::deps::strip_comment;
+use std::collections::BTreeMap;
+
+const BEGIN_DOCUMENT: &str = "\\begin{document}";
+const END_DOCUMENT: &str = "\\end{document}";
+const INDEX: &str = "\\index[compounds]";
+/// Commands whose argument is also written to the table of contents or the
+/// list of figures, where an `\index` would be indexed twice.
+const MOVING: &[&str] = &[
+    "\\part",
+    "\\chapter",
+    "\\section",
+    "\\subsection",
+    "\\subsubsection",
+    "\\paragraph",
+    "\\caption",
+];
+
+/// `--formula-index`: follows every `\ce{...}` in the body with an
+/// `\index[compounds]{...}` entry per species, sorted by Hill formula. A
+/// species written another way (`CH3CH2OH`) becomes a subentry of its Hill
+/// formula (`C2H6O`). Arguments that are not formulas or reactions are left
+/// alone.
+pub fn annotate(text: &str) -> String {
+    let start = text
+        .find(BEGIN_DOCUMENT)
+        .map_or(0, |begin| begin + BEGIN_DOCUMENT.len());
+    let (preamble, body) = text.split_at(start);
+    let mut out = String::with_capacity(text.len());
+    out.push_str(preamble);
+    for line in body.split_inclusive('\n') {
+        let code = strip_comment(line);
+        if MOVING.iter().any(|command| code.contains(command)) {
+            out.push_str(line);
+            continue;
+        }
+        let mut rest = code;
+        while let Some((before, argument, after)) = next_formula(rest) {
+            out.push_str(before);
+            for entry in entries(argument) {
+                out.push_str(&format!("{}{{{}}}", INDEX, entry));
+            }
+            rest = after;
+        }
+        out.push_str(rest);
+        out.push_str(&line[code.len()..]);
+    }
+    out
+}
+
+/// Sets up the compounds index in a main file: `imakeidx` (in place of
+/// `makeidx`), `\makeindex[name=compounds,...]` and `\printindex[compounds]`
+/// before `\end{document}`.
+pub fn setup(text: &str) -> String {
+    let (Some(begin), Some(end)) = (text.find(BEGIN_DOCUMENT), text.rfind(END_DOCUMENT)) else {
+        return text.to_string();
+    };
+    let (preamble, body) = text.split_at(begin);
+    let loads = |package: &str| {
+        preamble
+            .lines()
+            .any(|line| is_package(strip_comment(line).trim(), package))
+    };
+    let has_imakeidx = loads("imakeidx");
+    let has_makeidx = loads("makeidx");
+
+    let mut out = String::with_capacity(text.len() + 200);
+    let mut loaded = has_imakeidx;
+    for line in preamble.split_inclusive('\n') {
+        let command = strip_comment(line).trim();
+        if is_package(command, "makeidx") {
+            // makeidx and imakeidx define the same commands.
+            out.push_str(&format!("% {}", line));
+            if !loaded {
+                out.push_str("\\usepackage{imakeidx}\n");
+                loaded = true;
+            }
+            continue;
+        }
+        if !loaded && !has_makeidx && is_package(command, "hyperref") {
+            // hyperref goes last.
+            out.push_str("\\usepackage{imakeidx}\n");
+            loaded = true;
+        }
+        out.push_str(line);
+    }
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    if !loaded {
+        out.push_str("\\usepackage{imakeidx}\n");
+    }
+    out.push_str("\\makeindex[name=compounds,title=Index of Compounds,intoc]\n");
+
+    let end = end - begin;
+    out.push_str(&body[..end]);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str("\\printindex[compounds]\n");
+    out.push_str(&body[end..]);
+    out
+}
+
+/// Splits `text` around its first `\ce{...}`: the text up to and including
+/// the closing brace, the argument, and the rest.
+pub fn next_formula(text: &str) -> Option<(&str, &str, &str)> {
+    let mut from = 0;
+    loop {
+        let start = from + text[from..].find("\\ce")? + "\\ce".len();
+        let open = start + text[start..].len() - text[start..].trim_start().len();
+        if text[start..].starts_with(|c: char| c.is_ascii_alphabetic())
+            || !text[open..].starts_with('{')
+        {
+            from = start;
+            continue;
+        }
+        let mut depth = 0;
+        for (i, c) in text[open..].char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let close = open + i + 1;
+                        return Some((&text[..close], &text[open + 1..close - 1], &text[close..]));
+                    }
+                }
+                _ => {}
+            }
+        }
+        return None;
+    }
+}
+
+/// Index entries for the species of a `\ce` argument, without repeats.
+fn entries(argument: &str) -> Vec<String> {
+    let species: Vec<Species> = match Reaction::parse(argument) {
+        Ok(reaction) => reaction
+            .reactants
+            .into_iter()
+            .chain(reaction.products)
+            .collect(),
+        Err(_) => Species::parse(argument.trim()).into_iter().collect(),
+    };
+    let mut entries = Vec::new();
+    for species in &species {
+        if let Some(entry) = entry(species) {
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries
+}
+
+/// `C2H6O@\ce{C2H6O}!CH3CH2OH@\ce{CH3CH2OH}`; electrons get none.
+fn entry(species: &Species) -> Option<String> {
+    let (hill, charge) = hill(&species.elements)?;
+    let formula = species.formula.trim();
+    let written = match formula.split_once('^') {
+        Some((body, _)) => body,
+        None => formula.trim_end_matches(['+', '-']),
+    };
+    let key = escape(&format!("{}{}", hill, charge));
+    let top = format!("{}@\\ce{{{}}}", key, key);
+    if written == hill {
+        return Some(top);
+    }
+    let written = escape(&format!("{}{}", written, charge));
+    Some(format!("{}!{}@\\ce{{{}}}", top, written, written))
+}
+
+/// Hill formula (carbon, hydrogen, then alphabetical; all alphabetical
+/// without carbon) and the charge in mhchem notation.
+fn hill(elements: &BTreeMap<String, i64>) -> Option<(String, String)> {
+    let mut atoms: Vec<(&str, i64)> = elements
+        .iter()
+        .filter(|(element, _)| *element != CHARGE)
+        .map(|(element, &count)| (element.as_str(), count))
+        .collect();
+    if atoms.is_empty() {
+        return None;
+    }
+    if atoms.iter().any(|&(element, _)| element == "C") {
+        atoms.sort_by_key(|&(element, _)| (element != "C", element != "H", element));
+    }
+    let formula = atoms
+        .iter()
+        .map(|&(element, count)| match count {
+            1 => element.to_string(),
+            n => format!("{}{}", element, n),
+        })
+        .collect();
+    let charge = match elements.get(CHARGE).copied().unwrap_or(0) {
+        0 => String::new(),
+        1 => "^+".to_string(),
+        -1 => "^-".to_string(),
+        n if n > 0 => format!("^{{{}+}}", n),
+        n => format!("^{{{}-}}", -n),
+    };
+    Some((formula, charge))
+}
+
+/// Quotes makeindex's special characters.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '!' | '@' | '|' | '"') {
+            out.push('"');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn is_package(command: &str, package: &str) -> bool {
+    command.starts_with("\\usepackage") && command.ends_with(&format!("{{{}}}", package))
+}
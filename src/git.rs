@@ -0,0 +1,274 @@
+use crate::batch;
+use crate::cli::Args;
+use crate::deps::DependencyGraph;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+const USAGE: &str = "\
+Usage: chemtex git-changed [<rev-range>] [--dir DIR] [batch options]
+
+Compiles the documents under DIR (default: the repository root) whose main
+file or any file they include changed in <rev-range>. Without a range,
+uncommitted and untracked changes are used. Accepts the options of
+`chemtex batch`.";
+
+/// Compiles only the documents affected by the files changed in a revision
+/// range, following each document's include graph.
+pub async fn run_changed(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        batch::COMPILE_FLAGS,
+        &[batch::COMPILE_OPTIONS, &["dir"]].concat(),
+    )?;
+    let range = args.positional(0);
+    if args.positional(1).is_some() {
+        anyhow::bail!(USAGE);
+    }
+
+    let root = repo_root(Path::new("."))?;
+    let dir = args.value("dir").map(PathBuf::from).unwrap_or(root.clone());
+    let changed = changed_files(&root, range)?;
+    let description = range.unwrap_or("the working tree");
+    if changed.is_empty() {
+        println!("No files changed in {}", description);
+        return Ok(());
+    }
+
+    let out_dir = args.value("out-dir").map(PathBuf::from).unwrap_or_default();
+    let mut jobs = Vec::new();
+    for job in batch::jobs_from_dir(&dir, &out_dir)? {
+        let graph = DependencyGraph::scan(&job.input)
+            .with_context(|| format!("Failed to scan {}", job.input.display()))?;
+        let affected_by = graph
+            .files
+            .iter()
+            .filter_map(|file| std::path::absolute(file).ok())
+            .find(|file| changed.contains(file));
+        if let Some(file) = affected_by {
+            println!(
+                "[{}] Affected by {}",
+                job.name,
+                file.strip_prefix(&root).unwrap_or(&file).display()
+            );
+            jobs.push(job);
+        }
+    }
+
+    if jobs.is_empty() {
+        println!(
+            "{} changed file(s) in {}, no documents affected",
+            changed.len(),
+            description
+        );
+        return Ok(());
+    }
+    batch::compile(&args, jobs, &format!("changes of {}", description)).await
+}
+
+/// A document inside a remote repository: `URL[#branch][:path/to/main.tex]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitSource {
+    pub url: String,
+    pub branch: Option<String>,
+    pub path: Option<String>,
+}
+
+impl GitSource {
+    /// Parses `https://host/repo.git#branch:path/to/main.tex`. Both the branch
+    /// and the path are optional (`#main`, `#:summary.tex`).
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (url, fragment) = match spec.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (spec, None),
+        };
+        if url.is_empty() {
+            anyhow::bail!("Missing repository URL in {}", spec);
+        }
+        if url.starts_with('-') {
+            // Would be read as an option of `git clone`.
+            anyhow::bail!("Invalid repository URL: {}", url);
+        }
+        let (branch, path) = match fragment {
+            Some(fragment) => match fragment.split_once(':') {
+                Some((branch, path)) => (branch, path),
+                None => (fragment, ""),
+            },
+            None => ("", ""),
+        };
+        // The main file is looked up in the checkout, and must stay there.
+        if !Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            anyhow::bail!("The path in {} must be relative to the repository", spec);
+        }
+        let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_string());
+        Ok(Self {
+            url: url.to_string(),
+            branch: non_empty(branch),
+            path: non_empty(path),
+        })
+    }
+
+    /// Shallow-clones the repository into `dest` and returns the main file:
+    /// the requested path, or [`batch::find_main_document`] of the checkout.
+    ///
+    /// The repository is someone else's, and what is packed from it is
+    /// uploaded: a checkout with symlinks, which could point at any local
+    /// file, is refused, and the main file must lie inside it.
+    pub fn checkout(&self, dest: &Path) -> Result<PathBuf> {
+        self.clone_into(dest)?;
+        refuse_symlinks(dest).with_context(|| format!("Refusing to compile {}", self.url))?;
+        let main = match &self.path {
+            Some(path) => {
+                let main = dest.join(path);
+                if !main.is_file() {
+                    anyhow::bail!("{} does not exist in {}", path, self.url);
+                }
+                main
+            }
+            None => batch::find_main_document(dest)
+                .with_context(|| format!("Pick the main file with {}#branch:path", self.url))?,
+        };
+        anyhow::ensure!(
+            main.canonicalize()?.starts_with(dest.canonicalize()?),
+            "{} lies outside the checkout of {}",
+            main.display(),
+            self.url
+        );
+        Ok(main)
+    }
+
+    /// Shallow-clones the requested branch into `dest`.
+    pub fn clone_into(&self, dest: &Path) -> Result<()> {
+        let mut args = vec!["clone", "--depth", "1", "--quiet"];
+        if let Some(branch) = &self.branch {
+            args.extend(["--branch", branch.as_str()]);
+        }
+        let dest_str = dest.to_str().context("Invalid checkout path")?;
+        args.extend(["--", self.url.as_str(), dest_str]);
+        git(Path::new("."), &args).map(drop)
+    }
+}
+
+/// Fails on the first symlink under `dir`, `.git` aside.
+fn refuse_symlinks(dir: &Path) -> Result<()> {
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = fs::read_dir(&current)
+            .with_context(|| format!("Failed to read directory: {}", current.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let file_type = fs::symlink_metadata(&path)?.file_type();
+            if file_type.is_symlink() {
+                let shown = path.strip_prefix(dir).unwrap_or(&path);
+                anyhow::bail!("{} is a symlink", shown.display());
+            }
+            if file_type.is_dir() && path.file_name().is_some_and(|name| name != ".git") {
+                pending.push(path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Top-level directory of the repository containing `dir`.
+pub fn repo_root(dir: &Path) -> Result<PathBuf> {
+    let output = git(dir, &["rev-parse", "--show-toplevel"])?;
+    Ok(PathBuf::from(output.trim()))
+}
+
+/// Absolute paths of the files changed in `range` (anything `git diff`
+/// accepts), or of uncommitted and untracked files when `range` is `None`.
+pub fn changed_files(root: &Path, range: Option<&str>) -> Result<HashSet<PathBuf>> {
+    let mut listings = Vec::new();
+    match range {
+        Some(range) => listings.push(git(root, &["diff", "--name-only", range, "--"])?),
+        None => {
+            listings.push(git(root, &["diff", "--name-only", "HEAD", "--"])?);
+            listings.push(git(root, &["ls-files", "--others", "--exclude-standard"])?);
+        }
+    }
+
+    Ok(listings
+        .iter()
+        .flat_map(|listing| listing.lines())
+        .filter(|line| !line.is_empty())
+        .map(|line| root.join(line))
+        .collect())
+}
+
+/// Runs git in `dir` and returns what it printed.
+pub fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).context("git printed invalid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_name_a_path_inside_the_repository() {
+        let source = GitSource::parse("https://host/lab.git#main:report/main.tex").unwrap();
+        assert_eq!(source.branch.as_deref(), Some("main"));
+        assert_eq!(source.path.as_deref(), Some("report/main.tex"));
+        for spec in [
+            "https://host/lab.git#main:/home/me/notes.tex",
+            "https://host/lab.git#:../notes.tex",
+            "--upload-pack=touch /tmp/x",
+        ] {
+            assert!(GitSource::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checkouts_with_symlinks_are_refused() {
+        let dir = std::env::temp_dir().join(format!("chemtex-git-{}", std::process::id()));
+        let repo = dir.join("repo");
+        fs::create_dir_all(repo.join("figs")).unwrap();
+        fs::write(repo.join("main.tex"), "\\documentclass{article}\n").unwrap();
+        std::os::unix::fs::symlink("/etc/hostname", repo.join("figs/plot.png")).unwrap();
+        let commit = || -> Result<String> {
+            git(&repo, &["init", "--quiet"])?;
+            git(&repo, &["add", "."])?;
+            git(
+                &repo,
+                &[
+                    "-c",
+                    "user.name=t",
+                    "-c",
+                    "user.email=t@t",
+                    "commit",
+                    "--quiet",
+                    "-m",
+                    "lab",
+                ],
+            )
+        };
+        let committed = commit();
+
+        let source = GitSource::parse(&format!("file://{}", repo.display())).unwrap();
+        let checked_out = source.checkout(&dir.join("checkout"));
+        fs::remove_dir_all(&dir).unwrap();
+        committed.unwrap();
+        let error = format!("{:#}", checked_out.unwrap_err());
+        assert!(error.contains("figs/plot.png is a symlink"), "{}", error);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::batch;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::deps::{self, strip_comment, SourceLine};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: chemtex glossary [file.tex|project_dir] [--out FILE] [--check]
+
+Finds abbreviations defined in the text, as in \"thin-layer chromatography
+(TLC)\", and writes a glossaries section with one \\newacronym each. Input it
+in the preamble and put \\printnoidxglossary[type=\\acronymtype] where the list
+of abbreviations should go; no makeglossaries run is needed.
+
+Options:
+  --out FILE  Write the section to FILE instead of standard output
+  --check     Fail if an abbreviation is used before its definition";
+
+/// Longest abbreviation recognised in parentheses.
+const MAX_LENGTH: usize = 10;
+
+struct Definition {
+    long: String,
+    /// Line index and byte offset of the opening parenthesis.
+    position: (usize, usize),
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["check"], &["out"])?;
+    let path = PathBuf::from(args.positional(0).unwrap_or("."));
+    let main = if path.is_dir() {
+        match ProjectConfig::find(&path)?.and_then(|config| config.main) {
+            Some(main) => path.join(main),
+            None => batch::find_main_document(&path)?,
+        }
+    } else if path.extension().and_then(|e| e.to_str()) == Some("tex") {
+        path
+    } else {
+        anyhow::bail!(USAGE);
+    };
+
+    let lines = deps::read_lines(&main)?;
+    let definitions = definitions(&lines);
+    if definitions.is_empty() {
+        println!("No abbreviations defined in {}", main.display());
+        return Ok(());
+    }
+
+    let mut early = 0;
+    for (abbreviation, position) in used_before_definition(&lines, &definitions) {
+        let definition = &definitions[&abbreviation];
+        let (line, defined) = (&lines[position.0], &lines[definition.position.0]);
+        eprintln!(
+            "{}:{}: {} is used before its definition at {}:{}",
+            line.file.display(),
+            line.number,
+            abbreviation,
+            defined.file.display(),
+            defined.number
+        );
+        early += 1;
+    }
+
+    let section = render(&definitions, &main);
+    match args.value("out") {
+        Some(out) => {
+            fs::write(out, &section).with_context(|| format!("Failed to write file: {}", out))?;
+            println!("Wrote {} abbreviation(s) to {}", definitions.len(), out);
+        }
+        None => print!("{}", section),
+    }
+    if args.flag("check") && early > 0 {
+        anyhow::bail!("{} abbreviation(s) used before their definition", early);
+    }
+    Ok(())
+}
+
+/// The first definition of each abbreviation; later ones with another long
+/// form are reported.
+fn definitions(lines: &[SourceLine]) -> BTreeMap<String, Definition> {
+    let mut found: BTreeMap<String, Definition> = BTreeMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        let text = strip_comment(&line.text);
+        let mut from = 0;
+        while let Some(open) = text[from..].find('(').map(|i| from + i) {
+            from = open + 1;
+            let Some(close) = text[open..].find(')').map(|i| open + i) else {
+                break;
+            };
+            let abbreviation = &text[open + 1..close];
+            if !is_abbreviation(abbreviation) {
+                continue;
+            }
+            let Some(long) = long_form(&text[..open], abbreviation) else {
+                continue;
+            };
+            match found.get(abbreviation) {
+                Some(first) if letters(&first.long) != letters(&long) => eprintln!(
+                    "{}:{}: {} redefined as \"{}\" (first \"{}\")",
+                    line.file.display(),
+                    line.number,
+                    abbreviation,
+                    long,
+                    first.long
+                ),
+                Some(_) => {}
+                None => {
+                    found.insert(
+                        abbreviation.to_string(),
+                        Definition {
+                            long,
+                            position: (index, open),
+                        },
+                    );
+                }
+            }
+        }
+    }
+    found
+}
+
+/// `Thin-layer` → `thinlayer`, for comparing long forms.
+fn letters(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// `TLC`, `HPLC-MS`, `NaDES`: a capital, then letters, digits or hyphens,
+/// with at least two capitals.
+fn is_abbreviation(text: &str) -> bool {
+    let uppercase = text.chars().filter(char::is_ascii_uppercase).count();
+    text.len() <= MAX_LENGTH
+        && uppercase >= 2
+        && text.starts_with(|c: char| c.is_ascii_uppercase())
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// The words before the parenthesis that `abbreviation` abbreviates: its
+/// letters are matched right to left, the first one at the start of a word
+/// (Schwartz and Hearst's algorithm).
+fn long_form(before: &str, abbreviation: &str) -> Option<String> {
+    let clause = before
+        .rfind(['.', ';', ':', '(', '{', '}', '$'])
+        .map_or(before, |i| &before[i + 1..]);
+    let words: Vec<&str> = clause.split_whitespace().collect();
+    let letters: Vec<char> = abbreviation
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let window = (letters.len() + 5).min(letters.len() * 2);
+    let candidate = words[words.len().saturating_sub(window)..].join(" ");
+    if candidate.contains('\\') {
+        return None;
+    }
+
+    let chars: Vec<(usize, char)> = candidate
+        .char_indices()
+        .map(|(i, c)| (i, c.to_ascii_lowercase()))
+        .collect();
+    let mut position = chars.len();
+    for (index, &letter) in letters.iter().enumerate().rev() {
+        loop {
+            position = position.checked_sub(1)?;
+            let (_, c) = chars[position];
+            let word_start = position == 0 || !chars[position - 1].1.is_alphanumeric();
+            if c == letter && (index > 0 || word_start) {
+                break;
+            }
+        }
+    }
+    let long = candidate[chars[position].0..].trim();
+    if long.len() <= abbreviation.len() {
+        return None;
+    }
+    // Capitalised only because it starts a sentence.
+    let mut first = long.chars();
+    Some(match (first.next(), first.next()) {
+        (Some(a), Some(b)) if a.is_uppercase() && b.is_lowercase() => {
+            a.to_lowercase().chain(long.chars().skip(1)).collect()
+        }
+        _ => long.to_string(),
+    })
+}
+
+/// The first use of each defined abbreviation that comes before its
+/// definition.
+fn used_before_definition(
+    lines: &[SourceLine],
+    definitions: &BTreeMap<String, Definition>,
+) -> Vec<(String, (usize, usize))> {
+    let mut early = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        for (offset, word) in words(strip_comment(&line.text)) {
+            let word = match definitions.contains_key(word) {
+                true => word,
+                false => match word.strip_suffix('s') {
+                    Some(singular) if definitions.contains_key(singular) => singular,
+                    _ => continue,
+                },
+            };
+            let definition = &definitions[word];
+            let reported = early.iter().any(|(seen, _)| seen == word);
+            if (index, offset) < definition.position && !reported {
+                early.push((word.to_string(), (index, offset)));
+            }
+        }
+    }
+    early
+}
+
+/// Words of running text with their byte offsets; command names are skipped.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (start, c.is_ascii_alphanumeric()) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                if !text[..s].ends_with('\\') {
+                    words.push((s, &text[s..i]));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+fn render(definitions: &BTreeMap<String, Definition>, main: &Path) -> String {
+    let mut out = format!(
+        "% Abbreviations defined in {}, found by chemtex glossary.\n\
+         \\usepackage[acronym,nomain]{{glossaries}}\n\
+         \\makenoidxglossaries\n",
+        main.display()
+    );
+    for (abbreviation, definition) in definitions {
+        out.push_str(&format!(
+            "\\newacronym{{{}}}{{{}}}{{{}}}\n",
+            abbreviation.to_lowercase(),
+            abbreviation,
+            definition.long
+        ));
+    }
+    out.push_str("\\glsaddall[types=\\acronymtype]\n");
+    out
+}
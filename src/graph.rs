@@ -0,0 +1,276 @@
+//! `chemtex graph`: the include graph a project is packed from, as DOT or
+//! JSON, with every file's fate: packed, missing, left to the TeX
+//! distribution, outside the project (which fails the pack) or not
+//! referenced at all, and so not uploaded.
+
+use crate::batch;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::deps::{DependencyGraph, DependencyKind};
+use crate::pack;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: chemtex graph <main.tex|project_dir> [--dot | --json]
+
+Prints the dependency graph of a document: the files it inputs, the
+graphics, bibliographies and local packages it uses, resolved the way the
+project is packed for upload. Each file says why it is or is not uploaded;
+files of the project directory nothing refers to are listed too. DOT (the
+default) renders with Graphviz, e.g.
+  chemtex graph thesis.tex | dot -Tsvg > graph.svg
+
+Options:
+  --dot    Print Graphviz DOT
+  --json   Print JSON: nodes with a status and a reason, and edges";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Main,
+    Packed,
+    Missing,
+    /// A package or class with no local copy, which the server's TeX
+    /// distribution provides.
+    Distribution,
+    /// Resolved outside the main file's directory, which packing rejects.
+    Outside,
+    Unreferenced,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub status: Status,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: &'static str,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// The graph of `main`, with paths relative to its directory.
+    pub fn build(main: &Path) -> Result<Self> {
+        let root = main.parent().unwrap_or(Path::new(""));
+        let scanned = DependencyGraph::scan(main)?;
+        let main_id = relative(root, main);
+        let mut graph = Self::default();
+        let mut seen = HashSet::new();
+        graph.add(
+            &mut seen,
+            Node {
+                id: main_id,
+                status: Status::Main,
+                reason: "main file".to_string(),
+            },
+        );
+        for dependency in &scanned.dependencies {
+            let from = relative(root, &dependency.from);
+            let kind = dependency.kind.as_str();
+            let node = match &dependency.path {
+                Some(path) => {
+                    let id = relative(root, path);
+                    let outside = Path::new(&id)
+                        .components()
+                        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+                    match outside {
+                        true => Node {
+                            reason: format!(
+                                "{} of {}, outside the project directory: packing fails",
+                                kind, from
+                            ),
+                            status: Status::Outside,
+                            id,
+                        },
+                        false => Node {
+                            reason: format!("{} of {}", kind, from),
+                            status: Status::Packed,
+                            id,
+                        },
+                    }
+                }
+                None => match dependency.kind {
+                    DependencyKind::Package | DependencyKind::Class => {
+                        let extension = match dependency.kind {
+                            DependencyKind::Class => "cls",
+                            _ => "sty",
+                        };
+                        Node {
+                            id: format!("{}.{}", dependency.target, extension),
+                            status: Status::Distribution,
+                            reason: "no local copy: the TeX distribution provides it".to_string(),
+                        }
+                    }
+                    _ => Node {
+                        id: dependency.target.clone(),
+                        status: Status::Missing,
+                        reason: format!("{} of {}, not found: not uploaded", kind, from),
+                    },
+                },
+            };
+            graph.edges.push(Edge {
+                from,
+                to: node.id.clone(),
+                kind,
+            });
+            graph.add(&mut seen, node);
+        }
+
+        // What the project directory holds besides: the working directory
+        // for a main file given without one.
+        let listed = match root.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => root,
+        };
+        for file in pack::directory_files(listed)? {
+            graph.add(
+                &mut seen,
+                Node {
+                    id: relative(listed, &file),
+                    status: Status::Unreferenced,
+                    reason: "nothing refers to it: not uploaded".to_string(),
+                },
+            );
+        }
+        Ok(graph)
+    }
+
+    /// Adds `node` unless one with its id is there already.
+    fn add(&mut self, seen: &mut HashSet<String>, node: Node) {
+        if seen.insert(node.id.clone()) {
+            self.nodes.push(node);
+        }
+    }
+
+    pub fn dot(&self) -> String {
+        let mut dot = String::from("digraph dependencies {\n  rankdir=LR;\n  node [shape=box];\n");
+        for node in &self.nodes {
+            let style = match node.status {
+                Status::Main => "style=bold",
+                Status::Packed => "",
+                Status::Missing => "color=red, style=dashed",
+                Status::Distribution => "color=gray, fontcolor=gray, style=dashed",
+                Status::Outside => "color=orange",
+                Status::Unreferenced => "color=gray, fontcolor=gray",
+            };
+            let separator = if style.is_empty() { "" } else { ", " };
+            dot.push_str(&format!(
+                "  \"{}\" [tooltip=\"{}\"{}{}];\n",
+                escape(&node.id),
+                escape(&node.reason),
+                separator,
+                style
+            ));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!(
+                "  \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                escape(&edge.from),
+                escape(&edge.to),
+                edge.kind
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// `path` relative to `root`, with `/` separators.
+fn relative(root: &Path, path: &Path) -> String {
+    let path = path.strip_prefix(root).unwrap_or(path);
+    let parts: Vec<String> = path
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    parts.join("/")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["dot", "json"], &[])?;
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+    anyhow::ensure!(
+        !(args.flag("dot") && args.flag("json")),
+        "--dot and --json cannot be combined"
+    );
+    let main = match input.is_dir() {
+        true => match ProjectConfig::find(&input)?.and_then(|config| config.main) {
+            Some(main) => input.join(main),
+            None => batch::find_main_document(&input)?,
+        },
+        false => input,
+    };
+    anyhow::ensure!(main.is_file(), "File not found: {}", main.display());
+
+    let graph = Graph::build(&main)?;
+    if args.flag("json") {
+        println!("{}", serde_json::to_string_pretty(&graph)?);
+    } else {
+        print!("{}", graph.dot());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn every_file_says_why_it_is_or_is_not_uploaded() {
+        let dir = std::env::temp_dir().join(format!("chemtex-graph-{}", std::process::id()));
+        fs::create_dir_all(dir.join("chapters")).unwrap();
+        fs::write(
+            dir.join("main.tex"),
+            "\\documentclass{article}\n\\usepackage{amsmath,lab}\n\\begin{document}\n\
+             \\input{chapters/intro}\n\\bibliography{refs}\n\\end{document}\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("chapters/intro.tex"),
+            "\\includegraphics[width=5cm]{flask}\n",
+        )
+        .unwrap();
+        fs::write(dir.join("flask.png"), "").unwrap();
+        fs::write(dir.join("lab.sty"), "").unwrap();
+        fs::write(dir.join("notes.txt"), "").unwrap();
+
+        let graph = Graph::build(&dir.join("main.tex")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let status = |id: &str| {
+            graph
+                .nodes
+                .iter()
+                .find(|node| node.id == id)
+                .map(|node| node.status)
+        };
+        assert_eq!(status("main.tex"), Some(Status::Main));
+        assert_eq!(status("chapters/intro.tex"), Some(Status::Packed));
+        assert_eq!(status("flask.png"), Some(Status::Packed));
+        assert_eq!(status("lab.sty"), Some(Status::Packed));
+        assert_eq!(status("amsmath.sty"), Some(Status::Distribution));
+        assert_eq!(status("article.cls"), Some(Status::Distribution));
+        assert_eq!(status("refs"), Some(Status::Missing));
+        assert_eq!(status("notes.txt"), Some(Status::Unreferenced));
+        assert!(graph
+            .dot()
+            .contains("\"chapters/intro.tex\" -> \"flask.png\" [label=\"graphics\"];"));
+    }
+}
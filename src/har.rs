@@ -0,0 +1,360 @@
+//! `--capture-http session.har`: every request to the compile server and
+//! its response, in the HTTP Archive format browsers' developer tools and
+//! HAR viewers read, to diagnose and report server-side problems.
+//!
+//! Uploaded sources are never included (the upload is described by name,
+//! size and hash), credentials are masked (see [`crate::redact`]), and
+//! response bodies are truncated unless `--capture-bodies full` is given.
+//! The file is rewritten after each exchange, so an interrupted run leaves
+//! a valid one.
+
+use crate::cli::Args;
+use crate::fixtures::Upload;
+use crate::redact;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{SecondsFormat, Utc};
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+/// Text bodies longer than this are cut in `truncated` mode.
+const TRUNCATED_BYTES: usize = 64 << 10;
+
+static CAPTURE: OnceLock<Mutex<Capture>> = OnceLock::new();
+
+/// How much of the response bodies goes into the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bodies {
+    /// Everything; binary bodies (PDFs) base64-encoded.
+    Full,
+    /// Text up to 64 KiB, no binary bodies.
+    Truncated,
+    /// Sizes and types only.
+    None,
+}
+
+impl Bodies {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "full" => Ok(Self::Full),
+            "truncated" => Ok(Self::Truncated),
+            "none" => Ok(Self::None),
+            _ => anyhow::bail!(
+                "Unknown --capture-bodies value: {} (expected full, truncated or none)",
+                value
+            ),
+        }
+    }
+}
+
+struct Capture {
+    path: PathBuf,
+    bodies: Bodies,
+    entries: Vec<Entry>,
+}
+
+/// Applies `--capture-http FILE` and `--capture-bodies MODE` of a compiling
+/// command.
+pub fn configure(args: &Args) -> Result<()> {
+    let bodies = args
+        .value("capture-bodies")
+        .map(Bodies::parse)
+        .transpose()?;
+    match args.value("capture-http") {
+        Some(path) => start(PathBuf::from(path), bodies.unwrap_or(Bodies::Truncated)),
+        None if bodies.is_some() => anyhow::bail!("--capture-bodies needs --capture-http FILE"),
+        None => Ok(()),
+    }
+}
+
+/// Captures the exchanges of this process into `path`.
+pub fn start(path: PathBuf, bodies: Bodies) -> Result<()> {
+    let capture = Capture {
+        path,
+        bodies,
+        entries: Vec::new(),
+    };
+    capture.save()?;
+    CAPTURE
+        .set(Mutex::new(capture))
+        .map_err(|_| anyhow::anyhow!("HTTP capture is already running"))
+}
+
+pub fn capturing() -> bool {
+    CAPTURE.get().is_some()
+}
+
+/// A request on its way, noted when it is sent.
+pub struct Exchange {
+    started: String,
+    clock: Instant,
+    request: Request,
+}
+
+/// Notes a request about to be sent, if a capture is running.
+pub fn begin(request: &reqwest::Request, upload: Option<&Upload>) -> Option<Exchange> {
+    CAPTURE.get()?;
+    let url = request.url();
+    let post_data = upload.map(|upload| PostData {
+        mime_type: header(request.headers(), "content-type")
+            .unwrap_or_else(|| "multipart/form-data".to_string()),
+        text: format!(
+            "[not captured: {}, {} bytes, sha256 {}]",
+            upload.file_name, upload.bytes, upload.sha256
+        ),
+    });
+    Some(Exchange {
+        started: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        clock: Instant::now(),
+        request: Request {
+            method: request.method().to_string(),
+            url: redact::url(url),
+            http_version: "HTTP/1.1".to_string(),
+            headers: headers(request.headers()),
+            query_string: url
+                .query_pairs()
+                .map(|(name, value)| Header {
+                    value: redact::query_value(&name, &value),
+                    name: name.into_owned(),
+                })
+                .collect(),
+            body_size: header(request.headers(), "content-length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(0),
+            post_data,
+            headers_size: -1,
+        },
+    })
+}
+
+impl Exchange {
+    /// Adds the response to the archive.
+    pub fn response(
+        self,
+        status: reqwest::StatusCode,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<()> {
+        let mime_type = header(headers, "content-type").unwrap_or_default();
+        self.finish(|bodies| Response {
+            status: status.as_u16(),
+            status_text: status.canonical_reason().unwrap_or_default().to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            headers: self::headers(headers),
+            content: content(body, mime_type, bodies),
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: body.len() as i64,
+            error: None,
+        })
+    }
+
+    /// Adds a request that got no response, with the reason.
+    pub fn failed(self, error: &dyn std::fmt::Display) -> Result<()> {
+        let error = redact::text(&error.to_string()).into_owned();
+        self.finish(|_| Response {
+            status: 0,
+            status_text: String::new(),
+            http_version: String::new(),
+            headers: Vec::new(),
+            content: Content {
+                size: 0,
+                mime_type: String::new(),
+                text: None,
+                encoding: None,
+                comment: None,
+            },
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: -1,
+            error: Some(error),
+        })
+    }
+
+    fn finish(self, response: impl FnOnce(Bodies) -> Response) -> Result<()> {
+        let Some(capture) = CAPTURE.get() else {
+            return Ok(());
+        };
+        let mut capture = capture.lock().unwrap_or_else(|e| e.into_inner());
+        let time = self.clock.elapsed().as_secs_f64() * 1000.0;
+        let response = response(capture.bodies);
+        capture.entries.push(Entry {
+            started_date_time: self.started,
+            time,
+            request: self.request,
+            response,
+            cache: Cache {},
+            timings: Timings {
+                send: 0.0,
+                wait: time,
+                receive: 0.0,
+            },
+        });
+        capture.save()
+    }
+}
+
+impl Capture {
+    fn save(&self) -> Result<()> {
+        let har = Har {
+            log: Log {
+                version: "1.2",
+                creator: Creator {
+                    name: "chemtex",
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                entries: &self.entries,
+            },
+        };
+        let json = serde_json::to_string_pretty(&har)?;
+        fs::write(&self.path, json)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+fn content(body: &[u8], mime_type: String, bodies: Bodies) -> Content {
+    let mut content = Content {
+        size: body.len() as i64,
+        mime_type,
+        text: None,
+        encoding: None,
+        comment: None,
+    };
+    match (std::str::from_utf8(body), bodies) {
+        (_, Bodies::None) => {}
+        (Ok(text), Bodies::Full) => content.text = Some(redact::text(text).into_owned()),
+        (Err(_), Bodies::Full) => {
+            content.text = Some(STANDARD.encode(body));
+            content.encoding = Some("base64");
+        }
+        (Ok(text), Bodies::Truncated) if text.len() > TRUNCATED_BYTES => {
+            let mut end = TRUNCATED_BYTES;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            content.text = Some(redact::text(&text[..end]).into_owned());
+            content.comment = Some(format!("truncated to {} of {} bytes", end, text.len()));
+        }
+        (Ok(text), Bodies::Truncated) => content.text = Some(redact::text(text).into_owned()),
+        (Err(_), Bodies::Truncated) => {
+            content.comment = Some("binary body not captured".to_string());
+        }
+    }
+    content
+}
+
+fn headers(map: &HeaderMap) -> Vec<Header> {
+    map.iter()
+        .map(|(name, value)| Header {
+            name: name.to_string(),
+            value: redact::header(name.as_str(), &String::from_utf8_lossy(value.as_bytes())),
+        })
+        .collect()
+}
+
+fn header(map: &HeaderMap, name: &str) -> Option<String> {
+    map.get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+#[derive(Serialize)]
+struct Har<'a> {
+    log: Log<'a>,
+}
+
+#[derive(Serialize)]
+struct Log<'a> {
+    version: &'static str,
+    creator: Creator,
+    entries: &'a [Entry],
+}
+
+#[derive(Serialize)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    started_date_time: String,
+    time: f64,
+    request: Request,
+    response: Response,
+    cache: Cache,
+    timings: Timings,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    method: String,
+    url: String,
+    http_version: String,
+    headers: Vec<Header>,
+    query_string: Vec<Header>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<PostData>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PostData {
+    mime_type: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    headers: Vec<Header>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+    /// Why there is no response (a custom field, hence the underscore).
+    #[serde(rename = "_error", skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct Cache {}
+
+#[derive(Serialize)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
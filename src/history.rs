@@ -0,0 +1,280 @@
+use crate::cli::Args;
+use crate::job::JobReport;
+use crate::metrics;
+use crate::report::DocumentReport;
+use crate::storage;
+use crate::vault;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+Usage: chemtex history export [--out FILE]
+       chemtex history import FILE
+
+Copies the history of finished jobs between machines: `export` writes every
+entry as a JSON array (to stdout without --out), and `import` adds the
+entries of such a file, skipping those already recorded.";
+
+/// One finished job, recorded in `<data dir>/history.db`, a SQLite database
+/// in WAL mode, so that the daemon, watchers and single compiles can all
+/// write at once.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Unix time the job finished.
+    pub finished_at: u64,
+    /// Coarse failure class (see `metrics`), for failed jobs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_class: Option<String>,
+    #[serde(flatten)]
+    pub document: DocumentReport,
+}
+
+pub fn record(report: &JobReport) -> Result<()> {
+    let mut document = DocumentReport::from(report);
+    // Compile logs can be large and are already linked by `log_url`.
+    document.diagnostics.clear();
+    let entry = HistoryEntry {
+        finished_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        failure_class: match &report.result {
+            Err(e) if !report.cancelled => Some(metrics::failure_class(e).to_string()),
+            _ => None,
+        },
+        document,
+    };
+    store::insert(&[entry])?;
+    Ok(())
+}
+
+/// Every recorded job, oldest first.
+pub fn load() -> Result<Vec<HistoryEntry>> {
+    store::load()
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["help"], &["out"])?;
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    match (args.positional(0), args.positional(1)) {
+        (Some("export"), None) => {
+            let json = serde_json::to_string_pretty(&load()?)?;
+            match args.value("out") {
+                Some(out) => {
+                    fs::write(out, json + "\n")
+                        .with_context(|| format!("Failed to write {}", out))?;
+                }
+                None => println!("{}", json),
+            }
+            Ok(())
+        }
+        (Some("import"), Some(file)) => {
+            let text =
+                fs::read_to_string(file).with_context(|| format!("Failed to read {}", file))?;
+            let entries: Vec<HistoryEntry> = serde_json::from_str(&text)
+                .with_context(|| format!("{} is not an exported history", file))?;
+            let added = store::insert(&entries)?;
+            println!(
+                "Imported {} entries ({} already recorded)",
+                added,
+                entries.len() - added
+            );
+            Ok(())
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+fn data_path(name: &str) -> Result<PathBuf> {
+    let dir = storage::data_dir()?;
+    storage::ensure_dir(&dir)?;
+    Ok(dir.join(name))
+}
+
+/// Lines of the history file used before the database. Lines that do not parse (a truncated write, an
+/// entry from a newer version) are skipped.
+fn read_lines(path: &std::path::Path) -> Result<Vec<HistoryEntry>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read history: {}", path.display()))
+        }
+    };
+    Ok(parse_entries(text.lines()))
+}
+
+/// Parses stored entries, opening encrypted ones (see [`vault`]). Entries
+/// that do not parse are skipped; those that do not decrypt too, with a
+/// warning.
+fn parse_entries<'a>(stored: impl Iterator<Item = &'a str>) -> Vec<HistoryEntry> {
+    let mut sealed = None;
+    let mut locked = 0;
+    let entries = stored
+        .filter_map(|text| match vault::open_text(text) {
+            Ok(json) => serde_json::from_str(&json).ok(),
+            Err(e) => {
+                sealed.get_or_insert(e);
+                locked += 1;
+                None
+            }
+        })
+        .collect();
+    if let Some(e) = sealed {
+        eprintln!(
+            "Warning: {} history entries could not be decrypted: {:#}",
+            locked, e
+        );
+    }
+    entries
+}
+
+const LINES_FILE: &str = "history.jsonl";
+
+mod store {
+    use super::{data_path, parse_entries, read_lines, HistoryEntry, LINES_FILE};
+    use crate::cache;
+    use crate::vault;
+    use anyhow::{Context, Result};
+    use rusqlite::{params, Connection, TransactionBehavior};
+    use sha2::{Digest, Sha256};
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+
+    const DATABASE_FILE: &str = "history.db";
+    /// How long a writer waits for another to finish.
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Entries are kept as their JSON (encrypted when that is on), keyed by
+    /// the hash of the JSON, so an entry imported twice (or migrated by two
+    /// processes at once) is stored once.
+    const SCHEMA: &str = "
+        pragma journal_mode = wal;
+        pragma synchronous = normal;
+        create table if not exists history (
+            id integer primary key,
+            finished_at integer not null,
+            digest text not null unique,
+            entry text not null
+        );";
+
+    /// Adds the entries not recorded yet, returning how many were new.
+    pub fn insert(entries: &[HistoryEntry]) -> Result<usize> {
+        let mut db = open(&data_path(DATABASE_FILE)?, &data_path(LINES_FILE)?)?;
+        insert_into(&mut db, entries)
+    }
+
+    pub fn load() -> Result<Vec<HistoryEntry>> {
+        let db = open(&data_path(DATABASE_FILE)?, &data_path(LINES_FILE)?)?;
+        load_from(&db)
+    }
+
+    fn load_from(db: &Connection) -> Result<Vec<HistoryEntry>> {
+        let mut statement = db.prepare("select entry from history order by finished_at, id")?;
+        let stored = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .context("Failed to read history")?;
+        Ok(parse_entries(stored.iter().map(String::as_str)))
+    }
+
+    fn insert_into(db: &mut Connection, entries: &[HistoryEntry]) -> Result<usize> {
+        // Rolled back on drop unless committed.
+        let transaction = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut inserted = 0;
+        {
+            let mut statement = transaction.prepare(
+                "insert or ignore into history (finished_at, digest, entry) values (?, ?, ?)",
+            )?;
+            for entry in entries {
+                let json = serde_json::to_string(entry)?;
+                let digest = cache::to_hex(&Sha256::digest(json.as_bytes()));
+                inserted += statement.execute(params![
+                    entry.finished_at as i64,
+                    digest,
+                    vault::seal_text(json)?
+                ])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(inserted)
+    }
+
+    /// Opens the database at `path`, moving the entries of the `lines` file
+    /// into it the first time.
+    fn open(path: &Path, lines: &Path) -> Result<Connection> {
+        let mut db =
+            Connection::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        db.busy_timeout(BUSY_TIMEOUT)?;
+        db.execute_batch(SCHEMA)
+            .with_context(|| format!("Failed to set up {}", path.display()))?;
+        if lines.exists() {
+            insert_into(&mut db, &read_lines(lines)?)?;
+            match fs::rename(lines, lines.with_extension("jsonl.imported")) {
+                Ok(()) => {}
+                // Migrated by another process meanwhile.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to move {}", lines.display()))
+                }
+            }
+        }
+        Ok(db)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn entry(finished_at: u64, name: &str) -> HistoryEntry {
+            serde_json::from_value(serde_json::json!({
+                "finished_at": finished_at,
+                "name": name,
+                "input": format!("{}.tex", name),
+                "output": format!("{}.pdf", name),
+                "status": "ok",
+                "elapsed_ms": 1200,
+            }))
+            .unwrap()
+        }
+
+        #[test]
+        fn entries_are_stored_once_in_order_and_migrated_from_lines() {
+            let dir = std::env::temp_dir().join(format!("chemtex-history-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let (path, lines) = (dir.join(DATABASE_FILE), dir.join(LINES_FILE));
+            fs::write(
+                &lines,
+                format!(
+                    "{}\nnot json\n",
+                    serde_json::to_string(&entry(5, "old")).unwrap()
+                ),
+            )
+            .unwrap();
+
+            let mut db = open(&path, &lines).unwrap();
+            let added = insert_into(&mut db, &[entry(9, "late"), entry(7, "early")]).unwrap();
+            let again = insert_into(&mut db, &[entry(7, "early"), entry(5, "old")]).unwrap();
+            drop(db);
+            // A second connection sees the same rows, and nothing to migrate.
+            let names: Vec<String> = load_from(&open(&path, &lines).unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.document.name)
+                .collect();
+            let migrated = dir.join("history.jsonl.imported").exists();
+            fs::remove_dir_all(&dir).unwrap();
+
+            assert_eq!((added, again), (2, 0));
+            assert_eq!(names, ["old", "early", "late"]);
+            assert!(migrated);
+        }
+    }
+}
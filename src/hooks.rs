@@ -0,0 +1,146 @@
+use crate::job::{Job, JobReport};
+use crate::progress::Progress;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Local commands run around every compile, declared in a manifest:
+///
+/// ```yaml
+/// hooks:
+///   pre_compile: python plots/render.py
+///   post_compile:
+///     - cp "$CHEMTEX_OUTPUT" ~/Shared/
+/// ```
+///
+/// or in the `[hooks]` of a project's `.chemtex.toml`, which every compile
+/// of the project runs; a manifest's hooks take the place of its jobs'.
+/// Commands run through the shell from the manifest's (or project's)
+/// directory with the job described in `CHEMTEX_*` variables. A failing
+/// `pre_compile` command fails the job; `post_compile` failures are only
+/// reported.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub pre_compile: Vec<String>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub post_compile: Vec<String>,
+    /// Working directory of the commands.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.pre_compile.is_empty() && self.post_compile.is_empty()
+    }
+
+    /// The hooks, run from `dir`.
+    pub fn in_dir(self, dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            ..self
+        }
+    }
+
+    pub async fn pre_compile(&self, job: &Job, progress: &Progress, label: &str) -> Result<()> {
+        for command in &self.pre_compile {
+            progress.log(label, format!("Running pre-compile hook: {}", command));
+            self.run(command, job_env(job))
+                .await
+                .with_context(|| format!("Pre-compile hook failed: {}", command))?;
+        }
+        Ok(())
+    }
+
+    pub async fn post_compile(&self, report: &JobReport, progress: &Progress, label: &str) {
+        let mut env = job_env(&report.job);
+        let status = match &report.result {
+            Ok(_) => "success",
+            Err(_) if report.cancelled => "cancelled",
+            Err(_) => "failure",
+        };
+        env.push(("CHEMTEX_STATUS", status.to_string()));
+        env.push((
+            "CHEMTEX_TASK_ID",
+            report.details.task_id.clone().unwrap_or_default(),
+        ));
+        env.push(("CHEMTEX_ELAPSED_MS", report.elapsed.as_millis().to_string()));
+        if let Err(e) = &report.result {
+            env.push(("CHEMTEX_ERROR", format!("{:#}", e)));
+        }
+
+        for command in &self.post_compile {
+            progress.log(label, format!("Running post-compile hook: {}", command));
+            if let Err(e) = self.run(command, env.clone()).await {
+                progress.log(
+                    label,
+                    format!("Post-compile hook failed: {}: {:#}", command, e),
+                );
+            }
+        }
+    }
+
+    async fn run(&self, command: &str, env: Vec<(&str, String)>) -> Result<()> {
+        let mut process = if cfg!(windows) {
+            let mut process = Command::new("cmd");
+            process.args(["/C", command]);
+            process
+        } else {
+            let mut process = Command::new("sh");
+            process.args(["-c", command]);
+            process
+        };
+        if !self.dir.as_os_str().is_empty() {
+            process.current_dir(&self.dir);
+        }
+        let status = process
+            .envs(env)
+            .status()
+            .await
+            .context("Failed to start hook")?;
+        if !status.success() {
+            anyhow::bail!("exited with {}", status);
+        }
+        Ok(())
+    }
+}
+
+fn job_env(job: &Job) -> Vec<(&'static str, String)> {
+    vec![
+        ("CHEMTEX_JOB", job.name.clone()),
+        ("CHEMTEX_INPUT", absolute(&job.input)),
+        ("CHEMTEX_OUTPUT", absolute(&job.output)),
+        (
+            "CHEMTEX_ENGINE",
+            job.options.engine.clone().unwrap_or_default(),
+        ),
+        (
+            "CHEMTEX_PROFILE",
+            job.options.profile.clone().unwrap_or_default(),
+        ),
+    ]
+}
+
+fn absolute(path: &Path) -> String {
+    std::path::absolute(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// Accepts a single command or a list of commands.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(command) => vec![command],
+        OneOrMany::Many(commands) => commands,
+    })
+}
@@ -0,0 +1,1878 @@
+use crate::deps::strip_comment;
+use crate::languages;
+use crate::mathml;
+use crate::preprocess;
+use crate::render;
+use crate::schemes::Schemes;
+use crate::smiles;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+/// MathJax 3 typesets the math, `\ce` included, in the browser.
+const MATHJAX: &str = "\
+<script>
+MathJax = {
+  loader: {load: ['[tex]/mhchem']},
+  tex: {packages: {'[+]': ['mhchem']}, tags: 'ams'}
+};
+</script>
+<script async src=\"https://cdn.jsdelivr.net/npm/mathjax@3/es5/tex-chtml.js\"></script>";
+
+const STYLE: &str = "\
+<style>
+body { max-width: 48em; margin: 2em auto; padding: 0 1em; font: 17px/1.55 Georgia, serif; color: #222; }
+header { text-align: center; margin-bottom: 2em; }
+figure, .center { text-align: center; }
+figure img { max-width: 100%; }
+figcaption, caption { font-size: 0.92em; margin: 0.5em 0; }
+table { border-collapse: collapse; margin: 1em auto; }
+th, td { padding: 0.25em 0.8em; }
+thead th { border-top: 2px solid #222; border-bottom: 1px solid #222; }
+tbody tr:last-child td { border-bottom: 2px solid #222; }
+.math { overflow-x: auto; }
+.unsupported { border: 1px dashed #999; color: #666; padding: 0.5em; font-size: 0.9em; }
+.footnotes { font-size: 0.9em; border-top: 1px solid #ccc; margin-top: 2em; }
+</style>";
+
+/// Sectioning commands, outermost first.
+const SECTIONS: &[&str] = &[
+    "part",
+    "chapter",
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+];
+
+/// Math environments passed to MathJax as they are.
+const MATH_ENVIRONMENTS: &[&str] = &[
+    "equation",
+    "equation*",
+    "align",
+    "align*",
+    "gather",
+    "gather*",
+    "multline",
+    "multline*",
+    "flalign",
+    "flalign*",
+    "eqnarray",
+    "eqnarray*",
+    "displaymath",
+];
+
+/// Pictures drawn by TeX itself, which have no HTML counterpart.
+const DRAWINGS: &[&str] = &["tikzpicture", "scheme", "pspicture", "picture", "axis"];
+
+/// Commands dropped together with this many braced arguments.
+const DROPPED: &[(&str, usize)] = &[
+    ("noindent", 0),
+    ("centering", 0),
+    ("raggedright", 0),
+    ("raggedleft", 0),
+    ("newpage", 0),
+    ("clearpage", 0),
+    ("cleardoublepage", 0),
+    ("pagebreak", 0),
+    ("nopagebreak", 0),
+    ("linebreak", 0),
+    ("smallskip", 0),
+    ("medskip", 0),
+    ("bigskip", 0),
+    ("vfill", 0),
+    ("hfill", 0),
+    ("tiny", 0),
+    ("scriptsize", 0),
+    ("footnotesize", 0),
+    ("small", 0),
+    ("normalsize", 0),
+    ("large", 0),
+    ("Large", 0),
+    ("LARGE", 0),
+    ("huge", 0),
+    ("Huge", 0),
+    ("bfseries", 0),
+    ("itshape", 0),
+    ("normalfont", 0),
+    ("phantomsection", 0),
+    ("printindex", 0),
+    ("printbibliography", 0),
+    ("printnoidxglossary", 0),
+    ("listoffigures", 0),
+    ("listoftables", 0),
+    ("appendix", 0),
+    ("frontmatter", 0),
+    ("mainmatter", 0),
+    ("backmatter", 0),
+    ("vspace", 1),
+    ("hspace", 1),
+    ("index", 1),
+    ("pagestyle", 1),
+    ("thispagestyle", 1),
+    ("bibliographystyle", 1),
+    ("bibliography", 1),
+    ("addbibresource", 1),
+    ("pageref", 1),
+    ("setcounter", 2),
+    ("setlength", 2),
+    ("addtocounter", 2),
+    ("addcontentsline", 3),
+];
+
+/// Text commands and the HTML elements they become.
+const FORMATTING: &[(&str, &str)] = &[
+    ("textbf", "b"),
+    ("emph", "em"),
+    ("textit", "i"),
+    ("textsl", "i"),
+    ("underline", "u"),
+    ("texttt", "code"),
+    ("textsc", "span"),
+    ("textrm", "span"),
+    ("textsf", "span"),
+    ("textnormal", "span"),
+    ("mbox", "span"),
+    ("textsubscript", "sub"),
+    ("textsuperscript", "sup"),
+];
+
+/// Symbol commands in running text.
+const SYMBOLS: &[(&str, &str)] = &[
+    ("LaTeX", "LaTeX"),
+    ("TeX", "TeX"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("textbackslash", "\\"),
+    ("textasciitilde", "~"),
+    ("textasciicircum", "^"),
+    ("textdegree", "°"),
+    ("degree", "°"),
+    ("celsius", "°C"),
+    ("textpm", "±"),
+    ("texttimes", "×"),
+    ("textendash", "–"),
+    ("textemdash", "—"),
+    ("quad", "\u{2003}"),
+    ("qquad", "\u{2003}\u{2003}"),
+    ("guillemotleft", "«"),
+    ("guillemotright", "»"),
+    ("copyright", "©"),
+    ("S", "§"),
+];
+
+/// siunitx unit macros.
+const UNITS: &[(&str, &str)] = &[
+    ("celsius", "°C"),
+    ("degreeCelsius", "°C"),
+    ("degree", "°"),
+    ("kelvin", "K"),
+    ("percent", "%"),
+    ("mole", "mol"),
+    ("molar", "M"),
+    ("gram", "g"),
+    ("litre", "L"),
+    ("liter", "L"),
+    ("metre", "m"),
+    ("meter", "m"),
+    ("second", "s"),
+    ("minute", "min"),
+    ("hour", "h"),
+    ("joule", "J"),
+    ("pascal", "Pa"),
+    ("bar", "bar"),
+    ("atmosphere", "atm"),
+    ("volt", "V"),
+    ("ampere", "A"),
+    ("coulomb", "C"),
+    ("watt", "W"),
+    ("hertz", "Hz"),
+    ("newton", "N"),
+    ("ohm", "Ω"),
+    ("siemens", "S"),
+    ("angstrom", "Å"),
+    ("dalton", "Da"),
+    ("ppm", "ppm"),
+    ("electronvolt", "eV"),
+    ("calorie", "cal"),
+    ("nano", "n"),
+    ("micro", "µ"),
+    ("milli", "m"),
+    ("centi", "c"),
+    ("deci", "d"),
+    ("kilo", "k"),
+    ("mega", "M"),
+];
+
+/// A document's sources: the packed archive, or a single `.tex` file and
+/// the files next to it.
+pub struct Sources {
+    files: HashMap<String, Vec<u8>>,
+    dir: Option<PathBuf>,
+    main: String,
+}
+
+impl Sources {
+    /// Reads what was uploaded: a packed project or a single `.tex` file,
+    /// the latter prepared the way a single upload is.
+    pub fn read(input: &Path) -> Result<Self> {
+        let name = input
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid file name")?;
+        if name.ends_with(".zip") {
+            let file =
+                File::open(input).with_context(|| format!("Failed to open {}", input.display()))?;
+            let mut zip = ZipArchive::new(file)
+                .with_context(|| format!("{} is not a zip archive", input.display()))?;
+            let mut files = HashMap::new();
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index(index)?;
+                let mut bytes = Vec::new();
+                entry.read_to_end(&mut bytes)?;
+                files.insert(entry.name().to_string(), bytes);
+            }
+            let stem = name.trim_end_matches(".zip");
+            let main = match files.contains_key(&format!("{}.tex", stem)) {
+                true => format!("{}.tex", stem),
+                false => files
+                    .iter()
+                    .filter(|(name, _)| name.ends_with(".tex"))
+                    .find(|(_, bytes)| {
+                        let text = String::from_utf8_lossy(bytes);
+                        text.contains("\\documentclass") && text.contains("\\begin{document}")
+                    })
+                    .map(|(name, _)| name.clone())
+                    .with_context(|| format!("No main document in {}", input.display()))?,
+            };
+            return Ok(Self {
+                files,
+                dir: None,
+                main,
+            });
+        }
+        let text = fs::read_to_string(input)
+            .with_context(|| format!("Failed to read file: {}", input.display()))?;
+        let dir = input.parent().unwrap_or(Path::new(""));
+        let text = Schemes::from_text(&text, input)?.rewrite(&text)?;
+        let text = preprocess::expand(&text, dir)?;
+        Ok(Self {
+            files: HashMap::from([(name.to_string(), text.into_bytes())]),
+            dir: Some(dir.to_path_buf()),
+            main: name.to_string(),
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<Vec<u8>> {
+        let name = name.trim_start_matches("./");
+        if let Some(bytes) = self.files.get(name) {
+            return Some(bytes.clone());
+        }
+        fs::read(self.dir.as_ref()?.join(name)).ok()
+    }
+
+    /// A `.tex` file by the name `\input` gives it.
+    fn tex(&self, name: &str) -> Option<String> {
+        let bytes = self
+            .get(name)
+            .filter(|_| name.ends_with(".tex"))
+            .or_else(|| self.get(&format!("{}.tex", name)))?;
+        Some(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Where the HTML is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// A web page: MathJax typesets the math and images are embedded.
+    Web,
+    /// An EPUB chapter: math is MathML, images are separate files, and
+    /// what neither covers is left to [`Document::fill_fallbacks`].
+    Epub,
+}
+
+/// An image file of an EPUB.
+pub struct Image {
+    /// Path inside the book, relative to the chapter.
+    pub name: String,
+    pub media_type: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+/// A numbered heading, for tables of contents.
+pub struct Heading {
+    pub depth: usize,
+    pub id: String,
+    pub number: String,
+    pub title: String,
+}
+
+/// LaTeX an EPUB shows as a rendered image, if it can be rendered.
+pub struct Fallback {
+    pub source: String,
+    /// HTML shown when there is no image.
+    pub text: String,
+    pub display: bool,
+}
+
+/// A document converted to HTML.
+pub struct Document {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+    /// The body as HTML, title block and footnotes included.
+    pub body: String,
+    /// The main language babel or polyglossia sets, `en` when neither is
+    /// loaded, for the `lang` attribute.
+    pub language: &'static str,
+    /// The preamble without comments, for rendering fallbacks.
+    pub preamble: String,
+    pub toc: Vec<Heading>,
+    pub images: Vec<Image>,
+    /// Placeholders in the body, for [`Target::Epub`].
+    pub fallbacks: Vec<Fallback>,
+    /// Constructs left out, with counts.
+    pub unsupported: BTreeMap<String, usize>,
+}
+
+impl Document {
+    /// Replaces the fallback placeholders with `images`, one per fallback,
+    /// or with their text when there are none.
+    pub fn fill_fallbacks(&mut self, images: Option<Vec<Vec<u8>>>) {
+        let mut names = Vec::new();
+        if let Some(images) = images {
+            for (index, bytes) in images.into_iter().enumerate() {
+                let name = format!("images/fallback-{}.png", index + 1);
+                names.push(name.clone());
+                self.images.push(Image {
+                    name,
+                    media_type: "image/png",
+                    bytes,
+                });
+            }
+        }
+        let mut out = String::with_capacity(self.body.len());
+        let mut rest = self.body.as_str();
+        while let Some(start) = rest.find("\u{0}fallback:") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + "\u{0}fallback:".len()..];
+            let end = after.find('\u{0}').unwrap_or(after.len());
+            let index: usize = after[..end].parse().unwrap_or(0);
+            if let Some(fallback) = self.fallbacks.get(index) {
+                match names.get(index) {
+                    Some(name) => out.push_str(&format!(
+                        "<img class=\"{}\" src=\"{}\" alt=\"{}\"/>",
+                        if fallback.display {
+                            "display"
+                        } else {
+                            "inline"
+                        },
+                        name,
+                        escape(&fallback.source)
+                    )),
+                    None => out.push_str(&fallback.text),
+                }
+            }
+            rest = after.get(end + 1..).unwrap_or("");
+        }
+        out.push_str(rest);
+        self.body = out;
+    }
+}
+
+/// `--also-html`: writes an HTML version of the document uploaded as
+/// `input` to `output`, for the subset of LaTeX the templates use. Math and
+/// `\ce` formulas are typeset by MathJax; images are embedded.
+pub fn export(input: &Path, output: &Path) -> Result<()> {
+    let sources = Sources::read(input)?;
+    let document = convert(&sources, Target::Web)?;
+    fs::write(output, page(&document))
+        .with_context(|| format!("Failed to write file: {}", output.display()))?;
+    println!("HTML saved to: {}", output.display());
+    if !document.unsupported.is_empty() {
+        let left_out: Vec<String> = document
+            .unsupported
+            .iter()
+            .map(|(what, count)| format!("{} {}", count, what))
+            .collect();
+        eprintln!("Not in the HTML version: {}", left_out.join(", "));
+    }
+    Ok(())
+}
+
+/// Converts the main document of `sources`.
+pub fn convert(sources: &Sources, target: Target) -> Result<Document> {
+    let text = sources.tex(&sources.main).context("No main document")?;
+    let text = tag_structures(&inline_inputs(&text, sources, 0));
+    let (preamble, body) = text
+        .split_once("\\begin{document}")
+        .context("No \\begin{document}")?;
+    let body = body.split("\\end{document}").next().unwrap_or(body);
+    let preamble = without_comments(preamble);
+    let body = without_comments(body);
+
+    let mut converter = Converter::new(sources, target, &body);
+    let field = |converter: &mut Converter, command: &str| {
+        preamble
+            .find(&format!("\\{}", command))
+            .and_then(|i| argument(&preamble[i + command.len() + 1..]))
+            .map(|(value, _)| converter.inline(value).trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    converter.title = field(&mut converter, "title");
+    converter.author = field(&mut converter, "author");
+    converter.date = match field(&mut converter, "date") {
+        Some(date) => Some(date),
+        None => Some(chrono::Local::now().format("%Y-%m-%d").to_string()),
+    };
+
+    let html = converter.blocks(&body);
+    let mut html = converter.resolve(&html);
+    if !converter.footnotes.is_empty() {
+        html.push_str("<section class=\"footnotes\">\n<ol>\n");
+        for (index, note) in converter.footnotes.iter().enumerate() {
+            html.push_str(&format!(
+                "<li id=\"fn{0}\">{1} <a href=\"#fnref{0}\">↩</a></li>\n",
+                index + 1,
+                note
+            ));
+        }
+        html.push_str("</ol>\n</section>\n");
+    }
+    Ok(Document {
+        title: converter.title.take(),
+        author: converter.author.take(),
+        date: converter.date.take(),
+        body: html,
+        language: languages::declared(&preamble).unwrap_or("en"),
+        preamble,
+        toc: std::mem::take(&mut converter.toc),
+        images: std::mem::take(&mut converter.images),
+        fallbacks: std::mem::take(&mut converter.fallbacks),
+        unsupported: std::mem::take(&mut converter.unsupported),
+    })
+}
+
+/// A standalone page around a converted document.
+pub fn page(document: &Document) -> String {
+    let title = document
+        .title
+        .as_deref()
+        .map(strip_tags)
+        .unwrap_or_else(|| "Document".to_string());
+    let mut meta = String::new();
+    if let Some(author) = &document.author {
+        meta.push_str(&format!(
+            "<meta name=\"author\" content=\"{}\">\n",
+            strip_tags(author).replace('"', "&quot;")
+        ));
+    }
+    if let Some(date) = &document.date {
+        meta.push_str(&format!(
+            "<meta name=\"date\" content=\"{}\">\n",
+            strip_tags(date).replace('"', "&quot;")
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         {}<title>{}</title>\n{}\n{}\n</head>\n<body>\n<main>\n{}</main>\n</body>\n</html>\n",
+        document.language, meta, title, MATHJAX, STYLE, document.body
+    )
+}
+
+/// Replaces `\input` and `\include` with the files' text.
+fn inline_inputs(text: &str, sources: &Sources, depth: usize) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let code = strip_comment(line);
+        let found = ["\\input{", "\\include{", "\\subfile{"]
+            .iter()
+            .find_map(|command| code.find(command).map(|i| (i, command.len())));
+        let Some((start, length)) = found else {
+            out.push_str(line);
+            continue;
+        };
+        let Some(end) = code[start..].find('}').map(|i| start + i) else {
+            out.push_str(line);
+            continue;
+        };
+        let name = code[start + length..end].trim();
+        match sources.tex(name).filter(|_| depth < 16) {
+            Some(included) => {
+                out.push_str(&code[..start]);
+                out.push('\n');
+                out.push_str(&inline_inputs(&included, sources, depth + 1));
+                out.push('\n');
+                out.push_str(&line[end + 1..]);
+            }
+            None => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// Drops comments; like TeX, a comment also swallows its line break.
+fn without_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        out.push_str(strip_comment(line));
+    }
+    out
+}
+
+/// Marks structures `chemtex smiles` wrote, which carry their SMILES in a
+/// comment, so they can be drawn without TeX.
+fn tag_structures(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let code = strip_comment(line);
+        let tag = line[code.len()..].strip_prefix(smiles::GENERATED_TAG);
+        match tag {
+            Some(smiles) if code.trim_start().starts_with("\\chemfig") => {
+                out.push_str(&format!("\\chemtexsmiles{{{}}}\n", smiles.trim()));
+            }
+            _ => out.push_str(line),
+        }
+    }
+    out
+}
+
+/// `{argument}rest` → (`argument`, `rest`), skipping leading spaces.
+fn argument(text: &str) -> Option<(&str, &str)> {
+    let text = text.trim_start();
+    let body = text.strip_prefix('{')?;
+    let mut depth = 1;
+    let mut escaped = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&body[..i], &body[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `[option]rest` → (`option`, `rest`), if there is one.
+fn optional(text: &str) -> (Option<&str>, &str) {
+    let trimmed = text.trim_start();
+    let Some(body) = trimmed.strip_prefix('[') else {
+        return (None, text);
+    };
+    let mut depth = 0;
+    for (i, c) in body.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ']' if depth == 0 => return (Some(&body[..i]), &body[i + 1..]),
+            _ => {}
+        }
+    }
+    (None, text)
+}
+
+/// The body of `\begin{name}` up to its matching `\end{name}`, and the rest.
+fn environment<'a>(text: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let begin = format!("\\begin{{{}}}", name);
+    let end = format!("\\end{{{}}}", name);
+    let mut depth = 1;
+    let mut index = 0;
+    while index < text.len() {
+        let rest = &text[index..];
+        if rest.starts_with(&begin) {
+            depth += 1;
+            index += begin.len();
+        } else if rest.starts_with(&end) {
+            depth -= 1;
+            if depth == 0 {
+                return Some((&text[..index], &text[index + end.len()..]));
+            }
+            index += end.len();
+        } else {
+            index += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+/// Splits at `separator` outside braces and environments.
+fn split_top_level<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut index = 0;
+    while index < text.len() {
+        let rest = &text[index..];
+        if rest.starts_with("\\begin{") {
+            depth += 1;
+        } else if rest.starts_with("\\end{") {
+            depth -= 1;
+        }
+        if depth == 0 && rest.starts_with(separator) {
+            parts.push(&text[start..index]);
+            index += separator.len();
+            start = index;
+            continue;
+        }
+        match rest.as_bytes()[0] {
+            b'\\' => {
+                // Skip the escaped character so `\&` and `\\` do not split.
+                let next = rest[1..].chars().next().map_or(0, char::len_utf8);
+                if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+                    index += 1 + next;
+                    continue;
+                }
+            }
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        index += rest.chars().next().map_or(1, char::len_utf8);
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Whether `url` may be a link: http, https and mailto URLs and relative
+/// ones. Other schemes (`javascript:`, `data:`) could run in the reader's
+/// browser, so they stay text.
+fn linkable(url: &str) -> bool {
+    // Browsers drop whitespace and control characters inside a scheme.
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    let scheme = url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| !scheme.contains(['/', '?', '#']));
+    match scheme {
+        Some(scheme) => ["http", "https", "mailto"]
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed)),
+        None => true,
+    }
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// What a `\caption` or `\label` inside a float refers to.
+#[derive(Debug, Clone, Copy)]
+enum Float {
+    Figure(usize),
+    Table(usize),
+}
+
+struct Converter<'a> {
+    sources: &'a Sources,
+    target: Target,
+    title: Option<String>,
+    author: Option<String>,
+    date: Option<String>,
+    /// Index in [`SECTIONS`] of the outermost sectioning command used.
+    top_level: usize,
+    counters: [usize; 7],
+    figures: usize,
+    tables: usize,
+    float: Option<Float>,
+    /// The number `\label` refers to: the last section, figure or table.
+    current: String,
+    labels: HashMap<String, String>,
+    toc: Vec<Heading>,
+    equations: usize,
+    footnotes: Vec<String>,
+    images: Vec<Image>,
+    fallbacks: Vec<Fallback>,
+    unsupported: BTreeMap<String, usize>,
+}
+
+impl<'a> Converter<'a> {
+    fn new(sources: &'a Sources, target: Target, body: &str) -> Self {
+        Self {
+            sources,
+            target,
+            title: None,
+            author: None,
+            date: None,
+            top_level: SECTIONS
+                .iter()
+                .position(|s| body.contains(&format!("\\{}{{", s)))
+                .unwrap_or(2)
+                .max(1),
+            counters: [0; 7],
+            figures: 0,
+            tables: 0,
+            float: None,
+            current: String::new(),
+            labels: HashMap::new(),
+            toc: Vec::new(),
+            equations: 0,
+            footnotes: Vec::new(),
+            images: Vec::new(),
+            fallbacks: Vec::new(),
+            unsupported: BTreeMap::new(),
+        }
+    }
+
+    fn unsupported(&mut self, what: &str) {
+        *self.unsupported.entry(what.to_string()).or_insert(0) += 1;
+    }
+
+    fn placeholder(&mut self, what: &str) -> String {
+        self.unsupported(what);
+        note(what)
+    }
+
+    /// A drawing TeX has to make: a placeholder on the web, a fallback
+    /// image in an EPUB.
+    fn drawing(&mut self, what: &str, source: String) -> String {
+        match self.target {
+            Target::Web => self.placeholder(what),
+            Target::Epub => self.fallback(source, note(what), true) + "\n",
+        }
+    }
+
+    fn fallback(&mut self, source: String, text: String, display: bool) -> String {
+        self.fallbacks.push(Fallback {
+            source,
+            text,
+            display,
+        });
+        format!("\u{0}fallback:{}\u{0}", self.fallbacks.len() - 1)
+    }
+
+    /// Inline or display math.
+    fn math(&mut self, tex: &str, display: bool) -> String {
+        match (self.target, display) {
+            (Target::Web, false) => format!("\\({}\\)", escape(tex)),
+            (Target::Web, true) => format!("<div class=\"math\">\\[{}\\]</div>\n", escape(tex)),
+            (Target::Epub, _) => {
+                let math = match mathml::convert(tex, display) {
+                    Ok(math) => math,
+                    Err(_) => {
+                        let (source, text) = match display {
+                            true => (
+                                format!("\\[{}\\]", tex),
+                                format!("<pre><code>{}</code></pre>", escape(tex)),
+                            ),
+                            false => (
+                                format!("${}$", tex),
+                                format!("<code>{}</code>", escape(tex)),
+                            ),
+                        };
+                        self.fallback(source, text, display)
+                    }
+                };
+                match display {
+                    true => format!("<div class=\"math\">{}</div>\n", math),
+                    false => math,
+                }
+            }
+        }
+    }
+
+    /// A display math environment; an EPUB numbers its equations itself.
+    fn math_environment(&mut self, name: &str, body: &str) -> String {
+        if self.target == Target::Web {
+            return format!(
+                "<div class=\"math\">\\begin{{{0}}}{1}\\end{{{0}}}</div>\n",
+                name,
+                escape(body)
+            );
+        }
+        let base = name.trim_end_matches('*');
+        let numbered = !name.ends_with('*') && base != "displaymath";
+        let aligned = matches!(base, "align" | "flalign" | "eqnarray");
+        let rows = match base {
+            "align" | "flalign" | "eqnarray" | "gather" => split_top_level(body, "\\\\"),
+            _ => vec![body],
+        };
+        let mut anchors = String::new();
+        let mut converted = Vec::new();
+        let mut numbers = Vec::new();
+        let mut sources = Vec::new();
+        for row in rows {
+            let mut row = row.replace("\\\\", " ");
+            let mut labels = Vec::new();
+            while let Some(start) = row.find("\\label") {
+                let after = &row[start + "\\label".len()..];
+                let Some((label, after)) = argument(after) else {
+                    break;
+                };
+                labels.push(label.trim().to_string());
+                row = format!("{}{}", &row[..start], after);
+            }
+            let tagged = !row.contains("\\nonumber") && !row.contains("\\notag");
+            if row.trim().is_empty() {
+                continue;
+            }
+            let number = match numbered && tagged {
+                true => {
+                    self.equations += 1;
+                    Some(self.equations)
+                }
+                false => None,
+            };
+            for label in labels {
+                if let Some(number) = number {
+                    self.labels.insert(label.clone(), number.to_string());
+                }
+                anchors.push_str(&format!("<a id=\"{}\"></a>", escape(&label)));
+            }
+            numbers.push(number);
+            let row = row.replace("\\nonumber", "").replace("\\notag", "");
+            let cells: Vec<String> = match aligned {
+                true => split_top_level(&row, "&")
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                false => vec![row.clone()],
+            };
+            converted.push(
+                cells
+                    .iter()
+                    .map(|cell| mathml::expression(cell))
+                    .collect::<Result<Vec<_>>>(),
+            );
+            sources.push(row.trim().to_string());
+        }
+        let tags: Vec<String> = numbers
+            .iter()
+            .flatten()
+            .map(|n| format!("({})", n))
+            .collect();
+        let tex = sources.join(" \\\\\n");
+        let starred = match base {
+            "displaymath" => "equation*".to_string(),
+            "multline" | "equation" | "gather" | "align" | "flalign" => format!("{}*", base),
+            _ => "eqnarray*".to_string(),
+        };
+        let math = match converted.into_iter().collect::<Result<Vec<_>>>() {
+            Ok(rows) if !aligned && rows.len() == 1 => mathml::wrap(&rows[0].concat(), &tex, true),
+            Ok(rows) => {
+                let mut table = String::from("<mtable displaystyle=\"true\">");
+                for (cells, number) in rows.iter().zip(&numbers) {
+                    table.push_str("<mtr>");
+                    for (index, cell) in cells.iter().enumerate() {
+                        // Aligned columns alternate right and left.
+                        let align = match (aligned, index % 2) {
+                            (false, _) => "center",
+                            (true, 0) => "right",
+                            (true, _) => "left",
+                        };
+                        table.push_str(&format!("<mtd columnalign=\"{}\">{}</mtd>", align, cell));
+                    }
+                    if let Some(number) = number.filter(|_| tags.len() > 1) {
+                        table.push_str(&format!(
+                            "<mtd><mspace width=\"2em\"/><mtext>({})</mtext></mtd>",
+                            number
+                        ));
+                    }
+                    table.push_str("</mtr>");
+                }
+                table.push_str("</mtable>");
+                mathml::wrap(&table, &tex, true)
+            }
+            Err(_) => {
+                let source = format!("\\begin{{{0}}}\n{1}\n\\end{{{0}}}", starred, tex);
+                let text = format!("<pre><code>{}</code></pre>", escape(&tex));
+                self.fallback(source, text, true)
+            }
+        };
+        let number = match tags.len() {
+            1 => format!("<span class=\"number\">{}</span>", tags[0]),
+            _ => String::new(),
+        };
+        format!(
+            "{}<div class=\"equation\">{}{}</div>\n",
+            anchors, math, number
+        )
+    }
+
+    /// Converts running text split into paragraphs and blocks.
+    fn blocks(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        let mut paragraph = String::new();
+        let flush = |out: &mut String, paragraph: &mut String| {
+            let text = paragraph.trim();
+            // Labels alone, after a heading or caption, need no paragraph.
+            match text
+                .split("</a>")
+                .all(|part| part.trim().starts_with("<a id=") || part.trim().is_empty())
+            {
+                true => out.push_str(text),
+                false => out.push_str(&format!("<p>{}</p>\n", text)),
+            }
+            paragraph.clear();
+        };
+        for chunk in paragraphs(text) {
+            let mut rest = chunk;
+            while !rest.is_empty() {
+                match self.block(rest) {
+                    Some((html, after)) => {
+                        flush(&mut out, &mut paragraph);
+                        out.push_str(&html);
+                        rest = after;
+                    }
+                    None => {
+                        let (html, after) = self.token(rest);
+                        paragraph.push_str(&html);
+                        rest = after;
+                    }
+                }
+            }
+            flush(&mut out, &mut paragraph);
+        }
+        out
+    }
+
+    /// Converts text inside a paragraph; blocks found there are converted
+    /// too, without paragraph tags.
+    fn inline(&mut self, text: &str) -> String {
+        let mut out = String::new();
+        let mut rest = text;
+        while !rest.is_empty() {
+            match self.block(rest) {
+                Some((html, after)) => {
+                    out.push_str(&html);
+                    rest = after;
+                }
+                None => {
+                    let (html, after) = self.token(rest);
+                    out.push_str(&html);
+                    rest = after;
+                }
+            }
+        }
+        out
+    }
+
+    /// A block-level construct at the start of `text`.
+    fn block<'t>(&mut self, text: &'t str) -> Option<(String, &'t str)> {
+        if let Some(rest) = text.strip_prefix("\\[") {
+            let end = rest.find("\\]")?;
+            return Some((self.math(&rest[..end], true), &rest[end + 2..]));
+        }
+        if let Some(rest) = text.strip_prefix("$$") {
+            let end = rest.find("$$")?;
+            return Some((self.math(&rest[..end], true), &rest[end + 2..]));
+        }
+        let command = text.strip_prefix('\\')?;
+        let name_length = command
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(command.len());
+        let name = &command[..name_length];
+        let rest = &command[name_length..];
+        if let Some(level) = SECTIONS.iter().position(|s| *s == name) {
+            let (starred, rest) = match rest.strip_prefix('*') {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let (_, rest) = optional(rest);
+            let (title, rest) = argument(rest)?;
+            return Some((self.heading(level, starred, title), rest));
+        }
+        match name {
+            "maketitle" => Some((self.title_block(), rest)),
+            "tableofcontents" => Some(("\u{0}toc\u{0}".to_string(), rest)),
+            "par" => Some((String::new(), rest)),
+            // Figures and captions break the paragraph they are in.
+            "caption" | "includegraphics" | "chemfig" | "chemname" | "schemestart"
+            | "chemtexsmiles" => Some(self.command(name, rest)),
+            "begin" => {
+                let (environment_name, after) = argument(rest)?;
+                if !is_block_environment(environment_name) {
+                    return None;
+                }
+                let (body, after_end) = environment(after, environment_name)?;
+                Some((self.environment(environment_name, body), after_end))
+            }
+            _ => None,
+        }
+    }
+
+    fn heading(&mut self, level: usize, starred: bool, title: &str) -> String {
+        let title = self.inline(title).trim().to_string();
+        let depth = level.saturating_sub(self.top_level);
+        let tag = format!("h{}", (depth + 2).min(6));
+        if starred || level >= 5 {
+            return format!("<{0}>{1}</{0}>\n", tag, title);
+        }
+        self.counters[level] += 1;
+        for counter in &mut self.counters[level + 1..] {
+            *counter = 0;
+        }
+        let number: Vec<String> = self.counters[self.top_level.min(level)..=level]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let number = number.join(".");
+        let id = format!("sec-{}", number);
+        self.current = number.clone();
+        self.toc.push(Heading {
+            depth,
+            id: id.clone(),
+            number: number.clone(),
+            title: title.clone(),
+        });
+        format!(
+            "<{0} id=\"{1}\">{2}\u{2003}{3}</{0}>\n",
+            tag, id, number, title
+        )
+    }
+
+    fn title_block(&mut self) -> String {
+        let mut out = String::from("<header>\n");
+        if let Some(title) = &self.title {
+            out.push_str(&format!("<h1>{}</h1>\n", title));
+        }
+        if let Some(author) = &self.author {
+            out.push_str(&format!("<p class=\"author\">{}</p>\n", author));
+        }
+        if let Some(date) = &self.date {
+            out.push_str(&format!("<p class=\"date\">{}</p>\n", date));
+        }
+        out.push_str("</header>\n");
+        out
+    }
+
+    fn environment(&mut self, name: &str, body: &str) -> String {
+        let base = name.trim_end_matches('*');
+        match base {
+            "itemize" | "enumerate" | "description" => self.list(base, body),
+            _ if MATH_ENVIRONMENTS.contains(&name) => self.math_environment(name, body),
+            "figure" | "wrapfigure" | "SCfigure" => {
+                self.figures += 1;
+                let number = self.figures;
+                let (_, body) = optional(body);
+                self.float_body(Float::Figure(number), body, "figure")
+            }
+            "table" | "wraptable" => {
+                self.tables += 1;
+                let number = self.tables;
+                let (_, body) = optional(body);
+                self.float_body(Float::Table(number), body, "div class=\"table\"")
+            }
+            "tabular" | "tabularx" | "tabulary" | "longtable" => self.tabular(base, body),
+            "center" => format!("<div class=\"center\">\n{}</div>\n", self.blocks(body)),
+            "flushright" => format!(
+                "<div style=\"text-align: right\">\n{}</div>\n",
+                self.blocks(body)
+            ),
+            "quote" | "quotation" => {
+                format!("<blockquote>\n{}</blockquote>\n", self.blocks(body))
+            }
+            "abstract" => format!(
+                "<section class=\"abstract\">\n<h2>Abstract</h2>\n{}</section>\n",
+                self.blocks(body)
+            ),
+            "verbatim" | "lstlisting" | "minted" | "Verbatim" => {
+                let body = match base {
+                    "minted" => argument(body).map_or(body, |(_, rest)| rest),
+                    _ => optional(body).1,
+                };
+                format!(
+                    "<pre><code>{}</code></pre>\n",
+                    escape(body.trim_matches('\n'))
+                )
+            }
+            _ if DRAWINGS.contains(&base) => self.drawing(
+                &format!("{}(s)", base),
+                format!("\\begin{{{0}}}{1}\\end{{{0}}}", name, body),
+            ),
+            "thebibliography" => {
+                let (_, body) = argument(body).unwrap_or(("", body));
+                let mut out = String::from("<h2>References</h2>\n<ol class=\"references\">\n");
+                for item in split_top_level(body, "\\bibitem").into_iter().skip(1) {
+                    let (_, item) = optional(item);
+                    let (key, item) = argument(item).unwrap_or(("", item));
+                    out.push_str(&format!(
+                        "<li id=\"cite-{}\">{}</li>\n",
+                        key,
+                        self.inline(item).trim()
+                    ));
+                }
+                out.push_str("</ol>\n");
+                out
+            }
+            _ if is_theorem(base) => {
+                let (title, body) = optional(body);
+                let mut heading = capitalize(base);
+                if let Some(title) = title {
+                    heading = format!("{} ({})", heading, self.inline(title));
+                }
+                format!(
+                    "<div class=\"{}\"><b>{}.</b> {}</div>\n",
+                    base,
+                    heading,
+                    self.inline(body).trim()
+                )
+            }
+            _ => self.blocks(body),
+        }
+    }
+
+    fn float_body(&mut self, float: Float, body: &str, tag: &str) -> String {
+        let outer = self.float.replace(float);
+        let current = std::mem::replace(
+            &mut self.current,
+            match float {
+                Float::Figure(n) | Float::Table(n) => n.to_string(),
+            },
+        );
+        let html = self.blocks(body);
+        self.float = outer;
+        self.current = current;
+        let close = tag.split_whitespace().next().unwrap_or(tag);
+        format!("<{}>\n{}</{}>\n", tag, html, close)
+    }
+
+    fn list(&mut self, kind: &str, body: &str) -> String {
+        let tag = match kind {
+            "enumerate" => "ol",
+            "description" => "dl",
+            _ => "ul",
+        };
+        let mut out = format!("<{}>\n", tag);
+        for item in split_top_level(body, "\\item").into_iter().skip(1) {
+            let (label, item) = match item.trim_start().starts_with('[') {
+                true => optional(item),
+                false => (None, item),
+            };
+            let content = self.inline(item).trim().to_string();
+            match (tag, label) {
+                ("dl", label) => out.push_str(&format!(
+                    "<dt>{}</dt><dd>{}</dd>\n",
+                    label.map(|l| self.inline(l)).unwrap_or_default(),
+                    content
+                )),
+                (_, Some(label)) => out.push_str(&format!(
+                    "<li style=\"list-style: none\">{} {}</li>\n",
+                    self.inline(label),
+                    content
+                )),
+                (_, None) => out.push_str(&format!("<li>{}</li>\n", content)),
+            }
+        }
+        out.push_str(&format!("</{}>\n", tag));
+        out
+    }
+
+    fn tabular(&mut self, kind: &str, body: &str) -> String {
+        // Position, width and column specification.
+        let (_, mut body) = optional(body);
+        if kind != "tabular" && kind != "longtable" {
+            body = argument(body).map_or(body, |(_, rest)| rest);
+        }
+        let body = argument(body).map_or(body, |(_, rest)| rest);
+        let mut rows: Vec<(bool, String)> = Vec::new();
+        let mut header_rows = 0;
+        for row in split_top_level(body, "\\\\") {
+            let (_, row) = optional(row);
+            let mut row = row.to_string();
+            let mut ruled = false;
+            for rule in [
+                "\\toprule",
+                "\\midrule",
+                "\\bottomrule",
+                "\\hline",
+                "\\endhead",
+            ] {
+                if row.contains(rule) {
+                    ruled |= rule == "\\midrule" || (rule == "\\hline" && !rows.is_empty());
+                    row = row.replace(rule, "");
+                }
+            }
+            for rule in ["\\cmidrule", "\\cline"] {
+                while let Some(start) = row.find(rule) {
+                    let after = &row[start + rule.len()..];
+                    let after = after
+                        .trim_start()
+                        .strip_prefix('(')
+                        .and_then(|a| a.split_once(')'))
+                        .map_or(after, |(_, a)| a);
+                    let after = argument(after).map_or(after, |(_, rest)| rest);
+                    row = format!("{}{}", &row[..start], after);
+                }
+            }
+            if ruled && header_rows == 0 && !rows.is_empty() {
+                header_rows = rows.len();
+            }
+            if row.trim().is_empty() {
+                continue;
+            }
+            let cells: Vec<String> = split_top_level(&row, "&")
+                .into_iter()
+                .map(|cell| self.cell(cell))
+                .collect();
+            rows.push((false, cells.concat()));
+        }
+        let mut out = String::from("<table>\n");
+        for (index, (_, cells)) in rows.iter().enumerate() {
+            if index == 0 && header_rows > 0 {
+                out.push_str("<thead>\n");
+            }
+            if index == header_rows {
+                out.push_str("<tbody>\n");
+            }
+            let cells = match index < header_rows {
+                true => cells.replace("<td", "<th").replace("</td>", "</th>"),
+                false => cells.clone(),
+            };
+            out.push_str(&format!("<tr>{}</tr>\n", cells));
+            if index + 1 == header_rows {
+                out.push_str("</thead>\n");
+            }
+        }
+        if rows.len() > header_rows {
+            out.push_str("</tbody>\n");
+        }
+        out.push_str("</table>\n");
+        out
+    }
+
+    fn cell(&mut self, cell: &str) -> String {
+        let trimmed = cell.trim();
+        for command in ["\\multicolumn", "\\multirow"] {
+            let Some(rest) = trimmed.strip_prefix(command) else {
+                continue;
+            };
+            let parts = (|| {
+                let (count, rest) = argument(rest)?;
+                let (_, rest) = argument(rest)?;
+                let (text, _) = argument(rest)?;
+                Some((count.trim().parse::<usize>().ok()?, text))
+            })();
+            if let Some((count, text)) = parts {
+                let span = match command {
+                    "\\multicolumn" => "colspan",
+                    _ => "rowspan",
+                };
+                return format!(
+                    "<td {}=\"{}\">{}</td>",
+                    span,
+                    count,
+                    self.inline(text).trim()
+                );
+            }
+        }
+        format!("<td>{}</td>", self.inline(trimmed).trim())
+    }
+
+    /// One piece of running text: a character, a group, math or a command.
+    fn token<'t>(&mut self, text: &'t str) -> (String, &'t str) {
+        let c = text.chars().next().unwrap_or(' ');
+        let next = &text[c.len_utf8()..];
+        match c {
+            '$' => {
+                let end = next.find('$').unwrap_or(next.len());
+                return (
+                    self.math(&next[..end], false),
+                    next.get(end + 1..).unwrap_or(""),
+                );
+            }
+            '{' => {
+                return match argument(text) {
+                    Some((group, rest)) => (self.inline(group), rest),
+                    None => (String::new(), next),
+                };
+            }
+            '}' => return (String::new(), next),
+            '~' => return ("&#160;".to_string(), next),
+            '<' => return ("&lt;".to_string(), next),
+            '>' => return ("&gt;".to_string(), next),
+            '&' => return ("&amp;".to_string(), next),
+            '\n' | '\t' => return (" ".to_string(), next),
+            '\\' => {}
+            _ => {
+                for (latex, html) in [("---", "—"), ("--", "–"), ("``", "“"), ("''", "”")] {
+                    if let Some(rest) = text.strip_prefix(latex) {
+                        return (html.to_string(), rest);
+                    }
+                }
+                return (c.to_string(), next);
+            }
+        }
+
+        // Escaped characters and control symbols.
+        let Some(symbol) = next.chars().next() else {
+            return (String::new(), next);
+        };
+        if !symbol.is_ascii_alphabetic() {
+            let after = &next[symbol.len_utf8()..];
+            return match symbol {
+                '\\' => {
+                    let (_, after) = optional(after);
+                    ("<br/>\n".to_string(), after)
+                }
+                '(' => {
+                    let end = after.find("\\)").unwrap_or(after.len());
+                    (
+                        self.math(&after[..end], false),
+                        after.get(end + 2..).unwrap_or(""),
+                    )
+                }
+                '&' => ("&amp;".to_string(), after),
+                ',' => ("\u{2009}".to_string(), after),
+                ' ' => (" ".to_string(), after),
+                '-' | '@' | '/' => (String::new(), after),
+                c => (escape(&c.to_string()), after),
+            };
+        }
+        let length = next
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(next.len());
+        let name = &next[..length];
+        let mut rest = &next[length..];
+        if let Some(after) = rest.strip_prefix('*') {
+            rest = after;
+        }
+        self.command(name, rest)
+    }
+
+    fn command<'t>(&mut self, name: &str, rest: &'t str) -> (String, &'t str) {
+        let one = |rest: &'t str| argument(rest).unwrap_or(("", rest));
+        if let Some((_, count)) = DROPPED.iter().find(|(n, _)| *n == name) {
+            let (_, mut rest) = optional(rest);
+            for _ in 0..*count {
+                rest = one(rest).1;
+            }
+            return (String::new(), rest.strip_prefix(' ').unwrap_or(rest));
+        }
+        if let Some((_, tag)) = FORMATTING.iter().find(|(n, _)| *n == name) {
+            let (text, rest) = one(rest);
+            return (format!("<{0}>{1}</{0}>", tag, self.inline(text)), rest);
+        }
+        if let Some((_, symbol)) = SYMBOLS.iter().find(|(n, _)| *n == name) {
+            // A control word swallows the space after it.
+            let rest = rest.strip_prefix("{}").unwrap_or(rest);
+            return (symbol.to_string(), rest);
+        }
+        match name {
+            "ce" => {
+                let (formula, rest) = one(rest);
+                (self.math(&format!("\\ce{{{}}}", formula), false), rest)
+            }
+            "chemfig" => {
+                let (code, rest) = one(rest);
+                let source = format!("\\chemfig{{{}}}", code);
+                (self.drawing("chemfig structure(s)", source), rest)
+            }
+            "chemname" => {
+                let (structure, rest) = one(rest);
+                let (name, rest) = one(rest);
+                let source = format!("\\chemname{{{}}}{{{}}}", structure, name);
+                (self.drawing("chemfig structure(s)", source), rest)
+            }
+            "schemestart" => {
+                let end = rest.find("\\schemestop").unwrap_or(rest.len());
+                let source = format!("\\schemestart{}\\schemestop", &rest[..end]);
+                let rest = rest.get(end + "\\schemestop".len()..).unwrap_or("");
+                (self.drawing("reaction scheme(s)", source), rest)
+            }
+            "chemtexsmiles" => {
+                let (code, rest) = one(rest);
+                (self.structure(code), rest)
+            }
+            "includegraphics" => {
+                let (_, rest) = optional(rest);
+                let (file, rest) = one(rest);
+                (self.image(file.trim()), rest)
+            }
+            "caption" => {
+                let (_, rest) = optional(rest);
+                let (caption, rest) = one(rest);
+                let caption = self.inline(caption).trim().to_string();
+                let html = match self.float {
+                    Some(Float::Figure(n)) => {
+                        format!("<figcaption>Figure {}. {}</figcaption>\n", n, caption)
+                    }
+                    Some(Float::Table(n)) => {
+                        format!("<p class=\"caption\">Table {}. {}</p>\n", n, caption)
+                    }
+                    None => format!("<p class=\"caption\">{}</p>\n", caption),
+                };
+                (html, rest)
+            }
+            "label" => {
+                let (key, rest) = one(rest);
+                self.labels.insert(key.to_string(), self.current.clone());
+                (format!("<a id=\"{}\"></a>", escape(key)), rest)
+            }
+            "ref" | "autoref" | "cref" | "Cref" => {
+                let (key, rest) = one(rest);
+                (format!("\u{0}ref:{}\u{0}", key), rest)
+            }
+            "eqref" => {
+                let (key, rest) = one(rest);
+                (format!("\u{0}eqref:{}\u{0}", key), rest)
+            }
+            "cite" | "citep" | "citet" | "parencite" | "textcite" | "autocite" => {
+                let (_, rest) = optional(rest);
+                let (_, rest) = optional(rest);
+                let (keys, rest) = one(rest);
+                (format!("[{}]", escape(keys)), rest)
+            }
+            "footnote" => {
+                let (_, rest) = optional(rest);
+                let (note, rest) = one(rest);
+                let note = self.inline(note).trim().to_string();
+                self.footnotes.push(note);
+                let n = self.footnotes.len();
+                (
+                    format!("<sup id=\"fnref{0}\"><a href=\"#fn{0}\">{0}</a></sup>", n),
+                    rest,
+                )
+            }
+            "href" => {
+                let (url, rest) = one(rest);
+                let (text, rest) = one(rest);
+                let url = url.replace("\\%", "%").replace("\\#", "#");
+                let text = self.inline(text);
+                match linkable(&url) {
+                    true => (format!("<a href=\"{}\">{}</a>", escape(&url), text), rest),
+                    false => (text, rest),
+                }
+            }
+            "url" => {
+                let (url, rest) = one(rest);
+                let url = url.replace("\\%", "%").replace("\\#", "#");
+                match linkable(&url) {
+                    true => (format!("<a href=\"{0}\">{0}</a>", escape(&url)), rest),
+                    false => (escape(&url), rest),
+                }
+            }
+            "today" => (
+                chrono::Local::now().format("%Y-%m-%d").to_string(),
+                rest.strip_prefix("{}").unwrap_or(rest),
+            ),
+            "SI" | "qty" => {
+                let (_, rest) = optional(rest);
+                let (value, rest) = one(rest);
+                let (unit, rest) = one(rest);
+                let html = format!("{}&#160;{}", number(value), unit_text(unit));
+                (html, rest)
+            }
+            "si" | "unit" => {
+                let (_, rest) = optional(rest);
+                let (unit, rest) = one(rest);
+                (unit_text(unit), rest)
+            }
+            "num" => {
+                let (_, rest) = optional(rest);
+                let (value, rest) = one(rest);
+                (number(value), rest)
+            }
+            "ang" => {
+                let (value, rest) = one(rest);
+                (format!("{}°", escape(value)), rest)
+            }
+            "item" => {
+                let (_, rest) = optional(rest);
+                (String::new(), rest)
+            }
+            "begin" => {
+                // Environments that stay inline.
+                let (name, rest) = one(rest);
+                match environment(rest, name) {
+                    Some((body, rest)) => (self.inline(body), rest),
+                    None => (String::new(), rest),
+                }
+            }
+            "end" => (String::new(), one(rest).1),
+            _ => (String::new(), rest),
+        }
+    }
+
+    /// A structure `chemtex smiles` wrote, drawn from its SMILES.
+    fn structure(&mut self, code: &str) -> String {
+        match smiles::to_molecule(code).and_then(|molecule| render::png(&molecule)) {
+            Ok(png) => {
+                let name = format!("structure-{}.png", self.images.len() + 1);
+                self.embed(&name, "image/png", png, code)
+            }
+            Err(_) => {
+                let source = smiles::to_chemfig(code).unwrap_or_default();
+                self.drawing("chemfig structure(s)", source)
+            }
+        }
+    }
+
+    /// An image: embedded on the web, a file of its own in an EPUB.
+    fn embed(&mut self, name: &str, media_type: &'static str, bytes: Vec<u8>, alt: &str) -> String {
+        match self.target {
+            Target::Web => format!(
+                "<img src=\"data:{};base64,{}\" alt=\"{}\"/>\n",
+                media_type,
+                STANDARD.encode(&bytes),
+                escape(alt)
+            ),
+            Target::Epub => {
+                let file = Path::new(name)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let name = format!("images/{}-{}", self.images.len() + 1, file);
+                self.images.push(Image {
+                    name: name.clone(),
+                    media_type,
+                    bytes,
+                });
+                format!("<img src=\"{}\" alt=\"{}\"/>\n", escape(&name), escape(alt))
+            }
+        }
+    }
+
+    /// An embedded image, or a note for formats browsers cannot show.
+    fn image(&mut self, file: &str) -> String {
+        let candidates: Vec<String> = match Path::new(file).extension() {
+            Some(_) => vec![file.to_string()],
+            None => ["png", "jpg", "jpeg", "svg", "pdf"]
+                .iter()
+                .map(|e| format!("{}.{}", file, e))
+                .collect(),
+        };
+        for candidate in &candidates {
+            let Some(bytes) = self.sources.get(candidate) else {
+                continue;
+            };
+            let extension = candidate
+                .rsplit('.')
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            let mime = match extension.as_str() {
+                "png" => "image/png",
+                "jpg" | "jpeg" => "image/jpeg",
+                "gif" => "image/gif",
+                "svg" => "image/svg+xml",
+                _ => {
+                    let source = format!("\\includegraphics[width=\\linewidth]{{{}}}", file);
+                    return self.drawing("PDF or EPS image(s)", source);
+                }
+            };
+            return self.embed(candidate, mime, bytes, file);
+        }
+        self.placeholder("missing image(s)")
+    }
+
+    /// Fills in references and the table of contents once every label is
+    /// known.
+    fn resolve(&self, html: &str) -> String {
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(start) = rest.find('\u{0}') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let Some(end) = after.find('\u{0}') else {
+                break;
+            };
+            let marker = &after[..end];
+            if marker == "toc" {
+                // An EPUB has its own navigation.
+                if self.target == Target::Web {
+                    out.push_str(&self.table_of_contents());
+                }
+            } else if let Some((kind, key)) = marker
+                .split_once(':')
+                .filter(|(kind, _)| *kind == "ref" || *kind == "eqref")
+            {
+                let (open, close) = match kind {
+                    "eqref" => ("(", ")"),
+                    _ => ("", ""),
+                };
+                match (self.labels.get(key), self.target) {
+                    (Some(number), _) => out.push_str(&format!(
+                        "{}<a href=\"#{}\">{}</a>{}",
+                        open,
+                        escape(key),
+                        number,
+                        close
+                    )),
+                    // Equation labels are MathJax's.
+                    (None, Target::Web) => {
+                        out.push_str(&format!("\\(\\{}{{{}}}\\)", kind, escape(key)))
+                    }
+                    (None, Target::Epub) => out.push_str(&format!("{}??{}", open, close)),
+                }
+            } else {
+                // Fallbacks, filled in later.
+                out.push_str(&format!("\u{0}{}\u{0}", marker));
+            }
+            rest = &after[end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn table_of_contents(&self) -> String {
+        let mut out = String::from("<nav class=\"toc\">\n<h2>Contents</h2>\n<ul>\n");
+        for heading in &self.toc {
+            out.push_str(&format!(
+                "<li style=\"margin-left: {}em\"><a href=\"#{}\">{} {}</a></li>\n",
+                heading.depth * 3 / 2,
+                heading.id,
+                heading.number,
+                heading.title
+            ));
+        }
+        out.push_str("</ul>\n</nav>\n");
+        out
+    }
+}
+
+fn note(what: &str) -> String {
+    format!(
+        "<div class=\"unsupported\">This {} is only in the PDF version.</div>\n",
+        what.trim_end_matches("(s)")
+    )
+}
+
+/// Splits text at blank lines.
+fn paragraphs(text: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    let mut previous_blank = false;
+    for line in text.split_inclusive('\n') {
+        let blank = line.trim().is_empty();
+        if blank && !previous_blank && offset > start {
+            chunks.push(&text[start..offset]);
+        }
+        if blank {
+            start = offset + line.len();
+        }
+        previous_blank = blank;
+        offset += line.len();
+    }
+    if start < text.len() {
+        chunks.push(&text[start..]);
+    }
+    // A blank line inside an environment does not end it.
+    let mut merged: Vec<&str> = Vec::new();
+    let mut open = 0i32;
+    let mut from = 0usize;
+    for chunk in chunks {
+        let chunk_start = chunk.as_ptr() as usize - text.as_ptr() as usize;
+        if open == 0 {
+            from = chunk_start;
+        }
+        open += chunk.matches("\\begin{").count() as i32;
+        open -= chunk.matches("\\end{").count() as i32;
+        if open <= 0 {
+            open = 0;
+            merged.push(&text[from..chunk_start + chunk.len()]);
+        }
+    }
+    if open > 0 {
+        merged.push(&text[from..]);
+    }
+    merged
+}
+
+fn is_block_environment(name: &str) -> bool {
+    let base = name.trim_end_matches('*');
+    !matches!(base, "math" | "minipage") || MATH_ENVIRONMENTS.contains(&name)
+}
+
+fn is_theorem(name: &str) -> bool {
+    matches!(
+        name,
+        "definition"
+            | "defn"
+            | "theorem"
+            | "lemma"
+            | "proposition"
+            | "corollary"
+            | "example"
+            | "remark"
+            | "note"
+            | "proof"
+    )
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// `1.5e-3` → `1.5 × 10<sup>−3</sup>`; `+-` gets its sign.
+fn number(value: &str) -> String {
+    let value = value.trim().replace("+-", "±").replace("\\pm", "±");
+    match value.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) if exponent.parse::<i32>().is_ok() => format!(
+            "{}&#160;×&#160;10<sup>{}</sup>",
+            escape(mantissa),
+            exponent.replace('-', "−")
+        ),
+        _ => escape(&value),
+    }
+}
+
+/// `\milli\litre\per\second` → `mL/s`; literal units stay as written.
+fn unit_text(unit: &str) -> String {
+    let mut out = String::new();
+    let mut rest = unit.trim();
+    while !rest.is_empty() {
+        let Some(command) = rest.strip_prefix('\\') else {
+            let c = rest.chars().next().unwrap_or(' ');
+            match c {
+                '~' => out.push_str("&#160;"),
+                '.' => out.push('·'),
+                '^' => {
+                    let (power, after) = match argument(&rest[1..]) {
+                        Some((power, after)) => (power.to_string(), after),
+                        None => (
+                            rest[1..2.min(rest.len())].to_string(),
+                            &rest[2.min(rest.len())..],
+                        ),
+                    };
+                    out.push_str(&format!("<sup>{}</sup>", escape(&power)));
+                    rest = after;
+                    continue;
+                }
+                c => out.push_str(&escape(&c.to_string())),
+            }
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+        let length = command
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(command.len());
+        let name = &command[..length];
+        rest = &command[length..];
+        match name {
+            "per" => out.push('/'),
+            "square" => out.push_str("\u{0}2"),
+            "cubic" => out.push_str("\u{0}3"),
+            "squared" => out.push_str("<sup>2</sup>"),
+            "cubed" => out.push_str("<sup>3</sup>"),
+            _ => match UNITS.iter().find(|(n, _)| *n == name) {
+                Some((_, symbol)) => {
+                    out.push_str(symbol);
+                }
+                None => out.push_str(name),
+            },
+        }
+    }
+    // `\square\metre` puts the power after the unit.
+    let mut fixed = String::new();
+    let mut chars = out.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{0}' {
+            let power = chars.next().unwrap_or('2');
+            let mut unit = String::new();
+            while let Some(&next) = chars.peek() {
+                if !next.is_alphabetic() {
+                    break;
+                }
+                unit.push(next);
+                chars.next();
+            }
+            fixed.push_str(&format!("{}<sup>{}</sup>", unit, power));
+        } else {
+            fixed.push(c);
+        }
+    }
+    fixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn convert_text(text: &str) -> Document {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "chemtex-html-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let main = dir.join("main.tex");
+        fs::write(&main, text).unwrap();
+        let document = Sources::read(&main).and_then(|s| convert(&s, Target::Web));
+        fs::remove_dir_all(&dir).unwrap();
+        document.unwrap()
+    }
+
+    #[test]
+    fn only_web_and_mail_links_are_links() {
+        let document = convert_text(
+            "\\documentclass{article}\n\\begin{document}\n\
+             \\href{https://example.org/?a=1&b=2}{site} \\url{mailto:lab@example.org} \
+             \\href{notes.html\\#yield}{notes} \\href{javascript:alert(1)}{click} \
+             \\href{ JavaScript :alert(1)}{spaced} \\url{data:text/html,x}\n\
+             \\end{document}\n",
+        );
+        let body = &document.body;
+        assert!(body.contains("<a href=\"https://example.org/?a=1&amp;b=2\">site</a>"));
+        assert!(body.contains("<a href=\"mailto:lab@example.org\">mailto:lab@example.org</a>"));
+        assert!(body.contains("<a href=\"notes.html#yield\">notes</a>"));
+        assert!(!body.contains("javascript") && !body.contains("JavaScript"));
+        assert!(body.contains("click") && body.contains("spaced"));
+        assert!(body.contains("data:text/html,x") && !body.contains("href=\"data:"));
+    }
+
+    #[test]
+    fn the_page_language_is_the_main_language_of_the_preamble() {
+        let language = |preamble: &str| {
+            convert_text(&format!(
+                "\\documentclass{{article}}\n{}\\begin{{document}}\nТекст and text\n\\end{{document}}\n",
+                preamble
+            ))
+            .language
+        };
+        assert_eq!(language("\\usepackage[english,russian]{babel}\n"), "ru");
+        assert_eq!(
+            language("\\usepackage[russian,main=english]{babel}\n"),
+            "en"
+        );
+        assert_eq!(
+            language("\\usepackage{polyglossia}\n\\setdefaultlanguage{ukrainian}\n"),
+            "uk"
+        );
+        assert_eq!(language("% \\usepackage[russian]{babel}\n"), "en");
+        assert!(page(&convert_text(
+            "\\documentclass{article}\n\\usepackage[ngerman]{babel}\n\\begin{document}\nx\n\\end{document}\n"
+        ))
+        .starts_with("<!DOCTYPE html>\n<html lang=\"de\">"));
+    }
+}
@@ -0,0 +1,1715 @@
+use crate::api;
+use crate::balance::Species;
+use crate::cli::Args;
+use crate::job::{Job, Runner};
+use crate::languages::Languages;
+use crate::md2tex::escape;
+use crate::pack::{self, TempDir};
+use crate::scaffold::PREAMBLE;
+use crate::xml::{self, Element, Node};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+
+const USAGE: &str = "\
+Usage: chemtex import <report.docx|report.odt> [options]
+
+Converts a Word or OpenDocument text into LaTeX with the chemistry preamble
+and compiles it. Headings, lists, tables, footnotes, links, images (copied
+next to the output) and equations are carried over; common conversion
+artifacts are cleaned up, e.g. H\\textsubscript{2}O becomes \\ce{H2O} and
+typed heading numbers are dropped.
+
+Options:
+  --out FILE     Where to write the .tex (default: next to the input)
+  --lang LIST    Languages for babel, main first (default: ru,en when the
+                 text is in Russian)
+  --native       Use the built-in converter even when pandoc is available
+  --no-compile   Only write the .tex
+  --no-cache     Always submit when compiling, ignoring the build cache";
+
+/// Extra definitions pandoc's LaTeX expects from its own template.
+#[cfg(feature = "pandoc")]
+const PANDOC_PREAMBLE: &str = "\
+\\usepackage{longtable,array,calc}
+\\providecommand{\\tightlist}{\\setlength{\\itemsep}{0pt}\\setlength{\\parskip}{0pt}}
+\\providecommand{\\pandocbounded}[1]{#1}
+";
+
+/// Image formats pdflatex can include.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "pdf"];
+
+const HEADINGS: &[&str] = &["section", "subsection", "subsubsection", "paragraph"];
+
+/// Unicode characters written as math, in running text and in equations.
+const MATH_SYMBOLS: &[(char, &str)] = &[
+    ('α', "\\alpha"),
+    ('β', "\\beta"),
+    ('γ', "\\gamma"),
+    ('δ', "\\delta"),
+    ('ε', "\\varepsilon"),
+    ('η', "\\eta"),
+    ('θ', "\\theta"),
+    ('κ', "\\kappa"),
+    ('λ', "\\lambda"),
+    ('μ', "\\mu"),
+    ('µ', "\\mu"),
+    ('ν', "\\nu"),
+    ('π', "\\pi"),
+    ('ρ', "\\rho"),
+    ('σ', "\\sigma"),
+    ('τ', "\\tau"),
+    ('φ', "\\varphi"),
+    ('χ', "\\chi"),
+    ('ψ', "\\psi"),
+    ('ω', "\\omega"),
+    ('Γ', "\\Gamma"),
+    ('Δ', "\\Delta"),
+    ('Θ', "\\Theta"),
+    ('Λ', "\\Lambda"),
+    ('Σ', "\\Sigma"),
+    ('Φ', "\\Phi"),
+    ('Ψ', "\\Psi"),
+    ('Ω', "\\Omega"),
+    ('±', "\\pm"),
+    ('∓', "\\mp"),
+    ('×', "\\times"),
+    ('·', "\\cdot"),
+    ('⋅', "\\cdot"),
+    ('÷', "\\div"),
+    ('−', "-"),
+    ('≈', "\\approx"),
+    ('≠', "\\neq"),
+    ('≤', "\\leq"),
+    ('≥', "\\geq"),
+    ('∞', "\\infty"),
+    ('∝', "\\propto"),
+    ('∂', "\\partial"),
+    ('∇', "\\nabla"),
+    ('∑', "\\sum"),
+    ('∏', "\\prod"),
+    ('∫', "\\int"),
+    ('√', "\\surd"),
+    ('→', "\\rightarrow"),
+    ('←', "\\leftarrow"),
+    ('↔', "\\leftrightarrow"),
+    ('⇌', "\\rightleftharpoons"),
+    ('⇒', "\\Rightarrow"),
+    ('°', "^\\circ"),
+];
+
+/// Typographic characters and their LaTeX spelling in running text.
+const TEXT_SYMBOLS: &[(&str, &str)] = &[
+    ("°C", "\\si{\\celsius}"),
+    ("°", "\\si{\\degree}"),
+    ("“", "``"),
+    ("”", "''"),
+    ("„", ",,"),
+    ("‘", "`"),
+    ("’", "'"),
+    ("—", "---"),
+    ("–", "--"),
+    ("…", "\\ldots{}"),
+    ("\u{a0}", "~"),
+    ("\u{202f}", "\\,"),
+    ("\u{ad}", ""),
+    ("\u{200b}", ""),
+];
+
+/// Formatting commands whose neighbouring runs are merged.
+const RUN_COMMANDS: &[&str] = &[
+    "\\textbf",
+    "\\emph",
+    "\\underline",
+    "\\textsubscript",
+    "\\textsuperscript",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Docx,
+    Odt,
+}
+
+impl Format {
+    fn of(path: &Path) -> Result<Self> {
+        match path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_lowercase)
+            .as_deref()
+        {
+            Some("docx") => Ok(Self::Docx),
+            Some("odt") => Ok(Self::Odt),
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+}
+
+/// A converted document: its title and body, before the preamble goes on.
+#[derive(Debug, Default)]
+struct Converted {
+    title: Option<String>,
+    body: String,
+    /// Definitions the body needs beyond the chemistry preamble.
+    preamble: &'static str,
+}
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["native", "no-compile", "no-cache"],
+        &["out", "lang"],
+    )?;
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+    let format = Format::of(&input)?;
+    let output = args
+        .value("out")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| input.with_extension("tex"));
+    let out_dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let stem = output
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid output file name")?;
+    let media = format!("{}-media", stem);
+
+    let converted = match args.flag("native") {
+        true => None,
+        false => pandoc(&input, format, &out_dir, &media)?,
+    };
+    let converted = match converted {
+        Some(converted) => converted,
+        None => convert(&input, format, &out_dir, &media)?,
+    };
+
+    let mut document = String::from("\\documentclass[11pt,a4paper]{article}\n");
+    document.push_str(PREAMBLE);
+    document.push_str(converted.preamble);
+    let body = fix_artifacts(&converted.body);
+    match &converted.title {
+        Some(title) => {
+            document.push_str(&format!("\n\\title{{{}}}\n\\date{{}}\n", title));
+            document.push_str("\n\\begin{document}\n\\maketitle\n\n");
+        }
+        None => document.push_str("\n\\begin{document}\n\n"),
+    }
+    document.push_str(body.trim());
+    document.push_str("\n\n\\end{document}\n");
+    let cyrillic = document
+        .chars()
+        .any(|c| ('\u{400}'..='\u{4ff}').contains(&c));
+    let lang = match args.value("lang") {
+        Some(spec) => Some(spec),
+        None => cyrillic.then_some("ru,en"),
+    };
+    if let Some(spec) = lang {
+        document = Languages::parse(spec, None)?.inject(&document);
+    }
+    fs::write(&output, document)
+        .with_context(|| format!("Failed to write file: {}", output.display()))?;
+    println!("LaTeX written to {}", output.display());
+
+    if args.flag("no-compile") {
+        return Ok(());
+    }
+    let scratch = TempDir::new("import")?;
+    let archive = pack::pack_project(&output, scratch.path())?;
+    let runner = Runner::new(api::build_client()?, !args.flag("no-cache"))?;
+    let mut job = Job::new(&archive, Path::new(""))?;
+    job.output = output.with_extension("pdf");
+    runner.run(&job, "").await.result?;
+    Ok(())
+}
+
+/// Converts with pandoc when chemtex is built with the `pandoc` feature and
+/// the program is installed; `None` otherwise.
+#[cfg(feature = "pandoc")]
+fn pandoc(input: &Path, format: Format, out_dir: &Path, media: &str) -> Result<Option<Converted>> {
+    use std::process::Command;
+
+    let input = input
+        .canonicalize()
+        .with_context(|| format!("Failed to read file: {}", input.display()))?;
+    let from = match format {
+        Format::Docx => "docx",
+        Format::Odt => "odt",
+    };
+    // Run from the output directory so image paths are relative to it.
+    let result = Command::new("pandoc")
+        .arg(&input)
+        .args([
+            "--from",
+            from,
+            "--to",
+            "latex",
+            "--standalone",
+            "--wrap=preserve",
+        ])
+        .arg(format!("--extract-media={}", media))
+        .current_dir(out_dir)
+        .output();
+    let output = match result {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("Failed to run pandoc"),
+    };
+    if !output.status.success() {
+        anyhow::bail!(
+            "pandoc failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let latex = String::from_utf8_lossy(&output.stdout);
+    let (preamble, body) = latex
+        .split_once("\\begin{document}")
+        .context("pandoc wrote no document")?;
+    let body = body.split("\\end{document}").next().unwrap_or(body);
+    let title = preamble
+        .find("\\title{")
+        .and_then(|start| braced(&preamble[start + "\\title".len()..]))
+        .map(|(title, _)| title.to_string());
+    Ok(Some(Converted {
+        title,
+        body: body.replace("\\maketitle", ""),
+        preamble: PANDOC_PREAMBLE,
+    }))
+}
+
+#[cfg(not(feature = "pandoc"))]
+fn pandoc(_: &Path, _: Format, _: &Path, _: &str) -> Result<Option<Converted>> {
+    Ok(None)
+}
+
+/// The built-in converter; images are copied to `out_dir/media`.
+fn convert(input: &Path, format: Format, out_dir: &Path, media: &str) -> Result<Converted> {
+    let file =
+        File::open(input).with_context(|| format!("Failed to open file: {}", input.display()))?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("{} is not a {:?} document", input.display(), format))?;
+    let mut writer = match format {
+        Format::Docx => Writer::docx(&mut archive, media)?,
+        Format::Odt => Writer::odt(&mut archive, media)?,
+    };
+    let root = match format {
+        Format::Docx => "word/document.xml",
+        Format::Odt => "content.xml",
+    };
+    let document = xml::parse(&read_part(&mut archive, root)?.context("No document body")?)
+        .with_context(|| format!("Failed to parse {}", root))?;
+    match format {
+        Format::Docx => {
+            let body = document.child("w:body").context("No document body")?;
+            for element in body.elements() {
+                writer.docx_block(element);
+            }
+        }
+        Format::Odt => {
+            let text = document
+                .child("office:body")
+                .and_then(|body| body.child("office:text"))
+                .context("Not a text document")?;
+            for element in text.elements() {
+                writer.odt_block(element, 0);
+            }
+        }
+    }
+    writer.close_blocks();
+
+    if !writer.images.is_empty() {
+        let dir = out_dir.join(media);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+        for (part, name) in &writer.images {
+            let Some(bytes) = read_bytes(&mut archive, part)? else {
+                eprintln!("Image {} is missing from the document", part);
+                continue;
+            };
+            let path = dir.join(name);
+            fs::write(&path, bytes)
+                .with_context(|| format!("Failed to write file: {}", path.display()))?;
+        }
+    }
+    for (name, count) in &writer.skipped {
+        eprintln!("Skipped {} {}", count, name);
+    }
+    let title = writer.title.take().or(writer.meta_title.take());
+    Ok(Converted {
+        title,
+        body: writer.out,
+        preamble: "",
+    })
+}
+
+fn read_bytes(archive: &mut ZipArchive<File>, name: &str) -> Result<Option<Vec<u8>>> {
+    let mut entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", name)),
+    };
+    let mut bytes = Vec::new();
+    entry
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("Failed to read {}", name))?;
+    Ok(Some(bytes))
+}
+
+fn read_part(archive: &mut ZipArchive<File>, name: &str) -> Result<Option<String>> {
+    Ok(read_bytes(archive, name)?.map(|bytes| String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn read_xml(archive: &mut ZipArchive<File>, name: &str) -> Result<Option<Element>> {
+    match read_part(archive, name)? {
+        Some(text) => xml::parse(&text)
+            .map(Some)
+            .with_context(|| format!("Failed to parse {}", name)),
+        None => Ok(None),
+    }
+}
+
+/// Run formatting, from a Word run or an OpenDocument text style.
+#[derive(Debug, Clone, Copy, Default)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    superscript: bool,
+    subscript: bool,
+}
+
+impl Style {
+    /// Wraps `text` in the commands for this style, leaving surrounding
+    /// spaces outside.
+    fn apply(self, text: &str) -> String {
+        let inner = text.trim();
+        if inner.is_empty() {
+            return text.to_string();
+        }
+        let mut out = inner.to_string();
+        for (on, command) in [
+            (self.subscript, "\\textsubscript"),
+            (self.superscript, "\\textsuperscript"),
+            (self.italic, "\\emph"),
+            (self.bold, "\\textbf"),
+            (self.underline, "\\underline"),
+        ] {
+            if on {
+                out = format!("{}{{{}}}", command, out);
+            }
+        }
+        let start = text.len() - text.trim_start().len();
+        let end = text.trim_end().len();
+        format!("{}{}{}", &text[..start], out, &text[end..])
+    }
+}
+
+/// Converts a document part by part into `out`.
+#[derive(Default)]
+struct Writer {
+    out: String,
+    title: Option<String>,
+    /// From the document properties, used when no paragraph is styled as
+    /// the title.
+    meta_title: Option<String>,
+    /// Environments of the open nested lists (Word lists are flat
+    /// paragraphs with a level).
+    lists: Vec<&'static str>,
+    /// An image was placed in a figure that a following caption may close.
+    figure_open: bool,
+    /// Zip part and file name of each image to copy.
+    images: Vec<(String, String)>,
+    media: String,
+    skipped: Vec<(String, usize)>,
+
+    /// Word: paragraph style ids → lowercase names.
+    styles: HashMap<String, String>,
+    /// Word: (numId, level) → numbered rather than bulleted.
+    numbering: HashMap<(String, String), bool>,
+    /// Word: relationship ids → targets.
+    relationships: HashMap<String, String>,
+    /// Word: footnote ids → footnotes.
+    footnotes: HashMap<String, Element>,
+
+    /// OpenDocument: automatic and named text styles.
+    text_styles: HashMap<String, Style>,
+    /// OpenDocument: paragraph styles → their parent style.
+    parents: HashMap<String, String>,
+    /// OpenDocument: list styles that number their items.
+    numbered_lists: Vec<String>,
+    /// OpenDocument: MathML of embedded formula objects by directory.
+    formulas: HashMap<String, Element>,
+}
+
+impl Writer {
+    fn docx(archive: &mut ZipArchive<File>, media: &str) -> Result<Self> {
+        let mut writer = Self {
+            media: media.to_string(),
+            ..Self::default()
+        };
+        if let Some(styles) = read_xml(archive, "word/styles.xml")? {
+            for style in styles.elements().filter(|e| e.name == "w:style") {
+                let (Some(id), Some(name)) = (
+                    style.attribute("w:styleId"),
+                    style.child("w:name").and_then(|n| n.attribute("w:val")),
+                ) else {
+                    continue;
+                };
+                writer.styles.insert(id.to_string(), name.to_lowercase());
+            }
+        }
+        if let Some(numbering) = read_xml(archive, "word/numbering.xml")? {
+            let mut abstract_formats: HashMap<(String, String), bool> = HashMap::new();
+            for definition in numbering.elements().filter(|e| e.name == "w:abstractNum") {
+                let id = definition.attribute("w:abstractNumId").unwrap_or_default();
+                for level in definition.elements().filter(|e| e.name == "w:lvl") {
+                    let format = level
+                        .child("w:numFmt")
+                        .and_then(|f| f.attribute("w:val"))
+                        .unwrap_or("bullet");
+                    abstract_formats.insert(
+                        (
+                            id.to_string(),
+                            level.attribute("w:ilvl").unwrap_or("0").to_string(),
+                        ),
+                        !matches!(format, "bullet" | "none"),
+                    );
+                }
+            }
+            for num in numbering.elements().filter(|e| e.name == "w:num") {
+                let (Some(id), Some(abstract_id)) = (
+                    num.attribute("w:numId"),
+                    num.child("w:abstractNumId")
+                        .and_then(|a| a.attribute("w:val")),
+                ) else {
+                    continue;
+                };
+                for ((definition, level), numbered) in &abstract_formats {
+                    if definition == abstract_id {
+                        writer
+                            .numbering
+                            .insert((id.to_string(), level.clone()), *numbered);
+                    }
+                }
+            }
+        }
+        if let Some(relationships) = read_xml(archive, "word/_rels/document.xml.rels")? {
+            for relationship in relationships.elements() {
+                if let (Some(id), Some(target)) = (
+                    relationship.attribute("Id"),
+                    relationship.attribute("Target"),
+                ) {
+                    writer
+                        .relationships
+                        .insert(id.to_string(), target.to_string());
+                }
+            }
+        }
+        if let Some(footnotes) = read_xml(archive, "word/footnotes.xml")? {
+            for footnote in footnotes.elements().filter(|e| e.name == "w:footnote") {
+                if let Some(id) = footnote.attribute("w:id") {
+                    writer.footnotes.insert(id.to_string(), footnote.clone());
+                }
+            }
+        }
+        if let Some(core) = read_xml(archive, "docProps/core.xml")? {
+            writer.meta_title = core
+                .find("dc:title")
+                .map(|t| escape(t.text().trim()))
+                .filter(|t| !t.is_empty());
+        }
+        Ok(writer)
+    }
+
+    fn odt(archive: &mut ZipArchive<File>, media: &str) -> Result<Self> {
+        let mut writer = Self {
+            media: media.to_string(),
+            ..Self::default()
+        };
+        let mut style_roots = Vec::new();
+        if let Some(styles) = read_xml(archive, "styles.xml")? {
+            style_roots.push(styles);
+        }
+        if let Some(content) = read_xml(archive, "content.xml")? {
+            style_roots.push(content);
+        }
+        for root in &style_roots {
+            let mut styles = Vec::new();
+            root.find_all("style:style", &mut styles);
+            for style in styles {
+                let Some(name) = style.attribute("style:name") else {
+                    continue;
+                };
+                if let Some(parent) = style.attribute("style:parent-style-name") {
+                    writer.parents.insert(name.to_string(), parent.to_string());
+                }
+                if let Some(properties) = style.child("style:text-properties") {
+                    // `super 58%`, `33% 58%`, `-33% 58%` or `0% 100%`.
+                    let position = properties
+                        .attribute("style:text-position")
+                        .and_then(|p| p.split_whitespace().next())
+                        .unwrap_or_default();
+                    let raised = position.trim_end_matches('%').parse::<f64>().unwrap_or(0.0);
+                    writer.text_styles.insert(
+                        name.to_string(),
+                        Style {
+                            bold: properties.attribute("fo:font-weight") == Some("bold"),
+                            italic: properties.attribute("fo:font-style") == Some("italic"),
+                            underline: properties
+                                .attribute("style:text-underline-style")
+                                .is_some_and(|u| u != "none"),
+                            superscript: position == "super" || raised > 0.0,
+                            subscript: position == "sub" || raised < 0.0,
+                        },
+                    );
+                }
+            }
+            let mut lists = Vec::new();
+            root.find_all("text:list-style", &mut lists);
+            for list in lists {
+                let numbered = list
+                    .elements()
+                    .next()
+                    .is_some_and(|level| level.name == "text:list-level-style-number");
+                if let (true, Some(name)) = (numbered, list.attribute("style:name")) {
+                    writer.numbered_lists.push(name.to_string());
+                }
+            }
+        }
+        let objects: Vec<String> = archive
+            .file_names()
+            .filter(|name| name.ends_with("/content.xml"))
+            .map(str::to_string)
+            .collect();
+        for name in objects {
+            if let Some(object) = read_xml(archive, &name)? {
+                if local_name(&object.name) == "math" {
+                    let dir = name.trim_end_matches("/content.xml").to_string();
+                    writer.formulas.insert(dir, object);
+                }
+            }
+        }
+        if let Some(meta) = read_xml(archive, "meta.xml")? {
+            writer.meta_title = meta
+                .find("dc:title")
+                .map(|t| escape(t.text().trim()))
+                .filter(|t| !t.is_empty());
+        }
+        Ok(writer)
+    }
+
+    fn skip(&mut self, what: &str) {
+        match self.skipped.iter_mut().find(|(name, _)| name == what) {
+            Some((_, count)) => *count += 1,
+            None => self.skipped.push((what.to_string(), 1)),
+        }
+    }
+
+    /// Ends open lists and figures before a block that belongs to neither.
+    fn close_blocks(&mut self) {
+        self.close_lists(0);
+        self.close_figure();
+    }
+
+    fn close_lists(&mut self, depth: usize) {
+        while self.lists.len() > depth {
+            let environment = self.lists.pop().unwrap_or("itemize");
+            self.out.push_str(&format!("\\end{{{}}}\n", environment));
+        }
+        if depth == 0 && !self.out.ends_with("\n\n") && !self.out.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    fn close_figure(&mut self) {
+        if self.figure_open {
+            self.out.push_str("\\end{figure}\n\n");
+            self.figure_open = false;
+        }
+    }
+
+    fn list_item(&mut self, level: usize, numbered: bool, text: &str) {
+        self.close_figure();
+        let environment = match numbered {
+            true => "enumerate",
+            false => "itemize",
+        };
+        if self.lists.len() > level + 1 {
+            self.close_lists(level + 1);
+        }
+        if self.lists.len() == level + 1 && self.lists[level] != environment {
+            self.close_lists(level);
+        }
+        while self.lists.len() < level + 1 {
+            self.out.push_str(&format!("\\begin{{{}}}\n", environment));
+            self.lists.push(environment);
+        }
+        self.out.push_str(&format!("\\item {}\n", text.trim()));
+    }
+
+    fn heading(&mut self, level: usize, text: &str) {
+        self.close_blocks();
+        let command = HEADINGS[level.clamp(1, HEADINGS.len()) - 1];
+        self.out
+            .push_str(&format!("\\{}{{{}}}\n\n", command, text.trim()));
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        self.close_blocks();
+        let text = text.trim();
+        if !text.is_empty() {
+            self.out.push_str(text);
+            self.out.push_str("\n\n");
+        }
+    }
+
+    /// A figure of the images of an otherwise empty paragraph.
+    fn figure(&mut self, images: &[String]) {
+        self.close_blocks();
+        self.out.push_str("\\begin{figure}[h]\n\\centering\n");
+        for image in images {
+            self.out.push_str(image);
+            self.out.push('\n');
+        }
+        self.figure_open = true;
+    }
+
+    fn caption(&mut self, text: &str) {
+        self.out
+            .push_str(&format!("\\caption{{{}}}\n", text.trim()));
+        self.close_figure();
+    }
+
+    /// `\includegraphics` for an image part, copied later.
+    fn image(&mut self, part: &str) -> Option<String> {
+        let name = part
+            .rsplit('/')
+            .next()
+            .unwrap_or(part)
+            .replace(char::is_whitespace, "-");
+        let extension = name.rsplit('.').next().unwrap_or_default().to_lowercase();
+        if !IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            self.skip(&format!(
+                ".{} image(s); convert them to PNG or PDF",
+                extension
+            ));
+            return None;
+        }
+        if !self.images.iter().any(|(p, _)| p == part) {
+            self.images.push((part.to_string(), name.clone()));
+        }
+        Some(format!(
+            "\\includegraphics[width=0.8\\linewidth]{{{}/{}}}",
+            self.media, name
+        ))
+    }
+
+    fn table(&mut self, rows: Vec<Vec<(String, usize)>>) {
+        self.close_blocks();
+        let columns = rows
+            .iter()
+            .map(|row| row.iter().map(|(_, span)| span).sum::<usize>())
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        self.out.push_str(&format!(
+            "\\begin{{table}}[h]\n\\centering\n\\begin{{tabular}}{{{}}}\n\\toprule\n",
+            "l".repeat(columns)
+        ));
+        for (index, row) in rows.iter().enumerate() {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|(text, span)| match span {
+                    1 => text.clone(),
+                    n => format!("\\multicolumn{{{}}}{{l}}{{{}}}", n, text),
+                })
+                .collect();
+            self.out.push_str(&format!("{} \\\\\n", cells.join(" & ")));
+            if index == 0 && rows.len() > 1 {
+                self.out.push_str("\\midrule\n");
+            }
+        }
+        self.out
+            .push_str("\\bottomrule\n\\end{tabular}\n\\end{table}\n\n");
+    }
+
+    // Word
+
+    fn docx_block(&mut self, element: &Element) {
+        match element.name.as_str() {
+            "w:p" => self.docx_paragraph(element),
+            "w:tbl" => {
+                let rows = element
+                    .elements()
+                    .filter(|e| e.name == "w:tr")
+                    .map(|row| {
+                        row.elements()
+                            .filter(|e| e.name == "w:tc")
+                            .map(|cell| self.docx_cell(cell))
+                            .collect()
+                    })
+                    .collect();
+                self.table(rows);
+            }
+            "w:sdt" => {
+                if let Some(content) = element.child("w:sdtContent") {
+                    for child in content.elements() {
+                        self.docx_block(child);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn docx_cell(&mut self, cell: &Element) -> (String, usize) {
+        let properties = cell.child("w:tcPr");
+        let span = properties
+            .and_then(|p| p.child("w:gridSpan"))
+            .and_then(|s| s.attribute("w:val"))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+        // A cell merged into the one above stays empty.
+        let continued = properties
+            .and_then(|p| p.child("w:vMerge"))
+            .is_some_and(|m| m.attribute("w:val") != Some("restart"));
+        if continued {
+            return (String::new(), span);
+        }
+        let text: Vec<String> = cell
+            .elements()
+            .filter(|e| e.name == "w:p")
+            .map(|p| self.docx_inline(p).trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+        (text.join(" "), span)
+    }
+
+    fn docx_paragraph(&mut self, paragraph: &Element) {
+        let properties = paragraph.child("w:pPr");
+        let style = properties
+            .and_then(|p| p.child("w:pStyle"))
+            .and_then(|s| s.attribute("w:val"))
+            .map(|id| {
+                self.styles
+                    .get(id)
+                    .cloned()
+                    .unwrap_or_else(|| id.to_lowercase())
+            })
+            .unwrap_or_default();
+        let text = self.docx_inline(paragraph);
+
+        let mut blips = Vec::new();
+        paragraph.find_all("a:blip", &mut blips);
+        let targets: Vec<String> = blips
+            .iter()
+            .filter_map(|blip| blip.attribute("r:embed"))
+            .filter_map(|id| self.relationships.get(id).cloned())
+            .collect();
+        let images: Vec<String> = targets
+            .into_iter()
+            .filter_map(|target| {
+                let part = match target.strip_prefix('/') {
+                    Some(absolute) => absolute.to_string(),
+                    None => format!("word/{}", target),
+                };
+                self.image(&part)
+            })
+            .collect();
+        if text.trim().is_empty() && !images.is_empty() {
+            return self.figure(&images);
+        }
+        if self.figure_open && style.contains("caption") {
+            return self.caption(&text);
+        }
+
+        let numbering = properties.and_then(|p| p.child("w:numPr"));
+        let number = numbering
+            .and_then(|n| n.child("w:numId"))
+            .and_then(|n| n.attribute("w:val"))
+            .filter(|id| *id != "0");
+        if let Some(id) = number {
+            let level = numbering
+                .and_then(|n| n.child("w:ilvl"))
+                .and_then(|l| l.attribute("w:val"))
+                .unwrap_or("0");
+            let numbered = self
+                .numbering
+                .get(&(id.to_string(), level.to_string()))
+                .copied()
+                .unwrap_or(false);
+            self.list_item(level.parse().unwrap_or(0), numbered, &text);
+            for image in &images {
+                self.out.push_str(&format!("\n{}\n", image));
+            }
+            return;
+        }
+
+        let outline = properties
+            .and_then(|p| p.child("w:outlineLvl"))
+            .and_then(|l| l.attribute("w:val"))
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|level| *level < 9);
+        if style == "title" {
+            self.close_blocks();
+            self.title = Some(text.trim().to_string()).filter(|t| !t.is_empty());
+        } else if let Some(level) = style
+            .strip_prefix("heading ")
+            .and_then(|n| n.parse::<usize>().ok())
+            .or(outline.map(|level| level + 1))
+        {
+            self.heading(level, &text);
+        } else {
+            self.paragraph(&text);
+        }
+        if !images.is_empty() {
+            self.figure(&images);
+        }
+    }
+
+    fn docx_inline(&mut self, element: &Element) -> String {
+        let mut out = String::new();
+        for child in element.elements() {
+            match child.name.as_str() {
+                "w:r" => out.push_str(&self.docx_run(child)),
+                "w:hyperlink" => {
+                    let text = self.docx_inline(child);
+                    let target = child
+                        .attribute("r:id")
+                        .and_then(|id| self.relationships.get(id));
+                    match target {
+                        Some(url) => out.push_str(&format!(
+                            "\\href{{{}}}{{{}}}",
+                            url.replace('%', "\\%").replace('#', "\\#"),
+                            text
+                        )),
+                        None => out.push_str(&text),
+                    }
+                }
+                "w:ins" | "w:smartTag" | "w:customXml" | "w:fldSimple" | "w:sdt"
+                | "w:sdtContent" => out.push_str(&self.docx_inline(child)),
+                "m:oMathPara" => {
+                    let math: Vec<String> = child
+                        .elements()
+                        .filter(|e| e.name == "m:oMath")
+                        .map(|e| omml(e).trim().to_string())
+                        .collect();
+                    out.push_str(&format!("\n\\[\n{}\n\\]\n", math.join(" \\\\\n")));
+                }
+                "m:oMath" => out.push_str(&format!("${}$", omml(child).trim())),
+                _ => {}
+            }
+        }
+        out
+    }
+
+    fn docx_run(&mut self, run: &Element) -> String {
+        let properties = run.child("w:rPr");
+        let on = |name: &str| {
+            properties
+                .and_then(|p| p.child(name))
+                .is_some_and(|e| !matches!(e.attribute("w:val"), Some("0" | "false" | "none")))
+        };
+        let position = properties
+            .and_then(|p| p.child("w:vertAlign"))
+            .and_then(|v| v.attribute("w:val"));
+        let style = Style {
+            bold: on("w:b"),
+            italic: on("w:i"),
+            underline: on("w:u"),
+            superscript: position == Some("superscript"),
+            subscript: position == Some("subscript"),
+        };
+        let mut text = String::new();
+        let mut extra = String::new();
+        for child in run.elements() {
+            match child.name.as_str() {
+                "w:t" => text.push_str(&escape(&child.text())),
+                "w:tab" => text.push(' '),
+                "w:noBreakHyphen" => text.push('-'),
+                "w:softHyphen" => text.push_str("\\-"),
+                "w:br" => match child.attribute("w:type") {
+                    Some("page") => extra.push_str("\n\\newpage\n"),
+                    _ => text.push_str("\\\\\n"),
+                },
+                "w:sym" => {
+                    let symbol = child
+                        .attribute("w:char")
+                        .and_then(|code| u32::from_str_radix(code, 16).ok())
+                        .and_then(char::from_u32);
+                    if let Some(symbol) = symbol {
+                        text.push(symbol);
+                    }
+                }
+                "w:footnoteReference" => {
+                    let note = child
+                        .attribute("w:id")
+                        .and_then(|id| self.footnotes.get(id))
+                        .cloned();
+                    if let Some(note) = note {
+                        let text: Vec<String> = note
+                            .elements()
+                            .filter(|e| e.name == "w:p")
+                            .map(|p| self.docx_inline(p).trim().to_string())
+                            .collect();
+                        extra.push_str(&format!("\\footnote{{{}}}", text.join(" ")));
+                    }
+                }
+                _ => {}
+            }
+        }
+        style.apply(&text) + &extra
+    }
+
+    // OpenDocument
+
+    /// The named style an automatic paragraph style is based on.
+    fn odt_style<'a>(&'a self, name: &'a str) -> &'a str {
+        let mut name = name;
+        for _ in 0..8 {
+            match self.parents.get(name) {
+                Some(parent) if name.starts_with('P') => name = parent,
+                _ => break,
+            }
+        }
+        name
+    }
+
+    fn odt_block(&mut self, element: &Element, depth: usize) {
+        match element.name.as_str() {
+            "text:h" => {
+                let level = element
+                    .attribute("text:outline-level")
+                    .and_then(|l| l.parse().ok())
+                    .unwrap_or(1);
+                let text = self.odt_inline(element);
+                self.heading(level, &text);
+            }
+            "text:p" => {
+                let style = self
+                    .odt_style(element.attribute("text:style-name").unwrap_or_default())
+                    .to_lowercase();
+                let mut images = Vec::new();
+                let text = self.odt_paragraph(element, &mut images);
+                if text.trim().is_empty() && !images.is_empty() {
+                    return self.figure(&images);
+                }
+                if style == "title" {
+                    self.close_blocks();
+                    self.title = Some(text.trim().to_string()).filter(|t| !t.is_empty());
+                } else if let Some(level) = style
+                    .strip_prefix("heading_20_")
+                    .and_then(|n| n.parse::<usize>().ok())
+                {
+                    self.heading(level, &text);
+                } else if self.figure_open
+                    && (style.contains("caption") || style == "figure" || style == "illustration")
+                {
+                    self.caption(&text);
+                } else {
+                    self.paragraph(&text);
+                }
+                if !images.is_empty() {
+                    self.figure(&images);
+                }
+            }
+            "text:list" => self.odt_list(element, depth, None),
+            "table:table" => {
+                let mut rows = Vec::new();
+                let mut found = Vec::new();
+                element.find_all("table:table-row", &mut found);
+                for row in found {
+                    let cells = row
+                        .elements()
+                        .filter(|e| e.name == "table:table-cell")
+                        .map(|cell| {
+                            let span = cell
+                                .attribute("table:number-columns-spanned")
+                                .and_then(|s| s.parse().ok())
+                                .unwrap_or(1);
+                            let text: Vec<String> = cell
+                                .elements()
+                                .filter(|e| e.name == "text:p")
+                                .map(|p| self.odt_inline(p).trim().to_string())
+                                .filter(|t| !t.is_empty())
+                                .collect();
+                            (text.join(" "), span)
+                        })
+                        .collect();
+                    rows.push(cells);
+                }
+                self.table(rows);
+            }
+            "text:section" => {
+                for child in element.elements() {
+                    self.odt_block(child, depth);
+                }
+            }
+            "text:table-of-content" => {
+                self.close_blocks();
+                self.out.push_str("\\tableofcontents\n\n");
+            }
+            _ => {}
+        }
+    }
+
+    fn odt_list(&mut self, list: &Element, depth: usize, style: Option<&str>) {
+        let style = list
+            .attribute("text:style-name")
+            .or(style)
+            .map(String::from);
+        let numbered = style
+            .as_ref()
+            .is_some_and(|s| self.numbered_lists.contains(s));
+        for item in list.elements().filter(|e| e.name == "text:list-item") {
+            let mut text = String::new();
+            for child in item.elements() {
+                match child.name.as_str() {
+                    "text:p" | "text:h" => {
+                        let mut images = Vec::new();
+                        text.push_str(&self.odt_paragraph(child, &mut images));
+                        for image in images {
+                            text.push_str(&format!("\n{}\n", image));
+                        }
+                    }
+                    "text:list" => {
+                        self.list_item(depth, numbered, &text);
+                        text.clear();
+                        self.odt_list(child, depth + 1, style.as_deref());
+                    }
+                    _ => {}
+                }
+            }
+            if !text.trim().is_empty() {
+                self.list_item(depth, numbered, &text);
+            }
+        }
+        if depth == 0 {
+            self.close_lists(0);
+        }
+    }
+
+    /// The text of a paragraph; its images are collected separately.
+    fn odt_paragraph(&mut self, paragraph: &Element, images: &mut Vec<String>) -> String {
+        let mut frames = Vec::new();
+        paragraph.find_all("draw:frame", &mut frames);
+        for frame in frames {
+            if let Some(part) = frame
+                .child("draw:image")
+                .and_then(|i| i.attribute("xlink:href"))
+            {
+                if let Some(image) = self.image(part) {
+                    images.push(image);
+                }
+            }
+        }
+        self.odt_inline(paragraph)
+    }
+
+    fn odt_inline(&mut self, element: &Element) -> String {
+        let mut out = String::new();
+        for node in &element.children {
+            let child = match node {
+                Node::Text(text) => {
+                    out.push_str(&escape(text));
+                    continue;
+                }
+                Node::Element(child) => child,
+            };
+            match child.name.as_str() {
+                "text:span" => {
+                    let style = child
+                        .attribute("text:style-name")
+                        .and_then(|name| self.text_styles.get(name))
+                        .copied()
+                        .unwrap_or_default();
+                    let text = self.odt_inline(child);
+                    out.push_str(&style.apply(&text));
+                }
+                "text:s" => {
+                    let count = child
+                        .attribute("text:c")
+                        .and_then(|c| c.parse().ok())
+                        .unwrap_or(1);
+                    out.push_str(&" ".repeat(count));
+                }
+                "text:tab" => out.push(' '),
+                "text:line-break" => out.push_str("\\\\\n"),
+                "text:a" => {
+                    let text = self.odt_inline(child);
+                    match child.attribute("xlink:href") {
+                        Some(url) if !url.starts_with('#') => out.push_str(&format!(
+                            "\\href{{{}}}{{{}}}",
+                            url.replace('%', "\\%").replace('#', "\\#"),
+                            text
+                        )),
+                        _ => out.push_str(&text),
+                    }
+                }
+                "text:note" => {
+                    let body: Vec<String> = child
+                        .child("text:note-body")
+                        .map(|body| {
+                            body.elements()
+                                .map(|p| self.odt_inline(p).trim().to_string())
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    out.push_str(&format!("\\footnote{{{}}}", body.join(" ")));
+                }
+                "draw:frame" => {
+                    let object = child
+                        .child("draw:object")
+                        .and_then(|o| o.attribute("xlink:href"))
+                        .map(|href| href.trim_start_matches("./").to_string());
+                    match object.and_then(|dir| self.formulas.get(&dir)) {
+                        Some(math) => out.push_str(&format!("${}$", mathml(math).trim())),
+                        None if child.child("draw:object").is_some() => {
+                            self.skip("embedded object(s)")
+                        }
+                        None => {}
+                    }
+                }
+                "text:soft-page-break"
+                | "text:bookmark"
+                | "text:bookmark-start"
+                | "text:bookmark-end"
+                | "office:annotation"
+                | "office:annotation-end" => {}
+                _ => out.push_str(&self.odt_inline(child)),
+            }
+        }
+        out
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Text inside an equation: symbols become commands, LaTeX specials are
+/// escaped.
+fn math_text(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match MATH_SYMBOLS.iter().find(|(symbol, _)| *symbol == c) {
+            Some((_, command)) => {
+                out.push_str(command);
+                if command.ends_with(|c: char| c.is_ascii_alphabetic()) {
+                    out.push(' ');
+                }
+            }
+            None => match c {
+                '{' | '}' | '%' | '#' | '&' | '$' | '_' => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                '\\' => out.push_str("\\backslash "),
+                _ => out.push(c),
+            },
+        }
+    }
+    out
+}
+
+/// `{x}` as a group: braces unless it is a single character.
+fn group(text: &str) -> String {
+    let text = text.trim();
+    match text.chars().count() {
+        1 => text.to_string(),
+        _ => format!("{{{}}}", text),
+    }
+}
+
+const FUNCTIONS: &[&str] = &["sin", "cos", "tan", "ln", "log", "exp", "lim", "max", "min"];
+
+/// Office Math (OMML) to LaTeX.
+fn omml(element: &Element) -> String {
+    let part = |name: &str| element.child(name).map(omml).unwrap_or_default();
+    let chr = |default: &str| -> String {
+        element
+            .elements()
+            .find(|e| e.name.ends_with("Pr"))
+            .and_then(|p| p.child("m:chr"))
+            .and_then(|c| c.attribute("m:val"))
+            .unwrap_or(default)
+            .to_string()
+    };
+    match element.name.as_str() {
+        "m:r" => element
+            .elements()
+            .filter(|e| e.name == "m:t")
+            .map(|t| math_text(&t.text()))
+            .collect(),
+        "m:f" => format!("\\frac{{{}}}{{{}}}", part("m:num"), part("m:den")),
+        "m:sSup" => format!("{}^{}", group(&part("m:e")), group(&part("m:sup"))),
+        "m:sSub" => format!("{}_{}", group(&part("m:e")), group(&part("m:sub"))),
+        "m:sSubSup" => format!(
+            "{}_{}^{}",
+            group(&part("m:e")),
+            group(&part("m:sub")),
+            group(&part("m:sup"))
+        ),
+        "m:sPre" => format!(
+            "{{}}_{}^{}{}",
+            group(&part("m:sub")),
+            group(&part("m:sup")),
+            part("m:e")
+        ),
+        "m:rad" => match part("m:deg").trim() {
+            "" => format!("\\sqrt{{{}}}", part("m:e")),
+            degree => format!("\\sqrt[{}]{{{}}}", degree, part("m:e")),
+        },
+        "m:d" => {
+            let properties = element.child("m:dPr");
+            let delimiter = |name: &str, default: &str| -> String {
+                let c = properties
+                    .and_then(|p| p.child(name))
+                    .and_then(|c| c.attribute("m:val"))
+                    .unwrap_or(default);
+                match c {
+                    "" => ".".to_string(),
+                    "{" => "\\{".to_string(),
+                    "}" => "\\}".to_string(),
+                    "〈" | "⟨" => "\\langle".to_string(),
+                    "〉" | "⟩" => "\\rangle".to_string(),
+                    c => c.to_string(),
+                }
+            };
+            let inner: Vec<String> = element
+                .elements()
+                .filter(|e| e.name == "m:e")
+                .map(omml)
+                .collect();
+            format!(
+                "\\left{} {} \\right{}",
+                delimiter("m:begChr", "("),
+                inner.join(", "),
+                delimiter("m:endChr", ")")
+            )
+        }
+        "m:nary" => {
+            let operator = match chr("∫").as_str() {
+                "∑" => "\\sum",
+                "∏" => "\\prod",
+                "∬" => "\\iint",
+                "∮" => "\\oint",
+                _ => "\\int",
+            };
+            let mut out = operator.to_string();
+            let (sub, sup) = (part("m:sub"), part("m:sup"));
+            if !sub.trim().is_empty() {
+                out.push_str(&format!("_{}", group(&sub)));
+            }
+            if !sup.trim().is_empty() {
+                out.push_str(&format!("^{}", group(&sup)));
+            }
+            format!("{} {}", out, part("m:e"))
+        }
+        "m:func" => {
+            let name = part("m:fName");
+            let name = match FUNCTIONS.contains(&name.trim()) {
+                true => format!("\\{}", name.trim()),
+                false => name,
+            };
+            format!("{} {}", name, part("m:e"))
+        }
+        "m:acc" => {
+            let accent = match chr("\u{302}").as_str() {
+                "\u{307}" => "\\dot",
+                "\u{308}" => "\\ddot",
+                "\u{303}" => "\\tilde",
+                "\u{304}" | "\u{305}" => "\\bar",
+                "\u{20d7}" => "\\vec",
+                _ => "\\hat",
+            };
+            format!("{}{{{}}}", accent, part("m:e"))
+        }
+        "m:bar" => format!("\\overline{{{}}}", part("m:e")),
+        "m:limLow" => format!("{}_{}", part("m:e"), group(&part("m:lim"))),
+        "m:limUpp" => format!("{}^{}", part("m:e"), group(&part("m:lim"))),
+        "m:eqArr" => {
+            let rows: Vec<String> = element
+                .elements()
+                .filter(|e| e.name == "m:e")
+                .map(omml)
+                .collect();
+            format!(
+                "\\begin{{aligned}} {} \\end{{aligned}}",
+                rows.join(" \\\\ ")
+            )
+        }
+        "m:m" => {
+            let rows: Vec<String> = element
+                .elements()
+                .filter(|e| e.name == "m:mr")
+                .map(|row| {
+                    let cells: Vec<String> = row
+                        .elements()
+                        .filter(|e| e.name == "m:e")
+                        .map(omml)
+                        .collect();
+                    cells.join(" & ")
+                })
+                .collect();
+            format!("\\begin{{matrix}} {} \\end{{matrix}}", rows.join(" \\\\ "))
+        }
+        name if name.ends_with("Pr") => String::new(),
+        _ => element.elements().map(omml).collect(),
+    }
+}
+
+/// MathML, as OpenDocument formula objects store it, to LaTeX.
+fn mathml(element: &Element) -> String {
+    let parts: Vec<String> = element
+        .elements()
+        .filter(|e| local_name(&e.name) != "annotation")
+        .map(mathml)
+        .collect();
+    let nth = |i: usize| parts.get(i).map(String::as_str).unwrap_or_default();
+    match local_name(&element.name) {
+        "mi" | "mn" | "mo" => math_text(element.text().trim()),
+        "mtext" => format!("\\text{{{}}}", escape(&element.text())),
+        "mspace" => "\\,".to_string(),
+        "mfrac" => format!("\\frac{{{}}}{{{}}}", nth(0), nth(1)),
+        "msup" | "mover" => format!("{}^{}", group(nth(0)), group(nth(1))),
+        "msub" | "munder" => format!("{}_{}", group(nth(0)), group(nth(1))),
+        "msubsup" | "munderover" => {
+            format!("{}_{}^{}", group(nth(0)), group(nth(1)), group(nth(2)))
+        }
+        "msqrt" => format!("\\sqrt{{{}}}", parts.join(" ")),
+        "mroot" => format!("\\sqrt[{}]{{{}}}", nth(1), nth(0)),
+        "mfenced" => format!(
+            "\\left{} {} \\right{}",
+            element.attribute("open").unwrap_or("("),
+            parts.join(", "),
+            element.attribute("close").unwrap_or(")")
+        ),
+        "mtable" => format!("\\begin{{matrix}} {} \\end{{matrix}}", parts.join(" \\\\ ")),
+        "mtr" => parts.join(" & "),
+        // The first child is the presentation, the rest annotations.
+        "semantics" => nth(0).to_string(),
+        _ => parts.join(" "),
+    }
+}
+
+/// Cleans up what converters leave behind: split formatting runs, formulas
+/// typed with sub- and superscripts, empty commands, typed heading numbers,
+/// Unicode punctuation and symbols, and stray line breaks.
+fn fix_artifacts(body: &str) -> String {
+    let mut text = body.to_string();
+    for command in RUN_COMMANDS {
+        text = merge_runs(&text, command);
+        text = text.replace(&format!("{}{{}}", command), "");
+    }
+    text = chemical_formulas(&text);
+    text = symbols(&text);
+    text = heading_numbers(&text);
+
+    let mut out = String::with_capacity(text.len());
+    let mut blank = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank += 1;
+            if blank == 1 {
+                // A forced break ending a paragraph adds an empty line.
+                if out.ends_with("\\\\\n") {
+                    out.truncate(out.len() - 3);
+                    out.push('\n');
+                }
+                out.push('\n');
+            }
+            continue;
+        }
+        blank = 0;
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// `{body}rest` → (`body`, `rest`), braces balanced.
+fn braced(text: &str) -> Option<(&str, &str)> {
+    let text = text.strip_prefix('{')?;
+    let mut depth = 1;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((&text[..i], &text[i + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `\textbf{a}\textbf{b}` → `\textbf{ab}`.
+fn merge_runs(text: &str, command: &str) -> String {
+    let open = format!("{}{{", command);
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(&open) {
+        out.push_str(&rest[..start]);
+        let mut merged = String::new();
+        let mut tail = &rest[start + command.len()..];
+        while let Some((body, after)) = braced(tail) {
+            merged.push_str(body);
+            match after.strip_prefix(command) {
+                Some(next) if next.starts_with('{') => tail = next,
+                _ => {
+                    tail = after;
+                    break;
+                }
+            }
+        }
+        if tail.len() == rest[start + command.len()..].len() {
+            // Unbalanced: leave the rest alone.
+            out.push_str(&rest[start..]);
+            return out;
+        }
+        out.push_str(&format!("{}{{{}}}", command, merged));
+        rest = tail;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// `H\textsubscript{2}SO\textsubscript{4}` → `\ce{H2SO4}` when it reads as
+/// a formula.
+fn chemical_formulas(text: &str) -> String {
+    const SUB: &str = "\\textsubscript";
+    const SUP: &str = "\\textsuperscript";
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        let found = match (rest.find(SUB), rest.find(SUP)) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let Some(found) = found else {
+            out.push_str(rest);
+            return out;
+        };
+        // Back to the start of the word the script belongs to.
+        let start = rest[..found]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '(' | ')' | '[' | ']')))
+            .map_or(0, |i| i + 1);
+        let mut formula = rest[start..found].to_string();
+        let mut tail = &rest[found..];
+        let mut scripts = 0;
+        loop {
+            let (script, after) = if let Some(after) = tail.strip_prefix(SUB) {
+                match braced(after) {
+                    Some((body, after)) if body.chars().all(|c| c.is_ascii_digit()) => {
+                        (body.to_string(), after)
+                    }
+                    _ => break,
+                }
+            } else if let Some(after) = tail.strip_prefix(SUP) {
+                match braced(after) {
+                    Some((body, after)) => {
+                        let charge = body.replace('−', "-");
+                        let valid = !charge.is_empty()
+                            && charge.ends_with(['+', '-'])
+                            && charge[..charge.len() - 1]
+                                .chars()
+                                .all(|c| c.is_ascii_digit());
+                        match valid {
+                            true => (format!("^{}", charge), after),
+                            false => break,
+                        }
+                    }
+                    None => break,
+                }
+            } else {
+                break;
+            };
+            formula.push_str(&script);
+            scripts += 1;
+            let letters = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '(' | ')' | '[' | ']')))
+                .unwrap_or(after.len());
+            formula.push_str(&after[..letters]);
+            tail = &after[letters..];
+        }
+        let consumed = rest.len() - tail.len();
+        let is_formula = scripts > 0
+            && formula.starts_with(|c: char| c.is_ascii_uppercase() || c == '(' || c == '[')
+            && Species::parse(&formula).is_ok();
+        if is_formula {
+            out.push_str(&rest[..start]);
+            out.push_str(&format!("\\ce{{{}}}", formula));
+        } else {
+            // Not a formula: keep everything up to the end of the first
+            // script and look again after it.
+            let script_end = rest[found..].find('}').map_or(consumed, |i| found + i + 1);
+            out.push_str(&rest[..script_end]);
+            rest = &rest[script_end..];
+            continue;
+        }
+        rest = &rest[consumed..];
+    }
+}
+
+/// Unicode symbols outside math become LaTeX.
+fn symbols(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_math = false;
+    let mut display = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let rest = &text[i..];
+        if rest.starts_with("\\[") || rest.starts_with("\\]") {
+            display = rest.starts_with("\\[");
+            out.push_str(&rest[..2]);
+            chars.next();
+            continue;
+        }
+        if c == '\\' {
+            // Keep escaped characters, \$ included, as they are.
+            out.push(c);
+            if let Some((_, next)) = chars.next() {
+                out.push(next);
+            }
+            continue;
+        }
+        if c == '$' {
+            in_math = !in_math;
+            out.push(c);
+            continue;
+        }
+        if in_math || display {
+            match MATH_SYMBOLS.iter().find(|(symbol, _)| *symbol == c) {
+                Some((_, command)) => {
+                    out.push_str(command);
+                    out.push(' ');
+                }
+                None => out.push(c),
+            }
+            continue;
+        }
+        if let Some((symbol, latex)) = TEXT_SYMBOLS.iter().find(|(s, _)| rest.starts_with(s)) {
+            out.push_str(latex);
+            for _ in 1..symbol.chars().count() {
+                chars.next();
+            }
+            continue;
+        }
+        match MATH_SYMBOLS.iter().find(|(symbol, _)| *symbol == c) {
+            Some((_, command)) => out.push_str(&format!("${}$", command)),
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+/// `\section{2.1. Results}` → `\section{Results}`: the class numbers them.
+fn heading_numbers(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let heading = HEADINGS.iter().find_map(|h| {
+            line.strip_prefix(&format!("\\{}{{", h))
+                .map(|rest| (h, rest))
+        });
+        let Some((command, rest)) = heading else {
+            out.push_str(line);
+            continue;
+        };
+        let number = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(0);
+        let numbered = number > 0
+            && rest[..number].starts_with(|c: char| c.is_ascii_digit())
+            && rest[number..].starts_with([' ', ')']);
+        match numbered {
+            true => out.push_str(&format!(
+                "\\{}{{{}",
+                command,
+                rest[number..].trim_start_matches(')').trim_start()
+            )),
+            false => out.push_str(line),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    #[test]
+    fn converter_artifacts_are_cleaned_up() {
+        assert_eq!(
+            fix_artifacts(
+                "\\section{2.1. Results}\n\\textbf{Yi}\\textbf{eld}\\emph{} of \
+                 H\\textsubscript{2}SO\\textsubscript{4} and SO\\textsubscript{4}\\textsuperscript{2−}\n"
+            ),
+            "\\section{Results}\n\\textbf{Yield} of \\ce{H2SO4} and \\ce{SO4^2-}\n"
+        );
+        // Only what reads as a formula becomes one.
+        assert_eq!(
+            chemical_formulas("x\\textsubscript{i} and 10\\textsuperscript{th}"),
+            "x\\textsubscript{i} and 10\\textsuperscript{th}"
+        );
+    }
+
+    #[test]
+    fn equations_become_latex() {
+        let fraction = xml::parse(
+            "<m:f><m:num><m:r><m:t>a</m:t></m:r></m:num>\
+             <m:den><m:sSup><m:e><m:r><m:t>b</m:t></m:r></m:e>\
+             <m:sup><m:r><m:t>2</m:t></m:r></m:sup></m:sSup></m:den></m:f>",
+        )
+        .unwrap();
+        assert_eq!(omml(&fraction), "\\frac{a}{b^2}");
+        let mathml_fraction =
+            xml::parse("<math><mfrac><mi>x</mi><msub><mi>y</mi><mn>10</mn></msub></mfrac></math>")
+                .unwrap();
+        assert_eq!(mathml(&mathml_fraction), "\\frac{x}{y_{10}}");
+    }
+
+    #[test]
+    fn docx_documents_convert() {
+        let dir = std::env::temp_dir().join(format!("chemtex-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("report.docx");
+        let mut zip = ZipWriter::new(File::create(&input).unwrap());
+        zip.start_file("word/document.xml", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(
+            br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+<w:body>
+<w:p><w:pPr><w:pStyle w:val="Title"/></w:pPr><w:r><w:t>Titration</w:t></w:r></w:p>
+<w:p><w:r><w:rPr><w:rFonts w:ascii="Times &lt;New&gt; Roman" w:hAnsi="a>b"/></w:rPr><w:t xml:space="preserve">Add H</w:t></w:r><w:r><w:rPr><w:vertAlign w:val="subscript"/></w:rPr><w:t>2</w:t></w:r><w:r><w:t>O &amp; stir.</w:t></w:r></w:p>
+</w:body>
+</w:document>"#,
+        )
+        .unwrap();
+        zip.finish().unwrap();
+
+        let converted = convert(&input, Format::Docx, &dir, "media");
+        fs::remove_dir_all(&dir).unwrap();
+        let converted = converted.unwrap();
+        assert_eq!(converted.title.as_deref(), Some("Titration"));
+        assert_eq!(
+            fix_artifacts(&converted.body),
+            "Add \\ce{H2O} \\& stir.\n\n"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Hashes of a project's source files, kept between runs in
+//! `<cache dir>/index/<hash of the project path>.json`, so the build cache
+//! can be checked before the project is packed: an unchanged project is
+//! recognised from a `stat` of each file rather than by reading, packing
+//! and hashing all its images.
+//!
+//! A file whose size and modification time match its entry keeps the
+//! stored hash, unless it was modified less than two seconds before it was
+//! hashed: a later write within the same timestamp tick would then go
+//! unnoticed, so such "racily clean" entries are hashed again (as git does).
+//! The index names the project's files, so it is encrypted with the build
+//! cache (see [`crate::vault`]).
+
+use crate::api::CompileOptions;
+use crate::cache;
+use crate::pack::{self, Project};
+use crate::storage;
+use crate::vault;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long after being modified a file's hash is trusted, in nanoseconds.
+const RACY_NANOS: u128 = 2_000_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    /// Modification time, nanoseconds since the Unix epoch.
+    modified: u128,
+    /// When the file was hashed, likewise.
+    hashed: u128,
+    sha256: String,
+}
+
+impl Entry {
+    fn matches(&self, size: u64, modified: u128) -> bool {
+        self.size == size
+            && self.modified == modified
+            && self.hashed >= modified.saturating_add(RACY_NANOS)
+    }
+}
+
+/// The hashes of one project, by path relative to its directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    files: BTreeMap<String, Entry>,
+}
+
+/// A key for the build cache over the sources of `project`: `files` (see
+/// [`pack::project_files`]) and everything else in the project directory
+/// but `output`, which packing may read (data files of `%%chemtex:`
+/// directives), plus the options and the chemtex version, which decide how
+/// they are packed.
+pub fn source_key(
+    project: &Project,
+    files: &[PathBuf],
+    options: &CompileOptions,
+    output: &Path,
+) -> Result<String> {
+    let root = project.main.parent().unwrap_or(Path::new(""));
+    let mut sources: Vec<PathBuf> = pack::directory_files(root)?
+        .into_iter()
+        .filter(|file| !same_file(file, output))
+        .chain(files.iter().cloned())
+        .collect();
+    sources.sort();
+    sources.dedup();
+
+    let path = index_path(root)?;
+    let mut index: Index = fs::read(&path)
+        .ok()
+        .and_then(|stored| vault::open(&stored).ok())
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    let mut hashes = BTreeMap::new();
+    let mut changed = false;
+    for file in &sources {
+        let name = file.strip_prefix(root).unwrap_or(file).to_string_lossy();
+        let metadata =
+            fs::metadata(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_nanos())
+            .unwrap_or_default();
+        let entry = match index.files.get(name.as_ref()) {
+            Some(entry) if entry.matches(metadata.len(), modified) => entry.clone(),
+            _ => {
+                changed = true;
+                Entry {
+                    size: metadata.len(),
+                    modified,
+                    hashed: now(),
+                    sha256: hash_file(file)?,
+                }
+            }
+        };
+        hashes.insert(name.into_owned(), entry);
+    }
+    changed |= hashes.len() != index.files.len();
+    let key = key(project, options, &hashes);
+    if changed {
+        index.files = hashes;
+        if let Some(dir) = path.parent() {
+            storage::ensure_dir(dir)?;
+        }
+        let json = serde_json::to_vec(&index)?;
+        fs::write(&path, vault::seal(&json)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(key)
+}
+
+fn key(project: &Project, options: &CompileOptions, hashes: &BTreeMap<String, Entry>) -> String {
+    let mut hasher = Sha256::new();
+    let pack_options = format!("{:?}", project.options);
+    let main = project
+        .main
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    for field in [
+        "sources",
+        env!("CARGO_PKG_VERSION"),
+        &main,
+        options.engine.as_deref().unwrap_or(""),
+        options.profile.as_deref().unwrap_or(""),
+        &pack_options,
+    ] {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+    if let Some(mode) = options.mode {
+        hasher.update(mode.as_str().as_bytes());
+    }
+    for (name, entry) in hashes {
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(entry.sha256.as_bytes());
+    }
+    cache::to_hex(&hasher.finalize())
+}
+
+/// One index per project directory, named after its absolute path.
+fn index_path(root: &Path) -> Result<PathBuf> {
+    let root = fs::canonicalize(if root.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        root
+    })
+    .with_context(|| format!("Failed to resolve {}", root.display()))?;
+    let name = cache::to_hex(&Sha256::digest(root.to_string_lossy().as_bytes()));
+    Ok(storage::cache_dir()?
+        .join("index")
+        .join(format!("{}.json", &name[..16])))
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(cache::to_hex(&hasher.finalize()))
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || matches!((fs::canonicalize(a), fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trusts_only_entries_hashed_well_after_the_last_write() {
+        let entry = Entry {
+            size: 10,
+            modified: 5 * RACY_NANOS,
+            hashed: 6 * RACY_NANOS,
+            sha256: String::new(),
+        };
+        assert!(entry.matches(10, 5 * RACY_NANOS));
+        assert!(!entry.matches(11, 5 * RACY_NANOS));
+        assert!(!entry.matches(10, 5 * RACY_NANOS + 1));
+
+        let racy = Entry {
+            hashed: 5 * RACY_NANOS + 1,
+            ..entry
+        };
+        assert!(!racy.matches(10, 5 * RACY_NANOS));
+    }
+}
@@ -0,0 +1,1086 @@
+use crate::accessibility;
+use crate::accounts::{self, Selected};
+use crate::api::{self, BuildMode, CompilationFailed, CompileOptions, RemoteTask, StatusPolicy};
+use crate::attest::Signer;
+use crate::cache::BuildCache;
+use crate::citations::CitationStyle;
+use crate::config::ProjectConfig;
+use crate::diagnostics::{self, Diagnostic, Severity};
+use crate::eta::QueueEta;
+use crate::formulas;
+use crate::history;
+use crate::hooks::Hooks;
+use crate::index;
+use crate::languages::Languages;
+use crate::limits::{Limits, LimitsExceeded, OnExceed};
+use crate::local::{self, LocalMode};
+use crate::lock::OutputLock;
+use crate::mapped;
+use crate::metrics;
+use crate::pack::{self, Project};
+use crate::paths;
+use crate::plugins::Plugins;
+use crate::poller::StatusPoller;
+use crate::preprocess;
+use crate::progress::Progress;
+use crate::provenance;
+use crate::qr;
+use crate::schemes::Schemes;
+use crate::scratch;
+use crate::session::Session;
+use crate::shutdown;
+use crate::validate;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Numbers the jobs of this process.
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A single document to compile: one upload, one PDF.
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// Unique within the process, and shared by clones of the job; its
+    /// task in flight goes by it (see [`InFlight`]).
+    pub id: u64,
+    pub name: String,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub options: CompileOptions,
+    pub hooks: Hooks,
+    /// Applied to a single `.tex` input when it is read for upload.
+    pub citation_style: Option<CitationStyle>,
+    /// Likewise, adds an index of compounds (see [`crate::formulas`]).
+    pub formula_index: bool,
+    /// Likewise, sets up babel or polyglossia (see [`crate::languages`]).
+    pub languages: Option<Languages>,
+    /// Likewise, stamps a QR code for this link (see [`crate::qr`]).
+    pub qr: Option<String>,
+    /// A project packed by the job into `input`, after a look in the build
+    /// cache by its sources when `index_sources` (see [`crate::index`]).
+    pub project: Option<Project>,
+    /// Pack `project` while it uploads instead of into `input`.
+    pub stream: bool,
+    pub index_sources: bool,
+    /// Compile with Tectonic: a draft of a profile whose drafts are made
+    /// locally (see [`crate::manifest::Profile`]).
+    pub local: bool,
+    /// Checked against the PDF once it is written (see [`crate::limits`]).
+    pub limits: Limits,
+    /// Names of the jobs of the same batch that must compile first.
+    pub depends_on: Vec<String>,
+    /// What to do with statuses the client does not know: the `[status]`
+    /// of the input's project.
+    pub status_policy: StatusPolicy,
+    /// The account to compile with: the invocation's, or that of the
+    /// input's project (see [`accounts::for_input`]).
+    pub account: Selected,
+}
+
+impl Job {
+    pub fn new(input: &Path, output_dir: &Path) -> Result<Self> {
+        let file_name = paths::file_name(input)?;
+        let output = output_dir.join(generate_output_path(file_name)?);
+        Ok(Self {
+            id: NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed),
+            name: file_name.to_string(),
+            input: input.to_path_buf(),
+            output,
+            options: CompileOptions::default(),
+            hooks: ProjectConfig::hooks_for(input)?,
+            citation_style: None,
+            formula_index: false,
+            languages: None,
+            qr: None,
+            project: None,
+            stream: false,
+            index_sources: false,
+            local: false,
+            limits: Limits::default(),
+            depends_on: Vec::new(),
+            status_policy: ProjectConfig::status_for(input)?,
+            account: accounts::for_input(input)?,
+        })
+    }
+}
+
+/// What happened to a job, successful or not.
+#[derive(Debug)]
+pub struct JobReport {
+    pub job: Job,
+    pub details: TaskDetails,
+    pub elapsed: Duration,
+    pub result: Result<PathBuf>,
+    pub cancelled: bool,
+}
+
+impl JobReport {
+    pub fn cancelled(job: Job, task_id: Option<String>) -> Self {
+        Self {
+            job,
+            details: TaskDetails {
+                task_id,
+                ..TaskDetails::default()
+            },
+            elapsed: Duration::ZERO,
+            result: Err(anyhow::anyhow!("Cancelled after an earlier failure")),
+            cancelled: true,
+        }
+    }
+
+    /// A job not started because `dependency` did not compile.
+    pub fn blocked(job: Job, dependency: &str) -> Self {
+        Self {
+            job,
+            details: TaskDetails::default(),
+            elapsed: Duration::ZERO,
+            result: Err(anyhow::anyhow!(
+                "Not compiled: dependency {} failed",
+                dependency
+            )),
+            cancelled: true,
+        }
+    }
+
+    /// A job stopped by SIGINT or SIGTERM.
+    pub fn interrupted(job: Job, task_id: Option<String>, elapsed: Duration) -> Self {
+        Self {
+            job,
+            details: TaskDetails {
+                task_id,
+                ..TaskDetails::default()
+            },
+            elapsed,
+            result: Err(anyhow::anyhow!("Interrupted")),
+            cancelled: true,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// What the server told us about a job's task.
+#[derive(Debug, Clone, Default)]
+pub struct TaskDetails {
+    pub task_id: Option<String>,
+    pub compile_ms: Option<u64>,
+    pub warnings: Option<u32>,
+    pub log_url: Option<String>,
+    pub cached: bool,
+    pub diagnostics: Vec<Diagnostic>,
+    pub upload_bytes: Option<u64>,
+    /// The first queue position the task was given.
+    pub queue_position: Option<u32>,
+    /// The build cache entry of the job, which keeps its log once fetched.
+    pub cache_key: Option<String>,
+    /// Wall-clock time of each phase the job went through, in order.
+    pub phases: Vec<PhaseTiming>,
+    /// The log of a build made locally, which has no URL.
+    pub local_log: Option<String>,
+}
+
+impl TaskDetails {
+    /// Duration of `phase`, if the job reached it.
+    pub fn phase(&self, phase: Phase) -> Option<Duration> {
+        self.phases
+            .iter()
+            .find(|timing| timing.phase == phase)
+            .map(|timing| timing.duration)
+    }
+
+    /// `pack 0.1s, upload 1.4s, queue 12.0s, ...`, or `None` before the
+    /// first phase finished.
+    pub fn phase_summary(&self) -> Option<String> {
+        let timings: Vec<(Phase, Duration)> = self
+            .phases
+            .iter()
+            .map(|timing| (timing.phase, timing.duration))
+            .collect();
+        (!timings.is_empty()).then(|| format_phases(&timings))
+    }
+
+    fn record_phase(&mut self, phase: Phase, start: Instant) {
+        self.record_span(phase, start, Instant::now());
+    }
+
+    fn record_span(&mut self, phase: Phase, start: Instant, end: Instant) {
+        let duration = end.saturating_duration_since(start);
+        // Packing after a missed source lookup continues the same phase.
+        if let Some(last) = self.phases.last_mut().filter(|last| last.phase == phase) {
+            last.duration = duration;
+            return;
+        }
+        let started_at = SystemTime::now() - Instant::now().saturating_duration_since(start);
+        self.phases.push(PhaseTiming {
+            phase,
+            started_at,
+            duration,
+        });
+    }
+}
+
+/// Stages of a job, in the order they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading and filtering the input.
+    Pack,
+    Upload,
+    /// Waiting in the server's queue.
+    Queue,
+    Processing,
+    Download,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 5] = [
+        Self::Pack,
+        Self::Upload,
+        Self::Queue,
+        Self::Processing,
+        Self::Download,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pack => "pack",
+            Self::Upload => "upload",
+            Self::Queue => "queue",
+            Self::Processing => "processing",
+            Self::Download => "download",
+        }
+    }
+}
+
+pub fn format_phases(timings: &[(Phase, Duration)]) -> String {
+    timings
+        .iter()
+        .map(|(phase, duration)| format!("{} {:.1}s", phase.as_str(), duration.as_secs_f64()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    #[cfg_attr(not(feature = "otel"), allow(dead_code))]
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+/// Remote tasks of jobs that have been uploaded but have not finished yet,
+/// keyed by [`Job::id`], so they can be cancelled on the server. Neither
+/// names nor inputs do: jobs of one run can share them (`chemtex compare`).
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<Mutex<HashMap<u64, RemoteTask>>>);
+
+impl InFlight {
+    fn insert(&self, job_id: u64, task: &RemoteTask) {
+        let mut tasks = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.insert(job_id, task.clone());
+    }
+
+    /// The id of the job's task.
+    pub fn get(&self, job_id: u64) -> Option<String> {
+        self.task(job_id).map(|task| task.id)
+    }
+
+    pub fn task(&self, job_id: u64) -> Option<RemoteTask> {
+        let tasks = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.get(&job_id).cloned()
+    }
+
+    /// Removes and returns the job's task.
+    pub fn take(&self, job_id: u64) -> Option<RemoteTask> {
+        let mut tasks = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        tasks.remove(&job_id)
+    }
+}
+
+/// Builds under way, keyed by build cache key: a job whose upload would be
+/// identical to one already running waits for it and then takes its PDF
+/// from the cache instead of uploading the same sources again.
+#[derive(Debug, Clone, Default)]
+pub struct Builds(Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>);
+
+impl Builds {
+    /// Held until the build of `key` is in the cache.
+    async fn claim(
+        &self,
+        key: &str,
+        progress: &Progress,
+        label: &str,
+    ) -> tokio::sync::OwnedMutexGuard<()> {
+        let build = {
+            let mut builds = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            builds.entry(key.to_string()).or_default().clone()
+        };
+        match build.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => {
+                progress.log(
+                    label,
+                    "Waiting for an identical document of this run to compile...",
+                );
+                build.lock_owned().await
+            }
+        }
+    }
+}
+
+/// Everything shared by the jobs of one invocation.
+#[derive(Debug, Clone)]
+pub struct Runner {
+    pub client: reqwest::Client,
+    pub in_flight: InFlight,
+    pub cache: Option<BuildCache>,
+    pub builds: Builds,
+    pub poller: StatusPoller,
+    pub progress: Progress,
+    pub plugins: Plugins,
+    /// Fetch and parse the compile log after every job.
+    pub collect_diagnostics: bool,
+    /// Upload sources that do not look like a LaTeX document (see
+    /// [`validate::upload`]).
+    pub skip_validation: bool,
+    /// Cancel the server's tasks of interrupted jobs rather than leaving
+    /// them for `chemtex attach`.
+    pub cancel_on_interrupt: bool,
+    /// Write a provenance record next to every PDF (see
+    /// [`crate::provenance`]).
+    pub provenance: bool,
+    /// Sign the provenance records, which are then always written.
+    pub signer: Option<Signer>,
+    /// When to compile with Tectonic instead of the server (see
+    /// [`crate::local`]).
+    pub local: LocalMode,
+}
+
+impl Runner {
+    pub fn new(client: reqwest::Client, use_cache: bool) -> Result<Self> {
+        let cache = if use_cache {
+            Some(BuildCache::open_default()?)
+        } else {
+            None
+        };
+        Ok(Self {
+            client,
+            in_flight: InFlight::default(),
+            cache,
+            builds: Builds::default(),
+            poller: StatusPoller::default(),
+            progress: Progress::default(),
+            plugins: Plugins::load_default()?,
+            collect_diagnostics: false,
+            skip_validation: false,
+            cancel_on_interrupt: false,
+            provenance: false,
+            signer: None,
+            local: LocalMode::default(),
+        })
+    }
+
+    pub async fn run(&self, job: &Job, label: &str) -> JobReport {
+        let job = &job.account.with_defaults(job);
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        let mut details = TaskDetails::default();
+        // Held until the PDF is written.
+        let result = match OutputLock::acquire(&job.output, &self.progress, label).await {
+            Ok(_lock) => match job.hooks.pre_compile(job, &self.progress, label).await {
+                Ok(()) => {
+                    // Removed when the job ends, or its future is dropped.
+                    let mut session = match Session::begin(job) {
+                        Ok(session) => Some(session),
+                        Err(e) => {
+                            self.progress
+                                .log(label, format!("Failed to start session journal: {:#}", e));
+                            None
+                        }
+                    };
+                    self.execute(job, label, &mut details, &mut session).await
+                }
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        self.in_flight.take(job.id);
+        let result = result.and_then(|output| self.check_limits(job, output, &details, label));
+
+        let failure = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<CompilationFailed>());
+        if let Some(failure) = failure {
+            details.compile_ms = failure.duration_ms;
+            details.log_url = failure.log_url.clone();
+        }
+        if self.collect_diagnostics {
+            details.diagnostics = self.diagnostics(&job.account, &details, failure).await;
+        }
+
+        let report = JobReport {
+            job: job.clone(),
+            details,
+            elapsed: started.elapsed(),
+            result,
+            cancelled: false,
+        };
+        if let Some(summary) = report.details.phase_summary() {
+            self.progress
+                .log(label, format!("Time breakdown: {}", summary));
+        }
+        if let Err(e) = history::record(&report) {
+            self.progress
+                .log(label, format!("Failed to record history: {:#}", e));
+        }
+        if (self.provenance || self.signer.is_some()) && report.is_success() {
+            match provenance::write(self, &report, started_at).await {
+                Ok(path) => self
+                    .progress
+                    .log(label, format!("Provenance written to {}", path.display())),
+                Err(e) => self
+                    .progress
+                    .log(label, format!("Failed to write provenance: {:#}", e)),
+            }
+        }
+        job.hooks.post_compile(&report, &self.progress, label).await;
+        self.plugins.notify(&report);
+        #[cfg(feature = "otel")]
+        crate::otel::export(&self.client, &report).await;
+        report
+    }
+
+    /// Like [`Runner::run`], but stops the job on SIGINT or SIGTERM (see
+    /// [`Runner::interrupted`]).
+    pub async fn run_interruptible(&self, job: &Job, label: &str) -> JobReport {
+        let started = Instant::now();
+        tokio::select! {
+            report = self.run(job, label) => report,
+            () = shutdown::requested() => {
+                self.interrupted(job.clone(), label, started.elapsed()).await
+            }
+        }
+    }
+
+    /// Cleans up after a job whose future was dropped by a signal: removes
+    /// its partial PDF and records it in the history with its task id, which
+    /// is left running for `chemtex attach` unless `cancel_on_interrupt`.
+    pub async fn interrupted(&self, job: Job, label: &str, elapsed: Duration) -> JobReport {
+        let task = self.in_flight.take(job.id);
+        match fs::remove_file(partial_path(&job.output)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => self
+                .progress
+                .log(label, format!("Failed to remove partial PDF: {}", e)),
+        }
+        if let Some(task) = &task {
+            let task_id = &task.id;
+            if self.cancel_on_interrupt {
+                match api::cancel_task(&self.client, task).await {
+                    Ok(()) => self
+                        .progress
+                        .log(label, format!("Cancelled remote task {}", task_id)),
+                    Err(e) => self
+                        .progress
+                        .log(label, format!("Failed to cancel task {}: {:#}", task_id, e)),
+                }
+            } else {
+                self.progress.log(
+                    label,
+                    format!(
+                        "Task {} keeps running; `chemtex attach {}` downloads its PDF",
+                        task_id, task_id
+                    ),
+                );
+            }
+        }
+        let report = JobReport::interrupted(job, task.map(|task| task.id), elapsed);
+        if let Err(e) = history::record(&report) {
+            self.progress
+                .log(label, format!("Failed to record history: {:#}", e));
+        }
+        report
+    }
+
+    async fn execute(
+        &self,
+        job: &Job,
+        label: &str,
+        details: &mut TaskDetails,
+        session: &mut Option<Session>,
+    ) -> Result<PathBuf> {
+        let client = &self.client;
+
+        let pack_started = Instant::now();
+        let mut source_key = None;
+        if let Some(project) = &job.project {
+            let files = pack::project_files(&project.main, &project.options)?;
+            if let (Some(cache), true) = (&self.cache, job.index_sources) {
+                let key = index::source_key(project, &files, &job.options, &job.output)?;
+                details.record_phase(Phase::Pack, pack_started);
+                if let Some(output) = self.use_cached(cache, &key, job, label, details).await? {
+                    return Ok(output);
+                }
+                source_key = Some(key);
+            }
+            if job.stream && !self.compiles_locally(job) {
+                return self
+                    .execute_streamed(job, &files, source_key, label, details, session)
+                    .await;
+            }
+            let dest_dir = job.input.parent().unwrap_or(Path::new(""));
+            pack::pack_files(project, &files, dest_dir)?;
+        }
+        self.progress
+            .log(label, format!("Reading files: {}", job.input.display()));
+        let file_name = paths::file_name(&job.input)?;
+        let mut file_contents = mapped::read(&job.input)?;
+        if file_name.ends_with(".tex") {
+            for finding in accessibility::check_figures(&job.input)? {
+                self.progress.log(label, format!("Warning: {}", finding));
+            }
+            if let Ok(text) = std::str::from_utf8(&file_contents) {
+                let dir = job.input.parent().unwrap_or(Path::new(""));
+                let text = Schemes::from_text(text, &job.input)?.rewrite(text)?;
+                let mut text = preprocess::expand(&text, dir)?;
+                if let Some(style) = job.citation_style {
+                    text = style.inject(&text);
+                }
+                if let Some(languages) = &job.languages {
+                    text = languages.inject(&text);
+                }
+                if job.formula_index {
+                    text = formulas::annotate(&formulas::setup(&text));
+                }
+                if let Some(link) = &job.qr {
+                    text = qr::inject(&text, link);
+                }
+                file_contents = Bytes::from(text.into_bytes());
+            }
+        }
+        let file_contents = self.plugins.filter_upload(file_name, file_contents)?;
+        validate::upload(file_name, &file_contents, !self.skip_validation)?;
+
+        let cache_key = BuildCache::key(&file_contents, file_name, &job.options);
+        details.record_phase(Phase::Pack, pack_started);
+        // Held until the build is stored, when there is a cache to store it.
+        let _build = match &self.cache {
+            Some(_) => Some(self.builds.claim(&cache_key, &self.progress, label).await),
+            None => None,
+        };
+        if let Some(cache) = &self.cache {
+            if let Some(output) = self
+                .use_cached(cache, &cache_key, job, label, details)
+                .await?
+            {
+                return Ok(output);
+            }
+        }
+
+        if self.compiles_locally(job) {
+            return self
+                .compile_locally(job, &file_contents, file_name, label, details)
+                .await;
+        }
+        self.progress.log(
+            label,
+            format!("Uploading file to {}...", job.account.server()),
+        );
+        let upload_started = Instant::now();
+        let upload_bytes = file_contents.len() as u64;
+        let uploaded = api::upload_file(
+            client,
+            &job.account,
+            file_contents.clone(),
+            file_name,
+            &job.options,
+        )
+        .await;
+        let task_id = match uploaded {
+            Ok(task_id) => task_id,
+            // A final build is not replaced by a draft-quality one.
+            Err(e)
+                if metrics::failure_class(&e) == "network"
+                    && job.options.mode != Some(BuildMode::Final)
+                    && local::available() =>
+            {
+                self.progress.log(
+                    label,
+                    format!("Server unreachable ({:#}), compiling locally instead", e),
+                );
+                return self
+                    .compile_locally(job, &file_contents, file_name, label, details)
+                    .await;
+            }
+            Err(e) => return Err(e),
+        };
+        details.upload_bytes = Some(upload_bytes);
+        details.record_phase(Phase::Upload, upload_started);
+        let (pdf_bytes, download_url) = self
+            .complete(job, &task_id, label, details, session)
+            .await?;
+        // A project is found by its sources from now on.
+        let key = source_key.as_ref().unwrap_or(&cache_key);
+        self.store_build(key, &task_id, &download_url, &pdf_bytes, label, details);
+
+        self.progress
+            .log(label, format!("PDF saved to: {}", job.output.display()));
+        Ok(job.output.clone())
+    }
+
+    /// Uploads a project as it is packed (`--stream`). The archive is never
+    /// whole on this side, so plugin upload filters, which need all of it,
+    /// are skipped, and the build is cached by `source_key` only.
+    async fn execute_streamed(
+        &self,
+        job: &Job,
+        files: &[PathBuf],
+        source_key: Option<String>,
+        label: &str,
+        details: &mut TaskDetails,
+        session: &mut Option<Session>,
+    ) -> Result<PathBuf> {
+        let project = job
+            .project
+            .as_ref()
+            .context("Only projects can be streamed")?;
+        let main_name = paths::file_name(&project.main)?;
+        let main = fs::read(&project.main)
+            .with_context(|| format!("Failed to read file: {}", project.main.display()))?;
+        validate::upload(main_name, &main, !self.skip_validation)?;
+
+        self.progress.log(
+            label,
+            format!(
+                "Packing {} while uploading it to {}...",
+                project.main.display(),
+                job.account.server()
+            ),
+        );
+        let upload_started = Instant::now();
+        let (task_id, upload_bytes) = api::upload_project(
+            &self.client,
+            &job.account,
+            project,
+            files,
+            &job.name,
+            &job.options,
+        )
+        .await?;
+        details.upload_bytes = Some(upload_bytes);
+        details.record_phase(Phase::Upload, upload_started);
+        let (pdf_bytes, download_url) = self
+            .complete(job, &task_id, label, details, session)
+            .await?;
+        if let Some(key) = &source_key {
+            self.store_build(key, &task_id, &download_url, &pdf_bytes, label, details);
+        }
+
+        self.progress
+            .log(label, format!("PDF saved to: {}", job.output.display()));
+        Ok(job.output.clone())
+    }
+
+    /// Waits for an uploaded job's task, then downloads and writes its PDF.
+    /// Returns the PDF and the URL it came from.
+    async fn complete(
+        &self,
+        job: &Job,
+        task_id: &str,
+        label: &str,
+        details: &mut TaskDetails,
+        session: &mut Option<Session>,
+    ) -> Result<(Vec<u8>, String)> {
+        let client = &self.client;
+        let uploaded = Instant::now();
+        self.progress
+            .log(label, format!("File uploaded. Task ID: {}", task_id));
+        let task = RemoteTask {
+            id: task_id.to_string(),
+            account: job.account.clone(),
+        };
+        self.in_flight.insert(job.id, &task);
+        self.journal(session, label, |session| session.uploaded(task_id));
+        details.task_id = Some(task_id.to_string());
+
+        self.progress
+            .log(label, "Waiting for compilation to complete...");
+        let polled = api::poll_status(
+            client,
+            &task,
+            &self.poller,
+            &job.status_policy,
+            &self.progress,
+            &QueueEta::from_history(),
+            label,
+        )
+        .await;
+        let (processing_started, queue_position) = match &polled {
+            Ok(completed) => (completed.processing_started, completed.queue_position),
+            Err(e) => e
+                .downcast_ref::<CompilationFailed>()
+                .map(|failure| (failure.processing_started, failure.queue_position))
+                .unwrap_or_default(),
+        };
+        details.queue_position = queue_position;
+        let finished = Instant::now();
+        let processing_started = processing_started
+            .unwrap_or(finished)
+            .clamp(uploaded, finished);
+        details.record_span(Phase::Queue, uploaded, processing_started);
+        details.record_span(Phase::Processing, processing_started, finished);
+        let completed = polled?;
+        details.compile_ms = completed.duration_ms;
+        details.warnings = completed.warnings;
+        details.log_url = completed.log_url;
+
+        self.progress.log(
+            label,
+            format!("Downloading PDF from {}", completed.download_url),
+        );
+        self.journal(session, label, |session| {
+            session.downloading(&completed.download_url)
+        });
+        let download_started = Instant::now();
+        let pdf_bytes = api::download_pdf(client, &job.account, &completed.download_url).await?;
+        details.record_phase(Phase::Download, download_started);
+
+        write_output(&job.output, &pdf_bytes)?;
+        self.plugins.post_process(&job.output)?;
+        Ok((pdf_bytes, completed.download_url))
+    }
+
+    /// Fails the job, or warns, when its PDF went over its limits.
+    fn check_limits(
+        &self,
+        job: &Job,
+        output: PathBuf,
+        details: &TaskDetails,
+        label: &str,
+    ) -> Result<PathBuf> {
+        if job.limits.is_empty() {
+            return Ok(output);
+        }
+        let exceeded = job.limits.exceeded(&output, details.compile_ms)?;
+        if exceeded.is_empty() {
+            return Ok(output);
+        }
+        match job.limits.on_exceed.unwrap_or_default() {
+            OnExceed::Warn => {
+                self.progress
+                    .log(label, format!("Warning: {}", LimitsExceeded(exceeded)));
+                Ok(output)
+            }
+            OnExceed::Fail => Err(LimitsExceeded(exceeded).into()),
+        }
+    }
+
+    fn compiles_locally(&self, job: &Job) -> bool {
+        self.local == LocalMode::Always || job.local
+    }
+
+    /// Compiles with Tectonic instead of the server (see [`crate::local`]),
+    /// writing the PDF as a downloaded one and keeping the log for the
+    /// diagnostics. Local builds are not cached, being made by another
+    /// engine than the server's.
+    async fn compile_locally(
+        &self,
+        job: &Job,
+        contents: &[u8],
+        file_name: &str,
+        label: &str,
+        details: &mut TaskDetails,
+    ) -> Result<PathBuf> {
+        let started = Instant::now();
+        let build =
+            local::compile(contents, file_name, &job.options, &self.progress, label).await?;
+        details.record_phase(Phase::Processing, started);
+        details.compile_ms = Some(build.duration_ms);
+        let warnings = diagnostics::parse_log(&build.log)
+            .iter()
+            .filter(|d| d.severity == Severity::Warning)
+            .count();
+        details.warnings = Some(warnings as u32);
+        details.local_log = Some(build.log);
+        let pdf_bytes = build.outcome?;
+
+        write_output(&job.output, &pdf_bytes)?;
+        self.plugins.post_process(&job.output)?;
+        self.progress
+            .log(label, format!("PDF saved to: {}", job.output.display()));
+        Ok(job.output.clone())
+    }
+
+    /// Records a phase in the session journal, if the job has one; a job
+    /// carries on without it.
+    fn journal(
+        &self,
+        session: &mut Option<Session>,
+        label: &str,
+        update: impl FnOnce(&mut Session) -> Result<()>,
+    ) {
+        if let Some(journal) = session {
+            if let Err(e) = update(journal) {
+                self.progress
+                    .log(label, format!("Failed to update session journal: {:#}", e));
+            }
+        }
+    }
+
+    /// The diagnostics of a finished job: those collected while it ran, or
+    /// its log parsed now.
+    pub async fn diagnose(&self, report: &JobReport) -> Vec<Diagnostic> {
+        if !report.details.diagnostics.is_empty() {
+            return report.details.diagnostics.clone();
+        }
+        let failure = report
+            .result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<CompilationFailed>());
+        self.diagnostics(&report.job.account, &report.details, failure)
+            .await
+    }
+
+    /// The compile log of a job, from a local build, the build cache or the
+    /// server.
+    pub async fn compile_log(&self, account: &Selected, details: &TaskDetails) -> Option<String> {
+        if let Some(log) = &details.local_log {
+            return Some(log.clone());
+        }
+        let cached = self.cache.as_ref().zip(details.cache_key.as_deref());
+        if let Some(log) = cached.and_then(|(cache, key)| cache.log(key)) {
+            return Some(log);
+        }
+        let log = api::fetch_log(&self.client, account, details.log_url.as_deref()?)
+            .await
+            .ok()?;
+        if let Some((cache, key)) = cached {
+            // Only saves fetching it again, so a failure is no news.
+            let _ = cache.store_log(key, &log);
+        }
+        Some(log)
+    }
+
+    /// Parses the compile log, falling back to the server's error message
+    /// when the log is unavailable.
+    async fn diagnostics(
+        &self,
+        account: &Selected,
+        details: &TaskDetails,
+        failure: Option<&CompilationFailed>,
+    ) -> Vec<Diagnostic> {
+        if let Some(log) = self.compile_log(account, details).await {
+            return diagnostics::parse_log(&log);
+        }
+        match failure {
+            Some(failure) => {
+                let parsed = diagnostics::parse_log(&failure.message);
+                if parsed.is_empty() {
+                    vec![diagnostics::from_message(&failure.message)]
+                } else {
+                    parsed
+                }
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Writes the cached build under `key` as the job's PDF, if there is one.
+    async fn use_cached(
+        &self,
+        cache: &BuildCache,
+        key: &str,
+        job: &Job,
+        label: &str,
+        details: &mut TaskDetails,
+    ) -> Result<Option<PathBuf>> {
+        let download_started = Instant::now();
+        let Some(pdf_bytes) = self.reuse_cached(cache, key, job, label, details).await else {
+            return Ok(None);
+        };
+        details.record_phase(Phase::Download, download_started);
+        write_output(&job.output, &pdf_bytes)?;
+        self.plugins.post_process(&job.output)?;
+        self.progress
+            .log(label, format!("PDF saved to: {}", job.output.display()));
+        Ok(Some(job.output.clone()))
+    }
+
+    /// Keeps a finished build in the cache, if there is one; failing to is
+    /// only reported.
+    fn store_build(
+        &self,
+        key: &str,
+        task_id: &str,
+        download_url: &str,
+        pdf: &[u8],
+        label: &str,
+        details: &mut TaskDetails,
+    ) {
+        if let Some(cache) = &self.cache {
+            details.cache_key = Some(key.to_string());
+            if let Err(e) = cache.store(key, task_id, download_url, pdf) {
+                self.progress
+                    .log(label, format!("Failed to update build cache: {:#}", e));
+            }
+        }
+    }
+
+    /// Returns the PDF of an identical earlier build, re-downloading it by its
+    /// stored URL when only the metadata survived.
+    async fn reuse_cached(
+        &self,
+        cache: &BuildCache,
+        key: &str,
+        job: &Job,
+        label: &str,
+        details: &mut TaskDetails,
+    ) -> Option<Vec<u8>> {
+        let cached = cache.lookup(key)?;
+        let pdf_bytes = match cached.pdf {
+            Some(bytes) => bytes,
+            None => {
+                self.progress.log(
+                    label,
+                    format!(
+                        "Re-downloading cached build from {}",
+                        cached.entry.download_url
+                    ),
+                );
+                match api::download_pdf(&self.client, &job.account, &cached.entry.download_url)
+                    .await
+                {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        self.progress.log(
+                            label,
+                            format!("Cached build unavailable ({:#}), recompiling", e),
+                        );
+                        return None;
+                    }
+                }
+            }
+        };
+        self.progress.log(
+            label,
+            format!(
+                "Using cached build (task {}), pass --no-cache to recompile",
+                cached.entry.task_id
+            ),
+        );
+        details.task_id = Some(cached.entry.task_id);
+        details.cache_key = Some(key.to_string());
+        details.cached = true;
+        Some(pdf_bytes)
+    }
+}
+
+/// Writes the PDF in the temp root and renames it into place, so an
+/// interrupted write never leaves a truncated PDF behind. When the temp
+/// root is on another file system, the PDF is staged next to `output`.
+pub fn write_output(output: &Path, pdf_bytes: &[u8]) -> Result<()> {
+    if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    let temp = scratch::file(&format!("{}.partial", paths::file_name(output)?))?;
+    fs::write(&temp, pdf_bytes)
+        .with_context(|| format!("Failed to write PDF file: {}", temp.display()))?;
+    if fs::rename(&temp, paths::long_path(output)).is_ok() {
+        return Ok(());
+    }
+    let partial = partial_path(output);
+    let staged = fs::copy(&temp, paths::long_path(&partial));
+    let _ = fs::remove_file(&temp);
+    staged.with_context(|| format!("Failed to write PDF file: {}", partial.display()))?;
+    fs::rename(paths::long_path(&partial), paths::long_path(output))
+        .with_context(|| format!("Failed to write PDF file: {}", output.display()))
+}
+
+fn partial_path(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+fn generate_output_path(input_file_name: &str) -> Result<PathBuf> {
+    let output_name = Path::new(input_file_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid file name")?;
+    Ok(PathBuf::from(format!("{}.pdf", output_name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_take_the_status_policy_of_their_project() {
+        let dir = std::env::temp_dir().join(format!("chemtex-job-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(".chemtex.toml"),
+            "[status]\nterminal = [\"Expired\"]\nmax_unknown = 3\n",
+        )
+        .unwrap();
+        let job = Job::new(&dir.join("report.tex"), &dir);
+        let elsewhere = Job::new(&dir.join("none/report.tex"), &dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let policy = job.unwrap().status_policy;
+        assert_eq!(policy.terminal, ["Expired"]);
+        assert_eq!(policy.max_unknown, 3);
+        assert!(elsewhere.unwrap().status_policy.is_default());
+    }
+
+    #[test]
+    fn tasks_in_flight_go_by_job() {
+        // Two engines compiling one file at once, as `chemtex compare` does.
+        let input = Path::new("titration.tex");
+        let (pdflatex, xelatex) = (
+            Job::new(input, Path::new("")).unwrap(),
+            Job::new(input, Path::new("")).unwrap(),
+        );
+        let task = |id: &str| RemoteTask {
+            id: id.to_string(),
+            account: Selected::default(),
+        };
+        let in_flight = InFlight::default();
+        in_flight.insert(pdflatex.id, &task("1"));
+        in_flight.insert(xelatex.id, &task("2"));
+        assert_eq!(
+            in_flight.take(pdflatex.id).map(|task| task.id).as_deref(),
+            Some("1")
+        );
+        assert_eq!(in_flight.get(xelatex.clone().id).as_deref(), Some("2"));
+        assert_eq!(in_flight.get(pdflatex.id), None);
+    }
+
+    #[test]
+    fn jobs_take_the_hooks_of_their_project() {
+        let dir = std::env::temp_dir().join(format!("chemtex-job-hooks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(".chemtex.toml"),
+            "[hooks]\npre_compile = \"python plots/render.py\"\npost_compile = [\"./publish.sh\"]\n",
+        )
+        .unwrap();
+        let job = Job::new(&dir.join("report.tex"), Path::new(""));
+        let elsewhere = Job::new(&dir.join("none/report.tex"), Path::new(""));
+        fs::remove_dir_all(&dir).unwrap();
+        let hooks = job.unwrap().hooks;
+        assert_eq!(hooks.pre_compile, ["python plots/render.py"]);
+        assert_eq!(hooks.post_compile, ["./publish.sh"]);
+        assert_eq!(hooks.dir, dir);
+        assert!(elsewhere.unwrap().hooks.is_empty());
+    }
+}
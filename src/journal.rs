@@ -0,0 +1,281 @@
+use crate::accounts;
+use crate::api::{self, CompileOptions};
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::deps;
+use crate::job::{Job, Runner};
+use crate::md2tex::escape;
+use crate::pack::{self, PackOptions, TempDir};
+use crate::scaffold::PREAMBLE;
+use crate::{plot, table};
+use anyhow::{Context, Result};
+use chrono::Local;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: chemtex journal add <title> [options]
+
+Appends a datestamped entry to a lab notebook and recompiles it, so the PDF
+is a chronological record of the work, e.g.
+  chemtex journal add \"Titration run 3\" --data run3.csv --fit linear
+
+Each entry is a file in entries/, input at the end of the notebook. Data
+files are copied to data/ and shown as a table and, when the notebook loads
+pgfplots, a plot, both generated from the copy when packing. A notebook is
+created (notebook.tex with a .chemtex.toml) if the directory has none.
+
+Options:
+  --notebook DIR   The notebook project (default: the current directory)
+  --data FILE      CSV data to tabulate and plot (repeatable)
+  --note TEXT      A paragraph of text for the entry
+  --x NAME         Column on the plot's x axis
+  --y NAME         Column on the plot's y axis
+  --fit KIND       Fit to draw: linear, proportional, quadratic or exponential
+  --no-compile     Only write the entry
+  --no-cache       Always submit, ignoring the build cache";
+
+const END_DOCUMENT: &str = "\\end{document}";
+const NOTEBOOK: &str = "notebook.tex";
+
+const NOTEBOOK_TEMPLATE: &str = "\
+\\documentclass[11pt,a4paper]{article}
+\\input{preamble}
+\\usepackage{pgfplots}
+\\pgfplotsset{compat=1.18}
+
+\\title{{{title}}}
+\\author{}
+\\date{Started {{date}}}
+
+\\begin{document}
+\\maketitle
+\\tableofcontents
+
+\\end{document}
+";
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["no-compile", "no-cache"],
+        &["notebook", "data", "note", "x", "y", "fit"],
+    )?;
+    let title = match (args.positional(0), args.positional(1)) {
+        (Some("add"), Some(title)) => title,
+        _ => anyhow::bail!(USAGE),
+    };
+    let dir = PathBuf::from(args.value("notebook").unwrap_or("."));
+    let (main, config) = open_notebook(&dir)?;
+
+    let now = Local::now();
+    let date = now.format("%Y-%m-%d").to_string();
+    let slug = slug(title);
+    let entry = unused(&dir.join("entries"), &format!("{}-{}", date, slug), "tex");
+    let has_pgfplots = deps::read_lines(&main)?
+        .iter()
+        .any(|line| deps::strip_comment(&line.text).contains("{pgfplots}"));
+
+    let mut text = format!(
+        "% Added by chemtex journal on {}.\n\\section{{{} --- {}}}\n\\noindent\\textit{{Recorded at {}}}\n\n",
+        now.format("%Y-%m-%d %H:%M"),
+        date,
+        escape(title),
+        now.format("%H:%M")
+    );
+    if let Some(note) = args.value("note") {
+        text.push_str(&format!("{}\n\n", escape(note)));
+    }
+    for data in args.values("data") {
+        text.push_str(&data_section(
+            Path::new(data),
+            &dir,
+            &date,
+            &args,
+            has_pgfplots,
+        )?);
+    }
+
+    fs::create_dir_all(dir.join("entries"))
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    fs::write(&entry, text)
+        .with_context(|| format!("Failed to write file: {}", entry.display()))?;
+    let relative = entry
+        .strip_prefix(main.parent().unwrap_or(Path::new("")))
+        .unwrap_or(&entry)
+        .with_extension("");
+    append_input(&main, &relative.to_string_lossy().replace('\\', "/"))?;
+    println!("Added {} to {}", entry.display(), main.display());
+
+    if args.flag("no-compile") {
+        return Ok(());
+    }
+    compile(&dir, &main, config, !args.flag("no-cache")).await
+}
+
+/// The notebook's main file and config, creating both when the directory
+/// has neither.
+fn open_notebook(dir: &Path) -> Result<(PathBuf, ProjectConfig)> {
+    if let Some(config) = ProjectConfig::find(dir)? {
+        let main = dir.join(config.main.as_deref().unwrap_or(Path::new(NOTEBOOK)));
+        anyhow::ensure!(
+            main.is_file(),
+            "{} has no notebook {}",
+            dir.display(),
+            main.display()
+        );
+        return Ok((main, config));
+    }
+    let main = dir.join(NOTEBOOK);
+    if !main.is_file() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+        let title = match dir.canonicalize()?.file_name() {
+            Some(name) => escape(&name.to_string_lossy()),
+            None => "Lab notebook".to_string(),
+        };
+        let text = NOTEBOOK_TEMPLATE
+            .replace("{{title}}", &title)
+            .replace("{{date}}", &Local::now().format("%Y-%m-%d").to_string());
+        fs::write(&main, text)
+            .with_context(|| format!("Failed to write file: {}", main.display()))?;
+        let preamble = dir.join("preamble.tex");
+        if !preamble.exists() {
+            fs::write(&preamble, PREAMBLE)
+                .with_context(|| format!("Failed to write file: {}", preamble.display()))?;
+        }
+        println!("Started a lab notebook in {}", main.display());
+    }
+    let config = ProjectConfig {
+        main: Some(NOTEBOOK.into()),
+        engine: Some("pdflatex".to_string()),
+        output: Some(Path::new(NOTEBOOK).with_extension("pdf")),
+        ..ProjectConfig::default()
+    };
+    config.save(dir)?;
+    Ok((main, config))
+}
+
+/// Copies `data` into the notebook and returns the directives showing it.
+fn data_section(data: &Path, dir: &Path, date: &str, args: &Args, plot: bool) -> Result<String> {
+    let name = data
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid data file name")?;
+    let extension = data.extension().and_then(|e| e.to_str()).unwrap_or("csv");
+    let copy = unused(
+        &dir.join("data"),
+        &format!("{}-{}", date, slug(name)),
+        extension,
+    );
+    let file = copy.file_name().unwrap_or_default().to_string_lossy();
+    let caption = escape(&data.file_name().unwrap_or_default().to_string_lossy());
+    let options = format!(", caption=\"{}\"", caption);
+    let mut plot_options = String::new();
+    for option in ["x", "y", "fit"] {
+        if let Some(value) = args.value(option) {
+            plot_options.push_str(&format!(", {}={}", option, value));
+        }
+    }
+
+    // Checked against the original before anything is written.
+    let original = data.display().to_string();
+    table::directive(&format!("{}{}", original, options), Path::new(""))
+        .with_context(|| format!("Failed to tabulate {}", data.display()))?;
+    let plot = match plot {
+        false => {
+            eprintln!(
+                "The notebook does not load pgfplots; {} is not plotted",
+                data.display()
+            );
+            false
+        }
+        true => match plot::directive(&format!("{}{}", original, plot_options), Path::new("")) {
+            Ok(_) => true,
+            // Asked for explicitly, a plot that cannot be drawn is an error.
+            Err(e) if !plot_options.is_empty() => {
+                return Err(e).with_context(|| format!("Failed to plot {}", data.display()))
+            }
+            Err(e) => {
+                eprintln!("Not plotting {}: {:#}", data.display(), e);
+                false
+            }
+        },
+    };
+    fs::create_dir_all(dir.join("data"))
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    fs::copy(data, &copy).with_context(|| format!("Failed to copy {}", data.display()))?;
+
+    // Directives resolve paths against the entry in entries/.
+    let mut out = format!("%%chemtex:table(../data/{}{})\n", file, options);
+    if plot {
+        out.push_str(&format!(
+            "%%chemtex:plot(../data/{}{})\n",
+            file, plot_options
+        ));
+    }
+    Ok(out + "\n")
+}
+
+/// Inputs the entry just before `\end{document}`, after the earlier ones.
+fn append_input(main: &Path, entry: &str) -> Result<()> {
+    let text = fs::read_to_string(main)
+        .with_context(|| format!("Failed to read file: {}", main.display()))?;
+    let end = text
+        .rfind(END_DOCUMENT)
+        .with_context(|| format!("{} has no {}", main.display(), END_DOCUMENT))?;
+    let (body, tail) = text.split_at(end);
+    let mut out = body.to_string();
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&format!("\\input{{{}}}\n", entry));
+    out.push_str(tail);
+    fs::write(main, out).with_context(|| format!("Failed to write file: {}", main.display()))
+}
+
+async fn compile(dir: &Path, main: &Path, config: ProjectConfig, use_cache: bool) -> Result<()> {
+    let account = accounts::chosen().for_project(config.account.as_deref())?;
+    let options = PackOptions::from_config(&config)?;
+    let scratch = TempDir::new("journal")?;
+    let archive = pack::pack_project_with(main, scratch.path(), &options)?;
+    let runner = Runner::new(api::build_client()?, use_cache)?;
+    let mut job = Job::new(&archive, Path::new(""))?;
+    job.account = account;
+    job.hooks = config.hooks.in_dir(dir);
+    job.output = match config.output {
+        Some(output) => dir.join(output),
+        None => main.with_extension("pdf"),
+    };
+    job.options = CompileOptions {
+        engine: config.engine,
+        profile: config.profile,
+        ..CompileOptions::default()
+    };
+    runner.run(&job, "").await.result?;
+    Ok(())
+}
+
+/// `Titration run 3` → `titration-run-3`.
+fn slug(text: &str) -> String {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    match words.is_empty() {
+        true => "entry".to_string(),
+        false => words.join("-"),
+    }
+}
+
+/// `dir/stem.extension`, or `dir/stem-2.extension` and so on if taken.
+fn unused(dir: &Path, stem: &str, extension: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.{}", stem, extension));
+    let mut n = 2;
+    while path.exists() {
+        path = dir.join(format!("{}-{}.{}", stem, n, extension));
+        n += 1;
+    }
+    path
+}
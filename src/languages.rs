@@ -0,0 +1,306 @@
+use crate::deps::strip_comment;
+use anyhow::Result;
+
+const BEGIN_DOCUMENT: &str = "\\begin{document}";
+const MARKER: &str = "% chemtex --lang";
+
+/// Packages the language setup replaces wherever a preamble loads them.
+const REPLACED: &[&str] = &["fontenc", "inputenc", "babel", "polyglossia", "fontspec"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Russian,
+}
+
+impl Language {
+    fn parse(code: &str) -> Result<Self> {
+        Ok(match code.trim().to_lowercase().as_str() {
+            "en" | "english" => Self::English,
+            "ru" | "russian" => Self::Russian,
+            other => anyhow::bail!("Unknown language {:?} (en, ru)", other),
+        })
+    }
+
+    fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::Russian => "ru",
+        }
+    }
+
+    /// The name babel and polyglossia know it by.
+    fn name(self) -> &'static str {
+        match self {
+            Self::English => "english",
+            Self::Russian => "russian",
+        }
+    }
+
+    /// The hunspell dictionary for it.
+    fn dictionary(self) -> &'static str {
+        match self {
+            Self::English => "en_US",
+            Self::Russian => "ru_RU",
+        }
+    }
+
+    fn is_cyrillic(self) -> bool {
+        self == Self::Russian
+    }
+
+    /// Caption names Russian documents are usually expected to use where
+    /// babel's defaults differ (`Рис.`, `Оглавление`).
+    fn captions(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::English => &[],
+            Self::Russian => &[
+                ("contentsname", "Содержание"),
+                ("figurename", "Рисунок"),
+                ("tablename", "Таблица"),
+                ("refname", "Список литературы"),
+                ("bibname", "Список литературы"),
+                ("abstractname", "Аннотация"),
+            ],
+        }
+    }
+}
+
+/// The languages of a document, set with `--lang ru,en` when a project is
+/// created or packed, or with `lang` in `.chemtex.toml`. The first is the
+/// main language; the others get their own hyphenation patterns and
+/// captions inside `\foreignlanguage` and `otherlanguage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Languages {
+    languages: Vec<Language>,
+    /// XeLaTeX and LuaLaTeX get fontspec and polyglossia instead of
+    /// fontenc and babel.
+    unicode: bool,
+}
+
+impl Languages {
+    /// Parses `ru,en` for documents compiled with `engine`.
+    pub fn parse(spec: &str, engine: Option<&str>) -> Result<Self> {
+        let mut languages = Vec::new();
+        for code in spec.split(',').filter(|code| !code.trim().is_empty()) {
+            let language = Language::parse(code)?;
+            if !languages.contains(&language) {
+                languages.push(language);
+            }
+        }
+        anyhow::ensure!(!languages.is_empty(), "No languages in --lang {:?}", spec);
+        let unicode = matches!(engine, Some("xelatex" | "lualatex"));
+        Ok(Self { languages, unicode })
+    }
+
+    fn main(&self) -> Language {
+        self.languages[0]
+    }
+
+    /// The hunspell dictionaries of the languages, main first.
+    pub fn dictionaries(&self) -> Vec<&'static str> {
+        self.languages.iter().map(|l| l.dictionary()).collect()
+    }
+
+    fn cyrillic(&self) -> bool {
+        self.languages.iter().any(|l| l.is_cyrillic())
+    }
+
+    /// Sets up the languages in a main file (or a preamble file of its
+    /// own): the font, encoding and language packages it loads are
+    /// commented out and the setup goes in place of the first of them, or
+    /// after `\documentclass`. Files that already carry it come back
+    /// unchanged.
+    pub fn inject(&self, text: &str) -> String {
+        if text.contains(MARKER) {
+            return text.to_string();
+        }
+        let (preamble, body) = text.split_at(text.find(BEGIN_DOCUMENT).unwrap_or(text.len()));
+        let mut out = String::with_capacity(text.len() + 500);
+        let mut injected = false;
+        let documentclass = preamble.lines().any(|line| {
+            strip_comment(line)
+                .trim_start()
+                .starts_with("\\documentclass")
+        });
+        for line in preamble.split_inclusive('\n') {
+            let command = strip_comment(line).trim();
+            if self.replaces(command) {
+                out.push_str(&format!("% {}", line));
+                if !line.ends_with('\n') {
+                    out.push('\n');
+                }
+                if !injected && !documentclass {
+                    out.push_str(&self.setup());
+                    injected = true;
+                }
+                continue;
+            }
+            out.push_str(line);
+            if !injected && command.starts_with("\\documentclass") {
+                if !line.ends_with('\n') {
+                    out.push('\n');
+                }
+                out.push_str(&self.setup());
+                injected = true;
+            }
+        }
+        if !injected {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(&self.setup());
+        }
+        out.push_str(body);
+        out
+    }
+
+    /// Comments out the packages the setup replaces in a file the main file
+    /// inputs, so they are not loaded twice with other options.
+    pub fn strip(&self, text: &str) -> String {
+        if text.contains(MARKER) {
+            return text.to_string();
+        }
+        let (preamble, body) = text.split_at(text.find(BEGIN_DOCUMENT).unwrap_or(text.len()));
+        let mut out = String::with_capacity(text.len());
+        for line in preamble.split_inclusive('\n') {
+            match self.replaces(strip_comment(line).trim()) {
+                true => out.push_str(&format!("% {}", line)),
+                false => out.push_str(line),
+            }
+        }
+        out.push_str(body);
+        out
+    }
+
+    fn replaces(&self, command: &str) -> bool {
+        // Latin Modern has no Cyrillic.
+        let lmodern = self.cyrillic() && is_package(command, "lmodern");
+        lmodern || REPLACED.iter().any(|package| is_package(command, package))
+    }
+
+    /// The preamble lines for these languages.
+    fn setup(&self) -> String {
+        let codes: Vec<&str> = self.languages.iter().map(|l| l.code()).collect();
+        let mut out = format!("{} {}\n", MARKER, codes.join(","));
+        let others: Vec<&str> = self.languages[1..].iter().map(|l| l.name()).collect();
+        let captions = if self.unicode {
+            out.push_str("\\usepackage{fontspec}\n");
+            if self.cyrillic() {
+                out.push_str("\\setmainfont{CMU Serif}\n");
+                out.push_str("\\setsansfont{CMU Sans Serif}\n");
+                out.push_str("\\setmonofont{CMU Typewriter Text}\n");
+            }
+            out.push_str("\\usepackage{polyglossia}\n");
+            out.push_str(&format!("\\setdefaultlanguage{{{}}}\n", self.main().name()));
+            if !others.is_empty() {
+                out.push_str(&format!("\\setotherlanguages{{{}}}\n", others.join(",")));
+            }
+            "\\gappto"
+        } else {
+            // The last encoding and language given are the defaults.
+            let mut encodings = Vec::new();
+            for language in &self.languages {
+                let encoding = match language.is_cyrillic() {
+                    true => "T2A",
+                    false => "T1",
+                };
+                if !encodings.contains(&encoding) {
+                    encodings.insert(0, encoding);
+                }
+            }
+            let names: Vec<&str> = self.languages.iter().rev().map(|l| l.name()).collect();
+            out.push_str(&format!(
+                "\\usepackage[{}]{{fontenc}}\n",
+                encodings.join(",")
+            ));
+            out.push_str("\\usepackage[utf8]{inputenc}\n");
+            out.push_str(&format!("\\usepackage[{}]{{babel}}\n", names.join(",")));
+            "\\addto"
+        };
+        for language in &self.languages {
+            if language.captions().is_empty() {
+                continue;
+            }
+            out.push_str(&format!("{}\\captions{}{{%\n", captions, language.name()));
+            for (name, value) in language.captions() {
+                out.push_str(&format!("  \\def\\{}{{{}}}%\n", name, value));
+            }
+            out.push_str("}\n");
+        }
+        out
+    }
+}
+
+/// babel and polyglossia names of the languages documents are commonly in,
+/// with their BCP 47 codes.
+const CODES: &[(&str, &str)] = &[
+    ("english", "en"),
+    ("american", "en"),
+    ("USenglish", "en"),
+    ("british", "en"),
+    ("UKenglish", "en"),
+    ("russian", "ru"),
+    ("ukrainian", "uk"),
+    ("belarusian", "be"),
+    ("kazakh", "kk"),
+    ("german", "de"),
+    ("ngerman", "de"),
+    ("french", "fr"),
+    ("spanish", "es"),
+    ("italian", "it"),
+    ("portuguese", "pt"),
+    ("polish", "pl"),
+    ("czech", "cs"),
+    ("chinese", "zh"),
+    ("japanese", "ja"),
+];
+
+/// The main language a preamble sets with babel (the last language option,
+/// or `main=`) or polyglossia (`\setdefaultlanguage`), as a BCP 47 code for
+/// `lang` attributes: `ru` for `\usepackage[english,russian]{babel}`.
+pub fn declared(preamble: &str) -> Option<&'static str> {
+    let code = |name: &str| {
+        CODES
+            .iter()
+            .find(|(known, _)| *known == name.trim())
+            .map(|(_, code)| *code)
+    };
+    let mut main = None;
+    for (index, _) in preamble.match_indices('\\') {
+        let rest = &preamble[index + 1..];
+        let command = &rest[..rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len())];
+        let rest = rest[command.len()..].trim_start();
+        let (options, rest) = match rest.strip_prefix('[') {
+            Some(rest) => rest.split_once(']').unwrap_or((rest, "")),
+            None => ("", rest),
+        };
+        let Some((argument, _)) = rest
+            .trim_start()
+            .strip_prefix('{')
+            .and_then(|r| r.split_once('}'))
+        else {
+            continue;
+        };
+        let language = match command {
+            "usepackage" | "RequirePackage" if argument.trim() == "babel" => {
+                let options: Vec<&str> = options.split(',').map(str::trim).collect();
+                match options.iter().find_map(|o| o.strip_prefix("main=")) {
+                    Some(name) => code(name),
+                    None => options.iter().rev().find_map(|o| code(o)),
+                }
+            }
+            "setdefaultlanguage" | "setmainlanguage" => code(argument),
+            _ => None,
+        };
+        main = language.or(main);
+    }
+    main
+}
+
+fn is_package(command: &str, package: &str) -> bool {
+    (command.starts_with("\\usepackage") || command.starts_with("\\RequirePackage"))
+        && command.ends_with(&format!("{{{}}}", package))
+}
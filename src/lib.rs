@@ -0,0 +1,104 @@
+//! The modules of the `chemtex` command, as a library for its benchmarks.
+
+pub mod accessibility;
+pub mod accounts;
+pub mod api;
+pub mod attach;
+pub mod attest;
+pub mod badge;
+pub mod balance;
+pub mod batch;
+pub mod bib;
+pub mod cache;
+pub mod callbacks;
+pub mod ci;
+pub mod citations;
+pub mod cli;
+pub mod compare;
+pub mod compendium;
+pub mod config;
+pub mod cookies;
+pub mod count;
+pub mod cron;
+pub mod daemon;
+pub mod deps;
+pub mod diagnostics;
+pub mod elements;
+pub mod epub;
+pub mod eta;
+pub mod fixtures;
+pub mod flashcards;
+pub mod formulas;
+pub mod git;
+pub mod glossary;
+pub mod graph;
+pub mod har;
+pub mod history;
+pub mod hooks;
+pub mod html;
+pub mod import;
+pub mod index;
+pub mod job;
+pub mod journal;
+pub mod languages;
+#[cfg(unix)]
+pub mod limits;
+pub mod local;
+pub mod lock;
+pub mod lsp;
+pub mod manifest;
+pub mod mapped;
+pub mod mathml;
+pub mod md2tex;
+pub mod metrics;
+pub mod molecule;
+pub mod molfile;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod overleaf;
+pub mod pack;
+pub mod package;
+pub mod paths;
+pub mod pictograms;
+pub mod plot;
+pub mod plugins;
+pub mod poller;
+pub mod preprocess;
+pub mod preview;
+pub mod progress;
+pub mod provenance;
+pub mod publish;
+pub mod qr;
+pub mod rasterize;
+pub mod redact;
+pub mod render;
+pub mod report;
+pub mod safety;
+pub mod scaffold;
+pub mod schemes;
+pub mod scratch;
+pub mod selective;
+pub mod session;
+pub mod shutdown;
+pub mod sigfigs;
+pub mod smiles;
+pub mod spectrum;
+pub mod spell;
+pub mod sqlite;
+pub mod state;
+pub mod stats;
+pub mod stdio;
+pub mod stoich;
+pub mod storage;
+pub mod suggestions;
+pub mod table;
+pub mod templates;
+pub mod titlepage;
+pub mod triage;
+pub mod tui;
+pub mod validate;
+pub mod variables;
+pub mod vault;
+pub mod watch;
+pub mod window;
+pub mod xml;
@@ -0,0 +1,166 @@
+//! Bounds a batch manifest expects a document's PDF to stay within, checked
+//! once it is written, to catch runaway documents (an appendix included by
+//! mistake, images left at full resolution, a bibliography in a loop):
+//!
+//! ```yaml
+//! defaults:
+//!   limits:
+//!     max_pages: 40
+//!     max_size: 20M
+//!     max_compile_seconds: 120
+//! jobs:
+//!   - input: compendium/main.tex
+//!     limits:
+//!       max_pages: 400
+//!       on_exceed: warn
+//! ```
+//!
+//! A job's limits override the defaults one by one. Exceeding one fails the
+//! job, keeping its PDF, unless `on_exceed: warn`.
+
+use crate::api;
+use crate::cache;
+use crate::count;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub max_pages: Option<u32>,
+    /// `20M`, or a number of bytes.
+    pub max_size: Option<Size>,
+    /// Of the compile itself, as reported by the server or Tectonic; builds
+    /// served from the cache are not timed again.
+    pub max_compile_seconds: Option<u64>,
+    pub on_exceed: Option<OnExceed>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnExceed {
+    #[default]
+    Fail,
+    Warn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Size {
+    Bytes(u64),
+    Text(String),
+}
+
+impl Size {
+    fn bytes(&self) -> Result<u64> {
+        match self {
+            Self::Bytes(bytes) => Ok(*bytes),
+            Self::Text(text) => {
+                api::parse_size(text).with_context(|| format!("Invalid max_size: {}", text))
+            }
+        }
+    }
+}
+
+/// The limits a PDF went over, as the job's error.
+#[derive(Debug)]
+pub struct LimitsExceeded(pub Vec<String>);
+
+impl fmt::Display for LimitsExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Limits exceeded: {}", self.0.join(", "))
+    }
+}
+
+impl std::error::Error for LimitsExceeded {}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.max_pages.is_none() && self.max_size.is_none() && self.max_compile_seconds.is_none()
+    }
+
+    /// These limits, with those of `defaults` where they set none.
+    pub fn or(self, defaults: &Limits) -> Limits {
+        Limits {
+            max_pages: self.max_pages.or(defaults.max_pages),
+            max_size: self.max_size.or_else(|| defaults.max_size.clone()),
+            max_compile_seconds: self.max_compile_seconds.or(defaults.max_compile_seconds),
+            on_exceed: self.on_exceed.or(defaults.on_exceed),
+        }
+    }
+
+    /// Checks that the size can be read, so a typo fails when the manifest
+    /// is loaded rather than after the build.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(size) = &self.max_size {
+            size.bytes()?;
+        }
+        Ok(())
+    }
+
+    /// What `output` went over, each like `312 pages (at most 40)`.
+    pub fn exceeded(&self, output: &Path, compile_ms: Option<u64>) -> Result<Vec<String>> {
+        let mut exceeded = Vec::new();
+        if let Some(max) = self.max_pages {
+            let pdf =
+                fs::read(output).with_context(|| format!("Failed to read {}", output.display()))?;
+            if let Some(pages) = count::page_count(&pdf).filter(|pages| *pages > max) {
+                exceeded.push(format!("{} pages (at most {})", pages, max));
+            }
+        }
+        if let Some(max) = &self.max_size {
+            let max = max.bytes()?;
+            let size = fs::metadata(output)
+                .with_context(|| format!("Failed to read {}", output.display()))?
+                .len();
+            if size > max {
+                exceeded.push(format!(
+                    "{} PDF (at most {})",
+                    cache::format_bytes(size),
+                    cache::format_bytes(max)
+                ));
+            }
+        }
+        if let (Some(max), Some(ms)) = (self.max_compile_seconds, compile_ms) {
+            if ms > max * 1000 {
+                exceeded.push(format!(
+                    "compiled in {} (at most {})",
+                    api::format_milliseconds(ms),
+                    api::format_milliseconds(max * 1000)
+                ));
+            }
+        }
+        Ok(exceeded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn job_limits_override_the_defaults_one_by_one() {
+        let defaults: Limits =
+            serde_yaml::from_str("max_pages: 40\nmax_size: 1K\non_exceed: warn").unwrap();
+        let limits: Limits = serde_yaml::from_str("max_pages: 400\nmax_size: 10").unwrap();
+        let limits = limits.or(&defaults);
+        assert_eq!(limits.max_pages, Some(400));
+        assert_eq!(limits.max_size, Some(Size::Bytes(10)));
+        assert_eq!(limits.on_exceed, Some(OnExceed::Warn));
+
+        let pdf = std::env::temp_dir().join(format!("chemtex-limits-{}.pdf", std::process::id()));
+        fs::write(&pdf, [b'%'; 2048]).unwrap();
+        let exceeded = defaults.exceeded(&pdf, Some(3000)).unwrap();
+        fs::remove_file(&pdf).unwrap();
+        assert_eq!(exceeded, ["2.0 KiB PDF (at most 1.0 KiB)"]);
+        assert!(Limits {
+            max_size: Some(Size::Text("lots".to_string())),
+            ..Limits::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Compiling on this machine with Tectonic, when the `tectonic` program is
+//! installed: always with `--local`, and in place of the server when it
+//! cannot be reached. chemtex does not link Tectonic; it runs the program
+//! found on `PATH`. The job goes through the same packing beforehand and
+//! the same diagnostics, output and history afterwards; only the upload,
+//! queue and download are replaced by a run of `tectonic`, which fetches
+//! the packages it needs once and then works offline.
+//!
+//! Tectonic is XeTeX-based, so a job's engine is not honoured, and the
+//! document runs untrusted (no shell escape).
+
+use crate::api::{CompilationFailed, CompileOptions};
+use crate::batch;
+use crate::pack::{self, TempDir};
+use crate::progress::Progress;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+/// When a job compiles locally.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LocalMode {
+    /// When the server cannot be reached and Tectonic is installed.
+    #[default]
+    Fallback,
+    /// Always (`--local`).
+    Always,
+}
+
+/// What a local build produced.
+#[derive(Debug)]
+pub struct LocalBuild {
+    /// The PDF, or why the document did not compile.
+    pub outcome: std::result::Result<Vec<u8>, CompilationFailed>,
+    pub log: String,
+    pub duration_ms: u64,
+}
+
+/// Checks that `--local` can work on this machine.
+pub fn check_supported() -> Result<()> {
+    anyhow::ensure!(
+        available(),
+        "--local needs Tectonic (https://tectonic-typesetting.github.io) installed"
+    );
+    Ok(())
+}
+
+/// Whether local builds can be made: the `tectonic` program is installed.
+pub fn available() -> bool {
+    std::process::Command::new("tectonic")
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Compiles `contents`, a `.tex` file or a packed project named
+/// `file_name`. Fails only when Tectonic cannot be run; a document that
+/// does not compile is a build whose outcome says why.
+pub async fn compile(
+    contents: &[u8],
+    file_name: &str,
+    options: &CompileOptions,
+    progress: &Progress,
+    label: &str,
+) -> Result<LocalBuild> {
+    if let Some(engine) = options.engine.as_deref().filter(|e| *e != "xelatex") {
+        progress.log(
+            label,
+            format!(
+                "Tectonic compiles with XeTeX; engine {} is not used",
+                engine
+            ),
+        );
+    }
+    let scratch = TempDir::new("local")?;
+    let sources = scratch.path().join("sources");
+    let out_dir = scratch.path().join("out");
+    for dir in [&sources, &out_dir] {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    let main = match Path::new(file_name).extension().and_then(|e| e.to_str()) {
+        Some("zip") => {
+            let archive = scratch.path().join(file_name);
+            fs::write(&archive, contents)
+                .with_context(|| format!("Failed to write file: {}", archive.display()))?;
+            pack::extract_archive(&archive, &sources)?;
+            // Packed projects keep the main file's name.
+            let named = sources.join(Path::new(file_name).with_extension("tex"));
+            match named.is_file() {
+                true => named,
+                false => batch::find_main_document(&sources)?,
+            }
+        }
+        _ => {
+            let main = sources.join(file_name);
+            fs::write(&main, contents)
+                .with_context(|| format!("Failed to write file: {}", main.display()))?;
+            main
+        }
+    };
+
+    progress.log(label, "Compiling locally with Tectonic...");
+    let started = Instant::now();
+    let output = tokio::process::Command::new("tectonic")
+        .args([
+            "--keep-logs",
+            "--untrusted",
+            "--chatter",
+            "minimal",
+            "--outdir",
+        ])
+        .arg(&out_dir)
+        .arg(&main)
+        .current_dir(main.parent().unwrap_or(&sources))
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to run tectonic")?;
+    let duration_ms = started.elapsed().as_millis() as u64;
+    let stem = main.file_stem().unwrap_or_default().to_string_lossy();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let log = fs::read_to_string(out_dir.join(format!("{}.log", stem)))
+        .unwrap_or_else(|_| stderr.clone().into_owned());
+    let failed = |message: &str| CompilationFailed {
+        message: message.trim().to_string(),
+        duration_ms: Some(duration_ms),
+        log_url: None,
+        processing_started: None,
+        queue_position: None,
+    };
+    let outcome = if output.status.success() {
+        let pdf_path = out_dir.join(format!("{}.pdf", stem));
+        fs::read(&pdf_path).map_err(|_| failed("Tectonic wrote no PDF"))
+    } else {
+        let message = log
+            .lines()
+            .find_map(|line| line.strip_prefix("! "))
+            .or_else(|| stderr.lines().rfind(|line| !line.trim().is_empty()))
+            .unwrap_or("tectonic failed");
+        Err(failed(message))
+    };
+    Ok(LocalBuild {
+        outcome,
+        log,
+        duration_ms,
+    })
+}
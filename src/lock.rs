@@ -0,0 +1,55 @@
+use crate::cache;
+use crate::progress::Progress;
+use crate::storage;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+/// Held while a job writes an output PDF, so two chemtex processes building
+/// the same document (`watch` and a manual compile, say) take turns instead
+/// of uploading twice and racing to write the PDF. The one that waited then
+/// usually finds the other's build in the cache.
+///
+/// The lock is an OS file lock on `locks/<hash of the output path>` in the
+/// data directory, released when the guard is dropped or the process dies.
+#[derive(Debug)]
+pub struct OutputLock {
+    _file: File,
+}
+
+impl OutputLock {
+    pub async fn acquire(output: &Path, progress: &Progress, label: &str) -> Result<Self> {
+        let dir = storage::data_dir()?.join("locks");
+        storage::ensure_dir(&dir)?;
+        let output = std::path::absolute(output)
+            .with_context(|| format!("Invalid output path: {}", output.display()))?;
+        let digest = Sha256::digest(output.to_string_lossy().as_bytes());
+        let path = dir.join(format!("{}.lock", cache::to_hex(&digest[..8])));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file: {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => return Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+        progress.log(
+            label,
+            format!(
+                "Waiting for another chemtex run writing {}...",
+                output.display()
+            ),
+        );
+        tokio::task::spawn_blocking(move || file.lock().map(|()| Self { _file: file }))
+            .await
+            .context("Lock task panicked")?
+            .with_context(|| format!("Failed to lock {}", path.display()))
+    }
+}
@@ -0,0 +1,89 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use url::Url;
+
+const SOURCE: &str = "chemtex";
+/// LSP ranges are character-based; TeX only tells us the line, so mark the
+/// whole line.
+const LINE_END_CHARACTER: u32 = 1000;
+
+/// Parameters of a `textDocument/publishDiagnostics` notification.
+#[derive(Debug, Clone, Serialize)]
+pub struct PublishDiagnosticsParams {
+    pub uri: String,
+    pub diagnostics: Vec<LspDiagnostic>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LspDiagnostic {
+    pub range: Range,
+    pub severity: u8,
+    pub source: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+/// Groups diagnostics by source file and converts them to LSP payloads.
+///
+/// Diagnostics are attached to [`Diagnostic::source_path`]. Files listed in
+/// `previously_published` that have no diagnostics this time get an empty
+/// list so editors clear stale squiggles.
+pub fn publish_diagnostics(
+    main: &Path,
+    diagnostics: &[Diagnostic],
+    previously_published: &HashSet<String>,
+) -> Vec<PublishDiagnosticsParams> {
+    let mut by_uri: BTreeMap<String, Vec<LspDiagnostic>> = BTreeMap::new();
+
+    if let Some(uri) = file_uri(main) {
+        by_uri.entry(uri).or_default();
+    }
+    for uri in previously_published {
+        by_uri.entry(uri.clone()).or_default();
+    }
+
+    for diagnostic in diagnostics {
+        let Some(uri) = file_uri(&diagnostic.source_path(main)) else {
+            continue;
+        };
+        let line = diagnostic.line.unwrap_or(1).saturating_sub(1);
+        by_uri.entry(uri).or_default().push(LspDiagnostic {
+            range: Range {
+                start: Position { line, character: 0 },
+                end: Position {
+                    line,
+                    character: LINE_END_CHARACTER,
+                },
+            },
+            severity: match diagnostic.severity {
+                Severity::Error => 1,
+                Severity::Warning => 2,
+            },
+            source: SOURCE,
+            message: diagnostic.message.clone(),
+        });
+    }
+
+    by_uri
+        .into_iter()
+        .map(|(uri, diagnostics)| PublishDiagnosticsParams { uri, diagnostics })
+        .collect()
+}
+
+fn file_uri(path: &Path) -> Option<String> {
+    let absolute = std::path::absolute(path).ok()?;
+    Url::from_file_path(absolute).ok().map(String::from)
+}
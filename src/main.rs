@@ -1,322 +1,544 @@
-use anyhow::{Context, Result};
-use reqwest::multipart;
-use serde::Deserialize;
-use std::fs;
+use anyhow::Result;
+use api::{BuildMode, CompileOptions};
+use badge::{Badge, BadgeConfig};
+use chem_tex_summury_creator::*;
+use ci::OutputFormat;
+use citations::CitationStyle;
+use cli::Args;
+use config::ProjectConfig;
+use git::GitSource;
+use job::{Job, Runner};
+use languages::Languages;
+use local::LocalMode;
+use pack::{PackOptions, Project, TempDir};
+use progress::Progress;
 use std::path::{Path, PathBuf};
-use tokio::time::{sleep, Duration};
 
-const BASE_URL: &str = "https://texcompile.ru";
-const POLL_INTERVAL_SECS: u64 = 5;
-const MAX_POLL_ATTEMPTS: u32 = 120;
-const REQUEST_TIMOUT_SECS: u64 = 600;
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum CompilationStatus {
-    Queued,
-    Processing,
-    Completed,
-    Failed,
-    Unknown(String),
-}
-
-impl CompilationStatus {
-    fn from_str(s: &str) -> Self {
-        match s {
-            "Queued" => Self::Queued,
-            "Processing" => Self::Processing,
-            "Completed" => Self::Completed,
-            "Failed" => Self::Failed,
-            other => Self::Unknown(other.to_string()),
-        }
+#[tokio::main]
+async fn main() {
+    let result = run().await;
+    scratch::cleanup();
+    if let Err(e) = result {
+        // As a returned error would print, but without credentials.
+        eprintln!("Error: {}", redact::text(&format!("{:?}", e)));
+        std::process::exit(1);
     }
 }
 
-#[derive(Debug, Deserialize)]
-struct UploadResponse {
-    success: bool,
-    data: Option<UploadData>,
-    error: Option<String>,
-    message: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct UploadData {
-    #[serde(rename = "taskId")]
-    task_id: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct StatusResponse {
-    success: bool,
-    data: Option<StatusData>,
-    error: Option<String>,
-}
-
-#[derive(Debug, Deserialize)]
-struct StatusData {
-    status: String,
-    #[serde(rename = "downloadUrl")]
-    download_url: Option<String>,
-    #[serde(rename = "errorMessage")]
-    error_message: Option<String>,
-    duration: Option<u64>,
-    #[serde(rename = "queuePosition")]
-    queue_position: Option<u32>,
-}
-
-impl StatusData {
-    fn compilation_status(&self) -> CompilationStatus {
-        CompilationStatus::from_str(&self.status)
+async fn run() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let account = accounts::take_arg(&mut args)?;
+    accounts::choose(account.as_deref())?;
+    if args.len() < 2 {
+        eprintln!(
+            "Usage: {} <path_to_tex_or_zip_file> [--no-cache] [--plain] [--format text|github|json]",
+            args[0]
+        );
+        eprintln!(
+            "       {} accounts [list | add NAME | remove NAME | default NAME]",
+            args[0]
+        );
+        eprintln!("       {} attach [TASK_ID | --last] [--out FILE]", args[0]);
+        eprintln!(
+            "       {} balance \"<reaction>\" [--over TEXT] [--under TEXT]",
+            args[0]
+        );
+        eprintln!(
+            "       {} batch <dir> | --manifest jobs.yaml | --retry-failed [options]",
+            args[0]
+        );
+        eprintln!("       {} bib add <DOI>... [--file FILE] [--acs]", args[0]);
+        eprintln!(
+            "       {} cache stats | gc [--max-size SIZE] | clear",
+            args[0]
+        );
+        eprintln!("       {} clean [--dry-run]", args[0]);
+        eprintln!(
+            "       {} compare --engines pdflatex,lualatex <file.tex|project_dir>",
+            args[0]
+        );
+        eprintln!(
+            "       {} compendium <summary.tex>... --out FILE [--title TEXT]",
+            args[0]
+        );
+        eprintln!(
+            "       {} count <main.tex|project_dir> [--max-words N] [--max-pages N]",
+            args[0]
+        );
+        eprintln!(
+            "       {} daemon [--listen ADDR] [--jobs N] [--config FILE]",
+            args[0]
+        );
+        eprintln!(
+            "       {} elements <symbol>... [--props LIST] [--as text|table]",
+            args[0]
+        );
+        eprintln!(
+            "       {} encryption enable [--key-file] | disable | status",
+            args[0]
+        );
+        eprintln!(
+            "       {} epub <main.tex|project_dir> [--out FILE] [--no-render]",
+            args[0]
+        );
+        eprintln!(
+            "       {} flashcards <summary.tex> --out deck.apkg [--no-render]",
+            args[0]
+        );
+        eprintln!("       {} git-changed [<rev-range>] [options]", args[0]);
+        eprintln!(
+            "       {} glossary [file.tex|project_dir] [--out FILE] [--check]",
+            args[0]
+        );
+        eprintln!(
+            "       {} graph <main.tex|project_dir> [--dot | --json]",
+            args[0]
+        );
+        eprintln!(
+            "       {} history export [--out FILE] | import FILE",
+            args[0]
+        );
+        eprintln!(
+            "       {} journal add <title> [--data FILE] [--note TEXT] [--notebook DIR]",
+            args[0]
+        );
+        eprintln!(
+            "       {} import <report.docx|report.odt> [--out FILE] [--no-compile]",
+            args[0]
+        );
+        eprintln!("       {} import-overleaf <project-url-or-zip>", args[0]);
+        eprintln!("       {} keys generate [--force] | public", args[0]);
+        eprintln!(
+            "       {} molfile <structure.mol|.sdf> [--as chemfig|png]",
+            args[0]
+        );
+        eprintln!(
+            "       {} new <template> <name> [--from REGISTRY] | --list",
+            args[0]
+        );
+        eprintln!(
+            "       {} package --target arxiv <main.tex|project_dir> [--out FILE] [--no-verify]",
+            args[0]
+        );
+        eprintln!(
+            "       {} plot <data.csv> [--x NAME] [--y NAME] [--fit KIND]",
+            args[0]
+        );
+        eprintln!("       {} publish login | logout", args[0]);
+        eprintln!(
+            "       {} safety <substance>... [--assets DIR] [--codes-only]",
+            args[0]
+        );
+        eprintln!(
+            "       {} smiles <SMILES> [--into FILE] [--marker NAME]",
+            args[0]
+        );
+        eprintln!(
+            "       {} spectrum <peaks.csv> [--kind nmr|ir] [--mhz N]",
+            args[0]
+        );
+        eprintln!(
+            "       {} spell [main.tex|project_dir] [--dict LIST] | --add WORD...",
+            args[0]
+        );
+        eprintln!(
+            "       {} stoich \"<reaction>\" --given FORMULA=AMOUNT... [--actual AMOUNT]",
+            args[0]
+        );
+        eprintln!(
+            "       {} table <data.csv> [--sig-figs N] [--units LIST]",
+            args[0]
+        );
+        eprintln!("       {} templates list | update", args[0]);
+        eprintln!("       {} tui [dir] [--jobs N]", args[0]);
+        eprintln!(
+            "       {} verify <report.pdf> [--key chemtex.pub] [--sources DIR]",
+            args[0]
+        );
+        eprintln!("       {} watch <file> [--serve] [--listen ADDR]", args[0]);
+        eprintln!("       {} --stdio", args[0]);
+        scratch::exit(1);
     }
 
-    fn format_duration(&self) -> String {
-        self.duration
-            .map(format_milliseconds)
-            .unwrap_or_else(|| "неизвестно".to_string())
+    match args[1].as_str() {
+        "compile" => compile_and_download(&args[2..]).await,
+        "clean" => scratch::run(&args[2..]),
+        "accounts" => accounts::run(&args[2..]),
+        "attach" => attach::run(&args[2..]).await,
+        "keys" => attest::run_keys(&args[2..]),
+        "verify" => attest::run_verify(&args[2..]),
+        "balance" => balance::run(&args[2..]),
+        "batch" => batch::run(&args[2..]).await,
+        "bib" => bib::run(&args[2..]).await,
+        "cache" => cache::run(&args[2..]),
+        "compare" => compare::run(&args[2..]).await,
+        "compendium" => compendium::run(&args[2..]).await,
+        "count" => count::run(&args[2..]),
+        "daemon" => daemon::run(&args[2..]).await,
+        "elements" => elements::run(&args[2..]),
+        "encryption" => vault::run(&args[2..]),
+        "epub" => epub::run(&args[2..]).await,
+        "flashcards" => flashcards::run(&args[2..]).await,
+        "git-changed" => git::run_changed(&args[2..]).await,
+        "glossary" => glossary::run(&args[2..]),
+        "graph" => graph::run(&args[2..]),
+        "history" => history::run(&args[2..]),
+        "journal" => journal::run(&args[2..]).await,
+        "md2tex" => md2tex::run(&args[2..]).await,
+        "molfile" => molfile::run(&args[2..]),
+        "new" => scaffold::run(&args[2..]).await,
+        "package" => package::run(&args[2..]).await,
+        "plot" => plot::run(&args[2..]),
+        "publish" => publish::run(&args[2..]),
+        "import" => import::run(&args[2..]).await,
+        "import-overleaf" => overleaf::run(&args[2..]).await,
+        "safety" => safety::run(&args[2..]),
+        "smiles" => smiles::run(&args[2..]),
+        "spectrum" => spectrum::run(&args[2..]),
+        "spell" => spell::run(&args[2..]),
+        "stats" => stats::run(&args[2..]),
+        "stoich" => stoich::run(&args[2..]),
+        "table" => table::run(&args[2..]),
+        "templates" => templates::run(&args[2..]).await,
+        "tui" => tui::run(&args[2..]).await,
+        "watch" => watch::run(&args[2..]).await,
+        "--stdio" => stdio::run().await,
+        _ => compile_and_download(&args[1..]).await,
     }
 }
 
-fn format_milliseconds(ms: u64) -> String {
-    let seconds = ms / 1000;
-    if seconds < 60 {
-        format!("{} сек.", seconds)
-    } else {
-        let minutes = seconds / 60;
-        let remaining_seconds = seconds % 60;
-        if minutes < 60 {
-            format! {"{} мин. {} сек.", minutes, remaining_seconds}
-        } else {
-            let hours = minutes / 60;
-            let remaining_minutes = minutes % 60;
-            format!("{} ч. {} м.", hours, remaining_minutes)
+const COMPILE_USAGE: &str = "\
+Usage: chemtex [compile] <path_to_tex_or_zip_file> [--no-cache] [--format text|github|json]
+       chemtex compile [project_dir] [--var NAME=VALUE] [--no-cache] [--format text|github|json]
+       chemtex compile --git URL[#branch][:path/to/main.tex] [--no-cache] [--format text|github|json]
+
+--citation-style acs|gost|apa sets the bibliography style of the main file
+when it is packed (natbib with BibTeX, or the biblatex style it loads).
+--only chapters/kinetics.tex (repeatable) compiles just those chapters of a
+project, with \\includeonly when they are \\include'd; the .aux files of a
+local build keep the numbers and references of the rest.
+--formula-index adds an Index of Compounds listing every \\ce{...} formula
+by Hill formula, with page references.
+--lang ru,en sets up babel (polyglossia with xelatex or lualatex) for these
+languages, the first being the main one: hyphenation patterns for each and
+Russian captions.
+--also-html writes an HTML version next to the PDF for publishing on a
+website: math and \\ce formulas are typeset by MathJax and images embedded,
+while TikZ, chemfig and PDF figures stay in the PDF only.
+When a compile fails with a common error (a command or environment of a
+package the preamble does not load, a missing file), a hint is printed;
+--fix adds the missing \\usepackage lines to the preamble.
+On a terminal, a failed compile is followed by a prompt to page through the
+log, list the errors, open one at its line in $VISUAL or $EDITOR, apply the
+suggested fixes and resubmit; --no-triage just reports the failure.
+--qr commit|URL stamps a QR code on the title page linking the printout to
+its source: `commit` is the web page of the current git commit on the
+`origin` remote (the server's task id is only assigned after upload, so it
+cannot be linked). Needs the qrcode package and LaTeX 2020 or later.
+--plain prints a status line only when the status changes rather than on
+every poll, for screen readers and logs.
+--provenance writes report.provenance.json next to report.pdf: the hashes
+of the sources and the PDF, the compile options, the server and the TeX
+version from the log, the task id, timestamps and the chemtex version.
+--sign also signs it with the key of `chemtex keys generate`, into
+report.provenance.json.minisig; `chemtex verify report.pdf` checks both.
+--priority P and --tag key=value (repeatable) are sent with the upload, as
+the task's priority and metadata for servers that take them, and kept in the
+history: `chemtex stats --tag source=ci` counts only the jobs tagged so.
+--format json prints progress on stderr and the outcome as one JSON object
+on stdout: {\"document\": {...}} on success, or {\"error\": {\"kind\": ...}}
+with the task id, log URL and an excerpt of the log's errors. The kinds are
+compilation_failed, interrupted, timeout, network_error, server_error and
+local_error.
+Figures with neither a \\caption nor alt text (\\includegraphics[alt={...}])
+are reported when the sources are packed.
+On Ctrl-C (or SIGTERM) the upload or download stops, partial files are
+removed and the task id is kept in the history: the server finishes the
+task and `chemtex attach` downloads it. --cancel-on-interrupt cancels the
+task instead.
+Inputs are checked before upload: empty or oversized files, invalid zip
+archives and .tex sources without \\documentclass or \\begin{document} fail
+locally; --no-validate uploads such sources anyway.
+Responses are capped at 1M and downloaded PDFs and logs at 256M; set
+CHEMTEX_MAX_RESPONSE_BYTES or CHEMTEX_MAX_DOWNLOAD_BYTES (e.g. 1G) to change
+the caps. PDFs of 16M or more are downloaded in 4 parallel ranges when the
+server supports it, each checked against the file's length, version and
+digest; CHEMTEX_SEGMENTED_MIN_BYTES and CHEMTEX_DOWNLOAD_SEGMENTS (at most
+16; 1 turns this off) change that.
+Zips of 8M or more are memory-mapped rather than read into memory
+(CHEMTEX_MMAP_MIN_BYTES changes the threshold).
+--record DIR saves every request to the server and its response in DIR;
+--replay DIR answers them from such a recording, offline. Both bypass the
+build cache. Uploads are recorded by name, size and hash, not content, so a
+recording can be attached to a bug report without the TeX sources (it does
+hold the PDF and log).
+--capture-http session.har writes every request to the server and its
+response as an HTTP Archive, for diagnosing and reporting server problems.
+Uploaded sources are left out and credentials masked; --capture-bodies
+full|truncated|none includes whole response bodies (PDFs base64-encoded),
+text bodies up to 64K (the default) or none.
+Builds are cached by a hash of what is uploaded and the compile options: an
+unchanged document is not uploaded again, and identical documents compiled
+together are uploaded once. In CI, keep $XDG_CACHE_HOME/chemtex between runs
+to reuse the builds of unchanged sources. The cache keeps up to
+CHEMTEX_CACHE_MAX_BYTES (1G) of PDFs and logs, evicting the least recently
+used builds; see `chemtex cache`.
+Cookies the server sets (backend affinity) are kept in cookies.json in the
+data directory and sent back by later runs; CHEMTEX_NO_COOKIES=1 turns this
+off.
+Projects are looked up in the build cache before they are packed, by the
+hashes of their files, which are kept between runs and recomputed only for
+files whose size or modification time changed.
+--stream packs a project (or --git checkout) while uploading it: each file
+is sent as soon as it is compressed, with no archive written to disk.
+Plugin upload filters need the whole archive and are skipped.
+--local compiles on this machine with Tectonic instead of the server, when
+the `tectonic` program is installed; builds also fall back to it when the
+server cannot be reached. Tectonic is XeTeX-based,
+so --engine is not honoured, and local builds are not cached.
+--draft and --final are sent with the upload for servers that build drafts
+quickly and final copies with every pass and the bibliography, and are
+cached apart. A --final build is never replaced by a local one when the
+server cannot be reached. `chemtex watch` builds drafts, locally for
+profiles marked `draft: local` in a batch manifest (see `chemtex batch`).
+--publish github:owner/repo@tag uploads the PDF, with its provenance record
+and signature if any, as assets of that GitHub release (created if need
+be), replacing assets of the same name; the token is GITHUB_TOKEN or the
+one `chemtex publish login` keeps in the OS keyring.
+--badge build.svg writes a status badge for a README (passing or failing,
+pages, build date) and --badge-url URL PUTs it to URL; `[badge]` in
+.chemtex.toml sets either for a project.";
+
+async fn compile_and_download(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &[
+            "no-cache",
+            "formula-index",
+            "also-html",
+            "fix",
+            "plain",
+            "cancel-on-interrupt",
+            "no-triage",
+            "no-validate",
+            "stream",
+            "provenance",
+            "sign",
+            "local",
+            "draft",
+            "final",
+        ],
+        &[
+            "format",
+            "git",
+            "var",
+            "citation-style",
+            "only",
+            "lang",
+            "qr",
+            "record",
+            "replay",
+            "capture-http",
+            "capture-bodies",
+            "priority",
+            "tag",
+            "publish",
+            "badge",
+            "badge-url",
+        ],
+    )?;
+    let publish_target = publish::target(&args)?;
+    fixtures::configure(&args)?;
+    har::configure(&args)?;
+    let citation_style = args
+        .value("citation-style")
+        .map(CitationStyle::parse)
+        .transpose()?;
+    let only: Vec<String> = args.values("only").into_iter().map(String::from).collect();
+    // Packed by the job, so the cache can be checked first.
+    let mut packed = None;
+    // Keeps the clone around until the upload has finished.
+    let mut checkout = None;
+    let mut project = None;
+    // The source `--fix` edits.
+    let mut source = None;
+    let input = match (args.value("git"), args.positional(0)) {
+        (Some(spec), None) => {
+            let source = GitSource::parse(spec)?;
+            let dir = checkout.insert(TempDir::new("git")?);
+            println!("Cloning {}...", source.url);
+            let main = source.checkout(dir.path())?;
+            let options = PackOptions {
+                citation_style,
+                only,
+                formula_index: args.flag("formula-index"),
+                languages: args
+                    .value("lang")
+                    .map(|spec| Languages::parse(spec, None))
+                    .transpose()?,
+                qr: args
+                    .value("qr")
+                    .map(|spec| qr::resolve(spec, dir.path()))
+                    .transpose()?,
+                ..PackOptions::default()
+            };
+            let archive = dir.path().join(pack::archive_name(&main)?);
+            packed = Some(Project { main, options });
+            archive
+        }
+        (None, path) if is_project(path) => {
+            let dir = PathBuf::from(path.unwrap_or("."));
+            let mut config = ProjectConfig::find(&dir)?.unwrap_or_default();
+            let main = match &config.main {
+                Some(main) => dir.join(main),
+                None => batch::find_main_document(&dir)?,
+            };
+            config
+                .variables
+                .extend(variables::parse_assignments(&args.values("var"))?);
+            let scratch = checkout.insert(TempDir::new("project")?);
+            let mut options = PackOptions::from_config(&config)?;
+            if citation_style.is_some() {
+                options.citation_style = citation_style;
+            }
+            if let Some(spec) = args.value("lang") {
+                options.languages = Some(Languages::parse(spec, config.engine.as_deref())?);
+            }
+            options.only = only;
+            options.formula_index |= args.flag("formula-index");
+            options.qr = args
+                .value("qr")
+                .or(config.qr.as_deref())
+                .map(|spec| qr::resolve(spec, &dir))
+                .transpose()?;
+            let archive = scratch.path().join(pack::archive_name(&main)?);
+            source = Some(main.clone());
+            packed = Some(Project { main, options });
+            project = Some((dir, config));
+            archive
+        }
+        (None, Some(_)) if !only.is_empty() => {
+            anyhow::bail!("--only needs a project directory, not a single file")
         }
+        (None, Some(_)) if args.flag("stream") => {
+            anyhow::bail!("--stream needs a project directory or --git, not a single file")
+        }
+        (None, Some(file_path)) => source.insert(PathBuf::from(file_path)).clone(),
+        _ => anyhow::bail!(COMPILE_USAGE),
+    };
+    let format = OutputFormat::parse(args.value("format"))?;
+
+    let use_cache = !args.flag("no-cache") && !fixtures::active();
+    let mut runner = Runner::new(api::build_client()?, use_cache)?;
+    if args.flag("plain") {
+        runner.progress = Progress::plain();
     }
-}
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <path_to_tex_or_zip_file>", args[0]);
-        std::process::exit(1);
+    if format == OutputFormat::Json {
+        runner.progress = runner.progress.on_stderr();
     }
-
-    let file_path = &args[1];
-    compile_and_download(file_path).await?;
-    Ok(())
-}
-
-async fn compile_and_download(file_path: &str) -> Result<()> {
-    println!("Reading files: {}", file_path);
-    let file_contents =
-        fs::read(file_path).with_context(|| format!("Failed to read file: {}", file_path))?;
-
-    let file_name = Path::new(file_path)
-        .file_name()
-        .and_then(|n| n.to_str())
-        .context("Invalid file name")?;
-
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(REQUEST_TIMOUT_SECS))
-        .build()
-        .context("Failed to create http client")?;
-
-    println!("Uploading file to {}...", BASE_URL);
-    let task_id = upload_file(&client, &file_contents, file_name).await?;
-    println!("File uploaded. Task ID: {}", task_id);
-
-    println!("Waiting for compilation to complete...");
-    let download_url = poll_status(&client, &task_id).await?;
-
-    println!("Downloading PDF from {}", download_url);
-    let pdf_bytes = download_pdf(&client, &download_url).await?;
-
-    let output_path = generate_output_path(file_name)?;
-    fs::write(&output_path, pdf_bytes)
-        .with_context(|| format!("Failed to write PDF file: {}", output_path.display()))?;
-
-    println!("PDF saved to: {}", output_path.display());
-    Ok(())
-}
-
-async fn upload_file(
-    client: &reqwest::Client,
-    file_contents: &[u8],
-    file_name: &str,
-) -> Result<String> {
-    let part = multipart::Part::bytes(file_contents.to_vec())
-        .file_name(file_name.to_string())
-        .mime_str(mime_type_from_filename(file_name)?)
-        .context("Failed to set MIME type")?;
-
-    let form = multipart::Form::new().part("texFile", part);
-
-    let response = client
-        .post(format!("{}/api/upload", BASE_URL))
-        .multipart(form)
-        .send()
-        .await
-        .context("Failed to submit form")?;
-
-    let status = response.status();
-    if !status.is_success() {
-        let text = response
-            .text()
-            .await
-            .context("Failed to read error response")?;
-        anyhow::bail!("Upload failed with status {}: {}", status, text);
+    runner.cancel_on_interrupt = args.flag("cancel-on-interrupt");
+    runner.skip_validation = args.flag("no-validate");
+    runner.provenance = args.flag("provenance");
+    if args.flag("sign") {
+        runner.signer = Some(attest::Signer::load_default()?);
     }
-
-    let upload_response: UploadResponse = response
-        .json()
-        .await
-        .context("Failed to parse upload response")?;
-
-    if !upload_response.success {
-        let error_msg = upload_response
-            .error
-            .or(upload_response.message)
-            .unwrap_or_else(|| "Unknown error".to_string());
-        anyhow::bail!("Upload failed: {}", error_msg);
+    if args.flag("local") {
+        local::check_supported()?;
+        runner.local = LocalMode::Always;
     }
-
-    let task_id = upload_response
-        .data
-        .map(|d| d.task_id)
-        .context("No task ID in response")?;
-
-    Ok(task_id)
-}
-
-async fn poll_status(client: &reqwest::Client, task_id: &str) -> Result<String> {
-    let poll_interval = Duration::from_secs(POLL_INTERVAL_SECS);
-
-    for attempt in 1..=MAX_POLL_ATTEMPTS {
-        let url = format!("{}/api/status/{}", BASE_URL, task_id);
-        let response = client
-            .get(&url)
-            .send()
-            .await
-            .context("Failed to check status")?;
-
-        let status = response.status();
-        if !status.is_success() {
-            let text = response
-                .text()
-                .await
-                .context("Failed to read error response")?;
-            anyhow::bail!("Status check failed with status {}: {}", status, text);
+    runner.collect_diagnostics = format != OutputFormat::Text;
+    let mut job = Job::new(&input, Path::new(""))?;
+    // A fresh clone has nothing to find in an index.
+    job.index_sources = project.is_some();
+    job.project = packed;
+    job.stream = args.flag("stream");
+    job.citation_style = citation_style;
+    job.formula_index = args.flag("formula-index");
+    job.languages = args
+        .value("lang")
+        .map(|spec| Languages::parse(spec, None))
+        .transpose()?;
+    if let (Some(spec), None, None) = (args.value("qr"), &checkout, &project) {
+        if input.extension().and_then(|e| e.to_str()) != Some("tex") {
+            anyhow::bail!("--qr needs .tex sources, not {}", input.display());
         }
-
-        let status_response: StatusResponse = response
-            .json()
-            .await
-            .context("Failed to parse status response")?;
-
-        if !status_response.success {
-            let error_msg = status_response
-                .error
-                .unwrap_or_else(|| "Unknown error".to_string());
-            anyhow::bail!("Status check returned error: {}", error_msg);
+        let dir = input.parent().unwrap_or(Path::new(""));
+        job.qr = Some(qr::resolve(spec, dir)?);
+    }
+    let mut badge_config = None;
+    if let Some((dir, config)) = project {
+        badge_config = config.badge.map(|badge| badge.relative_to(&dir));
+        job.options = CompileOptions {
+            engine: config.engine,
+            profile: config.profile,
+            ..CompileOptions::default()
+        };
+        if let Some(output) = config.output {
+            job.output = dir.join(output);
         }
-
-        let status_data = status_response.data.context("No status data in response")?;
-
-        match status_data.compilation_status() {
-            CompilationStatus::Queued => {
-                let queue_info = status_data
-                    .queue_position
-                    .filter(|&pos| pos > 0)
-                    .map(|pos| format!(" (position: {})", pos))
-                    .unwrap_or_default();
-                let duration_info = status_data.format_duration();
-                println!(
-                    "Status: Queued{} | Time in queue: {}",
-                    queue_info, duration_info
-                );
-            }
-            CompilationStatus::Processing => {
-                let duration_info = status_data.format_duration();
-                println!("Status: Processing... | Time: {}", duration_info);
+        job.status_policy = config.status;
+        job.hooks = config.hooks.in_dir(&dir);
+        job.account = accounts::chosen().for_project(config.account.as_deref())?;
+    }
+    job.options.set_labels(&args)?;
+    job.options.mode = BuildMode::from_args(&args)?;
+    let triage = format == OutputFormat::Text && !args.flag("no-triage") && triage::available();
+    let report = loop {
+        let report = runner.run_interruptible(&job, "").await;
+
+        if format == OutputFormat::Github {
+            ci::annotate(&report);
+            if let Some(task_id) = &report.details.task_id {
+                ci::set_output("task-id", task_id)?;
             }
-            CompilationStatus::Completed => {
-                println!(
-                    "Status: Completed! | Compilation time: {}",
-                    status_data.format_duration()
-                );
-                let download_url = status_data
-                    .download_url
-                    .context("No download URL in completed status")?;
-                return Ok(download_url);
+            if let Ok(output) = &report.result {
+                ci::set_output("artifact", &output.display().to_string())?;
             }
-            CompilationStatus::Failed => {
-                let duration_info = status_data.format_duration();
-                let error_msg = status_data
-                    .error_message
+        }
+        if report.result.is_err() && !report.cancelled {
+            let diagnostics = runner.diagnose(&report).await;
+            if triage {
+                let main = source
                     .as_deref()
-                    .unwrap_or("Unknown error");
-                anyhow::bail!("Compilation failed after {}: {}", duration_info, error_msg);
-            }
-            CompilationStatus::Unknown(status) => {
-                println!("Status: {} (unknown)", status)
+                    .filter(|main| main.extension().and_then(|e| e.to_str()) == Some("tex"));
+                if triage::offer(&runner, &report, &diagnostics, main).await? {
+                    continue;
+                }
+                // The error was shown before the prompt.
+                scratch::exit(1);
+            } else if suggestions::report(&diagnostics, source.as_deref(), args.flag("fix"))? {
+                eprintln!("Compile again to check the fixes");
             }
         }
-
-        if attempt < MAX_POLL_ATTEMPTS {
-            sleep(poll_interval).await;
+        break report;
+    };
+    if let Some(config) = BadgeConfig::from_args(&args, badge_config) {
+        let label = job.output.file_stem().unwrap_or_default().to_string_lossy();
+        let badge = Badge::from_reports(&label, std::slice::from_ref(&report));
+        badge::place(&runner.client, &config, &badge).await;
+    }
+    if format == OutputFormat::Json {
+        println!("{}", ci::json_result(&report, &report.details.diagnostics));
+        if report.result.is_err() {
+            // Already reported, as JSON.
+            scratch::exit(1);
         }
     }
-
-    anyhow::bail!("Compilation timeout after {} attempts", MAX_POLL_ATTEMPTS);
-}
-
-async fn download_pdf(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
-    let full_url = normalize_url(url);
-
-    let response = client
-        .get(&full_url)
-        .send()
-        .await
-        .context("Failed to download PDF")?;
-
-    let status = response.status();
-    if !status.is_success() {
-        anyhow::bail!("Filed to download PDF: status: {}", status);
+    let output = report.result?;
+    if args.flag("also-html") {
+        html::export(&input, &output.with_extension("html"))?;
     }
-
-    let bytes = response
-        .bytes()
-        .await
-        .context("Failed to read PDF bytes")?
-        .to_vec();
-
-    Ok(bytes)
-}
-
-fn normalize_url(url: &str) -> String {
-    if url.starts_with("http://") || url.starts_with("https://") {
-        url.to_string()
-    } else if url.starts_with('/') {
-        format!("{}{}", BASE_URL, url)
-    } else {
-        format!("{}/{}", BASE_URL, url)
+    if let Some(target) = &publish_target {
+        publish::publish(&runner.client, target, &output).await?;
     }
+    Ok(())
 }
 
-fn generate_output_path(input_file_name: &str) -> Result<PathBuf> {
-    let output_name = Path::new(input_file_name)
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .context("Invalid file name")?;
-    Ok(PathBuf::from(format!("{}.pdf", output_name)))
-}
-
-fn mime_type_from_filename(filename: &str) -> Result<&'static str> {
-    if filename.ends_with(".tex") {
-        Ok("text/x-tex")
-    } else if filename.ends_with(".zip") {
-        Ok("application/zip")
-    } else {
-        anyhow::bail!("Unsupported file type. Expected .tex or .zip");
+/// A project directory, or no path at all inside a project with a
+/// `.chemtex.toml`.
+fn is_project(path: Option<&str>) -> bool {
+    match path {
+        Some(path) => Path::new(path).is_dir(),
+        None => Path::new(config::CONFIG_FILE).is_file(),
     }
 }
@@ -0,0 +1,305 @@
+use crate::api::{BuildMode, CompileOptions};
+use crate::hooks::Hooks;
+use crate::job::Job;
+use crate::limits::Limits;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A batch manifest (`jobs.yaml`) listing documents and their options.
+///
+/// ```yaml
+/// defaults:
+///   engine: lualatex
+///   limits:
+///     max_pages: 60
+/// profiles:
+///   print:
+///     draft: local
+/// hooks:
+///   pre_compile: python plots/render.py
+///   post_compile: cp "$CHEMTEX_OUTPUT" ~/Shared/
+/// jobs:
+///   - input: lectures/01-kinetics.tex
+///     name: lecture-01
+///     output: lecture-01.pdf
+///   - input: compendium/main.tex
+///     engine: xelatex
+///     profile: print
+///     output: semester.pdf
+///     depends_on: [lecture-01]
+///     limits:
+///       max_pages: 400
+/// ```
+///
+/// A job starts once the jobs it `depends_on`, by name, have compiled (so
+/// it can include their PDFs), and is not compiled when one of them fails.
+/// See [`crate::limits`] for the limits.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub defaults: JobDefaults,
+    /// How the builds of each profile are made, by profile name.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    pub jobs: Vec<ManifestEntry>,
+}
+
+/// Build settings of a profile. Final builds (`--final`) always go through
+/// the server, with every pass and the bibliography.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Where drafts (`--draft`, and every `chemtex watch` build) compile.
+    #[serde(default)]
+    pub draft: DraftBuilds,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DraftBuilds {
+    /// On the server, like every other build.
+    #[default]
+    Remote,
+    /// With Tectonic on this machine (see [`crate::local`]): faster, but
+    /// XeTeX-only and without the server's passes.
+    Local,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobDefaults {
+    pub engine: Option<String>,
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub limits: Limits,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    pub input: PathBuf,
+    pub name: Option<String>,
+    pub engine: Option<String>,
+    pub profile: Option<String>,
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub limits: Limits,
+    /// Names of the jobs to compile first.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+        let manifest: Self = serde_yaml::from_str(&text)
+            .with_context(|| format!("Failed to parse manifest: {}", path.display()))?;
+        if manifest.jobs.is_empty() {
+            anyhow::bail!("Manifest {} declares no jobs", path.display());
+        }
+        let limits = std::iter::once(&manifest.defaults.limits)
+            .chain(manifest.jobs.iter().map(|entry| &entry.limits));
+        for limits in limits {
+            limits
+                .validate()
+                .with_context(|| format!("Invalid manifest: {}", path.display()))?;
+        }
+        Ok(manifest)
+    }
+
+    /// Whether drafts of `profile` compile locally.
+    pub fn drafts_locally(&self, profile: Option<&str>) -> bool {
+        profile
+            .and_then(|name| self.profiles.get(name))
+            .is_some_and(|profile| profile.draft == DraftBuilds::Local)
+    }
+
+    /// Resolves entries into jobs built in `mode`. Inputs are relative to
+    /// the manifest's directory; outputs are relative to `output_dir` when
+    /// given, otherwise to the manifest's directory as well.
+    pub fn into_jobs(
+        self,
+        manifest_path: &Path,
+        output_dir: Option<&Path>,
+        mode: Option<BuildMode>,
+    ) -> Result<Vec<Job>> {
+        let base = manifest_path.parent().unwrap_or(Path::new(""));
+        let output_base = output_dir.unwrap_or(base);
+        let hooks = self.hooks.clone().in_dir(base);
+
+        let mut jobs = Vec::with_capacity(self.jobs.len());
+        for entry in &self.jobs {
+            let input = base.join(&entry.input);
+            let mut job = Job::new(&input, output_base)?;
+            job.name = entry
+                .name
+                .clone()
+                .unwrap_or_else(|| entry.input.display().to_string());
+            if let Some(output) = &entry.output {
+                job.output = output_base.join(output);
+            }
+            job.options = CompileOptions {
+                engine: entry
+                    .engine
+                    .clone()
+                    .or_else(|| self.defaults.engine.clone()),
+                profile: entry
+                    .profile
+                    .clone()
+                    .or_else(|| self.defaults.profile.clone()),
+                mode,
+                ..CompileOptions::default()
+            };
+            job.local = mode == Some(BuildMode::Draft)
+                && self.drafts_locally(job.options.profile.as_deref());
+            job.limits = entry.limits.clone().or(&self.defaults.limits);
+            job.depends_on = entry.depends_on.clone();
+            if !hooks.is_empty() {
+                job.hooks = hooks.clone();
+            }
+            jobs.push(job);
+        }
+        check_dependencies(&jobs)
+            .with_context(|| format!("Invalid manifest: {}", manifest_path.display()))?;
+        Ok(jobs)
+    }
+}
+
+/// Every dependency names exactly one job, and none depends on itself,
+/// directly or not.
+fn check_dependencies(jobs: &[Job]) -> Result<()> {
+    let index: BTreeMap<&str, usize> = jobs
+        .iter()
+        .enumerate()
+        .map(|(i, job)| (job.name.as_str(), i))
+        .collect();
+    for job in jobs {
+        for dependency in &job.depends_on {
+            anyhow::ensure!(
+                index.contains_key(dependency.as_str()),
+                "{} depends on {}, which is not a job of the manifest",
+                job.name,
+                dependency
+            );
+            let named = jobs
+                .iter()
+                .filter(|other| other.name == *dependency)
+                .count();
+            anyhow::ensure!(
+                named == 1,
+                "{} jobs are named {}; give them distinct names to depend on one",
+                named,
+                dependency
+            );
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Visit {
+        New,
+        InProgress,
+        Done,
+    }
+    fn visit(
+        i: usize,
+        jobs: &[Job],
+        index: &BTreeMap<&str, usize>,
+        visits: &mut [Visit],
+        path: &mut Vec<usize>,
+    ) -> Result<()> {
+        match visits[i] {
+            Visit::Done => return Ok(()),
+            Visit::InProgress => {
+                let start = path.iter().position(|&j| j == i).unwrap_or(0);
+                let cycle: Vec<&str> = path[start..]
+                    .iter()
+                    .chain([&i])
+                    .map(|&j| jobs[j].name.as_str())
+                    .collect();
+                anyhow::bail!("Dependency cycle: {}", cycle.join(" -> "));
+            }
+            Visit::New => {}
+        }
+        visits[i] = Visit::InProgress;
+        path.push(i);
+        for dependency in &jobs[i].depends_on {
+            visit(index[dependency.as_str()], jobs, index, visits, path)?;
+        }
+        path.pop();
+        visits[i] = Visit::Done;
+        Ok(())
+    }
+    let mut visits = vec![Visit::New; jobs.len()];
+    for i in 0..jobs.len() {
+        visit(i, jobs, &index, &mut visits, &mut Vec::new())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drafts_of_local_profiles_compile_locally() {
+        let jobs = |mode| {
+            let manifest: Manifest = serde_yaml::from_str(
+                "defaults:\n  profile: print\nprofiles:\n  print:\n    draft: local\n\
+                 jobs:\n  - input: a.tex\n  - input: b.tex\n    profile: web\n",
+            )
+            .unwrap();
+            manifest
+                .into_jobs(Path::new("jobs.yaml"), None, mode)
+                .unwrap()
+                .iter()
+                .map(|job| (job.local, job.options.mode))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            jobs(Some(BuildMode::Draft)),
+            [
+                (true, Some(BuildMode::Draft)),
+                (false, Some(BuildMode::Draft))
+            ]
+        );
+        assert_eq!(
+            jobs(Some(BuildMode::Final)),
+            [
+                (false, Some(BuildMode::Final)),
+                (false, Some(BuildMode::Final))
+            ]
+        );
+        assert_eq!(jobs(None), [(false, None), (false, None)]);
+    }
+
+    #[test]
+    fn dependencies_must_name_one_job_and_not_loop() {
+        let check = |yaml: &str| {
+            let manifest: Manifest = serde_yaml::from_str(yaml).unwrap();
+            manifest
+                .into_jobs(Path::new("jobs.yaml"), None, None)
+                .map(|jobs| jobs.len())
+                .map_err(|e| format!("{:#}", e))
+        };
+        assert_eq!(
+            check("jobs:\n  - input: a.tex\n    depends_on: [b.tex]\n  - input: b.tex\n"),
+            Ok(2)
+        );
+        assert!(check("jobs:\n  - input: a.tex\n    depends_on: [c.tex]\n")
+            .unwrap_err()
+            .ends_with("a.tex depends on c.tex, which is not a job of the manifest"));
+        assert!(check(
+            "jobs:\n  - input: a.tex\n    depends_on: [b.tex]\n\
+             \x20 - input: b.tex\n    depends_on: [a.tex]\n"
+        )
+        .unwrap_err()
+        .ends_with("Dependency cycle: a.tex -> b.tex -> a.tex"));
+    }
+}
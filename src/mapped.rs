@@ -0,0 +1,127 @@
+//! Reading inputs to upload. Prebuilt zips of `CHEMTEX_MMAP_MIN_BYTES`
+//! (default 8M) or more are memory-mapped on Unix instead of copied onto the
+//! heap: hashing, validating and uploading them then reads the page cache
+//! directly, and the pages can be dropped again under memory pressure.
+//! Anything that cannot be mapped (an empty file, a pipe, another platform)
+//! is read as usual.
+//!
+//! The map is read-only and private, but a file truncated while it is being
+//! uploaded can still fault; inputs are expected to stay put during a run,
+//! as they already must for the upload to mean anything.
+
+use crate::api;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::fs::{self, File};
+use std::path::Path;
+
+const DEFAULT_MIN_BYTES: u64 = 8 << 20;
+
+/// The contents of `path`, mapped when it is a large zip.
+pub fn read(path: &Path) -> Result<Bytes> {
+    let zip = path.extension().and_then(|e| e.to_str()) == Some("zip");
+    if zip {
+        let file =
+            File::open(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+        let len = file.metadata().map(|m| m.len()).unwrap_or_default();
+        if len >= min_bytes()? {
+            if let Some(map) = map(&file, len) {
+                return Ok(map);
+            }
+        }
+    }
+    let contents =
+        fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+    Ok(Bytes::from(contents))
+}
+
+fn min_bytes() -> Result<u64> {
+    match std::env::var("CHEMTEX_MMAP_MIN_BYTES") {
+        Ok(value) => api::parse_size(&value)
+            .with_context(|| format!("Invalid CHEMTEX_MMAP_MIN_BYTES: {}", value)),
+        Err(_) => Ok(DEFAULT_MIN_BYTES),
+    }
+}
+
+#[cfg(unix)]
+fn map(file: &File, len: u64) -> Option<Bytes> {
+    unix::Map::new(file, len).map(Bytes::from_owner)
+}
+
+#[cfg(not(unix))]
+fn map(_: &File, _: u64) -> Option<Bytes> {
+    None
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::fs::File;
+    use std::os::fd::AsRawFd;
+
+    /// A read-only private mapping of a whole file, unmapped on drop.
+    pub struct Map {
+        ptr: *mut libc::c_void,
+        len: usize,
+    }
+
+    // SAFETY: the mapping is never written through and lives until drop.
+    unsafe impl Send for Map {}
+    unsafe impl Sync for Map {}
+
+    impl Map {
+        pub fn new(file: &File, len: u64) -> Option<Self> {
+            let len = usize::try_from(len).ok().filter(|&len| len > 0)?;
+            // SAFETY: a fresh mapping of `len` bytes of an open file; the
+            // result is checked before use and the fd may close afterwards.
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    file.as_raw_fd(),
+                    0,
+                )
+            };
+            (ptr != libc::MAP_FAILED).then_some(Self { ptr, len })
+        }
+    }
+
+    impl AsRef<[u8]> for Map {
+        fn as_ref(&self) -> &[u8] {
+            // SAFETY: `ptr` points at `len` readable bytes until drop.
+            unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+
+    impl Drop for Map {
+        fn drop(&mut self) {
+            // SAFETY: unmaps exactly the mapping made in `new`.
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::unix::Map;
+    use std::fs::{self, File};
+
+    #[test]
+    fn maps_whole_files_and_declines_empty_ones() {
+        let dir = std::env::temp_dir().join(format!("chemtex-mapped-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("project.zip");
+        let contents: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &contents).unwrap();
+        let map = Map::new(&File::open(&path).unwrap(), contents.len() as u64).unwrap();
+        assert_eq!(map.as_ref(), &contents[..]);
+
+        fs::write(&path, b"").unwrap();
+        assert!(Map::new(&File::open(&path).unwrap(), 0).is_none());
+        drop(map);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,838 @@
+use anyhow::{bail, Context, Result};
+
+const NAMESPACE: &str = "http://www.w3.org/1998/Math/MathML";
+
+/// Greek letters; capitals are upright.
+const GREEK: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ϵ"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "ϑ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "ϕ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+];
+
+/// Symbols that are identifiers rather than operators.
+const IDENTIFIERS: &[(&str, &str)] = &[
+    ("infty", "∞"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("emptyset", "∅"),
+    ("circ", "∘"),
+    ("degree", "°"),
+    ("prime", "′"),
+];
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("times", "×"),
+    ("cdot", "⋅"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("div", "÷"),
+    ("ast", "∗"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("longrightarrow", "⟶"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"),
+    ("leftrightarrow", "↔"),
+    ("rightleftharpoons", "⇌"),
+    ("uparrow", "↑"),
+    ("downarrow", "↓"),
+    ("approx", "≈"),
+    ("sim", "∼"),
+    ("simeq", "≃"),
+    ("cong", "≅"),
+    ("equiv", "≡"),
+    ("propto", "∝"),
+    ("le", "≤"),
+    ("leq", "≤"),
+    ("ge", "≥"),
+    ("geq", "≥"),
+    ("ne", "≠"),
+    ("neq", "≠"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("subset", "⊂"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("perp", "⊥"),
+    ("parallel", "∥"),
+    ("mid", "∣"),
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("int", "∫"),
+    ("iint", "∬"),
+    ("oint", "∮"),
+    ("cdots", "⋯"),
+    ("ldots", "…"),
+    ("dots", "…"),
+    ("vdots", "⋮"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lvert", "|"),
+    ("rvert", "|"),
+    ("vert", "|"),
+    ("lbrace", "{"),
+    ("rbrace", "}"),
+];
+
+/// Function names set upright.
+const FUNCTIONS: &[&str] = &[
+    "ln", "log", "lg", "exp", "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos",
+    "arctan", "sinh", "cosh", "tanh", "lim", "max", "min", "sup", "inf", "det", "gcd", "deg",
+];
+
+/// Accents over their argument.
+const ACCENTS: &[(&str, &str)] = &[
+    ("bar", "¯"),
+    ("overline", "¯"),
+    ("hat", "^"),
+    ("widehat", "^"),
+    ("tilde", "~"),
+    ("widetilde", "~"),
+    ("vec", "→"),
+    ("overrightarrow", "→"),
+    ("dot", "˙"),
+    ("ddot", "¨"),
+];
+
+/// Commands that change nothing MathML shows.
+const IGNORED: &[&str] = &[
+    "displaystyle",
+    "textstyle",
+    "scriptstyle",
+    "limits",
+    "nolimits",
+    "nonumber",
+    "notag",
+    "right",
+];
+
+/// Converts TeX math, mhchem's `\ce` included, to a `<math>` element with
+/// the source kept as an annotation. Constructs outside the supported
+/// subset are an error, so the caller can fall back to an image.
+pub fn convert(tex: &str, display: bool) -> Result<String> {
+    let row = expression(tex)?;
+    Ok(wrap(&row, tex, display))
+}
+
+/// The MathML for `tex`, without the `<math>` element.
+pub fn expression(tex: &str) -> Result<String> {
+    let mut parser = Parser::new(tex);
+    let row = parser.row(Stop::End)?;
+    Ok(format!("<mrow>{}</mrow>", row))
+}
+
+/// Wraps converted MathML in a `<math>` element annotated with `tex`.
+pub fn wrap(mathml: &str, tex: &str, display: bool) -> String {
+    format!(
+        "<math xmlns=\"{}\" display=\"{}\"><semantics>{}<annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+        NAMESPACE,
+        if display { "block" } else { "inline" },
+        mathml,
+        escape(tex.trim())
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    End,
+    Brace,
+    Bracket,
+    Right,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(tex: &str) -> Self {
+        Self {
+            chars: tex.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        let mut index = self.position;
+        for c in text.chars() {
+            if self.chars.get(index) != Some(&c) {
+                return false;
+            }
+            index += 1;
+        }
+        // `\right` but not `\rightarrow`.
+        !self.chars.get(index).is_some_and(|c| {
+            c.is_ascii_alphabetic() && text.ends_with(|c: char| c.is_ascii_alphabetic())
+        })
+    }
+
+    /// Atoms with their scripts up to `stop`.
+    fn row(&mut self, stop: Stop) -> Result<String> {
+        let mut out = String::new();
+        loop {
+            self.skip_spaces();
+            let Some(c) = self.peek() else {
+                match stop {
+                    Stop::End => return Ok(out),
+                    _ => bail!("Unbalanced group"),
+                }
+            };
+            match (c, stop) {
+                ('}', Stop::Brace) | (']', Stop::Bracket) => {
+                    self.position += 1;
+                    return Ok(out);
+                }
+                ('}', _) => bail!("Unbalanced }}"),
+                _ => {}
+            }
+            if stop == Stop::Right && self.starts_with("\\right") {
+                return Ok(out);
+            }
+            let base = match c {
+                '^' | '_' | '\'' => "<mrow></mrow>".to_string(),
+                _ => self.atom()?,
+            };
+            out.push_str(&self.scripts(base)?);
+        }
+    }
+
+    /// Subscripts, superscripts and primes after `base`.
+    fn scripts(&mut self, base: String) -> Result<String> {
+        let mut sub = None;
+        let mut sup: Option<String> = None;
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.position += 1;
+                    sub = Some(self.argument()?);
+                }
+                Some('^') if sup.is_none() => {
+                    self.position += 1;
+                    sup = Some(self.argument()?);
+                }
+                Some('\'') => {
+                    self.position += 1;
+                    let prime = "<mo>′</mo>".to_string();
+                    sup = Some(match sup {
+                        Some(sup) => format!("<mrow>{}{}</mrow>", sup, prime),
+                        None => prime,
+                    });
+                }
+                _ => break,
+            }
+        }
+        Ok(match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) => format!("<msub>{}{}</msub>", base, sub),
+            (None, Some(sup)) => format!("<msup>{}{}</msup>", base, sup),
+            (Some(sub), Some(sup)) => format!("<msubsup>{}{}{}</msubsup>", base, sub, sup),
+        })
+    }
+
+    /// A command argument or script: one atom, or a braced group.
+    fn argument(&mut self) -> Result<String> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('{') => {
+                self.position += 1;
+                Ok(format!("<mrow>{}</mrow>", self.row(Stop::Brace)?))
+            }
+            Some(_) => self.atom(),
+            None => bail!("Missing argument"),
+        }
+    }
+
+    /// A braced argument as raw text.
+    fn raw_argument(&mut self) -> Result<String> {
+        self.skip_spaces();
+        if self.peek() != Some('{') {
+            return self
+                .peek()
+                .map(|c| {
+                    self.position += 1;
+                    c.to_string()
+                })
+                .context("Missing argument");
+        }
+        let start = self.position + 1;
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => self.position += 1,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        let text: String = self.chars[start..self.position].iter().collect();
+                        self.position += 1;
+                        return Ok(text);
+                    }
+                }
+                _ => {}
+            }
+            self.position += 1;
+        }
+        bail!("Unbalanced group")
+    }
+
+    fn atom(&mut self) -> Result<String> {
+        let c = self.peek().context("Missing argument")?;
+        self.position += 1;
+        Ok(match c {
+            '{' => format!("<mrow>{}</mrow>", self.row(Stop::Brace)?),
+            '\\' => return self.command(),
+            c if c.is_ascii_digit()
+                || (c == '.' && self.peek().is_some_and(|c| c.is_ascii_digit())) =>
+            {
+                let mut number = c.to_string();
+                while let Some(c) = self.peek() {
+                    let decimal = matches!(c, '.' | ',')
+                        && self
+                            .chars
+                            .get(self.position + 1)
+                            .is_some_and(|c| c.is_ascii_digit());
+                    if !c.is_ascii_digit() && !decimal {
+                        break;
+                    }
+                    number.push(c);
+                    self.position += 1;
+                }
+                format!("<mn>{}</mn>", number)
+            }
+            c if c.is_alphabetic() => format!("<mi>{}</mi>", c),
+            '-' => "<mo>−</mo>".to_string(),
+            '~' => "<mspace width=\"0.33em\"/>".to_string(),
+            '&' => bail!("Alignment outside a table"),
+            '#' | '$' => bail!("Unexpected {}", c),
+            c => format!("<mo>{}</mo>", escape(&c.to_string())),
+        })
+    }
+
+    fn command(&mut self) -> Result<String> {
+        let Some(first) = self.peek() else {
+            bail!("Lone backslash");
+        };
+        self.position += 1;
+        if !first.is_ascii_alphabetic() {
+            return Ok(match first {
+                ',' => "<mspace width=\"0.17em\"/>".to_string(),
+                ':' | '>' => "<mspace width=\"0.22em\"/>".to_string(),
+                ';' => "<mspace width=\"0.28em\"/>".to_string(),
+                ' ' => "<mspace width=\"0.33em\"/>".to_string(),
+                '!' => String::new(),
+                '{' | '}' | '%' | '&' | '#' | '_' | '$' | '|' => {
+                    let symbol = if first == '|' { '‖' } else { first };
+                    format!("<mo>{}</mo>", escape(&symbol.to_string()))
+                }
+                '\\' => bail!("Line break outside a table"),
+                c => bail!("Unsupported command \\{}", c),
+            });
+        }
+        let mut name = first.to_string();
+        while let Some(c) = self.peek().filter(char::is_ascii_alphabetic) {
+            name.push(c);
+            self.position += 1;
+        }
+        if self.peek() == Some('*') {
+            self.position += 1;
+        }
+        let name = name.as_str();
+        if let Some((_, letter)) = GREEK.iter().find(|(n, _)| *n == name) {
+            return Ok(match name.starts_with(|c: char| c.is_uppercase()) {
+                true => format!("<mi mathvariant=\"normal\">{}</mi>", letter),
+                false => format!("<mi>{}</mi>", letter),
+            });
+        }
+        if let Some((_, symbol)) = IDENTIFIERS.iter().find(|(n, _)| *n == name) {
+            return Ok(format!("<mi>{}</mi>", symbol));
+        }
+        if let Some((_, symbol)) = OPERATORS.iter().find(|(n, _)| *n == name) {
+            return Ok(format!("<mo>{}</mo>", escape(symbol)));
+        }
+        if FUNCTIONS.contains(&name) {
+            return Ok(format!("<mi>{}</mi>", name));
+        }
+        if let Some((_, accent)) = ACCENTS.iter().find(|(n, _)| *n == name) {
+            let base = self.argument()?;
+            return Ok(format!(
+                "<mover accent=\"true\">{}<mo>{}</mo></mover>",
+                base, accent
+            ));
+        }
+        Ok(match name {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.argument()?;
+                let denominator = self.argument()?;
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "sqrt" => {
+                self.skip_spaces();
+                match self.peek() {
+                    Some('[') => {
+                        self.position += 1;
+                        let index = self.row(Stop::Bracket)?;
+                        let base = self.argument()?;
+                        format!("<mroot>{}<mrow>{}</mrow></mroot>", base, index)
+                    }
+                    _ => format!("<msqrt>{}</msqrt>", self.argument()?),
+                }
+            }
+            "text" | "textrm" | "textnormal" | "mbox" | "textup" => {
+                let text = self.raw_argument()?;
+                format!("<mtext>{}</mtext>", escape(&plain_text(&text)?))
+            }
+            "textit" => {
+                let text = self.raw_argument()?;
+                format!(
+                    "<mtext mathvariant=\"italic\">{}</mtext>",
+                    escape(&plain_text(&text)?)
+                )
+            }
+            "textbf" => {
+                let text = self.raw_argument()?;
+                format!(
+                    "<mtext mathvariant=\"bold\">{}</mtext>",
+                    escape(&plain_text(&text)?)
+                )
+            }
+            "mathrm" | "operatorname" | "mathbf" | "boldsymbol" | "mathit" | "mathcal"
+            | "mathbb" | "mathsf" | "mathtt" => {
+                let variant = match name {
+                    "mathrm" | "operatorname" => "normal",
+                    "mathbf" => "bold",
+                    "boldsymbol" => "bold-italic",
+                    "mathit" => "italic",
+                    "mathcal" => "script",
+                    "mathbb" => "double-struck",
+                    "mathsf" => "sans-serif",
+                    _ => "monospace",
+                };
+                let text = self.raw_argument()?;
+                match text.trim().chars().all(char::is_alphanumeric) && !text.trim().is_empty() {
+                    true => format!("<mi mathvariant=\"{}\">{}</mi>", variant, text.trim()),
+                    false => format!(
+                        "<mstyle mathvariant=\"{}\">{}</mstyle>",
+                        variant,
+                        expression(&text)?
+                    ),
+                }
+            }
+            "left" => {
+                let open = self.delimiter()?;
+                let inner = self.row(Stop::Right)?;
+                self.position += "\\right".chars().count();
+                let close = self.delimiter()?;
+                format!("<mrow>{}{}{}</mrow>", fence(&open), inner, fence(&close))
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl"
+            | "biggr" | "Biggl" | "Biggr" => {
+                let delimiter = self.delimiter()?;
+                format!("<mo stretchy=\"false\">{}</mo>", escape(&delimiter))
+            }
+            "underline" => format!(
+                "<munder accentunder=\"true\">{}<mo>_</mo></munder>",
+                self.argument()?
+            ),
+            "overset" | "stackrel" => {
+                let over = self.argument()?;
+                let base = self.argument()?;
+                format!("<mover>{}{}</mover>", base, over)
+            }
+            "underset" => {
+                let under = self.argument()?;
+                let base = self.argument()?;
+                format!("<munder>{}{}</munder>", base, under)
+            }
+            "quad" => "<mspace width=\"1em\"/>".to_string(),
+            "qquad" => "<mspace width=\"2em\"/>".to_string(),
+            "ce" => {
+                let formula = self.raw_argument()?;
+                chemistry(&formula)?
+            }
+            "label" | "tag" => {
+                self.raw_argument()?;
+                String::new()
+            }
+            "mathchoice" => bail!("Unsupported command \\mathchoice"),
+            _ if IGNORED.contains(&name) => String::new(),
+            _ => bail!("Unsupported command \\{}", name),
+        })
+    }
+
+    /// The delimiter after `\left`, `\right` or `\big`; `.` is none.
+    fn delimiter(&mut self) -> Result<String> {
+        self.skip_spaces();
+        let c = self.peek().context("Missing delimiter")?;
+        self.position += 1;
+        if c != '\\' {
+            return Ok(match c {
+                '.' => String::new(),
+                c => c.to_string(),
+            });
+        }
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_alphabetic() && !name.is_empty() {
+                break;
+            }
+            name.push(c);
+            self.position += 1;
+            if !c.is_ascii_alphabetic() {
+                break;
+            }
+        }
+        Ok(match name.as_str() {
+            "{" | "lbrace" => "{".to_string(),
+            "}" | "rbrace" => "}".to_string(),
+            "|" | "Vert" => "‖".to_string(),
+            "langle" => "⟨".to_string(),
+            "rangle" => "⟩".to_string(),
+            "lvert" | "rvert" | "vert" => "|".to_string(),
+            other => bail!("Unsupported delimiter \\{}", other),
+        })
+    }
+}
+
+fn fence(delimiter: &str) -> String {
+    match delimiter.is_empty() {
+        true => String::new(),
+        false => format!(
+            "<mo fence=\"true\" stretchy=\"true\">{}</mo>",
+            escape(delimiter)
+        ),
+    }
+}
+
+/// Text inside `\text{...}`: spaces and a few symbols; anything more is
+/// left to the fallback.
+fn plain_text(text: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => out.push('\u{a0}'),
+            '{' | '}' => {}
+            '\\' => match chars.next() {
+                Some(c @ ('%' | '&' | '#' | '_' | '$' | ' ')) => out.push(c),
+                Some(',') => out.push('\u{2009}'),
+                _ => bail!("Commands inside \\text"),
+            },
+            '$' => bail!("Math inside \\text"),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+/// mhchem's `\ce{...}`: species, coefficients, arrows, states of matter
+/// and precipitate and gas marks.
+pub fn chemistry(formula: &str) -> Result<String> {
+    let mut out = String::from("<mrow>");
+    for token in formula.split_whitespace() {
+        let arrow = token.find('[').map_or(token, |i| &token[..i]);
+        let symbol = match arrow {
+            "->" => Some("→"),
+            "<-" => Some("←"),
+            "<=>" | "<=>>" | "<<=>" => Some("⇌"),
+            "<->" => Some("↔"),
+            "=" => Some("="),
+            "+" => Some("+"),
+            "v" => Some("↓"),
+            "^" => Some("↑"),
+            "." | "*" => Some("⋅"),
+            _ => None,
+        };
+        match symbol {
+            Some(symbol) if arrow.len() < token.len() => {
+                // `->[heat]`: the condition goes over the arrow.
+                let condition = token[arrow.len()..]
+                    .trim_start_matches('[')
+                    .split(']')
+                    .next()
+                    .unwrap_or_default();
+                out.push_str(&format!(
+                    "<mover><mo stretchy=\"true\">{}</mo><mtext>{}</mtext></mover>",
+                    symbol,
+                    escape(&plain_text(condition.trim_matches('$'))?)
+                ));
+            }
+            Some(symbol) => out.push_str(&format!("<mo>{}</mo>", symbol)),
+            None => out.push_str(&species(token)?),
+        }
+    }
+    out.push_str("</mrow>");
+    Ok(out)
+}
+
+/// One species: `2H2O`, `SO4^2-`, `Fe^{3+}`, `NaCl(aq)`, `CuSO4*5H2O`.
+fn species(token: &str) -> Result<String> {
+    struct Piece {
+        base: String,
+        sub: Option<String>,
+        sup: Option<String>,
+    }
+    let mut pieces: Vec<Piece> = Vec::new();
+    let push = |pieces: &mut Vec<Piece>, base: String| {
+        pieces.push(Piece {
+            base,
+            sub: None,
+            sup: None,
+        })
+    };
+    let chars: Vec<char> = token.chars().collect();
+    let mut i = 0;
+    // A leading coefficient.
+    let coefficient: String = chars
+        .iter()
+        .take_while(|c| c.is_ascii_digit() || **c == '/')
+        .collect();
+    if !coefficient.is_empty() && coefficient.len() < chars.len() {
+        push(&mut pieces, format!("<mn>{}</mn>", coefficient));
+        i = coefficient.len();
+    }
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_ascii_uppercase() => {
+                let mut symbol = c.to_string();
+                i += 1;
+                while i < chars.len() && chars[i].is_ascii_lowercase() {
+                    symbol.push(chars[i]);
+                    i += 1;
+                }
+                push(
+                    &mut pieces,
+                    format!("<mi mathvariant=\"normal\">{}</mi>", symbol),
+                );
+            }
+            c if c.is_ascii_digit() => {
+                let digits: String = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect();
+                i += digits.len();
+                let charge = chars.get(i).filter(|c| matches!(c, '+' | '-'));
+                // `Fe3+` at the end of a species is a charge, as in mhchem.
+                let piece = pieces.last_mut().context("Subscript without an atom")?;
+                match charge {
+                    Some(sign) if i + 1 == chars.len() => {
+                        piece.sup = Some(format!(
+                            "<mrow><mn>{}</mn><mo>{}</mo></mrow>",
+                            digits,
+                            sign_of(*sign)
+                        ));
+                        i += 1;
+                    }
+                    _ => piece.sub = Some(format!("<mn>{}</mn>", digits)),
+                }
+            }
+            '^' => {
+                i += 1;
+                let charge: String = match chars.get(i) {
+                    Some('{') => {
+                        let end = chars[i..]
+                            .iter()
+                            .position(|c| *c == '}')
+                            .context("Unclosed charge")?;
+                        let charge = chars[i + 1..i + end].iter().collect();
+                        i += end + 1;
+                        charge
+                    }
+                    _ => {
+                        let charge: String = chars[i..]
+                            .iter()
+                            .take_while(|c| c.is_ascii_digit() || matches!(c, '+' | '-'))
+                            .collect();
+                        i += charge.len();
+                        charge
+                    }
+                };
+                let piece = pieces.last_mut().context("Charge without an atom")?;
+                piece.sup = Some(charge_markup(&charge));
+            }
+            '+' | '-'
+                if pieces.last().is_some() && chars[i..].iter().all(|c| matches!(c, '+' | '-')) =>
+            {
+                let charge: String = chars[i..].iter().collect();
+                i = chars.len();
+                if let Some(piece) = pieces.last_mut() {
+                    piece.sup = Some(charge_markup(&charge));
+                }
+            }
+            '(' => {
+                let rest: String = chars[i..].iter().collect();
+                match ["(aq)", "(s)", "(l)", "(g)", "(sol)"]
+                    .iter()
+                    .find(|state| rest.starts_with(**state))
+                {
+                    Some(state) => {
+                        push(&mut pieces, format!("<mtext>{}</mtext>", state));
+                        i += state.len();
+                    }
+                    None => {
+                        push(&mut pieces, "<mo>(</mo>".to_string());
+                        i += 1;
+                    }
+                }
+            }
+            ')' | '[' | ']' => {
+                push(&mut pieces, format!("<mo>{}</mo>", c));
+                i += 1;
+            }
+            '*' | '.' => {
+                push(&mut pieces, "<mo>⋅</mo>".to_string());
+                i += 1;
+                let digits: String = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect();
+                if !digits.is_empty() {
+                    push(&mut pieces, format!("<mn>{}</mn>", digits));
+                    i += digits.len();
+                }
+            }
+            c if c.is_ascii_lowercase() => {
+                push(&mut pieces, format!("<mi>{}</mi>", c));
+                i += 1;
+            }
+            c => bail!("Unsupported {:?} in \\ce{{{}}}", c, token),
+        }
+    }
+    Ok(pieces
+        .into_iter()
+        .map(|piece| match (piece.sub, piece.sup) {
+            (None, None) => piece.base,
+            (Some(sub), None) => format!("<msub>{}{}</msub>", piece.base, sub),
+            (None, Some(sup)) => format!("<msup>{}{}</msup>", piece.base, sup),
+            (Some(sub), Some(sup)) => {
+                format!("<msubsup>{}{}{}</msubsup>", piece.base, sub, sup)
+            }
+        })
+        .collect())
+}
+
+fn sign_of(sign: char) -> char {
+    match sign {
+        '-' => '−',
+        other => other,
+    }
+}
+
+/// `2-` → `<mrow><mn>2</mn><mo>−</mo></mrow>`.
+fn charge_markup(charge: &str) -> String {
+    let digits: String = charge.chars().filter(char::is_ascii_digit).collect();
+    let signs: String = charge
+        .chars()
+        .filter(|c| matches!(c, '+' | '-'))
+        .map(sign_of)
+        .collect();
+    match digits.is_empty() {
+        true => format!("<mo>{}</mo>", signs),
+        false => format!("<mrow><mn>{}</mn><mo>{}</mo></mrow>", digits, signs),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn math_and_chemistry_become_mathml() {
+        let cases = [
+            (
+                "\\frac{a}{b}",
+                "<mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac>",
+            ),
+            ("x^2_i", "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>"),
+            (
+                "\\sqrt{x} < \\alpha",
+                "<msqrt><mrow><mi>x</mi></mrow></msqrt><mo>&lt;</mo><mi>α</mi>",
+            ),
+            (
+                "\\ce{Fe^{3+}}",
+                "<mrow><msup><mi mathvariant=\"normal\">Fe</mi><mrow><mn>3</mn><mo>+</mo></mrow></msup></mrow>",
+            ),
+        ];
+        for (tex, mathml) in cases {
+            assert_eq!(
+                expression(tex).unwrap(),
+                format!("<mrow>{}</mrow>", mathml),
+                "{}",
+                tex
+            );
+        }
+        assert!(expression("\\ce{2H2 + O2 -> 2H2O}").unwrap().contains(
+            "<mo>+</mo><msub><mi mathvariant=\"normal\">O</mi><mn>2</mn></msub><mo>→</mo>"
+        ));
+        assert_eq!(
+            convert("a<b", true).unwrap(),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\"><semantics>\
+             <mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow>\
+             <annotation encoding=\"application/x-tex\">a&lt;b</annotation></semantics></math>"
+        );
+    }
+
+    #[test]
+    fn unsupported_commands_are_errors() {
+        assert!(convert("\\unknowncommand", false).is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use crate::api;
+use crate::cli::Args;
+use crate::job::{Job, Runner};
+use crate::pack::{self, TempDir};
+use crate::scaffold::PREAMBLE;
+use anyhow::{Context, Result};
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: chemtex md2tex <notes.md> [options]
+
+Options:
+  --out FILE   Where to write the .tex (default: next to the input)
+  --compile    Compile the result right away
+  --no-cache   Always submit when compiling, ignoring the build cache
+
+Markdown extensions: `ce:H2SO4` becomes \\ce{H2SO4}, ```chemfig blocks are
+drawn with chemfig and ```latex blocks are copied verbatim.";
+
+const SECTIONS: &[&str] = &[
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+];
+
+/// Converts Markdown notes into a standalone LaTeX document using the
+/// built-in chemistry preamble, optionally compiling it in the same run.
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["compile", "no-cache"], &["out"])?;
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+    let output = args
+        .value("out")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| input.with_extension("tex"));
+
+    let markdown = fs::read_to_string(&input)
+        .with_context(|| format!("Failed to read file: {}", input.display()))?;
+    fs::write(&output, convert(&markdown))
+        .with_context(|| format!("Failed to write file: {}", output.display()))?;
+    println!("LaTeX written to {}", output.display());
+
+    if !args.flag("compile") {
+        return Ok(());
+    }
+    // Packed so that images referenced from the notes are uploaded too.
+    let scratch = TempDir::new("md2tex")?;
+    let archive = pack::pack_project(&output, scratch.path())?;
+    let runner = Runner::new(api::build_client()?, !args.flag("no-cache"))?;
+    let mut job = Job::new(&archive, Path::new(""))?;
+    job.output = output.with_extension("pdf");
+    runner.run(&job, "").await.result?;
+    Ok(())
+}
+
+/// Renders a complete document. A leading `#` heading that is the only one
+/// of its level becomes the title, and the remaining headings move up a
+/// level.
+pub fn convert(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_MATH | Options::ENABLE_TASKLISTS;
+    let events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+    let top_level = events
+        .iter()
+        .filter(|e| {
+            matches!(
+                e,
+                Event::Start(Tag::Heading {
+                    level: HeadingLevel::H1,
+                    ..
+                })
+            )
+        })
+        .count();
+    let leading_title = top_level == 1
+        && matches!(
+            events.first(),
+            Some(Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                ..
+            }))
+        );
+
+    let mut writer = Writer {
+        buffers: vec![String::new()],
+        title: None,
+        title_pending: leading_title,
+        level_shift: usize::from(leading_title),
+        code: None,
+        image: None,
+        cell: 0,
+    };
+    for event in events {
+        writer.event(event);
+    }
+
+    let body = writer.buffers.concat();
+    let mut document = String::from("\\documentclass[11pt,a4paper]{article}\n");
+    document.push_str(PREAMBLE);
+    match &writer.title {
+        Some(title) => {
+            document.push_str(&format!("\n\\title{{{}}}\n\\date{{\\today}}\n", title));
+            document.push_str("\n\\begin{document}\n\\maketitle\n\n");
+        }
+        None => document.push_str("\n\\begin{document}\n\n"),
+    }
+    document.push_str(body.trim());
+    document.push_str("\n\n\\end{document}\n");
+    document
+}
+
+struct Writer {
+    /// Output stack: headings and image captions render into their own
+    /// buffer first.
+    buffers: Vec<String>,
+    title: Option<String>,
+    title_pending: bool,
+    level_shift: usize,
+    /// Language and contents of the fenced block being read.
+    code: Option<(String, String)>,
+    image: Option<String>,
+    cell: usize,
+}
+
+impl Writer {
+    fn write(&mut self, text: &str) {
+        if let Some(buffer) = self.buffers.last_mut() {
+            buffer.push_str(text);
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        if let Some((_, code)) = &mut self.code {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => self.end_code_block(),
+                _ => {}
+            }
+            return;
+        }
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.write(&escape(&text)),
+            Event::Code(code) => match code.strip_prefix("ce:") {
+                Some(formula) => self.write(&format!("\\ce{{{}}}", formula.trim())),
+                None => self.write(&format!("\\texttt{{{}}}", escape(&code))),
+            },
+            Event::InlineMath(math) => self.write(&format!("${}$", math)),
+            Event::DisplayMath(math) => self.write(&format!("\n\\[\n{}\n\\]\n", math.trim())),
+            Event::SoftBreak => self.write("\n"),
+            Event::HardBreak => self.write("\\\\\n"),
+            Event::Rule => self.write("\n\\noindent\\rule{\\linewidth}{0.4pt}\n\n"),
+            Event::TaskListMarker(done) => self.write(if done {
+                "\\texttt{[x]} "
+            } else {
+                "\\texttt{[ ]} "
+            }),
+            Event::Html(_) | Event::InlineHtml(_) | Event::FootnoteReference(_) => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading { .. } => self.buffers.push(String::new()),
+            Tag::BlockQuote(_) => self.write("\\begin{quote}\n"),
+            Tag::CodeBlock(kind) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or("").to_lowercase()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((language, String::new()));
+            }
+            Tag::List(None) => self.write("\\begin{itemize}\n"),
+            Tag::List(Some(start)) => {
+                self.write("\\begin{enumerate}\n");
+                if start != 1 {
+                    self.write(&format!("\\setcounter{{enumi}}{{{}}}\n", start - 1));
+                }
+            }
+            Tag::Item => self.write("\\item "),
+            Tag::Table(alignments) => {
+                let columns: String = alignments
+                    .iter()
+                    .map(|alignment| match alignment {
+                        Alignment::Center => 'c',
+                        Alignment::Right => 'r',
+                        Alignment::Left | Alignment::None => 'l',
+                    })
+                    .collect();
+                self.write(&format!(
+                    "\\begin{{table}}[h]\n\\centering\n\\begin{{tabular}}{{{}}}\n\\toprule\n",
+                    columns
+                ));
+            }
+            Tag::TableHead | Tag::TableRow => self.cell = 0,
+            Tag::TableCell => {
+                if self.cell > 0 {
+                    self.write(" & ");
+                }
+                self.cell += 1;
+            }
+            Tag::Emphasis => self.write("\\emph{"),
+            Tag::Strong => self.write("\\textbf{"),
+            Tag::Link { dest_url, .. } => {
+                self.write(&format!("\\href{{{}}}{{", escape_url(&dest_url)))
+            }
+            Tag::Image { dest_url, .. } => {
+                self.image = Some(dest_url.to_string());
+                self.buffers.push(String::new());
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph => self.write("\n\n"),
+            TagEnd::Heading(level) => {
+                let text = self.buffers.pop().unwrap_or_default();
+                if self.title_pending {
+                    self.title_pending = false;
+                    self.title = Some(text);
+                    return;
+                }
+                let index = (level as usize - 1).saturating_sub(self.level_shift);
+                let command = SECTIONS[index.min(SECTIONS.len() - 1)];
+                self.write(&format!("\\{}{{{}}}\n\n", command, text));
+            }
+            TagEnd::BlockQuote(_) => self.write("\\end{quote}\n\n"),
+            TagEnd::List(true) => self.write("\\end{enumerate}\n\n"),
+            TagEnd::List(false) => self.write("\\end{itemize}\n\n"),
+            TagEnd::Item => self.write("\n"),
+            TagEnd::Table => self.write("\\bottomrule\n\\end{tabular}\n\\end{table}\n\n"),
+            TagEnd::TableHead => self.write(" \\\\\n\\midrule\n"),
+            TagEnd::TableRow => self.write(" \\\\\n"),
+            TagEnd::Emphasis | TagEnd::Strong | TagEnd::Link => self.write("}"),
+            TagEnd::Image => {
+                let caption = self.buffers.pop().unwrap_or_default();
+                let path = self.image.take().unwrap_or_default();
+                let mut figure = format!(
+                    "\n\\begin{{figure}}[h]\n\\centering\n\\includegraphics[width=0.8\\linewidth]{{{}}}\n",
+                    path
+                );
+                if !caption.trim().is_empty() {
+                    figure.push_str(&format!("\\caption{{{}}}\n", caption.trim()));
+                }
+                figure.push_str("\\end{figure}\n");
+                self.write(&figure);
+            }
+            _ => {}
+        }
+    }
+
+    fn end_code_block(&mut self) {
+        let Some((language, code)) = self.code.take() else {
+            return;
+        };
+        let code = code.trim_end();
+        let block = match language.as_str() {
+            "chemfig" if code.contains("\\chemfig") => {
+                format!("\\begin{{center}}\n{}\n\\end{{center}}\n\n", code)
+            }
+            "chemfig" => format!(
+                "\\begin{{center}}\n\\chemfig{{{}}}\n\\end{{center}}\n\n",
+                code
+            ),
+            "latex" | "tex" => format!("{}\n\n", code),
+            _ => format!("\\begin{{verbatim}}\n{}\n\\end{{verbatim}}\n\n", code),
+        };
+        self.write(&block);
+    }
+}
+
+/// Escapes the characters LaTeX treats specially in text.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// `\href` takes the URL almost verbatim; only `%` and `#` need escaping.
+fn escape_url(url: &str) -> String {
+    url.replace('%', "\\%").replace('#', "\\#")
+}
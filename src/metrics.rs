@@ -0,0 +1,244 @@
+use crate::api::{CompilationFailed, PollTimeout, ServerError};
+use crate::job::{JobReport, Phase};
+use reqwest::StatusCode;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const DURATION_BUCKETS: &[f64] = &[1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+const SIZE_BUCKETS: &[f64] = &[
+    10_000.0,
+    100_000.0,
+    1_000_000.0,
+    5_000_000.0,
+    20_000_000.0,
+    100_000_000.0,
+];
+
+/// Counters and histograms exposed by the daemon on `/metrics` in the
+/// Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    submissions: u64,
+    results: BTreeMap<&'static str, u64>,
+    failures: BTreeMap<&'static str, u64>,
+    slot_wait: Histogram,
+    queue_wait: Histogram,
+    compile_duration: Histogram,
+    upload_bytes: Histogram,
+}
+
+impl Metrics {
+    pub fn record_submission(&self) {
+        self.lock().submissions += 1;
+    }
+
+    /// Time a job waited locally for a free compile slot.
+    pub fn record_slot_wait(&self, wait: Duration) {
+        self.lock()
+            .slot_wait
+            .observe(DURATION_BUCKETS, wait.as_secs_f64());
+    }
+
+    pub fn record_report(&self, report: &JobReport) {
+        let mut inner = self.lock();
+        let result = match &report.result {
+            Ok(_) if report.details.cached => "cached",
+            Ok(_) => "succeeded",
+            Err(_) if report.cancelled => "cancelled",
+            Err(_) => "failed",
+        };
+        *inner.results.entry(result).or_default() += 1;
+        if let Err(e) = &report.result {
+            if !report.cancelled {
+                *inner.failures.entry(failure_class(e)).or_default() += 1;
+            }
+        }
+
+        let details = &report.details;
+        if let Some(wait) = details.phase(Phase::Queue) {
+            inner
+                .queue_wait
+                .observe(DURATION_BUCKETS, wait.as_secs_f64());
+        }
+        if let Some(ms) = details.compile_ms {
+            inner
+                .compile_duration
+                .observe(DURATION_BUCKETS, ms as f64 / 1000.0);
+        }
+        if let Some(bytes) = details.upload_bytes {
+            inner.upload_bytes.observe(SIZE_BUCKETS, bytes as f64);
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let inner = self.lock();
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "chemtex_submissions_total",
+            "Jobs accepted by the daemon.",
+            &[(String::new(), inner.submissions)],
+        );
+        counter(
+            &mut out,
+            "chemtex_jobs_total",
+            "Finished jobs by result.",
+            &labelled("result", &inner.results),
+        );
+        counter(
+            &mut out,
+            "chemtex_failures_total",
+            "Failed jobs by failure class.",
+            &labelled("class", &inner.failures),
+        );
+        inner.slot_wait.render(
+            &mut out,
+            "chemtex_slot_wait_seconds",
+            "Time jobs waited for a local compile slot.",
+            DURATION_BUCKETS,
+        );
+        inner.queue_wait.render(
+            &mut out,
+            "chemtex_queue_wait_seconds",
+            "Time tasks waited in the remote queue before compiling.",
+            DURATION_BUCKETS,
+        );
+        inner.compile_duration.render(
+            &mut out,
+            "chemtex_compile_duration_seconds",
+            "Compile time reported by the server.",
+            DURATION_BUCKETS,
+        );
+        inner.upload_bytes.render(
+            &mut out,
+            "chemtex_upload_bytes",
+            "Size of uploaded documents.",
+            SIZE_BUCKETS,
+        );
+        out
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Coarse failure class for dashboards: is it the document, the network or
+/// the service? Read from the errors themselves, not their messages.
+pub fn failure_class(error: &anyhow::Error) -> &'static str {
+    for cause in error.chain() {
+        if cause.is::<CompilationFailed>() {
+            return "compilation";
+        }
+        if cause.is::<PollTimeout>() {
+            return "timeout";
+        }
+        if let Some(e) = cause.downcast_ref::<ServerError>() {
+            return match e.status {
+                Some(StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT) => "timeout",
+                _ => "server",
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return if e.is_timeout() { "timeout" } else { "network" };
+        }
+    }
+    "local"
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative counts per bucket, `+Inf` last.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[f64], value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; bounds.len() + 1];
+        }
+        for (bucket, bound) in self.buckets.iter_mut().zip(bounds) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        if let Some(infinity) = self.buckets.last_mut() {
+            *infinity += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str, bounds: &[f64]) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (i, bound) in bounds.iter().enumerate() {
+            let count = self.buckets.get(i).copied().unwrap_or(0);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum);
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+fn labelled(label: &str, values: &BTreeMap<&'static str, u64>) -> Vec<(String, u64)> {
+    values
+        .iter()
+        .map(|(value, count)| (format!("{{{}=\"{}\"}}", label, value), *count))
+        .collect()
+}
+
+fn counter(out: &mut String, name: &str, help: &str, samples: &[(String, u64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn failures_are_classified_by_their_type() {
+        let server = |status: Option<StatusCode>| -> anyhow::Error {
+            ServerError {
+                request: "Upload",
+                status,
+                message: "timeout while talking to the queue".to_string(),
+            }
+            .into()
+        };
+        assert_eq!(
+            failure_class(&server(Some(StatusCode::BAD_GATEWAY))),
+            "server"
+        );
+        assert_eq!(failure_class(&server(None)), "server");
+        assert_eq!(
+            failure_class(&server(Some(StatusCode::GATEWAY_TIMEOUT))),
+            "timeout"
+        );
+        let timeout = Err::<(), _>(PollTimeout {
+            after: Duration::from_secs(600),
+        })
+        .context("Job titration failed")
+        .unwrap_err();
+        assert_eq!(failure_class(&timeout), "timeout");
+        // Words in a message say nothing about where it came from.
+        let local = anyhow::anyhow!("Status check failed with status 500: timeout");
+        assert_eq!(failure_class(&local), "local");
+    }
+}
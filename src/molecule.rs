@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+/// A structure as an atom/bond graph with 2D coordinates, the common form
+/// of SMILES and MOL input on the way to chemfig.
+#[derive(Debug, Clone, Default)]
+pub struct Molecule {
+    pub atoms: Vec<Atom>,
+    pub bonds: Vec<Bond>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Atom {
+    pub element: String,
+    pub charge: i32,
+    /// Hydrogens given explicitly (`[NH4+]`); otherwise derived from the
+    /// usual valences.
+    pub hydrogens: Option<u32>,
+    pub aromatic: bool,
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Atom {
+    pub fn new(element: &str) -> Self {
+        Self {
+            element: element.to_string(),
+            charge: 0,
+            hydrogens: None,
+            aromatic: false,
+            x: 0.0,
+            y: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BondOrder {
+    Single,
+    Double,
+    Triple,
+    Aromatic,
+}
+
+impl BondOrder {
+    fn valence(self) -> u32 {
+        match self {
+            Self::Single | Self::Aromatic => 1,
+            Self::Double => 2,
+            Self::Triple => 3,
+        }
+    }
+
+    /// chemfig bond symbol.
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Single | Self::Aromatic => "-",
+            Self::Double => "=",
+            Self::Triple => "~",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Bond {
+    pub a: usize,
+    pub b: usize,
+    pub order: BondOrder,
+}
+
+impl Bond {
+    pub fn other(&self, atom: usize) -> usize {
+        if self.a == atom {
+            self.b
+        } else {
+            self.a
+        }
+    }
+}
+
+/// Usual valences of the elements whose hydrogens are left implicit.
+fn valences(element: &str) -> &'static [u32] {
+    match element {
+        "B" => &[3],
+        "C" | "Si" => &[4],
+        "N" | "P" | "As" => &[3, 5],
+        "O" | "Se" => &[2],
+        "S" => &[2, 4, 6],
+        "F" | "Cl" | "Br" | "I" => &[1],
+        _ => &[],
+    }
+}
+
+impl Molecule {
+    pub fn add_bond(&mut self, a: usize, b: usize, order: BondOrder) {
+        self.bonds.push(Bond { a, b, order });
+    }
+
+    /// Bond indices touching `atom`, in insertion order.
+    pub fn bonds_of(&self, atom: usize) -> Vec<usize> {
+        (0..self.bonds.len())
+            .filter(|&i| self.bonds[i].a == atom || self.bonds[i].b == atom)
+            .collect()
+    }
+
+    pub fn hydrogens(&self, atom: usize) -> u32 {
+        let a = &self.atoms[atom];
+        if let Some(count) = a.hydrogens {
+            return count;
+        }
+        let mut used: i64 = self
+            .bonds_of(atom)
+            .iter()
+            .map(|&i| i64::from(self.bonds[i].order.valence()))
+            .sum();
+        if a.aromatic {
+            // Of the aromatic atoms only carbon and boron carry implicit
+            // hydrogens; one more bond belongs to the ring's π system.
+            if !matches!(a.element.as_str(), "C" | "B") {
+                return 0;
+            }
+            used += 1;
+        }
+        // N+ and O+ bind one more partner, C+ and C- one fewer.
+        used += match a.element.as_str() {
+            "N" | "P" | "As" | "O" | "S" | "Se" => -i64::from(a.charge),
+            _ => i64::from(a.charge.abs()),
+        };
+        valences(&a.element)
+            .iter()
+            .map(|&v| i64::from(v))
+            .find(|&v| v >= used)
+            .map_or(0, |v| (v - used.max(0)) as u32)
+    }
+
+    /// Replaces aromatic bonds by alternating single and double bonds so
+    /// rings are drawn with explicit double bonds. Returns `false`, leaving
+    /// them single, when no valid assignment exists.
+    pub fn kekulize(&mut self) -> bool {
+        let hydrogens: Vec<u32> = (0..self.atoms.len()).map(|i| self.hydrogens(i)).collect();
+        let pending: Vec<usize> = (0..self.atoms.len())
+            .filter(|&i| {
+                let atom = &self.atoms[i];
+                let bonds = self.bonds_of(i);
+                let has_double = bonds
+                    .iter()
+                    .any(|&b| self.bonds[b].order == BondOrder::Double);
+                let valence = match atom.element.as_str() {
+                    "C" | "Si" => 4,
+                    "B" => 3,
+                    "N" | "P" | "As" => 3 + u32::from(atom.charge > 0),
+                    "O" | "S" | "Se" => 2 + u32::from(atom.charge > 0),
+                    _ => 0,
+                };
+                atom.aromatic && !has_double && (bonds.len() as u32 + hydrogens[i]) < valence
+            })
+            .collect();
+        let aromatic: Vec<usize> = (0..self.bonds.len())
+            .filter(|&i| self.bonds[i].order == BondOrder::Aromatic)
+            .collect();
+
+        let mut matched = vec![false; self.atoms.len()];
+        let mut doubles = Vec::new();
+        let found = self.match_doubles(&pending, &aromatic, &mut matched, &mut doubles);
+        // Hydrogens were counted with the aromatic rules; keep those counts.
+        for (atom, count) in self.atoms.iter_mut().zip(hydrogens) {
+            atom.aromatic = false;
+            atom.hydrogens = Some(count);
+        }
+        for &bond in &aromatic {
+            self.bonds[bond].order = BondOrder::Single;
+        }
+        if found {
+            for bond in doubles {
+                self.bonds[bond].order = BondOrder::Double;
+            }
+        }
+        found
+    }
+
+    /// Backtracking perfect matching of the `pending` atoms over aromatic
+    /// bonds.
+    fn match_doubles(
+        &self,
+        pending: &[usize],
+        aromatic: &[usize],
+        matched: &mut [bool],
+        doubles: &mut Vec<usize>,
+    ) -> bool {
+        let Some(&atom) = pending.iter().find(|&&a| !matched[a]) else {
+            return true;
+        };
+        for &bond in aromatic {
+            let Bond { a, b, .. } = self.bonds[bond];
+            if a != atom && b != atom {
+                continue;
+            }
+            let other = self.bonds[bond].other(atom);
+            if matched[other] || !pending.contains(&other) {
+                continue;
+            }
+            matched[atom] = true;
+            matched[other] = true;
+            doubles.push(bond);
+            if self.match_doubles(pending, aromatic, matched, doubles) {
+                return true;
+            }
+            doubles.pop();
+            matched[atom] = false;
+            matched[other] = false;
+        }
+        false
+    }
+
+    /// `\chemfig{...}` code for every connected component, drawn with the
+    /// bond angles of the atom coordinates.
+    pub fn to_chemfig(&self) -> String {
+        let mut writer = ChemfigWriter {
+            molecule: self,
+            unit: self.bond_length(),
+            seen: vec![false; self.atoms.len()],
+            tree: vec![false; self.bonds.len()],
+            hooks: HashMap::new(),
+        };
+        let mut components = Vec::new();
+        for start in 0..self.atoms.len() {
+            if writer.seen[start] {
+                continue;
+            }
+            writer.seen[start] = true;
+            writer.mark_tree(start);
+            writer.hooks.clear();
+            let mut out = String::new();
+            writer.write_atom(start, None, &mut out);
+            components.push(format!("\\chemfig{{{}}}", out));
+        }
+        components.join("\\quad ")
+    }
+
+    /// Median bond length, the drawing unit.
+    pub fn bond_length(&self) -> f64 {
+        let mut lengths: Vec<f64> = self
+            .bonds
+            .iter()
+            .map(|bond| self.distance(bond.a, bond.b))
+            .filter(|&d| d > 1e-6)
+            .collect();
+        lengths.sort_by(f64::total_cmp);
+        lengths.get(lengths.len() / 2).copied().unwrap_or(1.0)
+    }
+
+    pub fn distance(&self, a: usize, b: usize) -> f64 {
+        let (a, b) = (&self.atoms[a], &self.atoms[b]);
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+    }
+
+    /// Atom text as chemfig shows it: skeletal carbons stay empty, other
+    /// atoms get their hydrogens, on the side the bond arrives from.
+    fn label(&self, atom: usize, from_right: bool) -> String {
+        let a = &self.atoms[atom];
+        if a.element == "C" && a.charge == 0 && !self.bonds_of(atom).is_empty() {
+            return String::new();
+        }
+        let h = match self.hydrogens(atom) {
+            0 => String::new(),
+            1 => "H".to_string(),
+            n => format!("H_{}", n),
+        };
+        let charge = match a.charge {
+            0 => String::new(),
+            1 => "^{+}".to_string(),
+            -1 => "^{-}".to_string(),
+            n if n > 0 => format!("^{{{}+}}", n),
+            n => format!("^{{{}-}}", -n),
+        };
+        if from_right && !h.is_empty() {
+            format!("{}{}{}", h, a.element, charge)
+        } else {
+            format!("{}{}{}", a.element, h, charge)
+        }
+    }
+}
+
+struct ChemfigWriter<'a> {
+    molecule: &'a Molecule,
+    unit: f64,
+    seen: Vec<bool>,
+    /// Bonds of the depth-first spanning tree; the others close rings.
+    tree: Vec<bool>,
+    /// Ring-closure bond → hook name, assigned when its first end is written.
+    hooks: HashMap<usize, String>,
+}
+
+impl ChemfigWriter<'_> {
+    fn mark_tree(&mut self, atom: usize) {
+        for bond in self.molecule.bonds_of(atom) {
+            let other = self.molecule.bonds[bond].other(atom);
+            if !self.seen[other] {
+                self.seen[other] = true;
+                self.tree[bond] = true;
+                self.mark_tree(other);
+            }
+        }
+    }
+
+    fn write_atom(&mut self, atom: usize, parent_bond: Option<usize>, out: &mut String) {
+        let position = &self.molecule.atoms[atom];
+        let from_right = parent_bond.is_some_and(|bond| {
+            let parent = &self.molecule.atoms[self.molecule.bonds[bond].other(atom)];
+            parent.x > position.x + 1e-6
+        });
+        out.push_str(&self.molecule.label(atom, from_right));
+
+        let bonds = self.molecule.bonds_of(atom);
+        for &bond in bonds.iter().filter(|&&bond| !self.tree[bond]) {
+            match self.hooks.get(&bond) {
+                // The closing end carries the bond type.
+                Some(name) => match self.molecule.bonds[bond].order {
+                    BondOrder::Single | BondOrder::Aromatic => {
+                        out.push_str(&format!("?[{}]", name))
+                    }
+                    order => out.push_str(&format!("?[{},{{{}}}]", name, order.symbol())),
+                },
+                None => {
+                    let name = hook_name(self.hooks.len());
+                    out.push_str(&format!("?[{}]", name));
+                    self.hooks.insert(bond, name);
+                }
+            }
+        }
+
+        let children: Vec<usize> = bonds
+            .into_iter()
+            .filter(|&bond| self.tree[bond] && Some(bond) != parent_bond)
+            .collect();
+        for (i, &bond) in children.iter().enumerate() {
+            let child = self.molecule.bonds[bond].other(atom);
+            let last = i + 1 == children.len();
+            if !last {
+                out.push('(');
+            }
+            out.push_str(&self.bond_text(bond, atom, child));
+            self.write_atom(child, Some(bond), out);
+            if !last {
+                out.push(')');
+            }
+        }
+    }
+
+    /// `-[:30]`, `=[:-90,1.20]`: absolute angle, plus the length when it
+    /// differs noticeably from the average bond.
+    fn bond_text(&self, bond: usize, from: usize, to: usize) -> String {
+        let (a, b) = (&self.molecule.atoms[from], &self.molecule.atoms[to]);
+        let angle = (b.y - a.y).atan2(b.x - a.x).to_degrees().round() as i64;
+        let angle = if angle <= -180 { angle + 360 } else { angle };
+        let length = self.molecule.distance(from, to) / self.unit;
+        let symbol = self.molecule.bonds[bond].order.symbol();
+        if (length - 1.0).abs() > 0.1 {
+            format!("{}[:{},{:.2}]", symbol, angle, length)
+        } else {
+            format!("{}[:{}]", symbol, angle)
+        }
+    }
+}
+
+/// `a`, `b`, …, `z`, `aa`, `ab`, …
+fn hook_name(index: usize) -> String {
+    let mut name = Vec::new();
+    let mut index = index + 1;
+    while index > 0 {
+        index -= 1;
+        name.push(b'a' + (index % 26) as u8);
+        index /= 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
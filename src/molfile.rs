@@ -0,0 +1,258 @@
+use crate::cli::Args;
+use crate::molecule::{Atom, BondOrder, Molecule};
+use crate::render;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "\
+Usage: chemtex molfile <structure.mol|.sdf> [options]
+
+Prints a figure block for the structure, drawn with chemfig or as an image.
+
+Options:
+  --as chemfig|png  Output kind (default: chemfig)
+  --record N        Structure to take from a multi-record SDF file (default: 1)
+  --name NAME       Figure label and image file name (default: the
+                    structure's name, else the file name)
+  --caption TEXT    Figure caption (default: the structure's name)
+  --figures DIR     Where images go (default: figures)";
+
+/// Converts MOL (V2000 or V3000) and SDF structure files into chemfig code
+/// or a rendered image, printing the `figure` block that shows it.
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &[],
+        &["as", "record", "name", "caption", "figures"],
+    )?;
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+    let record = args.parsed::<usize>("record")?.unwrap_or(1);
+    let text = fs::read_to_string(&input)
+        .with_context(|| format!("Failed to read file: {}", input.display()))?;
+    let (title, mut molecule) = parse_record(&text, record)
+        .with_context(|| format!("Failed to parse {}", input.display()))?;
+    molecule.kekulize();
+
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let title = title.unwrap_or(stem);
+    let name = slug(args.value("name").unwrap_or(&title));
+    let caption = args.value("caption").unwrap_or(&title);
+
+    let body = match args.value("as").unwrap_or("chemfig") {
+        "chemfig" => molecule.to_chemfig(),
+        "png" => {
+            let dir = Path::new(args.value("figures").unwrap_or("figures"));
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+            let path = dir.join(format!("{}.png", name));
+            fs::write(&path, render::png(&molecule)?)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            eprintln!("Image written to {}", path.display());
+            format!(
+                "\\includegraphics{{{}}}",
+                path.to_string_lossy().replace('\\', "/")
+            )
+        }
+        other => anyhow::bail!("Unknown output kind {:?}, expected chemfig or png", other),
+    };
+    println!(
+        "\\begin{{figure}}[h]\n\\centering\n{}\n\\caption{{{}}}\n\\label{{fig:{}}}\n\\end{{figure}}",
+        body, caption, name
+    );
+    Ok(())
+}
+
+/// The `record`-th structure (1-based) of an SDF file, or the only one of a
+/// MOL file, with the name from its header line.
+fn parse_record(text: &str, record: usize) -> Result<(Option<String>, Molecule)> {
+    // Records after the first start on the line after `$$$$`; a blank first
+    // line is an empty name, so nothing else is trimmed.
+    let records: Vec<&str> = text
+        .split("$$$$")
+        .enumerate()
+        .map(|(i, r)| match i {
+            0 => r,
+            _ => r
+                .strip_prefix("\r\n")
+                .or_else(|| r.strip_prefix('\n'))
+                .unwrap_or(r),
+        })
+        .filter(|r| !r.trim().is_empty())
+        .collect();
+    let block = records
+        .get(record.saturating_sub(1))
+        .with_context(|| format!("No record {} ({} in file)", record, records.len()))?;
+    let lines: Vec<&str> = block.lines().collect();
+    let title = lines
+        .first()
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty());
+    let counts = lines.get(3).context("Missing counts line")?;
+    let mut molecule = if counts.contains("V3000") {
+        parse_v3000(&lines)?
+    } else {
+        parse_v2000(&lines, counts)?
+    };
+    strip_hydrogens(&mut molecule);
+    Ok((title, molecule))
+}
+
+fn parse_v2000(lines: &[&str], counts: &str) -> Result<Molecule> {
+    let field = |line: &str, range: std::ops::Range<usize>| {
+        line.get(range.start..range.end.min(line.len()))
+            .unwrap_or("")
+            .trim()
+            .to_string()
+    };
+    let atoms: usize = field(counts, 0..3).parse().context("Bad atom count")?;
+    let bonds: usize = field(counts, 3..6).parse().context("Bad bond count")?;
+    let mut molecule = Molecule::default();
+
+    for i in 0..atoms {
+        let line = lines.get(4 + i).context("Atom block ends early")?;
+        let mut atom = Atom::new(&field(line, 31..34));
+        atom.x = field(line, 0..10).parse().context("Bad x coordinate")?;
+        atom.y = field(line, 10..20).parse().context("Bad y coordinate")?;
+        // 1 = +3, 2 = +2, 3 = +1, 5 = -1, 6 = -2, 7 = -3.
+        atom.charge = match field(line, 36..39).parse::<i32>().unwrap_or(0) {
+            code @ (1..=3 | 5..=7) => 4 - code,
+            _ => 0,
+        };
+        molecule.atoms.push(atom);
+    }
+    for i in 0..bonds {
+        let line = lines.get(4 + atoms + i).context("Bond block ends early")?;
+        let a: usize = field(line, 0..3).parse().context("Bad bond atom")?;
+        let b: usize = field(line, 3..6).parse().context("Bad bond atom")?;
+        let order = bond_order(&field(line, 6..9))?;
+        add_bond(&mut molecule, a, b, order)?;
+    }
+
+    // `M  CHG` lines replace the charges of the atom block.
+    let properties = lines.iter().skip(4 + atoms + bonds);
+    for line in properties.filter(|l| l.starts_with("M  CHG")) {
+        let values: Vec<i32> = line[6..]
+            .split_whitespace()
+            .skip(1)
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        for pair in values.chunks(2) {
+            if let [atom, charge] = pair {
+                if let Some(atom) = molecule.atoms.get_mut((*atom as usize).wrapping_sub(1)) {
+                    atom.charge = *charge;
+                }
+            }
+        }
+    }
+    Ok(molecule)
+}
+
+fn parse_v3000(lines: &[&str]) -> Result<Molecule> {
+    let mut molecule = Molecule::default();
+    let mut section = "";
+    for line in lines {
+        let Some(content) = line.strip_prefix("M  V30 ") else {
+            continue;
+        };
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        match fields.as_slice() {
+            ["BEGIN", name, ..] => section = name,
+            ["END", ..] => section = "",
+            [_, element, x, y, _z, rest @ ..] if section == "ATOM" => {
+                let mut atom = Atom::new(element);
+                atom.x = x.parse().context("Bad x coordinate")?;
+                atom.y = y.parse().context("Bad y coordinate")?;
+                atom.charge = rest
+                    .iter()
+                    .find_map(|f| f.strip_prefix("CHG="))
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or(0);
+                molecule.atoms.push(atom);
+            }
+            [_, order, a, b, ..] if section == "BOND" => {
+                let order = bond_order(order)?;
+                add_bond(
+                    &mut molecule,
+                    a.parse().context("Bad bond atom")?,
+                    b.parse().context("Bad bond atom")?,
+                    order,
+                )?;
+            }
+            _ => {}
+        }
+    }
+    Ok(molecule)
+}
+
+fn bond_order(code: &str) -> Result<BondOrder> {
+    match code {
+        "1" => Ok(BondOrder::Single),
+        "2" => Ok(BondOrder::Double),
+        "3" => Ok(BondOrder::Triple),
+        "4" => Ok(BondOrder::Aromatic),
+        other => anyhow::bail!("Unsupported bond type {}", other),
+    }
+}
+
+/// Adds a bond between 1-based atom numbers; aromatic bonds mark their
+/// atoms aromatic.
+fn add_bond(molecule: &mut Molecule, a: usize, b: usize, order: BondOrder) -> Result<()> {
+    let count = molecule.atoms.len();
+    if a == 0 || b == 0 || a > count || b > count {
+        anyhow::bail!("Bond refers to missing atom {}-{}", a, b);
+    }
+    if order == BondOrder::Aromatic {
+        molecule.atoms[a - 1].aromatic = true;
+        molecule.atoms[b - 1].aromatic = true;
+    }
+    molecule.add_bond(a - 1, b - 1, order);
+    Ok(())
+}
+
+/// Drops hydrogens drawn as atoms on a single heavy atom; the labels show
+/// them again as implicit hydrogens.
+fn strip_hydrogens(molecule: &mut Molecule) {
+    let removable: Vec<bool> = (0..molecule.atoms.len())
+        .map(|i| {
+            let bonds = molecule.bonds_of(i);
+            molecule.atoms[i].element == "H"
+                && molecule.atoms[i].charge == 0
+                && bonds.len() == 1
+                && molecule.atoms[molecule.bonds[bonds[0]].other(i)].element != "H"
+        })
+        .collect();
+    let mut index = Vec::with_capacity(removable.len());
+    let mut next = 0;
+    for &remove in &removable {
+        index.push(next);
+        if !remove {
+            next += 1;
+        }
+    }
+    molecule
+        .bonds
+        .retain(|b| !removable[b.a] && !removable[b.b]);
+    for bond in &mut molecule.bonds {
+        bond.a = index[bond.a];
+        bond.b = index[bond.b];
+    }
+    let mut keep = removable.iter().map(|r| !r);
+    molecule.atoms.retain(|_| keep.next().unwrap_or(true));
+}
+
+/// `Aspirin (form II)` → `aspirin-form-ii`.
+fn slug(name: &str) -> String {
+    let slug: String = name
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    slug.split('-')
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -0,0 +1,162 @@
+//! OpenTelemetry trace export (`--features otel`).
+//!
+//! Every job becomes a `chemtex.job` span with one child span per phase
+//! (pack, upload, queue, processing, download), sent as OTLP/HTTP JSON to
+//! `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`, or to `/v1/traces` under
+//! `OTEL_EXPORTER_OTLP_ENDPOINT`. Nothing is sent when neither is set.
+//! `OTEL_EXPORTER_OTLP_HEADERS` (`key=value,...`) and `OTEL_SERVICE_NAME`
+//! are honoured as well.
+
+use crate::job::JobReport;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends the spans of a finished job. Export failures are only logged, they
+/// never fail the job.
+pub async fn export(client: &reqwest::Client, report: &JobReport) {
+    let Some(endpoint) = endpoint() else {
+        return;
+    };
+    let mut request = client
+        .post(&endpoint)
+        .timeout(EXPORT_TIMEOUT)
+        .json(&payload(report));
+    for (name, value) in headers() {
+        request = request.header(name, value);
+    }
+    match request.send().await {
+        Ok(response) if !response.status().is_success() => {
+            eprintln!(
+                "Warning: trace export to {} failed with status {}",
+                endpoint,
+                response.status()
+            );
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Warning: trace export to {} failed: {}", endpoint, e),
+    }
+}
+
+fn endpoint() -> Option<String> {
+    if let Some(url) = env("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT") {
+        return Some(url);
+    }
+    env("OTEL_EXPORTER_OTLP_ENDPOINT")
+        .map(|base| format!("{}/v1/traces", base.trim_end_matches('/')))
+}
+
+fn headers() -> Vec<(String, String)> {
+    env("OTEL_EXPORTER_OTLP_HEADERS")
+        .map(|list| {
+            list.split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn payload(report: &JobReport) -> Value {
+    let details = &report.details;
+    let end = SystemTime::now();
+    let start = end - report.elapsed;
+    let trace_id = id(&report.job.name, start, 16);
+    let root_id = id(&trace_id, start, 8);
+
+    let mut attributes = vec![
+        attribute("chemtex.job", &report.job.name),
+        attribute("chemtex.input", &report.job.input.display().to_string()),
+        attribute(
+            "chemtex.result",
+            match &report.result {
+                Ok(_) => "success",
+                Err(_) if report.cancelled => "cancelled",
+                Err(_) => "failure",
+            },
+        ),
+        json!({ "key": "chemtex.cached", "value": { "boolValue": details.cached } }),
+    ];
+    if let Some(task_id) = &details.task_id {
+        attributes.push(attribute("chemtex.task_id", task_id));
+    }
+    if let Some(bytes) = details.upload_bytes {
+        attributes.push(json!({
+            "key": "chemtex.upload_bytes",
+            "value": { "intValue": bytes.to_string() },
+        }));
+    }
+    let status = match &report.result {
+        Ok(_) => json!({ "code": 1 }),
+        Err(e) => json!({ "code": 2, "message": format!("{:#}", e) }),
+    };
+
+    let mut spans = vec![json!({
+        "traceId": trace_id,
+        "spanId": root_id,
+        "name": "chemtex.job",
+        "kind": 1,
+        "startTimeUnixNano": nanos(start),
+        "endTimeUnixNano": nanos(end),
+        "attributes": attributes,
+        "status": status,
+    })];
+    for timing in &details.phases {
+        let name = format!("chemtex.{}", timing.phase.as_str());
+        spans.push(json!({
+            "traceId": trace_id,
+            "spanId": id(&name, timing.started_at, 8),
+            "parentSpanId": root_id,
+            "name": name,
+            "kind": 1,
+            "startTimeUnixNano": nanos(timing.started_at),
+            "endTimeUnixNano": nanos(timing.started_at + timing.duration),
+        }));
+    }
+
+    let service = env("OTEL_SERVICE_NAME").unwrap_or_else(|| "chemtex".to_string());
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    attribute("service.name", &service),
+                    attribute("service.version", env!("CARGO_PKG_VERSION")),
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "chemtex" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// A random-looking hex id of `bytes` bytes, unique per process and moment.
+fn id(seed: &str, time: SystemTime, bytes: usize) -> String {
+    let digest = Sha256::new()
+        .chain_update(seed)
+        .chain_update(nanos(time))
+        .chain_update(std::process::id().to_le_bytes())
+        .finalize();
+    digest[..bytes]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,210 @@
+use crate::api::{self, CompileOptions};
+use crate::batch;
+use crate::cli::Args;
+use crate::git::GitSource;
+use crate::job::{Job, Runner};
+use crate::pack::{self, TempDir};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const OVERLEAF_HOST: &str = "https://www.overleaf.com";
+const OVERLEAF_GIT_HOST: &str = "https://git.overleaf.com/";
+/// Overleaf's session cookie, needed to download a project export.
+const SESSION_ENV: &str = "OVERLEAF_SESSION";
+
+const USAGE: &str = "\
+Usage: chemtex import-overleaf <project-url-or-zip> [options]
+
+Accepts an exported project zip, an Overleaf project URL (set OVERLEAF_SESSION
+to the value of your overleaf_session2 cookie) or a git.overleaf.com URL.
+
+Options:
+  --into DIR       Keep the imported project in DIR
+  --main FILE      Main file relative to the project (default: detected)
+  --engine NAME    Engine to use (default: from latexmkrc)
+  --profile NAME   Output profile
+  --no-cache       Always submit, ignoring the build cache";
+
+/// Imports an Overleaf project, normalizes its layout and compiles it.
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["no-cache"],
+        &["into", "main", "engine", "profile"],
+    )?;
+    let source = args.positional(0).context(USAGE)?;
+
+    let scratch = TempDir::new("overleaf")?;
+    let project_dir = match args.value("into") {
+        Some(dir) => {
+            let dir = PathBuf::from(dir);
+            if dir.exists() && fs::read_dir(&dir)?.next().is_some() {
+                anyhow::bail!("{} is not empty", dir.display());
+            }
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+            dir
+        }
+        None => scratch.path().join("project"),
+    };
+
+    let client = api::build_client()?;
+    fetch_project(&client, source, &project_dir, scratch.path()).await?;
+
+    let root = project_root(&project_dir)?;
+    let main = match args.value("main") {
+        Some(main) => {
+            let main = root.join(main);
+            if !main.is_file() {
+                anyhow::bail!("{} does not exist in the project", main.display());
+            }
+            main
+        }
+        None => batch::find_main_document(&root).context("Pick the main file with --main")?,
+    };
+    let options = CompileOptions {
+        engine: args
+            .value("engine")
+            .map(str::to_string)
+            .or_else(|| latexmkrc_engine(&root)),
+        profile: args.value("profile").map(str::to_string),
+        ..CompileOptions::default()
+    };
+    println!(
+        "Main file: {} (engine: {})",
+        main.strip_prefix(&root).unwrap_or(&main).display(),
+        options.engine.as_deref().unwrap_or("server default")
+    );
+
+    let archive = pack::pack_directory(&main, &root, scratch.path())?;
+    let mut job = Job::new(&archive, Path::new(""))?;
+    job.options = options;
+    let runner = Runner::new(client, !args.flag("no-cache"))?;
+    runner.run(&job, "").await.result?;
+
+    if args.value("into").is_some() {
+        println!("Project imported into {}", project_dir.display());
+    }
+    Ok(())
+}
+
+/// Puts the project files into `dest`, from a local zip, a git.overleaf.com
+/// clone or an Overleaf zip export.
+async fn fetch_project(
+    client: &reqwest::Client,
+    source: &str,
+    dest: &Path,
+    scratch: &Path,
+) -> Result<()> {
+    if source.starts_with(OVERLEAF_GIT_HOST) {
+        println!("Cloning {}...", source);
+        GitSource::parse(source)?.clone_into(dest)?;
+        return Ok(());
+    }
+
+    let archive = if source.starts_with("http://") || source.starts_with("https://") {
+        let url = export_url(source);
+        println!("Downloading {}...", url);
+        let mut request = client.get(&url);
+        if let Ok(session) = std::env::var(SESSION_ENV) {
+            request = request.header("Cookie", format!("overleaf_session2={}", session));
+        }
+        let response = request.send().await.context("Failed to download project")?;
+        let status = response.status();
+        let is_zip = response
+            .headers()
+            .get("Content-Type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|t| t.contains("zip") || t.contains("octet-stream"));
+        if !status.is_success() || !is_zip {
+            anyhow::bail!(
+                "Could not download the project export (status {}); set {} to your \
+                 overleaf_session2 cookie or download the zip manually",
+                status,
+                SESSION_ENV
+            );
+        }
+        let path = scratch.join("export.zip");
+        let bytes = response
+            .bytes()
+            .await
+            .context("Failed to read project export")?;
+        fs::write(&path, &bytes)
+            .with_context(|| format!("Failed to write file: {}", path.display()))?;
+        path
+    } else {
+        PathBuf::from(source)
+    };
+
+    pack::extract_archive(&archive, dest)
+}
+
+/// `https://www.overleaf.com/project/<id>` → its zip export URL; other URLs
+/// are assumed to point at a zip already.
+fn export_url(url: &str) -> String {
+    let Some(rest) = url
+        .strip_prefix(OVERLEAF_HOST)
+        .and_then(|r| r.strip_prefix("/project/"))
+    else {
+        return url.to_string();
+    };
+    let id: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect();
+    format!("{}/project/{}/download/zip", OVERLEAF_HOST, id)
+}
+
+/// Exports of a project sometimes wrap everything in a single top-level
+/// folder; descend into it so paths match what the editor showed.
+fn project_root(dir: &Path) -> Result<PathBuf> {
+    let mut root = dir.to_path_buf();
+    loop {
+        let entries: Vec<PathBuf> = fs::read_dir(&root)
+            .with_context(|| format!("Failed to read directory: {}", root.display()))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                !p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with('.') || n == "__MACOSX")
+            })
+            .collect();
+        match entries.as_slice() {
+            [only] if only.is_dir() => root = only.clone(),
+            _ => return Ok(root),
+        }
+    }
+}
+
+/// Engine selected in Overleaf's `latexmkrc` (`$pdf_mode = 5;` or
+/// `$pdflatex = 'xelatex %O %S';`), if any.
+fn latexmkrc_engine(root: &Path) -> Option<String> {
+    let text = ["latexmkrc", ".latexmkrc"]
+        .iter()
+        .find_map(|name| fs::read_to_string(root.join(name)).ok())?;
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_end_matches(';').trim();
+        match name.trim() {
+            "$pdf_mode" => match value {
+                "1" => return Some("pdflatex".to_string()),
+                "4" => return Some("lualatex".to_string()),
+                "5" => return Some("xelatex".to_string()),
+                _ => {}
+            },
+            "$pdflatex" => {
+                for engine in ["xelatex", "lualatex", "pdflatex"] {
+                    if value.contains(engine) {
+                        return Some(engine.to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
@@ -0,0 +1,414 @@
+use crate::accessibility;
+use crate::citations::CitationStyle;
+use crate::config::ProjectConfig;
+use crate::deps::DependencyGraph;
+use crate::formulas;
+use crate::languages::Languages;
+use crate::paths;
+use crate::preprocess;
+use crate::qr;
+use crate::safety;
+use crate::schemes::Schemes;
+use crate::scratch;
+use crate::selective;
+use crate::titlepage::TitlePage;
+use crate::variables::{self, Variables};
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Intermediate and output files of a local TeX run, left behind in exported
+/// projects.
+const BUILD_ARTIFACTS: &[&str] = &[
+    ".aux",
+    ".log",
+    ".out",
+    ".toc",
+    ".lof",
+    ".lot",
+    ".fls",
+    ".fdb_latexmk",
+    ".synctex.gz",
+    ".bbl",
+    ".blg",
+];
+
+/// How a project is transformed on its way into the archive; the sources on
+/// disk are left untouched.
+#[derive(Debug, Clone, Default)]
+pub struct PackOptions {
+    /// Values for `{{name}}` placeholders in the packed `.tex` files.
+    pub variables: Variables,
+    /// Rewrites the main file's preamble.
+    pub citation_style: Option<CitationStyle>,
+    /// Chapters to compile on their own (see [`selective`]).
+    pub only: Vec<String>,
+    /// Indexes every `\ce{...}` in an "Index of Compounds" (see
+    /// [`formulas`]).
+    pub formula_index: bool,
+    /// Sets up babel or polyglossia in the main file (see [`crate::languages`]).
+    pub languages: Option<Languages>,
+    /// Replaces the main file's `\maketitle` (see [`crate::titlepage`]).
+    pub titlepage: Option<TitlePage>,
+    /// Stamps a QR code for this link on the first page (see [`crate::qr`]).
+    pub qr: Option<String>,
+}
+
+impl PackOptions {
+    /// What a project's `.chemtex.toml` asks of packing: its variables,
+    /// citation style, formula index, languages and title page. The QR code,
+    /// which may need the project's git history, is left to the caller.
+    pub fn from_config(config: &ProjectConfig) -> Result<Self> {
+        Ok(Self {
+            variables: config.variables.clone(),
+            citation_style: config
+                .citation_style
+                .as_deref()
+                .map(CitationStyle::parse)
+                .transpose()?,
+            formula_index: config.formula_index,
+            languages: config
+                .lang
+                .as_deref()
+                .map(|spec| Languages::parse(spec, config.engine.as_deref()))
+                .transpose()?,
+            titlepage: config.titlepage.clone(),
+            ..Self::default()
+        })
+    }
+}
+
+/// Packs a multi-file project into a zip in `dest_dir`, named after the
+/// main file so the downloaded PDF keeps the document's name.
+///
+/// Every file reachable from `main` through its include graph is stored
+/// relative to the main file's directory; references outside that directory
+/// cannot be reproduced on the server and are rejected.
+pub fn pack_project(main: &Path, dest_dir: &Path) -> Result<PathBuf> {
+    pack_project_with(main, dest_dir, &PackOptions::default())
+}
+
+/// Like [`pack_project`], applying `options`.
+///
+/// Packed `.tex` files always have their `%%chemtex:` directives expanded
+/// (see [`preprocess`]) and their reaction schemes numbered (see
+/// [`crate::schemes`]).
+pub fn pack_project_with(main: &Path, dest_dir: &Path, options: &PackOptions) -> Result<PathBuf> {
+    let root = main.parent().unwrap_or(Path::new(""));
+    let files = project_files(main, options)?;
+    write_archive(main, root, &files, dest_dir, options)
+}
+
+/// `main.zip` for `main.tex`.
+pub fn archive_name(main: &Path) -> Result<String> {
+    let stem = main
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid file name")?;
+    Ok(format!("{}.zip", stem))
+}
+
+/// A project a job packs itself, so the build cache can be checked first
+/// (see [`crate::index`]) and the archive streamed (`--stream`).
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub main: PathBuf,
+    pub options: PackOptions,
+}
+
+/// Packs `files` of `project` (see [`project_files`]) like
+/// [`pack_project_with`].
+pub fn pack_files(project: &Project, files: &[PathBuf], dest_dir: &Path) -> Result<PathBuf> {
+    let root = project.main.parent().unwrap_or(Path::new(""));
+    write_archive(&project.main, root, files, dest_dir, &project.options)
+}
+
+/// Like [`pack_files`], but hands the archive to `send` in pieces instead
+/// of saving it: each file's entry as soon as it is compressed, then the
+/// central directory. Only the entry being compressed is held in memory.
+/// Returns the size of the archive.
+pub fn stream_files(
+    project: &Project,
+    files: &[PathBuf],
+    send: impl FnMut(Vec<u8>) -> io::Result<()>,
+) -> Result<u64> {
+    let main = &project.main;
+    let root = main.parent().unwrap_or(Path::new(""));
+    let mut zip = ZipWriter::new(Streamed {
+        send,
+        pending: Vec::new(),
+        sent: 0,
+        position: 0,
+    });
+    // Entries are final once the next one starts; see `Streamed`.
+    zip.set_flush_on_finish_file(true);
+    let mut streamed = write_entries(zip, main, root, files, &project.options)?;
+    streamed.flush().context("Failed to send archive")?;
+    Ok(streamed.sent)
+}
+
+/// The files of the project of `main`, warning about missing ones.
+pub fn project_files(main: &Path, options: &PackOptions) -> Result<Vec<PathBuf>> {
+    let root = main.parent().unwrap_or(Path::new(""));
+    let mut graph = DependencyGraph::scan(main)?;
+    for missing in &graph.missing {
+        eprintln!(
+            "Warning: {} references {}, which was not found",
+            main.display(),
+            missing.display()
+        );
+    }
+    if main.extension().and_then(|e| e.to_str()) == Some("tex") {
+        for finding in accessibility::check_figures(main)? {
+            eprintln!("Warning: {}", finding);
+        }
+    }
+    if !options.only.is_empty() {
+        // The .aux files of a local build keep the skipped chapters'
+        // numbers and references.
+        let text = fs::read_to_string(main)
+            .with_context(|| format!("Failed to read file: {}", main.display()))?;
+        for chapter in selective::skipped(&text, &options.only) {
+            let aux = root.join(format!("{}.aux", chapter));
+            match aux.is_file() {
+                true => graph.files.push(aux),
+                false => eprintln!(
+                    "Warning: {} is missing, so references into {} print as ??; \
+                     build the whole document locally once to keep them",
+                    aux.display(),
+                    chapter
+                ),
+            }
+        }
+    }
+    Ok(graph.files)
+}
+
+/// Packs every file under `root` (skipping hidden files and TeX build
+/// artifacts), for projects whose includes cannot all be found by scanning.
+pub fn pack_directory(main: &Path, root: &Path, dest_dir: &Path) -> Result<PathBuf> {
+    let files = directory_files(root)?;
+    write_archive(main, root, &files, dest_dir, &PackOptions::default())
+}
+
+/// Every file under `root` but hidden files and TeX build artifacts, sorted.
+pub fn directory_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = fs::read_dir(&current)
+            .with_context(|| format!("Failed to read directory: {}", current.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if name.starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                pending.push(path);
+            } else if !BUILD_ARTIFACTS.iter().any(|ext| name.ends_with(ext)) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn write_archive(
+    main: &Path,
+    root: &Path,
+    files: &[PathBuf],
+    dest_dir: &Path,
+    pack_options: &PackOptions,
+) -> Result<PathBuf> {
+    let archive_path = dest_dir.join(archive_name(main)?);
+    let archive = File::create(&archive_path)
+        .with_context(|| format!("Failed to create archive: {}", archive_path.display()))?;
+    write_entries(ZipWriter::new(archive), main, root, files, pack_options)?;
+    Ok(archive_path)
+}
+
+fn write_entries<W: Write + Seek>(
+    mut zip: ZipWriter<W>,
+    main: &Path,
+    root: &Path,
+    files: &[PathBuf],
+    pack_options: &PackOptions,
+) -> Result<W> {
+    let schemes = match main.extension().and_then(|e| e.to_str()) {
+        Some("tex") => Schemes::scan(main)?,
+        _ => Schemes::default(),
+    };
+
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut written = Vec::new();
+    let mut pictograms = Vec::new();
+    for file in files {
+        let name = file.strip_prefix(root).with_context(|| {
+            format!(
+                "{} is outside the project directory {}",
+                file.display(),
+                root.display()
+            )
+        })?;
+        let name = paths::archive_name(name)?;
+        let mut contents =
+            fs::read(file).with_context(|| format!("Failed to read file: {}", file.display()))?;
+        if name.ends_with(".tex") {
+            if let Ok(text) = std::str::from_utf8(&contents) {
+                // Before substituting, so title page fields can use variables.
+                let text = match (&pack_options.titlepage, file == main) {
+                    (Some(titlepage), true) => titlepage.inject(text),
+                    _ => text.to_string(),
+                };
+                let mut text = variables::substitute(&text, &pack_options.variables);
+                if file == main {
+                    if let Some(style) = pack_options.citation_style {
+                        text = style.inject(&text);
+                    }
+                    if !pack_options.only.is_empty() {
+                        text = selective::restrict(&text, &pack_options.only)?;
+                    }
+                    if let Some(link) = &pack_options.qr {
+                        text = qr::inject(&text, link);
+                    }
+                }
+                if let Some(languages) = &pack_options.languages {
+                    text = match file == main {
+                        true => languages.inject(&text),
+                        false => languages.strip(&text),
+                    };
+                }
+                let text = schemes
+                    .rewrite(&text)
+                    .with_context(|| format!("Failed to number schemes in {}", file.display()))?;
+                let dir = file.parent().unwrap_or(root);
+                let mut expanded = preprocess::expand(&text, dir)
+                    .with_context(|| format!("Failed to expand {}", file.display()))?;
+                if pack_options.formula_index {
+                    if file == main {
+                        expanded = formulas::setup(&expanded);
+                    }
+                    expanded = formulas::annotate(&expanded);
+                }
+                pictograms.extend(safety::referenced_pictograms(&expanded));
+                contents = expanded.into_bytes();
+            }
+        }
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(&contents)?;
+        written.push(name);
+    }
+    // Safety tables point at GHS pictograms the project may not have on disk.
+    for (name, pdf) in pictograms {
+        if !written.contains(&name) {
+            zip.start_file(name.as_str(), options)?;
+            zip.write_all(&pdf)?;
+            written.push(name);
+        }
+    }
+    zip.finish().context("Failed to write archive")
+}
+
+/// The sink of a streamed archive. The zip writer seeks back only into the
+/// entry it is writing, to fill in the sizes and checksum in its header,
+/// and flushes once an entry is complete, so everything before a flush is
+/// final and can be sent.
+struct Streamed<F> {
+    send: F,
+    /// Written but not yet final.
+    pending: Vec<u8>,
+    /// Bytes sent, the offset of `pending` in the archive.
+    sent: u64,
+    position: u64,
+}
+
+impl<F: FnMut(Vec<u8>) -> io::Result<()>> Write for Streamed<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let offset = (self.position - self.sent) as usize;
+        let overlap = buf.len().min(self.pending.len().saturating_sub(offset));
+        self.pending[offset..offset + overlap].copy_from_slice(&buf[..overlap]);
+        self.pending.extend_from_slice(&buf[overlap..]);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        self.sent += pending.len() as u64;
+        (self.send)(pending)
+    }
+}
+
+/// Required by `set_flush_on_finish_file`; the zip writer reads only to
+/// copy entries it has written, which packing never does.
+impl<F> Read for Streamed<F> {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "a streamed archive cannot be read back",
+        ))
+    }
+}
+
+impl<F> Seek for Streamed<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let end = self.sent + self.pending.len() as u64;
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => end.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match target {
+            Some(target) if (self.sent..=end).contains(&target) => {
+                self.position = target;
+                Ok(target)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot seek into the part of the archive already sent",
+            )),
+        }
+    }
+}
+
+/// Extracts a zip archive into `dest`, refusing entries that would escape it.
+pub fn extract_archive(archive: &Path, dest: &Path) -> Result<()> {
+    let file =
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    let mut zip = ZipArchive::new(file)
+        .with_context(|| format!("{} is not a zip archive", archive.display()))?;
+    zip.extract(dest)
+        .with_context(|| format!("Failed to extract {}", archive.display()))
+}
+
+/// A scratch directory under the temp root (see [`crate::scratch`]),
+/// removed on drop.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Result<Self> {
+        Ok(Self {
+            path: scratch::dir(prefix)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,664 @@
+use crate::accounts;
+use crate::api::{self, CompileOptions};
+use crate::batch;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::deps;
+use crate::job::{Job, Runner};
+use crate::pack::{self, PackOptions, TempDir};
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const USAGE: &str = "\
+Usage: chemtex package --target arxiv <main.tex|project_dir> [options]
+
+Produces a submission-ready archive of a document, e.g.
+  chemtex package --target arxiv paper/
+
+The project is packed as for compiling, then its \\input and \\include files
+are flattened into the main file and comments are stripped. The bibliography
+is replaced by the .bbl of a local build (inlined for BibTeX, shipped next to
+the main file for biblatex), so the .bib files are left out. Figures are
+normalized to PDF, PNG or JPEG: EPS is converted with epstopdf and SVG with
+rsvg-convert. The archive is then compiled exactly as it is, with pdflatex,
+to check that it builds.
+
+Options:
+  --target NAME   Where the archive is submitted; only arxiv is supported
+  --out FILE      The archive to write (default: <main>-arxiv.zip)
+  --bbl FILE      The bibliography to ship (default: the main file's .bbl)
+  --no-verify     Do not compile the archive
+  --no-cache      Always submit, ignoring the build cache";
+
+/// Environments whose lines are kept exactly, `%` included.
+const VERBATIM: &[&str] = &["verbatim", "verbatim*", "Verbatim", "lstlisting", "minted"];
+
+/// Figure formats pdflatex reads, by lowercase extension, and what they are
+/// normalized to.
+const FIGURE_FORMATS: &[(&str, &str)] = &[
+    ("pdf", "pdf"),
+    ("png", "png"),
+    ("jpg", "jpg"),
+    ("jpeg", "jpg"),
+    ("eps", "pdf"),
+    ("ps", "pdf"),
+    ("svg", "pdf"),
+];
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["no-verify", "no-cache"],
+        &["target", "out", "bbl"],
+    )?;
+    match args.value("target") {
+        Some("arxiv") => {}
+        Some(other) => anyhow::bail!("Unknown target: {} (supported: arxiv)", other),
+        None => anyhow::bail!(USAGE),
+    }
+    let input = PathBuf::from(args.positional(0).context(USAGE)?);
+
+    let scratch = TempDir::new("package")?;
+    let (main, config) = match input.is_dir() {
+        true => {
+            let config = ProjectConfig::find(&input)?.unwrap_or_default();
+            let main = match &config.main {
+                Some(main) => input.join(main),
+                None => batch::find_main_document(&input)?,
+            };
+            (main, config)
+        }
+        false => (input.clone(), ProjectConfig::default()),
+    };
+    let account = accounts::chosen().for_project(config.account.as_deref())?;
+    if let Some(engine @ ("xelatex" | "lualatex")) = config.engine.as_deref() {
+        eprintln!(
+            "Warning: the project is compiled with {}, arXiv uses pdflatex",
+            engine
+        );
+    }
+    let options = PackOptions::from_config(&config)?;
+    let packed = pack::pack_project_with(&main, scratch.path(), &options)?;
+    let root = scratch.path().join("sources");
+    pack::extract_archive(&packed, &root)?;
+
+    let name = main
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("Invalid file name")?;
+    let stem = main
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid file name")?;
+    let bbl = match args.value("bbl") {
+        Some(path) => PathBuf::from(path),
+        None => main.with_extension("bbl"),
+    };
+    let output = match args.value("out") {
+        Some(out) => PathBuf::from(out),
+        None => main.with_file_name(format!("{}-arxiv.zip", stem)),
+    };
+
+    let submission = Submission::build(&root, name, &bbl)?;
+    submission.write(&output)?;
+    println!("Archive saved to: {}", output.display());
+
+    if args.flag("no-verify") {
+        return Ok(());
+    }
+    println!("Verifying: compiling the archive with pdflatex...");
+    let runner = Runner::new(api::build_client()?, !args.flag("no-cache"))?;
+    let mut job = Job::new(&output, output.parent().unwrap_or(Path::new("")))?;
+    job.account = account;
+    job.options = CompileOptions {
+        engine: Some("pdflatex".to_string()),
+        profile: config.profile,
+        ..CompileOptions::default()
+    };
+    runner
+        .run(&job, "")
+        .await
+        .result
+        .context("The archive does not compile; it is not ready for submission")?;
+    Ok(())
+}
+
+/// The files of the archive, by name.
+struct Submission {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Submission {
+    /// Flattens `main` in the extracted sources under `root` and collects the
+    /// files it still needs.
+    fn build(root: &Path, main: &str, bbl: &Path) -> Result<Self> {
+        let mut inlined = HashSet::new();
+        let mut text = String::new();
+        flatten(
+            root,
+            &root.join(main),
+            &mut inlined,
+            &mut Vec::new(),
+            &mut text,
+        )?;
+
+        let mut files = BTreeMap::new();
+        text = bibliography(&text, main, bbl, root, &mut files)?;
+        let mut figures = BTreeMap::new();
+        text = normalize_figures(&text, root, &mut figures)?;
+        if !text
+            .lines()
+            .take(5)
+            .any(|line| line.contains("\\pdfoutput"))
+        {
+            // arXiv guesses between latex and pdflatex otherwise.
+            text.insert_str(0, "\\pdfoutput=1\n");
+        }
+        files.insert(main.to_string(), text.into_bytes());
+
+        let mut pending = vec![root.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(&dir)
+                .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+            {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let name = archive_name(root, &path)?;
+                let extension = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .map(str::to_ascii_lowercase);
+                if inlined.contains(&path)
+                    || figures.contains_key(&name)
+                    || extension.as_deref() == Some("bib")
+                    || files.contains_key(&name)
+                {
+                    continue;
+                }
+                let contents = fs::read(&path)
+                    .with_context(|| format!("Failed to read file: {}", path.display()))?;
+                files.insert(name, contents);
+            }
+        }
+        files.extend(figures.into_values());
+        Ok(Self { files })
+    }
+
+    fn write(&self, output: &Path) -> Result<()> {
+        if let Some(parent) = output.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let file = File::create(output)
+            .with_context(|| format!("Failed to create archive: {}", output.display()))?;
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, contents) in &self.files {
+            zip.start_file(name.as_str(), options)?;
+            zip.write_all(contents)?;
+        }
+        zip.finish().context("Failed to write archive")?;
+        Ok(())
+    }
+}
+
+fn archive_name(root: &Path, path: &Path) -> Result<String> {
+    let name = path
+        .strip_prefix(root)
+        .with_context(|| format!("{} is outside {}", path.display(), root.display()))?;
+    Ok(name.to_string_lossy().replace('\\', "/"))
+}
+
+/// Appends `file` to `out` with comments stripped and every `\input` and
+/// `\include` replaced by the file it reads, recording the inlined files.
+/// `open` holds the files being flattened, which `file` is read from.
+fn flatten(
+    root: &Path,
+    file: &Path,
+    inlined: &mut HashSet<PathBuf>,
+    open: &mut Vec<PathBuf>,
+    out: &mut String,
+) -> Result<()> {
+    let text = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read file: {}", file.display()))?;
+    open.push(file.to_path_buf());
+    let mut verbatim: Option<&str> = None;
+    for line in text.lines() {
+        if let Some(environment) = verbatim {
+            out.push_str(line);
+            out.push('\n');
+            if line.contains(&format!("\\end{{{}}}", environment)) {
+                verbatim = None;
+            }
+            continue;
+        }
+        let code = strip_comment(line);
+        if code.len() < line.len() && code.trim().is_empty() {
+            // A comment line contributes nothing, not even a space.
+            continue;
+        }
+        verbatim = VERBATIM
+            .iter()
+            .copied()
+            .find(|environment| code.contains(&format!("\\begin{{{}}}", environment)));
+        if verbatim.is_some() {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        if code.trim_start().starts_with("\\includeonly") {
+            eprintln!("Warning: dropping \\includeonly, every chapter is flattened");
+            continue;
+        }
+
+        let mut rest = code;
+        let mut inlined_here = false;
+        while let Some((start, end, command, target)) = next_input(rest) {
+            let path = [root.join(format!("{}.tex", target)), root.join(target)]
+                .into_iter()
+                .find(|path| path.is_file());
+            let Some(path) = path else {
+                out.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            };
+            out.push_str(&rest[..start]);
+            if !rest[..start].trim().is_empty() {
+                out.push('\n');
+            }
+            if let Some(cycle) = open.iter().position(|open| *open == path) {
+                let chain: Vec<String> = open[cycle..]
+                    .iter()
+                    .chain([&path])
+                    .map(|file| {
+                        archive_name(root, file).unwrap_or_else(|_| file.display().to_string())
+                    })
+                    .collect();
+                anyhow::bail!("\\input cycle: {}", chain.join(" -> "));
+            }
+            if command == "\\include" {
+                out.push_str("\\clearpage\n");
+            }
+            inlined.insert(path.clone());
+            flatten(root, &path, inlined, open, out)?;
+            if command == "\\include" {
+                out.push_str("\\clearpage\n");
+            }
+            rest = &rest[end..];
+            inlined_here = true;
+        }
+        if inlined_here && rest.trim().is_empty() {
+            // The inlined file already ended the line; another break would
+            // start a paragraph.
+            continue;
+        }
+        out.push_str(rest);
+        if code.len() < line.len() {
+            // Keeps the comment's swallowing of the line break.
+            out.push('%');
+        }
+        out.push('\n');
+    }
+    open.pop();
+    Ok(())
+}
+
+/// Drops the comment of `line`, if it has one. Unlike
+/// [`deps::strip_comment`], a `%` in the argument of `\url`, `\href` or
+/// `\verb` is part of the text (`\url{http://x.org/a%20b}`), and so is an
+/// escaped one after `\\`.
+fn strip_comment(line: &str) -> &str {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => return &line[..i],
+            b'\\' => {
+                let name = bytes[i + 1..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_alphabetic())
+                    .count();
+                if name == 0 {
+                    // A control symbol: `\%`, `\\`, `\{`.
+                    i += 2;
+                    continue;
+                }
+                let command = &line[i + 1..i + 1 + name];
+                i += 1 + name;
+                match command {
+                    "verb" => {
+                        if bytes.get(i) == Some(&b'*') {
+                            i += 1;
+                        }
+                        if let Some(&delimiter) = bytes.get(i).filter(|b| b.is_ascii()) {
+                            i = match bytes[i + 1..].iter().position(|&b| b == delimiter) {
+                                Some(end) => i + end + 2,
+                                None => bytes.len(),
+                            };
+                        }
+                    }
+                    "url" | "href" => {
+                        let spaces = bytes[i..]
+                            .iter()
+                            .take_while(|b| b.is_ascii_whitespace())
+                            .count();
+                        if bytes.get(i + spaces) == Some(&b'{') {
+                            let mut depth = 0;
+                            let mut j = i + spaces;
+                            while j < bytes.len() {
+                                match bytes[j] {
+                                    b'{' => depth += 1,
+                                    b'}' => {
+                                        depth -= 1;
+                                        if depth == 0 {
+                                            break;
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                                j += 1;
+                            }
+                            i = j + 1;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    line
+}
+
+/// The next `\input{...}` or `\include{...}` in `line`: its byte range, the
+/// command and the file it names.
+fn next_input(line: &str) -> Option<(usize, usize, &'static str, &str)> {
+    let mut offset = 0;
+    while let Some(pos) = line[offset..].find('\\') {
+        let start = offset + pos;
+        offset = start + 1;
+        for command in ["\\include", "\\input"] {
+            let Some(after) = line[start..].strip_prefix(command) else {
+                continue;
+            };
+            if after.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                continue;
+            }
+            let trimmed = after.trim_start();
+            let Some(body) = trimmed.strip_prefix('{') else {
+                continue;
+            };
+            let close = body.find('}')?;
+            let end = line.len() - body.len() + close + 1;
+            return Some((start, end, command, body[..close].trim()));
+        }
+    }
+    None
+}
+
+/// Replaces `\bibliography{...}` by the contents of `bbl`, or for biblatex
+/// ships it as the main file's `.bbl`.
+fn bibliography(
+    text: &str,
+    main: &str,
+    bbl: &Path,
+    root: &Path,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<String> {
+    let bibtex = text
+        .lines()
+        .any(|line| !deps::command_arguments(line, "\\bibliography").is_empty());
+    let biblatex = text.contains("\\addbibresource") || text.contains("\\printbibliography");
+    if !bibtex && !biblatex {
+        return Ok(text.to_string());
+    }
+    let contents = fs::read_to_string(bbl).with_context(|| {
+        format!(
+            "The bibliography needs {}: build the document locally with BibTeX or Biber, or pass --bbl",
+            bbl.display()
+        )
+    })?;
+    warn_if_stale(bbl, root)?;
+
+    if biblatex {
+        eprintln!("Note: biblatex .bbl files only work with the biblatex version that wrote them");
+        let name = Path::new(main).with_extension("bbl");
+        files.insert(name.to_string_lossy().into_owned(), contents.into_bytes());
+        return Ok(text.to_string());
+    }
+    let mut result = String::new();
+    for line in text.lines() {
+        let mut rest = line;
+        while let Some(start) = rest.find("\\bibliography") {
+            let after = &rest[start + "\\bibliography".len()..];
+            let (false, Some(body)) = (
+                after.starts_with(|c: char| c.is_ascii_alphabetic()),
+                after.trim_start().strip_prefix('{'),
+            ) else {
+                result.push_str(&rest[..start + "\\bibliography".len()]);
+                rest = after;
+                continue;
+            };
+            let close = body.find('}').context("Unterminated \\bibliography")?;
+            result.push_str(&rest[..start]);
+            result.push('\n');
+            result.push_str(contents.trim_end());
+            result.push('\n');
+            rest = &body[close + 1..];
+        }
+        result.push_str(rest);
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Warns when a .bib file in the sources was changed after `bbl` was written.
+fn warn_if_stale(bbl: &Path, root: &Path) -> Result<()> {
+    let Ok(written) = fs::metadata(bbl).and_then(|m| m.modified()) else {
+        return Ok(());
+    };
+    let project = bbl.parent().unwrap_or(Path::new(""));
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("bib") {
+            continue;
+        }
+        // The extracted copy is new; the original tells when it was edited.
+        let original = project.join(path.file_name().unwrap_or_default());
+        let edited = fs::metadata(&original).and_then(|m| m.modified());
+        if edited.is_ok_and(|edited| edited > written) {
+            eprintln!(
+                "Warning: {} is newer than {}; rebuild the bibliography",
+                original.display(),
+                bbl.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites every `\includegraphics` to name a PDF, PNG or JPEG file with
+/// its extension, converting figures into `figures` (by archive name, with
+/// the name they replace as the key).
+fn normalize_figures(
+    text: &str,
+    root: &Path,
+    figures: &mut BTreeMap<String, (String, Vec<u8>)>,
+) -> Result<String> {
+    let mut unsupported = Vec::new();
+    let mut result = String::new();
+    for line in text.lines() {
+        let mut rest = line;
+        for target in deps::command_arguments(line, "\\includegraphics") {
+            let Some(pos) = rest.find("\\includegraphics").and_then(|command| {
+                let braced = format!("{{{}}}", target);
+                rest[command..].find(&braced).map(|pos| command + pos)
+            }) else {
+                continue;
+            };
+            let replacement = match figure(root, target.trim(), figures) {
+                Ok(Some(name)) => name,
+                Ok(None) => target.to_string(),
+                Err(error) => {
+                    unsupported.push(format!("{}: {:#}", target, error));
+                    target.to_string()
+                }
+            };
+            result.push_str(&rest[..pos + 1]);
+            result.push_str(&replacement);
+            rest = &rest[pos + 1 + target.len()..];
+        }
+        result.push_str(rest);
+        result.push('\n');
+    }
+    if !unsupported.is_empty() {
+        anyhow::bail!(
+            "Convert these figures to PDF, PNG or JPEG:\n  {}",
+            unsupported.join("\n  ")
+        );
+    }
+    Ok(result)
+}
+
+/// Normalizes the figure `target` names and returns its new name, or `None`
+/// when it is not in the sources.
+fn figure(
+    root: &Path,
+    target: &str,
+    figures: &mut BTreeMap<String, (String, Vec<u8>)>,
+) -> Result<Option<String>> {
+    let candidates = std::iter::once(root.join(target)).chain(
+        FIGURE_FORMATS
+            .iter()
+            .flat_map(|(extension, _)| [extension.to_string(), extension.to_ascii_uppercase()])
+            .map(|extension| root.join(target).with_extension(extension)),
+    );
+    let Some(path) = candidates.into_iter().find(|path| path.is_file()) else {
+        return Ok(None);
+    };
+    let original = archive_name(root, &path)?;
+    if let Some((name, _)) = figures.get(&original) {
+        return Ok(Some(name.clone()));
+    }
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let normalized = FIGURE_FORMATS
+        .iter()
+        .find(|(from, _)| *from == extension)
+        .map(|(_, to)| *to)
+        .with_context(|| format!("pdflatex cannot read .{} files", extension))?;
+    let name = Path::new(&original)
+        .with_extension(normalized)
+        .to_string_lossy()
+        .into_owned();
+    let contents = match extension.as_str() {
+        "eps" | "ps" => convert(&path, "epstopdf", |output| {
+            vec![format!("--outfile={}", output.display())]
+        })?,
+        "svg" => convert(&path, "rsvg-convert", |output| {
+            vec![
+                "-f".to_string(),
+                "pdf".to_string(),
+                "-o".to_string(),
+                output.display().to_string(),
+            ]
+        })?,
+        _ => fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?,
+    };
+    figures.insert(original, (name.clone(), contents));
+    Ok(Some(name))
+}
+
+/// Runs `tool` on `input` with the arguments `options` gives for a scratch
+/// output file and returns the PDF it wrote.
+fn convert(input: &Path, tool: &str, options: impl Fn(&Path) -> Vec<String>) -> Result<Vec<u8>> {
+    let scratch = TempDir::new("figure")?;
+    let output = scratch.path().join("figure.pdf");
+    let status = Command::new(tool)
+        .args(options(&output))
+        .arg(input)
+        .status()
+        .with_context(|| format!("{} is not installed", tool))?;
+    if !status.success() {
+        anyhow::bail!("{} failed", tool);
+    }
+    fs::read(&output).with_context(|| format!("{} wrote no PDF", tool))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("chemtex-package-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        for (name, contents) in files {
+            fs::write(root.join(name), contents).unwrap();
+        }
+        root
+    }
+
+    fn flattened(root: &Path) -> Result<String> {
+        let mut out = String::new();
+        let result = flatten(
+            root,
+            &root.join("main.tex"),
+            &mut HashSet::new(),
+            &mut Vec::new(),
+            &mut out,
+        );
+        fs::remove_dir_all(root).unwrap();
+        result.map(|()| out)
+    }
+
+    #[test]
+    fn input_cycles_are_reported() {
+        let root = project(
+            "cycle",
+            &[
+                ("main.tex", "\\input{b}\n"),
+                ("b.tex", "B\n\\input{c}\n"),
+                ("c.tex", "C\n\\input{b}\n"),
+            ],
+        );
+        let error = flattened(&root).unwrap_err().to_string();
+        assert_eq!(error, "\\input cycle: b.tex -> c.tex -> b.tex");
+
+        // Reading a file twice is no cycle.
+        let root = project(
+            "twice",
+            &[("main.tex", "\\input{b}\n\\input{b}\n"), ("b.tex", "B\n")],
+        );
+        assert_eq!(flattened(&root).unwrap(), "B\nB\n");
+    }
+
+    #[test]
+    fn percent_signs_in_urls_are_not_comments() {
+        let root = project(
+            "url",
+            &[(
+                "main.tex",
+                "See \\url{http://x.org/a%20b} and \\href{http://x.org/%7E}{home}. % note\n\
+                 \\verb|50%| off, 5\\% more\\\\% gone\n",
+            )],
+        );
+        assert_eq!(
+            flattened(&root).unwrap(),
+            "See \\url{http://x.org/a%20b} and \\href{http://x.org/%7E}{home}. %\n\
+             \\verb|50%| off, 5\\% more\\\\%\n"
+        );
+    }
+}
@@ -0,0 +1,366 @@
+//! File names on their way between the local file system, zip archives and
+//! the server: non-ASCII names (`конспект.tex`), names Windows reserves, long
+//! Windows paths, and the ASCII name a file is uploaded under.
+
+use anyhow::{Context, Result};
+use std::path::{Component, Path, PathBuf};
+
+/// Names Windows refuses for files, whatever their extension.
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows does not allow in file names.
+const WINDOWS_INVALID: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Paths longer than this need the `\\?\` prefix on Windows.
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Russian and Ukrainian letters, for [`upload_name`].
+const CYRILLIC: &[(char, &str)] = &[
+    ('а', "a"),
+    ('б', "b"),
+    ('в', "v"),
+    ('г', "g"),
+    ('д', "d"),
+    ('е', "e"),
+    ('ё', "yo"),
+    ('ж', "zh"),
+    ('з', "z"),
+    ('и', "i"),
+    ('й', "y"),
+    ('к', "k"),
+    ('л', "l"),
+    ('м', "m"),
+    ('н', "n"),
+    ('о', "o"),
+    ('п', "p"),
+    ('р', "r"),
+    ('с', "s"),
+    ('т', "t"),
+    ('у', "u"),
+    ('ф', "f"),
+    ('х', "kh"),
+    ('ц', "ts"),
+    ('ч', "ch"),
+    ('ш', "sh"),
+    ('щ', "shch"),
+    ('ъ', ""),
+    ('ы', "y"),
+    ('ь', ""),
+    ('э', "e"),
+    ('ю', "yu"),
+    ('я', "ya"),
+    ('і', "i"),
+    ('ї', "yi"),
+    ('є', "ye"),
+    ('ґ', "g"),
+    ('ў', "u"),
+];
+
+/// Base letters and combining marks that compose into one character, as
+/// macOS stores `й` and `ё` decomposed (NFD) while sources use the composed
+/// form (NFC).
+const COMPOSITIONS: &[(char, char, char)] = &[
+    ('и', '\u{306}', 'й'),
+    ('И', '\u{306}', 'Й'),
+    ('е', '\u{308}', 'ё'),
+    ('Е', '\u{308}', 'Ё'),
+    ('і', '\u{308}', 'ї'),
+    ('І', '\u{308}', 'Ї'),
+    ('у', '\u{306}', 'ў'),
+    ('У', '\u{306}', 'Ў'),
+    ('a', '\u{301}', 'á'),
+    ('e', '\u{301}', 'é'),
+    ('i', '\u{301}', 'í'),
+    ('o', '\u{301}', 'ó'),
+    ('u', '\u{301}', 'ú'),
+    ('a', '\u{308}', 'ä'),
+    ('o', '\u{308}', 'ö'),
+    ('u', '\u{308}', 'ü'),
+    ('A', '\u{308}', 'Ä'),
+    ('O', '\u{308}', 'Ö'),
+    ('U', '\u{308}', 'Ü'),
+    ('e', '\u{300}', 'è'),
+    ('a', '\u{300}', 'à'),
+    ('n', '\u{303}', 'ñ'),
+    ('c', '\u{327}', 'ç'),
+];
+
+/// The file name of `path` as Unicode, which every name on Windows and any
+/// sanely named file elsewhere is; names that are not fail with a message
+/// instead of being mangled.
+pub fn file_name(path: &Path) -> Result<&str> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} has no file name", path.display()))?;
+    name.to_str().with_context(|| {
+        format!(
+            "The file name of {} is not valid Unicode; rename the file",
+            path.display()
+        )
+    })
+}
+
+/// The name a file is uploaded under: printable ASCII, with Cyrillic
+/// transliterated (`конспект.tex` → `konspekt.tex`) and anything else
+/// replaced by `_`, so the multipart part needs no percent-encoding the
+/// server might not decode. The extension is kept.
+pub fn upload_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in compose(name).chars() {
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+            out.push(c);
+        } else if let Some((_, latin)) = CYRILLIC.iter().find(|(cyrillic, _)| *cyrillic == lower) {
+            if c.is_uppercase() {
+                let mut letters = latin.chars();
+                if let Some(first) = letters.next() {
+                    out.push(first.to_ascii_uppercase());
+                    out.extend(letters);
+                }
+            } else {
+                out.push_str(latin);
+            }
+        } else if let Some((base, _, _)) = COMPOSITIONS
+            .iter()
+            .find(|(base, _, composed)| *composed == c && base.is_ascii())
+        {
+            out.push(*base);
+        } else {
+            out.push('_');
+        }
+    }
+    match out.rsplit_once('.') {
+        Some((stem, extension)) if stem.trim_matches('_').is_empty() => {
+            format!("document.{}", extension)
+        }
+        None if out.trim_matches('_').is_empty() => "document".to_string(),
+        _ => out,
+    }
+}
+
+/// Whether Windows refuses `name` as a file name: a reserved device name
+/// (`con.tex` too), a forbidden or control character, or a trailing dot or
+/// space.
+pub fn is_windows_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    WINDOWS_RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+        || name
+            .chars()
+            .any(|c| WINDOWS_INVALID.contains(&c) || c.is_control())
+        || name.ends_with('.')
+        || name.ends_with(' ')
+}
+
+/// `name` made usable as a file name everywhere, for names that come from
+/// outside (a task id, a job name): forbidden characters become `_` and
+/// reserved names get a `_` appended to their stem. Non-ASCII is kept.
+pub fn portable_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if WINDOWS_INVALID.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    while out.ends_with('.') || out.ends_with(' ') {
+        out.pop();
+    }
+    if out.is_empty() {
+        return "_".to_string();
+    }
+    if is_windows_reserved(&out) {
+        let stem_end = out.find('.').unwrap_or(out.len());
+        out.insert(stem_end, '_');
+    }
+    out
+}
+
+/// The name of `relative` inside a zip archive: components joined with `/`
+/// whatever the platform, decomposed letters composed so the name matches
+/// the `\input{...}` that refers to it. Fails on `..`, absolute paths and
+/// names that are not Unicode rather than storing a lossy name.
+pub fn archive_name(relative: &Path) -> Result<String> {
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => parts.push(compose(part.to_str().with_context(|| {
+                format!(
+                    "{} is not valid Unicode; rename the file",
+                    relative.display()
+                )
+            })?)),
+            Component::CurDir => {}
+            _ => anyhow::bail!("{} is not inside the project", relative.display()),
+        }
+    }
+    if parts.is_empty() {
+        anyhow::bail!("Empty archive path");
+    }
+    Ok(parts.join("/"))
+}
+
+/// `path` in a form Windows can open however long it is: absolute paths over
+/// the `MAX_PATH` limit get the `\\?\` prefix. Unchanged elsewhere.
+pub fn long_path(path: &Path) -> PathBuf {
+    if !cfg!(windows) {
+        return path.to_path_buf();
+    }
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let text = absolute.as_os_str().to_string_lossy();
+    if text.len() < WINDOWS_MAX_PATH || text.starts_with(r"\\?\") {
+        return path.to_path_buf();
+    }
+    match text.strip_prefix(r"\\") {
+        // A network share: \\server\share\... becomes \\?\UNC\server\share\...
+        Some(share) => PathBuf::from(format!(r"\\?\UNC\{}", share)),
+        None => {
+            let mut prefixed = std::ffi::OsString::from(r"\\?\");
+            prefixed.push(absolute.as_os_str());
+            PathBuf::from(prefixed)
+        }
+    }
+}
+
+/// Composes the decomposed letters of [`COMPOSITIONS`].
+fn compose(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let composed = chars.peek().and_then(|&mark| {
+            COMPOSITIONS
+                .iter()
+                .find(|(base, combining, _)| *base == c && *combining == mark)
+                .map(|(_, _, composed)| *composed)
+        });
+        match composed {
+            Some(composed) => {
+                out.push(composed);
+                chars.next();
+            }
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_names_are_ascii() {
+        let cases = [
+            ("main.tex", "main.tex"),
+            ("конспект.tex", "konspekt.tex"),
+            ("Отчёт по лабе 3.zip", "Otchyot_po_labe_3.zip"),
+            // Decomposed й (macOS) transliterates like the composed one.
+            ("кинетика\u{438}\u{306}.tex", "kinetikay.tex"),
+            ("Щёлочи.tex", "Shchyolochi.tex"),
+            ("化学.tex", "document.tex"),
+            ("résumé.tex", "resume.tex"),
+            ("a\"b;c.tex", "a_b_c.tex"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(upload_name(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn windows_reserved_names() {
+        let reserved = [
+            "CON", "con.tex", "Aux.pdf", "nul", "COM1.log", "lpt9", "a:b.tex",
+        ];
+        let allowed = [
+            "console.tex",
+            "main.tex",
+            "конспект.tex",
+            "com10.tex",
+            "aux1.tex",
+        ];
+        for name in reserved {
+            assert!(is_windows_reserved(name), "{:?}", name);
+        }
+        for name in allowed {
+            assert!(!is_windows_reserved(name), "{:?}", name);
+        }
+        assert!(is_windows_reserved("notes."));
+        assert!(is_windows_reserved("notes "));
+    }
+
+    #[test]
+    fn portable_names() {
+        let cases = [
+            ("main.pdf", "main.pdf"),
+            ("con.pdf", "con_.pdf"),
+            ("PRN", "PRN_"),
+            ("task:42/a?.pdf", "task_42_a_.pdf"),
+            ("конспект.pdf", "конспект.pdf"),
+            ("trailing. ", "trailing"),
+            ("...", "_"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(portable_name(input), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn archive_names_use_slashes_and_composed_letters() {
+        let cases = [
+            ("main.tex", "main.tex"),
+            ("chapters/kinetics.tex", "chapters/kinetics.tex"),
+            ("./figures/спектр.png", "figures/спектр.png"),
+            ("главы/\u{438}\u{306}од.tex", "главы/йод.tex"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                archive_name(Path::new(input)).unwrap(),
+                expected,
+                "{:?}",
+                input
+            );
+        }
+        assert!(archive_name(Path::new("../outside.tex")).is_err());
+        assert!(archive_name(Path::new("")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn archive_names_on_windows_use_slashes() {
+        assert_eq!(
+            archive_name(Path::new(r"chapters\kinetics.tex")).unwrap(),
+            "chapters/kinetics.tex"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_unicode_names_are_rejected() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let path = Path::new(OsStr::from_bytes(b"\xffmain.tex"));
+        assert!(file_name(path).is_err());
+        assert!(archive_name(path).is_err());
+        assert_eq!(
+            file_name(Path::new("dir/конспект.tex")).unwrap(),
+            "конспект.tex"
+        );
+    }
+
+    #[test]
+    fn long_paths_are_unchanged_off_windows() {
+        let long = "a/".repeat(200) + "main.tex";
+        if !cfg!(windows) {
+            assert_eq!(long_path(Path::new(&long)), PathBuf::from(&long));
+        }
+    }
+}
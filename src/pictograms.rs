@@ -0,0 +1,278 @@
+use std::fmt::Write as _;
+
+/// GHS hazard pictograms drawn as one-page vector PDFs, 100 × 100 points:
+/// a red-bordered diamond around a simplified black symbol.
+pub const CODES: &[&str] = &[
+    "GHS01", "GHS02", "GHS03", "GHS04", "GHS05", "GHS06", "GHS07", "GHS08", "GHS09",
+];
+
+const BLACK: &str = "0 0 0";
+const WHITE: &str = "1 1 1";
+const RED: &str = "0.9 0 0";
+
+/// The PDF for pictogram `code` (`GHS01`–`GHS09`).
+pub fn pdf(code: &str) -> Option<Vec<u8>> {
+    let mut page = Page::default();
+    page.polygon(&[(50.0, 1.0), (99.0, 50.0), (50.0, 99.0), (1.0, 50.0)]);
+    page.fill(RED);
+    page.polygon(&[(50.0, 9.0), (91.0, 50.0), (50.0, 91.0), (9.0, 50.0)]);
+    page.fill(WHITE);
+    match code {
+        "GHS01" => exploding_bomb(&mut page),
+        "GHS02" => flame(&mut page),
+        "GHS03" => flame_over_circle(&mut page),
+        "GHS04" => gas_cylinder(&mut page),
+        "GHS05" => corrosion(&mut page),
+        "GHS06" => skull(&mut page),
+        "GHS07" => exclamation_mark(&mut page),
+        "GHS08" => health_hazard(&mut page),
+        "GHS09" => environment(&mut page),
+        _ => return None,
+    }
+    Some(page.into_pdf())
+}
+
+fn exploding_bomb(page: &mut Page) {
+    page.circle(44.0, 37.0, 9.0);
+    page.fill(BLACK);
+    page.polygon(&[(50.0, 44.0), (55.0, 50.0), (53.0, 46.0)]);
+    page.fill(BLACK);
+    for (x, y) in [
+        (56.0, 72.0),
+        (66.0, 64.0),
+        (71.0, 52.0),
+        (47.0, 70.0),
+        (62.0, 44.0),
+    ] {
+        page.line(54.0, 49.0, x, y, 3.0, BLACK);
+    }
+}
+
+fn flame_shape(page: &mut Page, x: f64, y: f64, scale: f64) {
+    let p = |dx: f64, dy: f64| (x + dx * scale, y + dy * scale);
+    page.move_to(p(-14.0, 0.0));
+    page.curve_to(p(-18.0, 11.0), p(-10.0, 21.0), p(-6.0, 27.0));
+    page.curve_to(p(-5.0, 22.0), p(-3.0, 20.0), p(-2.0, 19.0));
+    page.curve_to(p(-2.0, 27.0), p(2.0, 33.0), p(6.0, 39.0));
+    page.curve_to(p(7.0, 31.0), p(10.0, 27.0), p(12.0, 23.0));
+    page.curve_to(p(16.0, 15.0), p(18.0, 7.0), p(14.0, 0.0));
+    page.fill(BLACK);
+    page.move_to(p(-6.0, 0.0));
+    page.curve_to(p(-8.0, 7.0), p(-2.0, 11.0), p(0.0, 15.0));
+    page.curve_to(p(2.0, 11.0), p(8.0, 7.0), p(6.0, 0.0));
+    page.fill(WHITE);
+}
+
+fn flame(page: &mut Page) {
+    page.rect(34.0, 27.0, 32.0, 5.0);
+    page.fill(BLACK);
+    flame_shape(page, 50.0, 34.0, 1.0);
+}
+
+fn flame_over_circle(page: &mut Page) {
+    page.rect(34.0, 25.0, 32.0, 4.0);
+    page.fill(BLACK);
+    page.circle(50.0, 39.0, 9.0);
+    page.fill(BLACK);
+    page.circle(50.0, 39.0, 5.0);
+    page.fill(WHITE);
+    flame_shape(page, 50.0, 48.0, 0.65);
+}
+
+fn gas_cylinder(page: &mut Page) {
+    // Lying at a slant around the centre.
+    let (sin, cos) = 20f64.to_radians().sin_cos();
+    let turn = |(x, y): (f64, f64)| {
+        let (dx, dy) = (x - 50.0, y - 48.0);
+        (50.0 + dx * cos - dy * sin, 48.0 + dx * sin + dy * cos)
+    };
+    page.circle_at(turn((37.0, 48.0)), 7.0);
+    page.fill(BLACK);
+    let body = [(37.0, 41.0), (62.0, 41.0), (62.0, 55.0), (37.0, 55.0)];
+    page.polygon(&body.map(turn));
+    page.fill(BLACK);
+    let neck = [(62.0, 44.5), (67.0, 44.5), (67.0, 51.5), (62.0, 51.5)];
+    page.polygon(&neck.map(turn));
+    page.fill(BLACK);
+    let valve = [(67.0, 42.0), (70.0, 42.0), (70.0, 54.0), (67.0, 54.0)];
+    page.polygon(&valve.map(turn));
+    page.fill(BLACK);
+}
+
+fn corrosion(page: &mut Page) {
+    // Two tubes pouring onto a surface and a hand.
+    page.line(30.0, 70.0, 44.0, 61.0, 4.0, BLACK);
+    page.line(70.0, 70.0, 56.0, 61.0, 4.0, BLACK);
+    for (x, y) in [(43.0, 54.0), (43.0, 47.0), (57.0, 54.0), (57.0, 47.0)] {
+        page.circle(x, y, 1.8);
+        page.fill(BLACK);
+    }
+    page.rect(28.0, 30.0, 19.0, 7.0);
+    page.fill(BLACK);
+    page.circle(40.0, 37.0, 3.0);
+    page.fill(WHITE);
+    page.rect(53.0, 30.0, 19.0, 7.0);
+    page.fill(BLACK);
+    for x in [55.0, 59.0, 63.0] {
+        page.rect(x, 37.0, 2.5, 5.0);
+        page.fill(BLACK);
+    }
+    page.circle(62.0, 33.5, 2.5);
+    page.fill(WHITE);
+}
+
+fn skull(page: &mut Page) {
+    page.circle(50.0, 58.0, 11.0);
+    page.fill(BLACK);
+    page.rect(44.0, 44.0, 12.0, 8.0);
+    page.fill(BLACK);
+    page.circle(45.5, 58.0, 3.2);
+    page.fill(WHITE);
+    page.circle(54.5, 58.0, 3.2);
+    page.fill(WHITE);
+    page.polygon(&[(50.0, 54.0), (48.0, 50.5), (52.0, 50.5)]);
+    page.fill(WHITE);
+    page.line(34.0, 29.0, 66.0, 41.0, 4.0, BLACK);
+    page.line(34.0, 41.0, 66.0, 29.0, 4.0, BLACK);
+    for (x, y) in [(33.0, 27.0), (33.0, 43.0), (67.0, 27.0), (67.0, 43.0)] {
+        page.circle(x, y, 2.8);
+        page.fill(BLACK);
+    }
+}
+
+fn exclamation_mark(page: &mut Page) {
+    page.polygon(&[(46.0, 44.0), (54.0, 44.0), (56.0, 72.0), (44.0, 72.0)]);
+    page.fill(BLACK);
+    page.circle(50.0, 35.0, 4.5);
+    page.fill(BLACK);
+}
+
+fn health_hazard(page: &mut Page) {
+    page.circle(50.0, 66.0, 5.5);
+    page.fill(BLACK);
+    page.move_to((36.0, 28.0));
+    page.line_to((36.0, 50.0));
+    page.curve_to((36.0, 56.0), (42.0, 59.0), (50.0, 59.0));
+    page.curve_to((58.0, 59.0), (64.0, 56.0), (64.0, 50.0));
+    page.line_to((64.0, 28.0));
+    page.fill(BLACK);
+    let star: Vec<(f64, f64)> = (0..16)
+        .map(|i| {
+            let angle = (i as f64 * 22.5 + 90.0).to_radians();
+            let radius = if i % 2 == 0 { 9.0 } else { 3.5 };
+            (50.0 + radius * angle.cos(), 43.0 + radius * angle.sin())
+        })
+        .collect();
+    page.polygon(&star);
+    page.fill(WHITE);
+}
+
+fn environment(page: &mut Page) {
+    page.line(30.0, 31.0, 70.0, 31.0, 2.0, BLACK);
+    page.line(38.0, 31.0, 38.0, 66.0, 2.5, BLACK);
+    page.line(38.0, 57.0, 31.0, 65.0, 2.0, BLACK);
+    page.line(38.0, 51.0, 45.0, 61.0, 2.0, BLACK);
+    page.line(38.0, 45.0, 31.0, 53.0, 2.0, BLACK);
+    page.ellipse(56.0, 38.0, 8.0, 3.5);
+    page.fill(BLACK);
+    page.polygon(&[(63.0, 38.0), (69.0, 42.0), (69.0, 34.0)]);
+    page.fill(BLACK);
+    page.circle(51.5, 38.5, 1.0);
+    page.fill(WHITE);
+}
+
+/// A PDF content stream under construction.
+#[derive(Default)]
+struct Page {
+    content: String,
+}
+
+impl Page {
+    fn move_to(&mut self, (x, y): (f64, f64)) {
+        let _ = writeln!(self.content, "{:.2} {:.2} m", x, y);
+    }
+
+    fn line_to(&mut self, (x, y): (f64, f64)) {
+        let _ = writeln!(self.content, "{:.2} {:.2} l", x, y);
+    }
+
+    fn curve_to(&mut self, a: (f64, f64), b: (f64, f64), end: (f64, f64)) {
+        let _ = writeln!(
+            self.content,
+            "{:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c",
+            a.0, a.1, b.0, b.1, end.0, end.1
+        );
+    }
+
+    fn polygon(&mut self, points: &[(f64, f64)]) {
+        self.move_to(points[0]);
+        for &point in &points[1..] {
+            self.line_to(point);
+        }
+    }
+
+    fn rect(&mut self, x: f64, y: f64, width: f64, height: f64) {
+        let _ = writeln!(self.content, "{} {} {} {} re", x, y, width, height);
+    }
+
+    fn circle(&mut self, x: f64, y: f64, radius: f64) {
+        self.ellipse(x, y, radius, radius);
+    }
+
+    fn circle_at(&mut self, (x, y): (f64, f64), radius: f64) {
+        self.circle(x, y, radius);
+    }
+
+    /// Four Bézier quarters.
+    fn ellipse(&mut self, x: f64, y: f64, rx: f64, ry: f64) {
+        const K: f64 = 0.5523;
+        self.move_to((x + rx, y));
+        self.curve_to((x + rx, y + K * ry), (x + K * rx, y + ry), (x, y + ry));
+        self.curve_to((x - K * rx, y + ry), (x - rx, y + K * ry), (x - rx, y));
+        self.curve_to((x - rx, y - K * ry), (x - K * rx, y - ry), (x, y - ry));
+        self.curve_to((x + K * rx, y - ry), (x + rx, y - K * ry), (x + rx, y));
+    }
+
+    fn line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, width: f64, colour: &str) {
+        let _ = writeln!(
+            self.content,
+            "{} w 1 J {} RG {:.2} {:.2} m {:.2} {:.2} l S",
+            width, colour, x1, y1, x2, y2
+        );
+    }
+
+    fn fill(&mut self, colour: &str) {
+        let _ = writeln!(self.content, "h {} rg f", colour);
+    }
+
+    fn into_pdf(self) -> Vec<u8> {
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 100] /Contents 4 0 R >>".to_string(),
+            format!(
+                "<< /Length {} >>\nstream\n{}endstream",
+                self.content.len(),
+                self.content
+            ),
+        ];
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            let _ = write!(pdf, "{} 0 obj\n{}\nendobj\n", i + 1, object);
+        }
+        let xref = pdf.len();
+        let _ = write!(pdf, "xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(pdf, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            pdf,
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        );
+        pdf.into_bytes()
+    }
+}
@@ -0,0 +1,323 @@
+use crate::cli::Args;
+use crate::sigfigs::round_uncertain;
+use crate::table::{self, Column};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage: chemtex plot <data.csv> [options]
+
+Prints a pgfplots figure of one CSV column against another, with an optional
+least-squares fit whose parameters and uncertainties go in the legend, e.g.
+  chemtex plot kinetics.csv --x t --y A --fit linear
+
+Columns are read as by `chemtex table`: units in the header, `V (mL)`, and
+uncertainties inline or in a `u(y)` column become error bars. In sources, a
+line %%chemtex:plot(kinetics.csv, x=t, y=A, fit=linear) is replaced by the
+figure when packing, so it follows the data file.
+
+Options:
+  --x NAME         Column on the x axis (default: the first numeric one)
+  --y NAME         Column on the y axis (default: the next numeric one)
+  --fit KIND       none (default), linear, proportional, quadratic or
+                   exponential
+  --caption TEXT   Figure caption
+  --label NAME     Label, referenced as fig:NAME
+  --delimiter C    CSV field separator";
+
+const OPTIONS: &[&str] = &["x", "y", "fit", "caption", "label", "delimiter"];
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], OPTIONS)?;
+    let path = args.positional(0).context(USAGE)?;
+    print!("{}", render(Path::new(path), &args)?);
+    Ok(())
+}
+
+/// Expands `%%chemtex:plot(data.csv, x=t, y=A, fit=linear)`, the CSV path
+/// relative to `dir`.
+pub fn directive(argument: &str, dir: &Path) -> Result<String> {
+    let (file, args) = table::directive_args(argument, &[], OPTIONS)?;
+    Ok(render(&dir.join(file), &args)?.trim_end().to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fit {
+    Linear,
+    Proportional,
+    Quadratic,
+    Exponential,
+}
+
+impl Fit {
+    fn parse(name: &str) -> Result<Option<Self>> {
+        Ok(match name {
+            "none" => None,
+            "linear" => Some(Self::Linear),
+            "proportional" => Some(Self::Proportional),
+            "quadratic" => Some(Self::Quadratic),
+            "exponential" => Some(Self::Exponential),
+            other => anyhow::bail!(
+                "Unknown fit {:?} (none, linear, proportional, quadratic, exponential)",
+                other
+            ),
+        })
+    }
+
+    /// Basis functions of the model; exponential fits `ln y`.
+    fn basis(self, x: f64) -> Vec<f64> {
+        match self {
+            Self::Linear | Self::Exponential => vec![x, 1.0],
+            Self::Proportional => vec![x],
+            Self::Quadratic => vec![x * x, x, 1.0],
+        }
+    }
+}
+
+/// Fitted parameters, in the order of [`Fit::basis`], with their standard
+/// uncertainties.
+struct Parameters {
+    values: Vec<f64>,
+    uncertainties: Vec<f64>,
+    r_squared: f64,
+}
+
+fn render(path: &Path, args: &Args) -> Result<String> {
+    let columns = table::load(path, args.value("delimiter"))?;
+    let numeric: Vec<&Column> = columns.iter().filter(|c| c.numeric).collect();
+    let pick = |option: &str, fallback: usize| -> Result<&Column> {
+        match args.value(option) {
+            Some(name) => columns.iter().find(|c| c.name == name).with_context(|| {
+                let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+                format!("No column {:?} (have: {})", name, names.join(", "))
+            }),
+            None => numeric
+                .get(fallback)
+                .copied()
+                .with_context(|| format!("{} has too few numeric columns", path.display())),
+        }
+    };
+    let x = pick("x", 0)?;
+    let y = pick("y", 1)?;
+    let points: Vec<(f64, f64, Option<f64>)> = (0..x.len())
+        .filter_map(|row| {
+            let (xv, _) = x.number(row)?;
+            let (yv, u) = y.number(row)?;
+            Some((xv, yv, u))
+        })
+        .collect();
+    if points.is_empty() {
+        anyhow::bail!("No rows with numbers in both {} and {}", x.name, y.name);
+    }
+
+    let mut out = String::from("\\begin{figure}[h]\n\\centering\n\\begin{tikzpicture}\n");
+    out.push_str(&format!(
+        "\\begin{{axis}}[xlabel={{{}}}, ylabel={{{}}}, legend pos=north west]\n",
+        x.label(),
+        y.label()
+    ));
+    let error_bars = points.iter().any(|(_, _, u)| u.is_some());
+    out.push_str(match error_bars {
+        true => {
+            "\\addplot[only marks, mark=*, error bars/.cd, y dir=both, y explicit] coordinates {\n"
+        }
+        false => "\\addplot[only marks, mark=*] coordinates {\n",
+    });
+    for (xv, yv, u) in &points {
+        match error_bars {
+            true => out.push_str(&format!(
+                "  ({}, {}) +- (0, {})\n",
+                xv,
+                yv,
+                u.unwrap_or(0.0)
+            )),
+            false => out.push_str(&format!("  ({}, {})\n", xv, yv)),
+        }
+    }
+    out.push_str("};\n");
+
+    if let Some(fit) = Fit::parse(args.value("fit").unwrap_or("none"))? {
+        let parameters = fit_points(fit, &points)?;
+        let (low, high) = points.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| {
+            (lo.min(p.0), hi.max(p.0))
+        });
+        out.push_str(&format!(
+            "\\addplot[domain={}:{}, samples=100, no markers] {{{}}};\n",
+            low,
+            high,
+            expression(fit, &parameters.values)
+        ));
+        out.push_str(&format!(
+            "\\legend{{Data, {}}}\n",
+            equation(fit, &parameters, x.symbol(), y.symbol())
+        ));
+        out.push_str(&format!("% R^2 = {:.5}\n", parameters.r_squared));
+    }
+    out.push_str("\\end{axis}\n\\end{tikzpicture}\n");
+    if let Some(caption) = args.value("caption") {
+        out.push_str(&format!("\\caption{{{}}}\n", caption));
+    }
+    if let Some(label) = args.value("label") {
+        out.push_str(&format!("\\label{{fig:{}}}\n", label));
+    }
+    out.push_str("\\end{figure}\n");
+    Ok(out)
+}
+
+/// Ordinary least squares over the basis of `fit`; the exponential model
+/// `y = A e^{kx}` is fitted as `ln y = kx + ln A`.
+fn fit_points(fit: Fit, points: &[(f64, f64, Option<f64>)]) -> Result<Parameters> {
+    let mut samples = Vec::with_capacity(points.len());
+    for &(x, y, _) in points {
+        let y = match fit {
+            Fit::Exponential if y <= 0.0 => {
+                anyhow::bail!("An exponential fit needs positive y values, got {}", y)
+            }
+            Fit::Exponential => y.ln(),
+            _ => y,
+        };
+        samples.push((fit.basis(x), y));
+    }
+    let count = fit.basis(0.0).len();
+    if samples.len() <= count {
+        anyhow::bail!("The fit needs more than {} points", count);
+    }
+
+    // Normal equations: (XᵀX) β = Xᵀy.
+    let mut normal = vec![vec![0.0; count]; count];
+    let mut rhs = vec![0.0; count];
+    for (row, y) in &samples {
+        for i in 0..count {
+            rhs[i] += row[i] * y;
+            for j in 0..count {
+                normal[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    let inverse = invert(normal).context("The x values do not determine the fit")?;
+    let values: Vec<f64> = inverse
+        .iter()
+        .map(|row| row.iter().zip(&rhs).map(|(a, b)| a * b).sum())
+        .collect();
+
+    let predict = |row: &[f64]| -> f64 { row.iter().zip(&values).map(|(a, b)| a * b).sum() };
+    let residual: f64 = samples
+        .iter()
+        .map(|(row, y)| (y - predict(row)).powi(2))
+        .sum();
+    let mean = samples.iter().map(|(_, y)| y).sum::<f64>() / samples.len() as f64;
+    let total: f64 = samples.iter().map(|(_, y)| (y - mean).powi(2)).sum();
+    let variance = residual / (samples.len() - count) as f64;
+    let mut uncertainties: Vec<f64> = (0..count)
+        .map(|i| (variance * inverse[i][i]).sqrt())
+        .collect();
+
+    let mut values = values;
+    if fit == Fit::Exponential {
+        // ln A → A, with u(A) = A·u(ln A).
+        values[1] = values[1].exp();
+        uncertainties[1] *= values[1];
+    }
+    Ok(Parameters {
+        values,
+        uncertainties,
+        r_squared: if total > 0.0 {
+            1.0 - residual / total
+        } else {
+            1.0
+        },
+    })
+}
+
+/// Gauss–Jordan inversion; `None` for a singular matrix.
+fn invert(mut matrix: Vec<Vec<f64>>) -> Option<Vec<Vec<f64>>> {
+    let size = matrix.len();
+    let mut inverse: Vec<Vec<f64>> = (0..size)
+        .map(|i| (0..size).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    for column in 0..size {
+        let pivot = (column..size)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        if matrix[pivot][column].abs() < 1e-12 {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = matrix[column][column];
+        for j in 0..size {
+            matrix[column][j] /= scale;
+            inverse[column][j] /= scale;
+        }
+        for row in 0..size {
+            if row == column {
+                continue;
+            }
+            let factor = matrix[row][column];
+            for j in 0..size {
+                matrix[row][j] -= factor * matrix[column][j];
+                inverse[row][j] -= factor * inverse[column][j];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+/// The fitted curve in pgfplots' math syntax.
+fn expression(fit: Fit, values: &[f64]) -> String {
+    match fit {
+        Fit::Linear => format!("{:e}*x + {:e}", values[0], values[1]),
+        Fit::Proportional => format!("{:e}*x", values[0]),
+        Fit::Quadratic => format!("{:e}*x^2 + {:e}*x + {:e}", values[0], values[1], values[2]),
+        Fit::Exponential => format!("{:e}*exp({:e}*x)", values[1], values[0]),
+    }
+}
+
+/// The fitted equation for the legend, each parameter rounded to its
+/// uncertainty: `$A = (\num{0.123 \pm 0.002})\,t + \num{0.004 \pm 0.001}$`.
+fn equation(fit: Fit, parameters: &Parameters, x: String, y: String) -> String {
+    let term = |i: usize| {
+        let (value, uncertainty) =
+            round_uncertain(parameters.values[i], parameters.uncertainties[i]);
+        format!("(\\num{{{} \\pm {}}})", value, uncertainty)
+    };
+    let right = match fit {
+        Fit::Linear => format!("{}\\,{} + {}", term(0), x, term(1)),
+        Fit::Proportional => format!("{}\\,{}", term(0), x),
+        Fit::Quadratic => format!(
+            "{}\\,{x}^2 + {}\\,{x} + {}",
+            term(0),
+            term(1),
+            term(2),
+            x = x
+        ),
+        Fit::Exponential => format!("{}\\,e^{{{}\\,{}}}", term(1), term(0), x),
+    };
+    // The legend is a comma-separated list, so the entry is braced.
+    format!("{{${} = {}$}}", y, right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn linear_fits_print_their_parameters_with_uncertainties() {
+        let dir = std::env::temp_dir().join(format!("chemtex-plot-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("kinetics.csv"),
+            "t (s),A\n0,0.10\n1,0.31\n2,0.49\n3,0.70\n4,0.90\n",
+        )
+        .unwrap();
+        let figure = directive("kinetics.csv, x=t, y=A, fit=linear", &dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let figure = figure.unwrap();
+        assert!(figure.contains("\\begin{axis}[xlabel={$t$ / \\si{s}}, ylabel={$A$}"));
+        assert!(figure.contains("  (3, 0.7)\n"));
+        assert!(figure.contains(
+            "\\legend{Data, {$A = (\\num{0.199 \\pm 0.003})\\,t + (\\num{0.102 \\pm 0.006})$}}"
+        ));
+        assert!(figure.contains("% R^2 = 0.99952"));
+    }
+}
@@ -0,0 +1,243 @@
+//! Pipeline plugins loaded from dynamic libraries (`--features plugins`).
+//!
+//! A plugin is a `cdylib` exporting a C ABI:
+//!
+//! ```c
+//! uint32_t chemtex_plugin_abi_version(void);           // must return 1
+//! const char *chemtex_plugin_name(void);
+//! // Optional packing filter: return a new buffer (freed with
+//! // chemtex_plugin_free) or NULL to keep the contents unchanged.
+//! uint8_t *chemtex_filter_upload(const char *file_name, const uint8_t *data,
+//!                                size_t len, size_t *out_len);
+//! void chemtex_plugin_free(uint8_t *data, size_t len);
+//! // Optional post-processor, may rewrite the PDF in place; non-zero fails the job.
+//! int32_t chemtex_post_process(const char *pdf_path);
+//! // Optional notifier, receives the job as report JSON.
+//! void chemtex_notify(const char *report_json);
+//! ```
+//!
+//! Plugins are loaded from `<data dir>/plugins` and from the paths listed in
+//! `CHEMTEX_PLUGINS`, in that order, and run in load order.
+
+use crate::job::JobReport;
+#[cfg(feature = "plugins")]
+use crate::report::DocumentReport;
+use anyhow::Result;
+use bytes::Bytes;
+use std::path::Path;
+
+const PLUGINS_ENV: &str = "CHEMTEX_PLUGINS";
+
+#[derive(Debug, Clone, Default)]
+pub struct Plugins {
+    #[cfg(feature = "plugins")]
+    loaded: std::sync::Arc<Vec<native::Plugin>>,
+}
+
+impl Plugins {
+    #[cfg(feature = "plugins")]
+    pub fn load_default() -> Result<Self> {
+        let mut paths = Vec::new();
+        let dir = crate::storage::data_dir()?.join("plugins");
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            let mut found: Vec<_> = entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.extension()
+                        .and_then(|e| e.to_str())
+                        .is_some_and(|e| e == std::env::consts::DLL_EXTENSION)
+                })
+                .collect();
+            found.sort();
+            paths.extend(found);
+        }
+        if let Some(list) = std::env::var_os(PLUGINS_ENV) {
+            paths.extend(std::env::split_paths(&list));
+        }
+
+        let loaded = paths
+            .iter()
+            .map(|path| native::Plugin::load(path))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            loaded: std::sync::Arc::new(loaded),
+        })
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    pub fn load_default() -> Result<Self> {
+        if std::env::var_os(PLUGINS_ENV).is_some() {
+            eprintln!(
+                "Warning: {} is set but chemtex was built without the `plugins` feature",
+                PLUGINS_ENV
+            );
+        }
+        Ok(Self::default())
+    }
+
+    /// Passes the upload through every packing filter.
+    pub fn filter_upload(&self, file_name: &str, contents: Bytes) -> Result<Bytes> {
+        #[cfg(feature = "plugins")]
+        {
+            if self.loaded.is_empty() {
+                return Ok(contents);
+            }
+            let mut contents = contents.to_vec();
+            for plugin in self.loaded.iter() {
+                contents = plugin.filter_upload(file_name, contents)?;
+            }
+            Ok(Bytes::from(contents))
+        }
+        #[cfg(not(feature = "plugins"))]
+        {
+            let _ = file_name;
+            Ok(contents)
+        }
+    }
+
+    /// Runs every post-processor on a freshly written PDF.
+    pub fn post_process(&self, pdf: &Path) -> Result<()> {
+        #[cfg(feature = "plugins")]
+        for plugin in self.loaded.iter() {
+            plugin.post_process(pdf)?;
+        }
+        #[cfg(not(feature = "plugins"))]
+        let _ = pdf;
+        Ok(())
+    }
+
+    /// Tells every notifier how a job ended.
+    pub fn notify(&self, report: &JobReport) {
+        #[cfg(feature = "plugins")]
+        if !self.loaded.is_empty() {
+            let json = serde_json::to_string(&DocumentReport::from(report)).unwrap_or_default();
+            for plugin in self.loaded.iter() {
+                plugin.notify(&json);
+            }
+        }
+        #[cfg(not(feature = "plugins"))]
+        let _ = report;
+    }
+}
+
+#[cfg(feature = "plugins")]
+mod native {
+    use anyhow::{Context, Result};
+    use libloading::{Library, Symbol};
+    use std::ffi::{c_char, CStr, CString};
+    use std::path::{Path, PathBuf};
+
+    const ABI_VERSION: u32 = 1;
+
+    type AbiVersionFn = unsafe extern "C" fn() -> u32;
+    type NameFn = unsafe extern "C" fn() -> *const c_char;
+    type FilterFn = unsafe extern "C" fn(*const c_char, *const u8, usize, *mut usize) -> *mut u8;
+    type FreeFn = unsafe extern "C" fn(*mut u8, usize);
+    type PostProcessFn = unsafe extern "C" fn(*const c_char) -> i32;
+    type NotifyFn = unsafe extern "C" fn(*const c_char);
+
+    pub struct Plugin {
+        name: String,
+        path: PathBuf,
+        library: Library,
+    }
+
+    impl std::fmt::Debug for Plugin {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("Plugin")
+                .field("name", &self.name)
+                .field("path", &self.path)
+                .finish()
+        }
+    }
+
+    impl Plugin {
+        pub fn load(path: &Path) -> Result<Self> {
+            // SAFETY: loading a library runs its initializers; plugins are
+            // trusted code installed by the user.
+            let library = unsafe { Library::new(path) }
+                .with_context(|| format!("Failed to load plugin {}", path.display()))?;
+            // SAFETY: the symbol types match the documented plugin ABI.
+            let name = unsafe {
+                let version: Symbol<AbiVersionFn> = library
+                    .get(b"chemtex_plugin_abi_version\0")
+                    .with_context(|| format!("{} is not a chemtex plugin", path.display()))?;
+                if version() != ABI_VERSION {
+                    anyhow::bail!(
+                        "{} targets plugin ABI {}, expected {}",
+                        path.display(),
+                        version(),
+                        ABI_VERSION
+                    );
+                }
+                match library.get::<NameFn>(b"chemtex_plugin_name\0") {
+                    Ok(name) if !name().is_null() => {
+                        CStr::from_ptr(name()).to_string_lossy().into_owned()
+                    }
+                    _ => path.display().to_string(),
+                }
+            };
+            Ok(Self {
+                name,
+                path: path.to_path_buf(),
+                library,
+            })
+        }
+
+        pub fn filter_upload(&self, file_name: &str, contents: Vec<u8>) -> Result<Vec<u8>> {
+            // SAFETY: see `load`; the returned buffer is copied before being
+            // handed back to the plugin's allocator.
+            unsafe {
+                let Ok(filter) = self.library.get::<FilterFn>(b"chemtex_filter_upload\0") else {
+                    return Ok(contents);
+                };
+                let free: Symbol<FreeFn> = self
+                    .library
+                    .get(b"chemtex_plugin_free\0")
+                    .with_context(|| format!("Plugin {} has no chemtex_plugin_free", self.name))?;
+                let file_name = CString::new(file_name)?;
+                let mut out_len = 0usize;
+                let out = filter(
+                    file_name.as_ptr(),
+                    contents.as_ptr(),
+                    contents.len(),
+                    &mut out_len,
+                );
+                if out.is_null() {
+                    return Ok(contents);
+                }
+                let filtered = std::slice::from_raw_parts(out, out_len).to_vec();
+                free(out, out_len);
+                Ok(filtered)
+            }
+        }
+
+        pub fn post_process(&self, pdf: &Path) -> Result<()> {
+            // SAFETY: see `load`.
+            unsafe {
+                let Ok(post_process) = self.library.get::<PostProcessFn>(b"chemtex_post_process\0")
+                else {
+                    return Ok(());
+                };
+                let path = CString::new(pdf.to_string_lossy().as_bytes())?;
+                let code = post_process(path.as_ptr());
+                if code != 0 {
+                    anyhow::bail!("Plugin {} failed to post-process PDF ({})", self.name, code);
+                }
+            }
+            Ok(())
+        }
+
+        pub fn notify(&self, report_json: &str) {
+            // SAFETY: see `load`.
+            unsafe {
+                if let (Ok(notify), Ok(json)) = (
+                    self.library.get::<NotifyFn>(b"chemtex_notify\0"),
+                    CString::new(report_json),
+                ) {
+                    notify(json.as_ptr());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+pub const DEFAULT_MAX_RPS: f64 = 2.0;
+const MIN_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_INTERVAL: Duration = Duration::from_secs(15);
+const BACKOFF_FACTOR: f64 = 1.5;
+/// Extra delay per queue position ahead of us, so deeply queued tasks are
+/// checked less often than ones about to start.
+const PER_QUEUE_POSITION: Duration = Duration::from_millis(1500);
+
+/// Shared by every task of an invocation: spaces status requests so that all
+/// tasks together never exceed `max_rps`.
+#[derive(Debug, Clone)]
+pub struct StatusPoller {
+    spacing: Duration,
+    next_slot: Arc<Mutex<Instant>>,
+}
+
+impl StatusPoller {
+    /// Fails unless `max_rps` is a positive number.
+    pub fn new(max_rps: f64) -> Result<Self> {
+        anyhow::ensure!(
+            max_rps.is_finite() && max_rps > 0.0,
+            "The request rate must be a positive number, not {}",
+            max_rps
+        );
+        let spacing = Duration::try_from_secs_f64(1.0 / max_rps)
+            .with_context(|| format!("The request rate {} is too low", max_rps))?;
+        Ok(Self::with_spacing(spacing))
+    }
+
+    fn with_spacing(spacing: Duration) -> Self {
+        Self {
+            spacing,
+            next_slot: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Waits until this caller may send a status request.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.spacing;
+            slot
+        };
+        sleep_until(slot).await;
+    }
+}
+
+impl Default for StatusPoller {
+    fn default() -> Self {
+        Self::with_spacing(Duration::from_secs_f64(1.0 / DEFAULT_MAX_RPS))
+    }
+}
+
+/// Per-task polling interval: short while the status is changing, backing
+/// off while it stays the same.
+#[derive(Debug)]
+pub struct AdaptiveInterval {
+    current: Duration,
+    last_observation: Option<(String, Option<u32>)>,
+}
+
+impl AdaptiveInterval {
+    pub fn new() -> Self {
+        Self {
+            current: MIN_INTERVAL,
+            last_observation: None,
+        }
+    }
+
+    /// Records the latest status and returns how long to wait before the next
+    /// check.
+    pub fn next(&mut self, status: &str, queue_position: Option<u32>) -> Duration {
+        let unchanged = self
+            .last_observation
+            .as_ref()
+            .is_some_and(|(last, position)| last == status && *position == queue_position);
+        if unchanged {
+            self.current = self.current.mul_f64(BACKOFF_FACTOR).min(MAX_INTERVAL);
+        } else {
+            self.current = MIN_INTERVAL;
+            self.last_observation = Some((status.to_string(), queue_position));
+        }
+
+        let queue_floor = PER_QUEUE_POSITION * queue_position.unwrap_or(0);
+        self.current.max(queue_floor).min(MAX_INTERVAL)
+    }
+}
+
+impl Default for AdaptiveInterval {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_rates_must_be_positive_numbers() {
+        assert_eq!(
+            StatusPoller::new(4.0).unwrap().spacing,
+            Duration::from_millis(250)
+        );
+        for invalid in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-300] {
+            assert!(StatusPoller::new(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{balance, elements, plot, safety, sigfigs, spectrum, table};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Directive lines in `.tex` sources, expanded when a project is packed or a
+/// single file uploaded; the files on disk keep the directive:
+///
+/// ```text
+/// %%chemtex:balance(Fe + O2 -> Fe2O3)
+/// ```
+///
+/// Lines without an argument list (`%%chemtex:aspirin`) are markers for
+/// other commands and stay as they are. Reaction schemes (`%%rxn:name`) are
+/// numbered before expansion, see [`crate::schemes`].
+const PREFIX: &str = "%%chemtex:";
+
+/// Inline element data, `\elem{Fe}{mass}`, replaced by the value.
+const ELEM: &str = "\\elem{";
+
+/// Inline measured values, `@val{0.012345 +- 0.0006 mol/L}`, rounded and
+/// typeset with siunitx.
+const VAL: &str = "@val{";
+
+/// Expands every directive line of `text`, keeping its indentation, and
+/// every inline macro. Files named by directives are relative to `dir`, the
+/// directory of the source.
+///
+/// `%%chemtex:sigfigs(N)` sets the significant figures of the `@val`s that
+/// follow it and have no uncertainty; the line itself is dropped.
+pub fn expand(text: &str, dir: &Path) -> Result<String> {
+    if !text.contains(PREFIX) && !text.contains(ELEM) && !text.contains(VAL) {
+        return Ok(text.to_string());
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut sig_figs = None;
+    for (number, line) in text.split_inclusive('\n').enumerate() {
+        let context = || format!("Line {}: {}", number + 1, line.trim());
+        let Some((name, argument)) = directive(line) else {
+            out.push_str(&expand_inline(line, sig_figs).with_context(context)?);
+            continue;
+        };
+        if name == "sigfigs" {
+            let figures = argument
+                .parse::<usize>()
+                .with_context(|| format!("Expected a count, got {:?}", argument))
+                .and_then(sigfigs::check_figures)
+                .with_context(context)?;
+            sig_figs = Some(figures);
+            continue;
+        }
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let expanded = match name {
+            "balance" => balance::balance(argument),
+            "plot" => plot::directive(argument, dir),
+            "safety" => safety::directive(argument),
+            "spectrum" => spectrum::directive(argument, dir),
+            "table" => table::directive(argument, dir),
+            other => Err(anyhow::anyhow!("Unknown directive {:?}", other)),
+        }
+        .with_context(context)?;
+        let indented: Vec<String> = expanded
+            .lines()
+            .map(|l| format!("{}{}", indent, l))
+            .collect();
+        out.push_str(&indented.join("\n"));
+        if line.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
+
+/// `%%chemtex:name(argument)` → (`name`, `argument`); the argument runs to
+/// the last `)` so it may contain parentheses itself.
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim().strip_prefix(PREFIX)?;
+    let (name, argument) = rest.split_once('(')?;
+    let argument = argument.strip_suffix(')')?;
+    Some((name.trim(), argument.trim()))
+}
+
+fn expand_inline(line: &str, sig_figs: Option<usize>) -> Result<String> {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    loop {
+        let next = [ELEM, VAL]
+            .into_iter()
+            .filter_map(|m| rest.find(m).map(|i| (i, m)))
+            .min();
+        let Some((start, name)) = next else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let after = &rest[start + name.len()..];
+        if name == VAL {
+            let (spec, tail) = after.split_once('}').context("Unclosed @val{")?;
+            out.push_str(&sigfigs::format_value(spec, sig_figs)?);
+            rest = tail;
+            continue;
+        }
+        let parsed = after.split_once('}').and_then(|(symbol, tail)| {
+            let (prop, tail) = tail.strip_prefix('{')?.split_once('}')?;
+            Some((symbol.trim(), prop.trim(), tail))
+        });
+        let Some((symbol, prop, tail)) = parsed else {
+            anyhow::bail!("Expected \\elem{{symbol}}{{property}}");
+        };
+        out.push_str(&elements::macro_value(symbol, prop)?);
+        rest = tail;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measured_values_are_rounded_to_their_uncertainty() {
+        assert_eq!(
+            expand(
+                "c = @val{0.012345 +- 0.0006 mol/L}, n = @val{3.14159}\n",
+                Path::new(".")
+            )
+            .unwrap(),
+            "c = \\SI{0.0123 \\pm 0.0006}{mol/L}, n = \\num{3.14159}\n"
+        );
+        assert_eq!(
+            expand("%%chemtex:sigfigs(2)\n@val{3.14159 g}\n", Path::new(".")).unwrap(),
+            "\\SI{3.1}{g}\n"
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>ChemTex live preview</title>
+<style>
+html, body { margin: 0; height: 100%; }
+iframe { border: 0; width: 100%; height: 100%; }
+#status { position: fixed; top: 4px; right: 8px; font: 12px sans-serif; color: #777; }
+</style>
+</head>
+<body>
+<div id="status">waiting for first build…</div>
+<iframe id="pdf" src="about:blank"></iframe>
+<script>
+const frame = document.getElementById("pdf");
+const status = document.getElementById("status");
+function load(version) {
+  frame.src = "/pdf?v=" + version;
+  status.textContent = "build " + version + " at " + new Date().toLocaleTimeString();
+}
+fetch("/version").then(r => r.text()).then(v => { if (v !== "0") load(v); });
+const events = new EventSource("/events");
+events.addEventListener("build", e => load(e.data));
+events.onerror = () => { status.textContent = "disconnected, retrying…"; };
+</script>
+</body>
+</html>
+"#;
+
+/// Serves the latest PDF of a watched document and pushes a server-sent
+/// event to open pages whenever a new build lands.
+#[derive(Clone)]
+pub struct PreviewServer {
+    state: Arc<PreviewState>,
+}
+
+struct PreviewState {
+    latest: Mutex<Option<(u64, PathBuf)>>,
+    builds: broadcast::Sender<u64>,
+}
+
+impl PreviewServer {
+    pub fn start(addr: SocketAddr) -> Result<Self> {
+        let (builds, _) = broadcast::channel(16);
+        let state = Arc::new(PreviewState {
+            latest: Mutex::new(None),
+            builds,
+        });
+
+        let service_state = Arc::clone(&state);
+        let make_service = make_service_fn(move |_| {
+            let state = Arc::clone(&service_state);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = Arc::clone(&state);
+                    async move { Ok::<_, Infallible>(state.handle(request)) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)
+            .with_context(|| format!("Failed to listen on {}", addr))?
+            .serve(make_service);
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                eprintln!("Preview server failed: {}", e);
+            }
+        });
+
+        Ok(Self { state })
+    }
+
+    /// Makes `pdf` the current preview and tells open pages to reload.
+    pub fn publish(&self, pdf: &Path) {
+        let version = {
+            let mut latest = self.state.latest.lock().unwrap_or_else(|e| e.into_inner());
+            let version = latest.as_ref().map(|(v, _)| v + 1).unwrap_or(1);
+            *latest = Some((version, pdf.to_path_buf()));
+            version
+        };
+        let _ = self.state.builds.send(version);
+    }
+}
+
+impl PreviewState {
+    fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::GET {
+            return plain(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+        }
+        match request.uri().path() {
+            "/" => Response::builder()
+                .header("Content-Type", "text/html; charset=utf-8")
+                .body(Body::from(PAGE))
+                .unwrap_or_default(),
+            "/version" => plain(StatusCode::OK, &self.version().to_string()),
+            "/pdf" => self.pdf(),
+            "/events" => self.events(),
+            _ => plain(StatusCode::NOT_FOUND, "Not found"),
+        }
+    }
+
+    fn version(&self) -> u64 {
+        let latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+        latest.as_ref().map(|(v, _)| *v).unwrap_or(0)
+    }
+
+    fn pdf(&self) -> Response<Body> {
+        let path = {
+            let latest = self.latest.lock().unwrap_or_else(|e| e.into_inner());
+            latest.as_ref().map(|(_, path)| path.clone())
+        };
+        let Some(path) = path else {
+            return plain(StatusCode::NOT_FOUND, "No build yet");
+        };
+        match std::fs::read(&path) {
+            Ok(bytes) => Response::builder()
+                .header("Content-Type", "application/pdf")
+                .header("Cache-Control", "no-store")
+                .body(Body::from(bytes))
+                .unwrap_or_default(),
+            Err(e) => plain(StatusCode::GONE, &format!("PDF unavailable: {}", e)),
+        }
+    }
+
+    fn events(&self) -> Response<Body> {
+        let (mut sender, body) = Body::channel();
+        let mut builds = self.builds.subscribe();
+        tokio::spawn(async move {
+            let mut keepalive = tokio::time::interval(KEEPALIVE_INTERVAL);
+            loop {
+                let chunk = tokio::select! {
+                    build = builds.recv() => match build {
+                        Ok(version) => format!("event: build\ndata: {}\n\n", version),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    _ = keepalive.tick() => ": keepalive\n\n".to_string(),
+                };
+                if sender.send_data(chunk.into()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Response::builder()
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .body(body)
+            .unwrap_or_default()
+    }
+}
+
+fn plain(status: StatusCode, text: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(text.to_string()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,99 @@
+use crate::redact;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Something worth telling the user about while a job runs.
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    Log(String),
+    Status {
+        status: String,
+        queue_position: Option<u32>,
+        duration_ms: Option<u64>,
+    },
+}
+
+/// Where job progress goes: printed to stdout (prefixed with the job label)
+/// by default, to stderr when stdout carries a result (`--format json`), or
+/// forwarded over a channel when stdout is reserved for a protocol such as
+/// `--stdio`.
+///
+/// Plain progress (`--plain`) is meant for screen readers and logs: a status
+/// line is printed when the status changes, not on every poll.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    channel: Option<UnboundedSender<(String, ProgressEvent)>>,
+    /// The last status announced for each label, in plain mode.
+    announced: Option<Arc<Mutex<HashMap<String, String>>>>,
+    stderr: bool,
+}
+
+impl Progress {
+    pub fn channel(sender: UnboundedSender<(String, ProgressEvent)>) -> Self {
+        Self {
+            channel: Some(sender),
+            announced: None,
+            stderr: false,
+        }
+    }
+
+    pub fn plain() -> Self {
+        Self {
+            channel: None,
+            announced: Some(Arc::default()),
+            stderr: false,
+        }
+    }
+
+    /// Prints to stderr instead of stdout.
+    pub fn on_stderr(self) -> Self {
+        Self {
+            stderr: true,
+            ..self
+        }
+    }
+
+    pub fn log(&self, label: &str, message: impl Into<String>) {
+        let message = redact::text(&message.into()).into_owned();
+        match &self.channel {
+            Some(sender) => {
+                let _ = sender.send((label.to_string(), ProgressEvent::Log(message)));
+            }
+            None if self.stderr => eprintln!("{}{}", label, message),
+            None => println!("{}{}", label, message),
+        }
+    }
+
+    /// A status line, `status | detail`, repeated on every poll; plain
+    /// progress only prints it when `status` changes.
+    pub fn status_line(&self, label: &str, status: &str, detail: &str) {
+        if let Some(announced) = &self.announced {
+            let mut announced = announced.lock().unwrap_or_else(|e| e.into_inner());
+            if announced.get(label).map(String::as_str) == Some(status) {
+                return;
+            }
+            announced.insert(label.to_string(), status.to_string());
+        }
+        self.log(label, format!("{} | {}", status, detail));
+    }
+
+    /// Structured status update; only forwarded to channels, since the
+    /// human-readable line is logged separately.
+    pub fn status(
+        &self,
+        label: &str,
+        status: &str,
+        queue_position: Option<u32>,
+        duration_ms: Option<u64>,
+    ) {
+        if let Some(sender) = &self.channel {
+            let event = ProgressEvent::Status {
+                status: status.to_string(),
+                queue_position,
+                duration_ms,
+            };
+            let _ = sender.send((label.to_string(), event));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Provenance records written next to each PDF with `--provenance`: the
+//! hashes of the sources it was built from, the compile options, the server
+//! and the TeX it ran, the task and when, and the chemtex version, so a
+//! compiled summary can be rebuilt and audited later.
+//!
+//! `report.pdf` gets `report.provenance.json`, and with `--sign` its
+//! signature (see [`crate::attest`]).
+
+use crate::api::CompileOptions;
+use crate::cache;
+use crate::deps::DependencyGraph;
+use crate::job::{JobReport, Runner};
+use crate::paths;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bumped when fields change meaning, not when they are added.
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Provenance {
+    pub format_version: u32,
+    pub tool: String,
+    pub tool_version: String,
+    pub document: String,
+    pub output: FileHash,
+    /// The main file first, then every file it includes, relative to the
+    /// main file's directory.
+    pub sources: Vec<FileHash>,
+    pub engine: Option<String>,
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// `draft` or `final`, for builds made with either flag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    pub server: String,
+    /// The banner of the compile log (`pdfTeX, Version 3.141592653-2.6-1.40.25
+    /// (TeX Live 2023)`), when the log could be fetched; the server has no
+    /// other way of telling.
+    pub tex: Option<String>,
+    pub task_id: Option<String>,
+    /// Served from the build cache rather than compiled now.
+    pub cached: bool,
+    /// Hash of what was uploaded with the compile options (the build cache
+    /// key).
+    pub build_key: Option<String>,
+    /// Unix times.
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileHash {
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+impl FileHash {
+    pub fn of(path: &Path, name: String) -> Result<Self> {
+        let contents =
+            fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
+        Ok(Self {
+            path: name,
+            sha256: cache::to_hex(&Sha256::digest(&contents)),
+            bytes: contents.len() as u64,
+        })
+    }
+}
+
+/// Where the provenance of `pdf` is written.
+pub fn path_for(pdf: &Path) -> PathBuf {
+    pdf.with_extension("provenance.json")
+}
+
+/// Writes the provenance of a successful job next to its PDF, signed when
+/// the runner has a signer.
+pub async fn write(runner: &Runner, report: &JobReport, started_at: SystemTime) -> Result<PathBuf> {
+    let output = report
+        .result
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("{:#}", e))?;
+    let job = &report.job;
+    let main = job
+        .project
+        .as_ref()
+        .map(|project| project.main.as_path())
+        .unwrap_or(&job.input);
+    let root = main.parent().unwrap_or(Path::new(""));
+    let sources = DependencyGraph::scan(main)?
+        .files
+        .iter()
+        .map(|file| FileHash::of(file, source_name(root, file)))
+        .collect::<Result<Vec<_>>>()?;
+    let tex = runner
+        .compile_log(&report.job.account, &report.details)
+        .await
+        .as_deref()
+        .and_then(tex_banner);
+    let CompileOptions {
+        engine,
+        profile,
+        priority,
+        tags,
+        mode,
+    } = job.options.clone();
+
+    let provenance = Provenance {
+        format_version: FORMAT_VERSION,
+        tool: env!("CARGO_PKG_NAME").to_string(),
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        document: job.name.clone(),
+        output: FileHash::of(output, paths::file_name(output)?.to_string())?,
+        sources,
+        engine,
+        profile,
+        priority,
+        tags,
+        mode: mode.map(|mode| mode.as_str().to_string()),
+        server: match report.details.local_log {
+            Some(_) => "local (Tectonic)".to_string(),
+            None => report.job.account.server().to_string(),
+        },
+        tex,
+        task_id: report.details.task_id.clone(),
+        cached: report.details.cached,
+        build_key: report.details.cache_key.clone(),
+        started_at: unix_secs(started_at),
+        finished_at: unix_secs(started_at + report.elapsed),
+    };
+    let path = path_for(output);
+    let json = serde_json::to_string_pretty(&provenance)?;
+    fs::write(&path, json + "\n").with_context(|| format!("Failed to write {}", path.display()))?;
+    if let Some(signer) = &runner.signer {
+        signer.sign(&path, &provenance)?;
+    }
+    Ok(path)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// `file` relative to `root`, with `/` separators.
+fn source_name(root: &Path, file: &Path) -> String {
+    file.strip_prefix(root)
+        .ok()
+        .and_then(|relative| paths::archive_name(relative).ok())
+        .unwrap_or_else(|| file.display().to_string())
+}
+
+/// The engine and version from the first line of a TeX log, `This is pdfTeX,
+/// Version ... (TeX Live 2023) (preloaded format=pdflatex ...)`.
+fn tex_banner(log: &str) -> Option<String> {
+    let line = log
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("This is "))?;
+    let banner = match line.find(" (preloaded") {
+        Some(end) => &line[..end],
+        None => line,
+    };
+    Some(banner.trim().to_string()).filter(|banner| !banner.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tex_banner_keeps_engine_and_distribution() {
+        let log = "This is pdfTeX, Version 3.141592653-2.6-1.40.25 (TeX Live 2023) \
+                   (preloaded format=pdflatex 2023.4.1)  1 MAR 2024 12:00\n\
+                   entering extended mode\n";
+        assert_eq!(
+            tex_banner(log).as_deref(),
+            Some("pdfTeX, Version 3.141592653-2.6-1.40.25 (TeX Live 2023)")
+        );
+        assert_eq!(tex_banner("! Undefined control sequence."), None);
+    }
+
+    #[test]
+    fn sources_are_named_relative_to_the_main_file() {
+        let root = Path::new("report");
+        assert_eq!(
+            source_name(root, &root.join("chapters").join("intro.tex")),
+            "chapters/intro.tex"
+        );
+        assert_eq!(
+            source_name(root, Path::new("elsewhere.tex")),
+            "elsewhere.tex"
+        );
+    }
+}
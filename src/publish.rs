@@ -0,0 +1,347 @@
+//! Publishing compiled PDFs as GitHub release assets: with `--publish
+//! github:owner/repo@tag`, every PDF a compile or batch writes is uploaded
+//! to the release of that tag, created if need be, with its provenance
+//! record and signature when there are any. Assets of the same name are
+//! replaced, so re-running a build republishes it.
+//!
+//! The token is `GITHUB_TOKEN` when set (as in Actions), otherwise the one
+//! `chemtex publish login` stored in the OS keyring, in builds with the
+//! `keyring` feature. It needs write access to the repository's contents.
+
+use crate::cli::Args;
+use crate::paths;
+use crate::provenance;
+use crate::redact;
+use anyhow::{Context, Result};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const DEFAULT_API: &str = "https://api.github.com";
+const TOKEN_ENV: &str = "GITHUB_TOKEN";
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "chemtex";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "github-token";
+
+const USAGE: &str = "\
+Usage: chemtex publish login    (reads a GitHub token on stdin)
+       chemtex publish logout
+
+Stores the token --publish github:owner/repo@tag uploads release assets
+with in the OS keyring (builds with the `keyring` feature). GITHUB_TOKEN,
+when set, is used instead. The token needs write access to the repository's
+contents; the release is created, from the default branch when the tag does
+not exist yet, if there is none.";
+
+/// Where `--publish` uploads to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub owner: String,
+    pub repo: String,
+    pub tag: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    upload_url: String,
+    html_url: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Asset {
+    id: u64,
+    name: String,
+}
+
+impl Target {
+    /// `github:owner/repo@tag`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let parse = || -> Option<Self> {
+            let rest = spec.strip_prefix("github:")?;
+            let (repository, tag) = rest.rsplit_once('@')?;
+            let (owner, repo) = repository.split_once('/')?;
+            let valid = |part: &str| !part.is_empty() && !part.contains('/');
+            (valid(owner) && valid(repo) && !tag.is_empty()).then(|| Self {
+                owner: owner.to_string(),
+                repo: repo.to_string(),
+                tag: tag.to_string(),
+            })
+        };
+        parse().with_context(|| {
+            format!(
+                "Invalid --publish target {:?} (expected github:owner/repo@tag)",
+                spec
+            )
+        })
+    }
+}
+
+/// The `--publish` target of the command line, if any.
+pub fn target(args: &Args) -> Result<Option<Target>> {
+    args.value("publish").map(Target::parse).transpose()
+}
+
+/// Uploads `pdf`, with its provenance record and signature when they
+/// exist, to the release of `target`.
+pub async fn publish(client: &reqwest::Client, target: &Target, pdf: &Path) -> Result<()> {
+    let token = token()?;
+    redact::secret(&token);
+    let api = std::env::var("GITHUB_API_URL").unwrap_or_else(|_| DEFAULT_API.to_string());
+    let github = GitHub {
+        client,
+        token: &token,
+        api: Url::parse(&api).with_context(|| format!("Invalid GITHUB_API_URL: {}", api))?,
+    };
+    let mut release = github.release(target).await?;
+
+    let provenance = provenance::path_for(pdf);
+    let signature = PathBuf::from(format!("{}.minisig", provenance.display()));
+    let files = [pdf.to_path_buf(), provenance, signature];
+    for file in files.iter().filter(|file| file.is_file()) {
+        let name = paths::file_name(file)?;
+        if let Some(index) = release.assets.iter().position(|asset| asset.name == name) {
+            let asset = release.assets.remove(index);
+            github
+                .send(
+                    Method::DELETE,
+                    github.repository(target, &["releases", "assets", &asset.id.to_string()]),
+                    "Replacing a release asset",
+                )
+                .await?;
+        }
+        let contents =
+            std::fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        let content_type = match file.extension().and_then(|e| e.to_str()) {
+            Some("pdf") => "application/pdf",
+            Some("json") => "application/json",
+            _ => "application/octet-stream",
+        };
+        // A URI template: `.../assets{?name,label}`.
+        let upload_url = release
+            .upload_url
+            .split('{')
+            .next()
+            .unwrap_or(&release.upload_url);
+        let mut url = Url::parse(upload_url).context("Invalid release upload URL")?;
+        url.query_pairs_mut().append_pair("name", name);
+        let response = github
+            .request(Method::POST, url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(contents)
+            .send()
+            .await
+            .context("Failed to upload release asset")?;
+        check(response, "Uploading a release asset").await?;
+    }
+    println!(
+        "Published {} to {}",
+        paths::file_name(pdf)?,
+        release.html_url
+    );
+    Ok(())
+}
+
+struct GitHub<'a> {
+    client: &'a reqwest::Client,
+    token: &'a str,
+    api: Url,
+}
+
+impl GitHub<'_> {
+    /// `{api}/repos/{owner}/{repo}/...`, each segment percent-encoded: tags
+    /// may hold `/`, `#` or `?`.
+    fn repository(&self, target: &Target, segments: &[&str]) -> Url {
+        let mut url = self.api.clone();
+        url.path_segments_mut()
+            .expect("the API URL is a base")
+            .pop_if_empty()
+            .extend(["repos", &target.owner, &target.repo])
+            .extend(segments);
+        url
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        self.client
+            .request(method, url)
+            .bearer_auth(self.token)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header(reqwest::header::USER_AGENT, "chemtex")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+    }
+
+    async fn send(&self, method: Method, url: Url, what: &str) -> Result<reqwest::Response> {
+        let response = self
+            .request(method, url)
+            .send()
+            .await
+            .with_context(|| format!("{} failed", what))?;
+        check(response, what).await
+    }
+
+    /// The release of the tag, created when there is none.
+    async fn release(&self, target: &Target) -> Result<Release> {
+        let response = self
+            .request(
+                Method::GET,
+                self.repository(target, &["releases", "tags", &target.tag]),
+            )
+            .send()
+            .await
+            .context("Failed to look up the release")?;
+        let response = if response.status() == StatusCode::NOT_FOUND {
+            let created = self
+                .request(Method::POST, self.repository(target, &["releases"]))
+                .json(&serde_json::json!({ "tag_name": target.tag, "name": target.tag }))
+                .send()
+                .await
+                .context("Failed to create the release")?;
+            check(created, "Creating the release").await?
+        } else {
+            check(response, "Looking up the release").await?
+        };
+        response.json().await.context("Failed to parse the release")
+    }
+}
+
+async fn check(response: reqwest::Response, what: &str) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    anyhow::bail!(
+        "{} failed with status {}: {}",
+        what,
+        status,
+        redact::text(body.trim())
+    )
+}
+
+fn token() -> Result<String> {
+    if let Ok(token) = std::env::var(TOKEN_ENV) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    keyring_token()
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_entry() -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).context("Failed to open the OS keyring")
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_token() -> Result<String> {
+    keyring_entry()?.get_password().with_context(|| {
+        format!(
+            "No GitHub token: set {} or run `chemtex publish login`",
+            TOKEN_ENV
+        )
+    })
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_token() -> Result<String> {
+    anyhow::bail!(
+        "No GitHub token: set {}, or build chemtex with `--features keyring` to keep one in \
+         the OS keyring",
+        TOKEN_ENV
+    )
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &[])?;
+    match (args.positional(0), args.positional(1)) {
+        (Some("login"), None) => login(),
+        (Some("logout"), None) => logout(),
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn login() -> Result<()> {
+    let mut token = String::new();
+    std::io::stdin()
+        .read_line(&mut token)
+        .context("Failed to read the token")?;
+    let token = token.trim();
+    anyhow::ensure!(!token.is_empty(), "No token on stdin");
+    keyring_entry()?
+        .set_password(token)
+        .context("Failed to store the token in the OS keyring")?;
+    println!("GitHub token stored in the OS keyring");
+    Ok(())
+}
+
+#[cfg(feature = "keyring")]
+fn logout() -> Result<()> {
+    match keyring_entry()?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {
+            println!("GitHub token removed from the OS keyring");
+            Ok(())
+        }
+        Err(e) => Err(e).context("Failed to remove the token from the OS keyring"),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+fn login() -> Result<()> {
+    anyhow::bail!("Storing the token needs chemtex built with `--features keyring`")
+}
+
+#[cfg(not(feature = "keyring"))]
+fn logout() -> Result<()> {
+    login()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets_name_a_repository_and_a_tag() {
+        assert_eq!(
+            Target::parse("github:chem-dept/lectures@v2026.1").unwrap(),
+            Target {
+                owner: "chem-dept".to_string(),
+                repo: "lectures".to_string(),
+                tag: "v2026.1".to_string(),
+            }
+        );
+        for invalid in [
+            "gitlab:a/b@v1",
+            "github:a@v1",
+            "github:a/b",
+            "github:a/b/c@v1",
+        ] {
+            assert!(Target::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn release_urls_encode_the_tag() {
+        let client = reqwest::Client::new();
+        let target = Target::parse("github:chem-dept/lectures@release/v1#2").unwrap();
+        for api in [
+            "https://ghe.example.org/api/v3",
+            "https://ghe.example.org/api/v3/",
+        ] {
+            let github = GitHub {
+                client: &client,
+                token: "",
+                api: Url::parse(api).unwrap(),
+            };
+            assert_eq!(
+                github
+                    .repository(&target, &["releases", "tags", &target.tag])
+                    .as_str(),
+                "https://ghe.example.org/api/v3/repos/chem-dept/lectures/releases/tags/release%2Fv1%232"
+            );
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::git;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+const BEGIN_DOCUMENT: &str = "\\begin{document}";
+
+/// The link a `--qr` value (or `qr` in `.chemtex.toml`) stands for: `commit`
+/// is the web page of the project's current git commit, anything else a URL
+/// used as it is.
+///
+/// The server's task id cannot be linked: it is assigned when the sources are
+/// uploaded, after the code has been typeset into them.
+pub fn resolve(spec: &str, dir: &Path) -> Result<String> {
+    match spec {
+        "commit" => commit_url(dir),
+        "task" => anyhow::bail!(
+            "--qr task is not possible: the task id is assigned after the sources, QR code \
+             included, are uploaded; use --qr commit or a URL"
+        ),
+        url if url.contains("://") => Ok(url.to_string()),
+        other => anyhow::bail!("--qr expects `commit` or a URL, not {}", other),
+    }
+}
+
+/// Web page of the commit checked out in `dir`, on the host of its `origin`.
+pub fn commit_url(dir: &Path) -> Result<String> {
+    let commit = git::git(dir, &["rev-parse", "HEAD"])?;
+    let remote = git::git(dir, &["remote", "get-url", "origin"])
+        .context("--qr commit needs an `origin` remote to link to")?;
+    let repository = web_url(remote.trim())
+        .with_context(|| format!("Cannot tell the web address of {}", remote.trim()))?;
+    if !git::git(dir, &["status", "--porcelain"])?.trim().is_empty() {
+        eprintln!("Warning: uncommitted changes are not in the commit the QR code links to");
+    }
+    let path = if repository.contains("gitlab") {
+        "-/commit"
+    } else if repository.contains("bitbucket") {
+        "commits"
+    } else {
+        "commit"
+    };
+    Ok(format!("{}/{}/{}", repository, path, commit.trim()))
+}
+
+/// `https://host/owner/repo` for the usual shapes of remote: https
+/// (with or without credentials), `ssh://` and scp-like `git@host:path`.
+fn web_url(remote: &str) -> Option<String> {
+    let (host, path) = match remote.split_once("://") {
+        Some(("https" | "http" | "ssh" | "git", rest)) => rest.split_once('/')?,
+        Some(_) => return None,
+        None => remote.split_once(':')?,
+    };
+    let host = host.rsplit('@').next()?;
+    // An ssh port is not the web server's.
+    let host = host.split(':').next()?;
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+    Some(format!("https://{}/{}", host, path))
+}
+
+/// Loads `qrcode` in the preamble and stamps a code for `link` in the
+/// bottom-right corner of the first page, the title page.
+pub fn inject(text: &str, link: &str) -> String {
+    let Some(pos) = text.find(BEGIN_DOCUMENT) else {
+        return text.to_string();
+    };
+    let setup = format!(
+        "\\usepackage{{qrcode}}\n\
+         \\AddToHookNext{{shipout/foreground}}{{\\put(\\dimexpr\\paperwidth-2.4cm\\relax,\
+         -\\dimexpr\\paperheight-0.8cm\\relax){{\\qrcode[height=1.6cm]{{{}}}}}}}\n",
+        escape(link)
+    );
+    format!("{}{}{}", &text[..pos], setup, &text[pos..])
+}
+
+/// Escapes the characters `qrcode` takes literally only when escaped.
+fn escape(link: &str) -> String {
+    let mut out = String::with_capacity(link.len());
+    for c in link.chars() {
+        if matches!(
+            c,
+            '#' | '$' | '&' | '^' | '_' | '~' | '%' | '\\' | '{' | '}'
+        ) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
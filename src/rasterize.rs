@@ -0,0 +1,177 @@
+use crate::api;
+use crate::job::{Job, Runner};
+use crate::pack::{self, TempDir};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const RESOLUTION: &str = "200";
+
+/// Whether pdftoppm (poppler) is installed.
+pub fn available() -> bool {
+    Command::new("pdftoppm")
+        .arg("-v")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// Compiles each snippet on a page of its own with the document's preamble
+/// and rasterizes the pages to PNG, in order. The snippets are packed as if
+/// they were in `input`, so its figures and inputs can be used.
+pub async fn snippets(
+    input: &Path,
+    preamble: &str,
+    snippets: &[String],
+    cache: bool,
+) -> Result<Vec<Vec<u8>>> {
+    let mut document =
+        String::from("\\documentclass[varwidth=12cm,border=8pt,multi=snippet]{standalone}\n");
+    for line in preamble.lines() {
+        let line = line.trim_start();
+        if ["\\documentclass", "\\title", "\\author", "\\date"]
+            .iter()
+            .any(|command| line.starts_with(command))
+        {
+            continue;
+        }
+        document.push_str(line);
+        document.push('\n');
+    }
+    if !document.contains("{mhchem}") && !document.contains("{chemmacros}") {
+        document.push_str("\\usepackage[version=4]{mhchem}\n");
+    }
+    document.push_str("\\begin{document}\n");
+    for snippet in snippets {
+        document.push_str(&format!(
+            "\\begin{{snippet}}\n{}\n\\end{{snippet}}\n",
+            snippet
+        ));
+    }
+    document.push_str("\\end{document}\n");
+
+    // Written next to the input so its figures and inputs are packed.
+    let dir = input.parent().unwrap_or(Path::new(""));
+    let stem = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let source = dir.join(format!(".{}-snippets.tex", stem));
+    fs::write(&source, document)
+        .with_context(|| format!("Failed to write file: {}", source.display()))?;
+    let scratch = TempDir::new("snippets")?;
+    let archive = pack::pack_project(&source, scratch.path());
+    let _ = fs::remove_file(&source);
+    let archive = archive?;
+
+    let runner = Runner::new(api::build_client()?, cache)?;
+    let mut job = Job::new(&archive, Path::new(""))?;
+    job.output = scratch.path().join("snippets.pdf");
+    let pdf = runner.run(&job, "").await.result?;
+
+    let prefix = scratch.path().join("snippet");
+    let status = Command::new("pdftoppm")
+        .args(["-png", "-r", RESOLUTION])
+        .arg(&pdf)
+        .arg(&prefix)
+        .status()
+        .context("Failed to run pdftoppm")?;
+    if !status.success() {
+        anyhow::bail!("pdftoppm failed on {}", pdf.display());
+    }
+    // snippet-01.png, snippet-02.png, ...: padded, so they sort by page.
+    let mut pages: Vec<PathBuf> = fs::read_dir(scratch.path())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("png"))
+        .collect();
+    pages.sort();
+    if pages.len() != snippets.len() {
+        anyhow::bail!(
+            "Expected {} rendered snippets, got {} pages",
+            snippets.len(),
+            pages.len()
+        );
+    }
+    pages
+        .iter()
+        .map(|page| fs::read(page).with_context(|| format!("Failed to read {}", page.display())))
+        .collect()
+}
+
+/// A page rendered in 8-bit grey.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrayPage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// The pages of `pdf` rendered in grey at `dpi`, in order.
+pub fn gray_pages(pdf: &Path, dpi: u32) -> Result<Vec<GrayPage>> {
+    let scratch = TempDir::new("pages")?;
+    let status = Command::new("pdftoppm")
+        .args(["-gray", "-r", &dpi.to_string()])
+        .arg(pdf)
+        .arg(scratch.path().join("page"))
+        .status()
+        .context("Failed to run pdftoppm")?;
+    if !status.success() {
+        anyhow::bail!("pdftoppm failed on {}", pdf.display());
+    }
+    let mut pages: Vec<PathBuf> = fs::read_dir(scratch.path())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("pgm"))
+        .collect();
+    pages.sort();
+    pages
+        .iter()
+        .map(|page| {
+            let data =
+                fs::read(page).with_context(|| format!("Failed to read {}", page.display()))?;
+            parse_pgm(&data).with_context(|| format!("Unexpected image: {}", page.display()))
+        })
+        .collect()
+}
+
+/// A binary PGM (`P5`) with 8-bit samples, as pdftoppm writes them.
+fn parse_pgm(data: &[u8]) -> Result<GrayPage> {
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while fields.len() < 4 {
+        while data.get(at).is_some_and(u8::is_ascii_whitespace) {
+            at += 1;
+        }
+        let start = at;
+        while data.get(at).is_some_and(|b| !b.is_ascii_whitespace()) {
+            at += 1;
+        }
+        anyhow::ensure!(at > start, "truncated header");
+        fields.push(std::str::from_utf8(&data[start..at])?);
+    }
+    anyhow::ensure!(fields[0] == "P5" && fields[3] == "255", "not an 8-bit PGM");
+    let width: usize = fields[1].parse()?;
+    let height: usize = fields[2].parse()?;
+    // One whitespace byte ends the header.
+    let pixels = data
+        .get(at + 1..at + 1 + width * height)
+        .context("truncated pixels")?;
+    Ok(GrayPage {
+        width,
+        height,
+        pixels: pixels.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pgm_header_and_pixels_are_read() {
+        let page = parse_pgm(b"P5\n3 2\n255\n\x00\x10\x20\x30\x40\xff").unwrap();
+        assert_eq!((page.width, page.height), (3, 2));
+        assert_eq!(page.pixels, [0x00, 0x10, 0x20, 0x30, 0x40, 0xff]);
+        assert!(parse_pgm(b"P5\n3 2\n255\n\x00").is_err());
+        assert!(parse_pgm(b"P6\n1 1\n255\n\x00\x00\x00").is_err());
+    }
+}
@@ -0,0 +1,558 @@
+use crate::molecule::{BondOrder, Molecule};
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+/// Pixels per bond; with the 300 dpi the PNG declares, a bond is 5 mm long.
+const BOND: f64 = 60.0;
+const MARGIN: f64 = 40.0;
+const LINE_HALF_WIDTH: f64 = 1.6;
+/// Gap between the lines of a double or triple bond.
+const OFFSET: f64 = 8.0;
+/// Bonds stop this far from a labelled atom.
+const LABEL_CLEARANCE: f64 = 18.0;
+const PIXELS_PER_METRE: u32 = 11_811;
+
+const LABEL_SCALE: usize = 4;
+const INDEX_SCALE: usize = 3;
+
+type Rgb = [u8; 3];
+
+const BLACK: Rgb = [0, 0, 0];
+
+/// CPK-like colours for heteroatom labels and their bond halves.
+fn colour(element: &str) -> Rgb {
+    match element {
+        "O" => [200, 0, 0],
+        "N" => [30, 60, 200],
+        "S" => [180, 140, 0],
+        "P" => [220, 110, 0],
+        "F" | "Cl" => [0, 150, 0],
+        "Br" => [140, 30, 30],
+        "I" => [110, 0, 150],
+        _ => BLACK,
+    }
+}
+
+/// Draws a skeletal formula and encodes it as PNG.
+pub fn png(molecule: &Molecule) -> Result<Vec<u8>> {
+    anyhow::ensure!(
+        !molecule.atoms.is_empty(),
+        "The structure has no atoms to draw"
+    );
+    let scale = BOND / molecule.bond_length();
+    let (min_x, max_x) = bounds(molecule.atoms.iter().map(|a| a.x));
+    let (min_y, max_y) = bounds(molecule.atoms.iter().map(|a| a.y));
+    // Coordinates that are not numbers come out as zero; a PNG needs a pixel.
+    let width = (((max_x - min_x) * scale + 2.0 * MARGIN).ceil() as usize).max(1);
+    let height = (((max_y - min_y) * scale + 2.0 * MARGIN).ceil() as usize).max(1);
+    let mut canvas = Canvas::new(width, height);
+    let position = |atom: usize| {
+        let a = &molecule.atoms[atom];
+        (
+            (a.x - min_x) * scale + MARGIN,
+            (max_y - a.y) * scale + MARGIN,
+        )
+    };
+    let labelled: Vec<bool> = (0..molecule.atoms.len())
+        .map(|i| {
+            let atom = &molecule.atoms[i];
+            atom.element != "C" || atom.charge != 0 || molecule.bonds_of(i).is_empty()
+        })
+        .collect();
+
+    for bond in &molecule.bonds {
+        let (a, b) = (position(bond.a), position(bond.b));
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = (dx * dx + dy * dy).sqrt().max(1e-9);
+        let (ux, uy) = (dx / length, dy / length);
+        let start = if labelled[bond.a] {
+            LABEL_CLEARANCE
+        } else {
+            0.0
+        };
+        let end = length
+            - if labelled[bond.b] {
+                LABEL_CLEARANCE
+            } else {
+                0.0
+            };
+        let at = |t: f64, offset: f64| (a.0 + ux * t - uy * offset, a.1 + uy * t + ux * offset);
+        let colours = (
+            colour(&molecule.atoms[bond.a].element),
+            colour(&molecule.atoms[bond.b].element),
+        );
+        let mut line = |from: f64, to: f64, offset: f64| {
+            canvas.split_line(at(from, offset), at(to, offset), colours);
+        };
+        match bond.order {
+            BondOrder::Single | BondOrder::Aromatic => line(start, end, 0.0),
+            BondOrder::Triple => {
+                for offset in [-OFFSET, 0.0, OFFSET] {
+                    line(start, end, offset);
+                }
+            }
+            BondOrder::Double => match inner_side(molecule, bond.a, bond.b, &position) {
+                // Inside a ring or chain: a full line plus a shorter one on
+                // the side of the neighbours.
+                Some(side) => {
+                    line(start, end, 0.0);
+                    let inset = OFFSET * 1.2;
+                    line(start.max(inset), end.min(length - inset), side * OFFSET);
+                }
+                None => {
+                    line(start, end, -OFFSET / 2.0);
+                    line(start, end, OFFSET / 2.0);
+                }
+            },
+        }
+    }
+
+    for (atom, _) in labelled.iter().enumerate().filter(|(_, &l)| l) {
+        draw_label(&mut canvas, molecule, atom, position(atom), &position);
+    }
+    Ok(canvas.encode())
+}
+
+fn bounds(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::MAX, f64::MIN), |(lo, hi), v| (lo.min(v), hi.max(v)))
+}
+
+/// Side (±1, in the bond's offset direction) on which every other neighbour
+/// of the bond's atoms lies, if they agree.
+fn inner_side(
+    molecule: &Molecule,
+    a: usize,
+    b: usize,
+    position: &dyn Fn(usize) -> (f64, f64),
+) -> Option<f64> {
+    let (pa, pb) = (position(a), position(b));
+    let (dx, dy) = (pb.0 - pa.0, pb.1 - pa.1);
+    let mut sides = Vec::new();
+    for (atom, other) in [(a, b), (b, a)] {
+        for bond in molecule.bonds_of(atom) {
+            let neighbour = molecule.bonds[bond].other(atom);
+            if neighbour == other {
+                continue;
+            }
+            let p = position(neighbour);
+            let cross = dx * (p.1 - pa.1) - dy * (p.0 - pa.0);
+            sides.push(cross.signum());
+        }
+    }
+    let first = *sides.first()?;
+    sides.iter().all(|&s| s == first).then_some(first)
+}
+
+/// Element symbol centred on the atom, hydrogens on the side away from the
+/// bonds, then the charge.
+fn draw_label(
+    canvas: &mut Canvas,
+    molecule: &Molecule,
+    atom: usize,
+    (x, y): (f64, f64),
+    position: &dyn Fn(usize) -> (f64, f64),
+) {
+    let a = &molecule.atoms[atom];
+    let colour = colour(&a.element);
+    let symbol_width = text_width(&a.element, LABEL_SCALE);
+    let top = y - 3.5 * LABEL_SCALE as f64;
+    let left = x - symbol_width / 2.0;
+    canvas.text(&a.element, left, top, LABEL_SCALE, colour);
+
+    let hydrogens = molecule.hydrogens(atom);
+    let count = if hydrogens > 1 {
+        hydrogens.to_string()
+    } else {
+        String::new()
+    };
+    let index_top = top + 3.0 * LABEL_SCALE as f64;
+    let gap = LABEL_SCALE as f64;
+    let bonds_to_right = molecule
+        .bonds_of(atom)
+        .iter()
+        .map(|&b| position(molecule.bonds[b].other(atom)).0 - x)
+        .sum::<f64>()
+        > 0.5;
+    let mut right = left + symbol_width + gap;
+    if hydrogens > 0 {
+        let width = text_width("H", LABEL_SCALE) + text_width(&count, INDEX_SCALE) + gap;
+        let h_left = if bonds_to_right {
+            left - gap - width
+        } else {
+            right
+        };
+        canvas.text("H", h_left, top, LABEL_SCALE, colour);
+        let index_left = h_left + text_width("H", LABEL_SCALE) + gap;
+        canvas.text(&count, index_left, index_top, INDEX_SCALE, colour);
+        if !bonds_to_right {
+            right += width + gap;
+        }
+    }
+    let charge = match a.charge {
+        0 => String::new(),
+        1 => "+".to_string(),
+        -1 => "-".to_string(),
+        n if n > 0 => format!("{}+", n),
+        n => format!("{}-", -n),
+    };
+    canvas.text(
+        &charge,
+        right,
+        top - 2.0 * INDEX_SCALE as f64,
+        INDEX_SCALE,
+        colour,
+    );
+}
+
+fn text_width(text: &str, scale: usize) -> f64 {
+    match text.chars().count() {
+        0 => 0.0,
+        n => (n * 6 - 1) as f64 * scale as f64,
+    }
+}
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![[255; 3]; width * height],
+        }
+    }
+
+    fn blend(&mut self, x: usize, y: usize, colour: Rgb, coverage: f64) {
+        let pixel = &mut self.pixels[y * self.width + x];
+        for (channel, target) in pixel.iter_mut().zip(colour) {
+            let value = *channel as f64 * (1.0 - coverage) + target as f64 * coverage;
+            *channel = value.round() as u8;
+        }
+    }
+
+    /// Bond line coloured by the atom at each half.
+    fn split_line(&mut self, from: (f64, f64), to: (f64, f64), (first, second): (Rgb, Rgb)) {
+        let middle = ((from.0 + to.0) / 2.0, (from.1 + to.1) / 2.0);
+        if first == second {
+            self.line(from, to, first);
+        } else {
+            self.line(from, middle, first);
+            self.line(middle, to, second);
+        }
+    }
+
+    /// Anti-aliased line: coverage falls off with the distance from the
+    /// segment.
+    fn line(&mut self, from: (f64, f64), to: (f64, f64), colour: Rgb) {
+        let reach = LINE_HALF_WIDTH + 1.0;
+        let x0 = (from.0.min(to.0) - reach).floor().max(0.0) as usize;
+        let y0 = (from.1.min(to.1) - reach).floor().max(0.0) as usize;
+        let x1 = ((from.0.max(to.0) + reach).ceil() as usize).min(self.width);
+        let y1 = ((from.1.max(to.1) + reach).ceil() as usize).min(self.height);
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let length_sq = (dx * dx + dy * dy).max(1e-9);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let t = (((px - from.0) * dx + (py - from.1) * dy) / length_sq).clamp(0.0, 1.0);
+                let (cx, cy) = (from.0 + t * dx, from.1 + t * dy);
+                let distance = ((px - cx).powi(2) + (py - cy).powi(2)).sqrt();
+                let coverage = (LINE_HALF_WIDTH + 0.5 - distance).clamp(0.0, 1.0);
+                if coverage > 0.0 {
+                    self.blend(x, y, colour, coverage);
+                }
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str, left: f64, top: f64, scale: usize, colour: Rgb) {
+        let mut x = left.round() as i64;
+        let y = top.round() as i64;
+        for c in text.chars() {
+            if let Some(rows) = glyph(c) {
+                for (row, bits) in rows.iter().enumerate() {
+                    for column in 0..5 {
+                        if bits & (0b10000 >> column) != 0 {
+                            self.fill(
+                                x + (column * scale) as i64,
+                                y + (row * scale) as i64,
+                                scale,
+                                colour,
+                            );
+                        }
+                    }
+                }
+            }
+            x += (6 * scale) as i64;
+        }
+    }
+
+    fn fill(&mut self, left: i64, top: i64, size: usize, colour: Rgb) {
+        for y in top.max(0)..(top + size as i64).min(self.height as i64) {
+            for x in left.max(0)..(left + size as i64).min(self.width as i64) {
+                self.pixels[y as usize * self.width + x as usize] = colour;
+            }
+        }
+    }
+
+    /// 8-bit RGB PNG, unfiltered rows, tagged with its resolution.
+    fn encode(&self) -> Vec<u8> {
+        let mut raw = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            raw.push(0);
+            raw.extend(row.iter().flatten());
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        // Writing into a Vec cannot fail.
+        let _ = encoder.write_all(&raw);
+        let compressed = encoder.finish().unwrap_or_default();
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        let mut header = Vec::new();
+        header.extend((self.width as u32).to_be_bytes());
+        header.extend((self.height as u32).to_be_bytes());
+        header.extend([8, 2, 0, 0, 0]);
+        chunk(&mut png, b"IHDR", &header);
+        let mut physical = Vec::new();
+        physical.extend(PIXELS_PER_METRE.to_be_bytes());
+        physical.extend(PIXELS_PER_METRE.to_be_bytes());
+        physical.push(1);
+        chunk(&mut png, b"pHYs", &physical);
+        chunk(&mut png, b"IDAT", &compressed);
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(kind);
+    png.extend(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    png.extend(hasher.finalize().to_be_bytes());
+}
+
+/// 5×7 bitmap glyphs for element symbols, counts and charges; each row's
+/// bit 4 is the leftmost pixel.
+fn glyph(c: char) -> Option<[u8; 7]> {
+    Some(match c {
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        'a' => [
+            0b00000, 0b00000, 0b01110, 0b00001, 0b01111, 0b10001, 0b01111,
+        ],
+        'b' => [
+            0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b11110,
+        ],
+        'c' => [
+            0b00000, 0b00000, 0b01110, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'd' => [
+            0b00001, 0b00001, 0b01101, 0b10011, 0b10001, 0b10001, 0b01111,
+        ],
+        'e' => [
+            0b00000, 0b00000, 0b01110, 0b10001, 0b11111, 0b10000, 0b01110,
+        ],
+        'f' => [
+            0b00110, 0b01001, 0b01000, 0b11100, 0b01000, 0b01000, 0b01000,
+        ],
+        'g' => [
+            0b00000, 0b01111, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110,
+        ],
+        'h' => [
+            0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001,
+        ],
+        'i' => [
+            0b00100, 0b00000, 0b01100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'j' => [
+            0b00010, 0b00000, 0b00110, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'k' => [
+            0b10000, 0b10000, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010,
+        ],
+        'l' => [
+            0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'm' => [
+            0b00000, 0b00000, 0b11010, 0b10101, 0b10101, 0b10001, 0b10001,
+        ],
+        'n' => [
+            0b00000, 0b00000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001,
+        ],
+        'o' => [
+            0b00000, 0b00000, 0b01110, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'p' => [
+            0b00000, 0b00000, 0b11110, 0b10001, 0b11110, 0b10000, 0b10000,
+        ],
+        'q' => [
+            0b00000, 0b00000, 0b01101, 0b10011, 0b01111, 0b00001, 0b00001,
+        ],
+        'r' => [
+            0b00000, 0b00000, 0b10110, 0b11001, 0b10000, 0b10000, 0b10000,
+        ],
+        's' => [
+            0b00000, 0b00000, 0b01110, 0b10000, 0b01110, 0b00001, 0b11110,
+        ],
+        't' => [
+            0b01000, 0b01000, 0b11100, 0b01000, 0b01000, 0b01001, 0b00110,
+        ],
+        'u' => [
+            0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b10011, 0b01101,
+        ],
+        'v' => [
+            0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'w' => [
+            0b00000, 0b00000, 0b10001, 0b10001, 0b10101, 0b10101, 0b01010,
+        ],
+        'x' => [
+            0b00000, 0b00000, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001,
+        ],
+        'y' => [
+            0b00000, 0b00000, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110,
+        ],
+        'z' => [
+            0b00000, 0b00000, 0b11111, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        '+' => [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+        '-' => [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::molecule::Atom;
+
+    #[test]
+    fn structures_without_extent_still_draw() {
+        assert!(png(&Molecule::default()).is_err());
+
+        let single = Molecule {
+            atoms: vec![Atom::new("O")],
+            bonds: Vec::new(),
+        };
+        assert!(png(&single).unwrap().starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,393 @@
+use crate::api::{self, CompileOptions};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::job::{Job, JobReport, Phase, TaskDetails};
+use crate::redact;
+use crate::storage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LAST_REPORT_FILE: &str = "last-batch.json";
+
+/// Machine-readable summary of a batch run.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchReport {
+    pub generated_at: u64,
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub documents: Vec<DocumentReport>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentStatus {
+    Ok,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DocumentReport {
+    pub name: String,
+    pub input: String,
+    pub output: String,
+    pub engine: Option<String>,
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<String>,
+    /// The job's `--tag` labels.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    pub status: DocumentStatus,
+    pub task_id: Option<String>,
+    pub elapsed_ms: u64,
+    pub compile_ms: Option<u64>,
+    pub warnings: Option<u32>,
+    pub log_url: Option<String>,
+    #[serde(default)]
+    pub cached: bool,
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_bytes: Option<u64>,
+    /// The first queue position the task was given, to estimate waits by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_position: Option<u32>,
+    /// Where the time went, to tell a slow network from a busy queue.
+    #[serde(default)]
+    pub phases: PhaseTimes,
+    /// Parsed compile log, only collected when a report format needs it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Wall-clock milliseconds spent in each phase of a job.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PhaseTimes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pack_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processing_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_ms: Option<u64>,
+}
+
+impl From<&TaskDetails> for PhaseTimes {
+    fn from(details: &TaskDetails) -> Self {
+        let ms = |phase| details.phase(phase).map(|d| d.as_millis() as u64);
+        Self {
+            pack_ms: ms(Phase::Pack),
+            upload_ms: ms(Phase::Upload),
+            queue_ms: ms(Phase::Queue),
+            processing_ms: ms(Phase::Processing),
+            download_ms: ms(Phase::Download),
+        }
+    }
+}
+
+impl BatchReport {
+    pub fn from_reports(reports: &[JobReport]) -> Self {
+        let documents: Vec<DocumentReport> = reports.iter().map(DocumentReport::from).collect();
+        let count = |status| documents.iter().filter(|d| d.status == status).count();
+        Self {
+            generated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            total: documents.len(),
+            succeeded: count(DocumentStatus::Ok),
+            failed: count(DocumentStatus::Failed),
+            cancelled: count(DocumentStatus::Cancelled),
+            documents,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read batch report: {}", path.display()))?;
+        serde_json::from_str(&text)
+            .with_context(|| format!("Failed to parse batch report: {}", path.display()))
+    }
+
+    /// Keeps a copy of the report in the data directory for `--retry-failed`.
+    pub fn save_as_last(&self) -> Result<()> {
+        self.write_json(&last_report_path()?)
+    }
+
+    /// Rebuilds the jobs that failed or were cancelled, with their original
+    /// names, outputs and options.
+    pub fn failed_jobs(&self) -> Result<Vec<Job>> {
+        self.documents
+            .iter()
+            .filter(|doc| doc.status != DocumentStatus::Ok)
+            .map(|doc| {
+                let input = PathBuf::from(&doc.input);
+                let mut job = Job::new(&input, Path::new(""))?;
+                job.name = doc.name.clone();
+                job.output = PathBuf::from(&doc.output);
+                job.options = CompileOptions {
+                    engine: doc.engine.clone(),
+                    profile: doc.profile.clone(),
+                    priority: doc.priority.clone(),
+                    tags: doc.tags.clone(),
+                    // Taken from --draft or --final of the retry.
+                    mode: None,
+                };
+                Ok(job)
+            })
+            .collect()
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize report")?;
+        write_file(path, json.as_bytes())
+    }
+
+    /// Writes a self-contained HTML page (inline styles, no external assets).
+    pub fn write_html(&self, path: &Path) -> Result<()> {
+        let mut rows = String::new();
+        for doc in &self.documents {
+            let status = match doc.status {
+                DocumentStatus::Ok => "ok",
+                DocumentStatus::Failed => "failed",
+                DocumentStatus::Cancelled => "cancelled",
+            };
+            let pdf = match doc.status {
+                DocumentStatus::Ok => format!(
+                    "<a href=\"{}\">PDF</a>",
+                    escape_html(&relative_link(path, &doc.output))
+                ),
+                _ => String::new(),
+            };
+            let log = doc
+                .log_url
+                .as_deref()
+                .map(|url| format!("<a href=\"{}\">log</a>", escape_html(url)))
+                .unwrap_or_default();
+            rows.push_str(&format!(
+                "<tr class=\"{status}\"><td>{}</td><td>{status}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{} {}</td><td>{}</td></tr>\n",
+                escape_html(&doc.name),
+                escape_html(doc.task_id.as_deref().unwrap_or("-")),
+                api::format_milliseconds(doc.elapsed_ms),
+                doc.compile_ms
+                    .map(api::format_milliseconds)
+                    .unwrap_or_else(|| "-".to_string()),
+                doc.warnings
+                    .map(|w| w.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                pdf,
+                log,
+                escape_html(doc.error.as_deref().unwrap_or("")),
+            ));
+        }
+
+        let html = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>ChemTex batch report</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 4px 8px; text-align: left; }}
+tr.ok td:nth-child(2) {{ color: #176f2c; }}
+tr.failed td:nth-child(2) {{ color: #b00020; }}
+tr.cancelled td:nth-child(2) {{ color: #777; }}
+</style>
+</head>
+<body>
+<h1>Batch report</h1>
+<p>{} document(s): {} succeeded, {} failed, {} cancelled.</p>
+<table>
+<tr><th>Document</th><th>Status</th><th>Task ID</th><th>Total time</th><th>Compile time</th><th>Warnings</th><th>Artifacts</th><th>Error</th></tr>
+{}</table>
+</body>
+</html>
+"#,
+            self.total, self.succeeded, self.failed, self.cancelled, rows
+        );
+        write_file(path, html.as_bytes())
+    }
+
+    /// Writes a JUnit XML report with one test case per document, so CI
+    /// systems (GitLab, Jenkins) show per-document results natively.
+    pub fn write_junit(&self, path: &Path) -> Result<()> {
+        let seconds = |ms: u64| format!("{:.3}", ms as f64 / 1000.0);
+        let total_ms: u64 = self.documents.iter().map(|d| d.elapsed_ms).sum();
+
+        let mut cases = String::new();
+        for doc in &self.documents {
+            cases.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"chemtex\" file=\"{}\" time=\"{}\">\n",
+                escape_xml(&doc.name),
+                escape_xml(&doc.input),
+                seconds(doc.elapsed_ms)
+            ));
+            match doc.status {
+                DocumentStatus::Ok => {}
+                DocumentStatus::Cancelled => cases.push_str(&format!(
+                    "      <skipped message=\"{}\"/>\n",
+                    escape_xml(doc.error.as_deref().unwrap_or("Cancelled"))
+                )),
+                DocumentStatus::Failed => {
+                    let errors: Vec<&Diagnostic> = doc
+                        .diagnostics
+                        .iter()
+                        .filter(|d| d.severity == Severity::Error)
+                        .collect();
+                    let message = errors
+                        .first()
+                        .map(|d| d.message.as_str())
+                        .or(doc.error.as_deref())
+                        .unwrap_or("Compilation failed");
+                    let mut details: Vec<String> =
+                        errors.iter().map(|d| describe_diagnostic(d)).collect();
+                    details.extend(doc.error.clone());
+                    cases.push_str(&format!(
+                        "      <failure type=\"CompilationError\" message=\"{}\">{}</failure>\n",
+                        escape_xml(message),
+                        escape_xml(&details.join("\n"))
+                    ));
+                }
+            }
+            let warnings: Vec<String> = doc
+                .diagnostics
+                .iter()
+                .filter(|d| d.severity == Severity::Warning)
+                .map(describe_diagnostic)
+                .collect();
+            if !warnings.is_empty() {
+                cases.push_str(&format!(
+                    "      <system-out>{}</system-out>\n",
+                    escape_xml(&warnings.join("\n"))
+                ));
+            }
+            cases.push_str("    </testcase>\n");
+        }
+
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="chemtex" tests="{total}" failures="{failed}" skipped="{skipped}" time="{time}">
+  <testsuite name="chemtex" tests="{total}" failures="{failed}" errors="0" skipped="{skipped}" time="{time}">
+{cases}  </testsuite>
+</testsuites>
+"#,
+            total = self.total,
+            failed = self.failed,
+            skipped = self.cancelled,
+            time = seconds(total_ms),
+            cases = cases,
+        );
+        write_file(path, xml.as_bytes())
+    }
+}
+
+impl From<&JobReport> for DocumentReport {
+    fn from(report: &JobReport) -> Self {
+        let status = match &report.result {
+            Ok(_) => DocumentStatus::Ok,
+            Err(_) if report.cancelled => DocumentStatus::Cancelled,
+            Err(_) => DocumentStatus::Failed,
+        };
+        Self {
+            name: report.job.name.clone(),
+            input: report.job.input.display().to_string(),
+            output: report.job.output.display().to_string(),
+            engine: report.job.options.engine.clone(),
+            profile: report.job.options.profile.clone(),
+            priority: report.job.options.priority.clone(),
+            tags: report.job.options.tags.clone(),
+            status,
+            task_id: report.details.task_id.clone(),
+            elapsed_ms: report.elapsed.as_millis() as u64,
+            compile_ms: report.details.compile_ms,
+            warnings: report.details.warnings,
+            log_url: report
+                .details
+                .log_url
+                .as_deref()
+                .map(|url| redact::text(url).into_owned()),
+            cached: report.details.cached,
+            error: report
+                .result
+                .as_ref()
+                .err()
+                .map(|e| redact::text(&format!("{:#}", e)).into_owned()),
+            upload_bytes: report.details.upload_bytes,
+            queue_position: report.details.queue_position,
+            phases: PhaseTimes::from(&report.details),
+            diagnostics: report.details.diagnostics.clone(),
+        }
+    }
+}
+
+pub fn last_report_path() -> Result<PathBuf> {
+    Ok(storage::data_dir()?.join(LAST_REPORT_FILE))
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write report: {}", path.display()))
+}
+
+/// Links `target` relative to the report's directory when possible, so the
+/// page keeps working when published together with the PDFs.
+fn relative_link(report_path: &Path, target: &str) -> String {
+    let report_dir = report_path.parent().unwrap_or(Path::new(""));
+    Path::new(target)
+        .strip_prefix(report_dir)
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| target.to_string())
+}
+
+/// `chapter2.tex:41: Undefined control sequence.`
+fn describe_diagnostic(diagnostic: &Diagnostic) -> String {
+    match (&diagnostic.file, diagnostic.line) {
+        (Some(file), Some(line)) => format!("{}:{}: {}", file, line, diagnostic.message),
+        (Some(file), None) => format!("{}: {}", file, diagnostic.message),
+        _ => diagnostic.message.clone(),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// [`escape_html`] without the characters XML 1.0 forbids even as
+/// references, such as the escape codes of colored TeX output, which would
+/// make the whole report unreadable to CI.
+fn escape_xml(text: &str) -> String {
+    let allowed = |c: &char| {
+        matches!(c, '\t' | '\n' | '\r') || (*c >= ' ' && !matches!(c, '\u{FFFE}' | '\u{FFFF}'))
+    };
+    escape_html(&text.chars().filter(allowed).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn junit_text_drops_characters_xml_forbids() {
+        assert_eq!(
+            escape_xml("\u{1b}[31m! Undefined <control> sequence\u{1b}[0m\u{0}\u{FFFF}"),
+            "[31m! Undefined &lt;control&gt; sequence[0m"
+        );
+        assert_eq!(escape_xml("line 1\n\tline 2\r"), "line 1\n\tline 2\r");
+    }
+}
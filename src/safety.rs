@@ -0,0 +1,277 @@
+use crate::cli::Args;
+use crate::md2tex::escape;
+use crate::{pictograms, table};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage: chemtex safety <substance>... [options]
+
+Prints the safety table of a lab report: GHS pictograms, signal word and
+H/P statements of each substance, from a bundled dataset, e.g.
+  chemtex safety \"sulfuric acid\" acetone > safety.tex
+
+Substances are matched by name, synonym, CAS number or formula. The
+pictograms are written as PDFs to the assets directory and referenced with
+\\includegraphics, so packing picks them up. In sources, a line
+%%chemtex:safety(sulfuric acid, acetone) is replaced by the table when
+packing, and the pictograms it needs are added to the archive.
+
+Options:
+  --assets DIR     Where to write the pictograms (default: ghs)
+  --caption TEXT   Table caption (default: Safety data)
+  --label NAME     Label, referenced as tab:NAME
+  --codes-only     Leave out the statement texts under the table
+  --list           List the bundled substances";
+
+const OPTIONS: &[&str] = &["assets", "caption", "label"];
+const FLAGS: &[&str] = &["codes-only", "list"];
+
+/// Pictograms referenced by tables from the directive, relative to the
+/// directory the document is compiled in.
+const ASSET_DIR: &str = "ghs";
+
+const DATASET: &str = include_str!("../data/safety.toml");
+
+#[derive(Debug, Deserialize)]
+struct Dataset {
+    substance: Vec<Substance>,
+    statements: BTreeMap<String, String>,
+}
+
+/// A substance's GHS classification; harmless ones have no signal word.
+#[derive(Debug, Deserialize)]
+pub struct Substance {
+    pub name: String,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub cas: Option<String>,
+    pub formula: Option<String>,
+    pub signal: Option<String>,
+    #[serde(default)]
+    pub pictograms: Vec<String>,
+    #[serde(default)]
+    pub hazards: Vec<String>,
+    #[serde(default)]
+    pub precautions: Vec<String>,
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, FLAGS, OPTIONS)?;
+    let dataset = dataset()?;
+    if args.flag("list") {
+        for substance in &dataset.substance {
+            println!(
+                "{:<36} {}",
+                substance.name,
+                substance.cas.as_deref().unwrap_or("")
+            );
+        }
+        return Ok(());
+    }
+    let names: Vec<&str> = (0..).map_while(|i| args.positional(i)).collect();
+    if names.is_empty() {
+        anyhow::bail!("{}", USAGE);
+    }
+    let substances = lookup_all(&dataset, &names)?;
+    let assets = Path::new(args.value("assets").unwrap_or(ASSET_DIR));
+    fs::create_dir_all(assets)
+        .with_context(|| format!("Failed to create directory: {}", assets.display()))?;
+    for code in used_pictograms(&substances) {
+        let path = assets.join(format!("{}.pdf", code));
+        let pdf = pictograms::pdf(code).with_context(|| format!("Unknown pictogram {}", code))?;
+        fs::write(&path, pdf)
+            .with_context(|| format!("Failed to write file: {}", path.display()))?;
+    }
+    print!(
+        "{}",
+        render(&dataset, &substances, &assets.to_string_lossy(), &args)
+    );
+    Ok(())
+}
+
+/// Expands `%%chemtex:safety(sulfuric acid, acetone, caption="...")`; the
+/// pictograms are left to [`referenced_pictograms`] at packing.
+pub fn directive(argument: &str) -> Result<String> {
+    let raw: Vec<String> = table::split_top_level(argument)
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.split_once('=') {
+            Some((name, value)) => format!("--{}={}", name.trim(), table::unquote(value.trim())),
+            None => table::unquote(&part),
+        })
+        .collect();
+    let args = Args::parse(&raw, FLAGS, &["caption", "label"])?;
+    let names: Vec<&str> = (0..).map_while(|i| args.positional(i)).collect();
+    if names.is_empty() {
+        anyhow::bail!("Expected at least one substance");
+    }
+    let dataset = dataset()?;
+    let substances = lookup_all(&dataset, &names)?;
+    Ok(render(&dataset, &substances, ASSET_DIR, &args)
+        .trim_end()
+        .to_string())
+}
+
+/// The pictograms a packed `.tex` file shows from the default assets
+/// directory, as archive paths with their PDFs.
+pub fn referenced_pictograms(text: &str) -> Vec<(String, Vec<u8>)> {
+    pictograms::CODES
+        .iter()
+        .filter_map(|code| {
+            let path = format!("{}/{}.pdf", ASSET_DIR, code);
+            if !text.contains(&format!("{{{}}}", path)) {
+                return None;
+            }
+            Some((path, pictograms::pdf(code)?))
+        })
+        .collect()
+}
+
+fn dataset() -> Result<Dataset> {
+    toml::from_str(DATASET).context("Bundled safety data is invalid")
+}
+
+fn lookup_all<'a>(dataset: &'a Dataset, names: &[&str]) -> Result<Vec<&'a Substance>> {
+    names
+        .iter()
+        .map(|name| {
+            lookup(dataset, name).with_context(|| {
+                let suggestions = suggest(dataset, name);
+                match suggestions.is_empty() {
+                    true => format!("Unknown substance {:?} (see `chemtex safety --list`)", name),
+                    false => format!(
+                        "Unknown substance {:?}; did you mean {}?",
+                        name,
+                        suggestions.join(", ")
+                    ),
+                }
+            })
+        })
+        .collect()
+}
+
+fn lookup<'a>(dataset: &'a Dataset, name: &str) -> Option<&'a Substance> {
+    let wanted = name.trim().to_lowercase();
+    dataset.substance.iter().find(|s| {
+        std::iter::once(&s.name)
+            .chain(&s.aliases)
+            .chain(&s.cas)
+            .chain(&s.formula)
+            .any(|key| key.to_lowercase() == wanted)
+    })
+}
+
+/// Substances sharing words with `name`, most shared first.
+fn suggest(dataset: &Dataset, name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    let words: Vec<&str> = name.split_whitespace().filter(|w| w.len() > 2).collect();
+    let mut scored: Vec<(usize, &str)> = dataset
+        .substance
+        .iter()
+        .map(|s| {
+            let shared = words.iter().filter(|w| s.name.contains(*w)).count();
+            (shared, s.name.as_str())
+        })
+        .filter(|&(shared, _)| shared > 0)
+        .collect();
+    scored.sort_by_key(|&(shared, _)| std::cmp::Reverse(shared));
+    scored
+        .into_iter()
+        .take(3)
+        .map(|(_, name)| format!("{:?}", name))
+        .collect()
+}
+
+/// Pictogram codes in order of first use.
+fn used_pictograms<'a>(substances: &[&'a Substance]) -> Vec<&'a str> {
+    let mut codes: Vec<&str> = Vec::new();
+    for code in substances.iter().flat_map(|s| &s.pictograms) {
+        if !codes.contains(&code.as_str()) {
+            codes.push(code);
+        }
+    }
+    codes
+}
+
+/// Combined codes (`P301+P330+P331`) without an entry of their own read as
+/// their parts in turn.
+fn statement(dataset: &Dataset, code: &str) -> Option<String> {
+    if let Some(text) = dataset.statements.get(code) {
+        return Some(text.clone());
+    }
+    let parts = code
+        .split('+')
+        .map(|part| dataset.statements.get(part.trim()).cloned())
+        .collect::<Option<Vec<String>>>()?;
+    Some(parts.join(" "))
+}
+
+fn render(dataset: &Dataset, substances: &[&Substance], assets: &str, args: &Args) -> String {
+    let mut out = String::from("\\begin{table}[h]\n\\centering\n");
+    out.push_str(&format!(
+        "\\caption{{{}}}\n",
+        args.value("caption").unwrap_or("Safety data")
+    ));
+    if let Some(label) = args.value("label") {
+        out.push_str(&format!("\\label{{tab:{}}}\n", label));
+    }
+    out.push_str("\\small\n\\begin{tabular}{p{3.2cm} p{3.2cm} l p{2.2cm} p{3.4cm}}\n");
+    out.push_str("\\toprule\n");
+    out.push_str("Substance & Pictograms & Signal word & H statements & P statements \\\\\n");
+    out.push_str("\\midrule\n");
+    for substance in substances {
+        let mut name = escape(&substance.name);
+        if let Some(formula) = &substance.formula {
+            name.push_str(&format!(" (\\ce{{{}}})", formula));
+        }
+        if let Some(cas) = &substance.cas {
+            name.push_str(&format!("\\newline CAS {}", cas));
+        }
+        let pictograms: Vec<String> = substance
+            .pictograms
+            .iter()
+            .map(|code| format!("\\includegraphics[height=0.9cm]{{{}/{}.pdf}}", assets, code))
+            .collect();
+        let codes = |codes: &[String]| match codes.is_empty() {
+            true => "--".to_string(),
+            false => codes.join(", "),
+        };
+        out.push_str(&format!(
+            "{} & {} & {} & {} & {} \\\\\n",
+            name,
+            match pictograms.is_empty() {
+                true => "--".to_string(),
+                false => pictograms.join(" "),
+            },
+            substance.signal.as_deref().unwrap_or("--"),
+            codes(&substance.hazards),
+            codes(&substance.precautions)
+        ));
+    }
+    out.push_str("\\bottomrule\n\\end{tabular}\n");
+
+    let mut codes: Vec<&str> = Vec::new();
+    for code in substances
+        .iter()
+        .flat_map(|s| &s.hazards)
+        .chain(substances.iter().flat_map(|s| &s.precautions))
+    {
+        if !codes.contains(&code.as_str()) {
+            codes.push(code);
+        }
+    }
+    if !args.flag("codes-only") && !codes.is_empty() {
+        out.push_str("\n\\footnotesize\n\\begin{description}\n");
+        for code in codes {
+            let text = statement(dataset, code).unwrap_or_else(|| "--".to_string());
+            out.push_str(&format!("\\item[{}] {}\n", code, escape(&text)));
+        }
+        out.push_str("\\end{description}\n");
+    }
+    out.push_str("\\end{table}\n");
+    out
+}
@@ -0,0 +1,427 @@
+use crate::api;
+use crate::batch;
+use crate::cli::Args;
+use crate::config::{ProjectConfig, CONFIG_FILE};
+use crate::languages::Languages;
+use crate::templates::{Registry, RemoteTemplate, METADATA_FILE};
+use crate::variables::{self, Variables};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Preamble shared by every built-in template: mhchem, chemfig, siunitx and
+/// booktabs with sensible defaults.
+pub const PREAMBLE: &str = include_str!("../templates/preamble.tex");
+
+/// A project skeleton: files relative to the project directory.
+pub struct Template {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub files: &'static [(&'static str, &'static str)],
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "lab-report",
+        description: "Laboratory report: aim, theory, methods, results table",
+        files: &[("main.tex", include_str!("../templates/lab-report/main.tex"))],
+    },
+    Template {
+        name: "lecture-summary",
+        description: "Lecture notes with reactions and structures",
+        files: &[(
+            "main.tex",
+            include_str!("../templates/lecture-summary/main.tex"),
+        )],
+    },
+    Template {
+        name: "cheat-sheet",
+        description: "Dense three-column landscape reference sheet",
+        files: &[(
+            "main.tex",
+            include_str!("../templates/cheat-sheet/main.tex"),
+        )],
+    },
+    Template {
+        name: "thesis-chapter",
+        description: "Thesis chapter with bibliography",
+        files: &[
+            (
+                "main.tex",
+                include_str!("../templates/thesis-chapter/main.tex"),
+            ),
+            (
+                "references.bib",
+                include_str!("../templates/thesis-chapter/references.bib"),
+            ),
+        ],
+    },
+];
+
+const USAGE: &str = "\
+Usage: chemtex new <template> <name> [options]
+       chemtex new --list
+
+Creates the project directory <name> with a chemistry preamble and a
+.chemtex.toml; compile it with `chemtex compile <name>`.
+
+Options:
+  --from REGISTRY   Take the template from a git URL, zip URL or directory of
+                    shared templates (see `chemtex templates`); a registry
+                    holding a single template only needs <name>
+  --var NAME=VALUE  Fill {{NAME}} placeholders (repeatable); missing values are
+                    asked for on a terminal or taken from <data dir>/variables.toml.
+                    TeX special characters in values are escaped; {{NAME|raw}}
+                    inserts the value as it is
+  --lang ru,en      Set up babel for these languages, the first being the main
+                    one (recorded in .chemtex.toml for registry templates)
+  --no-prompt       Leave placeholders without a value as they are
+  --force           Write into a non-empty directory";
+
+/// Text files get their `{{name}}` placeholders filled in.
+const TEXT_EXTENSIONS: &[&str] = &["tex", "bib", "sty", "cls", "toml", "md", "txt"];
+/// Of those, the ones whose values are escaped for TeX.
+const TEX_EXTENSIONS: &[&str] = &["tex", "bib", "sty", "cls"];
+
+/// Filled in automatically; never asked for or stored in the project config.
+const BUILTIN_VARIABLES: &[&str] = &["title", "date"];
+
+struct NewOptions {
+    force: bool,
+    variables: Variables,
+    prompt: bool,
+    lang: Option<String>,
+}
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["list", "force", "no-prompt"],
+        &["from", "var", "lang"],
+    )?;
+    if args.flag("list") {
+        print_templates();
+        return Ok(());
+    }
+    let mut variables = variables::user_defaults()?;
+    variables.extend(variables::parse_assignments(&args.values("var"))?);
+    let options = NewOptions {
+        force: args.flag("force"),
+        variables,
+        prompt: !args.flag("no-prompt"),
+        lang: args.value("lang").map(String::from),
+    };
+    if let Some(spec) = &options.lang {
+        Languages::parse(spec, None)?;
+    }
+    let client = api::build_client()?;
+
+    if let Some(source) = args.value("from") {
+        let registry = Registry::new(source)?;
+        let root = registry.root(&client, false).await?;
+        let templates = registry.templates(&root)?;
+        let (template, name) = match (templates.as_slice(), args.positional(0), args.positional(1))
+        {
+            ([only], Some(name), None) => (only, name),
+            (_, Some(template), Some(name)) => (find_remote(&templates, template)?, name),
+            _ => anyhow::bail!(USAGE),
+        };
+        return create_from_remote(template, Path::new(name), &options);
+    }
+
+    let (Some(template), Some(name)) = (args.positional(0), args.positional(1)) else {
+        anyhow::bail!(USAGE);
+    };
+    let dir = Path::new(name);
+    if let Some(builtin) = TEMPLATES.iter().find(|t| t.name == template) {
+        return create(builtin, dir, &options);
+    }
+    // Fall back to the configured and previously fetched registries.
+    let mut names: Vec<String> = TEMPLATES.iter().map(|t| t.name.to_string()).collect();
+    for registry in Registry::known()? {
+        let Ok(root) = registry.root(&client, false).await else {
+            continue;
+        };
+        let templates = registry.templates(&root)?;
+        if let Ok(remote) = find_remote(&templates, template) {
+            return create_from_remote(remote, dir, &options);
+        }
+        names.extend(templates.into_iter().map(|t| t.name));
+    }
+    anyhow::bail!(
+        "Unknown template {:?}, available: {}",
+        template,
+        names.join(", ")
+    )
+}
+
+fn find_remote<'a>(templates: &'a [RemoteTemplate], name: &str) -> Result<&'a RemoteTemplate> {
+    templates.iter().find(|t| t.name == name).with_context(|| {
+        let names: Vec<&str> = templates.iter().map(|t| t.name.as_str()).collect();
+        format!(
+            "Unknown template {:?}, available: {}",
+            name,
+            names.join(", ")
+        )
+    })
+}
+
+fn print_templates() {
+    let width = TEMPLATES.iter().map(|t| t.name.len()).max().unwrap_or(0);
+    for template in TEMPLATES {
+        println!("{:<width$}  {}", template.name, template.description);
+    }
+}
+
+/// Writes a built-in template into `dir`.
+fn create(template: &Template, dir: &Path, options: &NewOptions) -> Result<()> {
+    let preamble = match &options.lang {
+        Some(spec) => Languages::parse(spec, Some("pdflatex"))?.inject(PREAMBLE),
+        None => PREAMBLE.to_string(),
+    };
+    let mut files = vec![(PathBuf::from("preamble.tex"), preamble.into_bytes())];
+    files.extend(
+        template
+            .files
+            .iter()
+            .map(|(path, contents)| (PathBuf::from(path), contents.as_bytes().to_vec())),
+    );
+    let config = ProjectConfig {
+        main: Some("main.tex".into()),
+        engine: Some("pdflatex".to_string()),
+        ..ProjectConfig::default()
+    };
+    write_project(dir, files, config, options)?;
+    created(dir, template.name);
+    Ok(())
+}
+
+/// Copies a registry template into `dir`, adding a `.chemtex.toml` when the
+/// template does not ship one.
+fn create_from_remote(template: &RemoteTemplate, dir: &Path, options: &NewOptions) -> Result<()> {
+    let mut files = Vec::new();
+    read_template(&template.dir, Path::new(""), &mut files)?;
+    // Its preamble may be anywhere; packing sets the languages up instead.
+    let config = ProjectConfig {
+        lang: options.lang.clone(),
+        ..ProjectConfig::default()
+    };
+    write_project(dir, files, config, options)?;
+    created(dir, &template.name);
+    Ok(())
+}
+
+/// Fills in the placeholders of every text file, writes the project and
+/// records the variables in its config so packing can fill any added later.
+fn write_project(
+    dir: &Path,
+    files: Vec<(PathBuf, Vec<u8>)>,
+    default_config: ProjectConfig,
+    options: &NewOptions,
+) -> Result<()> {
+    prepare_dir(dir, options.force)?;
+    let lang = default_config.lang.clone();
+
+    let has_extension = |path: &Path, extensions: &[&str]| {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| extensions.contains(&e.to_ascii_lowercase().as_str()))
+    };
+    let is_text = |path: &Path| has_extension(path, TEXT_EXTENSIONS);
+    let mut used = Vec::new();
+    for (path, contents) in &files {
+        if let (true, Ok(text)) = (is_text(path), std::str::from_utf8(contents)) {
+            for name in variables::placeholders(text) {
+                if !used.contains(&name) {
+                    used.push(name);
+                }
+            }
+        }
+    }
+
+    let mut provided = options.variables.clone();
+    if options.prompt {
+        let missing: Vec<String> = used
+            .iter()
+            .filter(|name| {
+                !provided.contains_key(*name) && !BUILTIN_VARIABLES.contains(&name.as_str())
+            })
+            .cloned()
+            .collect();
+        variables::prompt(&missing, &mut provided)?;
+    }
+    let mut values = provided.clone();
+    values.insert("title".to_string(), project_title(dir));
+    values.insert(
+        "date".to_string(),
+        chrono::Local::now().format("%Y-%m-%d").to_string(),
+    );
+
+    for (path, contents) in files {
+        let target = dir.join(&path);
+        // With --force, a link left in the directory would be written through.
+        refuse_links(dir, &path)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let contents = match (is_text(&path), String::from_utf8(contents)) {
+            (true, Ok(text)) if has_extension(&path, TEX_EXTENSIONS) => {
+                variables::substitute(&text, &values).into_bytes()
+            }
+            (true, Ok(text)) => variables::substitute_plain(&text, &values).into_bytes(),
+            (_, Ok(text)) => text.into_bytes(),
+            (_, Err(e)) => e.into_bytes(),
+        };
+        fs::write(&target, contents)
+            .with_context(|| format!("Failed to write {}", target.display()))?;
+    }
+
+    let mut config = match ProjectConfig::find(dir)? {
+        Some(config) => config,
+        None => ProjectConfig {
+            main: match default_config.main {
+                Some(main) => Some(main),
+                None => batch::find_main_document(dir)?
+                    .strip_prefix(dir)
+                    .ok()
+                    .map(Path::to_path_buf),
+            },
+            output: default_output(dir),
+            ..default_config
+        },
+    };
+    if config.lang.is_none() {
+        config.lang = lang;
+    }
+    config
+        .variables
+        .extend(provided.into_iter().filter(|(name, _)| used.contains(name)));
+    config
+        .save(dir)
+        .with_context(|| format!("Failed to write {}", CONFIG_FILE))
+}
+
+/// Refuses to touch a non-empty directory unless `force` is set.
+fn prepare_dir(dir: &Path, force: bool) -> Result<()> {
+    let occupied = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+    if occupied && !force {
+        anyhow::bail!(
+            "{} already exists and is not empty (pass --force to overwrite)",
+            dir.display()
+        );
+    }
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))
+}
+
+/// Reads the files of a template. Symbolic links are skipped: a template
+/// from a registry could otherwise copy files from anywhere on this machine
+/// into the project.
+/// Fails when `relative`, or a directory on the way to it, is a symbolic
+/// link in `dir`.
+fn refuse_links(dir: &Path, relative: &Path) -> Result<()> {
+    let mut path = dir.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()) {
+            anyhow::bail!(
+                "{} is a symbolic link; not writing through it",
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn read_template(dir: &Path, relative: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        if name == ".git" || name == METADATA_FILE {
+            continue;
+        }
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if metadata.file_type().is_symlink() {
+            eprintln!(
+                "Warning: skipping {}, a symbolic link",
+                relative.join(name).display()
+            );
+            continue;
+        }
+        if metadata.is_dir() {
+            read_template(&path, &relative.join(name), files)?;
+        } else {
+            let contents =
+                fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            files.push((relative.join(name), contents));
+        }
+    }
+    Ok(())
+}
+
+fn created(dir: &Path, template: &str) {
+    println!(
+        "Created {} from the {} template; compile it with `chemtex compile {}`",
+        dir.display(),
+        template,
+        dir.display()
+    );
+}
+
+fn default_output(dir: &Path) -> Option<PathBuf> {
+    dir.file_name()
+        .map(|name| Path::new(name).with_extension("pdf"))
+}
+
+/// `organic-synthesis_lab` → `Organic Synthesis Lab`.
+fn project_title(dir: &Path) -> String {
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    name.split(['-', '_', ' '])
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn symbolic_links_are_neither_copied_nor_written_through() {
+        let root = std::env::temp_dir().join(format!("chemtex-scaffold-{}", std::process::id()));
+        let (template, project) = (root.join("template"), root.join("project"));
+        fs::create_dir_all(template.join("figures")).unwrap();
+        fs::create_dir_all(&project).unwrap();
+        fs::write(root.join("secret"), "private").unwrap();
+        fs::write(template.join("main.tex"), "\\documentclass{article}").unwrap();
+        symlink(root.join("secret"), template.join("notes.tex")).unwrap();
+        symlink(&root, template.join("figures/outside")).unwrap();
+        symlink(root.join("secret"), project.join("main.tex")).unwrap();
+
+        let mut files = Vec::new();
+        read_template(&template, Path::new(""), &mut files).unwrap();
+        let refused = refuse_links(&project, Path::new("main.tex"));
+        let allowed = refuse_links(&project, Path::new("figures/plot.png"));
+        fs::remove_dir_all(&root).unwrap();
+
+        let names: Vec<&Path> = files.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(names, [Path::new("main.tex")]);
+        assert!(refused.is_err());
+        assert!(allowed.is_ok());
+    }
+}
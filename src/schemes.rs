@@ -0,0 +1,141 @@
+use crate::deps::{self, SourceLine};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Marks the reaction scheme on the following lines, optionally with a
+/// caption: `%%rxn:esterification Fischer esterification`.
+const MARKER: &str = "%%rxn:";
+
+/// Refers to a scheme by name: `@rxn{esterification}` → `Scheme~3`.
+const REFERENCE: &str = "@rxn{";
+
+/// Reaction scheme numbers: the `%%rxn:` lines of a document numbered in
+/// reading order across all its files, so references stay right when a
+/// scheme moves to another chapter.
+#[derive(Debug, Default)]
+pub struct Schemes {
+    numbers: HashMap<String, usize>,
+}
+
+impl Schemes {
+    /// Numbers the schemes of `main` and the files it inputs.
+    pub fn scan(main: &Path) -> Result<Self> {
+        Self::number(&deps::read_lines(main)?)
+    }
+
+    /// Numbers the schemes of a single file.
+    pub fn from_text(text: &str, file: &Path) -> Result<Self> {
+        let lines: Vec<SourceLine> = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| SourceLine {
+                file: file.to_path_buf(),
+                number: index + 1,
+                text: line.to_string(),
+            })
+            .collect();
+        Self::number(&lines)
+    }
+
+    fn number(lines: &[SourceLine]) -> Result<Self> {
+        let mut schemes = Self::default();
+        let mut defined: HashMap<&str, &SourceLine> = HashMap::new();
+        for line in lines {
+            let Some((name, _)) = marker(&line.text) else {
+                continue;
+            };
+            if let Some(first) = defined.insert(name, line) {
+                anyhow::bail!(
+                    "{}:{}: reaction scheme {:?} is already marked at {}:{}",
+                    line.file.display(),
+                    line.number,
+                    name,
+                    first.file.display(),
+                    first.number
+                );
+            }
+            let number = schemes.numbers.len() + 1;
+            schemes.numbers.insert(name.to_string(), number);
+        }
+        Ok(schemes)
+    }
+
+    /// Replaces `%%rxn:` lines with the scheme's heading and `@rxn{...}`
+    /// with its number.
+    pub fn rewrite(&self, text: &str) -> Result<String> {
+        if !text.contains(MARKER) && !text.contains(REFERENCE) {
+            return Ok(text.to_string());
+        }
+        let mut out = String::with_capacity(text.len());
+        for (index, line) in text.split_inclusive('\n').enumerate() {
+            let context = || format!("Line {}: {}", index + 1, line.trim());
+            if let Some((name, caption)) = marker(line) {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let heading = match caption {
+                    "" => format!("\\textbf{{Scheme~{}.}}", self.get(name)?),
+                    caption => format!("\\textbf{{Scheme~{}.}}~{}", self.get(name)?, caption),
+                };
+                out.push_str(&format!(
+                    "{}\\par\\noindent{}\\par\\nopagebreak",
+                    indent, heading
+                ));
+                if line.ends_with('\n') {
+                    out.push('\n');
+                }
+                continue;
+            }
+            // References in a comment stay as written.
+            let (mut rest, comment) = line.split_at(deps::strip_comment(line).len());
+            while let Some(start) = rest.find(REFERENCE) {
+                out.push_str(&rest[..start]);
+                let (name, tail) = rest[start + REFERENCE.len()..]
+                    .split_once('}')
+                    .context("Unclosed @rxn{")
+                    .with_context(context)?;
+                let number = self.get(name.trim()).with_context(context)?;
+                out.push_str(&format!("Scheme~{}", number));
+                rest = tail;
+            }
+            out.push_str(rest);
+            out.push_str(comment);
+        }
+        Ok(out)
+    }
+
+    fn get(&self, name: &str) -> Result<usize> {
+        self.numbers.get(name).copied().with_context(|| {
+            format!(
+                "Unknown reaction scheme {:?} (no %%rxn:{} line)",
+                name, name
+            )
+        })
+    }
+}
+
+/// `%%rxn:name caption` → (`name`, `caption`).
+fn marker(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim().strip_prefix(MARKER)?;
+    let (name, caption) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    (!name.is_empty()).then(|| (name, caption.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_are_numbered_outside_comments() {
+        let text = "%%rxn:hydrolysis\n\\ce{A -> B}\n%%rxn:esterification Fischer\n\
+                    See @rxn{esterification} and @rxn{ hydrolysis }. % not @rxn{gone}\n\
+                    % @rxn{unclosed\n50\\% of @rxn{hydrolysis}\n";
+        let schemes = Schemes::from_text(text, Path::new("main.tex")).unwrap();
+        assert_eq!(
+            schemes.rewrite(text).unwrap(),
+            "\\par\\noindent\\textbf{Scheme~1.}\\par\\nopagebreak\n\\ce{A -> B}\n\
+             \\par\\noindent\\textbf{Scheme~2.}~Fischer\\par\\nopagebreak\n\
+             See Scheme~2 and Scheme~1. % not @rxn{gone}\n% @rxn{unclosed\n50\\% of Scheme~1\n"
+        );
+        assert!(schemes.rewrite("@rxn{unknown}\n").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! The managed temp root: every temporary file chemtex makes (PDFs being
+//! downloaded, archives packed for upload, checked-out and converted
+//! sources) goes under `<cache dir>/tmp`, or `CHEMTEX_TMPDIR`, rather than
+//! beside the inputs or loose in the system temp dir.
+//!
+//! Each process works in a session directory of its own, removed when it
+//! exits. The session holds a file lock for as long as the process runs, so
+//! a session left behind by a crash or a second Ctrl-C is recognised as
+//! abandoned and removed by the next run, or at once by `chemtex clean`.
+
+use crate::cache;
+use crate::cli::Args;
+use crate::storage;
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Held locked by the process owning the session directory.
+const LOCK_FILE: &str = ".lock";
+
+const USAGE: &str = "\
+Usage: chemtex clean [--dry-run]
+
+Removes the temporary files of chemtex runs that ended without cleaning up
+(a crash, or a second Ctrl-C), from the temp root <cache dir>/tmp or
+CHEMTEX_TMPDIR. Runs in progress keep theirs.";
+
+static SESSION: OnceLock<Result<Session, String>> = OnceLock::new();
+
+/// Makes the names handed out by a session unique.
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+struct Session {
+    dir: PathBuf,
+    /// Released by [`cleanup`], or by the OS when the process ends.
+    lock: Mutex<Option<File>>,
+}
+
+/// What [`sweep`] found abandoned.
+#[derive(Debug, Default)]
+pub struct Swept {
+    pub sessions: usize,
+    pub bytes: u64,
+}
+
+pub fn root() -> Result<PathBuf> {
+    match std::env::var_os("CHEMTEX_TMPDIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => Ok(storage::cache_dir()?.join("tmp")),
+    }
+}
+
+fn session() -> Result<&'static Session> {
+    let session = SESSION.get_or_init(|| {
+        let create = || -> Result<Session> {
+            let root = root()?;
+            storage::ensure_dir(&root)?;
+            // Old sessions are cleared out before a new one is added.
+            let _ = sweep(&root, false);
+            let dir = root.join(format!("{}-{}", std::process::id(), nanos()));
+            storage::ensure_dir(&dir)?;
+            // Locked before it is in place, so a sweep never sees it free.
+            let path = dir.join(LOCK_FILE);
+            let staged = dir.join(format!("{}.new", LOCK_FILE));
+            let lock = File::create(&staged)
+                .with_context(|| format!("Failed to create {}", staged.display()))?;
+            lock.lock()
+                .with_context(|| format!("Failed to lock {}", staged.display()))?;
+            fs::rename(&staged, &path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            Ok(Session {
+                dir,
+                lock: Mutex::new(Some(lock)),
+            })
+        };
+        create().map_err(|e| format!("{:#}", e))
+    });
+    session
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("Temp root: {}", e))
+}
+
+fn nanos() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default()
+}
+
+/// A path for a temporary file ending in `name`, not yet created.
+pub fn file(name: &str) -> Result<PathBuf> {
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    Ok(session()?.dir.join(format!("{}-{}", n, name)))
+}
+
+/// A new, empty temporary directory whose name starts with `prefix`.
+pub fn dir(prefix: &str) -> Result<PathBuf> {
+    let path = file(prefix)?;
+    fs::create_dir_all(&path)
+        .with_context(|| format!("Failed to create directory: {}", path.display()))?;
+    Ok(path)
+}
+
+/// Removes this process's session directory, if it made one. Run on exit.
+pub fn cleanup() {
+    let Some(Ok(session)) = SESSION.get() else {
+        return;
+    };
+    // Unlocked first: Windows does not remove open files.
+    session
+        .lock
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    let _ = fs::remove_dir_all(&session.dir);
+}
+
+/// Like [`std::process::exit`], removing the session directory first.
+pub fn exit(code: i32) -> ! {
+    cleanup();
+    std::process::exit(code)
+}
+
+/// Removes (or with `dry_run` only measures) the sessions under `root`
+/// whose process has ended, that is whose lock can be taken.
+pub fn sweep(root: &Path, dry_run: bool) -> Result<Swept> {
+    let mut swept = Swept::default();
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(swept),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", root.display())),
+    };
+    for entry in entries {
+        let dir = entry?.path();
+        if !dir.is_dir() || !abandoned(&dir) {
+            continue;
+        }
+        swept.sessions += 1;
+        swept.bytes += size(&dir);
+        if !dry_run {
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+    }
+    Ok(swept)
+}
+
+/// No process holds the session's lock. One without a lock is still being
+/// created, and left alone unless it is an hour old.
+fn abandoned(dir: &Path) -> bool {
+    let path = dir.join(LOCK_FILE);
+    match OpenOptions::new().write(true).open(&path) {
+        Ok(file) => file.try_lock().is_ok(),
+        Err(_) => fs::metadata(dir)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age.as_secs() > 3600),
+    }
+}
+
+fn size(path: &Path) -> u64 {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !meta.is_dir() {
+        return meta.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["dry-run"], &[])?;
+    if args.positional(0).is_some() {
+        anyhow::bail!(USAGE);
+    }
+    let root = root()?;
+    let dry_run = args.flag("dry-run");
+    let swept = sweep(&root, dry_run)?;
+    println!(
+        "{} {} abandoned temp session(s) in {} ({})",
+        if dry_run { "Would remove" } else { "Removed" },
+        swept.sessions,
+        root.display(),
+        cache::format_bytes(swept.bytes)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sweep_keeps_sessions_whose_lock_is_held() {
+        let root = std::env::temp_dir().join(format!("chemtex-scratch-{}", std::process::id()));
+        let live = root.join("1-1");
+        let dead = root.join("2-2");
+        for dir in [&live, &dead] {
+            fs::create_dir_all(dir).unwrap();
+            fs::write(dir.join("a.pdf"), b"%PDF").unwrap();
+        }
+        let held = File::create(live.join(LOCK_FILE)).unwrap();
+        held.lock().unwrap();
+        File::create(dead.join(LOCK_FILE)).unwrap();
+
+        let swept = sweep(&root, true).unwrap();
+        assert_eq!((swept.sessions, swept.bytes), (1, 4));
+        assert!(dead.exists());
+        sweep(&root, false).unwrap();
+        assert!(live.exists() && !dead.exists());
+
+        drop(held);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
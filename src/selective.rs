@@ -0,0 +1,82 @@
+use crate::deps::{command_arguments, strip_comment};
+use anyhow::Result;
+
+const BEGIN_DOCUMENT: &str = "\\begin{document}";
+
+/// `--only chapters/kinetics.tex`: the main file rewritten to build just the
+/// given chapters. Chapters pulled in with `\include` are selected with
+/// `\includeonly`, which keeps the numbering of the rest from their `.aux`
+/// files; otherwise the body's other `\input`/`\include` lines are
+/// commented out.
+pub fn restrict(text: &str, only: &[String]) -> Result<String> {
+    let begin = text
+        .find(BEGIN_DOCUMENT)
+        .ok_or_else(|| anyhow::anyhow!("--only needs a main file with \\begin{{document}}"))?;
+    let (preamble, body) = text.split_at(begin);
+    let includes = chapters(body, "\\include");
+    let inputs = chapters(body, "\\input");
+    let wanted: Vec<String> = only.iter().map(|c| normalize(c)).collect();
+
+    let mut selected = Vec::new();
+    for (chapter, target) in wanted.iter().zip(only) {
+        match includes.iter().find(|i| normalize(i) == *chapter) {
+            Some(include) => selected.push(include.clone()),
+            None if inputs.iter().any(|i| normalize(i) == *chapter) => {}
+            None => anyhow::bail!("{} is not \\include'd or \\input by the main file", target),
+        }
+    }
+    if selected.len() == wanted.len() {
+        return Ok(format!(
+            "{}\\includeonly{{{}}}\n{}",
+            preamble,
+            selected.join(","),
+            body
+        ));
+    }
+
+    let mut out = String::from(preamble);
+    for line in body.split_inclusive('\n') {
+        let code = strip_comment(line);
+        let referenced: Vec<&str> = ["\\include", "\\input"]
+            .iter()
+            .flat_map(|command| command_arguments(code, command))
+            .collect();
+        let skip = !referenced.is_empty()
+            && !referenced
+                .iter()
+                .any(|chapter| wanted.contains(&normalize(chapter)));
+        if skip {
+            out.push_str("% --only: ");
+        }
+        out.push_str(line);
+    }
+    Ok(out)
+}
+
+/// The `\include`d chapters of a main file that `only` leaves out, as
+/// written (without `.tex`).
+pub fn skipped(text: &str, only: &[String]) -> Vec<String> {
+    let body = text
+        .split_once(BEGIN_DOCUMENT)
+        .map_or(text, |(_, body)| body);
+    let wanted: Vec<String> = only.iter().map(|c| normalize(c)).collect();
+    chapters(body, "\\include")
+        .into_iter()
+        .filter(|chapter| !wanted.contains(&normalize(chapter)))
+        .map(|chapter| chapter.trim_end_matches(".tex").to_string())
+        .collect()
+}
+
+fn chapters(body: &str, command: &str) -> Vec<String> {
+    body.lines()
+        .flat_map(|line| command_arguments(strip_comment(line), command))
+        .map(|argument| argument.trim().to_string())
+        .collect()
+}
+
+/// `./chapters\kinetics.tex` → `chapters/kinetics`.
+fn normalize(path: &str) -> String {
+    let path = path.trim().replace('\\', "/");
+    let path = path.trim_start_matches("./");
+    path.strip_suffix(".tex").unwrap_or(path).to_string()
+}
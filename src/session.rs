@@ -0,0 +1,194 @@
+//! A journal of running jobs in `<data dir>/sessions`: each job rewrites
+//! its small record (input, output, options, task id, phase) at every phase
+//! and syncs it to disk, and removes it when the job ends, however it ends.
+//! A record left behind belongs to a run cut short by a crash or power
+//! loss; `chemtex attach --last` picks it up where it stopped, polling the
+//! task or downloading the PDF without uploading again.
+//!
+//! A running job holds an OS lock on `<id>.lock` next to its record, so
+//! records of live runs are told apart from abandoned ones.
+
+use crate::job::{Job, Phase};
+use crate::storage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub name: String,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// The phase the job was in (see [`Phase::as_str`]).
+    pub phase: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+    /// Unix time of the last update.
+    pub updated_at: u64,
+}
+
+/// The journal entry of a running job, removed on drop.
+#[derive(Debug)]
+pub struct Session {
+    path: PathBuf,
+    record: Record,
+    _lock: File,
+}
+
+impl Session {
+    /// Starts the journal entry of `job`.
+    pub fn begin(job: &Job) -> Result<Self> {
+        let dir = sessions_dir()?;
+        storage::ensure_dir(&dir)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let path = dir.join(format!("{}-{}.json", std::process::id(), nanos));
+        let lock = lock_file(&path)?;
+        lock.try_lock()
+            .with_context(|| format!("Failed to lock {}", path.display()))?;
+        let mut session = Self {
+            path,
+            record: Record {
+                name: job.name.clone(),
+                // `attach --last` may run from another directory.
+                input: std::path::absolute(&job.input)?,
+                output: std::path::absolute(&job.output)?,
+                engine: job.options.engine.clone(),
+                profile: job.options.profile.clone(),
+                phase: Phase::Pack.as_str().to_string(),
+                task_id: None,
+                download_url: None,
+                updated_at: 0,
+            },
+            _lock: lock,
+        };
+        session.save()?;
+        Ok(session)
+    }
+
+    /// Takes over the record at `path`, left by another run; `None` while
+    /// that run is still going.
+    pub fn resume(path: &Path) -> Result<Option<Self>> {
+        let lock = lock_file(path)?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Ok(None),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()))
+            }
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let record = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid session record {}", path.display()))?;
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            record,
+            _lock: lock,
+        }))
+    }
+
+    pub fn record(&self) -> &Record {
+        &self.record
+    }
+
+    /// Records the task the upload created.
+    pub fn uploaded(&mut self, task_id: &str) -> Result<()> {
+        self.record.phase = Phase::Queue.as_str().to_string();
+        self.record.task_id = Some(task_id.to_string());
+        self.save()
+    }
+
+    /// Records where the finished PDF is.
+    pub fn downloading(&mut self, download_url: &str) -> Result<()> {
+        self.record.phase = Phase::Download.as_str().to_string();
+        self.record.download_url = Some(download_url.to_string());
+        self.save()
+    }
+
+    /// Writes the record so that a crash at any point leaves the old or the
+    /// new one, on disk.
+    fn save(&mut self) -> Result<()> {
+        self.record.updated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let json = serde_json::to_string_pretty(&self.record)?;
+        let tmp = self.path.with_extension("json.tmp");
+        let mut file =
+            File::create(&tmp).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        file.write_all(json.as_bytes())
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        // The rename itself is only durable once the directory is synced
+        // (not possible on Windows, where it is durable already).
+        if let Some(dir) = self.path.parent() {
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = fs::remove_file(self.path.with_extension("lock"));
+    }
+}
+
+/// The records of runs that ended without cleaning up, newest first.
+pub fn abandoned() -> Result<Vec<PathBuf>> {
+    let dir = sessions_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read directory: {}", dir.display()))
+        }
+    };
+    let mut records = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            let modified = entry_time(&path);
+            records.push((modified, path));
+        }
+    }
+    records.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    Ok(records.into_iter().map(|(_, path)| path).collect())
+}
+
+fn entry_time(path: &Path) -> SystemTime {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .unwrap_or(UNIX_EPOCH)
+}
+
+fn lock_file(record: &Path) -> Result<File> {
+    let path = record.with_extension("lock");
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file: {}", path.display()))
+}
+
+fn sessions_dir() -> Result<PathBuf> {
+    Ok(storage::data_dir()?.join("sessions"))
+}
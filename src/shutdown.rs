@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+
+/// Exit status after a second signal, as a shell reports Ctrl-C.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+static ARMED: AtomicBool = AtomicBool::new(false);
+
+/// How many `requested` futures are waiting right now.
+static LISTENING: AtomicUsize = AtomicUsize::new(0);
+
+static WATCH: Once = Once::new();
+
+/// Counts a waiting `requested` for as long as it lives.
+struct Listening;
+
+impl Listening {
+    fn start() -> Self {
+        LISTENING.fetch_add(1, Ordering::SeqCst);
+        Listening
+    }
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        LISTENING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Resolves on SIGINT (Ctrl-C) or SIGTERM. Once one has been received,
+/// listening replaces the default "die at once" behaviour for the rest of
+/// the process, so a second signal exits right away: cleanup that hangs on
+/// the network cannot keep the user waiting.
+///
+/// Installing the handler also takes the default away from everything that
+/// runs later and does not listen (a prompt, a publish, an upload), so a
+/// signal that arrives while nobody is waiting here exits as well.
+pub async fn requested() {
+    let _listening = Listening::start();
+    WATCH.call_once(|| {
+        tokio::spawn(async {
+            loop {
+                received().await;
+                // A listener that got this signal arms before it stops
+                // counting, so this only sees signals nobody else handles.
+                if LISTENING.load(Ordering::SeqCst) == 0 && !ARMED.load(Ordering::SeqCst) {
+                    eprintln!("Interrupted");
+                    std::process::exit(INTERRUPTED_EXIT_CODE);
+                }
+            }
+        });
+    });
+    received().await;
+    if !ARMED.swap(true, Ordering::SeqCst) {
+        tokio::spawn(async {
+            received().await;
+            eprintln!("Interrupted again, exiting without cleaning up");
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn received() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                () = ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(_) => ctrl_c().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn received() {
+    ctrl_c().await
+}
+
+/// Ctrl-C; never, when the handler cannot be installed.
+async fn ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,159 @@
+//! Rounding to significant figures, shared by the generators that print
+//! measured values.
+
+use anyhow::{Context, Result};
+
+/// The most significant figures an `f64` holds: past 15 rounding prints
+/// float noise, and past about 300 the scale overflows.
+pub const MAX_FIGURES: usize = 15;
+
+/// Significant figures of a number as written: `0.0050` → 2, `120` → 3.
+pub fn sig_figs(number: &str) -> usize {
+    let mantissa = number.split(['e', 'E']).next().unwrap_or(number);
+    let digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+    digits.trim_start_matches('0').len().max(1)
+}
+
+/// `figures`, if it is a count [`round`] takes: 1 to [`MAX_FIGURES`].
+pub fn check_figures(figures: usize) -> Result<usize> {
+    anyhow::ensure!(
+        (1..=MAX_FIGURES).contains(&figures),
+        "Significant figures must be between 1 and {}, got {}",
+        MAX_FIGURES,
+        figures
+    );
+    Ok(figures)
+}
+
+/// `value` to `figures` significant figures, in exponent notation when
+/// they end before the decimal point: `1234.5` to 2 is `1.2e3`.
+pub fn round(value: f64, figures: usize) -> Result<String> {
+    check_figures(figures)?;
+    anyhow::ensure!(value.is_finite(), "Cannot round {}", value);
+    if value == 0.0 {
+        return Ok("0".to_string());
+    }
+    let magnitude = |v: f64| v.abs().log10().floor() as i32;
+    // Rounding may carry into the next power of ten (0.0996 → 0.100).
+    let scale = 10f64.powi(figures as i32 - 1 - magnitude(value));
+    let value = (value * scale).round() / scale;
+    let magnitude = magnitude(value);
+    let decimals = figures as i32 - 1 - magnitude;
+    Ok(if decimals >= 0 {
+        format!("{:.*}", decimals as usize, value)
+    } else {
+        // Written out, the zeros before the point would read as significant.
+        format!("{:.*e}", figures - 1, value)
+    })
+}
+
+/// A value and its uncertainty, rounded so the uncertainty keeps one
+/// significant figure (two when it starts with 1) and the value the same
+/// last decimal place: (0.012345, 0.00061) → (`0.0123`, `0.0006`).
+pub fn round_uncertain(value: f64, uncertainty: f64) -> (String, String) {
+    let uncertainty = uncertainty.abs();
+    if uncertainty == 0.0 || !uncertainty.is_finite() {
+        return (value.to_string(), "0".to_string());
+    }
+    let magnitude = uncertainty.log10().floor() as i32;
+    let leading = uncertainty / 10f64.powi(magnitude);
+    let place = if leading < 2.0 {
+        magnitude - 1
+    } else {
+        magnitude
+    };
+    let at = |x: f64| {
+        let scale = 10f64.powi(place);
+        let x = (x / scale).round() * scale;
+        format!("{:.*}", (-place).max(0) as usize, x)
+    };
+    (at(value), at(uncertainty))
+}
+
+/// Typesets `0.012345 +- 0.0006 mol/L` as `\SI{0.0123 \pm 0.0006}{mol/L}`:
+/// a value with an uncertainty is rounded to it, one without to `figures`
+/// significant figures when given, else kept as written. Without a unit the
+/// result is a `\num`.
+pub fn format_value(spec: &str, figures: Option<usize>) -> Result<String> {
+    let spec = spec.trim();
+    let (number, rest) = split_number(spec);
+    let value: f64 = number
+        .parse()
+        .with_context(|| format!("Expected a number in {:?}", spec))?;
+    let rest = rest.trim_start();
+    let separator = ["+-", "±", "+/-", "\\pm"]
+        .into_iter()
+        .find(|s| rest.starts_with(s));
+    let (body, unit) = match separator {
+        Some(separator) => {
+            let (number, unit) = split_number(rest[separator.len()..].trim_start());
+            let uncertainty: f64 = number
+                .parse()
+                .with_context(|| format!("Expected an uncertainty in {:?}", spec))?;
+            let (value, uncertainty) = round_uncertain(value, uncertainty);
+            (format!("{} \\pm {}", value, uncertainty), unit)
+        }
+        None => match figures {
+            Some(figures) => (round(value, figures)?, rest),
+            None => (number.to_string(), rest),
+        },
+    };
+    Ok(match unit.trim() {
+        "" => format!("\\num{{{}}}", body),
+        unit => format!("\\SI{{{}}}{{{}}}", body, unit),
+    })
+}
+
+/// Splits a leading number (sign, digits, point, exponent) off `text`.
+fn split_number(text: &str) -> (&str, &str) {
+    let bytes = text.as_bytes();
+    let mut end = 0;
+    while end < bytes.len() {
+        let c = bytes[end];
+        let exponent = matches!(c, b'e' | b'E')
+            && bytes
+                .get(end + 1)
+                .is_some_and(|n| n.is_ascii_digit() || *n == b'-' || *n == b'+');
+        let sign = matches!(c, b'-' | b'+') && (end == 0 || matches!(bytes[end - 1], b'e' | b'E'));
+        if c.is_ascii_digit() || c == b'.' || exponent || sign {
+            end += 1;
+        } else {
+            break;
+        }
+    }
+    (&text[..end], &text[end..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_zeros_and_exponents_count_as_written() {
+        assert_eq!(sig_figs("0.0050"), 2);
+        assert_eq!(sig_figs("120"), 3);
+        assert_eq!(sig_figs("1.20e3"), 3);
+        assert_eq!(sig_figs("1.0E-5"), 2);
+        assert_eq!(round(0.0996, 2).unwrap(), "0.10");
+        assert_eq!(round(1234.5, 2).unwrap(), "1.2e3");
+        assert_eq!(round(1234.5, 4).unwrap(), "1235");
+        assert_eq!(
+            format_value("1.234e-3 +- 2e-5 mol", None).unwrap(),
+            "\\SI{0.00123 \\pm 0.00002}{mol}"
+        );
+        assert_eq!(
+            format_value("6.02214e23", Some(3)).unwrap(),
+            "\\num{6.02e23}"
+        );
+    }
+    #[test]
+    fn figures_an_f64_cannot_hold_are_errors() {
+        assert_eq!(round(0.1025, 15).unwrap(), "0.102500000000000");
+        for figures in [0, 16, 20, 400] {
+            assert!(round(0.1025, figures).is_err(), "{}", figures);
+        }
+        assert!(round(f64::NAN, 3).is_err());
+        assert!(round(f64::INFINITY, 3).is_err());
+        assert!(format_value("3.14159 g", Some(400)).is_err());
+    }
+}
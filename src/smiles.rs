@@ -0,0 +1,692 @@
+use crate::cli::Args;
+use crate::molecule::{Atom, BondOrder, Molecule};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage: chemtex smiles <SMILES> [options]
+
+Prints chemfig code for the structure, e.g.
+  chemtex smiles \"CC(=O)OC1=CC=CC=C1C(=O)O\"
+
+Options:
+  --into FILE     Write the structure into FILE below its %%chemtex:<marker>
+                  line instead of printing it; running again replaces it
+  --marker NAME   Marker to look for (default: smiles)";
+
+/// Tags the line written below a marker so a later run can replace it.
+pub const GENERATED_TAG: &str = "% smiles: ";
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &["into", "marker"])?;
+    let smiles = args.positional(0).context(USAGE)?;
+    let chemfig = to_chemfig(smiles)?;
+    match args.value("into") {
+        Some(path) => {
+            let marker = args.value("marker").unwrap_or("smiles");
+            insert_at_marker(Path::new(path), marker, &chemfig, smiles)?;
+            println!("Structure written below %%chemtex:{} in {}", marker, path);
+        }
+        None => println!("{}", chemfig),
+    }
+    Ok(())
+}
+
+/// Parses `smiles` and lays it out as `\chemfig{...}` code, with chains
+/// zigzagging and rings of up to eight atoms drawn as regular polygons.
+pub fn to_chemfig(smiles: &str) -> Result<String> {
+    Ok(to_molecule(smiles)?.to_chemfig())
+}
+
+/// Parses `smiles` and lays it out, ready to draw.
+pub fn to_molecule(smiles: &str) -> Result<Molecule> {
+    let mut parsed = parse(smiles)?;
+    Layout::new(&parsed).place_all(&mut parsed.molecule);
+    parsed.molecule.kekulize();
+    Ok(parsed.molecule)
+}
+
+fn insert_at_marker(path: &Path, marker: &str, chemfig: &str, smiles: &str) -> Result<()> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let tag = format!("%%chemtex:{}", marker);
+    let mut lines: Vec<&str> = text.lines().collect();
+    let index = lines
+        .iter()
+        .position(|line| line.trim() == tag)
+        .with_context(|| format!("No {} line in {}", tag, path.display()))?;
+    let generated = format!("{} {}{}", chemfig, GENERATED_TAG, smiles);
+    let next = index + 1;
+    if lines
+        .get(next)
+        .is_some_and(|line| line.contains(GENERATED_TAG))
+    {
+        lines[next] = &generated;
+    } else {
+        lines.insert(next, &generated);
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    fs::write(path, out).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// A molecule plus the ring bonds the SMILES string closed explicitly.
+struct Parsed {
+    molecule: Molecule,
+    /// Ring-closure bonds, drawn from their opening atom to their closing one.
+    closures: Vec<usize>,
+}
+
+fn parse(smiles: &str) -> Result<Parsed> {
+    let chars: Vec<char> = smiles.trim().chars().collect();
+    let mut parsed = Parsed {
+        molecule: Molecule::default(),
+        closures: Vec::new(),
+    };
+    let mut previous: Option<usize> = None;
+    let mut branches: Vec<Option<usize>> = Vec::new();
+    let mut bond: Option<BondOrder> = None;
+    let mut open_rings: HashMap<u32, (usize, Option<BondOrder>)> = HashMap::new();
+    let mut i = 0;
+
+    let error = |i: usize, what: &str| {
+        anyhow::anyhow!("{} at position {} of SMILES {:?}", what, i + 1, smiles)
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '(' => {
+                if previous.is_none() {
+                    return Err(error(i, "Branch without an atom"));
+                }
+                branches.push(previous);
+                i += 1;
+            }
+            ')' => {
+                previous = branches.pop().ok_or_else(|| error(i, "Unbalanced ')'"))?;
+                i += 1;
+            }
+            '-' | '/' | '\\' => {
+                bond = Some(BondOrder::Single);
+                i += 1;
+            }
+            '=' => {
+                bond = Some(BondOrder::Double);
+                i += 1;
+            }
+            '#' => {
+                bond = Some(BondOrder::Triple);
+                i += 1;
+            }
+            ':' => {
+                bond = Some(BondOrder::Aromatic);
+                i += 1;
+            }
+            '$' => return Err(error(i, "Quadruple bonds are not supported")),
+            '.' => {
+                previous = None;
+                i += 1;
+            }
+            '0'..='9' | '%' => {
+                let atom = previous.ok_or_else(|| error(i, "Ring bond without an atom"))?;
+                let number = if c == '%' {
+                    let digits: String = chars.iter().skip(i + 1).take(2).collect();
+                    i += 3;
+                    digits
+                        .parse()
+                        .map_err(|_| error(i - 3, "Expected two digits after '%'"))?
+                } else {
+                    i += 1;
+                    c.to_digit(10).unwrap_or(0)
+                };
+                match open_rings.remove(&number) {
+                    Some((opener, _)) if opener == atom => {
+                        return Err(error(i - 1, "Ring bond closed on the atom that opened it"));
+                    }
+                    Some((opener, _))
+                        if parsed.molecule.bonds.iter().any(|b| {
+                            (b.a, b.b) == (opener, atom) || (b.a, b.b) == (atom, opener)
+                        }) =>
+                    {
+                        return Err(error(i - 1, "Ring bond between atoms already bonded"));
+                    }
+                    Some((opener, opened_with)) => {
+                        let order = bond
+                            .or(opened_with)
+                            .unwrap_or_else(|| parsed.default_order(opener, atom));
+                        parsed.closures.push(parsed.molecule.bonds.len());
+                        parsed.molecule.add_bond(opener, atom, order);
+                    }
+                    None => {
+                        open_rings.insert(number, (atom, bond));
+                    }
+                }
+                bond = None;
+            }
+            _ => {
+                let (atom, length) = parse_atom(&chars[i..])
+                    .ok_or_else(|| error(i, &format!("Unexpected {:?}", c)))?;
+                let index = parsed.molecule.atoms.len();
+                parsed.molecule.atoms.push(atom);
+                if let Some(parent) = previous {
+                    let order = bond.unwrap_or_else(|| parsed.default_order(parent, index));
+                    parsed.molecule.add_bond(parent, index, order);
+                }
+                previous = Some(index);
+                bond = None;
+                i += length;
+            }
+        }
+    }
+
+    if !branches.is_empty() {
+        anyhow::bail!("Unbalanced '(' in SMILES {:?}", smiles);
+    }
+    if let Some(number) = open_rings.keys().min() {
+        anyhow::bail!(
+            "Ring bond {} is never closed in SMILES {:?}",
+            number,
+            smiles
+        );
+    }
+    if parsed.molecule.atoms.is_empty() {
+        anyhow::bail!("Empty SMILES");
+    }
+    Ok(parsed)
+}
+
+impl Parsed {
+    /// Implicit bonds are aromatic between aromatic atoms, single otherwise.
+    fn default_order(&self, a: usize, b: usize) -> BondOrder {
+        if self.molecule.atoms[a].aromatic && self.molecule.atoms[b].aromatic {
+            BondOrder::Aromatic
+        } else {
+            BondOrder::Single
+        }
+    }
+}
+
+/// An organic-subset atom (`C`, `Cl`, `c`), a wildcard or a bracket atom
+/// (`[NH4+]`, `[13CH3]`, `[C@@H]`), with the number of characters read.
+fn parse_atom(chars: &[char]) -> Option<(Atom, usize)> {
+    let two: String = chars.iter().take(2).collect();
+    if two == "Cl" || two == "Br" {
+        return Some((Atom::new(&two), 2));
+    }
+    match chars[0] {
+        'B' | 'C' | 'N' | 'O' | 'P' | 'S' | 'F' | 'I' => {
+            Some((Atom::new(&chars[0].to_string()), 1))
+        }
+        'b' | 'c' | 'n' | 'o' | 'p' | 's' => {
+            let mut atom = Atom::new(&chars[0].to_ascii_uppercase().to_string());
+            atom.aromatic = true;
+            Some((atom, 1))
+        }
+        '*' => Some((Atom::new("R"), 1)),
+        '[' => parse_bracket_atom(chars),
+        _ => None,
+    }
+}
+
+fn parse_bracket_atom(chars: &[char]) -> Option<(Atom, usize)> {
+    let end = chars.iter().position(|&c| c == ']')?;
+    let inner = &chars[1..end];
+    let mut i = 0;
+    // Isotope labels do not change the drawing.
+    while inner.get(i).is_some_and(|c| c.is_ascii_digit()) {
+        i += 1;
+    }
+
+    let first = *inner.get(i)?;
+    let mut atom = if first.is_ascii_lowercase() {
+        let two: String = inner.iter().skip(i).take(2).collect();
+        let symbol = if two == "se" || two == "as" {
+            two
+        } else {
+            first.to_string()
+        };
+        i += symbol.len();
+        let mut atom = Atom::new(&capitalize(&symbol));
+        atom.aromatic = true;
+        atom
+    } else if first.is_ascii_uppercase() {
+        let mut symbol = first.to_string();
+        i += 1;
+        if let Some(&c) = inner.get(i).filter(|c| c.is_ascii_lowercase()) {
+            symbol.push(c);
+            i += 1;
+        }
+        Atom::new(&symbol)
+    } else {
+        return None;
+    };
+
+    // Stereo marks (@, @@) are not drawn.
+    while inner.get(i) == Some(&'@') {
+        i += 1;
+    }
+    let mut hydrogens = 0;
+    if inner.get(i) == Some(&'H') {
+        i += 1;
+        hydrogens = 1;
+        if let Some(digit) = inner.get(i).and_then(|c| c.to_digit(10)) {
+            hydrogens = digit;
+            i += 1;
+        }
+    }
+    atom.hydrogens = Some(hydrogens);
+
+    if let Some(&sign @ ('+' | '-')) = inner.get(i) {
+        let unit = if sign == '+' { 1 } else { -1 };
+        i += 1;
+        let mut charge = 1;
+        if let Some(digit) = inner.get(i).and_then(|c| c.to_digit(10)) {
+            charge = digit as i32;
+            i += 1;
+        } else {
+            while inner.get(i) == Some(&sign) {
+                charge += 1;
+                i += 1;
+            }
+        }
+        atom.charge = unit * charge;
+    }
+    // Atom classes (`:1`) are ignored too.
+    if inner.get(i) == Some(&':') {
+        i = inner.len();
+    }
+    (i == inner.len()).then_some((atom, end + 1))
+}
+
+fn capitalize(symbol: &str) -> String {
+    let mut chars = symbol.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+type Point = (f64, f64);
+
+/// Assigns 2D coordinates: ring systems are built from regular polygons
+/// (fused rings share an edge, spiro rings an atom) and placed as a whole,
+/// chains zigzag away from them.
+struct Layout {
+    /// Ring system of every ring atom, as an index into `systems`.
+    system_of: Vec<Option<usize>>,
+    /// Atom positions of each ring system in its own frame.
+    systems: Vec<HashMap<usize, Point>>,
+}
+
+impl Layout {
+    fn new(parsed: &Parsed) -> Self {
+        let molecule = &parsed.molecule;
+        let rings = smallest_rings(molecule, parsed.closures.len());
+
+        // Rings sharing an atom belong to the same system.
+        let mut ring_system: Vec<usize> = (0..rings.len()).collect();
+        for i in 0..rings.len() {
+            for j in 0..i {
+                if rings[i].iter().any(|atom| rings[j].contains(atom)) {
+                    let (from, to) = (ring_system[i], ring_system[j]);
+                    for system in ring_system.iter_mut().filter(|s| **s == from) {
+                        *system = to;
+                    }
+                }
+            }
+        }
+        let mut system_ids: Vec<usize> = ring_system.clone();
+        system_ids.sort_unstable();
+        system_ids.dedup();
+
+        let mut system_of = vec![None; molecule.atoms.len()];
+        let mut systems = Vec::new();
+        for (index, id) in system_ids.into_iter().enumerate() {
+            let members: Vec<&Vec<usize>> = rings
+                .iter()
+                .zip(&ring_system)
+                .filter(|(_, &s)| s == id)
+                .map(|(ring, _)| ring)
+                .collect();
+            for atom in members.iter().copied().flatten() {
+                system_of[*atom] = Some(index);
+            }
+            systems.push(build_system(&members));
+        }
+        Self { system_of, systems }
+    }
+
+    fn place_all(&self, molecule: &mut Molecule) {
+        let mut placed = vec![false; molecule.atoms.len()];
+        let mut offset = 0.0;
+        for root in 0..molecule.atoms.len() {
+            if placed[root] {
+                continue;
+            }
+            molecule.atoms[root].x = offset;
+            self.visit(molecule, root, None, &mut placed);
+            // Disconnected parts sit side by side.
+            let right = molecule
+                .atoms
+                .iter()
+                .zip(&placed)
+                .filter(|(_, &p)| p)
+                .map(|(a, _)| a.x)
+                .fold(f64::MIN, f64::max);
+            offset = right + 2.0;
+        }
+    }
+
+    /// Places everything reachable from `atom`, which is already in
+    /// position. `incoming` is the direction of the bond that reached it and
+    /// the side the chain bends to next.
+    fn visit(
+        &self,
+        molecule: &mut Molecule,
+        atom: usize,
+        incoming: Option<(f64, f64)>,
+        placed: &mut [bool],
+    ) {
+        placed[atom] = true;
+        if let Some(system) = self.system_of[atom] {
+            self.place_system(molecule, system, atom, incoming.map(|(d, _)| d), placed);
+            return;
+        }
+
+        let (d, flip) = incoming.unwrap_or((-30.0, 1.0));
+        let children: Vec<(usize, usize)> = molecule
+            .bonds_of(atom)
+            .into_iter()
+            .map(|bond| (bond, molecule.bonds[bond].other(atom)))
+            .filter(|&(_, child)| !placed[child])
+            .collect();
+        // Triple bonds keep their neighbours in a straight line.
+        let linear = molecule
+            .bonds_of(atom)
+            .iter()
+            .any(|&bond| molecule.bonds[bond].order == BondOrder::Triple);
+        // The main chain is the last child; branches bend away from it.
+        let turns: Vec<(f64, f64)> = match children.len() {
+            1 if linear => vec![(0.0, flip)],
+            1 => vec![(flip * 60.0, -flip)],
+            2 => vec![(-flip * 60.0, flip), (flip * 60.0, -flip)],
+            3 => vec![(90.0, 1.0), (-90.0, -1.0), (0.0, -flip)],
+            n => (0..n)
+                .map(|i| (-180.0 + 360.0 * (i + 1) as f64 / (n + 1) as f64, 1.0))
+                .collect(),
+        };
+        for ((_, child), (turn, next_flip)) in children.into_iter().zip(turns) {
+            if placed[child] {
+                continue;
+            }
+            self.step(molecule, atom, child, d + turn);
+            self.visit(molecule, child, Some((d + turn, next_flip)), placed);
+        }
+    }
+
+    /// Puts a whole ring system in place, entered at `entry` and extending
+    /// in the `incoming` direction, then its substituents, pointing away
+    /// from the rings.
+    fn place_system(
+        &self,
+        molecule: &mut Molecule,
+        system: usize,
+        entry: usize,
+        incoming: Option<f64>,
+        placed: &mut [bool],
+    ) {
+        let template = &self.systems[system];
+        let origin = template[&entry];
+        let count = template.len() as f64;
+        let centre = template
+            .values()
+            .fold((0.0, 0.0), |(x, y), p| (x + p.0 / count, y + p.1 / count));
+        let rotation = match incoming {
+            Some(direction) => {
+                direction.to_radians() - (centre.1 - origin.1).atan2(centre.0 - origin.0)
+            }
+            None => 0.0,
+        };
+        let (sin, cos) = rotation.sin_cos();
+        let (ex, ey) = (molecule.atoms[entry].x, molecule.atoms[entry].y);
+        let mut members: Vec<usize> = template.keys().copied().collect();
+        members.sort_unstable();
+        for &atom in &members {
+            let (dx, dy) = (template[&atom].0 - origin.0, template[&atom].1 - origin.1);
+            molecule.atoms[atom].x = ex + dx * cos - dy * sin;
+            molecule.atoms[atom].y = ey + dx * sin + dy * cos;
+            placed[atom] = true;
+        }
+
+        for &atom in &members {
+            let (x, y) = (molecule.atoms[atom].x, molecule.atoms[atom].y);
+            let mut away = (0.0, 0.0);
+            let mut substituents = Vec::new();
+            for bond in molecule.bonds_of(atom) {
+                let other = molecule.bonds[bond].other(atom);
+                if template.contains_key(&other) {
+                    away.0 += x - molecule.atoms[other].x;
+                    away.1 += y - molecule.atoms[other].y;
+                } else if !placed[other] {
+                    substituents.push(other);
+                }
+            }
+            let outward = away.1.atan2(away.0).to_degrees();
+            let count = substituents.len() as f64;
+            for (i, child) in substituents.into_iter().enumerate() {
+                if placed[child] {
+                    continue;
+                }
+                let direction = outward + (i as f64 - (count - 1.0) / 2.0) * 60.0;
+                self.step(molecule, atom, child, direction);
+                self.visit(molecule, child, Some((direction, 1.0)), placed);
+            }
+        }
+    }
+
+    /// Puts `to` one bond length from `from` in `direction` (degrees).
+    fn step(&self, molecule: &mut Molecule, from: usize, to: usize, direction: f64) {
+        let (sin, cos) = direction.to_radians().sin_cos();
+        molecule.atoms[to].x = molecule.atoms[from].x + cos;
+        molecule.atoms[to].y = molecule.atoms[from].y + sin;
+    }
+}
+
+/// A smallest set of smallest rings: the shortest ring through every bond is
+/// a candidate, and candidates are taken by size as long as they are
+/// independent of the ones taken (as bond sets over GF(2)). There are as
+/// many rings as the SMILES string closed.
+fn smallest_rings(molecule: &Molecule, count: usize) -> Vec<Vec<usize>> {
+    let mut candidates: Vec<Vec<usize>> = (0..molecule.bonds.len())
+        .filter_map(|bond| smallest_ring(molecule, bond))
+        .collect();
+    candidates.sort_by_key(Vec::len);
+
+    let mut basis: Vec<(usize, Vec<bool>)> = Vec::new();
+    let mut rings = Vec::new();
+    for ring in candidates {
+        if rings.len() == count {
+            break;
+        }
+        let mut bonds = vec![false; molecule.bonds.len()];
+        for (i, &atom) in ring.iter().enumerate() {
+            let next = ring[(i + 1) % ring.len()];
+            if let Some(bond) = molecule
+                .bonds_of(atom)
+                .into_iter()
+                .find(|&b| molecule.bonds[b].other(atom) == next)
+            {
+                bonds[bond] = true;
+            }
+        }
+        for (pivot, vector) in &basis {
+            if bonds[*pivot] {
+                bonds.iter_mut().zip(vector).for_each(|(b, v)| *b ^= v);
+            }
+        }
+        if let Some(pivot) = bonds.iter().position(|&b| b) {
+            basis.push((pivot, bonds));
+            rings.push(ring);
+        }
+    }
+    rings
+}
+
+/// Atoms of the smallest ring through `bond`, in ring order starting at one
+/// of its ends: a breadth-first search for the other end without it.
+fn smallest_ring(molecule: &Molecule, bond: usize) -> Option<Vec<usize>> {
+    let (start, goal) = (molecule.bonds[bond].a, molecule.bonds[bond].b);
+    let mut previous: HashMap<usize, usize> = HashMap::from([(start, start)]);
+    let mut queue = std::collections::VecDeque::from([start]);
+    while let Some(atom) = queue.pop_front() {
+        if atom == goal {
+            let mut ring = vec![goal];
+            while *ring.last()? != start {
+                ring.push(previous[ring.last()?]);
+            }
+            return Some(ring);
+        }
+        for other_bond in molecule.bonds_of(atom) {
+            let next = molecule.bonds[other_bond].other(atom);
+            if other_bond != bond && !previous.contains_key(&next) {
+                previous.insert(next, atom);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// Lays out the rings of one system in a common frame: the first as a
+/// regular polygon, then each ring touching the placed ones, fused rings on
+/// the far side of their shared edge and spiro rings beyond their shared
+/// atom.
+fn build_system(rings: &[&Vec<usize>]) -> HashMap<usize, Point> {
+    let mut positions: HashMap<usize, Point> = HashMap::new();
+    let mut done = vec![false; rings.len()];
+    while let Some(index) = (0..rings.len()).filter(|&i| !done[i]).max_by_key(|&i| {
+        let shared = rings[i]
+            .iter()
+            .filter(|a| positions.contains_key(a))
+            .count();
+        // Ties go to the ring written first.
+        (shared, std::cmp::Reverse(i))
+    }) {
+        done[index] = true;
+        let ring = rings[index];
+        let n = ring.len();
+        let step = std::f64::consts::TAU / n as f64;
+        let radius = 0.5 / (step / 2.0).sin();
+        let placed = |a: &usize| positions.contains_key(a);
+        let count = positions.len().max(1) as f64;
+        let centroid = positions
+            .values()
+            .fold((0.0, 0.0), |(x, y), p| (x + p.0 / count, y + p.1 / count));
+
+        let edge = (0..n).find(|&j| placed(&ring[j]) && placed(&ring[(j + 1) % n]));
+        let (first, centre, start_angle, step) = if let Some(j) = edge {
+            let (a, b) = (positions[&ring[j]], positions[&ring[(j + 1) % n]]);
+            let middle = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+            let mut normal = (a.1 - b.1, b.0 - a.0);
+            let length = (normal.0 * normal.0 + normal.1 * normal.1).sqrt().max(1e-9);
+            normal = (normal.0 / length, normal.1 / length);
+            if normal.0 * (middle.0 - centroid.0) + normal.1 * (middle.1 - centroid.1) < 0.0 {
+                normal = (-normal.0, -normal.1);
+            }
+            let apothem = 0.5 / (step / 2.0).tan();
+            let centre = (middle.0 + normal.0 * apothem, middle.1 + normal.1 * apothem);
+            let angle_a = (a.1 - centre.1).atan2(a.0 - centre.0);
+            let angle_b = (b.1 - centre.1).atan2(b.0 - centre.0);
+            let turn = (angle_b - angle_a).sin().signum() * step;
+            (j, centre, angle_a, turn)
+        } else if let Some(j) = (0..n).find(|&j| placed(&ring[j])) {
+            let shared = positions[&ring[j]];
+            let (dx, dy) = (shared.0 - centroid.0, shared.1 - centroid.1);
+            let length = (dx * dx + dy * dy).sqrt();
+            let (ux, uy) = if length > 1e-9 {
+                (dx / length, dy / length)
+            } else {
+                (1.0, 0.0)
+            };
+            let centre = (shared.0 + ux * radius, shared.1 + uy * radius);
+            (j, centre, (-uy).atan2(-ux), step)
+        } else {
+            (
+                0,
+                (0.0, 0.0),
+                -std::f64::consts::FRAC_PI_2 - step / 2.0,
+                step,
+            )
+        };
+
+        for k in 0..n {
+            let atom = ring[(first + k) % n];
+            let angle = start_angle + k as f64 * step;
+            positions.entry(atom).or_insert((
+                centre.0 + radius * angle.cos(),
+                centre.1 + radius * angle.sin(),
+            ));
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smiles_parse_into_atoms_bonds_and_rings() {
+        // SMILES, atoms, bonds, ring closures.
+        let cases = [
+            ("CCO", 3, 2, 0),
+            ("C1CCCCC1", 6, 6, 1),
+            ("c1ccccc1", 6, 6, 1),
+            ("C1CC2CCCC2C1", 8, 9, 2),
+            ("C%10CC%10", 3, 3, 1),
+            ("CC(=O)O", 4, 3, 0),
+            ("[NH4+].[Cl-]", 2, 0, 0),
+            ("C1CC=1", 3, 3, 1),
+        ];
+        for (smiles, atoms, bonds, closures) in cases {
+            let parsed = parse(smiles).unwrap();
+            assert_eq!(
+                (
+                    parsed.molecule.atoms.len(),
+                    parsed.molecule.bonds.len(),
+                    parsed.closures.len()
+                ),
+                (atoms, bonds, closures),
+                "{}",
+                smiles
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_smiles_are_errors() {
+        let cases = [
+            ("C11", "closed on the atom that opened it"),
+            ("C1C1", "already bonded"),
+            ("C1CC", "never closed"),
+            ("1CC", "Ring bond without an atom"),
+            ("C(C", "Unbalanced '('"),
+            ("CC)", "Unbalanced ')'"),
+            ("C%1C", "two digits after '%'"),
+            ("C$C", "Quadruple bonds"),
+            ("", "Empty SMILES"),
+        ];
+        for (smiles, message) in cases {
+            let error = parse(smiles).err().map(|e| e.to_string());
+            assert!(
+                error.as_deref().is_some_and(|e| e.contains(message)),
+                "{}: {:?}",
+                smiles,
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use crate::cli::Args;
+use crate::md2tex::escape;
+use crate::table::{self, Column};
+use anyhow::{Context, Result};
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage: chemtex spectrum <peaks.csv> [options]
+
+Draws an NMR or IR spectrum from a peak list as a pgfplots figure and prints
+the peak-assignment table. The CSV header names the columns:
+  shift (or wavenumber), intensity, multiplicity, J, integration, assignment
+of which only the first is required. Multiplicities are s, d, t, q, p, m,
+br and combinations such as dd or dt, with one coupling constant (Hz) per
+letter in J: `8.0 / 2.1`. For IR, intensities are numbers or s, m, w, and br
+in the multiplicity column widens the band.
+
+In sources, a line %%chemtex:spectrum(peaks.csv, mhz=400) is replaced by the
+output when packing.
+
+Options:
+  --kind nmr|ir      Spectrum type (default: ir when shifts exceed 400)
+  --nucleus N        NMR nucleus for labels (default: 1H)
+  --mhz N            Spectrometer frequency for multiplet splitting (default: 400)
+  --solvent TEXT     Solvent, named in the default caption
+  --as KIND          figure, table or both (default: both)
+  --caption TEXT     Figure caption
+  --label NAME       Labels fig:NAME and tab:NAME
+  --delimiter C      CSV field separator";
+
+const OPTIONS: &[&str] = &[
+    "kind",
+    "nucleus",
+    "mhz",
+    "solvent",
+    "as",
+    "caption",
+    "label",
+    "delimiter",
+];
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], OPTIONS)?;
+    let path = args.positional(0).context(USAGE)?;
+    print!("{}", render(Path::new(path), &args)?);
+    Ok(())
+}
+
+/// Expands `%%chemtex:spectrum(peaks.csv, mhz=400)`, the peak list relative
+/// to `dir`.
+pub fn directive(argument: &str, dir: &Path) -> Result<String> {
+    let (file, args) = table::directive_args(argument, &[], OPTIONS)?;
+    Ok(render(&dir.join(file), &args)?.trim_end().to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Nmr,
+    Ir,
+}
+
+struct Peak {
+    position: f64,
+    /// The position as written, for labels.
+    written: String,
+    intensity: Option<f64>,
+    multiplicity: String,
+    couplings: Vec<f64>,
+    integration: String,
+    assignment: String,
+}
+
+struct Spectrum {
+    kind: Kind,
+    nucleus: String,
+    mhz: f64,
+    solvent: Option<String>,
+    peaks: Vec<Peak>,
+}
+
+fn render(path: &Path, args: &Args) -> Result<String> {
+    let columns = table::load(path, args.value("delimiter"))?;
+    let find = |names: &[&str]| -> Option<&Column> {
+        columns
+            .iter()
+            .find(|c| names.contains(&c.name.to_lowercase().as_str()))
+    };
+    let position = find(&["shift", "δ", "delta", "ppm", "wavenumber", "nu", "ν"])
+        .or_else(|| columns.first())
+        .context("The peak list has no columns")?;
+    let intensity = find(&["intensity", "i", "height"]);
+    let multiplicity = find(&["multiplicity", "mult"]);
+    let couplings = find(&["j", "coupling"]);
+    let integration = find(&["integration", "integral", "h", "nh"]);
+    let assignment = find(&["assignment", "group"]);
+
+    let mut peaks = Vec::new();
+    for row in 0..position.len() {
+        let Some((value, _)) = position.number(row) else {
+            continue;
+        };
+        let text = |column: Option<&Column>| column.map_or("", |c| c.text(row)).to_string();
+        let intensity = intensity.and_then(|c| match c.text(row) {
+            "s" | "vs" => Some(80.0),
+            "m" => Some(50.0),
+            "w" | "vw" => Some(20.0),
+            other => other.parse().ok(),
+        });
+        let couplings = text(couplings)
+            .split(['/', ';', ' '])
+            .filter_map(|j| j.trim().parse().ok())
+            .collect();
+        peaks.push(Peak {
+            position: value,
+            written: position.text(row).to_string(),
+            intensity,
+            multiplicity: text(multiplicity),
+            couplings,
+            integration: text(integration),
+            assignment: text(assignment),
+        });
+    }
+    if peaks.is_empty() {
+        anyhow::bail!("{} lists no peaks", path.display());
+    }
+
+    let kind = match args.value("kind") {
+        Some("nmr") => Kind::Nmr,
+        Some("ir") => Kind::Ir,
+        Some(other) => anyhow::bail!("Unknown spectrum kind {:?}, expected nmr or ir", other),
+        None if peaks.iter().any(|p| p.position > 400.0) => Kind::Ir,
+        None => Kind::Nmr,
+    };
+    let spectrum = Spectrum {
+        kind,
+        nucleus: args.value("nucleus").unwrap_or("1H").to_string(),
+        mhz: args.parsed::<f64>("mhz")?.unwrap_or(400.0),
+        solvent: args.value("solvent").map(str::to_string),
+        peaks,
+    };
+    let label = args.value("label");
+    let mut out = String::new();
+    let what = args.value("as").unwrap_or("both");
+    if matches!(what, "figure" | "both") {
+        out.push_str(&spectrum.figure(args.value("caption"), label));
+    }
+    if matches!(what, "table" | "both") {
+        out.push_str(&spectrum.table(label));
+    }
+    if !matches!(what, "figure" | "table" | "both") {
+        anyhow::bail!("Unknown output {:?}, expected figure, table or both", what);
+    }
+    Ok(out)
+}
+
+impl Spectrum {
+    /// `$^{1}$H NMR` / `IR`.
+    fn title(&self) -> String {
+        match self.kind {
+            Kind::Ir => "IR".to_string(),
+            Kind::Nmr => {
+                let digits: String = self.nucleus.chars().filter(char::is_ascii_digit).collect();
+                let element: String = self
+                    .nucleus
+                    .chars()
+                    .filter(|c| !c.is_ascii_digit())
+                    .collect();
+                format!("$^{{{}}}${} NMR", digits, element)
+            }
+        }
+    }
+
+    fn figure(&self, caption: Option<&str>, label: Option<&str>) -> String {
+        let mut out = String::from("\\begin{figure}[h]\n\\centering\n\\begin{tikzpicture}\n");
+        let (low, high) = self.range();
+        match self.kind {
+            Kind::Nmr => {
+                out.push_str(&format!(
+                    "\\begin{{axis}}[width=\\linewidth, height=5cm, x dir=reverse, xmin={}, xmax={}, \
+                     xlabel={{$\\delta$ / ppm}}, ytick=\\empty, axis y line=none, axis x line*=bottom, ymin=0]\n",
+                    low, high
+                ));
+                out.push_str("\\addplot[ycomb, no markers] coordinates {\n");
+                for peak in &self.peaks {
+                    for (position, height) in self.lines(peak) {
+                        out.push_str(&format!("  ({:.4}, {:.3})\n", position, height));
+                    }
+                }
+                out.push_str("};\n");
+                for peak in &self.peaks {
+                    out.push_str(&format!(
+                        "\\node[above, font=\\tiny] at (axis cs:{}, {:.3}) {{{}}};\n",
+                        peak.position,
+                        self.height(peak),
+                        peak.written
+                    ));
+                }
+            }
+            Kind::Ir => {
+                out.push_str(&format!(
+                    "\\begin{{axis}}[width=\\linewidth, height=5cm, x dir=reverse, xmin={}, xmax={}, \
+                     xlabel={{$\\tilde{{\\nu}}$ / \\si{{\\per\\centi\\metre}}}}, ylabel={{$T$ / \\%}}, ymin=0, ymax=105]\n",
+                    low, high
+                ));
+                // Lorentzian bands below a 100 % baseline.
+                let bands: Vec<String> = self
+                    .peaks
+                    .iter()
+                    .map(|p| {
+                        let width = if p.multiplicity.contains("br") {
+                            60.0
+                        } else {
+                            12.0
+                        };
+                        format!("{}/(1+((x-{})/{})^2)", self.height(p), p.position, width)
+                    })
+                    .collect();
+                out.push_str(&format!(
+                    "\\addplot[domain={}:{}, samples=1200, no markers] {{100 - {}}};\n",
+                    low,
+                    high,
+                    bands.join(" - ")
+                ));
+                for peak in &self.peaks {
+                    out.push_str(&format!(
+                        "\\node[below, font=\\tiny] at (axis cs:{}, {:.1}) {{{}}};\n",
+                        peak.position,
+                        100.0 - self.height(peak),
+                        peak.written
+                    ));
+                }
+            }
+        }
+        out.push_str("\\end{axis}\n\\end{tikzpicture}\n");
+        let caption = caption.map(str::to_string).unwrap_or_else(|| {
+            let solvent = self
+                .solvent
+                .as_ref()
+                .map(|s| format!(", \\ce{{{}}}", s))
+                .unwrap_or_default();
+            match self.kind {
+                Kind::Nmr => format!("{} spectrum ({} MHz{})", self.title(), self.mhz, solvent),
+                Kind::Ir => format!(
+                    "{} spectrum{}",
+                    self.title(),
+                    solvent.replacen(", ", " in ", 1)
+                ),
+            }
+        });
+        out.push_str(&format!("\\caption{{{}}}\n", caption));
+        if let Some(label) = label {
+            out.push_str(&format!("\\label{{fig:{}}}\n", label));
+        }
+        out.push_str("\\end{figure}\n");
+        out
+    }
+
+    fn table(&self, label: Option<&str>) -> String {
+        let mut out = String::from("\\begin{table}[h]\n\\centering\n");
+        out.push_str(&format!("\\caption{{{} peak assignment}}\n", self.title()));
+        if let Some(label) = label {
+            out.push_str(&format!("\\label{{tab:{}}}\n", label));
+        }
+        match self.kind {
+            Kind::Nmr => {
+                out.push_str("\\begin{tabular}{S l l l l}\n\\toprule\n");
+                out.push_str(
+                    "{$\\delta$ / ppm} & Multiplicity & $J$ / Hz & Integration & Assignment \\\\\n\\midrule\n",
+                );
+                for peak in &self.peaks {
+                    let couplings: Vec<String> =
+                        peak.couplings.iter().map(|j| format!("{:.1}", j)).collect();
+                    out.push_str(&format!(
+                        "{} & {} & {} & {} & {} \\\\\n",
+                        peak.written,
+                        escape(&peak.multiplicity),
+                        couplings.join(", "),
+                        escape(&peak.integration),
+                        escape(&peak.assignment)
+                    ));
+                }
+            }
+            Kind::Ir => {
+                out.push_str("\\begin{tabular}{S l l}\n\\toprule\n");
+                out.push_str(
+                    "{$\\tilde{\\nu}$ / \\si{\\per\\centi\\metre}} & Intensity & Assignment \\\\\n\\midrule\n",
+                );
+                for peak in &self.peaks {
+                    let intensity = match peak.intensity {
+                        Some(i) if i >= 65.0 => "s",
+                        Some(i) if i >= 35.0 => "m",
+                        Some(_) => "w",
+                        None => "",
+                    };
+                    let shape = if peak.multiplicity.contains("br") {
+                        ", br"
+                    } else {
+                        ""
+                    };
+                    out.push_str(&format!(
+                        "{} & {}{} & {} \\\\\n",
+                        peak.written,
+                        intensity,
+                        shape,
+                        escape(&peak.assignment)
+                    ));
+                }
+            }
+        }
+        out.push_str("\\bottomrule\n\\end{tabular}\n\\end{table}\n");
+        out
+    }
+
+    /// Axis range: from 0 ppm to a whole ppm past the last peak for NMR,
+    /// 400–4000 for IR unless the peaks go beyond it.
+    fn range(&self) -> (f64, f64) {
+        let (low, high) = self.peaks.iter().fold((f64::MAX, f64::MIN), |(lo, hi), p| {
+            (lo.min(p.position), hi.max(p.position))
+        });
+        match self.kind {
+            Kind::Nmr => (low.floor().min(0.0), high.ceil() + 1.0),
+            Kind::Ir => (low.min(400.0), high.max(4000.0)),
+        }
+    }
+
+    /// Height of the tallest line of a peak: its intensity, else its
+    /// integration, else 1 (NMR) or 50 % (IR).
+    fn height(&self, peak: &Peak) -> f64 {
+        let integration = peak
+            .integration
+            .trim_end_matches('H')
+            .trim()
+            .parse::<f64>()
+            .ok();
+        match self.kind {
+            Kind::Nmr => peak.intensity.or(integration).unwrap_or(1.0),
+            Kind::Ir => peak.intensity.unwrap_or(50.0).clamp(0.0, 100.0),
+        }
+    }
+
+    /// Lines of a first-order multiplet: each letter splits every line by
+    /// its coupling constant with binomial intensities.
+    fn lines(&self, peak: &Peak) -> Vec<(f64, f64)> {
+        let mut lines = vec![(peak.position, 1.0)];
+        let counts: Vec<usize> = match peak.multiplicity.as_str() {
+            "quint" => vec![5],
+            "sext" => vec![6],
+            "sept" | "hept" => vec![7],
+            letters => letters
+                .chars()
+                .filter_map(|letter| match letter {
+                    'd' => Some(2),
+                    't' => Some(3),
+                    'q' => Some(4),
+                    'p' => Some(5),
+                    _ => None,
+                })
+                .collect(),
+        };
+        let mut couplings = peak.couplings.iter();
+        for count in counts {
+            let Some(&j) = couplings.next() else {
+                break;
+            };
+            let spacing = j / self.mhz;
+            let weights = binomial(count - 1);
+            lines = lines
+                .iter()
+                .flat_map(|&(position, height)| {
+                    weights.iter().enumerate().map(move |(k, weight)| {
+                        let offset = (k as f64 - (count - 1) as f64 / 2.0) * spacing;
+                        (position + offset, height * weight)
+                    })
+                })
+                .collect();
+        }
+        let tallest = lines.iter().map(|l| l.1).fold(0.0, f64::max);
+        let height = self.height(peak);
+        lines
+            .into_iter()
+            .map(|(position, h)| (position, h / tallest * height))
+            .collect()
+    }
+}
+
+/// Row `n` of Pascal's triangle.
+fn binomial(n: usize) -> Vec<f64> {
+    let mut row = vec![1.0];
+    for _ in 0..n {
+        let mut next = vec![1.0; row.len() + 1];
+        for i in 1..row.len() {
+            next[i] = row[i - 1] + row[i];
+        }
+        row = next;
+    }
+    row
+}
@@ -0,0 +1,227 @@
+use crate::batch;
+use crate::cli::Args;
+use crate::config::ProjectConfig;
+use crate::count;
+use crate::deps;
+use crate::elements;
+use crate::languages::Languages;
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const USAGE: &str = "\
+Usage: chemtex spell [main.tex|project_dir] [options]
+       chemtex spell --add WORD... [project_dir]
+
+Spell-checks the prose of a document with hunspell, skipping math, \\ce
+formulas, commands and the arguments that are not text (labels, citations,
+file names), and reports file:line findings.
+
+Besides the hunspell dictionaries, a bundled list of chemistry terms and
+element names is accepted, as is every word in the project's ignore list,
+.chemtex-words (one word per line; --add appends to it). Acronyms and words
+with digits are not checked.
+
+Options:
+  --dict LIST   hunspell dictionaries, e.g. en_GB,ru_RU (default: from
+                `lang` in .chemtex.toml, else en_US)
+  --add WORD    Add a word to the project's ignore list (repeatable)";
+
+/// The project's own words, next to `.chemtex.toml`.
+pub const IGNORE_FILE: &str = ".chemtex-words";
+
+const CHEMISTRY_WORDS: &str = include_str!("../data/chemistry-words.txt");
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["help"], &["dict", "add"])?;
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let input = PathBuf::from(args.positional(0).unwrap_or("."));
+    let (dir, main, config) = match input.is_dir() {
+        true => {
+            let config = ProjectConfig::find(&input)?.unwrap_or_default();
+            let main = match &config.main {
+                Some(main) => input.join(main),
+                None => batch::find_main_document(&input)?,
+            };
+            (input, main, config)
+        }
+        false => {
+            let dir = input.parent().unwrap_or(Path::new("")).to_path_buf();
+            let config = ProjectConfig::find(&dir)?.unwrap_or_default();
+            (dir, input, config)
+        }
+    };
+    let ignore_file = dir.join(IGNORE_FILE);
+
+    let added = args.values("add");
+    if !added.is_empty() {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&ignore_file)
+            .with_context(|| format!("Failed to open {}", ignore_file.display()))?;
+        for word in &added {
+            writeln!(file, "{}", word.trim())?;
+        }
+        println!("Added {} word(s) to {}", added.len(), ignore_file.display());
+        return Ok(());
+    }
+
+    let dictionaries = match (args.value("dict"), &config.lang) {
+        (Some(list), _) => list.to_string(),
+        (None, Some(lang)) => Languages::parse(lang, config.engine.as_deref())?
+            .dictionaries()
+            .join(","),
+        (None, None) => "en_US".to_string(),
+    };
+    let mut known = word_list(CHEMISTRY_WORDS);
+    known.extend(elements::names().map(str::to_lowercase));
+    if let Ok(text) = fs::read_to_string(&ignore_file) {
+        known.extend(word_list(&text));
+    }
+
+    let findings = check(&main, &dictionaries, &known)?;
+    for finding in &findings {
+        println!(
+            "{}:{}: {}",
+            finding.file.display(),
+            finding.line,
+            finding.word
+        );
+    }
+    if !findings.is_empty() {
+        let unique: BTreeSet<&str> = findings.iter().map(|f| f.word.as_str()).collect();
+        anyhow::bail!(
+            "{} misspelling(s) of {} word(s); add words that are right with `chemtex spell --add WORD`",
+            findings.len(),
+            unique.len()
+        );
+    }
+    println!("No misspellings in {}", main.display());
+    Ok(())
+}
+
+/// A word hunspell does not know, where it is.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub file: PathBuf,
+    pub line: usize,
+    pub word: String,
+}
+
+/// Checks the prose of `main` and the files it includes against the
+/// hunspell `dictionaries` (comma-separated), accepting `known` words
+/// (lowercase).
+pub fn check(main: &Path, dictionaries: &str, known: &HashSet<String>) -> Result<Vec<Finding>> {
+    let lines = deps::read_lines(main)?;
+    let mut text = String::new();
+    // Character offset of the start of every line.
+    let mut starts = Vec::with_capacity(lines.len());
+    let mut offset = 0;
+    for line in &lines {
+        starts.push(offset);
+        let code = deps::strip_comment(&line.text);
+        text.push_str(code);
+        text.push('\n');
+        offset += code.chars().count() + 1;
+    }
+    let (body, skipped) = match text.find("\\begin{document}") {
+        Some(start) => {
+            let end = start + "\\begin{document}".len();
+            (&text[end..], text[..end].chars().count())
+        }
+        None => (text.as_str(), 0),
+    };
+
+    let words = count::words(body);
+    let mut candidates = Vec::new();
+    for word in &words {
+        // Hyphenated compounds are checked part by part.
+        let mut part_offset = word.offset + skipped;
+        for part in word.text.split('-') {
+            if checkable(part) && !known.contains(&part.to_lowercase()) {
+                candidates.push((part_offset, part));
+            }
+            part_offset += part.chars().count() + 1;
+        }
+    }
+    let unique: BTreeSet<&str> = candidates.iter().map(|(_, word)| *word).collect();
+    if unique.is_empty() {
+        return Ok(Vec::new());
+    }
+    let misspelled = hunspell(dictionaries, &unique)?;
+
+    Ok(candidates
+        .into_iter()
+        .filter(|(_, word)| misspelled.contains(*word))
+        .map(|(offset, word)| {
+            let index = starts.partition_point(|&start| start <= offset) - 1;
+            Finding {
+                file: lines[index].file.clone(),
+                line: lines[index].number,
+                word: word.to_string(),
+            }
+        })
+        .collect())
+}
+
+/// Acronyms, single letters and words with digits are not checked.
+fn checkable(word: &str) -> bool {
+    word.chars().count() > 1
+        && word.chars().all(char::is_alphabetic)
+        && !word.chars().all(char::is_uppercase)
+}
+
+/// Lowercase words of a word list, skipping blank lines and `#` comments.
+fn word_list(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The `words` hunspell reports as misspelled.
+fn hunspell(dictionaries: &str, words: &BTreeSet<&str>) -> Result<HashSet<String>> {
+    let mut child = Command::new("hunspell")
+        .args(["-l", "-i", "utf-8", "-d", dictionaries])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("hunspell is not installed (https://hunspell.github.io)")?;
+    let mut input = String::new();
+    for word in words {
+        input.push_str(word);
+        input.push('\n');
+    }
+    let mut stdin = child
+        .stdin
+        .take()
+        .context("Failed to open hunspell's input")?;
+    // Written from a thread so a full output pipe cannot block the input.
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().context("Failed to run hunspell")?;
+    writer
+        .join()
+        .map_err(|_| anyhow::anyhow!("Failed to write to hunspell"))?
+        .context("Failed to write to hunspell")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "hunspell failed (are the {} dictionaries installed?): {}",
+            dictionaries,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
@@ -0,0 +1,429 @@
+use anyhow::Result;
+use std::cmp::Ordering;
+
+/// Writes SQLite 3 database files from scratch: tables and indexes filled
+/// once, for formats such as Anki's `.apkg` that ship a whole database.
+/// Nothing is read back or updated in place.
+#[derive(Debug, Default)]
+pub struct Database {
+    tables: Vec<Table>,
+    indexes: Vec<Index>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+#[derive(Debug)]
+struct Table {
+    name: String,
+    sql: String,
+    /// Whether the first column is an `integer primary key`, stored as the
+    /// rowid rather than in the record.
+    rowid_alias: bool,
+    rows: Vec<Vec<Value>>,
+}
+
+#[derive(Debug)]
+struct Index {
+    name: String,
+    table: String,
+    sql: String,
+    columns: Vec<usize>,
+}
+
+const PAGE_SIZE: usize = 4096;
+const HEADER_SIZE: usize = 100;
+const LEAF_TABLE: u8 = 0x0d;
+const INTERIOR_TABLE: u8 = 0x05;
+const LEAF_INDEX: u8 = 0x0a;
+const INTERIOR_INDEX: u8 = 0x02;
+/// Largest payload kept whole on a table leaf page; longer ones spill into
+/// overflow pages.
+const MAX_LOCAL: usize = PAGE_SIZE - 35;
+const MIN_LOCAL: usize = (PAGE_SIZE - 12) * 32 / 255 - 23;
+/// The same for index pages, whose entries here never need overflow.
+const MAX_LOCAL_INDEX: usize = (PAGE_SIZE - 12) * 64 / 255 - 23;
+
+impl Database {
+    /// Adds a table created by `sql`. With `rowid_alias`, the first value of
+    /// each row is its `integer primary key`; otherwise rows are numbered
+    /// from 1.
+    pub fn table(&mut self, name: &str, sql: &str, rowid_alias: bool, rows: Vec<Vec<Value>>) {
+        self.tables.push(Table {
+            name: name.to_string(),
+            sql: sql.to_string(),
+            rowid_alias,
+            rows,
+        });
+    }
+
+    /// Adds an index on `columns` (positions in the table's rows).
+    pub fn index(&mut self, name: &str, table: &str, sql: &str, columns: &[usize]) {
+        self.indexes.push(Index {
+            name: name.to_string(),
+            table: table.to_string(),
+            sql: sql.to_string(),
+            columns: columns.to_vec(),
+        });
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        // Page 1 holds the schema and is filled in last.
+        let mut pages = vec![vec![0; PAGE_SIZE]];
+        let mut schema = Vec::new();
+        for table in &self.tables {
+            let mut cells = Vec::with_capacity(table.rows.len());
+            for (i, row) in table.rows.iter().enumerate() {
+                let mut values = row.clone();
+                let rowid = match table.rowid_alias {
+                    true => {
+                        let Some(Value::Integer(id)) = row.first() else {
+                            anyhow::bail!("{}: row without an integer id", table.name);
+                        };
+                        values[0] = Value::Null;
+                        *id
+                    }
+                    false => i as i64 + 1,
+                };
+                cells.push((rowid, leaf_table_cell(&mut pages, rowid, &record(&values))));
+            }
+            cells.sort_by_key(|&(rowid, _)| rowid);
+            if let Some(pair) = cells.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                anyhow::bail!("{}: duplicate id {}", table.name, pair[0].0);
+            }
+            let root = build_table(&mut pages, cells)?;
+            schema.push(("table", &table.name, &table.name, &table.sql, root));
+        }
+        for index in &self.indexes {
+            let table = self
+                .tables
+                .iter()
+                .find(|t| t.name == index.table)
+                .ok_or_else(|| anyhow::anyhow!("Index {} on unknown table", index.name))?;
+            let mut keys: Vec<Vec<Value>> = table
+                .rows
+                .iter()
+                .enumerate()
+                .map(|(i, row)| {
+                    let rowid = match (table.rowid_alias, &row[0]) {
+                        (true, Value::Integer(id)) => *id,
+                        _ => i as i64 + 1,
+                    };
+                    let mut key: Vec<Value> =
+                        index.columns.iter().map(|&c| row[c].clone()).collect();
+                    key.push(Value::Integer(rowid));
+                    key
+                })
+                .collect();
+            keys.sort_by(|a, b| compare(a, b));
+            let entries = keys.iter().map(|key| record(key)).collect();
+            let root = build_index(&mut pages, entries)?;
+            schema.push(("index", &index.name, &index.table, &index.sql, root));
+        }
+
+        let mut cells = Vec::new();
+        for (i, (kind, name, table, sql, root)) in schema.into_iter().enumerate() {
+            let values = [
+                Value::Text(kind.to_string()),
+                Value::Text(name.clone()),
+                Value::Text(table.clone()),
+                Value::Integer(root.into()),
+                Value::Text(sql.clone()),
+            ];
+            let rowid = i as i64 + 1;
+            cells.push(leaf_table_cell(&mut pages, rowid, &record(&values)));
+        }
+        pages[0] = page(LEAF_TABLE, &cells, None, HEADER_SIZE)
+            .ok_or_else(|| anyhow::anyhow!("The schema does not fit on the first page"))?;
+        let header = file_header(pages.len());
+        pages[0][..HEADER_SIZE].copy_from_slice(&header);
+        Ok(pages.concat())
+    }
+}
+
+/// Builds a table b-tree from its leaf cells in rowid order; returns the
+/// root page number.
+fn build_table(pages: &mut Vec<Vec<u8>>, cells: Vec<(i64, Vec<u8>)>) -> Result<u32> {
+    // (page, largest rowid beneath it)
+    let mut level: Vec<(u32, i64)> = Vec::new();
+    for group in group_cells(cells, |(_, cell)| cell.len()) {
+        let last = group.last().map_or(0, |&(rowid, _)| rowid);
+        let cells: Vec<Vec<u8>> = group.into_iter().map(|(_, cell)| cell).collect();
+        level.push((push_page(pages, LEAF_TABLE, &cells, None)?, last));
+    }
+    while level.len() > 1 {
+        let children = std::mem::take(&mut level);
+        let mut groups = group_cells(children, |&(_, key)| 4 + varint(key as u64).len());
+        // An interior page needs a cell besides its right pointer.
+        let count = groups.len();
+        if count > 1 && groups[count - 1].len() == 1 {
+            let moved = groups[count - 2].pop().unwrap();
+            groups[count - 1].insert(0, moved);
+        }
+        for group in groups {
+            let (right, last) = *group.last().unwrap();
+            let cells: Vec<Vec<u8>> = group[..group.len() - 1]
+                .iter()
+                .map(|&(child, key)| [child.to_be_bytes().to_vec(), varint(key as u64)].concat())
+                .collect();
+            level.push((push_page(pages, INTERIOR_TABLE, &cells, Some(right))?, last));
+        }
+    }
+    Ok(level[0].0)
+}
+
+/// Builds an index b-tree from its records in key order; returns the root
+/// page number. Unlike a table, each entry is stored once: those between
+/// two pages move up into their parent.
+fn build_index(pages: &mut Vec<Vec<u8>>, entries: Vec<Vec<u8>>) -> Result<u32> {
+    if let Some(entry) = entries.iter().find(|e| e.len() > MAX_LOCAL_INDEX) {
+        anyhow::bail!("Index entry of {} bytes is too long", entry.len());
+    }
+    let cell = |entry: &Vec<u8>| [varint(entry.len() as u64), entry.clone()].concat();
+    let (groups, mut separators) = split_entries(entries, |e| cell(e).len());
+    let mut children = Vec::new();
+    for group in groups {
+        let cells: Vec<Vec<u8>> = group.iter().map(cell).collect();
+        children.push(push_page(pages, LEAF_INDEX, &cells, None)?);
+    }
+    while children.len() > 1 {
+        // Pair every child but the last with the separator after it.
+        let right = children.pop().unwrap();
+        let pairs: Vec<(u32, Vec<u8>)> = children.drain(..).zip(separators.drain(..)).collect();
+        let (groups, promoted) = split_entries(pairs, |(_, e)| 4 + cell(e).len());
+        // The child of a promoted pair is the right pointer of the page
+        // before it; the last page points at `right`.
+        let rights: Vec<u32> = promoted
+            .iter()
+            .map(|&(child, _)| child)
+            .chain([right])
+            .collect();
+        for (group, page_right) in groups.into_iter().zip(rights) {
+            let cells: Vec<Vec<u8>> = group
+                .iter()
+                .map(|(child, entry)| [child.to_be_bytes().to_vec(), cell(entry)].concat())
+                .collect();
+            children.push(push_page(pages, INTERIOR_INDEX, &cells, Some(page_right))?);
+        }
+        separators = promoted.into_iter().map(|(_, entry)| entry).collect();
+    }
+    Ok(children[0])
+}
+
+/// Splits `entries` into page-sized runs with one entry between each pair
+/// of runs, to be stored in the parent.
+fn split_entries<T>(entries: Vec<T>, size: impl Fn(&T) -> usize) -> (Vec<Vec<T>>, Vec<T>) {
+    let capacity = PAGE_SIZE - 12;
+    let mut groups = vec![Vec::new()];
+    let mut separators = Vec::new();
+    let mut used = 0;
+    let mut entries = entries.into_iter().peekable();
+    while let Some(entry) = entries.next() {
+        let cost = size(&entry) + 2;
+        let current = groups.last_mut().unwrap();
+        if !current.is_empty() && used + cost > capacity {
+            if entries.peek().is_some() {
+                separators.push(entry);
+                groups.push(Vec::new());
+                used = 0;
+            } else {
+                // Nothing would follow this separator: use the run's last.
+                separators.push(current.pop().unwrap());
+                groups.push(vec![entry]);
+                used = cost;
+            }
+            continue;
+        }
+        current.push(entry);
+        used += cost;
+    }
+    (groups, separators)
+}
+
+/// Splits `cells` into page-sized runs.
+fn group_cells<T>(cells: Vec<T>, size: impl Fn(&T) -> usize) -> Vec<Vec<T>> {
+    let capacity = PAGE_SIZE - 12;
+    let mut groups: Vec<Vec<T>> = vec![Vec::new()];
+    let mut used = 0;
+    for cell in cells {
+        let cost = size(&cell) + 2;
+        if !groups.last().unwrap().is_empty() && used + cost > capacity {
+            groups.push(Vec::new());
+            used = 0;
+        }
+        groups.last_mut().unwrap().push(cell);
+        used += cost;
+    }
+    groups
+}
+
+fn push_page(
+    pages: &mut Vec<Vec<u8>>,
+    kind: u8,
+    cells: &[Vec<u8>],
+    right: Option<u32>,
+) -> Result<u32> {
+    let page = page(kind, cells, right, 0)
+        .ok_or_else(|| anyhow::anyhow!("{} cells do not fit on a page", cells.len()))?;
+    pages.push(page);
+    Ok(pages.len() as u32)
+}
+
+/// A b-tree page whose header starts at `offset`, with the cells packed
+/// at its end.
+fn page(kind: u8, cells: &[Vec<u8>], right: Option<u32>, offset: usize) -> Option<Vec<u8>> {
+    let header = if right.is_some() { 12 } else { 8 };
+    let content: usize = cells.iter().map(Vec::len).sum();
+    if offset + header + 2 * cells.len() + content > PAGE_SIZE {
+        return None;
+    }
+    let mut page = vec![0; PAGE_SIZE];
+    let mut end = PAGE_SIZE;
+    for (i, cell) in cells.iter().enumerate() {
+        end -= cell.len();
+        page[end..end + cell.len()].copy_from_slice(cell);
+        let pointer = offset + header + 2 * i;
+        page[pointer..pointer + 2].copy_from_slice(&(end as u16).to_be_bytes());
+    }
+    page[offset] = kind;
+    page[offset + 3..offset + 5].copy_from_slice(&(cells.len() as u16).to_be_bytes());
+    // 0 stands for 65536, never the case with 4 KiB pages.
+    page[offset + 5..offset + 7].copy_from_slice(&(end as u16).to_be_bytes());
+    if let Some(right) = right {
+        page[offset + 8..offset + 12].copy_from_slice(&right.to_be_bytes());
+    }
+    Some(page)
+}
+
+/// A table leaf cell, moving the tail of a long payload into a chain of
+/// overflow pages.
+fn leaf_table_cell(pages: &mut Vec<Vec<u8>>, rowid: i64, payload: &[u8]) -> Vec<u8> {
+    let mut cell = varint(payload.len() as u64);
+    cell.extend(varint(rowid as u64));
+    if payload.len() <= MAX_LOCAL {
+        cell.extend_from_slice(payload);
+        return cell;
+    }
+    let spill = MIN_LOCAL + (payload.len() - MIN_LOCAL) % (PAGE_SIZE - 4);
+    let local = if spill <= MAX_LOCAL { spill } else { MIN_LOCAL };
+    cell.extend_from_slice(&payload[..local]);
+    let chunks: Vec<&[u8]> = payload[local..].chunks(PAGE_SIZE - 4).collect();
+    let first = pages.len() as u32 + 1;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let next = match i + 1 < chunks.len() {
+            true => first + i as u32 + 1,
+            false => 0,
+        };
+        let mut page = vec![0; PAGE_SIZE];
+        page[..4].copy_from_slice(&next.to_be_bytes());
+        page[4..4 + chunk.len()].copy_from_slice(chunk);
+        pages.push(page);
+    }
+    cell.extend(first.to_be_bytes());
+    cell
+}
+
+/// The record format: a header of serial types, then the values.
+fn record(values: &[Value]) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        match value {
+            Value::Null => types.extend(varint(0)),
+            Value::Integer(0) => types.extend(varint(8)),
+            Value::Integer(1) => types.extend(varint(9)),
+            Value::Integer(n) => {
+                let (serial, width) = match *n {
+                    n if i8::try_from(n).is_ok() => (1, 1),
+                    n if i16::try_from(n).is_ok() => (2, 2),
+                    n if (-(1 << 23)..1 << 23).contains(&n) => (3, 3),
+                    n if i32::try_from(n).is_ok() => (4, 4),
+                    n if (-(1 << 47)..1 << 47).contains(&n) => (5, 6),
+                    _ => (6, 8),
+                };
+                types.extend(varint(serial));
+                body.extend_from_slice(&n.to_be_bytes()[8 - width..]);
+            }
+            Value::Text(text) => {
+                types.extend(varint(2 * text.len() as u64 + 13));
+                body.extend_from_slice(text.as_bytes());
+            }
+        }
+    }
+    // The header size counts its own varint.
+    let mut size = types.len() + 1;
+    while varint(size as u64).len() + types.len() != size {
+        size += 1;
+    }
+    [varint(size as u64), types, body].concat()
+}
+
+/// SQLite's ordering for index keys with the BINARY collation.
+fn compare(a: &[Value], b: &[Value]) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Integer(_) => 1,
+            Value::Text(_) => 2,
+        }
+    }
+    for (x, y) in a.iter().zip(b) {
+        let order = match (x, y) {
+            (Value::Integer(x), Value::Integer(y)) => x.cmp(y),
+            (Value::Text(x), Value::Text(y)) => x.as_bytes().cmp(y.as_bytes()),
+            _ => rank(x).cmp(&rank(y)),
+        };
+        if order != Ordering::Equal {
+            return order;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Big-endian base-128 with a full ninth byte.
+fn varint(mut value: u64) -> Vec<u8> {
+    if value > 0x00ff_ffff_ffff_ffff {
+        let mut bytes = vec![0; 9];
+        bytes[8] = value as u8;
+        value >>= 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (value & 0x7f) as u8 | 0x80;
+            value >>= 7;
+        }
+        return bytes;
+    }
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+fn file_header(page_count: usize) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    let mut put = |offset: usize, value: u32| {
+        header[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    };
+    put(24, 1); // change counter
+    put(28, page_count as u32);
+    put(40, 1); // schema cookie
+    put(44, 4); // schema format
+    put(56, 1); // UTF-8
+    put(92, 1); // the page count is valid for change 1
+    put(96, 3_045_000);
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&(PAGE_SIZE as u16).to_be_bytes());
+    header[18] = 1;
+    header[19] = 1;
+    header[21] = 64;
+    header[22] = 32;
+    header[23] = 32;
+    header
+}
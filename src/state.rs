@@ -0,0 +1,167 @@
+use crate::cache::to_hex;
+use crate::deps::DependencyGraph;
+use crate::job::Job;
+use crate::storage;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const STATE_FILE: &str = "build-state.json";
+
+/// Fingerprints of the last successful build of every document, used to skip
+/// documents whose sources have not changed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildState {
+    documents: BTreeMap<String, BuiltDocument>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BuiltDocument {
+    fingerprint: String,
+    built_at: u64,
+}
+
+impl BuildState {
+    pub fn load_default() -> Result<Self> {
+        Ok(Self::load(storage::data_dir()?.join(STATE_FILE)))
+    }
+
+    /// The state saved at `path`. One that cannot be read is only a reason
+    /// to rebuild everything, so it is reported and taken as empty.
+    fn load(path: PathBuf) -> Self {
+        let loaded = match fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("Failed to parse build state: {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read build state: {}", path.display()))
+            }
+        };
+        let mut state = loaded.unwrap_or_else(|e| {
+            eprintln!("Warning: {:#}; rebuilding every document", e);
+            Self::default()
+        });
+        state.path = path;
+        state
+    }
+
+    /// Whether `job` built from these sources last time, and its PDF is
+    /// still there.
+    pub fn is_up_to_date(&self, job: &Job, fingerprint: &str) -> bool {
+        job.output.is_file()
+            && self
+                .documents
+                .get(&document_key(&job.input))
+                .is_some_and(|doc| doc.fingerprint == fingerprint)
+    }
+
+    pub fn record(&mut self, input: &Path, fingerprint: String) {
+        let built_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.documents.insert(
+            document_key(input),
+            BuiltDocument {
+                fingerprint,
+                built_at,
+            },
+        );
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            storage::ensure_dir(parent)?;
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        // Written aside and renamed, so an interrupted save leaves the
+        // previous state rather than half of one.
+        let tmp = self
+            .path
+            .with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&tmp, json)
+            .and_then(|()| fs::rename(&tmp, &self.path))
+            .with_context(|| format!("Failed to write build state: {}", self.path.display()))
+    }
+}
+
+/// Hash over the job's output path, its compile options and the contents of
+/// every file the document depends on.
+pub fn fingerprint(job: &Job) -> Result<String> {
+    let (input, options) = (&job.input, &job.options);
+    let graph = DependencyGraph::scan(input)?;
+    let mut files = graph.files;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    let output = job.output.to_string_lossy();
+    for field in [
+        output.as_ref(),
+        options.engine.as_deref().unwrap_or(""),
+        options.profile.as_deref().unwrap_or(""),
+    ] {
+        hasher.update((field.len() as u64).to_le_bytes());
+        hasher.update(field.as_bytes());
+    }
+    if let Some(mode) = options.mode {
+        hasher.update(mode.as_str().as_bytes());
+    }
+    for file in files {
+        let contents =
+            fs::read(&file).with_context(|| format!("Failed to read file: {}", file.display()))?;
+        let name = file.to_string_lossy();
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(Sha256::digest(&contents));
+    }
+    for missing in graph.missing {
+        hasher.update(missing.to_string_lossy().as_bytes());
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn document_key(input: &Path) -> String {
+    fs::canonicalize(input)
+        .unwrap_or_else(|_| input.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_without_their_pdf_are_rebuilt() {
+        let dir = std::env::temp_dir().join(format!("chemtex-state-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("lab.tex");
+        fs::write(&input, "\\documentclass{article}").unwrap();
+
+        let corrupt = dir.join("state.json");
+        fs::write(&corrupt, "{ not json").unwrap();
+        let mut state = BuildState::load(corrupt);
+        assert!(state.documents.is_empty());
+
+        let job = Job::new(&input, &dir.join("a")).unwrap();
+        let fingerprint = fingerprint(&job).unwrap();
+        let elsewhere = Job::new(&input, &dir.join("b")).unwrap();
+        assert_ne!(fingerprint, super::fingerprint(&elsewhere).unwrap());
+
+        state.record(&input, fingerprint.clone());
+        assert!(!state.is_up_to_date(&job, &fingerprint));
+        fs::create_dir_all(job.output.parent().unwrap()).unwrap();
+        fs::write(&job.output, "%PDF").unwrap();
+        assert!(state.is_up_to_date(&job, &fingerprint));
+
+        state.save().unwrap();
+        assert_eq!(BuildState::load(state.path.clone()).documents.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
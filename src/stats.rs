@@ -0,0 +1,363 @@
+use crate::api;
+use crate::cli::Args;
+use crate::history::{self, HistoryEntry};
+use crate::report::DocumentStatus;
+use anyhow::Result;
+use chrono::{DateTime, Local, TimeZone, Timelike};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_TOP: usize = 5;
+
+const USAGE: &str = "\
+Usage: chemtex stats [options]
+
+Options:
+  --since DAYS  Only count jobs from the last DAYS days
+  --top N       Rows in the busiest-hours and largest-upload tables (default 5)
+  --tag K=V     Only count jobs compiled with --tag K=V (repeatable)
+  --csv         Print one CSV row per job instead of the summary";
+
+/// Aggregates the local job history: compile times, failure classes, when the
+/// server queue is slowest and which uploads are largest.
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["csv", "help"], &["since", "top", "tag"])?;
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let top = args.parsed::<usize>("top")?.unwrap_or(DEFAULT_TOP);
+
+    let mut entries = history::load()?;
+    if let Some(days) = args.parsed::<u64>("since")? {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let cutoff = now.saturating_sub(days * 24 * 60 * 60);
+        entries.retain(|entry| entry.finished_at >= cutoff);
+    }
+    for spec in args.values("tag") {
+        let (key, value) = api::parse_tag(spec)?;
+        entries.retain(|entry| entry.document.tags.get(&key) == Some(&value));
+    }
+
+    if args.flag("csv") {
+        print_csv(&entries);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("No jobs recorded yet.");
+        return Ok(());
+    }
+    print_summary(&entries);
+    print_compile_times(&entries);
+    print_failures(&entries);
+    print_busiest_hours(&entries, top);
+    print_largest_uploads(&entries, top);
+    Ok(())
+}
+
+fn print_summary(entries: &[HistoryEntry]) {
+    let count = |status| {
+        entries
+            .iter()
+            .filter(|e| e.document.status == status)
+            .count()
+    };
+    let cached = entries.iter().filter(|e| e.document.cached).count();
+    let times = entries.iter().map(|e| e.finished_at);
+    let (first, last) = (times.clone().min(), times.max());
+    println!(
+        "{} job(s) from {} to {}: {} succeeded ({} cached), {} failed, {} cancelled",
+        entries.len(),
+        format_time(first),
+        format_time(last),
+        count(DocumentStatus::Ok),
+        cached,
+        count(DocumentStatus::Failed),
+        count(DocumentStatus::Cancelled),
+    );
+}
+
+fn print_compile_times(entries: &[HistoryEntry]) {
+    let mut rows = Vec::new();
+    let mut add = |label: &str, mut values: Vec<u64>| {
+        if values.is_empty() {
+            return;
+        }
+        values.sort_unstable();
+        let average = average(&values);
+        rows.push([
+            label.to_string(),
+            values.len().to_string(),
+            format_ms(average),
+            format_ms(percentile(&values, 50)),
+            format_ms(percentile(&values, 90)),
+            format_ms(percentile(&values, 99)),
+        ]);
+    };
+    add(
+        "Compile (server)",
+        entries
+            .iter()
+            .filter_map(|e| e.document.compile_ms)
+            .collect(),
+    );
+    add(
+        "Queue wait",
+        entries
+            .iter()
+            .filter_map(|e| e.document.phases.queue_ms)
+            .collect(),
+    );
+    add(
+        "Upload",
+        entries
+            .iter()
+            .filter_map(|e| e.document.phases.upload_ms)
+            .collect(),
+    );
+    add(
+        "Total",
+        entries
+            .iter()
+            .filter(|e| !e.document.cached)
+            .map(|e| e.document.elapsed_ms)
+            .collect(),
+    );
+
+    println!();
+    print_table(&["Timing", "Jobs", "Average", "p50", "p90", "p99"], &rows);
+}
+
+fn print_failures(entries: &[HistoryEntry]) {
+    let finished: Vec<&HistoryEntry> = entries
+        .iter()
+        .filter(|e| e.document.status != DocumentStatus::Cancelled)
+        .collect();
+    let mut classes: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in &finished {
+        if entry.document.status == DocumentStatus::Failed {
+            let class = entry.failure_class.as_deref().unwrap_or("unknown");
+            *classes.entry(class).or_default() += 1;
+        }
+    }
+    let failed: usize = classes.values().sum();
+
+    println!();
+    println!(
+        "Failure rate: {} ({} of {})",
+        format_rate(failed, finished.len()),
+        failed,
+        finished.len()
+    );
+    if failed == 0 {
+        return;
+    }
+    let mut classes: Vec<(&str, usize)> = classes.into_iter().collect();
+    classes.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    let rows: Vec<[String; 3]> = classes
+        .into_iter()
+        .map(|(class, count)| {
+            [
+                class.to_string(),
+                count.to_string(),
+                format_rate(count, finished.len()),
+            ]
+        })
+        .collect();
+    print_table(&["Failure class", "Jobs", "Rate"], &rows);
+}
+
+/// Local hours of the day ranked by average queue wait.
+fn print_busiest_hours(entries: &[HistoryEntry], top: usize) {
+    let mut hours: BTreeMap<u32, Vec<u64>> = BTreeMap::new();
+    for entry in entries {
+        if let (Some(queue_ms), Some(time)) = (entry.document.phases.queue_ms, local(entry)) {
+            hours.entry(time.hour()).or_default().push(queue_ms);
+        }
+    }
+    if hours.is_empty() {
+        return;
+    }
+    let mut hours: Vec<(u32, usize, u64)> = hours
+        .into_iter()
+        .map(|(hour, waits)| (hour, waits.len(), average(&waits)))
+        .collect();
+    hours.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+    let rows: Vec<[String; 3]> = hours
+        .into_iter()
+        .take(top)
+        .map(|(hour, jobs, average)| {
+            [
+                format!("{:02}:00-{:02}:59", hour, hour),
+                jobs.to_string(),
+                format_ms(average),
+            ]
+        })
+        .collect();
+
+    println!();
+    print_table(&["Busiest hours", "Jobs", "Avg queue wait"], &rows);
+}
+
+fn print_largest_uploads(entries: &[HistoryEntry], top: usize) {
+    let mut uploads: Vec<&HistoryEntry> = entries
+        .iter()
+        .filter(|e| e.document.upload_bytes.is_some())
+        .collect();
+    if uploads.is_empty() {
+        return;
+    }
+    uploads.sort_by_key(|e| std::cmp::Reverse(e.document.upload_bytes));
+    let rows: Vec<[String; 3]> = uploads
+        .into_iter()
+        .take(top)
+        .map(|e| {
+            [
+                e.document.name.clone(),
+                format_bytes(e.document.upload_bytes.unwrap_or_default()),
+                format_time(Some(e.finished_at)),
+            ]
+        })
+        .collect();
+
+    println!();
+    print_table(&["Largest uploads", "Size", "When"], &rows);
+}
+
+fn print_csv(entries: &[HistoryEntry]) {
+    println!(
+        "finished_at,name,input,status,failure_class,cached,task_id,elapsed_ms,compile_ms,\
+         pack_ms,upload_ms,queue_ms,processing_ms,download_ms,upload_bytes"
+    );
+    let number = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+    for entry in entries {
+        let doc = &entry.document;
+        let status = match doc.status {
+            DocumentStatus::Ok => "ok",
+            DocumentStatus::Failed => "failed",
+            DocumentStatus::Cancelled => "cancelled",
+        };
+        let fields = [
+            local(entry)
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default(),
+            doc.name.clone(),
+            doc.input.clone(),
+            status.to_string(),
+            entry.failure_class.clone().unwrap_or_default(),
+            doc.cached.to_string(),
+            doc.task_id.clone().unwrap_or_default(),
+            doc.elapsed_ms.to_string(),
+            number(doc.compile_ms),
+            number(doc.phases.pack_ms),
+            number(doc.phases.upload_ms),
+            number(doc.phases.queue_ms),
+            number(doc.phases.processing_ms),
+            number(doc.phases.download_ms),
+            number(doc.upload_bytes),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        println!("{}", row.join(","));
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn print_table<const N: usize>(headers: &[&str; N], rows: &[[String; N]]) {
+    let mut widths = headers.map(|h| h.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let format_row = |cells: [&str; N]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        line.join("  ").trim_end().to_string()
+    };
+    println!("{}", format_row(*headers));
+    for row in rows {
+        println!("{}", format_row(row.each_ref().map(String::as_str)));
+    }
+}
+
+/// The mean of `values`, not empty, summed wide enough that durations
+/// from a corrupt or hand-edited history cannot overflow.
+fn average(values: &[u64]) -> u64 {
+    let sum: u128 = values.iter().map(|&v| u128::from(v)).sum();
+    (sum / values.len() as u128) as u64
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn local(entry: &HistoryEntry) -> Option<DateTime<Local>> {
+    Local.timestamp_opt(entry.finished_at as i64, 0).single()
+}
+
+fn format_time(unix: Option<u64>) -> String {
+    let Some(unix) = unix else {
+        return "-".to_string();
+    };
+    Local
+        .timestamp_opt(unix as i64, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_else(|| unix.to_string())
+}
+
+fn format_ms(ms: u64) -> String {
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+fn format_rate(part: usize, total: usize) -> String {
+    if total == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}%", part as f64 * 100.0 / total as f64)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_and_percentiles_of_huge_values() {
+        assert_eq!(average(&[u64::MAX, u64::MAX, u64::MAX - 3]), u64::MAX - 1);
+        assert_eq!(average(&[1, 2]), 1);
+        let sorted: Vec<u64> = (1..=10).collect();
+        assert_eq!(percentile(&sorted, 50), 5);
+        assert_eq!(percentile(&sorted, 99), 10);
+        assert_eq!(percentile(&[7], 1), 7);
+    }
+}
@@ -0,0 +1,333 @@
+use crate::api::{self, CompileOptions};
+use crate::job::{Job, JobReport, Runner};
+use crate::lsp;
+use crate::progress::{Progress, ProgressEvent};
+use crate::redact;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::task::JoinHandle;
+
+const SHUTDOWN_FLUSH: Duration = Duration::from_millis(500);
+
+/// Newline-delimited JSON-RPC 2.0 over stdin/stdout for editor plugins.
+///
+/// Requests: `compile {input, output?, engine?, profile?}` → `{job}`,
+/// `cancel {job}`, `shutdown`. Notifications sent while a job runs:
+/// `progress {job, message}`, `status {job, status, queuePosition,
+/// durationMs}`, `diagnostics {job, diagnostics}` and
+/// `finished {job, success, output, taskId, error}`, plus LSP-shaped
+/// `textDocument/publishDiagnostics` for each affected source file.
+/// Messages without an `id` are notifications and get no reply.
+pub async fn run() -> Result<()> {
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = out_rx.recv().await {
+            let mut line = redact::json(message).to_string();
+            line.push('\n');
+            if stdout.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+            let _ = stdout.flush().await;
+        }
+    });
+
+    let (progress_tx, mut progress_rx) = mpsc::unbounded_channel::<(String, ProgressEvent)>();
+    let forward_tx = out_tx.clone();
+    tokio::spawn(async move {
+        while let Some((label, event)) = progress_rx.recv().await {
+            let _ = forward_tx.send(progress_notification(&label, event));
+        }
+    });
+
+    let mut runner = Runner::new(api::build_client()?, true)?;
+    runner.progress = Progress::channel(progress_tx);
+    runner.collect_diagnostics = true;
+
+    let session = Session {
+        runner,
+        out: out_tx,
+        jobs: Arc::new(Mutex::new(HashMap::new())),
+        published: Arc::new(Mutex::new(HashSet::new())),
+        next_id: 1,
+    };
+    session.serve().await?;
+
+    // Give the writer a moment to flush the final responses; background jobs
+    // may still hold senders, so don't wait for the channel to close.
+    let _ = tokio::time::timeout(SHUTDOWN_FLUSH, writer).await;
+    Ok(())
+}
+
+struct Session {
+    runner: Runner,
+    out: UnboundedSender<Value>,
+    jobs: Arc<Mutex<HashMap<u64, RunningJob>>>,
+    /// URIs that currently have diagnostics in the editor.
+    published: Arc<Mutex<HashSet<String>>>,
+    next_id: u64,
+}
+
+/// A running job's [`Job::id`], which its remote task goes by, and its
+/// handle.
+type RunningJob = (u64, JoinHandle<()>);
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    /// `None` for a notification; an explicit `null` id is a request.
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct CompileParams {
+    input: PathBuf,
+    output: Option<PathBuf>,
+    engine: Option<String>,
+    profile: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JobParams {
+    job: u64,
+}
+
+impl Session {
+    async fn serve(mut self) -> Result<()> {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Some(line) = lines.next_line().await.context("Failed to read stdin")? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let request: RpcRequest = match serde_json::from_str(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    self.send_error(Some(Value::Null), -32700, format!("Parse error: {}", e));
+                    continue;
+                }
+            };
+            let id = request.id.clone();
+
+            match request.method.as_str() {
+                "compile" => match serde_json::from_value::<CompileParams>(request.params) {
+                    Ok(params) => {
+                        if let Err(e) = self.compile(id.clone(), params) {
+                            self.send_error(id, -32602, format!("{:#}", e));
+                        }
+                    }
+                    Err(e) => self.send_error(id, -32602, format!("Invalid params: {}", e)),
+                },
+                "cancel" => match serde_json::from_value::<JobParams>(request.params) {
+                    Ok(params) => {
+                        let cancelled = self.cancel(params.job).await;
+                        self.send_result(id, json!({ "cancelled": cancelled }));
+                    }
+                    Err(e) => self.send_error(id, -32602, format!("Invalid params: {}", e)),
+                },
+                "shutdown" => {
+                    self.send_result(id, Value::Null);
+                    break;
+                }
+                other => self.send_error(id, -32601, format!("Method not found: {}", other)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a job, answering the request before any of its notifications.
+    fn compile(&mut self, request_id: Option<Value>, params: CompileParams) -> Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut job = Job::new(&params.input, Path::new(""))?;
+        job.name = id.to_string();
+        job.output = match params.output {
+            Some(output) => output,
+            None => params
+                .input
+                .parent()
+                .map(|dir| dir.join(&job.output))
+                .unwrap_or(job.output),
+        };
+        job.options = CompileOptions {
+            engine: params.engine,
+            profile: params.profile,
+            ..CompileOptions::default()
+        };
+
+        self.send_result(request_id, json!({ "job": id }));
+
+        let job_id = job.id;
+        let runner = self.runner.clone();
+        let out = self.out.clone();
+        let jobs = Arc::clone(&self.jobs);
+        let published = Arc::clone(&self.published);
+        // Hold the table while spawning so a job that finishes immediately
+        // cannot try to remove itself before it has been inserted.
+        let mut table = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let handle = tokio::spawn(async move {
+            let report = runner.run(&job, &job.name).await;
+            for message in publish_notifications(&report, &published) {
+                let _ = out.send(message);
+            }
+            for message in finished_notifications(id, &report) {
+                let _ = out.send(message);
+            }
+            jobs.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+        });
+        table.insert(id, (job_id, handle));
+        Ok(())
+    }
+
+    /// Stops the local job and asks the server to drop its task.
+    async fn cancel(&self, id: u64) -> bool {
+        let handle = self
+            .jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+        let Some((job_id, handle)) = handle else {
+            return false;
+        };
+        handle.abort();
+
+        if let Some(task) = self.runner.in_flight.take(job_id) {
+            if let Err(e) = api::cancel_task(&self.runner.client, &task).await {
+                let _ = self.out.send(notification(
+                    "progress",
+                    json!({ "job": id, "message": format!("Failed to cancel task {}: {:#}", task.id, e) }),
+                ));
+            }
+        }
+        let _ = self.out.send(notification(
+            "finished",
+            json!({ "job": id, "success": false, "cancelled": true }),
+        ));
+        true
+    }
+
+    /// Answers a request; notifications (no `id`) are not answered.
+    fn send_result(&self, id: Option<Value>, result: Value) {
+        let Some(id) = id else {
+            return;
+        };
+        let _ = self
+            .out
+            .send(json!({ "jsonrpc": "2.0", "id": id, "result": result }));
+    }
+
+    fn send_error(&self, id: Option<Value>, code: i64, message: String) {
+        let Some(id) = id else {
+            return;
+        };
+        let _ = self.out.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }));
+    }
+}
+
+/// Keeps a present `id`, `null` included, apart from a missing one.
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+fn progress_notification(label: &str, event: ProgressEvent) -> Value {
+    let job: Value = label
+        .parse::<u64>()
+        .map(Value::from)
+        .unwrap_or(json!(label));
+    match event {
+        ProgressEvent::Log(message) => {
+            notification("progress", json!({ "job": job, "message": message }))
+        }
+        ProgressEvent::Status {
+            status,
+            queue_position,
+            duration_ms,
+        } => notification(
+            "status",
+            json!({
+                "job": job,
+                "status": status,
+                "queuePosition": queue_position,
+                "durationMs": duration_ms,
+            }),
+        ),
+    }
+}
+
+/// `textDocument/publishDiagnostics` for every file touched by this build or
+/// an earlier one, so fixed errors disappear from the editor.
+fn publish_notifications(report: &JobReport, published: &Mutex<HashSet<String>>) -> Vec<Value> {
+    let mut published = published.lock().unwrap_or_else(|e| e.into_inner());
+    let payloads =
+        lsp::publish_diagnostics(&report.job.input, &report.details.diagnostics, &published);
+
+    published.clear();
+    payloads
+        .into_iter()
+        .map(|params| {
+            if !params.diagnostics.is_empty() {
+                published.insert(params.uri.clone());
+            }
+            notification(
+                "textDocument/publishDiagnostics",
+                serde_json::to_value(params).unwrap_or_default(),
+            )
+        })
+        .collect()
+}
+
+fn finished_notifications(id: u64, report: &JobReport) -> Vec<Value> {
+    vec![
+        notification(
+            "diagnostics",
+            json!({ "job": id, "diagnostics": report.details.diagnostics }),
+        ),
+        notification(
+            "finished",
+            json!({
+                "job": id,
+                "success": report.is_success(),
+                "output": report.result.as_ref().ok(),
+                "taskId": report.details.task_id,
+                "error": report.result.as_ref().err().map(|e| format!("{:#}", e)),
+            }),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_without_an_id_are_notifications() {
+        let parse = |line: &str| serde_json::from_str::<RpcRequest>(line).unwrap().id;
+        assert_eq!(parse(r#"{"jsonrpc":"2.0","method":"shutdown"}"#), None);
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":null,"method":"shutdown"}"#),
+            Some(Value::Null)
+        );
+        assert_eq!(
+            parse(r#"{"jsonrpc":"2.0","id":7,"method":"shutdown"}"#),
+            Some(json!(7))
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::balance::{self, Reaction, Species};
+use crate::cli::Args;
+use crate::elements;
+use crate::sigfigs::{self, round, sig_figs};
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+
+const USAGE: &str = "\
+Usage: chemtex stoich \"<reaction>\" --given FORMULA=AMOUNT... [options]
+
+Works out the limiting reagent and the theoretical (and percent) yield, and
+prints the solution as LaTeX (amsmath, siunitx and mhchem), e.g.
+  chemtex stoich \"Fe + O2 -> Fe2O3\" --given \"Fe=5.00 g\" --given \"O2=3.20 g\" \\
+      --actual \"6.10 g\"
+
+Amounts are masses (g, mg, kg) or amounts of substance (mol, mmol); reagents
+without --given are taken to be in excess.
+
+Options:
+  --given F=AMOUNT  Amount of the reagent F used (repeatable)
+  --product F       Product whose yield is wanted (default: the first one)
+  --actual AMOUNT   Amount of product obtained, for the percent yield
+  --sig-figs N      Significant figures of the results, 1 to 15 (default:
+                    as few as the least precise amount given)";
+
+/// As many figures as an `f64` holds.
+const MAX_SIG_FIGS: usize = sigfigs::MAX_FIGURES;
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &["given", "product", "actual", "sig-figs"])?;
+    let reaction = args.positional(0).context(USAGE)?;
+    let given = parse_given(&args.values("given"))?;
+    if given.is_empty() {
+        anyhow::bail!(
+            "Give the amount of at least one reagent with --given\n\n{}",
+            USAGE
+        );
+    }
+    let actual = args.value("actual").map(Amount::parse).transpose()?;
+    let sig_figs = match args.parsed::<usize>("sig-figs")? {
+        Some(n) => {
+            anyhow::ensure!(
+                (1..=MAX_SIG_FIGS).contains(&n),
+                "--sig-figs must be between 1 and {}",
+                MAX_SIG_FIGS
+            );
+            n
+        }
+        None => given
+            .iter()
+            .map(|(_, a)| a.sig_figs)
+            .chain(actual.as_ref().map(|a| a.sig_figs))
+            .min()
+            .unwrap_or(3)
+            .min(MAX_SIG_FIGS),
+    };
+    let problem = Problem {
+        reaction: Reaction::parse(reaction)?,
+        given,
+        product: args.value("product").map(str::to_string),
+        actual,
+        sig_figs,
+    };
+    print!("{}", problem.solve()?);
+    Ok(())
+}
+
+/// `--given` values, each reagent once.
+fn parse_given(specs: &[&str]) -> Result<Vec<(String, Amount)>> {
+    let mut given: Vec<(String, Amount)> = Vec::new();
+    for spec in specs {
+        let (formula, amount) = spec
+            .split_once('=')
+            .with_context(|| format!("Expected --given FORMULA=AMOUNT, got {:?}", spec))?;
+        let formula = formula.trim();
+        anyhow::ensure!(
+            given.iter().all(|(other, _)| other != formula),
+            "{} is given more than once",
+            formula
+        );
+        given.push((formula.to_string(), Amount::parse(amount)?));
+    }
+    Ok(given)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Gram,
+    Mole,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Amount {
+    /// In grams or moles.
+    value: f64,
+    unit: Unit,
+    sig_figs: usize,
+}
+
+impl Amount {
+    /// `5.00 g`, `250mg`, `0.10 mol`, `12.5 mmol`.
+    fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let split = text
+            .find(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E')
+            .with_context(|| format!("Amount {:?} needs a unit (g, mg, kg, mol, mmol)", text))?;
+        let (number, unit) = text.split_at(split);
+        let number = number.trim();
+        let value: f64 = number
+            .parse()
+            .with_context(|| format!("Bad amount {:?}", text))?;
+        let (unit, scale) = match unit.trim() {
+            "g" => (Unit::Gram, 1.0),
+            "mg" => (Unit::Gram, 1e-3),
+            "kg" => (Unit::Gram, 1e3),
+            "mol" => (Unit::Mole, 1.0),
+            "mmol" => (Unit::Mole, 1e-3),
+            other => anyhow::bail!("Unknown unit {:?} (use g, mg, kg, mol or mmol)", other),
+        };
+        if value <= 0.0 {
+            anyhow::bail!("Amount {:?} must be positive", text);
+        }
+        Ok(Self {
+            value: value * scale,
+            unit,
+            sig_figs: sig_figs(number),
+        })
+    }
+}
+
+struct Problem {
+    reaction: Reaction,
+    given: Vec<(String, Amount)>,
+    product: Option<String>,
+    actual: Option<Amount>,
+    sig_figs: usize,
+}
+
+impl Problem {
+    fn solve(&self) -> Result<String> {
+        let coefficients = self.reaction.coefficients()?;
+        let (reactant_coefficients, product_coefficients) =
+            coefficients.split_at(self.reaction.reactants.len());
+        let product_index = match &self.product {
+            Some(formula) => find(&self.reaction.products, formula, "product")?,
+            None => 0,
+        };
+        let product = &self.reaction.products[product_index];
+        let product_coefficient = product_coefficients[product_index];
+        let product_mass = molar_mass(product)?;
+
+        let number = |value: f64| round(value, self.sig_figs);
+        let mut out = String::new();
+        let equation = self.reaction.balanced()?;
+        writeln!(out, "% Worked solution for {}", equation)?;
+        writeln!(out, "\\[ {} \\]", equation)?;
+        writeln!(out, "\\begin{{align*}}")?;
+
+        // Amount of substance of every given reagent and its share per
+        // equation unit; the smallest share limits the reaction.
+        let mut limiting: Option<(usize, f64)> = None;
+        let mut shares = Vec::new();
+        for (formula, amount) in &self.given {
+            let index = find(&self.reaction.reactants, formula, "reagent")?;
+            let species = &self.reaction.reactants[index];
+            let moles = match amount.unit {
+                Unit::Mole => amount.value,
+                Unit::Gram => {
+                    let mass = molar_mass(species)?;
+                    let moles = amount.value / mass;
+                    writeln!(
+                        out,
+                        "  n({ce}) &= \\frac{{m({ce})}}{{M({ce})}} = \\frac{{\\SI{{{}}}{{\\gram}}}}{{\\SI{{{}}}{{\\gram\\per\\mole}}}} = \\SI{{{}}}{{\\mole}} \\\\",
+                        number(amount.value)?,
+                        grams_per_mole(mass),
+                        number(moles)?,
+                        ce = ce(&species.formula),
+                    )?;
+                    moles
+                }
+            };
+            let coefficient = reactant_coefficients[index];
+            let share = moles / coefficient as f64;
+            shares.push((index, moles, coefficient, share));
+            if limiting.is_none_or(|(_, smallest)| share < smallest) {
+                limiting = Some((index, share));
+            }
+        }
+        let Some((limiting, share)) = limiting else {
+            unreachable!("at least one reagent is given");
+        };
+        if shares.len() > 1 {
+            for (index, moles, coefficient, share) in &shares {
+                let ce = ce(&self.reaction.reactants[*index].formula);
+                match coefficient {
+                    1 => writeln!(out, "  n({}) &= \\SI{{{}}}{{\\mole}} \\\\", ce, number(*share)?)?,
+                    c => writeln!(
+                        out,
+                        "  \\frac{{n({})}}{{{}}} &= \\frac{{\\SI{{{}}}{{\\mole}}}}{{{}}} = \\SI{{{}}}{{\\mole}} \\\\",
+                        ce,
+                        c,
+                        number(*moles)?,
+                        c,
+                        number(*share)?
+                    )?,
+                }
+            }
+            writeln!(
+                out,
+                "  \\intertext{{The smallest ratio belongs to {}, the limiting reagent.}}",
+                ce(&self.reaction.reactants[limiting].formula)
+            )?;
+        }
+
+        let limiting_coefficient = reactant_coefficients[limiting];
+        let product_moles = share * product_coefficient as f64;
+        let product_ce = ce(&product.formula);
+        let ratio = match (product_coefficient, limiting_coefficient) {
+            (p, l) if p == l => String::new(),
+            (p, 1) => format!("{}\\,", p),
+            (p, l) => format!("\\frac{{{}}}{{{}}}\\,", p, l),
+        };
+        writeln!(
+            out,
+            "  n({}) &= {}n({}) = \\SI{{{}}}{{\\mole}} \\\\",
+            product_ce,
+            ratio,
+            ce(&self.reaction.reactants[limiting].formula),
+            number(product_moles)?
+        )?;
+        let theoretical = product_moles * product_mass;
+        write!(
+            out,
+            "  m_\\text{{theor}}({ce}) &= n({ce})\\,M({ce}) = \\SI{{{}}}{{\\mole}} \\times \\SI{{{}}}{{\\gram\\per\\mole}} = \\SI{{{}}}{{\\gram}}",
+            number(product_moles)?,
+            grams_per_mole(product_mass),
+            number(theoretical)?,
+            ce = product_ce,
+        )?;
+
+        if let Some(actual) = &self.actual {
+            let (obtained, expected, unit) = match actual.unit {
+                Unit::Gram => (actual.value, theoretical, "\\gram"),
+                Unit::Mole => (actual.value, product_moles, "\\mole"),
+            };
+            writeln!(out, " \\\\")?;
+            write!(
+                out,
+                "  \\text{{yield}} &= \\frac{{\\SI{{{}}}{{{unit}}}}}{{\\SI{{{}}}{{{unit}}}}} \\times \\SI{{100}}{{\\percent}} = \\SI{{{}}}{{\\percent}}",
+                number(obtained)?,
+                number(expected)?,
+                number(obtained / expected * 100.0)?,
+                unit = unit,
+            )?;
+        }
+        writeln!(out)?;
+        writeln!(out, "\\end{{align*}}")?;
+        Ok(out)
+    }
+}
+
+fn find(species: &[Species], formula: &str, what: &str) -> Result<usize> {
+    species
+        .iter()
+        .position(|s| s.formula == formula)
+        .with_context(|| {
+            let known: Vec<&str> = species.iter().map(|s| s.formula.as_str()).collect();
+            format!(
+                "No {} {:?} in the reaction (have: {})",
+                what,
+                formula,
+                known.join(", ")
+            )
+        })
+}
+
+/// Molar mass in g/mol.
+fn molar_mass(species: &Species) -> Result<f64> {
+    let mut mass = 0.0;
+    for (element, count) in &species.elements {
+        if element == balance::CHARGE {
+            continue;
+        }
+        let atomic = elements::atomic_mass(element)
+            .with_context(|| format!("Unknown element {:?} in {}", element, species.formula))?;
+        mass += atomic * *count as f64;
+    }
+    if mass == 0.0 {
+        anyhow::bail!("{} contains no atoms", species.formula);
+    }
+    Ok(mass)
+}
+
+/// Molar masses are quoted to two decimals, like in tables.
+fn grams_per_mole(mass: f64) -> String {
+    format!("{:.2}", (mass * 100.0).round() / 100.0)
+}
+
+fn ce(formula: &str) -> String {
+    format!("\\ce{{{}}}", formula)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(extra: &[&str]) -> Vec<String> {
+        ["Fe + O2 -> Fe2O3", "--given", "Fe=5.00 g"]
+            .iter()
+            .chain(extra)
+            .map(|arg| arg.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn significant_figures_are_bounded() {
+        for bad in ["0", "16", "400"] {
+            let error = run(&args(&["--sig-figs", bad])).unwrap_err();
+            assert!(error.to_string().contains("between 1 and 15"), "{}", bad);
+        }
+        let problem = Problem {
+            reaction: Reaction::parse("Fe + O2 -> Fe2O3").unwrap(),
+            given: parse_given(&["Fe=5.00 g"]).unwrap(),
+            product: None,
+            actual: None,
+            sig_figs: MAX_SIG_FIGS,
+        };
+        assert!(problem
+            .solve()
+            .unwrap()
+            .contains("\\SI{7.14867042707494}{\\gram}"));
+    }
+
+    #[test]
+    fn reagents_are_given_once() {
+        let error = parse_given(&["O2=3.20 g", "Fe=5.00 g", " O2 = 1 mol"]).unwrap_err();
+        assert_eq!(error.to_string(), "O2 is given more than once");
+        assert!(run(&args(&["--given", "Fe=2 mol"])).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Directory for persistent state (build state, history, reports).
+///
+/// `CHEMTEX_HOME` overrides everything; otherwise the platform's data
+/// directory is used (`$XDG_DATA_HOME/chemtex`, `~/.local/share/chemtex`,
+/// `%LOCALAPPDATA%\chemtex`). [`cache_dir`] follows the same rules.
+pub fn data_dir() -> Result<PathBuf> {
+    if let Some(home) = env::var_os("CHEMTEX_HOME") {
+        return Ok(PathBuf::from(home).join("data"));
+    }
+    platform_dir("XDG_DATA_HOME", &[".local", "share"])
+}
+
+/// Directory for disposable state (build cache, downloaded templates).
+///
+pub fn cache_dir() -> Result<PathBuf> {
+    if let Some(home) = env::var_os("CHEMTEX_HOME") {
+        return Ok(PathBuf::from(home).join("cache"));
+    }
+    platform_dir("XDG_CACHE_HOME", &[".cache"])
+}
+
+fn platform_dir(xdg_var: &str, home_fallback: &[&str]) -> Result<PathBuf> {
+    if let Some(dir) = env::var_os(xdg_var).filter(|d| !d.is_empty()) {
+        return Ok(PathBuf::from(dir).join("chemtex"));
+    }
+    if let Some(dir) = env::var_os("LOCALAPPDATA") {
+        return Ok(PathBuf::from(dir).join("chemtex"));
+    }
+    let home = env::var_os("HOME").context("Cannot determine home directory (set CHEMTEX_HOME)")?;
+    let mut dir = PathBuf::from(home);
+    dir.extend(home_fallback);
+    Ok(dir.join("chemtex"))
+}
+
+/// Writes `contents` readable by its owner only, for keys and tokens.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    // The mode applies to new files only; narrow an existing one too.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict {}", path.display()))?;
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn ensure_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_are_narrowed_when_they_exist_already() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = env::temp_dir().join(format!("chemtex-private-{}", std::process::id()));
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private(&path, b"key").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(contents, "key");
+    }
+}
@@ -0,0 +1,316 @@
+use crate::deps;
+use crate::diagnostics::{Diagnostic, Severity};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Commands and the package (with options) that defines them.
+const COMMANDS: &[(&str, &str, Option<&str>)] = &[
+    ("ce", "mhchem", Some("version=4")),
+    ("pu", "mhchem", Some("version=4")),
+    ("ch", "chemmacros", None),
+    ("chemfig", "chemfig", None),
+    ("chemname", "chemfig", None),
+    ("schemestart", "chemfig", None),
+    ("SI", "siunitx", None),
+    ("si", "siunitx", None),
+    ("num", "siunitx", None),
+    ("qty", "siunitx", None),
+    ("unit", "siunitx", None),
+    ("SIrange", "siunitx", None),
+    ("sisetup", "siunitx", None),
+    ("includegraphics", "graphicx", None),
+    ("toprule", "booktabs", None),
+    ("midrule", "booktabs", None),
+    ("bottomrule", "booktabs", None),
+    ("multirow", "multirow", None),
+    ("url", "url", None),
+    ("href", "hyperref", None),
+    ("autoref", "hyperref", None),
+    ("cref", "cleveref", None),
+    ("Cref", "cleveref", None),
+    ("citep", "natbib", None),
+    ("citet", "natbib", None),
+    ("eqref", "amsmath", None),
+    ("text", "amsmath", None),
+    ("dfrac", "amsmath", None),
+    ("mathbb", "amssymb", None),
+    ("degree", "gensymb", None),
+    ("celsius", "gensymb", None),
+    ("color", "xcolor", None),
+    ("textcolor", "xcolor", None),
+    ("tikz", "tikz", None),
+    ("captionof", "caption", None),
+    ("bm", "bm", None),
+    ("sout", "ulem", None),
+    ("uline", "ulem", None),
+];
+
+/// Environments and the package that defines them.
+const ENVIRONMENTS: &[(&str, &str, Option<&str>)] = &[
+    ("align", "amsmath", None),
+    ("align*", "amsmath", None),
+    ("gather", "amsmath", None),
+    ("multline", "amsmath", None),
+    ("cases", "amsmath", None),
+    ("pmatrix", "amsmath", None),
+    ("bmatrix", "amsmath", None),
+    ("tikzpicture", "tikz", None),
+    ("axis", "pgfplots", None),
+    ("longtable", "longtable", None),
+    ("tabularx", "tabularx", None),
+    ("wrapfigure", "wrapfig", None),
+    ("subfigure", "subcaption", None),
+    ("scheme", "chemstyle", None),
+    ("reaction", "chemmacros", None),
+    ("reactions", "chemmacros", None),
+    ("minted", "minted", None),
+    ("lstlisting", "listings", None),
+];
+
+/// What to do about a diagnostic, and the fix `--fix` can apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub message: String,
+    pub fix: Option<Fix>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fix {
+    /// Load a package in the preamble.
+    UsePackage {
+        package: &'static str,
+        options: Option<&'static str>,
+    },
+}
+
+impl Fix {
+    fn line(&self) -> String {
+        match self {
+            Self::UsePackage {
+                package,
+                options: Some(options),
+            } => format!("\\usepackage[{}]{{{}}}", options, package),
+            Self::UsePackage {
+                package,
+                options: None,
+            } => format!("\\usepackage{{{}}}", package),
+        }
+    }
+}
+
+/// A suggestion for an error TeX commonly stops with, if it is one.
+pub fn suggest(diagnostic: &Diagnostic) -> Option<Suggestion> {
+    if diagnostic.severity != Severity::Error {
+        return None;
+    }
+    let message = diagnostic.message.as_str();
+
+    if let Some(rest) = message.strip_prefix("Undefined control sequence \\") {
+        let command = rest.trim_end_matches('.');
+        let (_, package, options) = COMMANDS.iter().find(|(c, _, _)| *c == command)?;
+        return Some(Suggestion {
+            message: format!("\\{} is defined by the {} package", command, package),
+            fix: Some(Fix::UsePackage {
+                package,
+                options: *options,
+            }),
+        });
+    }
+    if let Some(environment) = between(message, "Environment ", " undefined") {
+        let (_, package, options) = ENVIRONMENTS.iter().find(|(e, _, _)| *e == environment)?;
+        return Some(Suggestion {
+            message: format!(
+                "the {} environment is defined by the {} package",
+                environment, package
+            ),
+            fix: Some(Fix::UsePackage {
+                package,
+                options: *options,
+            }),
+        });
+    }
+    if let Some(file) = between(message, "File `", "' not found") {
+        return Some(missing_file(file));
+    }
+    if message.starts_with("Missing $ inserted") {
+        return Some(Suggestion {
+            message: "a math command (_, ^, \\alpha...) is used outside math mode: wrap it in \
+                      $...$, or write formulas as \\ce{H2SO4}"
+                .to_string(),
+            fix: None,
+        });
+    }
+    if let Some(character) = between(message, "Unicode character ", " not set up") {
+        return Some(Suggestion {
+            message: format!(
+                "{} needs a font encoding for it: set lang = \"ru,en\" (or --lang) for Cyrillic, \
+                 or engine = \"xelatex\" in .chemtex.toml",
+                character
+            ),
+            fix: None,
+        });
+    }
+    if let Some(package) = between(message, "Option clash for package ", ".") {
+        return Some(Suggestion {
+            message: format!(
+                "{} is loaded twice with different options: load it once, with all of them",
+                package
+            ),
+            fix: None,
+        });
+    }
+    if message.starts_with("Too many }'s") || message.starts_with("Runaway argument") {
+        return Some(Suggestion {
+            message: "the braces do not balance near this line".to_string(),
+            fix: None,
+        });
+    }
+    None
+}
+
+fn missing_file(file: &str) -> Suggestion {
+    let (stem, extension) = file.rsplit_once('.').unwrap_or((file, ""));
+    match extension {
+        "sty" | "cls" => {
+            let kind = if extension == "sty" {
+                "package"
+            } else {
+                "class"
+            };
+            let known = COMMANDS
+                .iter()
+                .chain(ENVIRONMENTS)
+                .map(|(_, package, _)| *package)
+                .filter(|package| *package != stem)
+                .min_by_key(|package| distance(package, stem))
+                .filter(|package| distance(package, stem) <= 2);
+            let message = match known {
+                Some(package) => {
+                    format!("there is no {} {}; did you mean {}?", kind, stem, package)
+                }
+                None => format!(
+                    "the compiler has no {} {}: put {} next to the main file and it is packed \
+                     with the project",
+                    kind, stem, file
+                ),
+            };
+            Suggestion { message, fix: None }
+        }
+        _ => Suggestion {
+            message: format!(
+                "{} is not in the project: check the path (it is relative to the main file, \
+                 and case-sensitive on the server)",
+                file
+            ),
+            fix: None,
+        },
+    }
+}
+
+fn between<'a>(text: &'a str, prefix: &str, suffix: &str) -> Option<&'a str> {
+    let start = text.find(prefix)? + prefix.len();
+    let end = start + text[start..].find(suffix)?;
+    Some(&text[start..end])
+}
+
+/// Levenshtein distance, for near-miss package names.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Prints a suggestion for every diagnostic that has one and, with `fix`,
+/// applies the fixes to the sources of `main`. Returns whether anything was
+/// fixed.
+pub fn report(diagnostics: &[Diagnostic], main: Option<&Path>, fix: bool) -> Result<bool> {
+    let mut fixes: Vec<Fix> = Vec::new();
+    for diagnostic in diagnostics {
+        let Some(suggestion) = suggest(diagnostic) else {
+            continue;
+        };
+        let location = match (&diagnostic.file, diagnostic.line) {
+            (Some(file), Some(line)) => format!("{}:{}: ", file, line),
+            (None, Some(line)) => format!("line {}: ", line),
+            _ => String::new(),
+        };
+        eprintln!("Hint: {}{}", location, suggestion.message);
+        if let Some(suggested) = suggestion.fix {
+            if !fixes.contains(&suggested) {
+                fixes.push(suggested);
+            }
+        }
+    }
+    if fixes.is_empty() {
+        return Ok(false);
+    }
+    let Some(main) = main.filter(|main| main.extension().and_then(|e| e.to_str()) == Some("tex"))
+    else {
+        return Ok(false);
+    };
+    if !fix {
+        eprintln!("Run again with --fix to add the missing \\usepackage lines");
+        return Ok(false);
+    }
+    let mut fixed = false;
+    for suggested in &fixes {
+        if let Some(file) = apply(main, suggested)? {
+            println!("Added {} to {}", suggested.line(), file.display());
+            fixed = true;
+        }
+    }
+    Ok(fixed)
+}
+
+/// Inserts the fix after the last `\usepackage` of the preamble (which may
+/// be in a file the main file inputs), or after `\documentclass`. Returns the
+/// file changed, or `None` when the package is loaded already.
+fn apply(main: &Path, fix: &Fix) -> Result<Option<PathBuf>> {
+    let Fix::UsePackage { package, .. } = fix;
+    let lines = deps::read_lines(main)?;
+    let mut anchor = None;
+    for line in &lines {
+        let code = deps::strip_comment(&line.text);
+        if code.contains("\\begin{document}") {
+            break;
+        }
+        let loaded = deps::command_arguments(code, "\\usepackage")
+            .iter()
+            .chain(&deps::command_arguments(code, "\\RequirePackage"))
+            .any(|names| names.split(',').any(|name| name.trim() == *package));
+        if loaded {
+            return Ok(None);
+        }
+        if code.contains("\\usepackage") || (anchor.is_none() && code.contains("\\documentclass")) {
+            anchor = Some(line);
+        }
+    }
+    let anchor = anchor
+        .with_context(|| format!("No preamble in {} to add {} to", main.display(), fix.line()))?;
+
+    let text = fs::read_to_string(&anchor.file)
+        .with_context(|| format!("Failed to read file: {}", anchor.file.display()))?;
+    let mut result = String::with_capacity(text.len() + 40);
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        result.push_str(line);
+        if index + 1 == anchor.number {
+            if !line.ends_with('\n') {
+                result.push('\n');
+            }
+            result.push_str(&fix.line());
+            result.push('\n');
+        }
+    }
+    fs::write(&anchor.file, result)
+        .with_context(|| format!("Failed to write {}", anchor.file.display()))?;
+    Ok(Some(anchor.file.clone()))
+}
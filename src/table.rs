@@ -0,0 +1,506 @@
+use crate::cli::Args;
+use crate::md2tex::escape;
+use crate::sigfigs::{round, MAX_FIGURES};
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+const USAGE: &str = "\
+Usage: chemtex table <data.csv> [options]
+
+Prints the CSV file as a booktabs table with siunitx S columns, e.g.
+  chemtex table titration.csv --sig-figs 3 --units \"c(mol/L),V(mL)\"
+
+The first row holds the column names, optionally with units: `V (mL)`.
+Values may carry uncertainties (`1.23 +- 0.02`, `1.23 ± 0.02`), or a column
+named `±x` or `u(x)` holds the uncertainties of column x. Decimal commas
+(`0,25`) read as points. In sources, a line
+  %%chemtex:table(data.csv, sig-figs=3, units=\"c(mol/L),V(mL)\")
+is replaced by the table when packing, the path relative to the source.
+
+Options:
+  --sig-figs N     Round values to N significant figures, 1 to 15
+  --units LIST     Units by column, NAME(UNIT) separated by commas
+  --caption TEXT   Table caption
+  --label NAME     Label, referenced as tab:NAME
+  --delimiter C    Field separator (default: , or ; whichever the header uses)";
+
+const OPTIONS: &[&str] = &["sig-figs", "units", "caption", "label", "delimiter"];
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], OPTIONS)?;
+    let path = args.positional(0).context(USAGE)?;
+    print!("{}", render(Path::new(path), &args)?);
+    Ok(())
+}
+
+/// Expands `%%chemtex:table(data.csv, sig-figs=3, units="c(mol/L)")`, the
+/// CSV path relative to `dir`.
+pub fn directive(argument: &str, dir: &Path) -> Result<String> {
+    let (file, args) = directive_args(argument, &[], OPTIONS)?;
+    let table = render(&dir.join(file), &args)?;
+    Ok(table.trim_end().to_string())
+}
+
+/// Splits the argument of a data directive, `file.csv, name=value, flag`,
+/// into the file and its options, parsed like the command line.
+pub fn directive_args(argument: &str, flags: &[&str], options: &[&str]) -> Result<(String, Args)> {
+    let mut parts = split_top_level(argument).into_iter();
+    let file = parts
+        .next()
+        .filter(|f| !f.is_empty())
+        .context("Missing data file")?;
+    let raw: Vec<String> = parts
+        .map(|option| match option.split_once('=') {
+            Some((name, value)) => format!("--{}={}", name.trim(), unquote(value.trim())),
+            None => format!("--{}", option),
+        })
+        .collect();
+    Ok((unquote(&file), Args::parse(&raw, flags, options)?))
+}
+
+/// The columns of a CSV file whose first row names them; the delimiter is
+/// detected unless given.
+pub fn load(path: &Path, delimiter: Option<&str>) -> Result<Vec<Column>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file: {}", path.display()))?;
+    let delimiter = match delimiter {
+        Some("\\t" | "tab") => '\t',
+        Some(d) => d.chars().next().context("Empty --delimiter")?,
+        None => detect_delimiter(&text),
+    };
+    let mut rows = parse_csv(&text, delimiter);
+    rows.retain(|row| row.iter().any(|cell| !cell.trim().is_empty()));
+    if rows.is_empty() {
+        anyhow::bail!("{} has no rows", path.display());
+    }
+    let header = rows.remove(0);
+    Ok(build_columns(&header, &rows))
+}
+
+fn render(path: &Path, args: &Args) -> Result<String> {
+    let mut columns = load(path, args.value("delimiter"))?;
+    let units = parse_units(args.value("units").unwrap_or(""))?;
+    let sig_figs = args.parsed::<usize>("sig-figs")?;
+    if let Some(n) = sig_figs {
+        anyhow::ensure!(
+            (1..=MAX_FIGURES).contains(&n),
+            "--sig-figs must be between 1 and {}",
+            MAX_FIGURES
+        );
+    }
+
+    for column in &mut columns {
+        if let Some((_, unit)) = units.iter().find(|(name, _)| *name == column.name) {
+            column.unit = Some(unit.clone());
+        }
+    }
+    for (name, _) in &units {
+        if !columns.iter().any(|c| &c.name == name) {
+            anyhow::bail!(
+                "--units names column {:?}, which {} lacks",
+                name,
+                path.display()
+            );
+        }
+    }
+
+    let mut out = String::from("\\begin{table}[h]\n\\centering\n");
+    if let Some(caption) = args.value("caption") {
+        out.push_str(&format!("\\caption{{{}}}\n", caption));
+    }
+    if let Some(label) = args.value("label") {
+        out.push_str(&format!("\\label{{tab:{}}}\n", label));
+    }
+    let cells = columns
+        .iter()
+        .map(|c| c.cells(sig_figs))
+        .collect::<Result<Vec<_>>>()?;
+    let specs: Vec<String> = columns
+        .iter()
+        .zip(&cells)
+        .map(|(column, cells)| match column.numeric {
+            true => format!("S[table-format={}]", table_format(cells)),
+            false => "l".to_string(),
+        })
+        .collect();
+    out.push_str(&format!(
+        "\\begin{{tabular}}{{{}}}\n\\toprule\n",
+        specs.join(" ")
+    ));
+    let headers: Vec<String> = columns.iter().map(Column::header).collect();
+    out.push_str(&format!("{} \\\\\n\\midrule\n", headers.join(" & ")));
+    for row in 0..columns[0].len() {
+        let line: Vec<&str> = cells.iter().map(|c| c[row].as_str()).collect();
+        out.push_str(&format!("{} \\\\\n", line.join(" & ")));
+    }
+    out.push_str("\\bottomrule\n\\end{tabular}\n\\end{table}\n");
+    Ok(out)
+}
+
+pub struct Column {
+    pub name: String,
+    pub unit: Option<String>,
+    values: Vec<String>,
+    /// Uncertainties from a separate `±x` / `u(x)` column.
+    uncertainties: Option<Vec<String>>,
+    pub numeric: bool,
+}
+
+impl Column {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The cell in `row` as written, trimmed.
+    pub fn text(&self, row: usize) -> &str {
+        self.values.get(row).map_or("", |v| v.trim())
+    }
+
+    /// The value in `row` and its uncertainty, if any; `None` for an empty
+    /// or non-numeric cell.
+    pub fn number(&self, row: usize) -> Option<(f64, Option<f64>)> {
+        let (value, uncertainty) = self.cell(row);
+        Some((
+            value.parse().ok()?,
+            uncertainty.and_then(|u| u.parse().ok()),
+        ))
+    }
+
+    /// The name in math mode: `c`, `V_1`, or `\text{pH}` for names that
+    /// are not quantity symbols.
+    pub fn symbol(&self) -> String {
+        let symbol = self.name.len() <= 3
+            && self.name != "pH"
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        match symbol {
+            true => self.name.clone(),
+            false => format!("\\text{{{}}}", escape(&self.name)),
+        }
+    }
+
+    /// `$c$ / \si{mol/L}`, for table headers and axis labels.
+    pub fn label(&self) -> String {
+        let name = format!("${}$", self.symbol());
+        match &self.unit {
+            Some(unit) => format!("{} / \\si{{{}}}", name, unit),
+            None => name,
+        }
+    }
+
+    fn header(&self) -> String {
+        match self.numeric {
+            true => format!("{{{}}}", self.label()),
+            false => self.label(),
+        }
+    }
+
+    /// The value in `row` and its uncertainty, inline or from the
+    /// uncertainty column.
+    fn cell(&self, row: usize) -> (&str, Option<&str>) {
+        let (value, uncertainty) = split_uncertainty(&self.values[row]);
+        let uncertainty = uncertainty.or_else(|| {
+            self.uncertainties
+                .as_ref()
+                .map(|u| u[row].trim())
+                .filter(|u| !u.is_empty())
+        });
+        (value, uncertainty)
+    }
+
+    fn cells(&self, sig_figs: Option<usize>) -> Result<Vec<String>> {
+        if !self.numeric {
+            return Ok(self.values.iter().map(|v| escape(v.trim())).collect());
+        }
+        (0..self.len())
+            .map(|row| match self.cell(row) {
+                ("", _) => Ok("{}".to_string()),
+                (value, uncertainty) => number_cell(value, uncertainty, sig_figs),
+            })
+            .collect()
+    }
+}
+
+/// Groups the CSV columns, folding uncertainty columns into the column
+/// they belong to.
+fn build_columns(header: &[String], rows: &[Vec<String>]) -> Vec<Column> {
+    let column = |index: usize| -> Vec<String> {
+        rows.iter()
+            .map(|row| decimal_point(row.get(index).map_or("", String::as_str)))
+            .collect()
+    };
+    let mut columns: Vec<Column> = Vec::new();
+    for (index, title) in header.iter().enumerate() {
+        let values = column(index);
+        // Before the unit split, which would read `u(c)` as `u` in `c`.
+        let target = uncertainty_of(title.trim()).map(|t| split_unit(t).0);
+        if let Some(column) = target.and_then(|t| columns.iter_mut().find(|c| c.name == t)) {
+            column.uncertainties = Some(values);
+            continue;
+        }
+        let numeric = values
+            .iter()
+            .map(|v| split_uncertainty(v).0)
+            .filter(|v| !v.is_empty())
+            .all(|v| v.parse::<f64>().is_ok());
+        let (name, unit) = split_unit(title.trim());
+        columns.push(Column {
+            name,
+            unit,
+            values,
+            uncertainties: None,
+            numeric,
+        });
+    }
+    columns
+}
+
+/// `V (mL)` / `V [mL]` → (`V`, `mL`).
+fn split_unit(title: &str) -> (String, Option<String>) {
+    for (open, close) in [('(', ')'), ('[', ']')] {
+        if let Some(inner) = title.strip_suffix(close) {
+            if let Some(start) = inner.rfind(open) {
+                if start > 0 {
+                    let unit = inner[start + 1..].trim().to_string();
+                    return (inner[..start].trim().to_string(), Some(unit));
+                }
+            }
+        }
+    }
+    (title.to_string(), None)
+}
+
+/// `±c`, `+-c` and `u(c)` name the uncertainties of `c`.
+fn uncertainty_of(name: &str) -> Option<&str> {
+    name.strip_prefix('±')
+        .or_else(|| name.strip_prefix("+-"))
+        .or_else(|| name.strip_prefix("u(").and_then(|n| n.strip_suffix(')')))
+        .map(str::trim)
+}
+
+/// `1,23` → `1.23`: a number with a decimal comma, as spreadsheets in many
+/// locales export it, is read like one with a point. Other cells are kept.
+fn decimal_point(value: &str) -> String {
+    if !value.contains(',') {
+        return value.to_string();
+    }
+    let pointed = value.replace(',', ".");
+    let (number, uncertainty) = split_uncertainty(&pointed);
+    let numeric =
+        number.parse::<f64>().is_ok() && uncertainty.is_none_or(|u| u.parse::<f64>().is_ok());
+    match numeric {
+        true => pointed,
+        false => value.to_string(),
+    }
+}
+
+/// `1.2e3` → (`1.2`, `3`).
+fn split_exponent(number: &str) -> (&str, Option<&str>) {
+    match number.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (number, None),
+    }
+}
+
+/// `1.23 +- 0.02` / `1.23 ± 0.02` → (`1.23`, `0.02`).
+fn split_uncertainty(value: &str) -> (&str, Option<&str>) {
+    for separator in ["±", "+-", "+/-"] {
+        if let Some((value, uncertainty)) = value.split_once(separator) {
+            return (value.trim(), Some(uncertainty.trim()));
+        }
+    }
+    (value.trim(), None)
+}
+
+/// A value for an `S` column, rounded if asked, with its uncertainty in
+/// compact form: `1.234 ± 0.02` → `1.23(2)`, `1.23e3 ± 20` → `1.23(2)e3`.
+fn number_cell(value: &str, uncertainty: Option<&str>, sig_figs: Option<usize>) -> Result<String> {
+    let value = match sig_figs {
+        Some(figures) => round(value.parse().unwrap_or(0.0), figures)?,
+        None => value.to_string(),
+    };
+    let Some(uncertainty) = uncertainty.and_then(|u| u.parse::<f64>().ok()) else {
+        return Ok(value);
+    };
+    let (mantissa, exponent) = split_exponent(&value);
+    let decimals = mantissa.split_once('.').map_or(0, |(_, f)| f.len()) as i32;
+    let place = decimals - exponent.and_then(|e| e.parse::<i32>().ok()).unwrap_or(0);
+    let digits = (uncertainty * 10f64.powi(place)).round().max(1.0);
+    Ok(match exponent {
+        Some(exponent) => format!("{}({})e{}", mantissa, digits, exponent),
+        None => format!("{}({})", value, digits),
+    })
+}
+
+/// siunitx `table-format` wide enough for every cell: `-12.50(3)` → `-2.2(1)`,
+/// `1.2(1)e-5` → `1.1(1)e-1`.
+fn table_format(cells: &[String]) -> String {
+    let (mut sign, mut integer, mut fraction, mut uncertainty) = (false, 1, 0, 0);
+    let (mut exponent_sign, mut exponent) = (false, 0);
+    for cell in cells {
+        let (cell, power) = split_exponent(cell);
+        if let Some(power) = power {
+            let digits = power.trim_start_matches(['-', '+']);
+            exponent_sign |= power.starts_with('-');
+            exponent = exponent.max(digits.len());
+        }
+        let (number, rest) = cell.split_once('(').unwrap_or((cell, ""));
+        if number == "{}" {
+            continue;
+        }
+        let number = match number.strip_prefix('-') {
+            Some(n) => {
+                sign = true;
+                n
+            }
+            None => number,
+        };
+        let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+        integer = integer.max(int.len());
+        fraction = fraction.max(frac.len());
+        uncertainty = uncertainty.max(rest.trim_end_matches(')').len());
+    }
+    let mut format = format!("{}{}.{}", if sign { "-" } else { "" }, integer, fraction);
+    if uncertainty > 0 {
+        format.push_str(&format!("({})", uncertainty));
+    }
+    if exponent > 0 {
+        let sign = if exponent_sign { "-" } else { "" };
+        format.push_str(&format!("e{}{}", sign, exponent));
+    }
+    format
+}
+
+/// `c(mol/L),V(mL)` → [(`c`, `mol/L`), (`V`, `mL`)].
+fn parse_units(list: &str) -> Result<Vec<(String, String)>> {
+    split_top_level(list)
+        .into_iter()
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (name, unit) = split_unit(&item);
+            let unit = unit.with_context(|| format!("Expected NAME(UNIT), got {:?}", item))?;
+            Ok((name, unit))
+        })
+        .collect()
+}
+
+/// Splits at commas outside quotes and brackets, trimming each part.
+pub fn split_top_level(text: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut depth = 0;
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '[' | '{' if !quoted => depth += 1,
+            ')' | ']' | '}' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        parts.last_mut().unwrap().push(c);
+    }
+    parts.iter().map(|p| p.trim().to_string()).collect()
+}
+
+pub fn unquote(text: &str) -> String {
+    text.strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(text)
+        .to_string()
+}
+
+fn detect_delimiter(text: &str) -> char {
+    let header = text.lines().next().unwrap_or("");
+    match header.matches(';').count() > header.matches(',').count() {
+        true => ';',
+        false => ',',
+    }
+}
+
+/// RFC 4180 fields: quoted fields may hold delimiters, newlines and `""`.
+fn parse_csv(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(values: &[&str], sig_figs: Option<usize>) -> Vec<String> {
+        let rows: Vec<Vec<String>> = values.iter().map(|v| vec![v.to_string()]).collect();
+        build_columns(&["x".to_string()], &rows)[0]
+            .cells(sig_figs)
+            .unwrap()
+    }
+
+    #[test]
+    fn decimal_commas_are_numbers() {
+        let rows = parse_csv("c (mol/L);V\n0,1025 ± 0,0004;12,5\n", ';');
+        let columns = build_columns(&rows[0], &rows[1..]);
+        assert!(columns.iter().all(|c| c.numeric));
+        assert_eq!(columns[0].cells(None).unwrap(), ["0.1025(4)"]);
+        assert_eq!(table_format(&columns[1].cells(None).unwrap()), "2.1");
+        assert!(!build_columns(&["who".to_string()], &[vec!["Smith, J".to_string()]])[0].numeric);
+    }
+
+    #[test]
+    fn exponents_keep_their_decimals() {
+        let values = cells(&["1.23e3 +- 20", "4.5e-3", "-6.0E2"], None);
+        assert_eq!(values, ["1.23(2)e3", "4.5e-3", "-6.0E2"]);
+        assert_eq!(table_format(&values), "-1.2(1)e-1");
+        assert_eq!(cells(&["123456"], Some(2)), ["1.2e5"]);
+        assert_eq!(
+            table_format(&cells(&["12.50 +- 0.03", "3"], None)),
+            "2.2(1)"
+        );
+    }
+    #[test]
+    fn sig_figs_must_fit_an_f64() {
+        let dir = std::env::temp_dir().join(format!("chemtex-table-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("data.csv"), "m (g)\n3.14159\n").unwrap();
+        let rendered = |figures: &str| directive(&format!("data.csv, sig-figs={}", figures), &dir);
+        let results = ["0", "16", "400", "3"].map(rendered);
+        fs::remove_dir_all(&dir).unwrap();
+        for result in &results[..3] {
+            let error = result.as_ref().unwrap_err().to_string();
+            assert!(error.contains("between 1 and 15"), "{}", error);
+        }
+        assert!(results[3].as_ref().unwrap().contains("3.14"));
+    }
+}
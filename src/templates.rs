@@ -0,0 +1,302 @@
+use crate::api;
+use crate::cache::to_hex;
+use crate::cli::Args;
+use crate::git::GitSource;
+use crate::pack::{self, TempDir};
+use crate::scaffold::TEMPLATES;
+use crate::storage;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Registries every `chemtex new` consults, comma-separated.
+const REGISTRY_ENV: &str = "CHEMTEX_TEMPLATE_REGISTRY";
+const SOURCE_FILE: &str = "source";
+pub const METADATA_FILE: &str = "template.toml";
+
+const USAGE: &str = "\
+Usage: chemtex templates list
+       chemtex templates update";
+
+/// A shared collection of project templates: a git repository (optionally
+/// `URL#branch:subdir`), a zip archive URL or a local directory. Every
+/// top-level folder containing a `.tex` file is a template; a registry whose
+/// root holds the `.tex` files is a single template named after the URL.
+/// An optional `template.toml` in a template folder sets its `description`.
+///
+/// Remote registries are cached under `<cache dir>/templates` and only
+/// fetched again by `chemtex templates update`.
+#[derive(Debug)]
+pub struct Registry {
+    pub source: String,
+    cache: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct RemoteTemplate {
+    pub name: String,
+    pub description: String,
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    description: String,
+}
+
+impl Registry {
+    pub fn new(source: &str) -> Result<Self> {
+        let digest = Sha256::digest(source.as_bytes());
+        let cache = storage::cache_dir()?
+            .join("templates")
+            .join(&to_hex(&digest)[..16]);
+        Ok(Self {
+            source: source.to_string(),
+            cache,
+        })
+    }
+
+    /// Every registry configured in `CHEMTEX_TEMPLATE_REGISTRY` or fetched
+    /// before with `--from`.
+    pub fn known() -> Result<Vec<Self>> {
+        let mut sources: Vec<String> = std::env::var(REGISTRY_ENV)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        if let Ok(entries) = fs::read_dir(storage::cache_dir()?.join("templates")) {
+            let mut cached: Vec<String> = entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| fs::read_to_string(e.path().join(SOURCE_FILE)).ok())
+                .map(|s| s.trim().to_string())
+                .collect();
+            cached.sort();
+            sources.extend(cached);
+        }
+        let mut unique: Vec<String> = Vec::new();
+        for source in sources {
+            if !unique.contains(&source) {
+                unique.push(source);
+            }
+        }
+        unique.iter().map(|s| Self::new(s)).collect()
+    }
+
+    fn is_local_dir(&self) -> bool {
+        Path::new(&self.source).is_dir()
+    }
+
+    /// Directory holding the registry's templates, fetching it first when it
+    /// is not cached yet or `refresh` is set.
+    pub async fn root(&self, client: &reqwest::Client, refresh: bool) -> Result<PathBuf> {
+        if self.is_local_dir() {
+            return Ok(PathBuf::from(&self.source));
+        }
+        let files = self.cache.join("files");
+        if refresh || !files.is_dir() {
+            self.fetch(client, &files).await?;
+        }
+        let subdir = GitSource::parse(&self.source)
+            .ok()
+            .and_then(|source| source.path);
+        Ok(match subdir {
+            Some(path) if !self.is_archive() => files.join(path),
+            _ => files,
+        })
+    }
+
+    fn is_archive(&self) -> bool {
+        self.source.to_ascii_lowercase().ends_with(".zip")
+    }
+
+    /// Downloads into a scratch directory and swaps it in, so a failed
+    /// update keeps the previous copy.
+    async fn fetch(&self, client: &reqwest::Client, files: &Path) -> Result<()> {
+        println!("Fetching templates from {}...", self.source);
+        let scratch = TempDir::new("templates")?;
+        let dest = scratch.path().join("files");
+        if self.is_archive() {
+            let archive =
+                if self.source.starts_with("http://") || self.source.starts_with("https://") {
+                    let bytes = client
+                        .get(&self.source)
+                        .send()
+                        .await
+                        .and_then(|r| r.error_for_status())
+                        .with_context(|| format!("Failed to download {}", self.source))?
+                        .bytes()
+                        .await
+                        .with_context(|| format!("Failed to download {}", self.source))?;
+                    let path = scratch.path().join("templates.zip");
+                    fs::write(&path, &bytes)
+                        .with_context(|| format!("Failed to write file: {}", path.display()))?;
+                    path
+                } else {
+                    PathBuf::from(&self.source)
+                };
+            pack::extract_archive(&archive, &dest)?;
+        } else {
+            let mut source = GitSource::parse(&self.source)?;
+            source.path = None;
+            source.clone_into(&dest)?;
+        }
+
+        storage::ensure_dir(&self.cache)?;
+        if files.exists() {
+            fs::remove_dir_all(files)
+                .with_context(|| format!("Failed to remove {}", files.display()))?;
+        }
+        fs::rename(&dest, files)
+            .or_else(|_| copy_dir(&dest, files))
+            .with_context(|| format!("Failed to update {}", files.display()))?;
+        fs::write(self.cache.join(SOURCE_FILE), &self.source)
+            .with_context(|| format!("Failed to write {}", self.cache.display()))
+    }
+
+    pub fn templates(&self, root: &Path) -> Result<Vec<RemoteTemplate>> {
+        if has_tex(root) {
+            return Ok(vec![RemoteTemplate {
+                name: self.default_name(),
+                description: metadata(root).description,
+                dir: root.to_path_buf(),
+            }]);
+        }
+        let mut templates: Vec<RemoteTemplate> = fs::read_dir(root)
+            .with_context(|| format!("Failed to read directory: {}", root.display()))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            // Not through symbolic links, which may point anywhere.
+            .filter(|p| fs::symlink_metadata(p).is_ok_and(|m| m.is_dir()))
+            .filter(|p| !is_hidden(p) && has_tex(p))
+            .map(|dir| RemoteTemplate {
+                name: dir
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                description: metadata(&dir).description,
+                dir,
+            })
+            .collect();
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(templates)
+    }
+
+    /// `https://git.example.edu/chem/lab-report.git` → `lab-report`.
+    fn default_name(&self) -> String {
+        let source = self.source.split('#').next().unwrap_or(&self.source);
+        let last = source
+            .trim_end_matches('/')
+            .rsplit(['/', '\\', ':'])
+            .next()
+            .unwrap_or(source);
+        last.trim_end_matches(".git")
+            .trim_end_matches(".zip")
+            .to_string()
+    }
+}
+
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &[], &[])?;
+    let client = api::build_client()?;
+    match args.positional(0) {
+        Some("list") => list(&client).await,
+        Some("update") => update(&client).await,
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+async fn list(client: &reqwest::Client) -> Result<()> {
+    println!("Built-in:");
+    for template in TEMPLATES {
+        println!("  {:<20} {}", template.name, template.description);
+    }
+    for registry in Registry::known()? {
+        println!();
+        println!("{}:", registry.source);
+        let templates = match registry.root(client, false).await {
+            Ok(root) => registry.templates(&root)?,
+            Err(e) => {
+                println!("  unavailable: {:#}", e);
+                continue;
+            }
+        };
+        for template in templates {
+            let line = format!("  {:<20} {}", template.name, template.description);
+            println!("{}", line.trim_end());
+        }
+    }
+    Ok(())
+}
+
+async fn update(client: &reqwest::Client) -> Result<()> {
+    let registries = Registry::known()?;
+    if registries.is_empty() {
+        println!(
+            "No template registries configured (set {} or use `chemtex new --from URL`)",
+            REGISTRY_ENV
+        );
+    }
+    let mut failed = 0;
+    for registry in registries.iter().filter(|r| !r.is_local_dir()) {
+        match registry.root(client, true).await {
+            Ok(root) => println!(
+                "{}: {} template(s)",
+                registry.source,
+                registry.templates(&root)?.len()
+            ),
+            Err(e) => {
+                eprintln!("{}: update failed: {:#}", registry.source, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} registry update(s) failed", failed);
+    }
+    Ok(())
+}
+
+fn metadata(dir: &Path) -> Metadata {
+    fs::read_to_string(dir.join(METADATA_FILE))
+        .ok()
+        .and_then(|text| toml::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn has_tex(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|entries| {
+        entries.filter_map(|e| e.ok()).any(|e| {
+            e.path()
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("tex"))
+        })
+    })
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
+
+/// Copies a fetched registry; symbolic links in it are left out.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            continue;
+        } else if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
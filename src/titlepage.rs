@@ -0,0 +1,193 @@
+use crate::deps::strip_comment;
+use serde::{Deserialize, Serialize};
+
+const BEGIN_DOCUMENT: &str = "\\begin{document}";
+
+/// A title page generated from `[titlepage]` in `.chemtex.toml`:
+///
+/// ```toml
+/// [titlepage]
+/// layout = "russian"
+/// university = "Lomonosov Moscow State University"
+/// department = "Department of Chemistry"
+/// course = "Physical Chemistry"
+/// author = "{{author}}"
+/// supervisor = "Prof. A. B. Ivanov"
+/// city = "Moscow"
+/// ```
+///
+/// When the main file is packed its `\maketitle` (or, without one, the start
+/// of the document) becomes the title page. The title, and the author and
+/// date when not given here, are the ones set with `\title`, `\author` and
+/// `\date`. Fields may use LaTeX commands and `{{name}}` placeholders.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TitlePage {
+    #[serde(default)]
+    pub layout: Layout,
+    pub university: Option<String>,
+    pub department: Option<String>,
+    pub course: Option<String>,
+    /// The kind of work: "Laboratory report", "Курсовая работа".
+    pub kind: Option<String>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub supervisor: Option<String>,
+    /// Printed with the year at the foot of Russian title pages.
+    pub city: Option<String>,
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// University, then title, author and supervisor, date: centred.
+    #[default]
+    International,
+    /// GOST-style: university and department at the head, the work in the
+    /// middle, "Выполнил(а)"/"Проверил(а)" on the right, city and year at
+    /// the foot.
+    Russian,
+}
+
+impl TitlePage {
+    /// Puts the title page in place of the main file's `\maketitle`, or at the
+    /// start of the document.
+    pub fn inject(&self, text: &str) -> String {
+        let page = self.render();
+        let mut out = String::with_capacity(text.len() + page.len());
+        let mut injected = false;
+        for line in text.split_inclusive('\n') {
+            let code = strip_comment(line);
+            if !injected {
+                if let Some(pos) = find_command(code, "\\maketitle") {
+                    out.push_str(&line[..pos]);
+                    out.push_str(&page);
+                    out.push_str(&line[pos + "\\maketitle".len()..]);
+                    injected = true;
+                    continue;
+                }
+            }
+            out.push_str(line);
+        }
+        if injected {
+            return out;
+        }
+        match text.find(BEGIN_DOCUMENT) {
+            Some(pos) => {
+                let end = pos + BEGIN_DOCUMENT.len();
+                format!("{}\n{}{}", &text[..end], page, &text[end..])
+            }
+            None => text.to_string(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let field = |value: &Option<String>| value.as_deref().map(escape);
+        let title = field(&self.title).unwrap_or_else(|| "\\csname @title\\endcsname".to_string());
+        let author =
+            field(&self.author).unwrap_or_else(|| "\\csname @author\\endcsname".to_string());
+        let mut page = String::from("\\begin{titlepage}\n\\centering\n");
+        let mut line = |text: String| {
+            page.push_str(&text);
+            page.push('\n');
+        };
+        match self.layout {
+            Layout::International => {
+                if let Some(university) = field(&self.university) {
+                    line(format!("{{\\large {}\\par}}", university));
+                }
+                if let Some(department) = field(&self.department) {
+                    line(format!("{}\\par", department));
+                }
+                line("\\vspace{3cm}".to_string());
+                if let Some(kind) = field(&self.kind) {
+                    line(format!("{{\\large {}\\par}}\\vspace{{0.5cm}}", kind));
+                }
+                line(format!("{{\\huge\\bfseries {}\\par}}", title));
+                if let Some(course) = field(&self.course) {
+                    line(format!("\\vspace{{1cm}}{{\\large {}\\par}}", course));
+                }
+                line(format!("\\vspace{{2cm}}{{\\Large {}\\par}}", author));
+                line("\\vfill".to_string());
+                if let Some(supervisor) = field(&self.supervisor) {
+                    line(format!("Supervisor: {}\\par\\vspace{{1em}}", supervisor));
+                }
+                let date =
+                    field(&self.date).unwrap_or_else(|| "\\csname @date\\endcsname".to_string());
+                line(format!("{{\\large {}\\par}}", date));
+            }
+            Layout::Russian => {
+                if let Some(university) = field(&self.university) {
+                    line(format!("{{\\bfseries {}\\par}}", university));
+                }
+                if let Some(department) = field(&self.department) {
+                    line(format!("{}\\par", department));
+                }
+                line("\\vfill".to_string());
+                if let Some(kind) = field(&self.kind) {
+                    line(format!("{{\\large {}\\par}}\\vspace{{0.5em}}", kind));
+                }
+                line(format!("{{\\Large\\bfseries {}\\par}}", title));
+                if let Some(course) = field(&self.course) {
+                    line(format!("\\vspace{{0.5em}}по дисциплине «{}»\\par", course));
+                }
+                line("\\vfill".to_string());
+                line("\\begin{flushright}\n\\begin{tabular}{@{}l@{}}".to_string());
+                line(format!("Выполнил(а): {}\\\\", author));
+                if let Some(supervisor) = field(&self.supervisor) {
+                    line(format!("Проверил(а): {}\\\\", supervisor));
+                }
+                line("\\end{tabular}\n\\end{flushright}".to_string());
+                line("\\vfill".to_string());
+                let date = field(&self.date).unwrap_or_else(|| "\\the\\year".to_string());
+                match field(&self.city) {
+                    Some(city) => line(format!("{}~{}", city, date)),
+                    None => line(date),
+                }
+            }
+        }
+        page.push_str("\\end{titlepage}\n");
+        page
+    }
+}
+
+/// `command` in `code`, not as the prefix of a longer name.
+fn find_command(code: &str, command: &str) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(pos) = code[offset..].find(command) {
+        let start = offset + pos;
+        let end = start + command.len();
+        if !code[end..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Some(start);
+        }
+        offset = end;
+    }
+    None
+}
+
+/// Escapes the characters of plain text that would break the page (`&` in
+/// "Faculty of Chemistry & Materials"), leaving commands, braces and
+/// `{{name}}` placeholders alone.
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut previous = None;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("{{") {
+            if let Some(end) = rest.find("}}") {
+                out.push_str(&rest[..end + 2]);
+                rest = &rest[end + 2..];
+                previous = Some('}');
+                continue;
+            }
+        }
+        if matches!(c, '&' | '%' | '#' | '$' | '_') && previous != Some('\\') {
+            out.push('\\');
+        }
+        out.push(c);
+        previous = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
@@ -0,0 +1,187 @@
+//! The prompt offered after a failed compile on a terminal: read the log or
+//! the errors parsed from it, open a file at an error's line in `$VISUAL` or
+//! `$EDITOR`, apply the suggested fixes, and resubmit, all without leaving
+//! the run.
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::job::{JobReport, Runner};
+use crate::suggestions;
+use anyhow::{Context, Result};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const MENU: &str = "[l]og, [e]rrors, [o]pen N, [f]ix, [r]esubmit, [q]uit> ";
+
+/// Whether there is someone to ask: stdin and stderr are terminals.
+pub fn available() -> bool {
+    std::io::stdin().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// Asks what to do about the failed job of `report` until the answer is to
+/// resubmit (true) or to stop (false). `main` is the main `.tex` file the
+/// errors are relative to, when the sources are at hand.
+pub async fn offer(
+    runner: &Runner,
+    report: &JobReport,
+    diagnostics: &[Diagnostic],
+    main: Option<&Path>,
+) -> Result<bool> {
+    if let Err(e) = &report.result {
+        eprintln!("Error: {:#}", e);
+    }
+    if !diagnostics.is_empty() {
+        list(diagnostics);
+    }
+    let mut log = None;
+    loop {
+        eprint!("{}", MENU);
+        std::io::stderr().flush()?;
+        let Some(line) = read_line().await? else {
+            eprintln!();
+            return Ok(false);
+        };
+        let mut words = line.split_whitespace();
+        match words.next().unwrap_or("") {
+            "l" | "log" => {
+                if log.is_none() {
+                    log = runner
+                        .compile_log(&report.job.account, &report.details)
+                        .await;
+                }
+                match &log {
+                    Some(log) => page(log)?,
+                    None => eprintln!("The compile log is not available"),
+                }
+            }
+            "e" | "errors" => list(diagnostics),
+            "o" | "open" => {
+                let number = match words.next().map(str::parse::<usize>) {
+                    None => 1,
+                    Some(Ok(number)) => number,
+                    Some(Err(_)) => {
+                        eprintln!("Give the number of an error, as listed by `e`");
+                        continue;
+                    }
+                };
+                match (main, number.checked_sub(1).and_then(|i| diagnostics.get(i))) {
+                    (None, _) => eprintln!("The sources are not at hand to open"),
+                    (_, None) => eprintln!("There is no error {}", number),
+                    (Some(main), Some(diagnostic)) => {
+                        edit(&diagnostic.source_path(main), diagnostic.line)?
+                    }
+                }
+            }
+            "f" | "fix" => {
+                if !suggestions::report(diagnostics, main, true)? {
+                    eprintln!("No fixes to apply");
+                }
+            }
+            "r" | "resubmit" => return Ok(true),
+            "q" | "quit" => return Ok(false),
+            "" => {}
+            other => eprintln!("Unknown answer: {}", other),
+        }
+    }
+}
+
+fn list(diagnostics: &[Diagnostic]) {
+    if diagnostics.is_empty() {
+        eprintln!("No errors could be parsed from the log");
+    }
+    for (i, diagnostic) in diagnostics.iter().enumerate() {
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let location = match (&diagnostic.file, diagnostic.line) {
+            (Some(file), Some(line)) => format!("{}:{}: ", file, line),
+            (Some(file), None) => format!("{}: ", file),
+            (None, Some(line)) => format!("line {}: ", line),
+            (None, None) => String::new(),
+        };
+        eprintln!(
+            "{:>3}. {}{}: {}",
+            i + 1,
+            location,
+            severity,
+            diagnostic.message
+        );
+    }
+}
+
+/// The next line of stdin, or `None` at its end. Read on the blocking pool
+/// so the runtime keeps serving signals while the user thinks.
+async fn read_line() -> Result<Option<String>> {
+    tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        let read = std::io::stdin()
+            .read_line(&mut line)
+            .context("Failed to read answer")?;
+        Ok((read > 0).then_some(line))
+    })
+    .await
+    .context("Failed to read answer")?
+}
+
+/// Shows `text` through `$PAGER` (`less` by default), or prints it when
+/// there is no pager.
+fn page(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+    let mut words = pager.split_whitespace();
+    let child = words.next().and_then(|program| {
+        Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .spawn()
+            .ok()
+    });
+    let Some(mut child) = child else {
+        eprintln!("{}", text);
+        return Ok(());
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may be quit before reading everything.
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait().context("Failed to run the pager")?;
+    Ok(())
+}
+
+/// Opens `file` at `line` in `$VISUAL` or `$EDITOR` (`vi` by default),
+/// waiting for the editor to exit.
+fn edit(file: &Path, line: Option<u32>) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("$EDITOR is empty")?;
+    let mut command = Command::new(program);
+    command.args(words);
+    let name = Path::new(program)
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or(program);
+    match line {
+        // VS Code and its forks take `--goto file:line`; most other editors
+        // (vi, nano, emacs, kakoune, micro) `+line file`.
+        Some(line) if matches!(name, "code" | "codium" | "cursor") => {
+            command
+                .arg("--goto")
+                .arg(format!("{}:{}", file.display(), line));
+        }
+        Some(line) => {
+            command.arg(format!("+{}", line)).arg(file);
+        }
+        None => {
+            command.arg(file);
+        }
+    }
+    let status = command
+        .status()
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !status.success() {
+        eprintln!("{} exited with {}", program, status);
+    }
+    Ok(())
+}
@@ -0,0 +1,511 @@
+use crate::api;
+use crate::batch;
+use crate::cli::Args;
+use crate::job::{Job, JobReport, Runner};
+use crate::progress::{Progress, ProgressEvent};
+use crate::report::{self, BatchReport, DocumentReport, DocumentStatus};
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+
+const DEFAULT_JOBS: usize = 4;
+const TICK: Duration = Duration::from_millis(250);
+const MAX_LOG_LINES: usize = 500;
+
+const USAGE: &str = "\
+Usage: chemtex tui [dir] [options]
+
+Options:
+  --jobs N      Compile up to N documents concurrently (default 4)
+  --out-dir DIR Write PDFs to DIR
+  --no-cache    Always submit, ignoring the build cache
+
+Keys: ↑/↓ select, Enter compile, a compile all, r retry failed,
+      x cancel, o open PDF, l toggle log, q quit";
+
+/// Full-screen dashboard over the documents of a directory: live status and
+/// queue position of running compiles, the outcome of the last batch, and
+/// keybindings to start, cancel and retry jobs.
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["no-cache", "help"], &["jobs", "out-dir"])?;
+    if args.flag("help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    let dir = PathBuf::from(args.positional(0).unwrap_or("."));
+    let out_dir = args.value("out-dir").map(PathBuf::from).unwrap_or_default();
+    let max_jobs = args.parsed::<usize>("jobs")?.unwrap_or(DEFAULT_JOBS).max(1);
+
+    let jobs = batch::jobs_from_dir(&dir, &out_dir).context(USAGE)?;
+    let history = load_history();
+    let message = format!("{} document(s) in {}", jobs.len(), dir.display());
+
+    let (progress_tx, progress_rx) = mpsc::unbounded_channel::<(String, ProgressEvent)>();
+    let mut runner = Runner::new(api::build_client()?, !args.flag("no-cache"))?;
+    runner.progress = Progress::channel(progress_tx);
+
+    let (done_tx, done_rx) = mpsc::unbounded_channel();
+    let mut dashboard = Dashboard {
+        rows: jobs
+            .into_iter()
+            .map(|job| {
+                let last = history.get(&job.input.display().to_string()).cloned();
+                JobRow::new(job, last)
+            })
+            .collect(),
+        selected: TableState::default().with_selected(Some(0)),
+        show_log: false,
+        runner,
+        permits: Arc::new(Semaphore::new(max_jobs)),
+        done: done_tx,
+        message,
+    };
+
+    let mut terminal = ratatui::init();
+    let result = dashboard
+        .event_loop(&mut terminal, progress_rx, done_rx)
+        .await;
+    ratatui::restore();
+    dashboard.cancel_all().await;
+    result
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum RowState {
+    Idle,
+    Queued,
+    Running {
+        status: String,
+        queue_position: Option<u32>,
+    },
+    Succeeded,
+    Failed(String),
+    Cancelled,
+}
+
+struct JobRow {
+    job: Job,
+    state: RowState,
+    started: Option<Instant>,
+    elapsed: Option<Duration>,
+    task_id: Option<String>,
+    log: Vec<String>,
+    handle: Option<JoinHandle<()>>,
+    last: Option<DocumentReport>,
+}
+
+impl JobRow {
+    fn new(job: Job, last: Option<DocumentReport>) -> Self {
+        Self {
+            job,
+            state: RowState::Idle,
+            started: None,
+            elapsed: None,
+            task_id: None,
+            log: Vec::new(),
+            handle: None,
+            last,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        matches!(self.state, RowState::Queued | RowState::Running { .. })
+    }
+
+    fn push_log(&mut self, line: String) {
+        self.log.push(line);
+        if self.log.len() > MAX_LOG_LINES {
+            self.log.remove(0);
+        }
+    }
+}
+
+enum Update {
+    Started(usize),
+    Finished(usize, Box<JobReport>),
+}
+
+struct Dashboard {
+    rows: Vec<JobRow>,
+    selected: TableState,
+    show_log: bool,
+    runner: Runner,
+    permits: Arc<Semaphore>,
+    done: mpsc::UnboundedSender<Update>,
+    message: String,
+}
+
+impl Dashboard {
+    async fn event_loop(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        mut progress: mpsc::UnboundedReceiver<(String, ProgressEvent)>,
+        mut done: mpsc::UnboundedReceiver<Update>,
+    ) -> Result<()> {
+        let mut keys = spawn_key_reader();
+        let mut tick = tokio::time::interval(TICK);
+
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            tokio::select! {
+                _ = tick.tick() => {}
+                Some((label, event)) = progress.recv() => self.on_progress(&label, event),
+                Some(update) = done.recv() => match update {
+                    Update::Started(index) => self.on_started(index),
+                    Update::Finished(index, report) => self.on_finished(index, *report),
+                },
+                key = keys.recv() => match key {
+                    Some((code, modifiers)) => {
+                        if !self.on_key(code, modifiers).await {
+                            return Ok(());
+                        }
+                    }
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Returns false when the user asked to quit.
+    async fn on_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let selected = self.selected.selected().unwrap_or(0);
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Down | KeyCode::Char('j') if selected + 1 < self.rows.len() => {
+                self.selected.select(Some(selected + 1));
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.selected.select(Some(selected.saturating_sub(1)));
+            }
+            KeyCode::Enter => self.start(selected),
+            KeyCode::Char('a') => {
+                for index in 0..self.rows.len() {
+                    self.start(index);
+                }
+            }
+            KeyCode::Char('r') => {
+                let failed: Vec<usize> = (0..self.rows.len())
+                    .filter(|&i| self.is_failed(i))
+                    .collect();
+                self.message = format!("Retrying {} failed document(s)", failed.len());
+                for index in failed {
+                    self.start(index);
+                }
+            }
+            KeyCode::Char('x') => self.cancel(selected).await,
+            KeyCode::Char('o') => self.open(selected),
+            KeyCode::Char('l') => self.show_log = !self.show_log,
+            _ => {}
+        }
+        true
+    }
+
+    fn is_failed(&self, index: usize) -> bool {
+        let row = &self.rows[index];
+        match row.state {
+            RowState::Failed(_) => true,
+            RowState::Idle => row
+                .last
+                .as_ref()
+                .is_some_and(|last| last.status == DocumentStatus::Failed),
+            _ => false,
+        }
+    }
+
+    fn start(&mut self, index: usize) {
+        let Some(row) = self.rows.get_mut(index) else {
+            return;
+        };
+        if row.is_active() {
+            return;
+        }
+        row.state = RowState::Queued;
+        row.started = None;
+        row.elapsed = None;
+        row.task_id = None;
+        row.log.clear();
+
+        let runner = self.runner.clone();
+        let permits = Arc::clone(&self.permits);
+        let done = self.done.clone();
+        let job = row.job.clone();
+        row.handle = Some(tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await;
+            let _ = done.send(Update::Started(index));
+            let report = runner.run(&job, &job.name).await;
+            let _ = done.send(Update::Finished(index, Box::new(report)));
+        }));
+    }
+
+    async fn cancel(&mut self, index: usize) {
+        let Some(row) = self.rows.get_mut(index) else {
+            return;
+        };
+        if !row.is_active() {
+            return;
+        }
+        if let Some(handle) = row.handle.take() {
+            handle.abort();
+        }
+        row.state = RowState::Cancelled;
+        row.elapsed = row.started.map(|s| s.elapsed());
+        self.message = format!("Cancelled {}", row.job.name);
+
+        if let Some(task) = self.runner.in_flight.take(row.job.id) {
+            match api::cancel_task(&self.runner.client, &task).await {
+                Ok(()) => row.push_log(format!("Cancelled remote task {}", task.id)),
+                Err(e) => {
+                    self.message = format!("Failed to cancel task {}: {:#}", task.id, e);
+                }
+            }
+        }
+    }
+
+    async fn cancel_all(&mut self) {
+        for index in 0..self.rows.len() {
+            self.cancel(index).await;
+        }
+    }
+
+    fn open(&mut self, index: usize) {
+        let Some(row) = self.rows.get(index) else {
+            return;
+        };
+        let output = &row.job.output;
+        if !output.exists() {
+            self.message = format!("{} has not been built yet", output.display());
+            return;
+        }
+        self.message = match open_path(output) {
+            Ok(()) => format!("Opened {}", output.display()),
+            Err(e) => format!("{:#}", e),
+        };
+    }
+
+    fn on_progress(&mut self, label: &str, event: ProgressEvent) {
+        let Some(row) = self.rows.iter_mut().find(|r| r.job.name == label) else {
+            return;
+        };
+        if !row.is_active() {
+            return;
+        }
+        match event {
+            ProgressEvent::Log(message) => row.push_log(message),
+            ProgressEvent::Status {
+                status,
+                queue_position,
+                ..
+            } => {
+                row.task_id = self.runner.in_flight.get(row.job.id);
+                row.state = RowState::Running {
+                    status,
+                    queue_position,
+                };
+            }
+        }
+    }
+
+    fn on_started(&mut self, index: usize) {
+        if let Some(row) = self.rows.get_mut(index).filter(|r| r.is_active()) {
+            row.started = Some(Instant::now());
+            row.state = RowState::Running {
+                status: "Uploading".to_string(),
+                queue_position: None,
+            };
+        }
+    }
+
+    fn on_finished(&mut self, index: usize, report: JobReport) {
+        let Some(row) = self.rows.get_mut(index) else {
+            return;
+        };
+        if !row.is_active() {
+            return;
+        }
+        row.handle = None;
+        row.elapsed = Some(report.elapsed);
+        row.task_id = report.details.task_id.clone();
+        row.state = match &report.result {
+            Ok(_) => RowState::Succeeded,
+            Err(e) => RowState::Failed(format!("{:#}", e)),
+        };
+        if let Err(e) = &report.result {
+            row.push_log(format!("Error: {:#}", e));
+        }
+        row.last = Some(DocumentReport::from(&report));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let constraints = if self.show_log {
+            vec![
+                Constraint::Percentage(55),
+                Constraint::Min(5),
+                Constraint::Length(1),
+            ]
+        } else {
+            vec![Constraint::Min(3), Constraint::Length(1)]
+        };
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(constraints)
+            .split(frame.area());
+
+        let rows = self.rows.iter().map(|row| {
+            let (status, color) = status_cell(row);
+            let elapsed = row
+                .elapsed
+                .or_else(|| row.is_active().then(|| row.started.map(|s| s.elapsed()))?)
+                .map(|d| api::format_milliseconds(d.as_millis() as u64))
+                .unwrap_or_else(|| "-".to_string());
+            let last = row
+                .last
+                .as_ref()
+                .map(|last| {
+                    format!(
+                        "{} in {}",
+                        match last.status {
+                            DocumentStatus::Ok => "ok",
+                            DocumentStatus::Failed => "failed",
+                            DocumentStatus::Cancelled => "cancelled",
+                        },
+                        api::format_milliseconds(last.elapsed_ms)
+                    )
+                })
+                .unwrap_or_else(|| "-".to_string());
+            Row::new(vec![
+                row.job.name.clone(),
+                status,
+                elapsed,
+                row.task_id.clone().unwrap_or_else(|| "-".to_string()),
+                last,
+            ])
+            .style(Style::default().fg(color))
+        });
+
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Percentage(35),
+                Constraint::Percentage(20),
+                Constraint::Length(10),
+                Constraint::Percentage(20),
+                Constraint::Percentage(25),
+            ],
+        )
+        .header(
+            Row::new(vec!["Document", "Status", "Elapsed", "Task ID", "Last run"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::default().borders(Borders::ALL).title(" chemtex "))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(table, areas[0], &mut self.selected);
+
+        if self.show_log {
+            let selected = self.selected.selected().unwrap_or(0);
+            if let Some(row) = self.rows.get(selected) {
+                let height = areas[1].height.saturating_sub(2) as usize;
+                let lines: Vec<Line> = row
+                    .log
+                    .iter()
+                    .skip(row.log.len().saturating_sub(height))
+                    .map(|l| Line::from(l.as_str()))
+                    .collect();
+                let log = Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(format!(" Log: {} ", row.job.name)),
+                );
+                frame.render_widget(log, areas[1]);
+            }
+        }
+
+        let footer = format!(
+            "{}  |  Enter compile  a all  r retry failed  x cancel  o open  l log  q quit",
+            self.message
+        );
+        frame.render_widget(
+            Paragraph::new(footer).style(Style::default().fg(Color::DarkGray)),
+            areas[areas.len() - 1],
+        );
+    }
+}
+
+fn status_cell(row: &JobRow) -> (String, Color) {
+    match &row.state {
+        RowState::Idle => ("idle".to_string(), Color::Reset),
+        RowState::Queued => ("waiting".to_string(), Color::DarkGray),
+        RowState::Running {
+            status,
+            queue_position: Some(position),
+        } => (format!("{} (#{})", status, position), Color::Yellow),
+        RowState::Running { status, .. } => (status.clone(), Color::Yellow),
+        RowState::Succeeded => ("done".to_string(), Color::Green),
+        RowState::Failed(_) => ("failed".to_string(), Color::Red),
+        RowState::Cancelled => ("cancelled".to_string(), Color::Magenta),
+    }
+}
+
+/// Last batch report keyed by input path; a dashboard without history is
+/// still useful, so a missing or unreadable report is ignored.
+fn load_history() -> HashMap<String, DocumentReport> {
+    report::last_report_path()
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| BatchReport::load(&path).ok())
+        .map(|report| {
+            report
+                .documents
+                .into_iter()
+                .map(|doc| (doc.input.clone(), doc))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Reads terminal key presses on a blocking thread and forwards them.
+fn spawn_key_reader() -> mpsc::UnboundedReceiver<(KeyCode, KeyModifiers)> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                if tx.send((key.code, key.modifiers)).is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    });
+    rx
+}
+
+/// Opens a file with the desktop's default application.
+fn open_path(path: &Path) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        std::process::Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    command
+        .arg(path)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::deps;
+use anyhow::{Context, Result};
+use std::io::{Cursor, Read};
+use zip::ZipArchive;
+
+/// Larger uploads are almost certainly the wrong file (a build directory, a
+/// video) rather than a document.
+const MAX_UPLOAD_BYTES: usize = 100 << 20;
+
+/// How much of a `.tex` file in an archive is read to find its preamble.
+const PREAMBLE_BYTES: u64 = 64 << 10;
+
+/// Checks what is about to be uploaded, so inputs the server would reject
+/// fail at once and locally. With `strict`, a `.tex` file (or, in an
+/// archive, at least one of them) must also look like a LaTeX document:
+/// a `\documentclass` or `\begin{document}` outside comments.
+pub fn upload(file_name: &str, contents: &[u8], strict: bool) -> Result<()> {
+    if contents.is_empty() {
+        anyhow::bail!("{} is empty", file_name);
+    }
+    if contents.len() > MAX_UPLOAD_BYTES {
+        anyhow::bail!(
+            "{} is {} MiB, more than the {} MiB a document plausibly needs",
+            file_name,
+            contents.len() >> 20,
+            MAX_UPLOAD_BYTES >> 20
+        );
+    }
+    if file_name.ends_with(".zip") {
+        archive(file_name, contents, strict)
+    } else {
+        tex(file_name, contents, strict)
+    }
+}
+
+fn tex(file_name: &str, contents: &[u8], strict: bool) -> Result<()> {
+    if contents.contains(&0) {
+        anyhow::bail!("{} is a binary file, not LaTeX source", file_name);
+    }
+    let text = std::str::from_utf8(contents).with_context(|| {
+        format!(
+            "{} is not UTF-8; save it as UTF-8 (the server reads nothing else)",
+            file_name
+        )
+    })?;
+    if strict && !is_document(text) {
+        anyhow::bail!(
+            "{} has no \\documentclass or \\begin{{document}}: is it a chapter rather than \
+             the main file? Pass --no-validate to upload it anyway",
+            file_name
+        );
+    }
+    Ok(())
+}
+
+fn archive(file_name: &str, contents: &[u8], strict: bool) -> Result<()> {
+    let mut zip = ZipArchive::new(Cursor::new(contents))
+        .with_context(|| format!("{} is not a valid zip archive", file_name))?;
+    let sources: Vec<String> = zip
+        .file_names()
+        .filter(|name| name.ends_with(".tex"))
+        .map(String::from)
+        .collect();
+    if sources.is_empty() {
+        anyhow::bail!("{} contains no .tex file", file_name);
+    }
+    if !strict {
+        return Ok(());
+    }
+    for name in &sources {
+        let entry = zip
+            .by_name(name)
+            .with_context(|| format!("Failed to read {} in {}", name, file_name))?;
+        let mut head = Vec::new();
+        entry
+            .take(PREAMBLE_BYTES)
+            .read_to_end(&mut head)
+            .with_context(|| format!("Failed to read {} in {}", name, file_name))?;
+        if is_document(&String::from_utf8_lossy(&head)) {
+            return Ok(());
+        }
+    }
+    anyhow::bail!(
+        "No .tex file in {} has a \\documentclass or \\begin{{document}}; pass --no-validate \
+         to upload it anyway",
+        file_name
+    )
+}
+
+fn is_document(text: &str) -> bool {
+    text.lines()
+        .map(deps::strip_comment)
+        .any(|code| code.contains("\\documentclass") || code.contains("\\begin{document}"))
+}
@@ -0,0 +1,186 @@
+use crate::storage;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Values for `{{name}}` placeholders in template and project files.
+pub type Variables = BTreeMap<String, String>;
+
+/// User-wide defaults (`author = "Jane Doe"`), applied to every project.
+const DEFAULTS_FILE: &str = "variables.toml";
+
+/// Replaces every `{{name}}` (spaces inside the braces allowed) that has a
+/// value, with TeX's special characters escaped, so a value such as
+/// `R&D 50%` cannot break or inject into the document. `{{name|raw}}`
+/// inserts the value as it is, for values that are TeX themselves.
+/// Unknown placeholders are left untouched.
+pub fn substitute(text: &str, variables: &Variables) -> String {
+    replace(text, variables, escape)
+}
+
+/// Like [`substitute`], without escaping, for files that are not TeX.
+pub fn substitute_plain(text: &str, variables: &Variables) -> String {
+    replace(text, variables, str::to_string)
+}
+
+fn replace(text: &str, variables: &Variables, escape: fn(&str) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(placeholder) = next_placeholder(rest) {
+        out.push_str(&rest[..placeholder.start]);
+        match variables.get(placeholder.name) {
+            Some(value) if placeholder.raw => out.push_str(value),
+            Some(value) => out.push_str(&escape(value)),
+            None => out.push_str(&rest[placeholder.start..placeholder.end]),
+        }
+        rest = &rest[placeholder.end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Names of the placeholders in `text`, in order of first use.
+pub fn placeholders(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = text;
+    while let Some(placeholder) = next_placeholder(rest) {
+        if !names.iter().any(|n| n == placeholder.name) {
+            names.push(placeholder.name.to_string());
+        }
+        rest = &rest[placeholder.end..];
+    }
+    names
+}
+
+struct Placeholder<'a> {
+    /// Byte range in the text.
+    start: usize,
+    end: usize,
+    name: &'a str,
+    /// `{{name|raw}}`.
+    raw: bool,
+}
+
+/// The first placeholder in `text`. Only identifiers count, so TeX such as
+/// `{{\bf x}}` is never mistaken for one.
+fn next_placeholder(text: &str) -> Option<Placeholder<'_>> {
+    let mut offset = 0;
+    while let Some(found) = text[offset..].find("{{") {
+        let start = offset + found;
+        let inner_start = start + 2;
+        if let Some(len) = text[inner_start..].find("}}") {
+            let inner = text[inner_start..inner_start + len].trim();
+            let (name, raw) = match inner.strip_suffix("|raw") {
+                Some(name) => (name.trim_end(), true),
+                None => (inner, false),
+            };
+            let is_identifier = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if is_identifier {
+                return Some(Placeholder {
+                    start,
+                    end: inner_start + len + 2,
+                    name,
+                    raw,
+                });
+            }
+        }
+        offset = start + 1;
+    }
+    None
+}
+
+/// A value as text in a TeX document.
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Parses `--var key=value` assignments.
+pub fn parse_assignments(assignments: &[&str]) -> Result<Variables> {
+    assignments
+        .iter()
+        .map(|assignment| {
+            let (name, value) = assignment
+                .split_once('=')
+                .with_context(|| format!("Expected NAME=VALUE, got {:?}", assignment))?;
+            Ok((name.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// `<data dir>/variables.toml`, if present.
+pub fn user_defaults() -> Result<Variables> {
+    let path = storage::data_dir()?.join(DEFAULTS_FILE);
+    match fs::read_to_string(&path) {
+        Ok(text) => {
+            toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+        }
+        Err(_) => Ok(Variables::new()),
+    }
+}
+
+/// Asks for every name in `missing` on the terminal; empty answers leave the
+/// placeholder in place. Does nothing when stdin is not a terminal.
+pub fn prompt(missing: &[String], variables: &mut Variables) -> Result<()> {
+    let stdin = std::io::stdin();
+    if missing.is_empty() || !stdin.is_terminal() {
+        return Ok(());
+    }
+    let mut lines = stdin.lock().lines();
+    for name in missing {
+        print!("{}: ", name.replace(['_', '-'], " "));
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let value = line.context("Failed to read answer")?;
+        let value = value.trim();
+        if !value.is_empty() {
+            variables.insert(name.clone(), value.to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_escaped_unless_marked_raw() {
+        let variables = Variables::from([
+            ("lab".to_string(), "R&D 50% #3 {x}_y".to_string()),
+            ("author".to_string(), "\\textbf{Ada}".to_string()),
+        ]);
+        let text = "\\title{{{ lab }}} \\author{{{author|raw}}} {{\\bf x}} {{missing}}";
+        assert_eq!(
+            substitute(text, &variables),
+            "\\title{R\\&D 50\\% \\#3 \\{x\\}\\_y} \\author{\\textbf{Ada}} {{\\bf x}} {{missing}}"
+        );
+        assert_eq!(
+            substitute_plain("lab = \"{{lab}}\"", &variables),
+            "lab = \"R&D 50% #3 {x}_y\""
+        );
+        assert_eq!(
+            escape("C:\\ ~ ^ $"),
+            "C:\\textbackslash{} \\textasciitilde{} \\textasciicircum{} \\$"
+        );
+        assert_eq!(placeholders(text), ["lab", "author", "missing"]);
+    }
+}
@@ -0,0 +1,345 @@
+//! Encryption at rest of what chemtex keeps about your documents: the PDFs
+//! and compile logs of the build cache, the file index that recognises
+//! unchanged projects and the entries of the history, for unpublished work
+//! on shared machines.
+//!
+//! `chemtex encryption enable` creates a random key, stores it in the OS
+//! keyring and turns encryption on in `<data dir>/encryption.toml`. With
+//! `--key-file`, or in builds without the `keyring` feature, the key is
+//! kept in that file instead, readable by its owner only. Data is sealed
+//! with XChaCha20-Poly1305 under a fresh nonce; what was stored before
+//! stays readable, as does what is sealed after `disable`, which keeps the
+//! key.
+//!
+//! Not encrypted: the small records of running jobs (`<data dir>/sessions`,
+//! input and output paths and task ids), the build cache's entries (keys,
+//! task ids and download URLs), the last report, and what is written next
+//! to the PDF on request, such as provenance records and reports.
+
+use crate::cli::Args;
+use crate::storage;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+const CONFIG_FILE: &str = "encryption.toml";
+/// Starts every sealed file.
+const MAGIC: &[u8; 8] = b"CTXSEAL1";
+/// Starts every sealed text (a history entry).
+const TEXT_PREFIX: &str = "sealed:";
+const NONCE_LEN: usize = 24;
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "chemtex";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "encryption-key";
+
+const USAGE: &str = "\
+Usage: chemtex encryption enable [--key-file]
+       chemtex encryption disable
+       chemtex encryption status
+
+`enable` encrypts the cached PDFs and logs, the file index and the history
+entries written from now on, with a key kept in the OS keyring or, with
+--key-file, in <data dir>/encryption.toml. Earlier data stays as it was:
+`chemtex cache clear` drops unencrypted builds. `disable` stops encrypting
+but keeps the key, so encrypted data stays readable. Job records, cache
+entries and reports are not encrypted.";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    enabled: bool,
+    /// Base64 of the 32-byte key, unless it is in the keyring.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    keyring: bool,
+}
+
+/// The configuration of this invocation, read on first use.
+struct State {
+    enabled: bool,
+    cipher: Option<XChaCha20Poly1305>,
+}
+
+static STATE: OnceLock<Result<State, String>> = OnceLock::new();
+
+fn config_path() -> Result<PathBuf> {
+    Ok(storage::data_dir()?.join(CONFIG_FILE))
+}
+
+impl Config {
+    fn load() -> Result<Option<Self>> {
+        let path = config_path()?;
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let config: Self =
+            toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        // A key file copied in or written by an older version may be
+        // readable by others.
+        #[cfg(unix)]
+        if config.key.is_some() {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path)
+                .map(|metadata| metadata.permissions().mode())
+                .unwrap_or_default();
+            if mode & 0o077 != 0 {
+                fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+                    .with_context(|| format!("Failed to restrict {}", path.display()))?;
+            }
+        }
+        Ok(Some(config))
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = config_path()?;
+        storage::ensure_dir(path.parent().unwrap_or(&path))?;
+        let text = toml::to_string(self).context("Failed to serialize encryption settings")?;
+        storage::write_private(&path, text.as_bytes())
+    }
+
+    fn key(&self) -> Result<Vec<u8>> {
+        let encoded = match (&self.key, self.keyring) {
+            (Some(key), _) => key.clone(),
+            (None, true) => keyring_key()?,
+            (None, false) => anyhow::bail!("{} has no key", CONFIG_FILE),
+        };
+        let key = STANDARD.decode(encoded.trim())?;
+        anyhow::ensure!(key.len() == 32, "The encryption key is not 32 bytes");
+        Ok(key)
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_entry() -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).context("Failed to open the OS keyring")
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_key() -> Result<String> {
+    keyring_entry()?
+        .get_password()
+        .context("Failed to read the encryption key from the OS keyring")
+}
+
+#[cfg(not(feature = "keyring"))]
+fn keyring_key() -> Result<String> {
+    anyhow::bail!(
+        "The encryption key is in the OS keyring; build chemtex with `--features keyring`"
+    )
+}
+
+fn state() -> Result<&'static State> {
+    let state = STATE.get_or_init(|| {
+        let load = || -> Result<State> {
+            let Some(config) = Config::load()? else {
+                return Ok(State {
+                    enabled: false,
+                    cipher: None,
+                });
+            };
+            let key = config.key()?;
+            Ok(State {
+                enabled: config.enabled,
+                cipher: Some(XChaCha20Poly1305::new(Key::from_slice(&key))),
+            })
+        };
+        load().map_err(|e| format!("{:#}", e))
+    });
+    state
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("Encryption at rest: {}", e))
+}
+
+/// `plain` encrypted when encryption is on, else unchanged.
+pub fn seal(plain: &[u8]) -> Result<Vec<u8>> {
+    let state = state()?;
+    match (&state.cipher, state.enabled) {
+        (Some(cipher), true) => seal_with(cipher, plain),
+        _ => Ok(plain.to_vec()),
+    }
+}
+
+/// The contents of `data` whether it was sealed or not.
+pub fn open(data: &[u8]) -> Result<Vec<u8>> {
+    let Some(sealed) = data.strip_prefix(MAGIC) else {
+        return Ok(data.to_vec());
+    };
+    let cipher = state()?
+        .cipher
+        .as_ref()
+        .context("Encrypted data, but no encryption key (see `chemtex encryption`)")?;
+    open_with(cipher, sealed)
+}
+
+/// Like [`seal`], for data stored as text.
+pub fn seal_text(plain: String) -> Result<String> {
+    let state = state()?;
+    match (&state.cipher, state.enabled) {
+        (Some(cipher), true) => {
+            let sealed = seal_with(cipher, plain.as_bytes())?;
+            Ok(format!(
+                "{}{}",
+                TEXT_PREFIX,
+                STANDARD.encode(&sealed[MAGIC.len()..])
+            ))
+        }
+        _ => Ok(plain),
+    }
+}
+
+/// Like [`open`], for text from [`seal_text`].
+pub fn open_text(text: &str) -> Result<String> {
+    let Some(encoded) = text.strip_prefix(TEXT_PREFIX) else {
+        return Ok(text.to_string());
+    };
+    let mut sealed = MAGIC.to_vec();
+    sealed.extend(STANDARD.decode(encoded)?);
+    String::from_utf8(open(&sealed)?).context("Decrypted text is not UTF-8")
+}
+
+fn seal_with(cipher: &XChaCha20Poly1305, plain: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|e| anyhow::anyhow!("No randomness: {}", e))?;
+    let sealed = cipher
+        .encrypt(XNonce::from_slice(&nonce), plain)
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+    Ok([&MAGIC[..], &nonce, &sealed].concat())
+}
+
+fn open_with(cipher: &XChaCha20Poly1305, sealed: &[u8]) -> Result<Vec<u8>> {
+    anyhow::ensure!(sealed.len() >= NONCE_LEN, "Truncated encrypted data");
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), sealed)
+        .map_err(|_| anyhow::anyhow!("Encrypted data does not open with this key"))
+}
+
+pub fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(raw_args, &["key-file"], &[])?;
+    let config = Config::load()?;
+    match args.positional(0) {
+        Some("enable") => {
+            if let Some(mut config) = config {
+                config.key()?;
+                config.enabled = true;
+                config.save()?;
+                println!("Encryption at rest enabled, with the existing key");
+                return Ok(());
+            }
+            let mut key = [0u8; 32];
+            getrandom::getrandom(&mut key)
+                .map_err(|e| anyhow::anyhow!("No randomness for the key: {}", e))?;
+            let encoded = STANDARD.encode(key);
+            let config = if args.flag("key-file") {
+                Config {
+                    enabled: true,
+                    key: Some(encoded),
+                    keyring: false,
+                }
+            } else {
+                store_in_keyring(&encoded)?;
+                Config {
+                    enabled: true,
+                    key: None,
+                    keyring: true,
+                }
+            };
+            config.save()?;
+            println!(
+                "Encryption at rest enabled, key in {}",
+                match config.keyring {
+                    true => "the OS keyring".to_string(),
+                    false => config_path()?.display().to_string(),
+                }
+            );
+            println!("Earlier builds stay unencrypted; `chemtex cache clear` removes them");
+            Ok(())
+        }
+        Some("disable") => {
+            let Some(mut config) = config else {
+                println!("Encryption at rest is not enabled");
+                return Ok(());
+            };
+            config.enabled = false;
+            config.save()?;
+            println!("Encryption at rest disabled; the key is kept to read encrypted data");
+            Ok(())
+        }
+        Some("status") => {
+            match config {
+                Some(config) => println!(
+                    "Encryption at rest {}, key in {}",
+                    if config.enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    },
+                    if config.keyring {
+                        "the OS keyring".to_string()
+                    } else {
+                        config_path()?.display().to_string()
+                    }
+                ),
+                None => println!("Encryption at rest is not set up"),
+            }
+            Ok(())
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn store_in_keyring(key: &str) -> Result<()> {
+    keyring_entry()?.set_password(key).context(
+        "Failed to store the encryption key in the OS keyring (--key-file keeps it in a file)",
+    )
+}
+
+#[cfg(not(feature = "keyring"))]
+fn store_in_keyring(_key: &str) -> Result<()> {
+    anyhow::bail!(
+        "chemtex is built without the `keyring` feature; --key-file keeps the key in a file"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_data_opens_only_with_its_key() {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&[1; 32]));
+        let sealed = seal_with(&cipher, b"%PDF-1.5").unwrap();
+        assert!(sealed.starts_with(MAGIC));
+        assert_ne!(seal_with(&cipher, b"%PDF-1.5").unwrap(), sealed);
+        assert_eq!(
+            open_with(&cipher, &sealed[MAGIC.len()..]).unwrap(),
+            b"%PDF-1.5"
+        );
+
+        let other = XChaCha20Poly1305::new(Key::from_slice(&[2; 32]));
+        assert!(open_with(&other, &sealed[MAGIC.len()..]).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open_with(&cipher, &tampered[MAGIC.len()..]).is_err());
+    }
+
+    #[test]
+    fn unsealed_data_passes_through() {
+        assert_eq!(open(b"%PDF-1.5").unwrap(), b"%PDF-1.5");
+        assert_eq!(
+            open_text("{\"name\":\"doc\"}").unwrap(),
+            "{\"name\":\"doc\"}"
+        );
+    }
+}
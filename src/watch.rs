@@ -0,0 +1,116 @@
+use crate::api::{self, BuildMode};
+use crate::cli::Args;
+use crate::job::{Job, Runner};
+use crate::local;
+use crate::manifest::Manifest;
+use crate::preview::PreviewServer;
+use crate::progress::Progress;
+use crate::state;
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_INTERVAL_MS: u64 = 1000;
+const DEFAULT_PREVIEW_LISTEN: &str = "127.0.0.1:8080";
+
+const USAGE: &str = "\
+Usage: chemtex watch <path_to_tex_or_zip_file> [options]
+
+Options:
+  --interval MS    How often to check sources for changes (default 1000)
+  --profile NAME   Compile with this server profile
+  --manifest FILE  Take the profile's settings from a batch manifest: with
+                   `draft: local`, builds are made locally with Tectonic
+  --serve          Serve a live preview that reloads after every build
+  --listen ADDR    Preview address (default 127.0.0.1:8080)
+  --no-cache       Always submit, ignoring the build cache
+  --plain          Print a status only when it changes, for screen readers
+
+Every build is a draft (see `chemtex compile --draft`); compile with --final
+for the copy to hand out.";
+
+/// Recompiles a document whenever it or any file it includes changes.
+pub async fn run(raw_args: &[String]) -> Result<()> {
+    let args = Args::parse(
+        raw_args,
+        &["serve", "no-cache", "plain"],
+        &["interval", "listen", "profile", "manifest"],
+    )?;
+    let input = args.positional(0).context(USAGE)?;
+    let interval = Duration::from_millis(
+        args.parsed::<u64>("interval")?
+            .unwrap_or(DEFAULT_INTERVAL_MS)
+            .max(100),
+    );
+
+    let mut runner = Runner::new(api::build_client()?, !args.flag("no-cache"))?;
+    if args.flag("plain") {
+        runner.progress = Progress::plain();
+    }
+    let mut job = Job::new(Path::new(input), Path::new(""))?;
+    job.options.mode = Some(BuildMode::Draft);
+    job.options.profile = args.value("profile").map(str::to_string);
+    if let Some(path) = args.value("manifest") {
+        let manifest = Manifest::load(Path::new(path))?;
+        if !manifest.hooks.is_empty() {
+            let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+            job.hooks = manifest.hooks.clone().in_dir(dir);
+        }
+        if job.options.profile.is_none() {
+            job.options.profile.clone_from(&manifest.defaults.profile);
+        }
+        if manifest.drafts_locally(job.options.profile.as_deref()) {
+            match local::available() {
+                true => job.local = true,
+                false => println!("Tectonic is not available, so drafts are built on the server"),
+            }
+        }
+    }
+
+    let preview = if args.flag("serve") {
+        let addr: SocketAddr = args
+            .value("listen")
+            .unwrap_or(DEFAULT_PREVIEW_LISTEN)
+            .parse()
+            .context("Invalid --listen address")?;
+        let preview = PreviewServer::start(addr)?;
+        println!("Live preview at http://{}", addr);
+        Some(preview)
+    } else {
+        None
+    };
+
+    println!("Watching {} (Ctrl+C to stop)", job.input.display());
+    let mut last_fingerprint: Option<String> = None;
+    // Reported once, not on every tick it persists.
+    let mut scan_error: Option<String> = None;
+    loop {
+        match state::fingerprint(&job) {
+            Ok(fingerprint) if last_fingerprint.as_ref() != Some(&fingerprint) => {
+                scan_error = None;
+                last_fingerprint = Some(fingerprint);
+                let report = runner.run(&job, "").await;
+                match (&report.result, &preview) {
+                    (Ok(output), Some(preview)) => preview.publish(output),
+                    (Ok(_), None) => {}
+                    (Err(e), _) => eprintln!("Build failed: {:#}", e),
+                }
+                println!("Waiting for changes...");
+            }
+            Ok(_) => {
+                if scan_error.take().is_some() {
+                    println!("Waiting for changes...");
+                }
+            }
+            Err(e) => {
+                let message = format!("{:#}", e);
+                if scan_error.as_ref() != Some(&message) {
+                    eprintln!("Failed to scan sources: {}", message);
+                    scan_error = Some(message);
+                }
+            }
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,226 @@
+//! Submit windows (`--submit-window 22:00-06:00`): non-urgent batches, and
+//! the daemon's non-urgent jobs, wait for off-peak hours before they are
+//! uploaded, so that recompiling the whole course archive does not queue
+//! behind, or in front of, the day's interactive builds.
+//!
+//! Within the window the hour to start in is taken from the history: the
+//! one whose jobs waited least in the server's queue, once enough jobs
+//! were submitted in it to go by, otherwise the start of the window.
+//! Documents queued at `--priority urgent` or `high` are never deferred.
+
+use crate::api;
+use crate::history::{self, HistoryEntry};
+use crate::shutdown;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local, Timelike};
+use std::fmt;
+
+/// Priorities that skip the window.
+const URGENT_PRIORITIES: &[&str] = &["urgent", "high"];
+/// Jobs submitted in an hour for its queue waits to count.
+const MIN_SAMPLES: usize = 3;
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A daily span of local time, which may wrap past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmitWindow {
+    /// Minutes after midnight.
+    start: u32,
+    end: u32,
+}
+
+/// When to submit, and the queue wait observed at that hour.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Plan {
+    pub at: DateTime<Local>,
+    pub median_queue_ms: Option<u64>,
+}
+
+impl SubmitWindow {
+    /// `22:00-06:00`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let parse = || -> Option<Self> {
+            let (start, end) = spec.split_once('-')?;
+            Some(Self {
+                start: minutes(start)?,
+                end: minutes(end)?,
+            })
+        };
+        let window = parse()
+            .with_context(|| format!("Invalid submit window {:?} (expected HH:MM-HH:MM)", spec))?;
+        anyhow::ensure!(
+            window.start != window.end,
+            "Submit window {} is empty",
+            spec
+        );
+        Ok(window)
+    }
+
+    pub fn contains(&self, time: &DateTime<Local>) -> bool {
+        let minute = time.hour() * 60 + time.minute();
+        if self.start < self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    /// When to submit work deferred at `now`: the quietest hour of the
+    /// window's next (or current) opening by the history, or its start.
+    pub fn plan(&self, now: DateTime<Local>) -> Plan {
+        let medians = history::load()
+            .map(|entries| hourly_queue_medians(&entries))
+            .unwrap_or_default();
+        self.plan_with(now, &medians)
+    }
+
+    fn plan_with(&self, now: DateTime<Local>, medians: &[Option<u64>; 24]) -> Plan {
+        let opening = if self.contains(&now) {
+            now
+        } else {
+            let minute = now.hour() * 60 + now.minute();
+            let wait = (self.start + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY;
+            let at = now + Duration::minutes(i64::from(wait));
+            at.with_second(0)
+                .and_then(|t| t.with_nanosecond(0))
+                .unwrap_or(at)
+        };
+        // The opening, then every full hour until the window closes.
+        let mut candidates = vec![opening];
+        let mut hour = opening
+            .with_minute(0)
+            .and_then(|t| t.with_second(0))
+            .and_then(|t| t.with_nanosecond(0))
+            .unwrap_or(opening)
+            + Duration::hours(1);
+        while self.contains(&hour) && hour < opening + Duration::days(1) {
+            candidates.push(hour);
+            hour += Duration::hours(1);
+        }
+        let plan = |at: DateTime<Local>| Plan {
+            at,
+            median_queue_ms: medians[at.hour() as usize],
+        };
+        candidates
+            .into_iter()
+            .map(plan)
+            .filter(|plan| plan.median_queue_ms.is_some())
+            .min_by_key(|plan| plan.median_queue_ms)
+            .unwrap_or_else(|| plan(opening))
+    }
+}
+
+impl fmt::Display for SubmitWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+fn minutes(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+pub fn is_urgent(priority: Option<&str>) -> bool {
+    priority.is_some_and(|p| URGENT_PRIORITIES.iter().any(|u| u.eq_ignore_ascii_case(p)))
+}
+
+/// The median queue wait of the jobs submitted in each local hour.
+fn hourly_queue_medians(entries: &[HistoryEntry]) -> [Option<u64>; 24] {
+    let mut waits: [Vec<u64>; 24] = Default::default();
+    for entry in entries {
+        let Some(queue_ms) = entry.document.phases.queue_ms else {
+            continue;
+        };
+        let submitted = entry
+            .finished_at
+            .saturating_sub(entry.document.elapsed_ms / 1000);
+        let Some(submitted) = DateTime::from_timestamp(submitted as i64, 0) else {
+            continue;
+        };
+        waits[submitted.with_timezone(&Local).hour() as usize].push(queue_ms);
+    }
+    waits.map(|mut waits| {
+        if waits.len() < MIN_SAMPLES {
+            return None;
+        }
+        waits.sort_unstable();
+        Some(waits[waits.len() / 2])
+    })
+}
+
+impl Plan {
+    /// How long until the submission; zero when it is due.
+    pub fn delay(&self) -> std::time::Duration {
+        (self.at - Local::now()).to_std().unwrap_or_default()
+    }
+}
+
+/// Waits until `plan.at`, or fails when interrupted first.
+pub async fn wait(plan: &Plan) -> Result<()> {
+    tokio::select! {
+        () = tokio::time::sleep(plan.delay()) => Ok(()),
+        () = shutdown::requested() => {
+            anyhow::bail!("Interrupted while waiting for the submit window; nothing was submitted")
+        }
+    }
+}
+
+/// `at 23:00 (median queue wait 40 s at that hour)` or `at 22:00`.
+pub fn describe(plan: &Plan) -> String {
+    let at = match plan.at.date_naive() == Local::now().date_naive() {
+        true => plan.at.format("%H:%M").to_string(),
+        false => plan.at.format("%a %H:%M").to_string(),
+    };
+    match plan.median_queue_ms {
+        Some(ms) => format!(
+            "at {} (median queue wait {} at that hour)",
+            at,
+            api::format_milliseconds(ms)
+        ),
+        None => format!("at {}", at),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn deferred_work_starts_at_the_quietest_hour_of_the_window() {
+        let window = SubmitWindow::parse("22:00-06:00").unwrap();
+        assert_eq!(window.to_string(), "22:00-06:00");
+        assert!(SubmitWindow::parse("22:00-22:00").is_err());
+        assert!(SubmitWindow::parse("25:00-06:00").is_err());
+
+        let afternoon = Local.with_ymd_and_hms(2026, 3, 2, 15, 30, 0).unwrap();
+        let night = Local.with_ymd_and_hms(2026, 3, 2, 23, 10, 0).unwrap();
+        assert!(!window.contains(&afternoon) && window.contains(&night));
+
+        let mut medians = [None; 24];
+        assert_eq!(
+            window.plan_with(afternoon, &medians).at,
+            Local.with_ymd_and_hms(2026, 3, 2, 22, 0, 0).unwrap()
+        );
+        assert_eq!(window.plan_with(night, &medians).at, night);
+
+        medians[23] = Some(60_000);
+        medians[2] = Some(5_000);
+        medians[12] = Some(1_000);
+        let plan = window.plan_with(afternoon, &medians);
+        assert_eq!(
+            plan.at,
+            Local.with_ymd_and_hms(2026, 3, 3, 2, 0, 0).unwrap()
+        );
+        assert_eq!(plan.median_queue_ms, Some(5_000));
+    }
+}
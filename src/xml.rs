@@ -0,0 +1,174 @@
+use anyhow::{Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+/// A parsed XML element. Names keep their namespace prefix (`w:p`), which
+/// is how OOXML and ODF documents are written in practice.
+#[derive(Debug, Clone, Default)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Child elements, in order.
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// The first child element called `name`.
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|e| e.name == name)
+    }
+
+    /// The first element called `name` at any depth, this one included.
+    pub fn find(&self, name: &str) -> Option<&Element> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.elements().find_map(|e| e.find(name))
+    }
+
+    /// Every element called `name` at any depth, in document order.
+    pub fn find_all<'a>(&'a self, name: &str, found: &mut Vec<&'a Element>) {
+        for element in self.elements() {
+            if element.name == name {
+                found.push(element);
+            }
+            element.find_all(name, found);
+        }
+    }
+
+    /// All text inside the element.
+    pub fn text(&self) -> String {
+        let mut out = String::new();
+        for node in &self.children {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Element(element) => out.push_str(&element.text()),
+            }
+        }
+        out
+    }
+}
+
+/// Parses a document into its root element. Declarations, comments,
+/// processing instructions and doctypes are skipped; CDATA becomes text.
+pub fn parse(text: &str) -> Result<Element> {
+    let mut reader = Reader::from_str(text);
+    let mut stack: Vec<Element> = vec![Element::default()];
+    loop {
+        let event = reader
+            .read_event()
+            .with_context(|| format!("Malformed XML at byte {}", reader.error_position()))?;
+        match event {
+            Event::Start(start) => stack.push(start_tag(&start)?),
+            Event::Empty(start) => {
+                let element = start_tag(&start)?;
+                push(&mut stack, Node::Element(element));
+            }
+            Event::End(_) => {
+                let element = stack.pop().context("Unexpected closing tag")?;
+                anyhow::ensure!(!stack.is_empty(), "Unexpected closing tag");
+                push(&mut stack, Node::Element(element));
+            }
+            Event::Text(text) => {
+                let text = text.unescape().context("Malformed text")?;
+                if !text.is_empty() {
+                    push(&mut stack, Node::Text(text.into_owned()));
+                }
+            }
+            Event::CData(cdata) => {
+                let text = String::from_utf8_lossy(&cdata.into_inner()).into_owned();
+                push(&mut stack, Node::Text(text));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    anyhow::ensure!(stack.len() == 1, "Unclosed element");
+    let document = stack.pop().unwrap_or_default();
+    document
+        .children
+        .into_iter()
+        .find_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+        .context("No root element")
+}
+
+fn push(stack: &mut [Element], node: Node) {
+    if let Some(parent) = stack.last_mut() {
+        parent.children.push(node);
+    }
+}
+
+fn start_tag(start: &BytesStart) -> Result<Element> {
+    let name = String::from_utf8_lossy(start.name().as_ref()).into_owned();
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.with_context(|| format!("Malformed attribute in <{}>", name))?;
+        let value = attribute
+            .unescape_value()
+            .with_context(|| format!("Malformed attribute in <{}>", name))?;
+        attributes.push((
+            String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+            value.into_owned(),
+        ));
+    }
+    Ok(Element {
+        name,
+        attributes,
+        children: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_parse_into_elements_with_prefixed_names() {
+        let document = parse(
+            "<?xml version=\"1.0\"?>\n<!-- generated -->\n\
+             <w:document xmlns:w=\"urn:w\"><w:body>\
+             <w:r><w:rFonts w:ascii=\"a>b\" w:hAnsi='x &amp; y'/>\
+             <w:t xml:space=\"preserve\">H&#8322;O &lt;1&gt; </w:t></w:r>\
+             <w:t><![CDATA[a < b]]></w:t></w:body></w:document>",
+        )
+        .unwrap();
+        assert_eq!(document.name, "w:document");
+        let fonts = document.find("w:rFonts").unwrap();
+        assert_eq!(fonts.attribute("w:ascii"), Some("a>b"));
+        assert_eq!(fonts.attribute("w:hAnsi"), Some("x & y"));
+        let mut texts = Vec::new();
+        document.find_all("w:t", &mut texts);
+        assert_eq!(texts[0].text(), "H\u{2082}O <1> ");
+        assert_eq!(texts[1].text(), "a < b");
+    }
+
+    #[test]
+    fn malformed_documents_are_errors() {
+        for invalid in ["<a><b></a></b>", "<a>", "<a x=1/>", "<a>&unknown;</a>", ""] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,509 @@
+//! End-to-end runs of the `chemtex` binary against recorded server
+//! interactions (`--replay`, see `src/fixtures.rs`), offline.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const DOCUMENT: &str = "\\documentclass{article}\n\\begin{document}\nHello\n\\end{document}\n";
+
+/// A scratch directory holding the sources and `CHEMTEX_HOME`, removed
+/// when the test ends.
+struct Sandbox {
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("chemtex-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self { dir }
+    }
+
+    fn write(&self, name: &str, contents: &str) {
+        let path = self.dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_with_env(args, &[])
+    }
+
+    fn run_with_env(&self, args: &[&str], env: &[(&str, &str)]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_chemtex"))
+            .args(args)
+            .current_dir(&self.dir)
+            .env("CHEMTEX_HOME", self.dir.join("home"))
+            .env("RUST_BACKTRACE", "0")
+            .envs(env.iter().copied())
+            .output()
+            .unwrap()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn fixture(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+        .display()
+        .to_string()
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[test]
+fn compile_downloads_the_recorded_pdf() {
+    let sandbox = Sandbox::new("success");
+    sandbox.write("doc.tex", DOCUMENT);
+
+    let output = sandbox.run(&[
+        "compile",
+        "doc.tex",
+        "--replay",
+        &fixture("compile-success"),
+    ]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let stdout = text(&output.stdout);
+    assert!(stdout.contains("Task ID: task-ok"), "{}", stdout);
+    assert!(
+        stdout.contains("Status: Queued (position: 2)"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Status: Completed!"), "{}", stdout);
+    let pdf = fs::read(sandbox.dir.join("doc.pdf")).unwrap();
+    let recorded = fs::read(Path::new(&fixture("compile-success")).join("005.bin")).unwrap();
+    assert_eq!(pdf, recorded);
+}
+
+#[test]
+fn compile_writes_a_status_badge() {
+    let sandbox = Sandbox::new("badge");
+    sandbox.write("doc.tex", DOCUMENT);
+
+    let output = sandbox.run(&[
+        "compile",
+        "doc.tex",
+        "--badge",
+        "badges/build.svg",
+        "--replay",
+        &fixture("compile-success"),
+    ]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let svg = text(&fs::read(sandbox.dir.join("badges/build.svg")).unwrap());
+    assert!(svg.starts_with("<svg"), "{}", svg);
+    assert!(svg.contains("doc: passing"), "{}", svg);
+}
+
+#[test]
+fn provenance_records_the_sources_and_the_task() {
+    let sandbox = Sandbox::new("provenance");
+    sandbox.write("doc.tex", DOCUMENT);
+
+    let output = sandbox.run(&[
+        "compile",
+        "doc.tex",
+        "--provenance",
+        "--replay",
+        &fixture("compile-success"),
+    ]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let json = fs::read_to_string(sandbox.dir.join("doc.provenance.json")).unwrap();
+    let provenance: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(provenance["task_id"], "task-ok");
+    assert_eq!(provenance["output"]["path"], "doc.pdf");
+    assert_eq!(provenance["sources"][0]["path"], "doc.tex");
+    assert_eq!(
+        provenance["sources"][0]["bytes"],
+        DOCUMENT.len() as u64,
+        "{}",
+        json
+    );
+}
+
+#[test]
+fn failed_compile_reports_the_error_and_a_hint_from_the_log() {
+    let sandbox = Sandbox::new("failure");
+    sandbox.write(
+        "doc.tex",
+        "\\documentclass{article}\n\\begin{document}\n\\ce{H2O}\n\\end{document}\n",
+    );
+
+    let output = sandbox.run(&[
+        "compile",
+        "doc.tex",
+        "--replay",
+        &fixture("compile-failure"),
+    ]);
+
+    assert!(!output.status.success());
+    let stderr = text(&output.stderr);
+    assert!(
+        stderr.contains("LaTeX Error: compilation failed"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("\\ce is defined by the mhchem package"),
+        "{}",
+        stderr
+    );
+    assert!(!sandbox.dir.join("doc.pdf").exists());
+}
+
+#[test]
+fn batch_matches_uploads_to_their_recorded_tasks() {
+    let sandbox = Sandbox::new("batch");
+    sandbox.write("docs/a.tex", &DOCUMENT.replace("Hello", "A"));
+    sandbox.write("docs/b.tex", &DOCUMENT.replace("Hello", "B"));
+
+    let output = sandbox.run(&["batch", "docs", "--replay", &fixture("batch")]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    for name in ["a", "b"] {
+        let pdf = text(&fs::read(sandbox.dir.join(format!("{}.pdf", name))).unwrap());
+        assert!(
+            pdf.contains(&format!("% {}\n", name)),
+            "{}.pdf: {}",
+            name,
+            pdf
+        );
+    }
+}
+
+#[test]
+fn manifest_limits_fail_or_warn_about_oversized_pdfs() {
+    let sandbox = Sandbox::new("limits");
+    sandbox.write("docs/a.tex", &DOCUMENT.replace("Hello", "A"));
+    sandbox.write("docs/b.tex", &DOCUMENT.replace("Hello", "B"));
+    sandbox.write(
+        "jobs.yaml",
+        "defaults:\n  limits:\n    max_size: 1\njobs:\n  - input: docs/a.tex\n\
+         \x20 - input: docs/b.tex\n    limits:\n      on_exceed: warn\n",
+    );
+
+    let output = sandbox.run(&[
+        "batch",
+        "--manifest",
+        "jobs.yaml",
+        "--replay",
+        &fixture("batch"),
+    ]);
+
+    assert!(!output.status.success());
+    let stdout = text(&output.stdout);
+    assert!(
+        stdout.contains("Limits exceeded:") && stdout.contains("(at most 1 B)"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("[docs/b.tex] Warning: Limits exceeded:")
+            && stdout.contains("  docs/a.tex: Limits exceeded:"),
+        "{}",
+        stdout
+    );
+    // The PDFs are kept to look into.
+    assert!(sandbox.dir.join("a.pdf").exists() && sandbox.dir.join("b.pdf").exists());
+}
+
+#[test]
+fn manifest_dependents_are_not_compiled_after_a_failure() {
+    let sandbox = Sandbox::new("depends");
+    sandbox.write("docs/a.tex", &DOCUMENT.replace("Hello", "A"));
+    sandbox.write("docs/b.tex", &DOCUMENT.replace("Hello", "B"));
+    sandbox.write(
+        "jobs.yaml",
+        "jobs:\n  - input: docs/b.tex\n    depends_on: [chapter]\n\
+         \x20 - input: docs/a.tex\n    name: chapter\n    limits:\n      max_size: 1\n",
+    );
+
+    let output = sandbox.run(&[
+        "batch",
+        "--manifest",
+        "jobs.yaml",
+        "--replay",
+        &fixture("batch"),
+    ]);
+
+    assert!(!output.status.success());
+    let stdout = text(&output.stdout);
+    assert!(
+        stdout.contains("[docs/b.tex] Not compiled: chapter failed"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("[docs/b.tex] Uploading"), "{}", stdout);
+    assert!(!sandbox.dir.join("b.pdf").exists());
+}
+
+#[test]
+fn compare_builds_the_document_with_each_engine() {
+    let sandbox = Sandbox::new("compare");
+    sandbox.write("a.tex", &DOCUMENT.replace("Hello", "A"));
+
+    let output = sandbox.run(&[
+        "compare",
+        "--engines",
+        "pdflatex,lualatex",
+        "a.tex",
+        "--no-visual",
+        "--replay",
+        &fixture("batch"),
+    ]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let stdout = text(&output.stdout);
+    for engine in ["pdflatex", "lualatex"] {
+        assert!(sandbox.dir.join(format!("a-{}.pdf", engine)).exists());
+        assert!(
+            stdout
+                .lines()
+                .any(|line| line.starts_with(engine) && line.contains("OK")),
+            "{}",
+            stdout
+        );
+    }
+}
+
+#[test]
+fn replay_fails_when_the_recording_runs_out() {
+    let sandbox = Sandbox::new("exhausted");
+    sandbox.write("doc.tex", DOCUMENT);
+    let upload_only: String =
+        fs::read_to_string(Path::new(&fixture("compile-success")).join("interactions.jsonl"))
+            .unwrap()
+            .lines()
+            .take(1)
+            .collect();
+    sandbox.write("recording/interactions.jsonl", &upload_only);
+
+    let output = sandbox.run(&["compile", "doc.tex", "--replay", "recording"]);
+
+    assert!(!output.status.success());
+    let stderr = text(&output.stderr);
+    assert!(
+        stderr
+            .contains("No recorded response left for GET https://texcompile.ru/api/status/task-ok"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn record_and_replay_cannot_be_combined() {
+    let sandbox = Sandbox::new("both");
+    sandbox.write("doc.tex", DOCUMENT);
+
+    let output = sandbox.run(&["compile", "doc.tex", "--record", "a", "--replay", "b"]);
+
+    assert!(!output.status.success());
+    assert!(text(&output.stderr).contains("--record and --replay cannot be combined"));
+}
+
+#[test]
+fn capture_http_writes_a_har_without_the_sources() {
+    let sandbox = Sandbox::new("har");
+    sandbox.write("doc.tex", DOCUMENT);
+
+    let output = sandbox.run(&[
+        "compile",
+        "doc.tex",
+        "--replay",
+        &fixture("compile-success"),
+        "--capture-http",
+        "session.har",
+    ]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let har = fs::read_to_string(sandbox.dir.join("session.har")).unwrap();
+    let har: serde_json::Value = serde_json::from_str(&har).unwrap();
+    let entries = har["log"]["entries"].as_array().unwrap();
+    let urls: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry["request"]["url"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        urls,
+        [
+            "https://texcompile.ru/api/upload",
+            "https://texcompile.ru/api/status/task-ok",
+            "https://texcompile.ru/api/status/task-ok",
+            "https://texcompile.ru/api/status/task-ok",
+            "https://texcompile.ru/files/task-ok.pdf",
+        ]
+    );
+    let upload = entries[0]["request"]["postData"]["text"].as_str().unwrap();
+    assert!(upload.starts_with("[not captured: doc.tex"), "{}", upload);
+    assert!(!har.to_string().contains("Hello"));
+    let pdf = &entries[4]["response"]["content"];
+    assert!(pdf.get("text").is_none(), "{}", pdf);
+}
+
+#[test]
+fn streamed_projects_are_packed_while_they_upload() {
+    let sandbox = Sandbox::new("stream");
+    sandbox.write(
+        "proj/main.tex",
+        &DOCUMENT.replace("Hello", "\\input{chapters/one}"),
+    );
+    sandbox.write("proj/chapters/one.tex", "Hello\n");
+
+    let output = sandbox.run(&[
+        "compile",
+        "proj",
+        "--stream",
+        "--replay",
+        &fixture("compile-success"),
+    ]);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let stdout = text(&output.stdout);
+    assert!(
+        stdout.contains("Packing proj/main.tex while uploading"),
+        "{}",
+        stdout
+    );
+    assert!(sandbox.dir.join("main.pdf").is_file());
+
+    let output = sandbox.run(&["compile", "proj/main.tex", "--stream"]);
+    assert!(!output.status.success());
+    assert!(text(&output.stderr).contains("--stream needs a project directory"));
+}
+
+/// Splits downloads of more than 1K into three ranges.
+const SEGMENTED: &[(&str, &str)] = &[
+    ("CHEMTEX_SEGMENTED_MIN_BYTES", "1K"),
+    ("CHEMTEX_DOWNLOAD_SEGMENTS", "3"),
+];
+
+#[test]
+fn large_pdfs_are_downloaded_in_verified_segments() {
+    let sandbox = Sandbox::new("segmented");
+    sandbox.write("doc.tex", DOCUMENT);
+    let fixtures = fixture("segmented-download");
+
+    let output = sandbox.run_with_env(&["compile", "doc.tex", "--replay", &fixtures], SEGMENTED);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    assert!(!text(&output.stderr).contains("segmented download failed"));
+    let pdf = fs::read(sandbox.dir.join("doc.pdf")).unwrap();
+    let expected = fs::read(Path::new(&fixtures).join("full.pdf")).unwrap();
+    assert_eq!(pdf, expected);
+}
+
+#[test]
+fn a_bad_segment_falls_back_to_a_whole_download() {
+    let sandbox = Sandbox::new("segment-mismatch");
+    sandbox.write("doc.tex", DOCUMENT);
+    let fixtures = fixture("segment-mismatch");
+
+    let output = sandbox.run_with_env(&["compile", "doc.tex", "--replay", &fixtures], SEGMENTED);
+
+    assert!(output.status.success(), "{}", text(&output.stderr));
+    let stderr = text(&output.stderr);
+    assert!(
+        stderr.contains("came back with status 200 OK instead of a range"),
+        "{}",
+        stderr
+    );
+    let pdf = fs::read(sandbox.dir.join("doc.pdf")).unwrap();
+    let expected = fs::read(Path::new(&fixtures).join("full.pdf")).unwrap();
+    assert_eq!(pdf, expected);
+}
+
+#[test]
+fn attach_last_resumes_a_crashed_run_from_its_session_journal() {
+    let sandbox = Sandbox::new("session");
+    let output = sandbox.dir.join("doc.pdf");
+    // What a run killed while its task was queued leaves behind.
+    sandbox.write(
+        "home/data/sessions/1-1.json",
+        &format!(
+            r#"{{"name": "doc", "input": "doc.tex", "output": {:?}, "phase": "queue", "task_id": "task-ok", "updated_at": 1}}"#,
+            output.display().to_string()
+        ),
+    );
+
+    let run = sandbox.run(&["attach", "--last", "--replay", &fixture("compile-success")]);
+
+    assert!(run.status.success(), "{}", text(&run.stderr));
+    let stdout = text(&run.stdout);
+    assert!(stdout.contains("Attaching to task task-ok"), "{}", stdout);
+    let recorded = fs::read(Path::new(&fixture("compile-success")).join("005.bin")).unwrap();
+    assert_eq!(fs::read(&output).unwrap(), recorded);
+    let sessions = fs::read_dir(sandbox.dir.join("home/data/sessions")).unwrap();
+    assert_eq!(sessions.count(), 0);
+}
+
+#[test]
+fn history_moves_between_machines_without_duplicates() {
+    let sandbox = Sandbox::new("history");
+    sandbox.write("doc.tex", DOCUMENT);
+    let output = sandbox.run(&[
+        "compile",
+        "doc.tex",
+        "--replay",
+        &fixture("compile-success"),
+    ]);
+    assert!(output.status.success(), "{}", text(&output.stderr));
+
+    let export = sandbox.run(&["history", "export", "--out", "history.json"]);
+    assert!(export.status.success(), "{}", text(&export.stderr));
+    let exported = fs::read_to_string(sandbox.dir.join("history.json")).unwrap();
+    assert!(exported.contains("task-ok"), "{}", exported);
+
+    let other = sandbox.dir.join("history.json").display().to_string();
+    let elsewhere = [("CHEMTEX_HOME", "elsewhere")];
+    let import = sandbox.run_with_env(&["history", "import", &other], &elsewhere);
+    assert!(import.status.success(), "{}", text(&import.stderr));
+    assert!(text(&import.stdout).contains("Imported 1 entries (0 already recorded)"));
+    let again = sandbox.run_with_env(&["history", "import", &other], &elsewhere);
+    assert!(text(&again.stdout).contains("Imported 0 entries (1 already recorded)"));
+}
+
+#[test]
+fn json_format_reports_a_typed_error_on_stdout() {
+    let sandbox = Sandbox::new("json-error");
+    sandbox.write(
+        "doc.tex",
+        "\\documentclass{article}\n\\begin{document}\n\\ce{H2O}\n\\end{document}\n",
+    );
+
+    let output = sandbox.run(&[
+        "compile",
+        "doc.tex",
+        "--format",
+        "json",
+        "--replay",
+        &fixture("compile-failure"),
+    ]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|e| panic!("{}: {}", e, text(&output.stdout)));
+    let error = &result["error"];
+    assert_eq!(error["kind"], "compilation_failed");
+    assert_eq!(error["task_id"], "task-bad");
+    let excerpt = error["log_excerpt"].as_str().unwrap();
+    assert!(
+        excerpt.contains("Undefined control sequence"),
+        "{}",
+        excerpt
+    );
+    assert!(text(&output.stderr).contains("Task ID: task-bad"));
+}